@@ -1,5 +1,6 @@
 //! This module handles the glue between the windowing system winit and the rest of the
 //! application.
+use std::fmt::Display;
 use std::ops::Deref;
 use std::time::Instant;
 
@@ -8,11 +9,13 @@ use winit::dpi::PhysicalSize;
 use winit::error::OsError;
 use winit::event::WindowEvent;
 use winit::event_loop::ActiveEventLoop;
+use winit::monitor::MonitorHandle;
 use winit::window::{Fullscreen, Window, WindowId};
 
 use crate::game::{Game, MainMenu};
+use crate::instance::InstanceGuard;
 use crate::render::Renderer;
-use crate::settings;
+use crate::settings::{self, MonitorPreference};
 
 struct TaikoAppInner {
     game: Game,
@@ -23,25 +26,181 @@ pub struct TaikoApp {
     inner: Option<TaikoAppInner>,
     frame_time: Instant,
     delta: f32,
+    /// Keeps other copies of the game from starting while this one is running.
+    instance: Option<InstanceGuard>,
 }
 
 impl TaikoApp {
-    pub fn new() -> Self {
+    pub fn new(instance: Option<InstanceGuard>) -> Self {
         Self {
             inner: None,
             frame_time: Instant::now(),
             delta: 1. / 60.,
+            instance,
+        }
+    }
+
+    /// Recreates the renderer after the graphics device has been lost, and has the game rebuild
+    /// everything it had on the GPU. If that doesn't work there's nothing more we can do, so the
+    /// game exits.
+    fn recover_from_device_loss(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(TaikoAppInner { mut game, renderer }) = self.inner.take() else {
+            return;
+        };
+
+        log::warn!("graphics device was lost, recreating the renderer");
+        let window = renderer.window;
+        // The old surface has to be gone before a new one can be made for the same window
+        drop(renderer);
+
+        let renderer = Renderer::new(window).and_then(|mut renderer| {
+            game.recreate_gpu_resources(&mut renderer)?;
+            Ok(renderer)
+        });
+
+        match renderer {
+            Ok(renderer) => {
+                log::info!("recovered from losing the graphics device");
+                self.inner = Some(TaikoAppInner { game, renderer });
+            }
+
+            Err(e) => {
+                log::error!("couldn't recover from losing the graphics device: {e}");
+                event_loop.exit();
+            }
+        }
+
+        // Recovering can take a while, and that shouldn't count as a long frame
+        self.frame_time = Instant::now();
+    }
+}
+
+/// What a monitor is called and what it can display, as far as the player is concerned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorInfo {
+    pub name: Option<String>,
+    pub size: PhysicalSize<u32>,
+    /// The monitor's refresh rate in Hz, if the OS knows it.
+    pub refresh_rate: Option<f32>,
+    /// The resolutions and refresh rates the monitor supports, biggest first, without repeats.
+    pub video_modes: Vec<(PhysicalSize<u32>, f32)>,
+}
+
+impl MonitorInfo {
+    fn new(monitor: &MonitorHandle) -> Self {
+        let mut video_modes: Vec<_> = monitor
+            .video_modes()
+            .map(|mode| (mode.size(), mode.refresh_rate_millihertz() as f32 / 1000.0))
+            .collect();
+        video_modes.sort_by(|(a, a_rate), (b, b_rate)| {
+            (b.width, b.height)
+                .cmp(&(a.width, a.height))
+                .then(b_rate.total_cmp(a_rate))
+        });
+        // The same mode is listed again for each bit depth
+        video_modes.dedup();
+
+        Self {
+            name: monitor.name(),
+            size: monitor.size(),
+            refresh_rate: monitor
+                .refresh_rate_millihertz()
+                .map(|millihertz| millihertz as f32 / 1000.0),
+            video_modes,
+        }
+    }
+}
+
+impl Display for MonitorInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = self.name.as_deref().unwrap_or("unnamed");
+        write!(f, "{name} ({}x{}", self.size.width, self.size.height)?;
+        if let Some(refresh_rate) = self.refresh_rate {
+            write!(f, " @ {refresh_rate}Hz")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Lists the monitors the game could be displayed on, in the order the OS gives them, which is the
+/// order [MonitorPreference::index] counts in.
+pub fn list_monitors(window: &Window) -> Vec<MonitorInfo> {
+    window
+        .available_monitors()
+        .map(|monitor| MonitorInfo::new(&monitor))
+        .collect()
+}
+
+/// Finds the preferred monitor in the given list of monitor names, returning its index.
+///
+/// Monitors are matched by name. If the preferred monitor doesn't have a name, it's matched by its
+/// position in the list instead. Returns None if the monitor can't be found (e.g. if it's been
+/// unplugged), in which case the primary monitor should be used.
+pub fn preferred_monitor_index(
+    preference: &MonitorPreference,
+    monitor_names: &[Option<String>],
+) -> Option<usize> {
+    match &preference.name {
+        Some(name) => monitor_names
+            .iter()
+            .position(|monitor| monitor.as_ref() == Some(name)),
+        None => (preference.index < monitor_names.len()).then_some(preference.index),
+    }
+}
+
+/// Chooses which monitor the game should be displayed on, based on the player's settings.
+fn choose_monitor(
+    event_loop: &ActiveEventLoop,
+    preference: Option<&MonitorPreference>,
+) -> Option<MonitorHandle> {
+    let monitors: Vec<MonitorHandle> = event_loop.available_monitors().collect();
+
+    for (i, monitor) in monitors.iter().enumerate() {
+        log::info!(
+            "monitor {i}: {}, scale factor {}",
+            MonitorInfo::new(monitor),
+            monitor.scale_factor(),
+        );
+    }
+
+    let preference = preference?;
+    let names: Vec<Option<String>> = monitors.iter().map(MonitorHandle::name).collect();
+
+    match preferred_monitor_index(preference, &names) {
+        Some(index) => Some(monitors[index].clone()),
+        None => {
+            log::warn!("couldn't find monitor {preference:?}, using the primary monitor instead");
+            event_loop.primary_monitor()
         }
     }
 }
 
+/// Moves the window to the monitor the player has chosen, the same way [create_window] puts it
+/// there to begin with. Like there, the primary monitor is used if the chosen one can't be found.
+pub fn move_to_monitor(window: &Window, preference: Option<&MonitorPreference>) {
+    let monitors: Vec<MonitorHandle> = window.available_monitors().collect();
+    let names: Vec<Option<String>> = monitors.iter().map(MonitorHandle::name).collect();
+    let monitor = preference
+        .and_then(|preference| preferred_monitor_index(preference, &names))
+        .map(|index| monitors[index].clone())
+        .or_else(|| window.primary_monitor());
+
+    if window.fullscreen().is_some() {
+        window.set_fullscreen(Some(Fullscreen::Borderless(monitor)));
+    } else if let Some(monitor) = monitor {
+        window.set_outer_position(monitor.position());
+    }
+}
+
 fn create_window(
     event_loop: &ActiveEventLoop,
     settings: impl Deref<Target = settings::Settings>,
 ) -> Result<Window, OsError> {
+    let monitor = choose_monitor(event_loop, settings.visual.monitor.as_ref());
+
     let (resolution, fullscreen) = match settings.visual.resolution {
         settings::ResolutionState::BorderlessFullscreen => {
-            (None, Some(Fullscreen::Borderless(None)))
+            (None, Some(Fullscreen::Borderless(monitor.clone())))
         }
         settings::ResolutionState::Windowed(width, height) => {
             (Some(PhysicalSize::new(width, height)), None)
@@ -58,6 +217,10 @@ fn create_window(
 
     if let Some(resolution) = resolution {
         attributes = attributes.with_inner_size(resolution);
+
+        if let Some(monitor) = monitor {
+            attributes = attributes.with_position(monitor.position());
+        }
     };
 
     event_loop.create_window(attributes)
@@ -83,7 +246,7 @@ impl ApplicationHandler for TaikoApp {
 
     fn window_event(
         &mut self,
-        event_loop: &ActiveEventLoop,
+        _event_loop: &ActiveEventLoop,
         _window_id: WindowId,
         event: WindowEvent,
     ) {
@@ -100,13 +263,19 @@ impl ApplicationHandler for TaikoApp {
 
             match event {
                 WindowEvent::CloseRequested => {
-                    event_loop.exit();
+                    game.request_close();
                 }
 
                 WindowEvent::Resized(size) => {
                     renderer.resize(size);
                 }
 
+                // The window has moved to a monitor with a different scale factor (or its monitor
+                // was unplugged), so make sure the renderer matches the window's new size.
+                WindowEvent::ScaleFactorChanged { .. } => {
+                    renderer.resize(renderer.window.inner_size());
+                }
+
                 _ => {}
             }
         }
@@ -121,6 +290,21 @@ impl ApplicationHandler for TaikoApp {
             return;
         };
 
+        if renderer.is_device_lost() {
+            self.recover_from_device_loss(event_loop);
+            return;
+        }
+
+        // Someone tried to start the game again, so show them the one that's already running
+        if self
+            .instance
+            .as_ref()
+            .is_some_and(InstanceGuard::focus_requested)
+        {
+            renderer.window.set_minimized(false);
+            renderer.window.focus_window();
+        }
+
         game.update(self.delta, renderer, event_loop);
         match renderer.render(game) {
             Ok(_) => {}
@@ -138,3 +322,57 @@ impl ApplicationHandler for TaikoApp {
         self.frame_time = time;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_preferred_monitor_index() {
+        let monitors = [
+            Some("DELL U2720Q".to_string()),
+            None,
+            Some("LG".to_string()),
+        ];
+
+        let by_name = MonitorPreference {
+            name: Some("LG".to_string()),
+            index: 0,
+        };
+        assert_eq!(preferred_monitor_index(&by_name, &monitors), Some(2));
+
+        let by_index = MonitorPreference {
+            name: None,
+            index: 1,
+        };
+        assert_eq!(preferred_monitor_index(&by_index, &monitors), Some(1));
+
+        // The monitor has been unplugged
+        let missing = MonitorPreference {
+            name: Some("BenQ".to_string()),
+            index: 1,
+        };
+        assert_eq!(preferred_monitor_index(&missing, &monitors), None);
+
+        let out_of_range = MonitorPreference {
+            name: None,
+            index: 3,
+        };
+        assert_eq!(preferred_monitor_index(&out_of_range, &monitors), None);
+    }
+
+    #[test]
+    fn test_monitor_info_display() {
+        let mut monitor = MonitorInfo {
+            name: Some("DELL U2720Q".to_string()),
+            size: PhysicalSize::new(3840, 2160),
+            refresh_rate: Some(59.94),
+            video_modes: Vec::new(),
+        };
+        assert_eq!(monitor.to_string(), "DELL U2720Q (3840x2160 @ 59.94Hz)");
+
+        monitor.name = None;
+        monitor.refresh_rate = None;
+        assert_eq!(monitor.to_string(), "unnamed (3840x2160)");
+    }
+}
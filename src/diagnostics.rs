@@ -0,0 +1,407 @@
+//! A self-test that checks everything the game needs to run and writes a report about it.
+//!
+//! When something doesn't work, the report (`diagnostics.txt` in the data directory) is the first
+//! thing to ask for. It can be made by running the game with `--diagnose`, which runs the checks
+//! without opening a window, or from the settings screen.
+//!
+//! Each check is a function that returns a [CheckOutcome]. A check that fails (or even panics)
+//! is recorded as failed, and the rest of the checks still run.
+use std::fmt::Display;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+
+use kira::manager::{backend::DefaultBackend, AudioManager, AudioManagerSettings};
+
+use crate::game::{count_songs, SONGS_DIR, SPRITES_PATH, TEXTURE_MANIFEST};
+use crate::paths::paths;
+use crate::render::{FONTS, FONTS_PATH};
+use crate::settings::try_read_settings;
+
+/// The name of the report file. It's written to the data directory (see [crate::paths]).
+pub const DIAGNOSTICS_PATH: &str = "diagnostics.txt";
+/// Running the game with this flag writes the report and exits without opening a window.
+pub const DIAGNOSE_FLAG: &str = "--diagnose";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    /// The check found something that might cause problems, but isn't necessarily broken.
+    Warning,
+    Failed,
+}
+
+impl Display for CheckStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Warning => "warning",
+            CheckStatus::Failed => "FAILED",
+        };
+
+        write!(f, "{status}")
+    }
+}
+
+/// What a check found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckOutcome {
+    pub status: CheckStatus,
+    /// Lines of information about what was checked.
+    pub details: Vec<String>,
+}
+
+impl CheckOutcome {
+    pub fn ok(details: Vec<String>) -> Self {
+        Self {
+            status: CheckStatus::Ok,
+            details,
+        }
+    }
+
+    /// An outcome that's ok, unless there are any warnings, in which case it's a warning and the
+    /// warnings are added to the details.
+    pub fn with_warnings(mut details: Vec<String>, warnings: Vec<String>) -> Self {
+        let status = if warnings.is_empty() {
+            CheckStatus::Ok
+        } else {
+            CheckStatus::Warning
+        };
+
+        details.extend(warnings.into_iter().map(|w| format!("warning: {w}")));
+        Self { status, details }
+    }
+}
+
+/// The result of a single check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub outcome: CheckOutcome,
+}
+
+/// Runs a check, turning any error or panic into a failed result.
+pub fn run_check<F>(name: &'static str, check: F) -> CheckResult
+where
+    F: FnOnce() -> anyhow::Result<CheckOutcome>,
+{
+    let outcome = match std::panic::catch_unwind(AssertUnwindSafe(check)) {
+        Ok(Ok(outcome)) => outcome,
+
+        Ok(Err(e)) => CheckOutcome {
+            status: CheckStatus::Failed,
+            details: vec![format!("error: {e:#}")],
+        },
+
+        Err(_) => CheckOutcome {
+            status: CheckStatus::Failed,
+            details: vec!["the check panicked".to_string()],
+        },
+    };
+
+    CheckResult { name, outcome }
+}
+
+/// The results of all the checks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiagnosticsReport {
+    pub results: Vec<CheckResult>,
+}
+
+impl DiagnosticsReport {
+    /// Whether every check passed (warnings are allowed).
+    pub fn passed(&self) -> bool {
+        self.results
+            .iter()
+            .all(|result| result.outcome.status != CheckStatus::Failed)
+    }
+
+    /// Writes the report to the given path.
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+}
+
+impl Display for DiagnosticsReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "lunataiko diagnostics")?;
+
+        for result in &self.results {
+            writeln!(f)?;
+            writeln!(f, "[{}] {}", result.outcome.status, result.name)?;
+
+            for line in &result.outcome.details {
+                writeln!(f, "    {line}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What the game's graphics are using, if the game has a window open.
+#[derive(Debug, Clone)]
+pub struct GraphicsInfo {
+    pub adapter: wgpu::AdapterInfo,
+    pub surface_format: wgpu::TextureFormat,
+}
+
+fn adapter_details(adapter: &wgpu::AdapterInfo) -> Vec<String> {
+    vec![
+        format!("adapter: {} ({:?})", adapter.name, adapter.device_type),
+        format!("backend: {:?}", adapter.backend),
+        format!("driver: {} {}", adapter.driver, adapter.driver_info),
+    ]
+}
+
+fn check_versions() -> anyhow::Result<CheckOutcome> {
+    Ok(CheckOutcome::ok(vec![
+        format!("game version: {}", env!("CARGO_PKG_VERSION")),
+        format!(
+            "build: {}",
+            if cfg!(debug_assertions) {
+                "debug"
+            } else {
+                "release"
+            }
+        ),
+        format!(
+            "platform: {} {}",
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        ),
+    ]))
+}
+
+fn check_graphics(graphics: Option<&GraphicsInfo>) -> anyhow::Result<CheckOutcome> {
+    if let Some(graphics) = graphics {
+        let mut details = adapter_details(&graphics.adapter);
+        details.push(format!("surface format: {:?}", graphics.surface_format));
+        return Ok(CheckOutcome::ok(details));
+    }
+
+    // There's no window, so ask for an adapter the same way the renderer does, minus the surface
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..Default::default()
+    });
+
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: Default::default(),
+        compatible_surface: None,
+        force_fallback_adapter: false,
+    }))
+    .ok_or(anyhow::anyhow!("no graphics adapter was found"))?;
+
+    let mut details = adapter_details(&adapter.get_info());
+    details.push("surface format: unknown (no window was opened)".to_string());
+    Ok(CheckOutcome::ok(details))
+}
+
+fn check_audio(audio: Option<&AudioManager>) -> anyhow::Result<CheckOutcome> {
+    let details = |manager: &AudioManager| {
+        vec![
+            "backend: cpal (kira's default backend)".to_string(),
+            format!("state: {:?}", manager.state()),
+            format!("sound capacity: {}", manager.sound_capacity()),
+            "device and latency: not reported by the audio backend".to_string(),
+        ]
+    };
+
+    match audio {
+        Some(manager) => Ok(CheckOutcome::ok(details(manager))),
+        None => {
+            // This opens the output device, but nothing is ever played
+            let manager = AudioManager::<DefaultBackend>::new(AudioManagerSettings::default())?;
+            Ok(CheckOutcome::ok(details(&manager)))
+        }
+    }
+}
+
+fn check_settings(path: &Path) -> anyhow::Result<CheckOutcome> {
+    let mut details = vec![format!("settings file: {}", path.display())];
+
+    if !path.exists() {
+        return Ok(CheckOutcome::with_warnings(
+            details,
+            vec!["the settings file doesn't exist yet, so the defaults are used".to_string()],
+        ));
+    }
+
+    match try_read_settings(path) {
+        Ok(_) => {
+            details.push("the settings file is valid".to_string());
+            Ok(CheckOutcome::ok(details))
+        }
+
+        Err(e) => Err(anyhow::anyhow!("the settings file couldn't be read: {e:?}")),
+    }
+}
+
+fn check_songs(dir: &Path) -> anyhow::Result<CheckOutcome> {
+    let count = count_songs(dir)?;
+    let details = vec![
+        format!("songs directory: {}", dir.display()),
+        format!("charts read: {}", count.parsed),
+        format!("charts that failed: {}", count.failed.len()),
+    ];
+
+    // Errors reading a chart already say which file they're in
+    let mut warnings: Vec<String> = count
+        .failed
+        .iter()
+        .map(|(_, error)| error.clone())
+        .chain(
+            count
+                .warnings
+                .iter()
+                .map(|(path, warning)| format!("{}: {warning}", path.display())),
+        )
+        .collect();
+
+    if count.parsed == 0 {
+        warnings.push("no songs were found".to_string());
+    }
+
+    Ok(CheckOutcome::with_warnings(details, warnings))
+}
+
+/// Checks that every file in `files` exists in `dir`.
+fn check_files(dir: &Path, files: &[&str]) -> anyhow::Result<CheckOutcome> {
+    let missing: Vec<PathBuf> = files
+        .iter()
+        .map(|file| dir.join(file))
+        .filter(|path| !path.exists())
+        .collect();
+
+    if missing.is_empty() {
+        Ok(CheckOutcome::ok(vec![format!(
+            "all {} files in {} are present",
+            files.len(),
+            dir.display()
+        )]))
+    } else {
+        let missing: Vec<_> = missing.iter().map(|path| path.display()).collect();
+        Err(anyhow::anyhow!(
+            "missing files: {}",
+            itertools::join(missing, ", ")
+        ))
+    }
+}
+
+fn check_assets() -> anyhow::Result<CheckOutcome> {
+    let fonts: Vec<&str> = FONTS.iter().map(|(_, filename, _)| *filename).collect();
+    let textures = check_files(Path::new(SPRITES_PATH), TEXTURE_MANIFEST)?;
+    let fonts = check_files(Path::new(FONTS_PATH), &fonts)?;
+
+    Ok(CheckOutcome::ok(
+        textures.details.into_iter().chain(fonts.details).collect(),
+    ))
+}
+
+/// Runs all the checks.
+///
+/// If the game is running, pass in its graphics info and audio manager so they are reported on,
+/// rather than starting up new ones.
+pub fn run_diagnostics(
+    graphics: Option<&GraphicsInfo>,
+    audio: Option<&AudioManager>,
+) -> DiagnosticsReport {
+    let settings_path = paths().settings_file();
+
+    DiagnosticsReport {
+        results: vec![
+            run_check("Versions", check_versions),
+            run_check("Graphics", || check_graphics(graphics)),
+            run_check("Audio", || check_audio(audio)),
+            run_check("Settings", || check_settings(&settings_path)),
+            run_check("Songs", || check_songs(Path::new(SONGS_DIR))),
+            run_check("Assets", check_assets),
+        ],
+    }
+}
+
+/// Runs all the checks and writes the report to the data directory. Returns where it was written
+/// and whether every check passed.
+pub fn write_diagnostics(
+    graphics: Option<&GraphicsInfo>,
+    audio: Option<&AudioManager>,
+) -> anyhow::Result<(PathBuf, bool)> {
+    let path = paths().data_dir().join(DIAGNOSTICS_PATH);
+    let report = run_diagnostics(graphics, audio);
+    report.write_to(&path)?;
+    Ok((path, report.passed()))
+}
+
+/// Describes the outcome of [write_diagnostics] for the player.
+pub fn describe_diagnostics(result: &anyhow::Result<(PathBuf, bool)>) -> String {
+    match result {
+        Ok((path, true)) => format!("All checks passed. Wrote report to \"{}\"", path.display()),
+        Ok((path, false)) => format!("Some checks failed. Wrote report to \"{}\"", path.display()),
+        Err(e) => format!("Couldn't write diagnostics report: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_report_format() {
+        let report = DiagnosticsReport {
+            results: vec![
+                run_check("First", || {
+                    Ok(CheckOutcome::ok(vec!["everything's fine".to_string()]))
+                }),
+                run_check("Second", || {
+                    Ok(CheckOutcome::with_warnings(
+                        vec!["mostly fine".to_string()],
+                        vec!["but not quite".to_string()],
+                    ))
+                }),
+            ],
+        };
+
+        assert_eq!(
+            report.to_string(),
+            "lunataiko diagnostics\n\
+             \n\
+             [ok] First\n    everything's fine\n\
+             \n\
+             [warning] Second\n    mostly fine\n    warning: but not quite\n"
+        );
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn test_failures_dont_abort() {
+        let report = DiagnosticsReport {
+            results: vec![
+                run_check("Error", || Err(anyhow::anyhow!("it broke"))),
+                run_check("Panic", || panic!("it really broke")),
+                run_check("Fine", || Ok(CheckOutcome::ok(vec![]))),
+            ],
+        };
+
+        let statuses: Vec<_> = report.results.iter().map(|r| r.outcome.status).collect();
+        assert_eq!(
+            statuses,
+            [CheckStatus::Failed, CheckStatus::Failed, CheckStatus::Ok]
+        );
+        assert_eq!(report.results[0].outcome.details, ["error: it broke"]);
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn test_check_files() {
+        let dir = temp_dir("check_files");
+        std::fs::write(dir.join("present.png"), "").unwrap();
+
+        assert!(check_files(&dir, &["present.png"]).is_ok());
+        let error = check_files(&dir, &["present.png", "missing.png"]).unwrap_err();
+        assert!(error.to_string().contains("missing.png"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,786 @@
+//! Utilities for dealing with song audio.
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use kira::dsp::Frame;
+use kira::manager::AudioManager;
+use kira::sound::static_sound::{StaticSoundData, StaticSoundHandle, StaticSoundSettings};
+use kira::tween::Tween;
+use kira::{CommandError, Volume};
+
+use crate::game::SOUNDS_PATH;
+use crate::settings::DrumInput;
+
+/// The loudness (RMS, in dBFS) that normalisation aims for.
+const TARGET_LOUDNESS_DB: f32 = -16.0;
+/// The loudest a peak is allowed to get after normalisation, in dBFS.
+const PEAK_CEILING_DB: f32 = -1.0;
+/// Normalisation will never change a song's volume by more than this many decibels.
+const MAX_GAIN_DB: f32 = 6.0;
+/// How many seconds of audio (starting from the preview start) are analysed.
+const ANALYSIS_LENGTH: f64 = 10.0;
+/// Anything quieter than this is treated as silence, and won't be normalised.
+const SILENCE_DB: f32 = -60.0;
+
+fn amplitude_to_db(amplitude: f32) -> f32 {
+    20.0 * amplitude.log10()
+}
+
+/// An estimate of how loud a piece of audio is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    /// The root mean square of the samples, in dBFS.
+    pub rms_db: f32,
+    /// The loudest sample, in dBFS.
+    pub peak_db: f32,
+}
+
+impl Loudness {
+    /// Measures the loudness of a buffer of frames. Both channels are taken into account.
+    ///
+    /// Returns None if the buffer is empty.
+    pub fn measure(frames: &[Frame]) -> Option<Self> {
+        if frames.is_empty() {
+            return None;
+        }
+
+        let mut sum_of_squares = 0.0f64;
+        let mut peak = 0.0f32;
+
+        for frame in frames {
+            sum_of_squares += (frame.left as f64).powi(2) + (frame.right as f64).powi(2);
+            peak = peak.max(frame.left.abs()).max(frame.right.abs());
+        }
+
+        let rms = (sum_of_squares / (frames.len() * 2) as f64).sqrt() as f32;
+
+        Some(Self {
+            rms_db: amplitude_to_db(rms),
+            peak_db: amplitude_to_db(peak),
+        })
+    }
+}
+
+/// Calculates the gain (in decibels) that should be applied to audio of the given loudness so
+/// that songs all play at roughly the same volume.
+///
+/// The gain is limited so it never pushes the peak above [PEAK_CEILING_DB] (so normalisation
+/// won't cause clipping), and is always within ±[MAX_GAIN_DB]. Silent audio gets no gain.
+pub fn normalisation_gain(loudness: Loudness) -> f32 {
+    if loudness.rms_db < SILENCE_DB {
+        return 0.0;
+    }
+
+    let gain = TARGET_LOUDNESS_DB - loudness.rms_db;
+    let headroom = PEAK_CEILING_DB - loudness.peak_db;
+
+    // If the song is already clipping, the headroom will be negative, which will turn the song
+    // down. That's fine, it just means it'll be quieter than the target.
+    gain.min(headroom).clamp(-MAX_GAIN_DB, MAX_GAIN_DB)
+}
+
+/// Converts a gain in decibels into a kira volume.
+pub fn gain_to_volume(gain_db: f32) -> Volume {
+    Volume::Decibels(gain_db as f64)
+}
+
+/// The volume to play a song at, given its normalisation gain (in decibels) and the volume the
+/// chart asks for (`SONGVOL`, as a percentage).
+pub fn song_volume(gain_db: f32, song_volume: f32) -> Volume {
+    Volume::Amplitude(gain_to_volume(gain_db).as_amplitude() * song_volume as f64 / 100.0)
+}
+
+/// The volume to play hit sounds at, given a chart's `SEVOL` and the player's hit sound volume
+/// setting (both percentages).
+pub fn hit_sound_volume(se_volume: f32, setting: f32) -> Volume {
+    Volume::Amplitude(se_volume as f64 / 100.0 * setting as f64 / 100.0)
+}
+
+/// The don and ka sounds played whenever a drum key is pressed.
+///
+/// Every press plays a new instance of its sound, so the sounds of a fast roll overlap instead of
+/// cutting each other off.
+pub struct HitSounds {
+    don: StaticSoundData,
+    kat: StaticSoundData,
+}
+
+impl HitSounds {
+    /// Loads the hit sounds, to be played at the given volume (see [hit_sound_volume]).
+    pub fn load(volume: Volume) -> anyhow::Result<Self> {
+        let load = |filename: &str| {
+            let path = format!("{SOUNDS_PATH}/{filename}");
+            StaticSoundData::from_file(&path, StaticSoundSettings::new().volume(volume))
+                .map_err(|e| anyhow::anyhow!("couldn't load {path}: {e}"))
+        };
+
+        Ok(Self {
+            don: load("don.wav")?,
+            kat: load("kat.wav")?,
+        })
+    }
+
+    pub fn play(&self, input: DrumInput, audio: &mut AudioManager) {
+        let sound = if input.is_don() { &self.don } else { &self.kat };
+        audio.play(sound.clone()).or_log("couldn't play hit sound");
+    }
+}
+
+/// The kinds of audio file songs can use, in the order they're looked for.
+pub const AUDIO_EXTENSIONS: [&str; 4] = ["ogg", "mp3", "wav", "flac"];
+
+/// Finds the audio file a chart is asking for.
+///
+/// Charts often give the wrong extension for their audio (e.g. `song.ogg` when the file is
+/// `song.mp3`), so if there's nothing at the given path, the same name is tried with each of
+/// [AUDIO_EXTENSIONS] instead. If none of them exist either, the path is returned as it is, so that
+/// errors name the file the chart asked for. `exists` says whether there's a file at a path.
+pub fn resolve_audio_path(path: &Path, exists: impl Fn(&Path) -> bool) -> PathBuf {
+    if exists(path) {
+        return path.to_path_buf();
+    }
+
+    AUDIO_EXTENSIONS
+        .iter()
+        .map(|extension| path.with_extension(extension))
+        .find(|candidate| exists(candidate))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Decodes the audio file at the given path and measures the loudness of the section starting at
+/// `start` (in seconds).
+fn analyse_loudness(path: &Path, start: f64) -> anyhow::Result<Option<Loudness>> {
+    let data = StaticSoundData::from_file(path, StaticSoundSettings::default())?;
+
+    let sample_rate = data.sample_rate as f64;
+    let start_frame = ((start.max(0.0) * sample_rate) as usize).min(data.frames.len());
+    let end_frame = (start_frame + (ANALYSIS_LENGTH * sample_rate) as usize).min(data.frames.len());
+
+    Ok(Loudness::measure(&data.frames[start_frame..end_frame]))
+}
+
+/// Analyses the loudness of a song preview on another thread.
+///
+/// Once the analysis is done, the audio path is sent back along with the normalisation gain that
+/// should be applied to it. If the analysis fails, the error is logged and nothing is sent.
+pub fn spawn_loudness_analysis(path: String, start: f64, sender: Sender<(String, f32)>) {
+    std::thread::spawn(move || match analyse_loudness(Path::new(&path), start) {
+        Ok(loudness) => {
+            let gain = loudness.map(normalisation_gain).unwrap_or(0.0);
+            let _ = sender.send((path, gain));
+        }
+
+        Err(e) => log::error!("couldn't analyse the loudness of \"{path}\": {e}"),
+    });
+}
+
+/// Synthesises a short metronome tick: a sine blip that decays to silence over 30ms.
+pub fn metronome_tick() -> StaticSoundData {
+    const SAMPLE_RATE: u32 = 48000;
+    const FREQUENCY: f32 = 1500.0;
+    const LENGTH: f32 = 0.03;
+
+    let length = (SAMPLE_RATE as f32 * LENGTH) as usize;
+    let frames = (0..length)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let envelope = 1.0 - i as f32 / length as f32;
+            Frame::from_mono(0.5 * envelope * (std::f32::consts::TAU * FREQUENCY * t).sin())
+        })
+        .collect();
+
+    StaticSoundData {
+        sample_rate: SAMPLE_RATE,
+        frames,
+        settings: StaticSoundSettings::new(),
+    }
+}
+
+/// Synthesises the chime played when the combo reaches a milestone: two rising sine notes, each
+/// decaying over 120ms.
+pub fn combo_chime() -> StaticSoundData {
+    const SAMPLE_RATE: u32 = 48000;
+    const FREQUENCIES: [f32; 2] = [880.0, 1320.0];
+    const NOTE_LENGTH: f32 = 0.12;
+
+    let note_length = (SAMPLE_RATE as f32 * NOTE_LENGTH) as usize;
+    let frames = (0..note_length * FREQUENCIES.len())
+        .map(|i| {
+            let frequency = FREQUENCIES[i / note_length];
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let envelope = 1.0 - (i % note_length) as f32 / note_length as f32;
+            Frame::from_mono(0.4 * envelope * (std::f32::consts::TAU * frequency * t).sin())
+        })
+        .collect();
+
+    StaticSoundData {
+        sample_rate: SAMPLE_RATE,
+        frames,
+        settings: StaticSoundSettings::new(),
+    }
+}
+
+/// Makes a sound that's completely silent for the given number of seconds.
+///
+/// This is for scenes that need something to play but have no audio of their own. It uses a low
+/// sample rate, since there's nothing to hear anyway.
+pub fn silence(length: f32) -> StaticSoundData {
+    const SAMPLE_RATE: u32 = 8000;
+
+    let length = (SAMPLE_RATE as f32 * length) as usize;
+
+    StaticSoundData {
+        sample_rate: SAMPLE_RATE,
+        frames: vec![Frame::ZERO; length].into(),
+        settings: StaticSoundSettings::new(),
+    }
+}
+
+/// An extension trait for results that aren't worth crashing the game over.
+///
+/// Audio commands in particular can fail if kira's command queue is full, but a missed fade or
+/// stop is much less bad than a panic in the middle of a song.
+pub trait OrLog<T> {
+    /// Logs the error (if there is one) along with the given context, and discards it.
+    fn or_log(self, context: &str) -> Option<T>;
+}
+
+impl<T, E: Display> OrLog<T> for Result<T, E> {
+    fn or_log(self, context: &str) -> Option<T> {
+        match self {
+            Ok(t) => Some(t),
+            Err(e) => {
+                log::error!("{context}: {e}");
+                None
+            }
+        }
+    }
+}
+
+/// The audio handle commands that gameplay can't do without.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackCommand {
+    Pause,
+    /// Pauses with a short fade out, so the song doesn't cut off abruptly when the player pauses
+    /// it themselves.
+    FadeOut,
+    Resume,
+}
+
+/// The subset of an audio handle's interface used by [AudioWatchdog].
+pub trait PlaybackControl {
+    fn pause(&mut self, tween: Tween) -> Result<(), CommandError>;
+    fn resume(&mut self, tween: Tween) -> Result<(), CommandError>;
+}
+
+impl PlaybackControl for StaticSoundHandle {
+    fn pause(&mut self, tween: Tween) -> Result<(), CommandError> {
+        StaticSoundHandle::pause(self, tween)
+    }
+
+    fn resume(&mut self, tween: Tween) -> Result<(), CommandError> {
+        StaticSoundHandle::resume(self, tween)
+    }
+}
+
+/// How long the song takes to fade out when it's paused with [PlaybackCommand::FadeOut].
+const PAUSE_FADE: Duration = Duration::from_millis(150);
+
+/// Makes sure critical commands to the song's audio handle go through.
+///
+/// If a command fails, it is retried once on the next frame. If that fails too, the watchdog
+/// gives up and marks the audio as "degraded", which means the song has to carry on without
+/// relying on the audio (e.g. by timing the song with its own clock).
+#[derive(Debug, Default)]
+pub struct AudioWatchdog {
+    pending: Option<PlaybackCommand>,
+    degraded: bool,
+}
+
+impl AudioWatchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends a command to the handle. If it fails, it will be retried on the next call to
+    /// [AudioWatchdog::update].
+    pub fn send(&mut self, handle: &mut impl PlaybackControl, command: PlaybackCommand) {
+        // A newer command supersedes any one that's still waiting to be retried.
+        self.pending = None;
+
+        if let Err(e) = Self::try_send(handle, command) {
+            log::warn!("audio command {command:?} failed, retrying next frame: {e}");
+            self.pending = Some(command);
+        }
+    }
+
+    /// Retries the last command if it failed. Should be called once per frame.
+    pub fn update(&mut self, handle: &mut impl PlaybackControl) {
+        let Some(command) = self.pending.take() else {
+            return;
+        };
+
+        if let Err(e) = Self::try_send(handle, command) {
+            log::error!("audio command {command:?} failed twice, continuing without audio: {e}");
+            self.degraded = true;
+        }
+    }
+
+    /// Whether a command has failed for good, meaning the audio can't be trusted to be in sync
+    /// (or playing at all).
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    fn try_send(
+        handle: &mut impl PlaybackControl,
+        command: PlaybackCommand,
+    ) -> Result<(), CommandError> {
+        match command {
+            PlaybackCommand::Pause => handle.pause(Tween::default()),
+            PlaybackCommand::FadeOut => handle.pause(Tween {
+                duration: PAUSE_FADE,
+                ..Default::default()
+            }),
+            PlaybackCommand::Resume => handle.resume(Tween::default()),
+        }
+    }
+}
+
+/// How loud the music gets while a menu or overlay is open, as a fraction of its normal volume.
+pub const DUCKED_GAIN: f32 = 0.4;
+/// How long it takes the music to go from full volume to ducked, in seconds.
+const DUCK_TIME: f32 = 0.25;
+/// How long it takes the music to go from ducked back to full volume, in seconds.
+const RESTORE_TIME: f32 = 0.5;
+
+/// What the music ducking is doing at the moment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuckState {
+    /// The music is at full volume.
+    Idle,
+    /// The music is fading down, from the given gain at the given time.
+    Ducking { from: f32, start: f32 },
+    /// The music is ducked, and will stay that way until every overlay has let go.
+    Held,
+    /// The music is fading back up, from the given gain at the given time.
+    Restoring { from: f32, start: f32 },
+}
+
+/// A change to the music's volume that should be sent to the audio manager.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GainChange {
+    pub gain: f32,
+    /// How long the fade to the new gain should take, in seconds.
+    pub duration: f32,
+}
+
+impl GainChange {
+    pub fn tween(&self) -> Tween {
+        Tween {
+            duration: Duration::from_secs_f32(self.duration),
+            ..Default::default()
+        }
+    }
+}
+
+/// Turns the music down while menus or overlays are open, and back up once they've all closed.
+///
+/// Each overlay holds the ducking under its own name. Holding it twice under the same name, or
+/// having several overlays open at once, doesn't turn the music down any further. The fades are
+/// always worked out from wherever the volume is when they start, so an overlay opening halfway
+/// through a restore fades down from there rather than jumping.
+#[derive(Debug)]
+pub struct MusicDucking {
+    holders: HashSet<&'static str>,
+    state: DuckState,
+}
+
+impl Default for MusicDucking {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MusicDucking {
+    pub fn new() -> Self {
+        Self {
+            holders: HashSet::new(),
+            state: DuckState::Idle,
+        }
+    }
+
+    /// The gain the music should be at, at the given time.
+    pub fn gain(&self, now: f32) -> f32 {
+        let fade = |from: f32, to: f32, start: f32| match fade_progress(from, to, start, now) {
+            progress if progress >= 1.0 => to,
+            progress => from + (to - from) * progress,
+        };
+
+        match self.state {
+            DuckState::Idle => 1.0,
+            DuckState::Held => DUCKED_GAIN,
+            DuckState::Ducking { from, start } => fade(from, DUCKED_GAIN, start),
+            DuckState::Restoring { from, start } => fade(from, 1.0, start),
+        }
+    }
+
+    /// Moves on to the next state once a fade has finished. Should be called once per frame.
+    pub fn update(&mut self, now: f32) {
+        self.state = match self.state {
+            DuckState::Ducking { from, start }
+                if fade_progress(from, DUCKED_GAIN, start, now) >= 1.0 =>
+            {
+                DuckState::Held
+            }
+            DuckState::Restoring { from, start } if fade_progress(from, 1.0, start, now) >= 1.0 => {
+                DuckState::Idle
+            }
+            state => state,
+        };
+    }
+
+    /// Ducks the music on behalf of the given overlay. Returns the volume change to make, if the
+    /// music wasn't already ducked.
+    pub fn hold(&mut self, overlay: &'static str, now: f32) -> Option<GainChange> {
+        let first = self.holders.is_empty();
+        if !self.holders.insert(overlay) || !first {
+            return None;
+        }
+
+        let from = self.gain(now);
+        self.state = DuckState::Ducking { from, start: now };
+
+        Some(GainChange {
+            gain: DUCKED_GAIN,
+            duration: fade_time(from, DUCKED_GAIN),
+        })
+    }
+
+    /// Lets go of the ducking held by the given overlay. Returns the volume change to make, if
+    /// that was the last overlay holding it.
+    pub fn release(&mut self, overlay: &'static str, now: f32) -> Option<GainChange> {
+        if !self.holders.remove(overlay) || !self.holders.is_empty() {
+            return None;
+        }
+
+        let from = self.gain(now);
+        self.state = DuckState::Restoring { from, start: now };
+
+        Some(GainChange {
+            gain: 1.0,
+            duration: fade_time(from, 1.0),
+        })
+    }
+
+    /// Holds or releases the ducking for the given overlay, for overlays that are checked every
+    /// frame.
+    pub fn set_held(&mut self, overlay: &'static str, held: bool, now: f32) -> Option<GainChange> {
+        if held {
+            self.hold(overlay, now)
+        } else {
+            self.release(overlay, now)
+        }
+    }
+}
+
+/// How far through a fade between the two gains that started at the given time is, from 0 to 1.
+fn fade_progress(from: f32, to: f32, start: f32, now: f32) -> f32 {
+    let duration = fade_time(from, to);
+    if duration <= 0.0 {
+        return 1.0;
+    }
+
+    ((now - start) / duration).clamp(0.0, 1.0)
+}
+
+/// How long a fade between the two gains should take. Partial fades take part of the time, so
+/// the volume always changes at the same speed.
+fn fade_time(from: f32, to: f32) -> f32 {
+    let full_time = if to < from { DUCK_TIME } else { RESTORE_TIME };
+    full_time * (to - from).abs() / (1.0 - DUCKED_GAIN)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{silent_wav, temp_dir};
+
+    #[test]
+    fn test_resolve_audio_path() {
+        let files = [Path::new("songs/a/song.mp3"), Path::new("songs/a/song.ogg")];
+        let exists = |path: &Path| files.contains(&path);
+        let resolve = |path: &str| resolve_audio_path(Path::new(path), exists);
+
+        // A file that's there is used as it is, even if another extension is tried first
+        assert_eq!(resolve("songs/a/song.mp3"), Path::new("songs/a/song.mp3"));
+        // Otherwise the first extension that exists is used
+        assert_eq!(resolve("songs/a/song.wav"), Path::new("songs/a/song.ogg"));
+        assert_eq!(resolve("songs/a/song"), Path::new("songs/a/song.ogg"));
+
+        let only_flac = |path: &Path| path == Path::new("b/Song Name.flac");
+        assert_eq!(
+            resolve_audio_path(Path::new("b/Song Name.ogg"), only_flac),
+            Path::new("b/Song Name.flac")
+        );
+
+        // Nothing found leaves the path alone
+        assert_eq!(resolve("songs/b/song.ogg"), Path::new("songs/b/song.ogg"));
+    }
+
+    #[test]
+    fn test_load_audio_with_wrong_extension() {
+        use kira::sound::streaming::{StreamingSoundData, StreamingSoundSettings};
+
+        const SAMPLE_RATE: u32 = 8000;
+
+        // A tenth of a second of silence
+        let samples = SAMPLE_RATE / 10;
+        let wav = silent_wav(SAMPLE_RATE, samples);
+
+        let dir = temp_dir("audio_ext");
+        std::fs::write(dir.join("song.wav"), wav).unwrap();
+
+        // The chart says ogg, but both ways of playing the song find the wav
+        let path = resolve_audio_path(&dir.join("song.ogg"), Path::exists);
+        assert_eq!(path, dir.join("song.wav"));
+        let data = StaticSoundData::from_file(&path, StaticSoundSettings::default()).unwrap();
+        assert_eq!(data.frames.len(), samples as usize);
+        assert!(StreamingSoundData::from_file(&path, StreamingSoundSettings::default()).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A handle that fails a set number of times before it starts working.
+    struct MockHandle {
+        failures_left: u32,
+        commands: Vec<PlaybackCommand>,
+    }
+
+    impl MockHandle {
+        fn new(failures: u32) -> Self {
+            Self {
+                failures_left: failures,
+                commands: Vec::new(),
+            }
+        }
+
+        fn receive(&mut self, command: PlaybackCommand) -> Result<(), CommandError> {
+            if self.failures_left > 0 {
+                self.failures_left -= 1;
+                Err(CommandError::CommandQueueFull)
+            } else {
+                self.commands.push(command);
+                Ok(())
+            }
+        }
+    }
+
+    impl PlaybackControl for MockHandle {
+        fn pause(&mut self, _tween: Tween) -> Result<(), CommandError> {
+            self.receive(PlaybackCommand::Pause)
+        }
+
+        fn resume(&mut self, _tween: Tween) -> Result<(), CommandError> {
+            self.receive(PlaybackCommand::Resume)
+        }
+    }
+
+    #[test]
+    fn test_watchdog_success() {
+        let mut handle = MockHandle::new(0);
+        let mut watchdog = AudioWatchdog::new();
+
+        watchdog.send(&mut handle, PlaybackCommand::Resume);
+        watchdog.update(&mut handle);
+
+        assert_eq!(handle.commands, vec![PlaybackCommand::Resume]);
+        assert!(!watchdog.is_degraded());
+    }
+
+    #[test]
+    fn test_watchdog_retries_once() {
+        let mut handle = MockHandle::new(1);
+        let mut watchdog = AudioWatchdog::new();
+
+        watchdog.send(&mut handle, PlaybackCommand::Resume);
+        assert!(handle.commands.is_empty());
+
+        watchdog.update(&mut handle);
+        assert_eq!(handle.commands, vec![PlaybackCommand::Resume]);
+        assert!(!watchdog.is_degraded());
+
+        // Nothing left to retry
+        watchdog.update(&mut handle);
+        assert_eq!(handle.commands.len(), 1);
+    }
+
+    #[test]
+    fn test_watchdog_degrades() {
+        let mut handle = MockHandle::new(2);
+        let mut watchdog = AudioWatchdog::new();
+
+        watchdog.send(&mut handle, PlaybackCommand::Pause);
+        watchdog.update(&mut handle);
+        assert!(handle.commands.is_empty());
+        assert!(watchdog.is_degraded());
+
+        // It doesn't keep retrying after giving up
+        watchdog.update(&mut handle);
+        assert!(handle.commands.is_empty());
+    }
+
+    #[test]
+    fn test_or_log() {
+        assert_eq!(Ok::<_, CommandError>(3).or_log("context"), Some(3));
+        assert_eq!(
+            Err::<i32, _>(CommandError::CommandQueueFull).or_log("context"),
+            None
+        );
+    }
+
+    fn sine_wave(amplitude: f32, length: usize) -> Vec<Frame> {
+        (0..length)
+            .map(|i| {
+                let sample = amplitude * (i as f32 * 0.05).sin();
+                Frame::new(sample, sample)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_measure_loudness() {
+        assert_eq!(Loudness::measure(&[]), None);
+
+        // A square wave at full volume has an RMS and peak of exactly 0 dBFS
+        let square = (0..100)
+            .map(|i| {
+                if i % 2 == 0 {
+                    Frame::new(1.0, 1.0)
+                } else {
+                    Frame::new(-1.0, -1.0)
+                }
+            })
+            .collect::<Vec<_>>();
+        let loudness = Loudness::measure(&square).unwrap();
+        assert!(loudness.rms_db.abs() < 0.01);
+        assert!(loudness.peak_db.abs() < 0.01);
+
+        // A sine wave's RMS is 1/sqrt(2) of its peak, which is about 3dB quieter
+        let loudness = Loudness::measure(&sine_wave(0.5, 10000)).unwrap();
+        assert!((loudness.peak_db - amplitude_to_db(0.5)).abs() < 0.01);
+        assert!((loudness.peak_db - loudness.rms_db - 3.01).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_normalisation_gain() {
+        // Quiet songs get turned up, but not by more than the maximum
+        let quiet = Loudness::measure(&sine_wave(0.05, 10000)).unwrap();
+        assert_eq!(normalisation_gain(quiet), MAX_GAIN_DB);
+
+        // Loud songs get turned down, but not by more than the maximum
+        let loud = Loudness {
+            rms_db: 0.0,
+            peak_db: 0.0,
+        };
+        assert_eq!(normalisation_gain(loud), -MAX_GAIN_DB);
+
+        // A song that's slightly too quiet gets turned up exactly to the target
+        let slightly_quiet = Loudness {
+            rms_db: TARGET_LOUDNESS_DB - 2.0,
+            peak_db: -10.0,
+        };
+        assert!((normalisation_gain(slightly_quiet) - 2.0).abs() < 0.001);
+
+        // A quiet song with loud peaks is only turned up until the peaks hit the ceiling
+        let peaky = Loudness {
+            rms_db: -20.0,
+            peak_db: -2.0,
+        };
+        assert!((normalisation_gain(peaky) - 1.0).abs() < 0.001);
+
+        // Silence is left alone
+        let silence = Loudness::measure(&vec![Frame::ZERO; 1000]).unwrap();
+        assert_eq!(normalisation_gain(silence), 0.0);
+    }
+
+    #[test]
+    fn test_song_volume() {
+        assert_eq!(song_volume(0.0, 100.0).as_amplitude(), 1.0);
+        assert_eq!(song_volume(0.0, 80.0).as_amplitude(), 0.8);
+        // The chart's volume goes on top of normalisation
+        assert!((song_volume(6.0, 50.0).as_amplitude() - 0.998).abs() < 0.001);
+        // No volume is silent, whatever the gain
+        assert_eq!(song_volume(MAX_GAIN_DB, 0.0).as_amplitude(), 0.0);
+    }
+
+    #[test]
+    fn test_hit_sound_volume() {
+        assert_eq!(hit_sound_volume(100.0, 100.0).as_amplitude(), 1.0);
+        assert_eq!(hit_sound_volume(120.0, 50.0).as_amplitude(), 0.6);
+        assert_eq!(hit_sound_volume(0.0, 100.0).as_amplitude(), 0.0);
+    }
+
+    #[test]
+    fn test_ducking_cycle() {
+        let mut ducking = MusicDucking::new();
+        assert_eq!(ducking.gain(0.0), 1.0);
+
+        let change = ducking.hold("settings", 1.0).unwrap();
+        assert_eq!(change.gain, DUCKED_GAIN);
+        assert!((change.duration - DUCK_TIME).abs() < 1e-6);
+        assert!((ducking.gain(1.0 + DUCK_TIME / 2.0) - 0.7).abs() < 1e-6);
+
+        ducking.update(1.0 + DUCK_TIME);
+        assert_eq!(ducking.state, DuckState::Held);
+        assert_eq!(ducking.gain(5.0), DUCKED_GAIN);
+
+        let change = ducking.release("settings", 5.0).unwrap();
+        assert_eq!(change.gain, 1.0);
+        assert!((change.duration - RESTORE_TIME).abs() < 1e-6);
+
+        ducking.update(5.0 + RESTORE_TIME);
+        assert_eq!(ducking.state, DuckState::Idle);
+        assert_eq!(ducking.gain(10.0), 1.0);
+    }
+
+    #[test]
+    fn test_ducking_doesnt_stack() {
+        let mut ducking = MusicDucking::new();
+        assert!(ducking.hold("settings", 0.0).is_some());
+        assert!(ducking.hold("settings", 0.1).is_none());
+        assert!(ducking.hold("quit dialog", 0.1).is_none());
+
+        ducking.update(1.0);
+        assert_eq!(ducking.gain(1.0), DUCKED_GAIN);
+
+        // The music only comes back once everything has let go
+        assert!(ducking.release("settings", 2.0).is_none());
+        assert_eq!(ducking.state, DuckState::Held);
+        assert!(ducking.release("settings", 2.0).is_none());
+        assert!(ducking.release("quit dialog", 2.0).is_some());
+
+        // Letting go of something that wasn't holding it does nothing
+        assert!(ducking.release("settings", 2.1).is_none());
+    }
+
+    #[test]
+    fn test_interrupted_restore() {
+        let mut ducking = MusicDucking::new();
+        ducking.hold("settings", 0.0);
+        ducking.update(1.0);
+        ducking.release("settings", 1.0);
+
+        // Opening another overlay halfway through fades down from where the volume got to
+        let halfway = 1.0 + RESTORE_TIME / 2.0;
+        let change = ducking.hold("settings", halfway).unwrap();
+        assert!((ducking.gain(halfway) - 0.7).abs() < 1e-6);
+        assert!((change.duration - DUCK_TIME / 2.0).abs() < 1e-6);
+
+        ducking.update(halfway + DUCK_TIME);
+        assert_eq!(ducking.state, DuckState::Held);
+    }
+}
@@ -0,0 +1,375 @@
+//! A screen for finding the player's note offset, by having them tap along to a metronome.
+//!
+//! The ticks are scheduled on a kira clock, so they're exactly in time however the frames go. The
+//! taps are timed from the moment the clock was started, the same way hits are timed from the
+//! moment a song's audio is started in taiko mode, so whatever delay there is between the game
+//! and the player's ears and fingers ends up in the offset.
+use std::time::Instant;
+
+use egui::RichText;
+use kira::clock::{ClockHandle, ClockSpeed};
+use kira::manager::AudioManager;
+use kira::sound::static_sound::StaticSoundData;
+use winit::event::{ElementState, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use crate::game::audio::{metronome_tick, OrLog};
+use crate::game::input_queue::InputQueue;
+use crate::game::tap_stats::TapStatistics;
+use crate::game::{Action, Context, GameState, StateTransition};
+use crate::settings::{save_settings, settings, SETTINGS};
+
+/// The number of seconds between each tick.
+const BEAT_LENGTH: f32 = 0.5;
+/// How many ticks there are to get into the rhythm before the taps start counting.
+const COUNT_IN_BEATS: u32 = 4;
+/// How many ticks the player taps along to after the count-in.
+const TAP_BEATS: u32 = 20;
+/// The fewest taps there can be left once the outliers are gone for the offset to be trusted.
+const MIN_TAPS: usize = TAP_BEATS as usize / 2;
+/// The most the taps that are used can vary (see [Calibration::spread]) for the offset to be
+/// trusted, in seconds.
+const MAX_SPREAD: f32 = 0.040;
+
+const STRIP_SIZE: [f32; 2] = [600.0, 60.0];
+/// How many seconds of beats the strip shows ahead of the line.
+const STRIP_LOOKAHEAD: f32 = 2.0;
+
+/// The offset worked out from the player's taps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    /// How late the player taps, in seconds. This is what the global note offset should be (in
+    /// milliseconds).
+    pub offset: f32,
+    /// How much the taps that were used vary (their median absolute deviation), in seconds.
+    pub spread: f32,
+    pub taps_used: usize,
+    /// The taps that were too far from the rest to be used.
+    pub taps_rejected: usize,
+}
+
+/// Works out the offset from how early or late each tap was.
+///
+/// The offset is the median of the taps, leaving out the outliers (see
+/// [TapStatistics::without_outliers]) so one mistimed tap doesn't skew it. Returns None if there
+/// aren't enough taps left, or they're too spread out to trust.
+pub fn calibrate(taps: &TapStatistics) -> Option<Calibration> {
+    let used = taps.without_outliers();
+    if used.len() < MIN_TAPS {
+        return None;
+    }
+
+    let offset = used.median()?;
+    let spread = used.median_deviation()?;
+    if spread > MAX_SPREAD {
+        return None;
+    }
+
+    Some(Calibration {
+        offset,
+        spread,
+        taps_used: used.len(),
+        taps_rejected: taps.len() - used.len(),
+    })
+}
+
+/// How early or late a tap at the given time (since the first tick) was, against whichever tick
+/// it's closest to. Returns None if that's one of the count-in ticks, or past the last one.
+fn tap_error(time: f32) -> Option<f32> {
+    let beat = (time / BEAT_LENGTH).round();
+    let counted = COUNT_IN_BEATS as f32..(COUNT_IN_BEATS + TAP_BEATS) as f32;
+    counted.contains(&beat).then_some(time - beat * BEAT_LENGTH)
+}
+
+/// The offset calibration screen.
+pub struct CalibrationScreen {
+    tick: StaticSoundData,
+    /// Ticks once a beat, to play the ticks on. Dropping it stops any ticks that haven't played.
+    clock: Option<ClockHandle>,
+    /// The instant the clock was started, at the first tick.
+    start: Instant,
+    /// Taps that haven't been counted yet, with the time since the first tick each one was made
+    /// at, the same way taiko mode queues up hits.
+    pending_taps: InputQueue,
+    /// How early or late each counted tap was.
+    taps: TapStatistics,
+    /// Once the last tick has gone by, the offset the taps came to (if they came to one).
+    result: Option<Option<Calibration>>,
+    /// Whether the result has been saved as the global offset.
+    saved: bool,
+    try_again: bool,
+    exit: bool,
+}
+
+impl CalibrationScreen {
+    pub fn new(audio: &mut AudioManager) -> anyhow::Result<Self> {
+        let mut screen = Self {
+            tick: metronome_tick(),
+            clock: None,
+            start: Instant::now(),
+            pending_taps: InputQueue::new(),
+            taps: TapStatistics::new(TAP_BEATS as usize),
+            result: None,
+            saved: false,
+            try_again: false,
+            exit: false,
+        };
+        screen.start_ticking(audio)?;
+        Ok(screen)
+    }
+
+    /// Schedules every tick and starts the clock, forgetting any taps from before.
+    fn start_ticking(&mut self, audio: &mut AudioManager) -> anyhow::Result<()> {
+        // Any ticks left from last time stop with their clock
+        self.clock = None;
+
+        let clock = audio.add_clock(ClockSpeed::SecondsPerTick(BEAT_LENGTH as f64))?;
+        for beat in 0..COUNT_IN_BEATS + TAP_BEATS {
+            let start_time = clock.time() + beat as u64;
+            audio.play(
+                self.tick
+                    .with_modified_settings(|settings| settings.start_time(start_time)),
+            )?;
+        }
+        clock.start()?;
+
+        self.start = Instant::now();
+        self.clock = Some(clock);
+        self.pending_taps.clear();
+        self.taps = TapStatistics::new(TAP_BEATS as usize);
+        self.result = None;
+        self.saved = false;
+        Ok(())
+    }
+
+    fn elapsed(&self) -> f32 {
+        self.start.elapsed().as_secs_f32()
+    }
+
+    /// Draws the beats coming towards a line, which they cross on the tick. The count-in beats are
+    /// grey.
+    fn beat_strip_ui(&self, ui: &mut egui::Ui) {
+        let (rect, _) = ui.allocate_exact_size(STRIP_SIZE.into(), egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 4.0, egui::Color32::from_gray(30));
+
+        let line_x = rect.left() + rect.width() * 0.2;
+        painter.vline(
+            line_x,
+            rect.y_range(),
+            egui::Stroke::new(2.0, egui::Color32::WHITE),
+        );
+
+        let now = self.elapsed();
+        let pixels_per_second = (rect.right() - line_x) / STRIP_LOOKAHEAD;
+        for beat in 0..COUNT_IN_BEATS + TAP_BEATS {
+            let x = line_x + (beat as f32 * BEAT_LENGTH - now) * pixels_per_second;
+            let colour = if beat < COUNT_IN_BEATS {
+                egui::Color32::from_gray(120)
+            } else {
+                egui::Color32::from_rgb(0xF8, 0x48, 0x28)
+            };
+
+            painter.circle_filled(egui::pos2(x, rect.center().y), rect.height() / 4.0, colour);
+        }
+    }
+
+    fn result_ui(&mut self, ui: &mut egui::Ui, result: Option<Calibration>) {
+        let Some(calibration) = result else {
+            ui.label(
+                "There weren't enough taps close enough together to work out an offset. Try \
+                again?",
+            );
+            return;
+        };
+
+        let offset_ms = (calibration.offset * 1000.0).round();
+        let timing = if offset_ms >= 0.0 { "late" } else { "early" };
+        ui.label(
+            RichText::new(format!("You tap {}ms {timing}", offset_ms.abs()))
+                .size(24.0)
+                .strong(),
+        );
+        ui.label(format!(
+            "Give or take {:.1}ms, from {} taps ({} left out)",
+            calibration.spread * 1000.0,
+            calibration.taps_used,
+            calibration.taps_rejected
+        ));
+
+        if self.saved {
+            ui.label(format!("Saved! Your note offset is now {offset_ms:+}ms."));
+        } else if ui
+            .button(format!("Save {offset_ms:+}ms as the note offset"))
+            .clicked()
+        {
+            SETTINGS.write().unwrap().game.global_note_offset = offset_ms;
+            save_settings().or_log("couldn't save settings");
+            self.saved = true;
+        }
+    }
+}
+
+impl GameState for CalibrationScreen {
+    fn update(&mut self, ctx: &mut Context, _delta_time: f32) -> StateTransition {
+        if ctx
+            .keyboard
+            .is_just_pressed(PhysicalKey::Code(KeyCode::Escape))
+        {
+            self.exit = true;
+        }
+
+        if self.try_again {
+            self.try_again = false;
+            self.start_ticking(ctx.audio)
+                .or_log("couldn't start the metronome");
+        }
+
+        while let Some((_, time)) = self.pending_taps.pop() {
+            if let Some(error) = tap_error(time) {
+                self.taps.push(error);
+            }
+        }
+
+        // Late taps on the last tick can still count until halfway to the next one
+        let end = (COUNT_IN_BEATS + TAP_BEATS) as f32 * BEAT_LENGTH - BEAT_LENGTH / 2.0;
+        if self.result.is_none() && self.elapsed() >= end {
+            self.result = Some(calibrate(&self.taps));
+        }
+
+        if self.exit {
+            StateTransition::Pop
+        } else {
+            StateTransition::Continue
+        }
+    }
+
+    fn debug_ui(&mut self, ctx: egui::Context, _audio: &mut AudioManager) {
+        egui::Window::new("Offset calibration").show(&ctx, |ui| {
+            ui.label(format!(
+                "Tap a drum key on every tick. The first {COUNT_IN_BEATS} (the grey ones) are \
+                just to get the rhythm."
+            ));
+            ui.label(format!(
+                "Current note offset: {:+}ms",
+                settings().game.global_note_offset
+            ));
+            ui.add_space(10.0);
+
+            match self.result {
+                None => {
+                    self.beat_strip_ui(ui);
+
+                    let beat = (self.elapsed() / BEAT_LENGTH).floor() as i64;
+                    let status = if beat < COUNT_IN_BEATS as i64 {
+                        format!("Get ready... {}", COUNT_IN_BEATS as i64 - beat.max(0))
+                    } else {
+                        format!("Taps: {} / {TAP_BEATS}", self.taps.len())
+                    };
+                    ui.label(RichText::new(status).size(20.0));
+                }
+
+                Some(result) => {
+                    self.result_ui(ui, result);
+
+                    if ui.button("Try again").clicked() {
+                        self.try_again = true;
+                    }
+                }
+            }
+
+            ui.add_space(20.0);
+
+            if ui.button(RichText::new("back").size(20.0)).clicked() {
+                self.exit = true;
+            }
+        });
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &WindowEvent) {
+        if let &WindowEvent::KeyboardInput { event, .. } = &event {
+            let key = event.physical_key;
+            let pressed = event.state == ElementState::Pressed && !ctx.keyboard.is_pressed(key);
+
+            let input = settings().game.key_mappings.drum_input(key);
+            if let (Some(input), true) = (input, pressed && self.result.is_none()) {
+                self.pending_taps.push(input, self.elapsed());
+            }
+        }
+    }
+
+    fn ducks_music(&self) -> bool {
+        true
+    }
+
+    fn control_hints(&self) -> Vec<(Action, &str)> {
+        vec![
+            (Action::Don, "Tap along"),
+            (Action::Kat, "Tap along"),
+            (Action::Back, "Back"),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{a} != {b}");
+    }
+
+    fn taps(errors: &[f32]) -> TapStatistics {
+        let mut taps = TapStatistics::new(TAP_BEATS as usize);
+        for &error in errors {
+            taps.push(error);
+        }
+        taps
+    }
+
+    #[test]
+    fn test_calibrate() {
+        // Taps that are all a bit late, by a steady amount
+        let mut errors: Vec<f32> = (0..20)
+            .map(|i| 0.030 + if i % 2 == 0 { 0.004 } else { -0.004 })
+            .collect();
+        let calibration = calibrate(&taps(&errors)).unwrap();
+        assert_close(calibration.offset, 0.030);
+        assert_close(calibration.spread, 0.004);
+        assert_eq!(calibration.taps_rejected, 0);
+
+        // One badly mistimed tap doesn't move it (a mean would have moved 20ms)
+        errors[5] = -0.400;
+        let calibration = calibrate(&taps(&errors)).unwrap();
+        assert_eq!(calibration.taps_rejected, 1);
+        assert_eq!(calibration.taps_used, 19);
+        assert!((calibration.offset - 0.030).abs() < 0.005);
+    }
+
+    #[test]
+    fn test_calibrate_needs_enough_taps() {
+        assert_eq!(calibrate(&taps(&[])), None);
+        assert_eq!(calibrate(&taps(&[0.01; MIN_TAPS - 1])), None);
+        assert!(calibrate(&taps(&[0.01; MIN_TAPS])).is_some());
+
+        // Taps all over the place can't be trusted
+        let scattered: Vec<f32> = (0..20).map(|i| (i as f32 - 10.0) * 0.02).collect();
+        assert_eq!(calibrate(&taps(&scattered)), None);
+    }
+
+    #[test]
+    fn test_tap_error() {
+        let first_counted = COUNT_IN_BEATS as f32 * BEAT_LENGTH;
+
+        // Taps during the count-in don't count, even if they're close to a counted tick
+        assert_eq!(tap_error(0.0), None);
+        assert_eq!(tap_error(first_counted - BEAT_LENGTH), None);
+
+        assert_close(tap_error(first_counted + 0.02).unwrap(), 0.02);
+        assert_close(tap_error(first_counted - 0.02).unwrap(), -0.02);
+
+        // Nor do taps after the last tick
+        let after_last = (COUNT_IN_BEATS + TAP_BEATS) as f32 * BEAT_LENGTH;
+        assert_eq!(tap_error(after_last), None);
+    }
+}
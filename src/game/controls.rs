@@ -0,0 +1,207 @@
+//! The help bar at the bottom of the screen, which shows what the keys do on the current screen.
+//!
+//! Each state says which actions are relevant to it with
+//! [GameState::control_hints](super::GameState::control_hints). The keys shown for each action are
+//! looked up from the settings every frame, so rebinding a key shows up straight away.
+use kaku::{FontSize, HorizontalAlignment, Text, TextBuilder, VerticalAlignment};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use crate::game::layout::{safe_area_inset, HINT_BAR_HEIGHT, HINT_TEXT, SCREEN_HEIGHT};
+use crate::render::shapes::{Shape, ShapeBuilder, SolidColour};
+use crate::render::text::BuildTextWithRenderer;
+use crate::render::{Renderable, Renderer};
+use crate::settings::{settings, DrumInput, KeyMap};
+
+const BAR_COL: [f32; 4] = [0., 0., 0., 0.6];
+const HINT_TEXT_SIZE: f32 = 22.;
+/// How wide the hints can get before they're cut short. This leaves room for the version text on
+/// the right.
+const HINT_MAX_WIDTH: f32 = 1400.;
+const HINT_FONTS: [&str; 1] = ["mplus regular"];
+/// The space between each hint.
+const HINT_SEPARATOR: &str = "     ";
+
+/// Something the player can do with the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Don,
+    Kat,
+    /// Moving up and down a list.
+    Scroll,
+    /// Jumping between groups of songs in the song list.
+    JumpGroup,
+    Undo,
+    Back,
+    /// Starting the song again from the beginning.
+    Restart,
+    /// Moving back and forward through the song in practice mode.
+    Seek,
+    /// Moving back and forward through the song's sections in practice mode.
+    SeekSection,
+    /// Setting where the song loops in practice mode.
+    Loop,
+    /// Showing when the player makes the most mistakes in practice mode.
+    WorstSpot,
+}
+
+impl Action {
+    /// The names of the keys that do this action, given the player's key bindings.
+    pub fn key_names(&self, key_map: &KeyMap) -> Vec<String> {
+        let drum_keys = |inputs: [DrumInput; 2]| {
+            inputs
+                .into_iter()
+                .map(|input| key_name(key_map.key_for(input)))
+                .collect()
+        };
+
+        match self {
+            Action::Don => drum_keys([DrumInput::LeftDon, DrumInput::RightDon]),
+            Action::Kat => drum_keys([DrumInput::LeftKat, DrumInput::RightKat]),
+            Action::Scroll => vec!["Up".to_string(), "Down".to_string()],
+            Action::JumpGroup => vec!["PgUp".to_string(), "PgDn".to_string()],
+            Action::Undo => vec!["Ctrl+Z".to_string()],
+            Action::Back => vec!["Esc".to_string()],
+            Action::Restart => vec![key_name(key_map.quick_restart)],
+            Action::Seek => vec!["Left".to_string(), "Right".to_string()],
+            Action::SeekSection => vec!["PgUp".to_string(), "PgDn".to_string()],
+            Action::Loop => vec!["L".to_string()],
+            Action::WorstSpot => vec!["H".to_string()],
+        }
+    }
+}
+
+/// A short name for a key, for showing to the player.
+pub fn key_name(key: PhysicalKey) -> String {
+    let PhysicalKey::Code(code) = key else {
+        return "?".to_string();
+    };
+
+    match code {
+        KeyCode::Escape => "Esc".to_string(),
+        KeyCode::Space => "Space".to_string(),
+        KeyCode::Semicolon => ";".to_string(),
+        KeyCode::Comma => ",".to_string(),
+        KeyCode::Period => ".".to_string(),
+        KeyCode::Slash => "/".to_string(),
+        KeyCode::ShiftLeft => "LShift".to_string(),
+        KeyCode::ShiftRight => "RShift".to_string(),
+        code => {
+            let name = format!("{code:?}");
+            ["Key", "Digit", "Arrow"]
+                .iter()
+                .find_map(|prefix| name.strip_prefix(prefix))
+                .map_or(name.clone(), str::to_string)
+        }
+    }
+}
+
+/// Puts hints together into the line shown in the help bar, e.g. "F/J Hit     Esc Quit".
+pub fn format_hints(hints: &[(Action, &str)], key_map: &KeyMap) -> String {
+    hints
+        .iter()
+        .map(|(action, description)| {
+            format!("{} {description}", action.key_names(key_map).join("/"))
+        })
+        .collect::<Vec<_>>()
+        .join(HINT_SEPARATOR)
+}
+
+/// The bar at the bottom of the screen that shows the current state's control hints.
+pub struct ControlHintBar {
+    background: Shape,
+    text: Text,
+    /// How wide the hints can get, after making room for the safe area margin.
+    max_width: f32,
+    /// The hints that are being shown, so the text is only rebuilt when they change.
+    shown: String,
+}
+
+impl ControlHintBar {
+    pub fn new(renderer: &mut Renderer) -> anyhow::Result<Self> {
+        let margin = settings().visual.safe_area_margin();
+        let [inset_x, inset_y] = safe_area_inset(margin);
+
+        // The bar grows down to fill the margin below it, so it doesn't look like it's floating
+        let top = SCREEN_HEIGHT - HINT_BAR_HEIGHT - inset_y;
+        let background = ShapeBuilder::new()
+            .filled_rectangle([0., top], [1920., SCREEN_HEIGHT], SolidColour::new(BAR_COL))?
+            .build(&renderer.device);
+
+        let text = TextBuilder::new("", renderer.font(HINT_FONTS[0]), HINT_TEXT.position(margin))
+            .horizontal_align(HorizontalAlignment::Left)
+            .vertical_align(VerticalAlignment::Middle)
+            .font_size(Some(FontSize::Px(HINT_TEXT_SIZE)))
+            .color([1.0; 4])
+            .build_text(renderer);
+
+        Ok(Self {
+            background,
+            text,
+            max_width: HINT_MAX_WIDTH - 2. * inset_x,
+            shown: String::new(),
+        })
+    }
+
+    /// Shows the given hints, or hides the bar if there aren't any.
+    pub fn update(&mut self, hints: &[(Action, &str)], key_map: &KeyMap, renderer: &mut Renderer) {
+        let line = format_hints(hints, key_map);
+        if line == self.shown {
+            return;
+        }
+
+        let (_, fitted) = renderer.fit_text(&HINT_FONTS, &line, HINT_TEXT_SIZE, 0., self.max_width);
+        self.text.set_text(
+            fitted,
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+        self.shown = line;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shown.is_empty()
+    }
+}
+
+impl Renderable for ControlHintBar {
+    fn render<'pass>(
+        &'pass self,
+        renderer: &'pass Renderer,
+        render_pass: &mut wgpu::RenderPass<'pass>,
+    ) {
+        self.background.render(renderer, render_pass);
+        self.text.render(renderer, render_pass);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_key_names() {
+        assert_eq!(key_name(PhysicalKey::Code(KeyCode::KeyF)), "F");
+        assert_eq!(key_name(PhysicalKey::Code(KeyCode::Digit1)), "1");
+        assert_eq!(key_name(PhysicalKey::Code(KeyCode::ArrowLeft)), "Left");
+        assert_eq!(key_name(PhysicalKey::Code(KeyCode::Semicolon)), ";");
+        assert_eq!(key_name(PhysicalKey::Code(KeyCode::F5)), "F5");
+    }
+
+    #[test]
+    fn test_hints_follow_rebinds() {
+        let mut key_map = KeyMap::default();
+        let hints = [(Action::Don, "Hit"), (Action::Back, "Quit")];
+        assert_eq!(format_hints(&hints, &key_map), "F/J Hit     Esc Quit");
+
+        key_map.left_don = PhysicalKey::Code(KeyCode::KeyV);
+        key_map.right_don = PhysicalKey::Code(KeyCode::KeyN);
+        assert_eq!(format_hints(&hints, &key_map), "V/N Hit     Esc Quit");
+
+        assert_eq!(format_hints(&[], &key_map), "");
+
+        let hints = [(Action::Restart, "Restart")];
+        key_map.quick_restart = PhysicalKey::Code(KeyCode::Backspace);
+        assert_eq!(format_hints(&hints, &key_map), "Backspace Restart");
+    }
+}
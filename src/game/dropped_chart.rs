@@ -0,0 +1,421 @@
+//! Playing a chart that was dropped onto the window, without adding it to the song library.
+//!
+//! The chart is read straight from wherever it was dropped, and its audio is played from next to
+//! it. Plays of it aren't saved, since the song library is where the game keeps track of songs.
+//! If the player likes the chart, they can add it to the library, which copies the tja file and
+//! its audio into a new directory in the songs directory.
+use std::path::{Component, Path, PathBuf};
+
+use egui::RichText;
+use kira::manager::AudioManager;
+use kira::sound::static_sound::StaticSoundSettings;
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use crate::game::audio::{resolve_audio_path, song_volume};
+use crate::game::song_select::{resolve_file_paths, DIFFICULTY_NAMES, SONGS_DIR};
+use crate::game::taiko_mode::{PlayModifiers, SongAssets, TaikoMode};
+use crate::game::{Action, Context, GameState, StateTransition};
+use crate::notechart_parser::{parse_tja_file_with_options, ParseOptions, Song};
+use crate::settings::settings;
+
+/// The name given to a chart's directory if its tja file doesn't have a usable name.
+const FALLBACK_DIR_NAME: &str = "Dropped chart";
+
+/// Whether the given path looks like a tja file.
+pub fn is_tja_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("tja"))
+}
+
+/// A chart that was read from outside the song library.
+pub struct DroppedChart {
+    pub tja_path: PathBuf,
+    /// The song, with its audio filenames resolved relative to the tja file.
+    pub song: Song,
+    /// The audio files the chart uses, as they're written in the tja file.
+    audio_files: Vec<String>,
+}
+
+impl DroppedChart {
+    /// Reads the tja file at the given path. The parser is as lenient as it can be, since the
+    /// chart hasn't been checked by being put in the library.
+    pub fn read(tja_path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(tja_path)?;
+        let options = ParseOptions {
+            comment_labels: true,
+            lenient: true,
+            max_subdivisions: settings().game.max_measure_subdivisions,
+        };
+        let mut song = parse_tja_file_with_options(&contents, options)?;
+
+        let mut audio_files = vec![song.audio_filename.clone()];
+        for filename in song
+            .difficulties
+            .iter()
+            .flatten()
+            .filter_map(|difficulty| difficulty.audio_filename.clone())
+        {
+            if !audio_files.contains(&filename) {
+                audio_files.push(filename);
+            }
+        }
+
+        resolve_file_paths(&mut song, tja_path.parent().unwrap_or(Path::new("")));
+
+        Ok(Self {
+            tja_path: tja_path.to_path_buf(),
+            song,
+            audio_files,
+        })
+    }
+
+    /// Copies the chart into the given songs directory, along with the audio files it uses, and
+    /// returns the directory it was copied to.
+    ///
+    /// The directory is named after the tja file, with a number added if that name is taken.
+    pub fn add_to_library(&self, songs_dir: &Path) -> anyhow::Result<PathBuf> {
+        // The audio has to end up in the same place relative to the tja file
+        if let Some(outside) = self.audio_files.iter().find(|file| {
+            !Path::new(file)
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        }) {
+            anyhow::bail!("the chart uses audio from outside its folder (\"{outside}\")");
+        }
+
+        let name = self
+            .tja_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().trim().to_string())
+            .filter(|stem| !stem.is_empty())
+            .unwrap_or_else(|| FALLBACK_DIR_NAME.to_string());
+
+        let dir = library_dir(songs_dir, &name);
+        let dir_name = dir.file_name().unwrap_or_default().to_string_lossy();
+        let source_dir = self.tja_path.parent().unwrap_or(Path::new(""));
+
+        std::fs::create_dir_all(&dir)?;
+        std::fs::copy(&self.tja_path, dir.join(format!("{dir_name}.tja")))?;
+
+        for file in &self.audio_files {
+            // If the chart has the extension wrong, the file is copied with its real one, so it's
+            // found the same way in the library
+            let source = resolve_audio_path(&source_dir.join(file), Path::exists);
+            let destination = match source.file_name() {
+                Some(name) => dir.join(file).with_file_name(name),
+                None => dir.join(file),
+            };
+            if let Some(parent) = destination.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            std::fs::copy(source, destination)?;
+        }
+
+        Ok(dir)
+    }
+}
+
+/// Returns a directory in the songs directory with the given name that doesn't exist yet. If the
+/// name is taken, a number is added to the end of it, e.g. "Song (2)".
+pub fn library_dir(songs_dir: &Path, name: &str) -> PathBuf {
+    (1..)
+        .map(|i| match i {
+            1 => songs_dir.join(name),
+            i => songs_dir.join(format!("{name} ({i})")),
+        })
+        .find(|path| !path.exists())
+        .expect("ran out of numbers")
+}
+
+/// The difficulty panel for a dropped chart, or the reason it couldn't be read.
+pub struct DroppedChartScreen {
+    chart: Result<DroppedChart, String>,
+    difficulty: usize,
+    /// Where the chart was copied to, if the player added it to the library.
+    library_dir: Option<PathBuf>,
+    /// Something that went wrong after the chart was read, e.g. its audio couldn't be loaded.
+    error: Option<String>,
+    play: bool,
+    exit: bool,
+}
+
+impl DroppedChartScreen {
+    pub fn new(tja_path: &Path) -> Self {
+        let chart = DroppedChart::read(tja_path)
+            .map_err(|e| format!("Couldn't read \"{}\": {e}", tja_path.display()));
+
+        // Start on the hardest difficulty the chart has
+        let difficulty = chart
+            .as_ref()
+            .ok()
+            .and_then(|chart| chart.song.difficulties.iter().rposition(Option::is_some))
+            .unwrap_or(0);
+
+        Self {
+            chart,
+            difficulty,
+            library_dir: None,
+            error: None,
+            play: false,
+            exit: false,
+        }
+    }
+
+    fn start_song(&self, ctx: &mut Context, chart: &DroppedChart) -> anyhow::Result<TaikoMode> {
+        let assets = SongAssets::load(
+            chart.song.course_audio_filename(self.difficulty),
+            StaticSoundSettings::default()
+                .volume(song_volume(0.0, chart.song.song_volume))
+                .output_destination(ctx.music_track),
+        )?;
+
+        let scene = TaikoMode::new(
+            &chart.song,
+            assets,
+            ctx.audio,
+            self.difficulty,
+            PlayModifiers::default(),
+            ctx.renderer,
+            ctx.textures,
+        )?;
+
+        // Once it's in the library, the play can be saved the next time it's played from there
+        Ok(scene.without_saving_play())
+    }
+
+    fn add_to_library(&mut self) {
+        let Ok(chart) = &self.chart else {
+            return;
+        };
+
+        match chart.add_to_library(Path::new(SONGS_DIR)) {
+            Ok(dir) => self.library_dir = Some(dir),
+            Err(e) => self.error = Some(format!("Couldn't add the chart to the library: {e}")),
+        }
+    }
+}
+
+impl GameState for DroppedChartScreen {
+    fn update(&mut self, ctx: &mut Context, _delta_time: f32) -> StateTransition {
+        if self.exit
+            || ctx
+                .keyboard
+                .is_just_pressed(PhysicalKey::Code(KeyCode::Escape))
+        {
+            return StateTransition::Pop;
+        }
+
+        if !std::mem::take(&mut self.play) {
+            return StateTransition::Continue;
+        }
+
+        let Ok(chart) = &self.chart else {
+            return StateTransition::Continue;
+        };
+
+        match self.start_song(ctx, chart) {
+            Ok(scene) => StateTransition::Push(Box::new(scene)),
+            Err(e) => {
+                self.error = Some(format!("Couldn't start the song: {e}"));
+                StateTransition::Continue
+            }
+        }
+    }
+
+    fn debug_ui(&mut self, ctx: egui::Context, _audio: &mut AudioManager) {
+        egui::Window::new("dropped chart")
+            .title_bar(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(&ctx, |ui| {
+                let chart = match &self.chart {
+                    Ok(chart) => chart,
+                    Err(message) => {
+                        ui.label(RichText::new("This chart couldn't be read").size(25.0));
+                        ui.label(RichText::new(message).size(17.0));
+                        self.exit = ui.button(RichText::new("Back").size(20.0)).clicked();
+                        return;
+                    }
+                };
+
+                let title = chart.song.display_title(settings().visual.romanised_titles);
+                ui.label(RichText::new(title).size(25.0).strong());
+
+                match &self.library_dir {
+                    Some(dir) => ui.label(format!("Added to the library in \"{}\"", dir.display())),
+                    None => ui.label(RichText::new("Not in library").italics()),
+                };
+
+                ui.add_space(10.0);
+
+                ui.horizontal(|ui| {
+                    for (i, difficulty) in chart
+                        .song
+                        .difficulties
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(i, d)| Some((i, d.as_ref()?)))
+                    {
+                        let level = match difficulty.level(settings().game.prefer_estimated_levels)
+                        {
+                            Some((level, false)) => level.to_string(),
+                            Some((level, true)) => format!("~{level}"),
+                            None => "?".to_string(),
+                        };
+
+                        ui.selectable_value(
+                            &mut self.difficulty,
+                            i,
+                            RichText::new(format!("{}\n{level}★", DIFFICULTY_NAMES[i])).size(20.0),
+                        );
+                    }
+                });
+
+                if ui.button(RichText::new("Play!").size(17.0)).clicked() {
+                    self.play = true;
+                }
+
+                if self.library_dir.is_none()
+                    && ui
+                        .button("Add to library")
+                        .on_hover_text(
+                            "Copies the chart and its audio into the songs folder, so your plays \
+                            of it are saved",
+                        )
+                        .clicked()
+                {
+                    self.add_to_library();
+                }
+
+                if let Some(error) = &self.error {
+                    ui.label(RichText::new(error).color(egui::Color32::from_rgb(255, 80, 80)));
+                }
+
+                self.exit = ui.button("Back").clicked();
+            });
+    }
+
+    fn ducks_music(&self) -> bool {
+        // The song select's preview keeps playing underneath this
+        true
+    }
+
+    fn control_hints(&self) -> Vec<(Action, &str)> {
+        vec![(Action::Back, "Back")]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    const TJA: &str =
+        "TITLE:Dropped\nWAVE:audio/song.ogg\nCOURSE:Oni\nLEVEL:5\n\n#START\n1111,\n#END\n";
+
+    /// Writes a chart and its audio to the given directory, and returns the path of the tja file.
+    fn write_chart(dir: &Path, tja: &str) -> PathBuf {
+        let tja_path = dir.join("My Chart.tja");
+        std::fs::write(&tja_path, tja).unwrap();
+        std::fs::create_dir_all(dir.join("audio")).unwrap();
+        std::fs::write(dir.join("audio/song.ogg"), "not really audio").unwrap();
+        tja_path
+    }
+
+    #[test]
+    fn test_read_dropped_chart() {
+        let dir = temp_dir("dropped_read");
+        let chart = DroppedChart::read(&write_chart(&dir, TJA)).unwrap();
+
+        assert_eq!(chart.song.title, "Dropped");
+        assert_eq!(
+            Path::new(&chart.song.audio_filename),
+            dir.join("audio/song.ogg")
+        );
+
+        std::fs::write(dir.join("Broken.tja"), "TITLE:Broken\n#START\n1111,\n").unwrap();
+        let error = DroppedChart::read(&dir.join("Broken.tja")).err().unwrap();
+        assert!(error.to_string().contains("line"), "{error}");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_add_to_library() {
+        let dir = temp_dir("dropped_library");
+        let songs_dir = dir.join("songs");
+        let downloads = dir.join("downloads");
+        std::fs::create_dir_all(&downloads).unwrap();
+
+        let chart = DroppedChart::read(&write_chart(&downloads, TJA)).unwrap();
+
+        // The copy has to be readable the same way as everything else in the library
+        let first = chart.add_to_library(&songs_dir).unwrap();
+        assert_eq!(first, songs_dir.join("My Chart"));
+        assert!(first.join("My Chart.tja").exists());
+        assert!(first.join("audio/song.ogg").exists());
+
+        // Adding it again doesn't overwrite the first copy
+        let second = chart.add_to_library(&songs_dir).unwrap();
+        assert_eq!(second, songs_dir.join("My Chart (2)"));
+        assert!(second.join("My Chart (2).tja").exists());
+        assert_eq!(
+            library_dir(&songs_dir, "My Chart"),
+            songs_dir.join("My Chart (3)")
+        );
+
+        let song = crate::game::song_select::read_song_dir(&second).unwrap();
+        assert_eq!(song.title, "Dropped");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_add_with_wrong_audio_extension() {
+        let dir = temp_dir("dropped_wrong_extension");
+        let songs_dir = dir.join("songs");
+        let downloads = dir.join("downloads");
+        std::fs::create_dir_all(&downloads).unwrap();
+
+        // The chart says the audio is an ogg, but it's really an mp3
+        let tja_path = write_chart(&downloads, TJA);
+        std::fs::rename(
+            downloads.join("audio/song.ogg"),
+            downloads.join("audio/song.mp3"),
+        )
+        .unwrap();
+        let chart = DroppedChart::read(&tja_path).unwrap();
+        assert_eq!(
+            Path::new(&chart.song.audio_filename),
+            downloads.join("audio/song.mp3")
+        );
+
+        let copy = chart.add_to_library(&songs_dir).unwrap();
+        assert!(copy.join("audio/song.mp3").exists());
+        let song = crate::game::song_select::read_song_dir(&copy).unwrap();
+        assert_eq!(Path::new(&song.audio_filename), copy.join("audio/song.mp3"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_audio_outside_chart_folder() {
+        let dir = temp_dir("dropped_outside");
+        let tja = TJA.replace("audio/song.ogg", "../song.ogg");
+        let chart = DroppedChart::read(&write_chart(&dir, &tja)).unwrap();
+
+        assert!(chart.add_to_library(&dir.join("songs")).is_err());
+        assert!(!dir.join("songs/My Chart").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_tja_file() {
+        assert!(is_tja_file(Path::new("songs/a/a.tja")));
+        assert!(is_tja_file(Path::new("A.TJA")));
+        assert!(!is_tja_file(Path::new("a.ogg")));
+        assert!(!is_tja_file(Path::new("tja")));
+    }
+}
@@ -0,0 +1,165 @@
+//! Keeps track of how long frames are taking, so we can tell whether a bad play was down to the
+//! player or the machine.
+//!
+//! This is updated every frame, so it's a fixed-size histogram that never allocates.
+use serde::{Deserialize, Serialize};
+
+/// The width of each bucket in the histogram, in milliseconds.
+const BUCKET_WIDTH: f32 = 0.5;
+/// The number of buckets. Anything longer than the last bucket goes in the last bucket.
+const NUM_BUCKETS: usize = 200;
+
+/// Frames that take longer than this (in milliseconds) are counted as slow.
+pub const SLOW_FRAME_TIME: f32 = 25.0;
+/// If the 99th percentile frame time (in milliseconds) is above this, the player is warned that
+/// performance might have affected their timing.
+pub const P99_WARNING_THRESHOLD: f32 = 20.0;
+
+/// A histogram of frame times.
+#[derive(Debug, Clone)]
+pub struct FrameTimeHistogram {
+    buckets: [u32; NUM_BUCKETS],
+    frames: u32,
+    slow_frames: u32,
+}
+
+impl Default for FrameTimeHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameTimeHistogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: [0; NUM_BUCKETS],
+            frames: 0,
+            slow_frames: 0,
+        }
+    }
+
+    /// Forgets every frame recorded so far.
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Records a frame that took the given number of seconds.
+    pub fn record(&mut self, delta_time: f32) {
+        let millis = delta_time * 1000.0;
+        let bucket = ((millis / BUCKET_WIDTH) as usize).min(NUM_BUCKETS - 1);
+
+        self.buckets[bucket] += 1;
+        self.frames += 1;
+
+        if millis > SLOW_FRAME_TIME {
+            self.slow_frames += 1;
+        }
+    }
+
+    /// Returns the frame time (in milliseconds) that the given proportion of frames (from 0 to 1)
+    /// were no longer than, or None if no frames have been recorded.
+    ///
+    /// This is only as precise as the buckets, so it gives the upper edge of the bucket the
+    /// percentile falls in.
+    pub fn percentile(&self, p: f32) -> Option<f32> {
+        if self.frames == 0 {
+            return None;
+        }
+
+        let target = ((self.frames as f32 * p).ceil() as u32).max(1);
+        let mut seen = 0;
+
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Some((i + 1) as f32 * BUCKET_WIDTH);
+            }
+        }
+
+        Some(NUM_BUCKETS as f32 * BUCKET_WIDTH)
+    }
+
+    /// Summarises the frames recorded so far, or returns None if there haven't been any.
+    pub fn stats(&self) -> Option<FrameStats> {
+        Some(FrameStats {
+            p50: self.percentile(0.5)?,
+            p95: self.percentile(0.95)?,
+            p99: self.percentile(0.99)?,
+            slow_frames: self.slow_frames,
+        })
+    }
+}
+
+/// A summary of how long frames took during a play. Times are in milliseconds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct FrameStats {
+    pub p50: f32,
+    pub p95: f32,
+    pub p99: f32,
+    /// The number of frames that took longer than [SLOW_FRAME_TIME].
+    pub slow_frames: u32,
+}
+
+impl FrameStats {
+    /// Whether frames were slow enough that the player's timing might have suffered.
+    pub fn may_have_affected_timing(&self) -> bool {
+        self.p99 > P99_WARNING_THRESHOLD
+    }
+}
+
+impl std::fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "frame times {:.1}/{:.1}/{:.1}ms (p50/p95/p99), {} over {:.0}ms",
+            self.p50, self.p95, self.p99, self.slow_frames, SLOW_FRAME_TIME
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_empty_histogram() {
+        let histogram = FrameTimeHistogram::new();
+        assert_eq!(histogram.percentile(0.5), None);
+        assert_eq!(histogram.stats(), None);
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut histogram = FrameTimeHistogram::new();
+
+        // 98 frames at 60fps and 2 long hitches
+        for _ in 0..98 {
+            histogram.record(1.0 / 60.0);
+        }
+        histogram.record(0.030);
+        histogram.record(0.040);
+
+        let stats = histogram.stats().unwrap();
+        assert_eq!(stats.p50, 17.0);
+        assert_eq!(stats.p95, 17.0);
+        assert_eq!(stats.p99, 30.5);
+        assert_eq!(stats.slow_frames, 2);
+        assert!(stats.may_have_affected_timing());
+
+        histogram.clear();
+        histogram.record(1.0 / 144.0);
+        let stats = histogram.stats().unwrap();
+        assert_eq!(stats.p99, 7.0);
+        assert_eq!(stats.slow_frames, 0);
+        assert!(!stats.may_have_affected_timing());
+    }
+
+    #[test]
+    fn test_very_long_frames() {
+        // Frames longer than the histogram covers still count, in the last bucket
+        let mut histogram = FrameTimeHistogram::new();
+        histogram.record(2.0);
+        assert_eq!(histogram.percentile(1.0), Some(100.0));
+        assert_eq!(histogram.stats().unwrap().slow_frames, 1);
+    }
+}
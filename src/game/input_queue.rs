@@ -0,0 +1,37 @@
+//! Drum hits waiting to be judged, each timed as soon as its key event arrived.
+//!
+//! Key events are handled between frames, so timing a hit when the next update runs would add up
+//! to a frame of latency and jitter. Instead the state times each hit against its own clock the
+//! moment the event comes in, and queues it up for the next update. That way what the hit counts
+//! as doesn't depend on the frame rate.
+use std::collections::VecDeque;
+
+use crate::settings::DrumInput;
+
+/// Drum hits that haven't been judged yet, with the time each one was made at, in the order they
+/// were made.
+#[derive(Debug, Clone, Default)]
+pub struct InputQueue {
+    hits: VecDeque<(DrumInput, f32)>,
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues up a hit made at the given time.
+    pub fn push(&mut self, input: DrumInput, time: f32) {
+        self.hits.push_back((input, time));
+    }
+
+    /// Takes the hit that was made first out of the queue.
+    pub fn pop(&mut self) -> Option<(DrumInput, f32)> {
+        self.hits.pop_front()
+    }
+
+    /// Forgets every hit in the queue, e.g. when the clock they were timed against jumps.
+    pub fn clear(&mut self) {
+        self.hits.clear();
+    }
+}
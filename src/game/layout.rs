@@ -0,0 +1,172 @@
+//! Where things go on the screen.
+//!
+//! Everything is laid out on a 1920x1080 screen, which is scaled to fit the window. The playfield
+//! (the header's background and the note field) always spans the whole width of the screen. The
+//! HUD around it is placed with [Anchor]s instead, which are pushed in from the edges of the
+//! screen by the player's safe area margin. That way TVs and projectors that cut off the edges of
+//! the picture (overscan) don't take the HUD with them.
+
+pub const SCREEN_WIDTH: f32 = 1920.;
+pub const SCREEN_HEIGHT: f32 = 1080.;
+
+// The playfield.
+// TODO: Replace this system something more sophisticated that respects resolution
+pub const HEADER_HEIGHT: f32 = 315.;
+pub const SPACER_WIDTH: f32 = 8.;
+pub const NOTE_FIELD_Y: f32 = HEADER_HEIGHT + SPACER_WIDTH;
+// The point on the screen where notes should be hit
+pub const NOTE_HIT_X: f32 = 690.;
+// The Y value where notes should be drawn
+pub const NOTE_Y: f32 = NOTE_FIELD_Y + NOTE_FIELD_HEIGHT / 2.0;
+pub const NOTE_FIELD_HEIGHT: f32 = 232.;
+pub const LEFT_PANEL_WIDTH: f32 = 480.;
+/// How far down player 2's note field is from player 1's in a 2P battle, which puts it right
+/// underneath.
+pub const PLAYER_2_FIELD_OFFSET: f32 = NOTE_FIELD_HEIGHT + SPACER_WIDTH;
+/// How far down player 2's header is in a 2P battle. The top of it is hidden behind the note
+/// fields, so only the soul gauge, accuracy and score show, under player 2's note field.
+pub const PLAYER_2_HEADER_OFFSET: f32 = 705.;
+
+/// How far from the edges of the screen and the header text is kept.
+pub const HUD_MARGIN: f32 = 10.;
+/// The height of the help bar at the bottom of the screen.
+pub const HINT_BAR_HEIGHT: f32 = 44.;
+
+/// The corner of the screen an [Anchor] is placed relative to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// A point the HUD is placed at, which moves in from its corner of the screen as the safe area
+/// margin grows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Anchor {
+    /// Where the anchor is with no margin.
+    position: [f32; 2],
+    corner: Corner,
+}
+
+impl Anchor {
+    pub const fn new(position: [f32; 2], corner: Corner) -> Self {
+        Self { position, corner }
+    }
+
+    /// Where the anchor is with the given safe area margin (as a fraction of the screen size).
+    pub fn position(&self, margin: f32) -> [f32; 2] {
+        let [dx, dy] = safe_area_inset(margin);
+        let [x, y] = self.position;
+
+        match self.corner {
+            Corner::TopLeft => [x + dx, y + dy],
+            Corner::TopRight => [x - dx, y + dy],
+            Corner::BottomLeft => [x + dx, y - dy],
+            Corner::BottomRight => [x - dx, y - dy],
+        }
+    }
+}
+
+/// The right edge of the song title in the header.
+pub const HEADER_TITLE: Anchor = Anchor::new([1880., 20.], Corner::TopRight);
+/// The right edge of the song's subtitle in the header.
+pub const HEADER_SUBTITLE: Anchor = Anchor::new([1880., 200.], Corner::TopRight);
+/// The right edge of the accuracy in the header.
+pub const HEADER_ACCURACY: Anchor = Anchor::new([1880., 140.], Corner::TopRight);
+/// The top right of the soul gauge in the header, between the title and the accuracy.
+pub const HEADER_GAUGE: Anchor = Anchor::new([1880., 108.], Corner::TopRight);
+/// The right edge of the score in the header, under the subtitle.
+pub const HEADER_SCORE: Anchor = Anchor::new([1880., 250.], Corner::TopRight);
+/// The left edge of the badge in the header that shows a replay is playing back, level with the
+/// score.
+pub const HEADER_BADGE: Anchor = Anchor::new([40., 250.], Corner::TopLeft);
+/// The top left of the key input display shown in stream mode.
+pub const KEY_DISPLAY: Anchor = Anchor::new([40., 960.], Corner::BottomLeft);
+/// The left end of the text in the help bar.
+pub const HINT_TEXT: Anchor = Anchor::new(
+    [20., SCREEN_HEIGHT - HINT_BAR_HEIGHT / 2.],
+    Corner::BottomLeft,
+);
+/// The top left of the waveform strip shown in practice mode, just above the help bar.
+pub const PRACTICE_WAVEFORM: [f32; 2] = [40., SCREEN_HEIGHT - HINT_BAR_HEIGHT - 100.];
+/// The size of the practice mode waveform strip, which spans the screen apart from a margin.
+pub const PRACTICE_WAVEFORM_SIZE: [f32; 2] = [SCREEN_WIDTH - 80., 80.];
+/// The top left of the fps counter.
+pub const FPS_COUNTER: Anchor = Anchor::new([1800., 0.], Corner::TopRight);
+/// The bottom right of the version text.
+pub const VERSION_TEXT: Anchor = Anchor::new([1910., 1070.], Corner::BottomRight);
+
+/// How far in from the left/right and top/bottom edges of the screen the safe area starts, given
+/// the margin as a fraction of the screen size.
+pub fn safe_area_inset(margin: f32) -> [f32; 2] {
+    [SCREEN_WIDTH * margin, SCREEN_HEIGHT * margin]
+}
+
+/// Moves a piece of text centred on the given point so that all of it stays below the header and
+/// inside the safe area, even once it has floated up by `rise` pixels. `size` is the width and
+/// height of the text. Returns the new centre.
+pub fn clamp_text_centre(centre: [f32; 2], size: [f32; 2], rise: f32, margin: f32) -> [f32; 2] {
+    let [width, height] = size;
+    let [dx, dy] = safe_area_inset(margin);
+
+    let min_x = width / 2. + HUD_MARGIN + dx;
+    let max_x = SCREEN_WIDTH - width / 2. - HUD_MARGIN - dx;
+    let min_y = HEADER_HEIGHT + SPACER_WIDTH + HUD_MARGIN + height / 2. + rise;
+    let max_y = SCREEN_HEIGHT - height / 2. - HUD_MARGIN - dy;
+
+    // Text too big to fit at all is kept away from the header first, then the left edge
+    [
+        centre[0].min(max_x).max(min_x),
+        centre[1].min(max_y).max(min_y),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_no_margin_keeps_positions() {
+        assert_eq!(HEADER_TITLE.position(0.0), [1880., 20.]);
+        assert_eq!(HEADER_SUBTITLE.position(0.0), [1880., 200.]);
+        assert_eq!(HEADER_ACCURACY.position(0.0), [1880., 140.]);
+        assert_eq!(HEADER_SCORE.position(0.0), [1880., 250.]);
+        assert_eq!(HEADER_GAUGE.position(0.0), [1880., 108.]);
+        assert_eq!(HEADER_BADGE.position(0.0), [40., 250.]);
+        assert_eq!(KEY_DISPLAY.position(0.0), [40., 960.]);
+        assert_eq!(HINT_TEXT.position(0.0), [20., 1080. - 22.]);
+        assert_eq!(FPS_COUNTER.position(0.0), [1800., 0.]);
+        assert_eq!(VERSION_TEXT.position(0.0), [1910., 1070.]);
+        assert_eq!(
+            clamp_text_centre([0., 800.], [100., 20.], 0., 0.0),
+            [50. + HUD_MARGIN, 800.]
+        );
+    }
+
+    #[test]
+    fn test_player_2_header() {
+        // Player 2's gauge is clear of their note field, and their score (at its biggest, in stream
+        // mode) is clear of the help bar
+        let field_bottom = NOTE_FIELD_Y + PLAYER_2_FIELD_OFFSET + NOTE_FIELD_HEIGHT + SPACER_WIDTH;
+        assert!(HEADER_GAUGE.position(0.0)[1] + PLAYER_2_HEADER_OFFSET > field_bottom);
+
+        let score_bottom = HEADER_SCORE.position(0.0)[1] + PLAYER_2_HEADER_OFFSET + 52.;
+        assert!(score_bottom < SCREEN_HEIGHT - HINT_BAR_HEIGHT);
+    }
+
+    #[test]
+    fn test_anchors_follow_margin() {
+        // 5% of the screen is 96 pixels across and 54 down
+        assert_eq!(HEADER_TITLE.position(0.05), [1880. - 96., 20. + 54.]);
+        assert_eq!(KEY_DISPLAY.position(0.05), [40. + 96., 960. - 54.]);
+        assert_eq!(VERSION_TEXT.position(0.05), [1910. - 96., 1070. - 54.]);
+
+        // Text is kept inside the safe area too
+        assert_eq!(
+            clamp_text_centre([0., 1080.], [100., 20.], 0., 0.05),
+            [50. + HUD_MARGIN + 96., 1080. - 10. - HUD_MARGIN - 54.]
+        );
+    }
+}
@@ -2,9 +2,11 @@ use kaku::{FontSize, HorizontalAlignment, Text, TextBuilder, VerticalAlignment};
 
 use crate::{
     game::{
+        taiko_mode::TaikoMode,
         ui_elements::{Button, ButtonOptions},
         Context, GameState, RenderContext, StateTransition, TextureCache,
     },
+    local_data::local_data,
     render::{
         rgb,
         shapes::{LinearGradient, Shape, ShapeBuilder, SolidColour},
@@ -13,6 +15,7 @@ use crate::{
     },
 };
 
+use super::settings_screen::SettingsScreen;
 use super::SongSelect;
 
 pub struct MainMenu {
@@ -21,6 +24,10 @@ pub struct MainMenu {
     title: Text,
     taiko_mode_button: Button,
     settings_button: Button,
+    tutorial_button: Button,
+    /// Suggests the tutorial to players who haven't finished it yet.
+    tutorial_hint: Text,
+    show_tutorial_hint: bool,
     exit_button: Button,
 }
 
@@ -99,6 +106,32 @@ impl MainMenu {
             renderer,
         )?;
 
+        let tutorial_button = Button::new(
+            "Tutorial",
+            [120., 560.],
+            ButtonOptions {
+                colour: rgb!(0xF8, 0xB0, 0x20),
+                text_outline_colour: rgb!(0x7A, 0x4A, 0x08),
+                ..Default::default()
+            },
+            renderer,
+        )?;
+
+        let tutorial_hint = TextBuilder::new(
+            "New here? Try the tutorial!",
+            renderer.font("mochiy pop one"),
+            [340., 660.],
+        )
+        .font_size(Some(FontSize::Px(28.)))
+        .vertical_align(VerticalAlignment::Top)
+        .horizontal_align(HorizontalAlignment::Center)
+        .color(rgb!(0x72, 0x19, 0x19))
+        .build(
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+
         let exit_button = Button::new(
             "Exit",
             [120., 940.],
@@ -124,6 +157,9 @@ impl MainMenu {
             title,
             taiko_mode_button,
             settings_button,
+            tutorial_button,
+            tutorial_hint,
+            show_tutorial_hint: !local_data().stats.tutorial_completed,
             exit_button,
         })
     }
@@ -136,22 +172,52 @@ impl GameState for MainMenu {
         ctx.render(&self.title);
         ctx.render(&self.taiko_mode_button);
         ctx.render(&self.settings_button);
+        ctx.render(&self.tutorial_button);
+
+        if self.show_tutorial_hint {
+            ctx.render(&self.tutorial_hint);
+        }
+
         ctx.render(&self.exit_button);
     }
 
     fn update(&mut self, ctx: &mut Context, _delta_time: f32) -> StateTransition {
         self.taiko_mode_button.update(ctx);
         self.settings_button.update(ctx);
+        self.tutorial_button.update(ctx);
         self.exit_button.update(ctx);
 
+        // This might have changed if the player just came back from the tutorial
+        self.show_tutorial_hint = !local_data().stats.tutorial_completed;
+
         if self.taiko_mode_button.is_clicked(ctx) {
             StateTransition::Push(Box::new(
-                SongSelect::new(ctx.textures, ctx.renderer).unwrap(),
+                SongSelect::new(ctx.textures, ctx.renderer, ctx.music_track).unwrap(),
             ))
+        } else if self.settings_button.is_clicked(ctx) {
+            StateTransition::Push(Box::new(SettingsScreen::new(ctx.renderer.window)))
+        } else if self.tutorial_button.is_clicked(ctx) {
+            match TaikoMode::tutorial(ctx.audio, ctx.renderer, ctx.textures) {
+                Ok(tutorial) => StateTransition::Push(Box::new(tutorial)),
+                Err(e) => {
+                    log::error!("couldn't start the tutorial: {e}");
+                    StateTransition::Continue
+                }
+            }
         } else if self.exit_button.is_clicked(ctx) {
             StateTransition::Exit
         } else {
             StateTransition::Continue
         }
     }
+
+    fn recreate_gpu_resources(
+        &mut self,
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+    ) -> anyhow::Result<()> {
+        // Nothing on the main menu needs to survive, so it can just be built again
+        *self = MainMenu::new(textures, renderer)?;
+        Ok(())
+    }
 }
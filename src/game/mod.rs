@@ -1,18 +1,45 @@
+mod audio;
+mod calibration;
+mod controls;
 mod credits;
+mod dropped_chart;
+mod frame_stats;
+mod input_queue;
+mod layout;
 mod main_menu;
 mod score_screen;
+mod settings_screen;
+mod song_cache;
+mod song_list;
 mod song_select;
+mod song_watcher;
 mod taiko_mode;
+mod tap_stats;
+mod time;
 mod ui_elements;
+mod waveform;
 
+pub use controls::Action;
+pub use frame_stats::{FrameStats, FrameTimeHistogram};
+use image::RgbaImage;
 use kaku::{FontSize, HorizontalAlignment, Text, TextBuilder, VerticalAlignment};
 pub use main_menu::MainMenu;
-pub use song_select::SongSelect;
+pub use song_cache::SONG_CACHE_PATH;
+pub use song_select::{count_songs, find_library_song, read_chart_file, SongSelect, SONGS_DIR};
+pub use taiko_mode::{PlayConditions, Replay, ScoreInt, REPLAYS_DIR};
+pub use time::GameTime;
 
 use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::Duration;
 
+use audio::{MusicDucking, OrLog};
+use dropped_chart::{is_tja_file, DroppedChartScreen};
 use kira::manager::{backend::DefaultBackend, AudioManager};
-use std::collections::HashMap;
+use kira::track::{TrackBuilder, TrackHandle, TrackId};
+use kira::tween::Tween;
+use kira::Volume;
+use std::collections::{HashMap, HashSet};
 
 use winit::{
     event::{ElementState, KeyEvent, MouseButton, WindowEvent},
@@ -20,10 +47,46 @@ use winit::{
     keyboard::{KeyCode, PhysicalKey},
 };
 
+use crate::render::stats::{BudgetWarnings, DrawStats, DRAW_BUDGETS};
 use crate::render::{self, texture::Texture, Renderable, Renderer};
+use crate::settings::settings;
+use controls::ControlHintBar;
+use layout::{FPS_COUNTER, VERSION_TEXT};
 
 const FPS_POLL_TIME: f32 = 0.5;
-const SPRITES_PATH: &str = "assets/images";
+pub const SPRITES_PATH: &str = "assets/images";
+pub const SOUNDS_PATH: &str = "assets/sounds";
+/// Every image in [SPRITES_PATH] that the game needs.
+pub const TEXTURE_MANIFEST: &[&str] = &[
+    "balloon 1.png",
+    "balloon 3.png",
+    "balloon 5.png",
+    "balloon speech bubble.png",
+    "big_don.png",
+    "big_drumroll_start.png",
+    "big_kat.png",
+    "don.png",
+    "drumroll_start.png",
+    "kat.png",
+    "song_select_bg.jpg",
+];
+/// The textures used in every song, which are loaded when the game starts and kept for the whole
+/// game.
+const PINNED_TEXTURES: &[&str] = &[
+    "don.png",
+    "kat.png",
+    "big_don.png",
+    "big_kat.png",
+    "drumroll_start.png",
+    "big_drumroll_start.png",
+    "balloon 1.png",
+    "balloon 3.png",
+    "balloon 5.png",
+];
+/// How long the audio takes to fade out when the game closes.
+const SHUTDOWN_FADE_TIME: Duration = Duration::from_millis(200);
+/// If saving takes longer than this when the game closes, we give up and exit anyway.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
 
 pub enum StateTransition {
     Continue,
@@ -33,12 +96,27 @@ pub enum StateTransition {
     Exit,
 }
 
+/// How a state responds to the player trying to close the game.
+pub enum CloseResponse {
+    /// Go ahead and close the game.
+    Confirm,
+    /// Don't close the game. The state is expected to ask the player what they want to do, and
+    /// return [StateTransition::Exit] if they still want to close it.
+    Veto,
+}
+
 pub struct Context<'ctx> {
     pub audio: &'ctx mut AudioManager,
     pub renderer: &'ctx mut Renderer,
     pub keyboard: &'ctx KeyboardState,
     pub textures: &'ctx mut TextureCache,
     pub mouse: &'ctx MouseState,
+    pub time: &'ctx mut GameTime,
+    /// How long recent frames have taken. States that care can clear this when they start.
+    pub frame_times: &'ctx mut FrameTimeHistogram,
+    /// The track music should be played on, so it can be turned down while menus are open.
+    /// Sound effects should stay on the main track.
+    pub music_track: TrackId,
 }
 
 pub struct RenderContext<'ctx, 'pass> {
@@ -68,6 +146,50 @@ pub trait GameState {
     fn render<'pass>(&'pass mut self, _ctx: &mut RenderContext<'_, 'pass>) {}
 
     fn handle_event(&mut self, _ctx: &mut Context, _event: &WindowEvent) {}
+
+    /// Called when this state is removed from the stack (i.e. popped or swapped out).
+    fn on_exit(&mut self, _ctx: &mut Context) {}
+
+    /// Called when the player tries to close the window while this state is active.
+    fn close_requested(&mut self) -> CloseResponse {
+        CloseResponse::Confirm
+    }
+
+    /// The keyboard controls to show in the help bar at the bottom of the screen while this state
+    /// is active, with a short description of each. The keys themselves are looked up from the
+    /// settings.
+    fn control_hints(&self) -> Vec<(Action, &str)> {
+        Vec::new()
+    }
+
+    /// Whether a tja file dropped onto the window should be opened while this state is active.
+    fn accepts_dropped_charts(&self) -> bool {
+        true
+    }
+
+    /// Whether frames that go over the [draw budgets](crate::render::stats::DRAW_BUDGETS) should
+    /// be warned about while this state is active. This is for gameplay, where a dropped frame
+    /// actually matters.
+    fn checks_draw_budgets(&self) -> bool {
+        false
+    }
+
+    /// Whether a menu or overlay is open in this state that the music should be turned down for.
+    fn ducks_music(&self) -> bool {
+        false
+    }
+
+    /// Called on every state in the stack after the graphics device was lost and the renderer has
+    /// been recreated. Anything the state had on the GPU (sprites, text, meshes) is gone, so states
+    /// that have any must override this and build them again. The texture cache has already been
+    /// reloaded by the time this is called.
+    fn recreate_gpu_resources(
+        &mut self,
+        _renderer: &mut Renderer,
+        _textures: &mut TextureCache,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
 /// A struct that keeps track of the state of the keyboard at each frame.
@@ -158,9 +280,43 @@ impl MouseState {
     }
 }
 
+/// Keeps track of how much memory the textures in a [TextureCache] use, and which of them are
+/// pinned (i.e. should stay loaded for as long as the game is running).
+#[derive(Default, Debug)]
+struct TextureMemory {
+    sizes: HashMap<&'static str, u64>,
+    pinned: HashSet<&'static str>,
+}
+
+impl TextureMemory {
+    fn insert(&mut self, filename: &'static str, bytes: u64) {
+        self.sizes.insert(filename, bytes);
+    }
+
+    fn pin(&mut self, filename: &'static str) {
+        self.pinned.insert(filename);
+    }
+
+    /// Stops keeping track of the given texture, unless it's pinned. Returns whether it was
+    /// released.
+    fn release(&mut self, filename: &'static str) -> bool {
+        !self.pinned.contains(filename) && self.sizes.remove(filename).is_some()
+    }
+
+    fn total_bytes(&self) -> u64 {
+        self.sizes.values().sum()
+    }
+}
+
+/// Loads textures and keeps them around so they can be shared.
+///
+/// Textures that are needed all the time (e.g. the notes) should be pinned when the game starts.
+/// Anything else is kept until it's released, so states that load textures only they need should
+/// release them when they exit.
 #[derive(Default)]
 pub struct TextureCache {
     cache: HashMap<&'static str, Rc<Texture>>,
+    memory: TextureMemory,
 }
 
 impl TextureCache {
@@ -173,24 +329,116 @@ impl TextureCache {
         match self.cache.get(&filename) {
             Some(tex) => Ok(Rc::clone(tex)),
             None => {
-                let tex = Rc::new(Texture::from_file(
-                    format!("{SPRITES_PATH}/{filename}"),
-                    device,
-                    queue,
-                )?);
-                self.cache.insert(filename, Rc::clone(&tex));
-                Ok(tex)
+                let image = Texture::decode_file(format!("{SPRITES_PATH}/{filename}"))?;
+                Ok(self.insert(device, queue, filename, &image))
             }
         }
     }
+
+    /// Puts a texture that's already been decoded (e.g. on another thread) in the cache. If the
+    /// texture is already cached, the cached one is kept.
+    pub fn insert(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        filename: &'static str,
+        image: &RgbaImage,
+    ) -> Rc<Texture> {
+        if let Some(tex) = self.cache.get(&filename) {
+            return Rc::clone(tex);
+        }
+
+        let tex = Rc::new(Texture::from_image(filename, image, device, queue));
+        let (width, height) = tex.dimensions;
+        self.memory
+            .insert(filename, width as u64 * height as u64 * 4);
+        self.cache.insert(filename, Rc::clone(&tex));
+        tex
+    }
+
+    /// Loads a texture and keeps it loaded until the game closes.
+    pub fn pin(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        filename: &'static str,
+    ) -> anyhow::Result<Rc<Texture>> {
+        let tex = self.get(device, queue, filename)?;
+        self.memory.pin(filename);
+        Ok(tex)
+    }
+
+    /// Removes a texture from the cache, unless it's pinned. The texture is freed once nothing
+    /// else is using it.
+    pub fn release(&mut self, filename: &'static str) {
+        if self.memory.release(filename) {
+            self.cache.remove(filename);
+        }
+    }
+
+    /// Loads every cached texture again, for when the old ones were lost along with the graphics
+    /// device. Pinned textures stay pinned. Anything still holding one of the old textures has to
+    /// get it from the cache again.
+    pub fn reload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+        for (filename, tex) in self.cache.iter_mut() {
+            *tex = Rc::new(Texture::from_file(
+                format!("{SPRITES_PATH}/{filename}"),
+                device,
+                queue,
+            )?);
+        }
+
+        Ok(())
+    }
+
+    /// The approximate amount of GPU memory used by the cached textures, in bytes.
+    pub fn total_bytes(&self) -> u64 {
+        self.memory.total_bytes()
+    }
+}
+
+/// Keeps track of the game while it's closing.
+struct Shutdown {
+    /// The UI time when the game started closing.
+    started: f32,
+    /// Receives a message once all the game's data has been saved.
+    saved: Receiver<()>,
 }
 
+impl Shutdown {
+    /// Returns whether the game is ready to exit: the audio has faded out and everything has been
+    /// saved, or it's taken so long that we're giving up on saving.
+    fn is_finished(&self, time: &GameTime) -> bool {
+        let elapsed = time.ui_time() - self.started;
+
+        if elapsed >= SHUTDOWN_TIMEOUT.as_secs_f32() {
+            log::error!("timed out waiting for game data to save, exiting anyway");
+            return true;
+        }
+
+        // If the sender was dropped without sending then the saving thread died, and there's no
+        // point waiting for it.
+        elapsed >= SHUTDOWN_FADE_TIME.as_secs_f32()
+            && self.saved.try_recv() != Err(TryRecvError::Empty)
+    }
+}
+
+/// The name the current state holds the music ducking under.
+const STATE_DUCKING: &str = "state";
+
 pub struct Game {
     audio_manager: AudioManager,
+    music_track: TrackHandle,
+    ducking: MusicDucking,
     state: Vec<Box<dyn GameState>>,
     keyboard: KeyboardState,
     mouse: MouseState,
     textures: TextureCache,
+    time: GameTime,
+    frame_times: FrameTimeHistogram,
+    /// What was drawn last frame.
+    draw_stats: DrawStats,
+    budget_warnings: BudgetWarnings,
 
     fps_timer: f32,
     frames_counted: u32,
@@ -198,6 +446,11 @@ pub struct Game {
     show_fps_counter: bool,
 
     version_text: Text,
+    control_hints: ControlHintBar,
+    /// The safe area margin the version text and help bar were laid out with.
+    safe_area_margin: f32,
+
+    shutdown: Option<Shutdown>,
 }
 
 impl Game {
@@ -205,50 +458,24 @@ impl Game {
     where
         F: FnOnce(&mut render::Renderer, &mut TextureCache) -> Box<dyn GameState>,
     {
-        let audio_manager = AudioManager::<DefaultBackend>::new(Default::default())?;
+        let mut audio_manager = AudioManager::<DefaultBackend>::new(Default::default())?;
+        let music_track = audio_manager.add_sub_track(TrackBuilder::new())?;
         let mut textures = TextureCache::default();
         // Let's load some important textures first
-        for tex in [
-            "don.png",
-            "kat.png",
-            "big_don.png",
-            "big_kat.png",
-            "drumroll_start.png",
-            "big_drumroll_start.png",
-        ] {
+        for &tex in PINNED_TEXTURES {
             textures
-                .get(&renderer.device, &renderer.queue, tex)
+                .pin(&renderer.device, &renderer.queue, tex)
                 .unwrap();
         }
 
         let state = create_state(renderer, &mut textures);
-
-        #[cfg(debug_assertions)]
-        let build = "debug";
-        #[cfg(not(debug_assertions))]
-        let build = "release";
-
-        let version_text = format!(
-            "luna's taiko sim - version {} ({})",
-            env!("CARGO_PKG_VERSION"),
-            build
-        );
-
-        let version_text =
-            TextBuilder::new(version_text, renderer.font("mplus regular"), [1910., 1070.])
-                .horizontal_align(HorizontalAlignment::Right)
-                .vertical_align(VerticalAlignment::Bottom)
-                .font_size(Some(FontSize::Px(18.)))
-                .color([1.; 4])
-                .outlined([0., 0., 0., 1.], 2.)
-                .build(
-                    &renderer.device,
-                    &renderer.queue,
-                    &mut renderer.text_renderer,
-                );
+        let version_text = Self::version_text(renderer);
+        let control_hints = ControlHintBar::new(renderer)?;
 
         Ok(Game {
             audio_manager,
+            music_track,
+            ducking: MusicDucking::new(),
             state: vec![state],
             keyboard: KeyboardState(HashMap::new()),
             mouse: MouseState {
@@ -256,23 +483,119 @@ impl Game {
                 button_map: HashMap::new(),
             },
             textures,
+            time: GameTime::new(),
+            frame_times: FrameTimeHistogram::new(),
+            draw_stats: DrawStats::default(),
+            budget_warnings: BudgetWarnings::default(),
 
             fps_timer: 0.0,
             frames_counted: 0,
             fps: 0.0,
             show_fps_counter: false,
             version_text,
+            control_hints,
+            safe_area_margin: settings().visual.safe_area_margin(),
+            shutdown: None,
         })
     }
 
+    fn version_text(renderer: &mut Renderer) -> Text {
+        #[cfg(debug_assertions)]
+        let build = "debug";
+        #[cfg(not(debug_assertions))]
+        let build = "release";
+
+        let version_text = format!(
+            "luna's taiko sim - version {} ({})",
+            env!("CARGO_PKG_VERSION"),
+            build
+        );
+
+        let position = VERSION_TEXT.position(settings().visual.safe_area_margin());
+        TextBuilder::new(version_text, renderer.font("mplus regular"), position)
+            .horizontal_align(HorizontalAlignment::Right)
+            .vertical_align(VerticalAlignment::Bottom)
+            .font_size(Some(FontSize::Px(18.)))
+            .color([1.; 4])
+            .outlined([0., 0., 0., 1.], 2.)
+            .build(
+                &renderer.device,
+                &renderer.queue,
+                &mut renderer.text_renderer,
+            )
+    }
+
+    /// Rebuilds everything the game had on the GPU, after the graphics device was lost and the
+    /// given renderer was created to replace the old one.
+    pub fn recreate_gpu_resources(&mut self, renderer: &mut Renderer) -> anyhow::Result<()> {
+        self.textures.reload(&renderer.device, &renderer.queue)?;
+        self.version_text = Self::version_text(renderer);
+        self.control_hints = ControlHintBar::new(renderer)?;
+
+        for state in self.state.iter_mut() {
+            state.recreate_gpu_resources(renderer, &mut self.textures)?;
+        }
+
+        Ok(())
+    }
+
+    /// Called when the player tries to close the window. The current state gets a chance to stop
+    /// this from happening.
+    pub fn request_close(&mut self) {
+        if let CloseResponse::Confirm = self.state.last_mut().unwrap().close_requested() {
+            self.begin_shutdown();
+        }
+    }
+
+    /// Starts closing the game: fades out all the audio and saves everything that needs to be
+    /// saved. The game will exit once this has finished.
+    fn begin_shutdown(&mut self) {
+        if self.shutdown.is_some() {
+            return;
+        }
+
+        self.audio_manager
+            .pause(Tween {
+                duration: SHUTDOWN_FADE_TIME,
+                ..Default::default()
+            })
+            .or_log("couldn't fade out audio");
+
+        let (sender, saved) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            if let Err(e) = crate::persistence::flush(crate::paths::paths().data_dir()) {
+                log::error!("couldn't save game data: {e}");
+            }
+
+            let _ = sender.send(());
+        });
+
+        self.shutdown = Some(Shutdown {
+            started: self.time.ui_time(),
+            saved,
+        });
+    }
+
     pub fn update(
         &mut self,
         delta: f32,
         renderer: &mut render::Renderer,
         event_loop: &ActiveEventLoop,
     ) {
+        self.time.advance(delta);
+
+        if let Some(shutdown) = self.shutdown.as_ref() {
+            if shutdown.is_finished(&self.time) {
+                event_loop.exit();
+            }
+
+            return;
+        }
+
         self.fps_timer += delta;
         self.frames_counted += 1;
+        self.frame_times.record(delta);
 
         if self.fps_timer >= FPS_POLL_TIME {
             self.fps = self.frames_counted as f32 / self.fps_timer;
@@ -286,6 +609,9 @@ impl Game {
             keyboard: &self.keyboard,
             mouse: &self.mouse,
             textures: &mut self.textures,
+            time: &mut self.time,
+            frame_times: &mut self.frame_times,
+            music_track: self.music_track.id(),
         };
 
         match self.state.last_mut().unwrap().update(&mut ctx, delta) {
@@ -293,12 +619,55 @@ impl Game {
             StateTransition::Pop => {
                 self.state
                     .pop()
-                    .expect("found no previous state to return to!");
+                    .expect("found no previous state to return to!")
+                    .on_exit(&mut ctx);
             }
-            StateTransition::Swap(state) => *self.state.last_mut().unwrap() = state,
-            StateTransition::Exit => event_loop.exit(),
+            StateTransition::Swap(state) => {
+                std::mem::replace(self.state.last_mut().unwrap(), state).on_exit(&mut ctx);
+            }
+            StateTransition::Exit => self.begin_shutdown(),
             StateTransition::Continue => {}
         }
+
+        let now = self.time.ui_time();
+        let ducked = self.state.last().unwrap().ducks_music();
+        if let Some(change) = self.ducking.set_held(STATE_DUCKING, ducked, now) {
+            self.music_track
+                .set_volume(Volume::Amplitude(change.gain as f64), change.tween())
+                .or_log("couldn't change the music volume");
+        }
+        self.ducking.update(now);
+
+        // The safe area can be changed in the settings, and it's easier to check for that here
+        // than to tell the game when it happens
+        let margin = settings().visual.safe_area_margin();
+        if margin != self.safe_area_margin {
+            self.safe_area_margin = margin;
+            self.version_text = Self::version_text(renderer);
+            match ControlHintBar::new(renderer) {
+                Ok(control_hints) => self.control_hints = control_hints,
+                Err(e) => log::error!("couldn't rebuild the help bar: {e}"),
+            }
+        }
+
+        // This is checked every frame so that rebinding a key shows up straight away
+        let hints = self.state.last().unwrap().control_hints();
+        self.control_hints
+            .update(&hints, &settings().game.key_mappings, renderer);
+    }
+
+    /// Keeps what was drawn in the last frame for the debug overlay, and warns if it was too much.
+    pub fn record_draw_stats(&mut self, stats: DrawStats) {
+        self.draw_stats = stats;
+
+        if self.state.last().unwrap().checks_draw_budgets() {
+            if let Some(warning) =
+                self.budget_warnings
+                    .check(&stats, &DRAW_BUDGETS, self.time.ui_time())
+            {
+                log::warn!("{warning}");
+            }
+        }
     }
 
     pub fn debug_ui(&mut self, ctx: egui::Context) {
@@ -307,15 +676,33 @@ impl Game {
             .unwrap()
             .debug_ui(ctx.clone(), &mut self.audio_manager);
 
-        if self.show_fps_counter {
+        // Debug overlays shouldn't end up on stream
+        if self.show_fps_counter && !settings().visual.stream_mode {
+            let [x, y] = FPS_COUNTER.position(self.safe_area_margin);
             egui::Area::new("fps counter".into())
-                .fixed_pos(egui::pos2(1800.0, 0.0))
+                .fixed_pos(egui::pos2(x, y))
                 .show(&ctx, |ui| {
                     ui.label(
                         egui::RichText::new(format!("fps: {:.2}", self.fps))
                             .color(egui::Color32::from_rgb(255, 0, 255))
                             .size(20.0),
                     );
+                    ui.label(
+                        egui::RichText::new(format!(
+                            "textures: {:.1} MiB",
+                            self.textures.total_bytes() as f64 / (1024.0 * 1024.0)
+                        ))
+                        .color(egui::Color32::from_rgb(255, 0, 255))
+                        .size(20.0),
+                    );
+
+                    for (name, count) in self.draw_stats.categories() {
+                        ui.label(
+                            egui::RichText::new(format!("{name}: {count}"))
+                                .color(egui::Color32::from_rgb(255, 0, 255))
+                                .size(20.0),
+                        );
+                    }
                 });
         }
     }
@@ -335,7 +722,19 @@ impl Game {
         };
 
         self.state.last_mut().unwrap().render(&mut ctx);
-        ctx.render(&self.version_text);
+
+        let (show_hints, stream_mode) = {
+            let visual = &settings().visual;
+            (visual.control_hints, visual.stream_mode)
+        };
+
+        if show_hints && !stream_mode && !self.control_hints.is_empty() {
+            ctx.render(&self.control_hints);
+        }
+
+        if !stream_mode {
+            ctx.render(&self.version_text);
+        }
     }
 
     pub fn handle_event(&mut self, event: &WindowEvent, renderer: &mut render::Renderer) {
@@ -348,10 +747,19 @@ impl Game {
             keyboard: &self.keyboard,
             mouse: &self.mouse,
             textures: &mut self.textures,
+            time: &mut self.time,
+            frame_times: &mut self.frame_times,
+            music_track: self.music_track.id(),
         };
 
         self.state.last_mut().unwrap().handle_event(&mut ctx, event);
 
+        if let WindowEvent::DroppedFile(path) = event {
+            if is_tja_file(path) && self.state.last().unwrap().accepts_dropped_charts() {
+                self.state.push(Box::new(DroppedChartScreen::new(path)));
+            }
+        }
+
         if let WindowEvent::KeyboardInput {
             event,
             is_synthetic: false,
@@ -366,8 +774,45 @@ impl Game {
             {
                 self.show_fps_counter = !self.show_fps_counter;
             }
+
+            // For testing that the game survives the graphics driver crashing
+            #[cfg(debug_assertions)]
+            if self
+                .keyboard
+                .is_just_pressed(PhysicalKey::Code(KeyCode::F10))
+            {
+                renderer.simulate_device_loss();
+            }
         }
 
         self.mouse.handle_input(event);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_texture_memory_released() {
+        let mut memory = TextureMemory::default();
+        memory.insert("don.png", 100);
+        memory.pin("don.png");
+        let baseline = memory.total_bytes();
+
+        // Entering a song loads some textures of its own
+        memory.insert("background.png", 1000);
+        memory.insert("jacket.png", 500);
+        assert_eq!(memory.total_bytes(), baseline + 1500);
+
+        // And exiting the song releases them
+        assert!(memory.release("background.png"));
+        assert!(memory.release("jacket.png"));
+        assert_eq!(memory.total_bytes(), baseline);
+
+        // Pinned textures are never released
+        assert!(!memory.release("don.png"));
+        assert!(!memory.release("jacket.png"));
+        assert_eq!(memory.total_bytes(), baseline);
+    }
+}
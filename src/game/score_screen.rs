@@ -1,48 +1,279 @@
+use std::time::{Duration, Instant};
+
 use kira::manager::AudioManager;
+use winit::keyboard::{KeyCode, PhysicalKey};
 
-use crate::game::taiko_mode::PlayResult;
-use crate::game::{Context, GameState, StateTransition};
+use crate::game::audio::OrLog;
+use crate::game::frame_stats::FrameStats;
+use crate::game::taiko_mode::{
+    dimmed_background, format_accuracy, NoteShuffle, NoteVisibility, PlayConditions, PlayResult,
+    Rally, ScoreInt,
+};
+use crate::game::{Action, Context, GameState, RenderContext, StateTransition, TextureCache};
+use crate::local_data::{local_data_mut, save_local_data, PlayRecord};
+use crate::notechart_parser::Player;
+use crate::render::shapes::Shape;
+use crate::render::texture::Sprite;
+use crate::render::Renderer;
+use crate::settings::settings;
 
-struct Score {
+pub(super) struct Score {
     // Some precomputed values to display
+    pub(super) accuracy: Option<f32>,
+    pub(super) gauge: f32,
+    /// Whether the gauge was full enough at the end to clear the song.
+    cleared: bool,
+    total: ScoreInt,
     goods: usize,
     okays: usize,
     bads: usize,
     max_combo: usize,
     drumrolls: u64,
+    best_roll_speed: f32,
+    roll_speed_bonus: ScoreInt,
+    rally: Option<Rally>,
+    frame_stats: Option<FrameStats>,
+    conditions: Option<PlayConditions>,
 }
 
 impl Score {
-    fn from_result(result: &PlayResult) -> Self {
+    pub(super) fn from_result(result: &PlayResult) -> Self {
         Self {
+            accuracy: result.accuracy(),
+            gauge: result.gauge(),
+            cleared: result.cleared(),
+            total: result.score(),
             goods: result.goods(),
             okays: result.okays(),
             bads: result.bads() + result.misses(),
             drumrolls: result.drumrolls(),
             max_combo: result.max_combo(),
+            best_roll_speed: result.best_roll_speed(),
+            roll_speed_bonus: result.roll_speed_bonus(),
+            rally: result.rally(),
+            frame_stats: result.frame_stats(),
+            conditions: result.conditions().cloned(),
         }
     }
+
+    /// Whether the game played the song by itself (for some of it at least).
+    fn autoplayed(&self) -> bool {
+        self.conditions
+            .as_ref()
+            .is_some_and(|conditions| conditions.autoplay)
+    }
+
+    /// The speed the song was played at, if it wasn't played at its normal speed.
+    fn practice_speed(&self) -> Option<f32> {
+        self.conditions
+            .as_ref()
+            .map(|conditions| conditions.playback_speed)
+            .filter(|speed| *speed != 1.0)
+    }
+
+    /// How the notes were hidden, if they were.
+    fn hidden_notes(&self) -> Option<NoteVisibility> {
+        self.conditions
+            .as_ref()
+            .map(|conditions| conditions.note_visibility)
+            .filter(|visibility| *visibility != NoteVisibility::Normal)
+    }
+
+    /// How the notes were shuffled, if they were, and the seed if it was random.
+    fn note_shuffle(&self) -> Option<(NoteShuffle, Option<u64>)> {
+        self.conditions
+            .as_ref()
+            .map(|conditions| (conditions.note_shuffle, conditions.shuffle_seed))
+            .filter(|(shuffle, _)| *shuffle != NoteShuffle::Off)
+    }
 }
 
+/// Saves the given roll speed if it's the fastest the player has ever rolled. Returns whether it
+/// was.
+fn record_roll_speed(speed: f32) -> bool {
+    let mut data = local_data_mut();
+
+    if speed <= data.stats.best_roll_speed {
+        return false;
+    }
+
+    data.stats.best_roll_speed = speed;
+    drop(data);
+
+    if let Err(e) = save_local_data() {
+        log::error!("couldn't save local data: {e}");
+    }
+
+    true
+}
+
+/// Adds a play to the history of the chart that was played. Returns whether it's a new personal
+/// best.
+fn record_play(song_key: &str, difficulty: usize, score: &Score) -> bool {
+    // Without the conditions, the play couldn't be compared with anything else in the history
+    let Some(conditions) = score.conditions.clone() else {
+        return false;
+    };
+
+    let play = PlayRecord::now(
+        difficulty,
+        score.total,
+        score.accuracy,
+        conditions,
+        score.frame_stats,
+    );
+    let new_best = local_data_mut().song_mut(song_key).record_play(play);
+
+    if let Err(e) = save_local_data() {
+        log::error!("couldn't save local data: {e}");
+    }
+
+    new_best
+}
+
+/// Who won a 2P battle: whoever scored more. If they scored the same, it's a draw.
+fn battle_winner(player_1: &Score, player_2: &Score) -> Option<Player> {
+    match player_1.total.cmp(&player_2.total) {
+        std::cmp::Ordering::Greater => Some(Player::Player1),
+        std::cmp::Ordering::Less => Some(Player::Player2),
+        std::cmp::Ordering::Equal => None,
+    }
+}
+
+/// Shows one player's results in a 2P battle.
+fn player_results_ui(ui: &mut egui::Ui, heading: &str, score: &Score) {
+    ui.label(egui::RichText::new(heading).size(20.0).strong());
+    ui.label(if score.cleared {
+        egui::RichText::new("Clear!").color(egui::Color32::GOLD)
+    } else {
+        egui::RichText::new("Failed").color(egui::Color32::LIGHT_RED)
+    });
+    ui.label(format!("Score: {}", score.total));
+    ui.label(format!("Accuracy: {}", format_accuracy(score.accuracy)));
+    ui.label(format!("Soul gauge: {:.0}%", score.gauge));
+    ui.label(format!("Good: {}", score.goods));
+    ui.label(format!("Ok: {}", score.okays));
+    ui.label(format!("Bad: {}", score.bads));
+    ui.label(format!("Drumrolls: {}", score.drumrolls));
+    ui.label(format!("Max Combo: {}", score.max_combo));
+}
+
+/// How long the results are shown before a key press can leave them, so a player who's still
+/// drumming at the end of the song doesn't skip past them.
+const INPUT_DELAY: Duration = Duration::from_secs(1);
+
 pub struct ScoreScreen {
+    /// The same dimmed background as the game, if it could be loaded.
+    background: Option<(Sprite, Shape)>,
     score: Score,
+    /// Player 2's results, if this was a 2P battle. [score](Self::score) is player 1's.
+    player_2: Option<Score>,
+    new_best_score: bool,
+    new_best_roll_speed: bool,
     song_name: String,
+    /// When the results were first shown.
+    shown_at: Instant,
     exit: bool,
 }
 
 impl ScoreScreen {
-    pub fn new(_ctx: &mut Context, song_name: String, result: PlayResult) -> Self {
+    /// Shows the results of a play. If the song's key is given, the play is saved to the chart's
+    /// history.
+    pub fn new(
+        ctx: &mut Context,
+        song_name: String,
+        song_key: Option<&str>,
+        difficulty: usize,
+        result: PlayResult,
+    ) -> Self {
+        let score = Score::from_result(&result);
+
+        Self {
+            background: dimmed_background(ctx.renderer, ctx.textures)
+                .or_log("couldn't load results background"),
+            new_best_score: song_key.is_some_and(|key| record_play(key, difficulty, &score)),
+            // The game's own rolling doesn't count as the player's
+            new_best_roll_speed: !score.autoplayed() && record_roll_speed(score.best_roll_speed),
+            score,
+            player_2: None,
+            song_name,
+            shown_at: Instant::now(),
+            exit: false,
+        }
+    }
+
+    /// Shows the results of a 2P battle side by side, with the winner. Battles aren't saved, and
+    /// don't count towards the best roll speed.
+    pub fn versus(
+        ctx: &mut Context,
+        song_name: String,
+        player_1: PlayResult,
+        player_2: PlayResult,
+    ) -> Self {
         Self {
-            score: Score::from_result(&result),
+            background: dimmed_background(ctx.renderer, ctx.textures)
+                .or_log("couldn't load results background"),
+            score: Score::from_result(&player_1),
+            player_2: Some(Score::from_result(&player_2)),
+            new_best_score: false,
+            new_best_roll_speed: false,
             song_name,
+            shown_at: Instant::now(),
             exit: false,
         }
     }
+
+    /// The results window for a 2P battle.
+    fn battle_ui(&mut self, ctx: &egui::Context, player_2: &Score) {
+        egui::Window::new("Battle results").show(ctx, |ui| {
+            ui.label(egui::RichText::new(&self.song_name).size(20.0).strong());
+            let winner = match battle_winner(&self.score, player_2) {
+                Some(Player::Player1) => "1P wins!",
+                Some(Player::Player2) => "2P wins!",
+                None => "Draw!",
+            };
+            ui.label(
+                egui::RichText::new(winner)
+                    .size(28.0)
+                    .strong()
+                    .color(egui::Color32::GOLD),
+            );
+            ui.add_space(10.0);
+
+            ui.columns(2, |columns| {
+                player_results_ui(&mut columns[0], "1P", &self.score);
+                player_results_ui(&mut columns[1], "2P", player_2);
+            });
+
+            if let Some(conditions) = &self.score.conditions {
+                ui.add_space(10.0);
+                ui.label(egui::RichText::new(format!("Conditions: {conditions}")).small());
+            }
+
+            self.exit = ui.button("Back to menu").clicked();
+        });
+    }
 }
 
 impl GameState for ScoreScreen {
-    fn update(&mut self, _ctx: &mut Context, _delta_time: f32) -> StateTransition {
-        if self.exit {
+    fn update(&mut self, ctx: &mut Context, _delta_time: f32) -> StateTransition {
+        // A don confirms, and escape does the same thing
+        let keys = {
+            let key_mappings = &settings().game.key_mappings;
+            [
+                key_mappings.left_don,
+                key_mappings.right_don,
+                PhysicalKey::Code(KeyCode::Enter),
+                PhysicalKey::Code(KeyCode::Escape),
+            ]
+        };
+
+        let key_pressed = self.shown_at.elapsed() >= INPUT_DELAY
+            && keys
+                .into_iter()
+                .any(|key| ctx.keyboard.is_just_pressed(key));
+
+        if self.exit || key_pressed {
             StateTransition::Pop
         } else {
             StateTransition::Continue
@@ -50,16 +281,179 @@ impl GameState for ScoreScreen {
     }
 
     fn debug_ui(&mut self, ctx: egui::Context, _audio: &mut AudioManager) {
+        if let Some(player_2) = self.player_2.take() {
+            self.battle_ui(&ctx, &player_2);
+            self.player_2 = Some(player_2);
+            return;
+        }
+
         egui::Window::new("Let's see your results!").show(&ctx, |ui| {
             ui.label(egui::RichText::new(&self.song_name).size(20.0).strong());
+            ui.label(if self.score.cleared {
+                egui::RichText::new("Clear!").color(egui::Color32::GOLD)
+            } else {
+                egui::RichText::new("Failed").color(egui::Color32::LIGHT_RED)
+            });
+            if self.score.autoplayed() {
+                ui.label(
+                    egui::RichText::new("AUTO")
+                        .size(20.0)
+                        .strong()
+                        .color(egui::Color32::LIGHT_BLUE),
+                )
+                .on_hover_text("The game played this by itself, so it's never a personal best.");
+            }
+            if let Some(speed) = self.score.practice_speed() {
+                ui.label(
+                    egui::RichText::new(format!("{speed}x SPEED"))
+                        .size(20.0)
+                        .strong()
+                        .color(egui::Color32::LIGHT_BLUE),
+                )
+                .on_hover_text(
+                    "This was played at a practice speed, so it's never a personal best.",
+                );
+            }
+            if let Some(visibility) = self.score.hidden_notes() {
+                ui.label(
+                    egui::RichText::new(visibility.name().to_uppercase())
+                        .size(20.0)
+                        .strong()
+                        .color(egui::Color32::LIGHT_BLUE),
+                );
+            }
+            if let Some((shuffle, seed)) = self.score.note_shuffle() {
+                let label = ui.label(
+                    egui::RichText::new(shuffle.name().to_uppercase())
+                        .size(20.0)
+                        .strong()
+                        .color(egui::Color32::LIGHT_BLUE),
+                );
+                let hover = "The notes were shuffled, so it's never a personal best.";
+                match seed {
+                    Some(seed) => label.on_hover_text(format!("{hover} (Seed {seed:x})")),
+                    None => label.on_hover_text(hover),
+                };
+            }
             ui.add_space(10.0);
+            ui.label(format!(
+                "Score: {}{}",
+                self.score.total,
+                if self.new_best_score {
+                    " (new personal best!)"
+                } else {
+                    ""
+                }
+            ));
+            ui.label(format!(
+                "Accuracy: {}",
+                format_accuracy(self.score.accuracy)
+            ));
+            ui.label(format!("Soul gauge: {:.0}%", self.score.gauge));
             ui.label(format!("Good: {}", self.score.goods));
             ui.label(format!("Ok: {}", self.score.okays));
             ui.label(format!("Bad: {}", self.score.bads));
             ui.label(format!("Drumrolls: {}", self.score.drumrolls));
+            ui.label(format!(
+                "Best roll speed: {:.0} hits/s{}",
+                self.score.best_roll_speed,
+                if self.new_best_roll_speed {
+                    " (new personal best!)"
+                } else {
+                    ""
+                }
+            ));
+
+            if self.score.roll_speed_bonus > 0 {
+                ui.label(format!(
+                    "Roll speed bonus: +{}",
+                    self.score.roll_speed_bonus
+                ));
+            }
+
             ui.label(format!("Max Combo: {}", self.score.max_combo));
 
+            if let Some(rally) = self.score.rally {
+                ui.label(format!(
+                    "Rally bonus: +{} ({} hits)",
+                    rally.bonus(),
+                    rally.hits()
+                ));
+            }
+
+            if let Some(conditions) = &self.score.conditions {
+                ui.add_space(10.0);
+
+                let label = format!("Conditions: {conditions}");
+                if conditions.is_default() {
+                    ui.label(egui::RichText::new(label).small());
+                } else {
+                    ui.label(egui::RichText::new(format!("⚠ {label}")).small())
+                        .on_hover_text(
+                            "This wasn't played under the usual conditions, so it can't be \
+                            compared with other scores.",
+                        );
+                }
+            }
+
+            if let Some(stats) = self.score.frame_stats {
+                ui.add_space(10.0);
+                ui.label(egui::RichText::new(format!("Performance: {stats}")).small());
+
+                if stats.may_have_affected_timing() {
+                    ui.label(
+                        "The game wasn't running very smoothly during this song, so your timing \
+                        may have been affected.",
+                    );
+                }
+            }
+
             self.exit = ui.button("Back to menu").clicked();
         });
     }
+
+    fn render<'pass>(&'pass mut self, ctx: &mut RenderContext<'_, 'pass>) {
+        if let Some((background, background_dim)) = &self.background {
+            ctx.render(background);
+            ctx.render(background_dim);
+        }
+    }
+
+    fn control_hints(&self) -> Vec<(Action, &str)> {
+        vec![
+            (Action::Don, "Back to menu"),
+            (Action::Back, "Back to menu"),
+        ]
+    }
+
+    fn recreate_gpu_resources(
+        &mut self,
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+    ) -> anyhow::Result<()> {
+        self.background = Some(dimmed_background(renderer, textures)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn score(total: ScoreInt) -> Score {
+        Score {
+            total,
+            ..Score::from_result(&PlayResult::new())
+        }
+    }
+
+    #[test]
+    fn test_battle_winner() {
+        assert_eq!(
+            battle_winner(&score(1000), &score(990)),
+            Some(Player::Player1)
+        );
+        assert_eq!(battle_winner(&score(0), &score(10)), Some(Player::Player2));
+        assert_eq!(battle_winner(&score(500), &score(500)), None);
+    }
 }
@@ -0,0 +1,452 @@
+use std::time::Instant;
+
+use egui::RichText;
+use kira::manager::AudioManager;
+use kira::sound::static_sound::StaticSoundData;
+use winit::event::{ElementState, WindowEvent};
+use winit::window::Window;
+
+use crate::app::{list_monitors, move_to_monitor, preferred_monitor_index, MonitorInfo};
+use crate::diagnostics::{describe_diagnostics, write_diagnostics};
+use crate::game::audio::{metronome_tick, OrLog};
+use crate::game::calibration::CalibrationScreen;
+use crate::game::layout::{HEADER_HEIGHT, NOTE_FIELD_HEIGHT, NOTE_FIELD_Y, NOTE_HIT_X, NOTE_Y};
+use crate::game::taiko_mode::{judgement_text_centre, JUDGEMENT_TEXT_SIZE};
+use crate::game::tap_stats::TapStatistics;
+use crate::game::{Action, Context, GameState, StateTransition};
+use crate::settings::{
+    save_settings, settings, JudgementPosition, MonitorPreference, VisualSettings,
+    HIT_SOUND_VOLUME_RANGE, HUD_SCALE_RANGE, LEAD_IN_RANGE, SAFE_AREA_RANGE, SETTINGS,
+};
+
+/// The number of seconds between each time the marker crosses the line.
+const TEST_BEAT_LENGTH: f32 = 0.75;
+const TEST_STRIP_SIZE: [f32; 2] = [500.0, 60.0];
+/// How big the judgement text preview is compared to the real screen.
+const PREVIEW_SCALE: f32 = 0.2;
+
+/// The settings screen.
+///
+/// This is where the player changes the settings that don't need a restart: song titles, control
+/// hints, the monitor, the safe area, the size and position of the HUD, stream mode, the lead-in,
+/// their note offset (which they can calibrate from here) and the hit sound volume. Each one is
+/// saved as soon as it's changed.
+///
+/// It also has a video latency test strip, a marker that crosses a line in time with a metronome
+/// tick. The player taps along, and the offset and jitter of their taps are shown so they can
+/// compare how different video settings feel. And there's a button to write a diagnostics report.
+pub struct SettingsScreen {
+    start: Instant,
+    /// The beat the last tick was played for.
+    last_beat: i64,
+    /// How far through the current beat we are, from 0 to 1. The marker is on the line at 0.
+    phase: f32,
+    taps: TapStatistics,
+    tick: StaticSoundData,
+    /// Whether the player asked for a diagnostics report.
+    run_diagnostics: bool,
+    /// What happened the last time a diagnostics report was written.
+    diagnostics_message: Option<String>,
+    /// Whether the player asked to calibrate their note offset.
+    open_calibration: bool,
+    /// The monitors that were plugged in when the screen was opened.
+    monitors: Vec<MonitorInfo>,
+    /// Whether the player chose a different monitor, which the window should move to.
+    move_window: bool,
+    exit: bool,
+}
+
+impl SettingsScreen {
+    pub fn new(window: &Window) -> Self {
+        Self {
+            start: Instant::now(),
+            last_beat: -1,
+            phase: 0.0,
+            taps: TapStatistics::default(),
+            tick: metronome_tick(),
+            run_diagnostics: false,
+            diagnostics_message: None,
+            open_calibration: false,
+            monitors: list_monitors(window),
+            move_window: false,
+            exit: false,
+        }
+    }
+
+    fn elapsed(&self) -> f32 {
+        self.start.elapsed().as_secs_f32()
+    }
+
+    fn test_strip_ui(&self, ui: &mut egui::Ui) {
+        let (rect, _) = ui.allocate_exact_size(TEST_STRIP_SIZE.into(), egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 4.0, egui::Color32::from_gray(30));
+
+        let centre = rect.center().x;
+        painter.vline(
+            centre,
+            rect.y_range(),
+            egui::Stroke::new(2.0, egui::Color32::WHITE),
+        );
+
+        // The marker crosses the line halfway along the strip, on the beat
+        let marker_x = rect.left() + rect.width() * ((self.phase + 0.5) % 1.0);
+        painter.circle_filled(
+            egui::pos2(marker_x, rect.center().y),
+            rect.height() / 4.0,
+            egui::Color32::from_rgb(0xF8, 0x48, 0x28),
+        );
+    }
+
+    /// Lets the player choose which monitor the game is displayed on, and shows what it supports.
+    fn monitor_ui(&mut self, ui: &mut egui::Ui) {
+        let names: Vec<Option<String>> = self.monitors.iter().map(|m| m.name.clone()).collect();
+        let mut selected = (settings().visual.monitor.as_ref())
+            .and_then(|preference| preferred_monitor_index(preference, &names));
+        let selected_text = match selected {
+            Some(index) => self.monitors[index].to_string(),
+            None => "Primary monitor".to_string(),
+        };
+
+        let mut changed = false;
+        egui::ComboBox::from_label("Monitor")
+            .selected_text(selected_text)
+            .show_ui(ui, |ui| {
+                changed |= ui
+                    .selectable_value(&mut selected, None, "Primary monitor")
+                    .changed();
+                for (index, monitor) in self.monitors.iter().enumerate() {
+                    changed |= ui
+                        .selectable_value(&mut selected, Some(index), monitor.to_string())
+                        .changed();
+                }
+            });
+
+        if let Some(monitor) = selected.map(|index| &self.monitors[index]) {
+            ui.collapsing("Supported resolutions", |ui| {
+                for (size, refresh_rate) in &monitor.video_modes {
+                    ui.label(format!("{}x{} @ {refresh_rate}Hz", size.width, size.height));
+                }
+            });
+        }
+
+        if changed {
+            SETTINGS.write().unwrap().visual.monitor = selected.map(|index| MonitorPreference {
+                name: self.monitors[index].name.clone(),
+                index,
+            });
+            save_settings().or_log("couldn't save settings");
+            self.move_window = true;
+        }
+    }
+}
+
+/// Draws a small picture of the gameplay screen with a judgement on it, at the size and position
+/// the player has chosen.
+fn judgement_preview_ui(ui: &mut egui::Ui, visual: &VisualSettings) {
+    let (rect, _) = ui.allocate_exact_size(
+        egui::vec2(1920.0, 1080.0) * PREVIEW_SCALE,
+        egui::Sense::hover(),
+    );
+    let painter = ui.painter_at(rect);
+    let to_preview = |[x, y]: [f32; 2]| rect.min + egui::vec2(x, y) * PREVIEW_SCALE;
+
+    painter.rect_filled(rect, 0.0, egui::Color32::from_gray(20));
+    painter.rect_filled(
+        egui::Rect::from_min_max(to_preview([0.0, 0.0]), to_preview([1920.0, HEADER_HEIGHT])),
+        0.0,
+        egui::Color32::from_rgb(90, 78, 210),
+    );
+    painter.rect_filled(
+        egui::Rect::from_min_max(
+            to_preview([0.0, NOTE_FIELD_Y]),
+            to_preview([1920.0, NOTE_FIELD_Y + NOTE_FIELD_HEIGHT]),
+        ),
+        0.0,
+        egui::Color32::from_gray(45),
+    );
+    painter.circle_stroke(
+        to_preview([NOTE_HIT_X, NOTE_Y]),
+        50.0 * PREVIEW_SCALE,
+        egui::Stroke::new(1.0, egui::Color32::from_gray(100)),
+    );
+
+    // The text is measured with egui here, which is close enough for a preview
+    let scale = visual.judgement_scale();
+    let colour = egui::Color32::from_rgb(255, 202, 14);
+    let galley = painter.layout_no_wrap(
+        "Good".to_string(),
+        egui::FontId::proportional(JUDGEMENT_TEXT_SIZE * scale * PREVIEW_SCALE),
+        colour,
+    );
+    let size = galley.size() / PREVIEW_SCALE;
+    let centre = judgement_text_centre(
+        visual.judgement_position,
+        scale,
+        [size.x, size.y],
+        visual.safe_area_margin(),
+    );
+
+    painter.galley(to_preview(centre) - galley.size() / 2.0, galley, colour);
+}
+
+/// Draws the edges of the safe area over the whole window, so the player can line them up with
+/// the edges of their TV.
+fn safe_area_guides(ctx: &egui::Context, margin: f32) {
+    let screen = ctx.screen_rect();
+    let painter = ctx.layer_painter(egui::LayerId::new(
+        egui::Order::Foreground,
+        egui::Id::new("safe area guides"),
+    ));
+
+    painter.rect_stroke(
+        screen.shrink2(screen.size() * margin),
+        0.0,
+        egui::Stroke::new(3.0, egui::Color32::from_rgb(255, 202, 14)),
+    );
+}
+
+impl GameState for SettingsScreen {
+    fn update(&mut self, ctx: &mut Context, _delta_time: f32) -> StateTransition {
+        let beats = self.elapsed() / TEST_BEAT_LENGTH;
+        self.phase = beats.fract();
+
+        let beat = beats.floor() as i64;
+        if beat > self.last_beat {
+            self.last_beat = beat;
+            ctx.audio
+                .play(self.tick.clone())
+                .or_log("couldn't play metronome tick");
+        }
+
+        if self.run_diagnostics {
+            self.run_diagnostics = false;
+            let result = write_diagnostics(Some(&ctx.renderer.graphics_info()), Some(ctx.audio));
+            self.diagnostics_message = Some(describe_diagnostics(&result));
+        }
+
+        if self.move_window {
+            self.move_window = false;
+            move_to_monitor(ctx.renderer.window, settings().visual.monitor.as_ref());
+        }
+
+        if self.open_calibration {
+            self.open_calibration = false;
+            match CalibrationScreen::new(ctx.audio) {
+                Ok(calibration) => return StateTransition::Push(Box::new(calibration)),
+                Err(e) => log::error!("couldn't start offset calibration: {e}"),
+            }
+        }
+
+        if self.exit {
+            StateTransition::Pop
+        } else {
+            StateTransition::Continue
+        }
+    }
+
+    fn debug_ui(&mut self, ctx: egui::Context, _audio: &mut AudioManager) {
+        egui::Window::new("Settings").show(&ctx, |ui| {
+            ui.heading("Video");
+            self.monitor_ui(ui);
+
+            ui.label("Latency test: tap a drum key when the marker crosses the line.");
+            self.test_strip_ui(ui);
+
+            let format_ms = |seconds: Option<f32>| match seconds {
+                Some(seconds) => format!("{:+.1}ms", seconds * 1000.0),
+                None => "-".to_string(),
+            };
+
+            ui.label(format!(
+                "Offset: {} (over the last {} taps)",
+                format_ms(self.taps.mean()),
+                self.taps.len()
+            ));
+            ui.label(format!("Jitter: {}", format_ms(self.taps.jitter())));
+
+            let mut romanised_titles = settings().visual.romanised_titles;
+            if ui
+                .checkbox(&mut romanised_titles, "Prefer romanised song titles")
+                .on_hover_text("For songs whose charts give one (with TITLEEN)")
+                .changed()
+            {
+                SETTINGS.write().unwrap().visual.romanised_titles = romanised_titles;
+                save_settings().or_log("couldn't save settings");
+            }
+
+            let mut control_hints = settings().visual.control_hints;
+            if ui
+                .checkbox(&mut control_hints, "Show control hints")
+                .on_hover_text("The bar at the bottom of the screen that says what the keys do")
+                .changed()
+            {
+                SETTINGS.write().unwrap().visual.control_hints = control_hints;
+                save_settings().or_log("couldn't save settings");
+            }
+
+            let mut safe_area_margin = settings().visual.safe_area_margin;
+            let response = ui
+                .add(
+                    egui::Slider::new(&mut safe_area_margin, SAFE_AREA_RANGE)
+                        .text("Safe area margin")
+                        .suffix("%"),
+                )
+                .on_hover_text("Keeps the HUD away from the edges of TVs that cut them off");
+
+            if response.hovered() || response.dragged() {
+                safe_area_guides(ui.ctx(), safe_area_margin / 100.0);
+            }
+
+            if response.changed() {
+                SETTINGS.write().unwrap().visual.safe_area_margin = safe_area_margin;
+                save_settings().or_log("couldn't save settings");
+            }
+
+            ui.add_space(20.0);
+            ui.heading("Judgements");
+
+            let mut visual = settings().visual.clone();
+            let mut changed = ui
+                .add(
+                    egui::Slider::new(&mut visual.judgement_scale, HUD_SCALE_RANGE)
+                        .text("Judgement size"),
+                )
+                .changed();
+
+            egui::ComboBox::from_label("Judgement position")
+                .selected_text(visual.judgement_position.name())
+                .show_ui(ui, |ui| {
+                    for position in JudgementPosition::ALL {
+                        changed |= ui
+                            .selectable_value(
+                                &mut visual.judgement_position,
+                                position,
+                                position.name(),
+                            )
+                            .changed();
+                    }
+                });
+
+            changed |= ui
+                .add(egui::Slider::new(&mut visual.combo_scale, HUD_SCALE_RANGE).text("Combo size"))
+                .changed();
+
+            judgement_preview_ui(ui, &visual);
+
+            if changed {
+                SETTINGS.write().unwrap().visual = visual;
+                save_settings().or_log("couldn't save settings");
+            }
+
+            ui.add_space(20.0);
+            ui.heading("Streaming");
+
+            let mut visual = settings().visual.clone();
+            let stream_mode_changed = ui
+                .checkbox(&mut visual.stream_mode, "Stream mode")
+                .on_hover_text("Hides debug overlays and makes the HUD a little bigger")
+                .changed();
+            let key_display_changed = ui
+                .add_enabled(
+                    visual.stream_mode,
+                    egui::Checkbox::new(&mut visual.key_input_display, "Show key inputs"),
+                )
+                .changed();
+
+            if stream_mode_changed || key_display_changed {
+                SETTINGS.write().unwrap().visual = visual;
+                save_settings().or_log("couldn't save settings");
+            }
+
+            ui.add_space(20.0);
+            ui.heading("Gameplay");
+
+            let mut lead_in = settings().game.lead_in();
+            if ui
+                .add(
+                    egui::Slider::new(&mut lead_in, LEAD_IN_RANGE)
+                        .text("Lead-in")
+                        .suffix("s"),
+                )
+                .on_hover_text(
+                    "The least time you get to see the notes coming before the first one. Songs \
+                    whose first note comes sooner start after a silent pause.",
+                )
+                .changed()
+            {
+                SETTINGS.write().unwrap().game.lead_in = lead_in;
+                save_settings().or_log("couldn't save settings");
+            }
+
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "Note offset: {:+}ms",
+                    settings().game.global_note_offset
+                ));
+                if ui
+                    .button("Calibrate")
+                    .on_hover_text("Work out your note offset by tapping along to a metronome")
+                    .clicked()
+                {
+                    self.open_calibration = true;
+                }
+            });
+
+            ui.add_space(20.0);
+            ui.heading("Audio");
+
+            let mut hit_sound_volume = settings().audio.hit_sound_volume();
+            if ui
+                .add(
+                    egui::Slider::new(&mut hit_sound_volume, HIT_SOUND_VOLUME_RANGE)
+                        .text("Hit sound volume")
+                        .suffix("%"),
+                )
+                .on_hover_text("How loud the drum is when you hit it")
+                .changed()
+            {
+                SETTINGS.write().unwrap().audio.hit_sound_volume = hit_sound_volume;
+                save_settings().or_log("couldn't save settings");
+            }
+
+            ui.add_space(20.0);
+            ui.heading("Troubleshooting");
+
+            if ui.button("Write diagnostics report").clicked() {
+                self.run_diagnostics = true;
+            }
+
+            if let Some(message) = &self.diagnostics_message {
+                ui.label(message);
+            }
+
+            ui.add_space(20.0);
+
+            if ui.button(RichText::new("back").size(20.0)).clicked() {
+                self.exit = true;
+            }
+        });
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &WindowEvent) {
+        if let &WindowEvent::KeyboardInput { event, .. } = &event {
+            let key = event.physical_key;
+            let pressed = event.state == ElementState::Pressed && !ctx.keyboard.is_pressed(key);
+
+            if settings().key_is_don_or_kat(key) && pressed {
+                // Measure against whichever crossing is closest, so early taps count as early
+                let beats = self.elapsed() / TEST_BEAT_LENGTH;
+                self.taps.push((beats - beats.round()) * TEST_BEAT_LENGTH);
+            }
+        }
+    }
+
+    fn ducks_music(&self) -> bool {
+        true
+    }
+
+    fn control_hints(&self) -> Vec<(Action, &str)> {
+        vec![(Action::Don, "Tap along"), (Action::Kat, "Tap along")]
+    }
+}
@@ -0,0 +1,155 @@
+//! A cache of the metadata of every song in the song library, so the song list doesn't have to
+//! parse every tja file again each time the game starts.
+//!
+//! Songs are keyed by the path of their tja file, and are only reused if the file is the same size
+//! and was last modified at the same time as when it was cached. Anything wrong with the cache
+//! file (it's missing, corrupt, or from a different version of the game) just means the songs are
+//! all parsed again.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::notechart_parser::Song;
+
+/// The path of the cache file, relative to the game's data directory.
+pub const SONG_CACHE_PATH: &str = "cache/songs.bin";
+
+/// The version of the cache file. This needs to go up whenever [Song] (or anything in it) changes,
+/// so that old caches are thrown away instead of being read wrongly.
+const CACHE_VERSION: u32 = 2;
+
+/// Enough about a file to tell whether it's changed since it was cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    modified: SystemTime,
+    size: u64,
+}
+
+impl FileStamp {
+    pub fn of<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+
+        Ok(Self {
+            modified: metadata.modified()?,
+            size: metadata.len(),
+        })
+    }
+    /// The stamp of several files that are read as one song (like a set of osu beatmaps), which
+    /// changes whenever any of them do.
+    pub fn of_all<P: AsRef<Path>>(paths: &[P]) -> std::io::Result<Self> {
+        let stamps = paths
+            .iter()
+            .map(Self::of)
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            modified: stamps
+                .iter()
+                .map(|stamp| stamp.modified)
+                .max()
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+            size: stamps.iter().map(|stamp| stamp.size).sum(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    stamp: FileStamp,
+    song: Song,
+}
+
+/// The metadata of songs that have been read before. See the [module docs](self).
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SongCache {
+    songs: HashMap<PathBuf, CacheEntry>,
+}
+
+impl SongCache {
+    /// Loads the cache from the given file. If it can't be read for any reason, the cache starts
+    /// out empty.
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let Ok(bytes) = std::fs::read(path) else {
+            return Self::default();
+        };
+
+        match bincode::deserialize::<(u32, SongCache)>(&bytes) {
+            Ok((CACHE_VERSION, cache)) => cache,
+            _ => {
+                log::info!("the song cache is out of date, so every song will be read again");
+                Self::default()
+            }
+        }
+    }
+
+    /// Writes the cache to the given file, creating the directory it goes in if needed.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        std::fs::write(path, bincode::serialize(&(CACHE_VERSION, self))?)?;
+        Ok(())
+    }
+
+    /// Returns the cached song for the given tja file, if the file hasn't changed since.
+    pub fn get(&self, tja_path: &Path, stamp: FileStamp) -> Option<&Song> {
+        self.songs
+            .get(tja_path)
+            .filter(|entry| entry.stamp == stamp)
+            .map(|entry| &entry.song)
+    }
+
+    pub fn insert(&mut self, tja_path: PathBuf, stamp: FileStamp, song: Song) {
+        self.songs.insert(tja_path, CacheEntry { stamp, song });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notechart_parser::{parse_tja_metadata, ParseOptions};
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_cache_round_trip() {
+        let dir = temp_dir("song_cache");
+
+        let tja_path = dir.join("song.tja");
+        let tja = "TITLE:Cached\nWAVE:song.ogg\n\nCOURSE:Oni\nLEVEL:9+\n\n#START\n1,\n#END\n";
+        std::fs::write(&tja_path, tja).unwrap();
+        let stamp = FileStamp::of(&tja_path).unwrap();
+        let song = parse_tja_metadata(tja, ParseOptions::default()).unwrap();
+
+        let mut cache = SongCache::default();
+        cache.insert(tja_path.clone(), stamp, song);
+
+        // The cache file goes in a directory that doesn't exist yet
+        let cache_path = dir.join(SONG_CACHE_PATH);
+        cache.save(&cache_path).unwrap();
+        let loaded = SongCache::load(&cache_path);
+        let cached = loaded.get(&tja_path, stamp).unwrap();
+        assert_eq!(cached.title, "Cached");
+        let oni = cached.difficulties[3].as_ref().unwrap();
+        assert_eq!(oni.star_level.unwrap().to_string(), "9+");
+
+        // A song is read again once its file changes
+        std::fs::write(&tja_path, tja.replace("9+", "10+")).unwrap();
+        let changed = FileStamp::of(&tja_path).unwrap();
+        assert!(loaded.get(&tja_path, changed).is_none());
+
+        // Caches that can't be read are thrown away
+        let is_empty = |cache: SongCache| cache.get(&tja_path, stamp).is_none();
+        std::fs::write(&cache_path, b"not a cache").unwrap();
+        assert!(is_empty(SongCache::load(&cache_path)));
+        let old_version = bincode::serialize(&(CACHE_VERSION + 1, &cache)).unwrap();
+        std::fs::write(&cache_path, old_version).unwrap();
+        assert!(is_empty(SongCache::load(&cache_path)));
+        assert!(is_empty(SongCache::load(dir.join("missing.bin"))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
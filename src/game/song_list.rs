@@ -0,0 +1,283 @@
+//! The order of the song list, and scrolling through it with the keyboard.
+//!
+//! Holding up or down steps through the list at a steady rate at first, then speeds up the longer
+//! it's held. PageUp and PageDown jump between groups of songs, which depend on how the list is
+//! sorted (e.g. all the songs starting with the same letter when sorting by title).
+use crate::notechart_parser::Song;
+
+/// How long between each step when a direction is held, in seconds.
+const SCROLL_INTERVAL: f32 = 0.15;
+/// How long a direction has to be held (in seconds) before the list starts speeding up.
+const FAST_SCROLL_DELAY: f32 = 1.0;
+/// How long it takes to get up to full speed once the list starts speeding up, in seconds.
+const FAST_SCROLL_RAMP_TIME: f32 = 2.0;
+/// How long between each step at full speed, in seconds.
+const FAST_SCROLL_INTERVAL: f32 = 0.02;
+
+/// The group songs without a genre go in when sorting by genre. It's listed last.
+const NO_GENRE: &str = "Unsorted";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortMode {
+    #[default]
+    Title,
+    Genre,
+}
+
+impl SortMode {
+    pub const ALL: [SortMode; 2] = [SortMode::Title, SortMode::Genre];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SortMode::Title => "Title",
+            SortMode::Genre => "Genre",
+        }
+    }
+
+    /// The group the song belongs to: the first letter of its title (or # if it doesn't start
+    /// with a letter) when sorting by title, or its genre when sorting by genre.
+    ///
+    /// Genres are often in Japanese, so they're only trimmed, not changed to one case.
+    pub fn group(&self, song: &Song) -> String {
+        match self {
+            SortMode::Title => match song.title.chars().next() {
+                Some(c) if c.is_alphabetic() => c.to_uppercase().collect(),
+                _ => "#".to_string(),
+            },
+            SortMode::Genre => song
+                .genre
+                .as_deref()
+                .map(str::trim)
+                .filter(|genre| !genre.is_empty())
+                .unwrap_or(NO_GENRE)
+                .to_string(),
+        }
+    }
+
+    /// Sorts the given song indices into the order they should be listed in.
+    pub fn sort(&self, songs: &[Song], order: &mut [usize]) {
+        order.sort_by_cached_key(|&i| {
+            let song = &songs[i];
+            let group = match self {
+                SortMode::Title => String::new(),
+                SortMode::Genre => self.group(song),
+            };
+
+            (group == NO_GENRE, group, song.title.to_lowercase())
+        });
+    }
+
+    /// Splits a sorted list of songs into its groups, in order.
+    pub fn split_groups(&self, songs: &[Song], order: &[usize]) -> Vec<(String, Vec<usize>)> {
+        let mut groups: Vec<(String, Vec<usize>)> = Vec::new();
+
+        for &id in order {
+            let group = self.group(&songs[id]);
+            match groups.last_mut() {
+                Some((last, ids)) if *last == group => ids.push(id),
+                _ => groups.push((group, vec![id])),
+            }
+        }
+
+        groups
+    }
+}
+
+/// Returns the position of the first item in the group after the one at `from`, or the last
+/// position if it's in the last group.
+pub fn next_group(groups: &[String], from: usize) -> usize {
+    let Some(current) = groups.get(from) else {
+        return from;
+    };
+
+    groups[from..]
+        .iter()
+        .position(|group| group != current)
+        .map_or(groups.len().saturating_sub(1), |i| from + i)
+}
+
+/// Returns the position of the first item in the group at `from`. If that's where `from` already
+/// is, returns the first item of the group before it instead.
+pub fn previous_group(groups: &[String], from: usize) -> usize {
+    let group_start = |end: usize| {
+        let current = &groups[end];
+        groups[..end]
+            .iter()
+            .rposition(|group| group != current)
+            .map_or(0, |i| i + 1)
+    };
+
+    if from == 0 || from >= groups.len() {
+        return 0;
+    }
+
+    let start = group_start(from);
+    if start < from {
+        start
+    } else {
+        group_start(from - 1)
+    }
+}
+
+/// How long to wait between steps once a direction has been held for the given number of seconds.
+pub fn scroll_interval(held_for: f32) -> f32 {
+    if held_for < FAST_SCROLL_DELAY {
+        return SCROLL_INTERVAL;
+    }
+
+    let ramp = ((held_for - FAST_SCROLL_DELAY) / FAST_SCROLL_RAMP_TIME).min(1.0);
+    SCROLL_INTERVAL + (FAST_SCROLL_INTERVAL - SCROLL_INTERVAL) * ramp
+}
+
+/// A direction key that's being held down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeldScroll {
+    /// 1 for down the list, -1 for up.
+    direction: isize,
+    held_since: f32,
+    /// The time the next step is due.
+    next_step: f32,
+}
+
+impl HeldScroll {
+    /// Starts holding the given direction. The first step happens straight away.
+    pub fn new(direction: isize, now: f32) -> Self {
+        Self {
+            direction,
+            held_since: now,
+            next_step: now,
+        }
+    }
+
+    pub fn direction(&self) -> isize {
+        self.direction
+    }
+
+    /// Returns how far to move through the list since the last time this was called.
+    pub fn steps(&mut self, now: f32) -> isize {
+        let mut steps = 0;
+
+        while self.next_step <= now {
+            steps += self.direction;
+            self.next_step += scroll_interval(self.next_step - self.held_since);
+        }
+
+        steps
+    }
+
+    /// Whether the list has started speeding up.
+    pub fn is_fast(&self, now: f32) -> bool {
+        now - self.held_since >= FAST_SCROLL_DELAY
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn song(title: &str, genre: Option<&str>) -> Song {
+        Song {
+            title: title.to_string(),
+            genre: genre.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sort_and_group() {
+        let songs = [
+            song("ready to", Some("Vocaloid")),
+            song("Angel Dream", Some("Pop")),
+            song("2000", None),
+            song("Amanojaku", Some("Vocaloid")),
+        ];
+
+        let mut order: Vec<_> = (0..songs.len()).collect();
+        SortMode::Title.sort(&songs, &mut order);
+        assert_eq!(order, [2, 3, 1, 0]);
+
+        let groups: Vec<_> = order
+            .iter()
+            .map(|&i| SortMode::Title.group(&songs[i]))
+            .collect();
+        assert_eq!(groups, ["#", "A", "A", "R"]);
+
+        SortMode::Genre.sort(&songs, &mut order);
+        assert_eq!(order, [1, 3, 0, 2]);
+
+        let groups: Vec<_> = order
+            .iter()
+            .map(|&i| SortMode::Genre.group(&songs[i]))
+            .collect();
+        assert_eq!(groups, ["Pop", "Vocaloid", "Vocaloid", NO_GENRE]);
+    }
+
+    #[test]
+    fn test_genre_sections() {
+        let songs = [
+            song("ready to", Some("Vocaloid ")),
+            song("Angel Dream", Some("ナムコオリジナル")),
+            song("2000", Some("  ")),
+            song("Amanojaku", Some("vocaloid")),
+            song("Kitsune", Some("Vocaloid")),
+        ];
+
+        let mut order: Vec<_> = (0..songs.len()).collect();
+        SortMode::Genre.sort(&songs, &mut order);
+
+        // Genres are trimmed, but different cases are different genres
+        assert_eq!(
+            SortMode::Genre.split_groups(&songs, &order),
+            [
+                ("Vocaloid".to_string(), vec![4, 0]),
+                ("vocaloid".to_string(), vec![3]),
+                ("ナムコオリジナル".to_string(), vec![1]),
+                (NO_GENRE.to_string(), vec![2]),
+            ]
+        );
+        assert_eq!(SortMode::Genre.split_groups(&songs, &[]), []);
+    }
+
+    #[test]
+    fn test_group_jumps() {
+        let groups: Vec<String> = ["A", "A", "A", "B", "C", "C"]
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+
+        assert_eq!(next_group(&groups, 0), 3);
+        assert_eq!(next_group(&groups, 1), 3);
+        assert_eq!(next_group(&groups, 3), 4);
+        assert_eq!(next_group(&groups, 4), 5);
+
+        assert_eq!(previous_group(&groups, 5), 4);
+        assert_eq!(previous_group(&groups, 4), 3);
+        assert_eq!(previous_group(&groups, 3), 0);
+        assert_eq!(previous_group(&groups, 2), 0);
+        assert_eq!(previous_group(&groups, 0), 0);
+
+        assert_eq!(next_group(&[], 0), 0);
+        assert_eq!(previous_group(&[], 0), 0);
+    }
+
+    #[test]
+    fn test_held_scroll_speeds_up() {
+        let mut scroll = HeldScroll::new(1, 0.0);
+        assert_eq!(scroll.steps(0.0), 1);
+        assert!(!scroll.is_fast(0.5));
+
+        // Steady for the first second...
+        assert_eq!(scroll.steps(0.99), 6);
+
+        // ...then faster and faster
+        let slow = scroll.steps(1.5);
+        let fast = scroll.steps(2.0);
+        assert!(fast > slow, "{fast} steps wasn't more than {slow}");
+        assert!(scroll.is_fast(2.0));
+
+        assert!((scroll_interval(10.0) - FAST_SCROLL_INTERVAL).abs() < 1e-6);
+
+        let mut up = HeldScroll::new(-1, 0.0);
+        assert_eq!(up.steps(0.2), -2);
+    }
+}
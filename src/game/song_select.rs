@@ -1,121 +1,1714 @@
-use std::{io, path::Path, rc::Rc};
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender, TryRecvError},
+    },
+};
 
 use crate::{
-    game::credits::CreditsScreen,
-    notechart_parser::{parse_tja_file, Song},
-    render::texture::SpriteBuilder,
+    game::{
+        audio::{resolve_audio_path, song_volume, spawn_loudness_analysis, OrLog},
+        credits::CreditsScreen,
+        dropped_chart::is_tja_file,
+        song_cache::{FileStamp, SongCache},
+        song_list::{next_group, previous_group, HeldScroll, SortMode},
+        song_watcher::SongWatcher,
+        taiko_mode::{
+            format_accuracy, list_replays, NoteShuffle, NoteVisibility, PlayModifiers, Replay,
+            SongAssets, PLAYBACK_SPEEDS,
+        },
+        time::EffectTimer,
+    },
+    local_data::{
+        leaderboard, local_data, local_data_mut, personal_best, save_local_data, SongDataEdit,
+        UndoStack,
+    },
+    notechart_parser::{
+        measure_starts, misplaced_barlines,
+        osu::{combine_beatmaps, parse_osu_file, OsuParseError},
+        parse_tja_file_with_options, parse_tja_metadata, to_tja_string, write_metadata_edits,
+        ChartStats, Difficulty, MetadataEdits, ParseOptions, Song, TJAParseError,
+        TJAParseErrorKind,
+    },
+    paths::paths,
+    render::{
+        text::{truncate_to_width, BuildTextWithRenderer, ELLIPSIS},
+        texture::SpriteBuilder,
+    },
+    settings::settings,
 };
 
 use crate::render::{texture::Sprite, Renderer};
 
 use egui::RichText;
+use kaku::{FontSize, HorizontalAlignment, Text, TextBuilder, VerticalAlignment};
 use kira::{
     manager::AudioManager,
     sound::{
-        static_sound::{StaticSoundData, StaticSoundSettings},
+        static_sound::StaticSoundSettings,
         streaming::{StreamingSoundData, StreamingSoundHandle, StreamingSoundSettings},
         FromFileError,
     },
+    track::TrackId,
     tween::Tween,
 };
 use lazy_static::lazy_static;
+use winit::{
+    event::{ElementState, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+};
 
 use crate::game::{
-    taiko_mode::TaikoMode, Context, GameState, RenderContext, StateTransition, TextureCache,
+    taiko_mode::TaikoMode, Action, Context, GameState, RenderContext, StateTransition, TextureCache,
 };
 
-type SongHandle = StreamingSoundHandle<FromFileError>;
+type SongHandle = StreamingSoundHandle<FromFileError>;
+
+lazy_static! {
+    static ref IN_TWEEN: Tween = Tween {
+        start_time: kira::StartTime::Immediate,
+        duration: std::time::Duration::from_secs_f32(0.2),
+        easing: kira::tween::Easing::OutPowi(2),
+    };
+    static ref OUT_TWEEN: Tween = Tween {
+        start_time: kira::StartTime::Immediate,
+        duration: std::time::Duration::from_secs_f32(0.2),
+        easing: kira::tween::Easing::InPowi(2),
+    };
+}
+
+// Potentially this could go in config but i'm not sure that's necessary
+pub const SONGS_DIR: &str = "songs";
+pub(super) const DIFFICULTY_NAMES: [&str; 5] = ["Easy", "Normal", "Hard", "Oni", "Ura"];
+/// How much the offset buttons change a song's offset by, in milliseconds.
+const OFFSET_NUDGE: f32 = 1.0;
+/// How long the message about an undo is shown for, in seconds.
+const TOAST_DURATION: f32 = 3.0;
+/// How long the group overlay takes to disappear once the list stops scrolling, in seconds.
+const GROUP_OVERLAY_FADE_TIME: f32 = 0.8;
+/// How far through disappearing the group overlay starts to fade out.
+const GROUP_OVERLAY_FADE_START: f32 = 0.4;
+/// The widest a song title in the song list can be, in points.
+const LIST_TITLE_WIDTH: f32 = 360.0;
+/// How tall the list of plays in the leaderboard can get before it scrolls, in points.
+const LEADERBOARD_HEIGHT: f32 = 400.0;
+/// How tall the genre sections in the song list can get before they scroll, in points.
+const GENRE_SECTIONS_HEIGHT: f32 = 500.0;
+
+pub struct SongSelect {
+    songs: Vec<Song>,
+    /// Where each song was read from.
+    song_files: Vec<SongFile>,
+    /// Whether each song's notes have been read. Songs are listed from their metadata, and their
+    /// notes are only read once they're selected.
+    notes_read: Vec<bool>,
+    /// Whether each song is missing any of its audio files, which are checked when it's read.
+    audio_missing: Vec<bool>,
+    /// Watches for songs being added, removed or edited, if that's possible.
+    song_watcher: Option<SongWatcher>,
+    selected: Option<usize>,
+    difficulty: usize,
+    /// The modifiers the song will be played with.
+    modifiers: PlayModifiers,
+    song_preview_handle: Option<SongHandle>,
+    /// The track the song preview is played on.
+    music_track: TrackId,
+    bg_sprite: Rc<Sprite>,
+    go_to_credits: bool,
+    exit: bool,
+    go_to_song: Option<(usize, usize)>,
+    /// Whether the song in `go_to_song` is to be played in practice mode.
+    practise_song: bool,
+    /// Whether songs are played as a local 2P battle (unless they're being practised).
+    battle: bool,
+    /// The replay file to play back next.
+    go_to_replay: Option<PathBuf>,
+    /// The song that's been chosen and is still loading. Nothing else can be done until it's
+    /// ready.
+    loading: Option<LoadingSong>,
+    replays_open: bool,
+    /// The replay files shown in the replay list, newest first. These are only looked for when
+    /// the list is opened.
+    replay_files: Vec<PathBuf>,
+
+    /// Songs whose loudness is currently being analysed (keyed by audio filename).
+    analysing_loudness: HashSet<String>,
+    loudness_sender: Sender<(String, f32)>,
+    loudness_receiver: Receiver<(String, f32)>,
+
+    metadata_editor: Option<MetadataEditor>,
+    /// Whether the leaderboard for the selected chart is open.
+    leaderboard_open: bool,
+
+    /// Edits to the player's song data (favourites etc.) that can be undone with Ctrl+Z.
+    undo: UndoStack,
+    /// A message about the last undo, and when it started showing.
+    toast: Option<(String, EffectTimer)>,
+    /// Whether hidden songs should be shown in the song list.
+    show_hidden: bool,
+    /// The UI time as of the last update.
+    ui_time: f32,
+
+    sort_mode: SortMode,
+    /// The song whose preview is playing (or was last asked to play).
+    previewed: Option<usize>,
+    /// The direction key being held to scroll through the list, if there is one.
+    held_scroll: Option<HeldScroll>,
+    group_overlay: GroupOverlay,
+}
+
+/// A song whose audio and textures are loading on another thread, and how it's to be played once
+/// they're ready.
+struct LoadingSong {
+    assets: Receiver<anyhow::Result<SongAssets>>,
+    song_id: usize,
+    difficulty: usize,
+    modifiers: PlayModifiers,
+    /// Whether the song is to be played as a local 2P battle.
+    versus: bool,
+    /// Whether the song is to be played in practice mode.
+    practice: bool,
+    /// The replay to play back on the song, if it's loading for one.
+    replay: Option<Replay>,
+}
+
+/// A big label that shows which group of songs (see [SortMode::group]) the list is up to while
+/// scrolling quickly through it.
+struct GroupOverlay {
+    text: Text,
+    group: String,
+    /// Whether the list is being scrolled quickly right now.
+    showing: bool,
+    /// The timer for disappearing, once the list has stopped.
+    fade: Option<EffectTimer>,
+    alpha: f32,
+}
+
+impl GroupOverlay {
+    fn new(renderer: &mut Renderer) -> Self {
+        let text = TextBuilder::new("", renderer.font("mochiy pop one"), [1250., 540.])
+            .horizontal_align(HorizontalAlignment::Center)
+            .vertical_align(VerticalAlignment::Middle)
+            .font_size(Some(FontSize::Px(160.)))
+            .color([1.0; 4])
+            .outlined([0., 0., 0., 1.], 6.)
+            .build_text(renderer);
+
+        Self {
+            text,
+            group: String::new(),
+            showing: false,
+            fade: None,
+            alpha: 0.0,
+        }
+    }
+
+    /// Builds the text again after the graphics device was lost, keeping whatever it was showing.
+    fn recreate(&mut self, renderer: &mut Renderer) {
+        let old = std::mem::replace(self, Self::new(renderer));
+        self.text.set_text(
+            old.group.clone(),
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+        self.group = old.group;
+        self.showing = old.showing;
+        self.fade = old.fade;
+    }
+
+    fn show(&mut self, group: String, renderer: &mut Renderer) {
+        if group != self.group {
+            self.text.set_text(
+                group.clone(),
+                &renderer.device,
+                &renderer.queue,
+                &mut renderer.text_renderer,
+            );
+            self.group = group;
+        }
+
+        self.showing = true;
+        self.fade = None;
+    }
+
+    /// Starts fading out the overlay, if it's showing.
+    fn hide(&mut self, now: f32) {
+        if self.showing {
+            self.showing = false;
+            self.fade = Some(EffectTimer::start(now, GROUP_OVERLAY_FADE_TIME));
+        }
+    }
+
+    fn update(&mut self, renderer: &Renderer, now: f32) {
+        let alpha = match (self.showing, self.fade.and_then(|fade| fade.progress(now))) {
+            (true, _) => 1.0,
+            (false, Some(progress)) => {
+                1.0 - ((progress - GROUP_OVERLAY_FADE_START) / (1.0 - GROUP_OVERLAY_FADE_START))
+                    .max(0.0)
+            }
+            (false, None) => 0.0,
+        };
+
+        if alpha != self.alpha {
+            self.text.set_color([1.0, 1.0, 1.0, alpha], &renderer.queue);
+            self.alpha = alpha;
+        }
+    }
+}
+
+/// The state of the panel for editing a song's metadata.
+struct MetadataEditor {
+    song_index: usize,
+    title: String,
+    subtitle: String,
+    genre: String,
+    demostart: f32,
+    levels: [Option<u8>; 5],
+    /// The error from the last attempt to save, if it failed.
+    error: Option<String>,
+}
+
+impl MetadataEditor {
+    fn new(song_index: usize, song: &Song) -> Self {
+        Self {
+            song_index,
+            title: song.title.clone(),
+            subtitle: song.subtitle.clone().unwrap_or_default(),
+            genre: song.genre.clone().unwrap_or_default(),
+            demostart: song.demostart,
+            levels: std::array::from_fn(|i| Self::level(song, i)),
+            error: None,
+        }
+    }
+
+    /// The level the editor starts at for the given difficulty. If the chart doesn't give one, this
+    /// is the estimate, which is only written to the file if it's changed.
+    fn level(song: &Song, difficulty: usize) -> Option<u8> {
+        let difficulty = song.difficulties[difficulty].as_ref()?;
+        difficulty
+            .star_level
+            .map(|level| level.level)
+            .or(difficulty.estimated_level)
+    }
+
+    /// Returns the edits that have been made to the given song.
+    fn edits(&self, song: &Song) -> MetadataEdits {
+        let changed = |new: &str, old: Option<&str>| {
+            (new != old.unwrap_or_default()).then(|| new.to_string())
+        };
+
+        MetadataEdits {
+            title: changed(&self.title, Some(&song.title)),
+            // Keep the subtitle hidden during the song if it was before
+            subtitle: changed(&self.subtitle, song.subtitle.as_deref()).map(|subtitle| {
+                if song.show_subtitle || subtitle.is_empty() {
+                    subtitle
+                } else {
+                    format!("--{subtitle}")
+                }
+            }),
+            genre: changed(&self.genre, song.genre.as_deref()),
+            demostart: (self.demostart != song.demostart).then_some(self.demostart),
+            levels: std::array::from_fn(|i| {
+                let old = Self::level(song, i);
+                self.levels[i].filter(|_| self.levels[i] != old)
+            }),
+        }
+    }
+}
+
+/// Where a song in the song library was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SongFile {
+    /// The song's tja file, or for songs made from osu!taiko beatmaps, the folder they're in.
+    path: PathBuf,
+    /// The path of the song inside the songs directory, e.g. `Genre/Artist/Song/Song.tja`.
+    relative_path: PathBuf,
+}
+
+impl SongFile {
+    /// The song with the given tja file (or folder of beatmaps), in the songs directory `root`.
+    fn new(root: &Path, path: PathBuf) -> Self {
+        let relative_path = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+
+        Self {
+            path,
+            relative_path,
+        }
+    }
+}
+
+/// Reads every song in the given directory, in order of their paths. Songs that can't be read are
+/// logged and left out.
+///
+/// Songs whose files haven't changed since they were cached are taken from the cache instead of
+/// being parsed again. The cache is left holding just the songs that were read.
+fn read_song_list_dir<P: AsRef<Path>>(
+    path: P,
+    cache: &mut SongCache,
+) -> anyhow::Result<Vec<(SongFile, Song)>> {
+    let song_files = find_song_files(path)?;
+    let old_cache = &*cache;
+    let songs = map_in_parallel(&song_files, |file| {
+        let stamp = song_stamp(&file.path).ok();
+        let song = match stamp.and_then(|stamp| old_cache.get(&file.path, stamp)) {
+            Some(song) => Ok(song.clone()),
+            None => read_library_song(&file.path, false),
+        };
+
+        (stamp, song)
+    });
+
+    let mut new_cache = SongCache::default();
+    let mut res = Vec::new();
+
+    for (file, (stamp, song)) in song_files.into_iter().zip(songs) {
+        match song {
+            Ok(song) => {
+                if let Some(stamp) = stamp {
+                    new_cache.insert(file.path.clone(), stamp, song.clone());
+                }
+                res.push((file, song));
+            }
+            Err(e) => log::error!("couldn't read song: {e}"),
+        }
+    }
+
+    *cache = new_cache;
+    Ok(res)
+}
+
+/// Reads the song list, using (and then updating) the song cache unless `rescan` is set.
+fn read_song_list(rescan: bool) -> anyhow::Result<Vec<(SongFile, Song)>> {
+    let cache_path = paths().song_cache_file();
+    let mut cache = if rescan {
+        SongCache::default()
+    } else {
+        SongCache::load(&cache_path)
+    };
+
+    let songs = read_song_list_dir(SONGS_DIR, &mut cache)?;
+    cache
+        .save(&cache_path)
+        .or_log("couldn't save the song cache");
+
+    Ok(songs)
+}
+
+/// Finds every tja file in the given directory and the directories inside it, sorted by path.
+///
+/// Songs can be organised into as many levels of folders as the player likes, and a folder can
+/// hold more than one tja file. Symlinked folders are followed, but never into a folder that's
+/// already been looked through.
+///
+/// A folder of osu!taiko beatmaps with no tja files in it is a song too, with a difficulty for
+/// each beatmap.
+fn find_song_files<P: AsRef<Path>>(path: P) -> io::Result<Vec<SongFile>> {
+    find_song_files_in(path.as_ref(), path.as_ref())
+}
+
+/// Finds every tja file in the given directory inside the songs directory `root`. See
+/// [find_song_files].
+fn find_song_files_in(root: &Path, dir: &Path) -> io::Result<Vec<SongFile>> {
+    let mut visited = HashSet::from([dir.canonicalize()?]);
+    let mut files = Vec::new();
+
+    find_songs(root, dir, &mut visited, &mut files)?;
+    Ok(files)
+}
+
+/// Adds the songs in the given directory (and the directories inside it, that haven't been
+/// visited yet) to the list.
+fn find_songs(
+    root: &Path,
+    dir: &Path,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<SongFile>,
+) -> io::Result<()> {
+    let mut paths = std::fs::read_dir(dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    if is_osu_set(&paths) {
+        files.push(SongFile::new(root, dir.to_path_buf()));
+    }
+
+    for path in paths {
+        // Unlike the entry's file type, this follows symlinks
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+
+        if metadata.is_dir() {
+            let first_visit = path
+                .canonicalize()
+                .is_ok_and(|canonical| visited.insert(canonical));
+
+            if first_visit {
+                find_songs(root, &path, visited, files)
+                    .unwrap_or_else(|e| log::error!("couldn't read {}: {e}", path.display()));
+            }
+        } else if is_tja_file(&path) {
+            files.push(SongFile::new(root, path));
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the given path looks like an osu beatmap.
+fn is_osu_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("osu"))
+}
+
+/// Whether a folder with the given files in it is a set of osu beatmaps, rather than a folder of
+/// tja files (or not a song at all).
+fn is_osu_set(paths: &[PathBuf]) -> bool {
+    let files = || paths.iter().filter(|path| path.is_file());
+    files().any(|path| is_osu_file(path)) && !files().any(|path| is_tja_file(path))
+}
+
+/// The beatmaps in the given folder, sorted by path.
+fn osu_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = std::fs::read_dir(dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| is_osu_file(path) && path.is_file())
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// The stamp of a song in the song library, which changes whenever any of its files do.
+fn song_stamp(path: &Path) -> io::Result<FileStamp> {
+    if is_tja_file(path) {
+        FileStamp::of(path)
+    } else {
+        FileStamp::of_all(&osu_files(path)?)
+    }
+}
+
+/// A change to the song library, found by [library_changes].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LibraryChange {
+    /// A new tja file or set of beatmaps.
+    Added(SongFile),
+    /// The tja file of the song at this index (or one of its beatmaps) was edited.
+    Modified(usize),
+    /// The song at this index is gone.
+    Removed(usize),
+}
+
+/// Works out what's happened to the songs in the songs directory `root`, given the paths that
+/// have changed in it and the songs that were already there.
+///
+/// Each song is only changed once, and new folders are searched for songs the same way the songs
+/// directory is.
+fn library_changes(changed: &[PathBuf], root: &Path, known: &[SongFile]) -> Vec<LibraryChange> {
+    let index_of = |path: &Path| known.iter().position(|file| file.path == path);
+    let mut changes = Vec::new();
+    let mut added = HashSet::new();
+    let mut changed_songs = HashSet::new();
+
+    for path in changed {
+        // A beatmap that's been added, edited or removed changes the song its folder makes up
+        if is_osu_file(path) {
+            let Some(dir) = path.parent() else {
+                continue;
+            };
+
+            match index_of(dir) {
+                Some(i) if changed_songs.insert(i) => changes.push(LibraryChange::Modified(i)),
+                None if path.exists() && added.insert(dir.to_path_buf()) => {
+                    for file in find_song_files_in(root, dir).unwrap_or_default() {
+                        if file.path == dir {
+                            changes.push(LibraryChange::Added(file));
+                        }
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        match std::fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => {
+                for file in find_song_files_in(root, path).unwrap_or_default() {
+                    if index_of(&file.path).is_none() && added.insert(file.path.clone()) {
+                        changes.push(LibraryChange::Added(file));
+                    }
+                }
+            }
+
+            Ok(_) if is_tja_file(path) => match index_of(path) {
+                Some(i) if changed_songs.insert(i) => changes.push(LibraryChange::Modified(i)),
+                None if added.insert(path.clone()) => {
+                    changes.push(LibraryChange::Added(SongFile::new(root, path.clone())))
+                }
+                _ => {}
+            },
+
+            Ok(_) => {}
+
+            // Whatever was here is gone, along with any songs that were in it
+            Err(_) => {
+                for (i, file) in known.iter().enumerate() {
+                    if file.path.starts_with(path) && changed_songs.insert(i) {
+                        changes.push(LibraryChange::Removed(i));
+                    }
+                }
+            }
+        }
+    }
+
+    changes
+}
+
+/// Removes the items at the given indices.
+fn remove_indices<T>(items: &mut Vec<T>, removed: &HashSet<usize>) {
+    let mut i = 0;
+    items.retain(|_| {
+        i += 1;
+        !removed.contains(&(i - 1))
+    });
+}
+
+/// Calls the function on every item, spread across as many threads as there are cores. The
+/// results are in the same order as the items, however long each one takes.
+fn map_in_parallel<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len());
+    let next = AtomicUsize::new(0);
+
+    let mut results = std::thread::scope(|scope| {
+        let workers = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    // Each thread takes the next item no one has started on yet
+                    let mut results = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            break results;
+                        };
+                        results.push((i, f(item)));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    results.sort_by_key(|&(i, _)| i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// How many of the songs in a directory could be read.
+#[derive(Debug, Default)]
+pub struct SongCount {
+    pub parsed: usize,
+    /// The songs (tja files or folders of beatmaps) that couldn't be read, and why.
+    pub failed: Vec<(PathBuf, String)>,
+    /// Songs that were read but look like they have something wrong with them, and what.
+    pub warnings: Vec<(PathBuf, String)>,
+}
+
+/// Whether any of the audio files the song plays don't exist.
+fn audio_missing(song: &Song) -> bool {
+    audio_files(song).any(|file| !Path::new(file).exists())
+}
+
+/// The song's audio file, followed by any its courses have of their own.
+fn audio_files(song: &Song) -> impl Iterator<Item = &str> {
+    std::iter::once(song.audio_filename.as_str()).chain(
+        song.difficulties
+            .iter()
+            .flatten()
+            .filter_map(|difficulty| difficulty.audio_filename.as_deref()),
+    )
+}
+
+/// Formats a length of time as minutes and seconds, e.g. "2:04".
+fn format_duration(seconds: f32) -> String {
+    let seconds = seconds.max(0.0).round() as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Describes a chart's stats, as a summary (e.g. "678 notes, 2:04, peak 12 hits/sec") and the
+/// details after it.
+fn describe_stats(stats: &ChartStats) -> (String, String) {
+    let summary = format!(
+        "{} notes, {}, peak {} hits/sec",
+        stats.hit_notes,
+        format_duration(stats.duration),
+        stats.peak_density
+    );
+    let details = format!(
+        "{} don, {} kat, {:.1}s of drumrolls, {} balloons",
+        stats.dons, stats.kats, stats.roll_time, stats.balloons
+    );
+
+    (summary, details)
+}
+
+/// Describes something the parser let through when reading a song leniently.
+fn parse_warning_message(kind: &TJAParseErrorKind) -> String {
+    match kind {
+        TJAParseErrorKind::BalloonCountMismatch { .. } => {
+            format!("{kind} (balloons without one take 5 hits)")
+        }
+        _ => format!("skipped {kind}"),
+    }
+}
+
+/// Whether a parse warning is about the chart itself rather than something that was left out of it,
+/// so it's worth showing with the song.
+fn is_chart_warning(kind: &TJAParseErrorKind) -> bool {
+    matches!(kind, TJAParseErrorKind::BalloonCountMismatch { .. })
+}
+
+/// Looks for anything odd about a song that was read successfully.
+fn song_warnings(song: &Song) -> Vec<String> {
+    let missing_movie = song
+        .bgmovie
+        .as_ref()
+        .filter(|movie| !Path::new(movie).exists())
+        .map(|movie| format!("the background movie \"{movie}\" doesn't exist"));
+    let missing_lyrics = song
+        .lyrics_file
+        .as_ref()
+        .filter(|lyrics| !Path::new(lyrics).exists())
+        .map(|lyrics| format!("the lyrics file \"{lyrics}\" doesn't exist"));
+    let missing_audio = audio_files(song)
+        .filter(|audio| !Path::new(audio).exists())
+        .map(|audio| format!("the audio file \"{audio}\" doesn't exist"));
+
+    let barline_warnings = song
+        .difficulties
+        .iter()
+        .enumerate()
+        .filter_map(|(i, difficulty)| Some((i, &difficulty.as_ref()?.chart)))
+        .filter_map(|(i, chart)| {
+            let starts = measure_starts(&chart.timing, chart.end_time);
+            let misplaced = misplaced_barlines(&chart.barlines, &starts).len();
+
+            (misplaced > 0).then(|| {
+                format!(
+                    "{}: {misplaced} barlines aren't at the start of a measure",
+                    DIFFICULTY_NAMES[i]
+                )
+            })
+        });
+
+    let parse_warnings = song.warnings.iter().map(|warning| {
+        format!(
+            "{} (at line {})",
+            parse_warning_message(&warning.kind),
+            warning.line_number()
+        )
+    });
+
+    missing_audio
+        .chain(missing_movie)
+        .chain(missing_lyrics)
+        .chain(barline_warnings)
+        .chain(parse_warnings)
+        .collect()
+}
+
+/// Tries to read every song in the given directory, counting how many succeed.
+pub fn count_songs<P: AsRef<Path>>(path: P) -> io::Result<SongCount> {
+    let mut count = SongCount::default();
+    let song_files = find_song_files(path)?;
+    let songs = map_in_parallel(&song_files, |file| read_library_song(&file.path, true));
+
+    for (file, song) in song_files.into_iter().zip(songs) {
+        match song {
+            Ok(song) => {
+                count.parsed += 1;
+                count.warnings.extend(
+                    song_warnings(&song)
+                        .into_iter()
+                        .map(|warning| (file.path.clone(), warning)),
+                );
+            }
+            Err(e) => count.failed.push((file.path, e.to_string())),
+        }
+    }
+
+    Ok(count)
+}
+
+/// Returns the path of the tja file in the given song directory.
+fn tja_file_path<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    let dir_name = path.as_ref().file_name().ok_or(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "couldn't read directory name",
+    ))?;
+
+    Ok(path
+        .as_ref()
+        .join(format!("{}.tja", dir_name.to_string_lossy())))
+}
+
+/// Reads the song in the given song directory (from the tja file named after the directory),
+/// notes and all. If there isn't a tja file, the osu beatmaps in the directory are read instead.
+#[cfg(test)]
+pub(super) fn read_song_dir<P: AsRef<Path>>(path: P) -> anyhow::Result<Song> {
+    let tja_path = tja_file_path(&path)?;
+
+    if !tja_path.exists() && !osu_files(path.as_ref())?.is_empty() {
+        read_osu_set(path.as_ref())
+    } else {
+        read_song_file(tja_path, parse_tja_file_with_options)
+    }
+}
+
+/// Finds the song with the given key (see [LocalData::song](crate::local_data::LocalData::song)) in
+/// the song library at the given path, and reads it, notes and all. Returns None if it isn't
+/// there.
+pub fn find_library_song<P: AsRef<Path>>(path: P, song_key: &str) -> anyhow::Result<Option<Song>> {
+    for file in find_song_files(path)? {
+        // Songs that can't be read can't be the one that was played
+        let Ok(song) = read_library_song(&file.path, false) else {
+            continue;
+        };
+
+        if song.audio_filename == song_key {
+            return read_library_song(&file.path, true).map(Some);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads the song in the given tja file or folder of osu beatmaps, notes and all, whether or not
+/// it's in the song library.
+pub fn read_chart_file(path: &Path) -> anyhow::Result<Song> {
+    read_library_song(path, true)
+}
+
+/// Reads a song in the song library, which is either a tja file or a folder of osu beatmaps (see
+/// [find_song_files]). `notes` is whether the notes are read as well as the metadata. Beatmaps
+/// are always read in full, since they're small enough that it doesn't take any longer.
+fn read_library_song(path: &Path, notes: bool) -> anyhow::Result<Song> {
+    if !is_tja_file(path) {
+        return read_osu_set(path);
+    }
+
+    if notes {
+        read_song_file(path, parse_tja_file_with_options)
+    } else {
+        read_song_file(path, parse_tja_metadata)
+    }
+}
+
+/// Reads the osu!taiko beatmaps in the given folder as one song. Beatmaps for the other osu game
+/// modes are left out.
+fn read_osu_set(dir: &Path) -> anyhow::Result<Song> {
+    let mut song = combine_osu_set(dir)?;
+    resolve_file_paths(&mut song, dir);
+
+    Ok(song)
+}
+
+/// Reads the osu!taiko beatmaps in the given folder as one song, like [read_osu_set], but leaves
+/// its file paths the way the beatmaps give them (relative to the folder).
+fn combine_osu_set(dir: &Path) -> anyhow::Result<Song> {
+    let mut beatmaps = Vec::new();
+
+    for path in osu_files(dir)? {
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+
+        match parse_osu_file(&contents) {
+            Ok(beatmap) => beatmaps.push(beatmap),
+            Err(OsuParseError::WrongMode(_)) => {}
+            Err(e) => log::warn!("{}: skipped the beatmap, since {e}", path.display()),
+        }
+    }
+
+    combine_beatmaps(beatmaps)
+        .ok_or_else(|| anyhow::anyhow!("{}: there are no osu!taiko beatmaps here", dir.display()))
+}
+
+/// Writes the song made from the osu beatmaps in the given folder out as a tja file in the same
+/// folder, named after it, so it can be edited like any other chart. Returns the tja file's path.
+///
+/// The beatmaps are left where they are, but since the folder has a tja file in it now, that's
+/// what the song is read from from then on.
+fn convert_to_tja(dir: &Path) -> anyhow::Result<PathBuf> {
+    let tja_path = tja_file_path(dir)?;
+    if tja_path.exists() {
+        anyhow::bail!("{} already exists", tja_path.display());
+    }
+
+    std::fs::write(&tja_path, to_tja_string(&combine_osu_set(dir)?))?;
+    Ok(tja_path)
+}
+
+/// Reads the song in the given tja file with the given parse function, which either reads the
+/// whole file or just its metadata (see [parse_tja_metadata]). The song's files are made relative
+/// to the game.
+fn read_song_file<P: AsRef<Path>>(
+    tja_path: P,
+    parse: fn(&str, ParseOptions) -> Result<Song, TJAParseError>,
+) -> anyhow::Result<Song> {
+    let tja_path = tja_path.as_ref();
+    let tja_file_contents = std::fs::read_to_string(tja_path)
+        .map_err(|e| anyhow::anyhow!("{}: {e}", tja_path.display()))?;
+
+    let options = ParseOptions {
+        comment_labels: settings().game.comment_section_labels,
+        lenient: true,
+        max_subdivisions: settings().game.max_measure_subdivisions,
+    };
+    // Parse errors point at the line in the file, the way a compiler would
+    let mut song = parse(&tja_file_contents, options).map_err(|e| {
+        let source_line = e.source_line(&tja_file_contents).unwrap_or_default();
+        anyhow::anyhow!(
+            "{}:{}: {}\n    {source_line}",
+            tja_path.display(),
+            e.line_number(),
+            e.kind
+        )
+    })?;
+    for warning in &song.warnings {
+        log::warn!(
+            "{}:{}: {}",
+            tja_path.display(),
+            warning.line_number(),
+            parse_warning_message(&warning.kind)
+        );
+    }
+    resolve_file_paths(&mut song, tja_path.parent().unwrap_or(Path::new("")));
+
+    Ok(song)
+}
+
+/// Makes the song's audio, movie and lyrics filenames (which are relative to its tja file, or its
+/// beatmaps) relative to the game instead, given the directory they're in.
+pub(super) fn resolve_file_paths(song: &mut Song, dir: &Path) {
+    // Audio files are looked for under other extensions if they aren't where the chart says
+    let audio_path = |filename: &str| {
+        resolve_audio_path(&dir.join(filename), Path::exists)
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    song.audio_filename = audio_path(&song.audio_filename);
+
+    for file in [song.bgmovie.as_mut(), song.lyrics_file.as_mut()]
+        .into_iter()
+        .flatten()
+    {
+        *file = dir.join(&file).to_string_lossy().into_owned();
+    }
+
+    for difficulty in song
+        .difficulties
+        .iter_mut()
+        .chain([&mut song.dan])
+        .flatten()
+    {
+        if let Some(filename) = difficulty.audio_filename.as_mut() {
+            *filename = audio_path(filename);
+        }
+
+        for dan_song in &mut difficulty.chart.dan_songs {
+            dan_song.audio_filename = audio_path(&dan_song.audio_filename);
+        }
+    }
+}
+
+/// Writes the given edits to the given tja file, and reads the song again.
+///
+/// The first time a song is edited, a copy of the original file is kept next to it with the
+/// extension `.tja.bak`.
+fn save_metadata_edits<P: AsRef<Path>>(tja_path: P, edits: &MetadataEdits) -> anyhow::Result<Song> {
+    let tja_path = tja_path.as_ref();
+    let contents = std::fs::read_to_string(tja_path)?;
+
+    let backup_path = tja_path.with_extension("tja.bak");
+    if !backup_path.exists() {
+        std::fs::copy(tja_path, &backup_path)?;
+    }
+
+    std::fs::write(tja_path, write_metadata_edits(&contents, edits))?;
+    read_song_file(tja_path, parse_tja_file_with_options)
+}
+
+impl SongSelect {
+    pub fn new(
+        textures: &mut TextureCache,
+        renderer: &mut Renderer,
+        music_track: TrackId,
+    ) -> anyhow::Result<Self> {
+        let (song_files, test_tracks): (Vec<_>, Vec<_>) =
+            read_song_list(false)?.into_iter().unzip();
+        let bg_sprite = Self::background(textures, renderer)?;
+
+        let (loudness_sender, loudness_receiver) = mpsc::channel();
+
+        Ok(SongSelect {
+            notes_read: vec![false; test_tracks.len()],
+            audio_missing: test_tracks.iter().map(audio_missing).collect(),
+            song_watcher: SongWatcher::new(SONGS_DIR).or_log("couldn't watch the songs directory"),
+            songs: test_tracks,
+            song_files,
+            bg_sprite: Rc::new(bg_sprite),
+            selected: None,
+            difficulty: 0,
+            modifiers: PlayModifiers::default(),
+            song_preview_handle: None,
+            music_track,
+            go_to_credits: false,
+            exit: false,
+            go_to_song: None,
+            practise_song: false,
+            battle: false,
+            go_to_replay: None,
+            loading: None,
+            replays_open: false,
+            replay_files: Vec::new(),
+            analysing_loudness: HashSet::new(),
+            loudness_sender,
+            loudness_receiver,
+            metadata_editor: None,
+            leaderboard_open: false,
+            undo: UndoStack::new(),
+            toast: None,
+            show_hidden: false,
+            ui_time: 0.0,
+            sort_mode: SortMode::default(),
+            previewed: None,
+            held_scroll: None,
+            group_overlay: GroupOverlay::new(renderer),
+        })
+    }
+
+    fn background(textures: &mut TextureCache, renderer: &mut Renderer) -> anyhow::Result<Sprite> {
+        Ok(SpriteBuilder::new(textures.get(
+            &renderer.device,
+            &renderer.queue,
+            "song_select_bg.jpg",
+        )?)
+        .build(renderer))
+    }
+
+    /// The title of the given song as it should appear in the song list, cut short if it's too
+    /// long to fit. Songs with missing audio are marked with a warning sign.
+    fn list_title(&self, ui: &egui::Ui, id: usize, size: f32) -> String {
+        let title = self.songs[id].display_title(settings().visual.romanised_titles);
+        let title = if self.audio_missing[id] {
+            Cow::Owned(format!("⚠ {title}"))
+        } else {
+            Cow::Borrowed(title)
+        };
+        let font = egui::FontId::proportional(size);
+
+        ui.fonts(|fonts| {
+            truncate_to_width(&title, LIST_TITLE_WIDTH, ELLIPSIS, |text| {
+                fonts
+                    .layout_no_wrap(text.to_string(), font.clone(), egui::Color32::WHITE)
+                    .size()
+                    .x
+            })
+            .into_owned()
+        })
+    }
+
+    /// The songs in the order they're listed, leaving out hidden songs unless they're being shown
+    /// (or are selected).
+    fn song_order(&self) -> Vec<usize> {
+        let data = local_data();
+        let mut order: Vec<usize> = (0..self.songs.len())
+            .filter(|&id| {
+                let hidden = data
+                    .song(&self.songs[id].audio_filename)
+                    .is_some_and(|data| data.hidden);
+
+                !hidden || self.show_hidden || self.selected == Some(id)
+            })
+            .collect();
+
+        self.sort_mode.sort(&self.songs, &mut order);
+        order
+    }
+
+    /// Moves the selection the given number of songs down the list (or up, if it's negative).
+    fn scroll_list(&mut self, steps: isize) {
+        let order = self.song_order();
+        let Some(last) = order.len().checked_sub(1) else {
+            return;
+        };
+
+        let position = match order.iter().position(|&id| Some(id) == self.selected) {
+            Some(position) => (position as isize + steps).clamp(0, last as isize) as usize,
+            None if steps < 0 => last,
+            None => 0,
+        };
+
+        self.selected = Some(order[position]);
+    }
+
+    /// Moves the selection to the start of the next (or previous) group of songs, and briefly
+    /// shows which group that is.
+    fn jump_group(&mut self, forward: bool, renderer: &mut Renderer, now: f32) {
+        let order = self.song_order();
+        let groups: Vec<_> = order
+            .iter()
+            .map(|&id| self.sort_mode.group(&self.songs[id]))
+            .collect();
+
+        let Some(position) = order.iter().position(|&id| Some(id) == self.selected) else {
+            self.scroll_list(if forward { 1 } else { -1 });
+            return;
+        };
+
+        let position = if forward {
+            next_group(&groups, position)
+        } else {
+            previous_group(&groups, position)
+        };
+
+        self.selected = Some(order[position]);
+        self.group_overlay.show(groups[position].clone(), renderer);
+        self.group_overlay.hide(now);
+    }
+
+    /// Picks up any songs that have been added, removed or edited since the last update. Changes
+    /// made while playing a song wait until the player comes back.
+    fn apply_library_changes(&mut self) {
+        let Some(watcher) = &self.song_watcher else {
+            return;
+        };
+        let changed = watcher.changed_paths();
+        if changed.is_empty() {
+            return;
+        }
+
+        let mut removed = HashSet::new();
+
+        for change in library_changes(&changed, Path::new(SONGS_DIR), &self.song_files) {
+            match change {
+                LibraryChange::Added(file) => {
+                    let Some(song) =
+                        read_library_song(&file.path, false).or_log("couldn't read new song")
+                    else {
+                        continue;
+                    };
+
+                    log::info!("found new song {}", file.path.display());
+                    self.audio_missing.push(audio_missing(&song));
+                    self.songs.push(song);
+                    self.song_files.push(file);
+                    self.notes_read.push(false);
+                }
+
+                // A song that's been broken by an edit keeps what it had before
+                LibraryChange::Modified(i) => {
+                    let Some(song) = read_library_song(&self.song_files[i].path, false)
+                        .or_log("couldn't read edited song")
+                    else {
+                        continue;
+                    };
+
+                    self.audio_missing[i] = audio_missing(&song);
+                    self.songs[i] = song;
+                    self.notes_read[i] = false;
+                    if self.selected == Some(i) {
+                        self.read_notes(i).or_log("couldn't read edited song");
+                    }
+                }
+
+                LibraryChange::Removed(i) => {
+                    removed.insert(i);
+                }
+            }
+        }
+
+        if !removed.is_empty() {
+            self.remove_songs(&removed);
+        }
+    }
+
+    /// Takes the songs at the given indices out of the list, keeping track of the ones left.
+    fn remove_songs(&mut self, removed: &HashSet<usize>) {
+        let new_index = |i: usize| {
+            (!removed.contains(&i)).then(|| i - removed.iter().filter(|&&r| r < i).count())
+        };
+
+        if self.previewed.is_some_and(|i| removed.contains(&i)) {
+            if let Some(handle) = self.song_preview_handle.as_mut() {
+                handle.stop(*OUT_TWEEN).or_log("couldn't stop song preview");
+            }
+            self.song_preview_handle = None;
+        }
+
+        self.selected = self.selected.and_then(new_index);
+        self.previewed = self.previewed.and_then(new_index);
+        self.go_to_song = self
+            .go_to_song
+            .and_then(|(i, difficulty)| Some((new_index(i)?, difficulty)));
+        if let Some(editor) = self.metadata_editor.as_mut() {
+            match new_index(editor.song_index) {
+                Some(i) => editor.song_index = i,
+                None => self.metadata_editor = None,
+            }
+        }
+
+        remove_indices(&mut self.songs, removed);
+        remove_indices(&mut self.song_files, removed);
+        remove_indices(&mut self.notes_read, removed);
+        remove_indices(&mut self.audio_missing, removed);
+    }
+
+    /// Reads every song again from scratch, ignoring the song cache.
+    fn rescan(&mut self) -> anyhow::Result<()> {
+        let (song_files, songs): (Vec<_>, Vec<_>) = read_song_list(true)?.into_iter().unzip();
+
+        // The songs might not be in the same places any more
+        self.selected = None;
+        self.go_to_song = None;
+        self.metadata_editor = None;
+        self.leaderboard_open = false;
+
+        self.notes_read = vec![false; songs.len()];
+        self.audio_missing = songs.iter().map(audio_missing).collect();
+        self.songs = songs;
+        self.song_files = song_files;
+        Ok(())
+    }
+
+    /// Reads the notes of the given song, if they haven't been read yet.
+    fn read_notes(&mut self, song_index: usize) -> anyhow::Result<()> {
+        if !self.notes_read[song_index] {
+            self.songs[song_index] = read_library_song(&self.song_files[song_index].path, true)?;
+            self.notes_read[song_index] = true;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the gain (in decibels) that should be applied to the given song's preview.
+    ///
+    /// If the song hasn't been analysed yet, this starts analysing it in the background and
+    /// returns 0 (unity gain). The result will be used the next time the song is previewed.
+    fn preview_gain(&mut self, selected: usize) -> f32 {
+        if !settings().audio.normalise_previews {
+            return 0.0;
+        }
+
+        let song = &self.songs[selected];
+        let audio_filename = song.course_audio_filename(self.difficulty);
+
+        if let Some(gain) = local_data()
+            .song(audio_filename)
+            .and_then(|data| data.preview_gain_db)
+        {
+            return gain;
+        }
+
+        if self.analysing_loudness.insert(audio_filename.to_string()) {
+            spawn_loudness_analysis(
+                audio_filename.to_string(),
+                song.course_demostart(self.difficulty) as f64,
+                self.loudness_sender.clone(),
+            );
+        }
+
+        0.0
+    }
+
+    /// Makes a change to the player's data for the given song, so that it can be undone later.
+    fn edit_song_data(&mut self, song_index: usize, edit: SongDataEdit) {
+        let song = &self.songs[song_index];
+        self.undo.apply(
+            &mut local_data_mut(),
+            &song.audio_filename,
+            &song.title,
+            edit,
+        );
+
+        if let Err(e) = save_local_data() {
+            log::error!("couldn't save local data: {e}");
+        }
+    }
+
+    /// Undoes the last change to the player's song data, if there is one.
+    fn undo_song_data_edit(&mut self, now: f32) {
+        let Some(message) = self.undo.undo(&mut local_data_mut()) else {
+            return;
+        };
+
+        if let Err(e) = save_local_data() {
+            log::error!("couldn't save local data: {e}");
+        }
+
+        self.toast = Some((message, EffectTimer::start(now, TOAST_DURATION)));
+    }
+
+    /// Shows a message at the bottom of the screen for a few seconds.
+    fn show_toast(&mut self, message: String) {
+        self.toast = Some((message, EffectTimer::start(self.ui_time, TOAST_DURATION)));
+    }
+
+    fn toast_ui(&self, ctx: &egui::Context) {
+        let Some((message, timer)) = &self.toast else {
+            return;
+        };
+
+        if timer.progress(self.ui_time).is_some() {
+            egui::Area::new("undo toast".into())
+                .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -60.0])
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.label(RichText::new(message).size(20.0));
+                    });
+                });
+        }
+    }
+
+    /// Stores the results of any loudness analyses that have finished.
+    fn receive_loudness_results(&mut self) {
+        let mut received = false;
+
+        for (audio_filename, gain) in self.loudness_receiver.try_iter() {
+            self.analysing_loudness.remove(&audio_filename);
+            local_data_mut().song_mut(&audio_filename).preview_gain_db = Some(gain);
+            received = true;
+        }
+
+        if received {
+            if let Err(e) = save_local_data() {
+                log::error!("couldn't save local data: {e}");
+            }
+        }
+    }
+
+    /// Shows the recent plays of the selected chart, if the leaderboard is open.
+    fn leaderboard_ui(&mut self, ctx: &egui::Context) {
+        let Some(song_index) = self.selected.filter(|_| self.leaderboard_open) else {
+            return;
+        };
+
+        let song = &self.songs[song_index];
+        let plays = local_data()
+            .song(&song.audio_filename)
+            .map(|data| data.plays_for(self.difficulty))
+            .unwrap_or_default();
+        let pinned = personal_best(&plays).is_some();
+
+        egui::Window::new(format!(
+            "{} ({})",
+            song.title, DIFFICULTY_NAMES[self.difficulty]
+        ))
+        .id(egui::Id::new("leaderboard"))
+        .open(&mut self.leaderboard_open)
+        .show(ctx, |ui| {
+            if plays.is_empty() {
+                ui.label("You haven't played this yet!");
+                return;
+            }
+
+            egui::ScrollArea::vertical()
+                .max_height(LEADERBOARD_HEIGHT)
+                .show(ui, |ui| {
+                    egui::Grid::new("leaderboard plays")
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for heading in ["", "Score", "Accuracy", "Date", ""] {
+                                ui.label(RichText::new(heading).strong());
+                            }
+                            ui.end_row();
+
+                            for (i, play) in leaderboard(&plays).into_iter().enumerate() {
+                                // The personal best is pinned to the top, outside the ranking
+                                let rank = match (pinned, i) {
+                                    (true, 0) => "Best".to_string(),
+                                    (true, i) => i.to_string(),
+                                    (false, i) => (i + 1).to_string(),
+                                };
+
+                                ui.label(rank);
+                                ui.label(play.score.to_string());
+                                ui.label(format_accuracy(play.accuracy));
+                                ui.label(play.date_string());
+
+                                if play.conditions.is_default() {
+                                    ui.label("");
+                                } else {
+                                    ui.label("⚠").on_hover_text(format!(
+                                        "Not played under the usual conditions: {}",
+                                        play.conditions
+                                    ));
+                                }
+                                ui.end_row();
+                            }
+                        });
+                });
+        });
+    }
+
+    /// Shows the list of saved replays, if it's open. Choosing one plays it back.
+    fn replays_ui(&mut self, ctx: &egui::Context) {
+        let mut chosen = None;
+        let mut verify = None;
+
+        egui::Window::new("replays")
+            .open(&mut self.replays_open)
+            .show(ctx, |ui| {
+                if self.replay_files.is_empty() {
+                    ui.label("There aren't any replays yet!");
+                    return;
+                }
+
+                egui::ScrollArea::vertical()
+                    .max_height(LEADERBOARD_HEIGHT)
+                    .show(ui, |ui| {
+                        for path in &self.replay_files {
+                            let name = path
+                                .file_stem()
+                                .unwrap_or_default()
+                                .to_string_lossy()
+                                .into_owned();
+                            ui.horizontal(|ui| {
+                                if ui.button(name).clicked() {
+                                    chosen = Some(path.clone());
+                                }
+
+                                if cfg!(debug_assertions) && ui.small_button("Verify").clicked() {
+                                    verify = Some(path.clone());
+                                }
+                            });
+                        }
+                    });
+            });
+
+        if chosen.is_some() {
+            self.go_to_replay = chosen;
+            self.replays_open = false;
+        }
+
+        if let Some(path) = verify {
+            let verification = Replay::load(&path).and_then(|replay| {
+                let (_, chart) = self.replay_chart(&replay)?;
+                replay.verify(chart)
+            });
+            match verification {
+                Ok(verification) => self.show_toast(format!("The replay {verification}")),
+                Err(e) => self.show_toast(format!("Couldn't verify the replay: {e}")),
+            }
+        }
+    }
+
+    /// Shows the metadata editing panel, if it's open.
+    fn metadata_editor_ui(&mut self, ctx: &egui::Context) {
+        let Some(editor) = self.metadata_editor.as_mut() else {
+            return;
+        };
+
+        let mut save = false;
+        let mut close = false;
+
+        egui::Window::new("edit metadata").show(ctx, |ui| {
+            egui::Grid::new("metadata grid").show(ui, |ui| {
+                ui.label("Title");
+                ui.text_edit_singleline(&mut editor.title);
+                ui.end_row();
+
+                ui.label("Subtitle");
+                ui.text_edit_singleline(&mut editor.subtitle);
+                ui.end_row();
+
+                ui.label("Genre");
+                ui.text_edit_singleline(&mut editor.genre);
+                ui.end_row();
+
+                ui.label("Preview start");
+                ui.add(
+                    egui::DragValue::new(&mut editor.demostart)
+                        .speed(0.1)
+                        .range(0.0..=f32::MAX)
+                        .suffix("s"),
+                );
+                ui.end_row();
+
+                for (name, level) in DIFFICULTY_NAMES.iter().zip(editor.levels.iter_mut()) {
+                    if let Some(level) = level {
+                        ui.label(format!("{name} level"));
+                        ui.add(egui::DragValue::new(level).range(1..=10).suffix("★"));
+                        ui.end_row();
+                    }
+                }
+            });
+
+            if let Some(error) = &editor.error {
+                ui.colored_label(egui::Color32::from_rgb(255, 80, 80), error);
+            }
+
+            ui.horizontal(|ui| {
+                save = ui.button("Save").clicked();
+                close = ui.button("Cancel").clicked();
+            });
+        });
+
+        if save {
+            let index = editor.song_index;
+            let edits = editor.edits(&self.songs[index]);
+
+            match save_metadata_edits(&self.song_files[index].path, &edits) {
+                Ok(song) => {
+                    self.songs[index] = song;
+                    self.notes_read[index] = true;
+                    close = true;
+                }
+
+                Err(e) => {
+                    log::error!("couldn't save metadata: {e}");
+                    editor.error = Some(format!("couldn't save metadata: {e}"));
+                }
+            }
+        }
+
+        if close {
+            self.metadata_editor = None;
+        }
+    }
+
+    /// Replaces the song at the given index, which is made from osu beatmaps, with a tja file
+    /// written out from them (see [convert_to_tja]).
+    fn convert_song(&mut self, song_index: usize) {
+        let converted = convert_to_tja(&self.song_files[song_index].path)
+            .and_then(|path| Ok((read_library_song(&path, true)?, path)));
+
+        match converted {
+            Ok((song, path)) => {
+                let file = &mut self.song_files[song_index];
+                if let Some(name) = path.file_name() {
+                    file.relative_path.push(name);
+                }
+                file.path = path;
+                let message = format!("Wrote {}", file.relative_path.display());
+
+                self.songs[song_index] = song;
+                self.notes_read[song_index] = true;
+                self.show_toast(message);
+            }
+
+            Err(e) => {
+                log::error!("couldn't convert the song to tja: {e}");
+                self.show_toast(format!("Couldn't convert the song to tja: {e}"));
+            }
+        }
+    }
+
+    /// Lists the songs in a collapsible section for each genre.
+    fn genre_sections(&mut self, ui: &mut egui::Ui, order: &[usize]) {
+        let groups = self.sort_mode.split_groups(&self.songs, order);
+        // When the selection moves into a section (e.g. with the keyboard), open it
+        let selection_moved = self.selected != self.previewed;
+
+        egui::ScrollArea::vertical()
+            .max_height(GENRE_SECTIONS_HEIGHT)
+            .show(ui, |ui| {
+                for (genre, ids) in groups {
+                    let has_selected = self.selected.is_some_and(|id| ids.contains(&id));
+
+                    egui::CollapsingHeader::new(RichText::new(&genre).size(20.0))
+                        .id_source(&genre)
+                        .open((has_selected && selection_moved).then_some(true))
+                        .show(ui, |ui| {
+                            for id in ids {
+                                let title = self.list_title(ui, id, 15.0);
+                                ui.selectable_value(
+                                    &mut self.selected,
+                                    Some(id),
+                                    RichText::new(title).size(15.0),
+                                );
+                            }
+                        });
+                }
+            });
+    }
+
+    /// Starts loading the given song's audio and textures on another thread. The song is played
+    /// normally unless the fields saying otherwise are changed.
+    fn load_song(
+        &self,
+        ctx: &Context,
+        song_id: usize,
+        difficulty: usize,
+        modifiers: PlayModifiers,
+    ) -> LoadingSong {
+        let song = &self.songs[song_id];
+        let audio_filename = song.course_audio_filename(difficulty);
+
+        let gain = if settings().audio.normalise_gameplay {
+            local_data()
+                .song(audio_filename)
+                .and_then(|data| data.preview_gain_db)
+                .unwrap_or(0.0)
+        } else {
+            0.0
+        };
+
+        let assets = SongAssets::spawn_load(
+            audio_filename.to_string(),
+            StaticSoundSettings::default()
+                .volume(song_volume(gain, song.song_volume))
+                .output_destination(ctx.music_track),
+        );
+
+        LoadingSong {
+            assets,
+            song_id,
+            difficulty,
+            modifiers,
+            versus: false,
+            practice: false,
+            replay: None,
+        }
+    }
+
+    /// Goes to the scene for the song that's loading, once it's ready. If it couldn't be loaded,
+    /// the player is told why and stays in song select.
+    fn finish_loading(&mut self, ctx: &mut Context) -> StateTransition {
+        let Some(loading) = &self.loading else {
+            return StateTransition::Continue;
+        };
 
-lazy_static! {
-    static ref IN_TWEEN: Tween = Tween {
-        start_time: kira::StartTime::Immediate,
-        duration: std::time::Duration::from_secs_f32(0.2),
-        easing: kira::tween::Easing::OutPowi(2),
-    };
-    static ref OUT_TWEEN: Tween = Tween {
-        start_time: kira::StartTime::Immediate,
-        duration: std::time::Duration::from_secs_f32(0.2),
-        easing: kira::tween::Easing::InPowi(2),
-    };
-}
+        let assets = match loading.assets.try_recv() {
+            Ok(assets) => assets,
+            Err(TryRecvError::Empty) => return StateTransition::Continue,
+            Err(TryRecvError::Disconnected) => Err(anyhow::anyhow!("the song stopped loading")),
+        };
+        let Some(loading) = self.loading.take() else {
+            return StateTransition::Continue;
+        };
 
-// Potentially this could go in config but i'm not sure that's necessary
-const SONGS_DIR: &str = "songs";
+        let song = &self.songs[loading.song_id];
+        let scene = assets
+            .and_then(|assets| {
+                TaikoMode::new(
+                    song,
+                    assets,
+                    ctx.audio,
+                    loading.difficulty,
+                    loading.modifiers,
+                    ctx.renderer,
+                    ctx.textures,
+                )
+            })
+            .and_then(|scene| {
+                if loading.versus {
+                    scene.versus(song, loading.modifiers, ctx.renderer, ctx.textures)
+                } else {
+                    Ok(scene)
+                }
+            });
 
-pub struct SongSelect {
-    songs: Vec<Song>,
-    selected: Option<usize>,
-    difficulty: usize,
-    song_preview_handle: Option<SongHandle>,
-    bg_sprite: Rc<Sprite>,
-    go_to_credits: bool,
-    exit: bool,
-    go_to_song: Option<(usize, usize)>,
-}
+        match scene {
+            Ok(scene) => {
+                if let Some(handle) = self.song_preview_handle.as_mut() {
+                    handle
+                        .stop(Default::default())
+                        .or_log("couldn't stop song preview");
+                }
 
-fn read_song_list_dir<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<Song>> {
-    let dir = std::fs::read_dir(path)?;
-    let mut res = Vec::new();
+                let scene = match loading.replay {
+                    Some(replay) => scene.playing_back(replay, ctx.renderer),
+                    None if loading.practice => scene.practice(),
+                    None => scene,
+                };
+                StateTransition::Push(Box::new(scene))
+            }
 
-    for file in dir.flatten() {
-        if file.file_type().map(|ty| ty.is_dir()).unwrap_or(false) {
-            let subdir_path = file.path();
+            Err(e) if loading.replay.is_some() => {
+                log::error!("couldn't play replay: {e}");
+                self.show_toast(format!("Couldn't play the replay: {e}"));
+                StateTransition::Continue
+            }
 
-            match read_song_dir(&subdir_path) {
-                Ok(song) => res.push(song),
-                Err(e) => log::error!(
-                    "error encountered while trying to read song at directory {}: {e}",
-                    subdir_path.to_string_lossy()
-                ),
+            Err(e) => {
+                log::error!("couldn't start song: {e}");
+                self.show_toast(format!("Couldn't start the song: {e}"));
+                StateTransition::Continue
             }
         }
     }
 
-    Ok(res)
-}
-
-fn read_song_dir<P: AsRef<Path>>(path: P) -> anyhow::Result<Song> {
-    let dir_name = path.as_ref().file_name().ok_or(io::Error::new(
-        io::ErrorKind::InvalidData,
-        "couldn't read directory name",
-    ))?;
+    /// Shows that a song is loading, in place of the rest of the UI.
+    fn loading_ui(&self, ctx: &egui::Context) {
+        let Some(loading) = &self.loading else {
+            return;
+        };
 
-    let tja_file_path = path
-        .as_ref()
-        .join(format!("{}.tja", dir_name.to_string_lossy()));
-    let tja_file_contents = std::fs::read_to_string(tja_file_path)?;
+        let title = self.songs[loading.song_id].display_title(settings().visual.romanised_titles);
+        egui::Area::new("loading".into())
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(RichText::new(format!("Loading {title}...")).size(30.0));
+                    });
+                });
+            });
+    }
 
-    let mut song = parse_tja_file(&tja_file_contents)?;
+    /// Finds the song the given replay was recorded on and reads its notes. Returns the song's
+    /// index along with the chart that was played.
+    fn replay_chart(&mut self, replay: &Replay) -> anyhow::Result<(usize, &Difficulty)> {
+        let song_id = self
+            .songs
+            .iter()
+            .position(|song| song.audio_filename == replay.song_key)
+            .ok_or_else(|| anyhow::anyhow!("{} isn't in the song list", replay.song_title))?;
 
-    let audio_filename = path
-        .as_ref()
-        .join(&song.audio_filename)
-        .to_string_lossy()
-        .into_owned();
+        self.read_notes(song_id)?;
+        let Some(chart) = self.songs[song_id]
+            .difficulties
+            .get(replay.difficulty)
+            .and_then(Option::as_ref)
+        else {
+            anyhow::bail!(
+                "{} doesn't have that difficulty any more",
+                replay.song_title
+            );
+        };
 
-    song.audio_filename = audio_filename;
-    Ok(song)
-}
+        Ok((song_id, chart))
+    }
 
-impl SongSelect {
-    pub fn new(textures: &mut TextureCache, renderer: &Renderer) -> anyhow::Result<Self> {
-        let test_tracks = read_song_list_dir(SONGS_DIR)?;
-        let bg_sprite = SpriteBuilder::new(textures.get(
-            &renderer.device,
-            &renderer.queue,
-            "song_select_bg.jpg",
-        )?)
-        .build(renderer);
+    /// Reads a replay and starts loading the song to play it back on, with the chart and
+    /// modifiers it was recorded with.
+    fn load_replay(&mut self, ctx: &Context, path: &Path) -> anyhow::Result<LoadingSong> {
+        let replay = Replay::load(path)?;
+        let (song_id, chart) = self.replay_chart(&replay)?;
+        replay.check_chart(&chart.chart.notes)?;
 
-        Ok(SongSelect {
-            songs: test_tracks,
-            bg_sprite: Rc::new(bg_sprite),
-            selected: None,
-            difficulty: 0,
-            song_preview_handle: None,
-            go_to_credits: false,
-            exit: false,
-            go_to_song: None,
+        let difficulty = replay.difficulty;
+        let modifiers = replay.conditions.modifiers();
+        Ok(LoadingSong {
+            replay: Some(replay),
+            ..self.load_song(ctx, song_id, difficulty, modifiers)
         })
     }
 
@@ -124,14 +1717,21 @@ impl SongSelect {
         audio: &mut AudioManager,
         selected: usize,
     ) -> anyhow::Result<StreamingSoundHandle<FromFileError>> {
+        let gain = self.preview_gain(selected);
         let selected = &self.songs[selected];
+        let demostart = selected.course_demostart(self.difficulty) as f64;
 
         let settings = StreamingSoundSettings::default()
-            .playback_region(selected.demostart as f64..)
+            .playback_region(demostart..)
             .fade_in_tween(Some(*IN_TWEEN))
-            .loop_region(selected.demostart as f64..);
+            .loop_region(demostart..)
+            .volume(song_volume(gain, selected.song_volume))
+            .output_destination(self.music_track);
 
-        let song = StreamingSoundData::from_file(&selected.audio_filename, settings)?;
+        let song = StreamingSoundData::from_file(
+            selected.course_audio_filename(self.difficulty),
+            settings,
+        )?;
 
         Ok(audio.play(song)?)
     }
@@ -139,37 +1739,71 @@ impl SongSelect {
 
 impl GameState for SongSelect {
     fn update(&mut self, ctx: &mut Context, _dt: f32) -> StateTransition {
+        self.receive_loudness_results();
+        self.ui_time = ctx.time.ui_time();
+
+        // The song list can't change under a song that's loading
+        if self.loading.is_some() {
+            return self.finish_loading(ctx);
+        }
+
+        self.apply_library_changes();
+
+        if let Some(scroll) = self.held_scroll.as_mut() {
+            let steps = scroll.steps(self.ui_time);
+            let fast = scroll.is_fast(self.ui_time);
+
+            if steps != 0 {
+                self.scroll_list(steps);
+            }
+
+            if let (true, Some(id)) = (fast, self.selected) {
+                let group = self.sort_mode.group(&self.songs[id]);
+                self.group_overlay.show(group, ctx.renderer);
+            }
+        }
+
+        self.group_overlay.update(ctx.renderer, self.ui_time);
+
         if self.go_to_credits {
             if let Some(handle) = self.song_preview_handle.as_mut() {
-                handle.stop(*OUT_TWEEN).unwrap();
+                handle.stop(*OUT_TWEEN).or_log("couldn't stop song preview");
             }
 
             self.go_to_credits = false;
             StateTransition::Push(Box::new(CreditsScreen::new()))
         } else if let Some((song_id, difficulty)) = self.go_to_song {
-            let sound_data = StaticSoundData::from_file(
-                &self.songs[song_id].audio_filename,
-                StaticSoundSettings::default(),
-            )
-            .unwrap();
+            // Anything wrong with the notes only turns up now
+            if let Err(e) = self.read_notes(song_id) {
+                log::error!("couldn't read song: {e}");
+                self.go_to_song = None;
+                self.show_toast("This song's chart couldn't be read".to_string());
+                return StateTransition::Continue;
+            }
 
             self.go_to_song = None;
 
-            if let Some(handle) = self.song_preview_handle.as_mut() {
-                handle.stop(Default::default()).unwrap();
-            }
+            // Every play gets its own random notes
+            let modifiers = self.modifiers.reseeded();
+            self.loading = Some(LoadingSong {
+                versus: self.battle && !self.practise_song,
+                practice: self.practise_song,
+                ..self.load_song(ctx, song_id, difficulty, modifiers)
+            });
+            StateTransition::Continue
+        } else if let Some(path) = self.go_to_replay.take() {
+            match self.load_replay(ctx, &path) {
+                Ok(loading) => {
+                    self.loading = Some(loading);
+                    StateTransition::Continue
+                }
 
-            StateTransition::Push(Box::new(
-                TaikoMode::new(
-                    &self.songs[song_id],
-                    sound_data,
-                    ctx.audio,
-                    difficulty,
-                    ctx.renderer,
-                    ctx.textures,
-                )
-                .expect("error creating taiko mode scene"),
-            ))
+                Err(e) => {
+                    log::error!("couldn't play replay: {e}");
+                    self.show_toast(format!("Couldn't play the replay: {e}"));
+                    StateTransition::Continue
+                }
+            }
         } else if self.exit {
             StateTransition::Pop
         } else {
@@ -177,10 +1811,19 @@ impl GameState for SongSelect {
         }
     }
     fn render<'pass>(&'pass mut self, ctx: &mut RenderContext<'_, 'pass>) {
-        ctx.render(self.bg_sprite.as_ref())
+        ctx.render(self.bg_sprite.as_ref());
+
+        if self.group_overlay.alpha > 0.0 {
+            ctx.render(&self.group_overlay.text);
+        }
     }
 
     fn debug_ui(&mut self, ctx: egui::Context, audio: &mut AudioManager) {
+        if self.loading.is_some() {
+            self.loading_ui(&ctx);
+            return;
+        }
+
         egui::SidePanel::left("main menu")
             .resizable(false)
             .show(&ctx, |ui| {
@@ -194,41 +1837,85 @@ impl GameState for SongSelect {
 
                 ui.add_space(50.0);
 
-                let old_song = self.selected;
+                let order = self.song_order();
 
-                egui::ComboBox::from_label("Song select")
-                    .selected_text(
-                        RichText::new(
-                            self.selected
-                                .map(|id| self.songs[id].title.as_str())
-                                .unwrap_or("None"),
+                if self.sort_mode == SortMode::Genre {
+                    self.genre_sections(ui, &order);
+                } else {
+                    egui::ComboBox::from_label("Song select")
+                        .selected_text(
+                            RichText::new(
+                                self.selected
+                                    .map(|id| self.list_title(ui, id, 20.0))
+                                    .unwrap_or_else(|| "None".to_string()),
+                            )
+                            .size(20.0),
                         )
-                        .size(20.0),
-                    )
-                    .show_ui(ui, |ui| {
-                        ui.selectable_value(
-                            &mut self.selected,
-                            None,
-                            RichText::new("none").size(15.0),
-                        );
-
-                        for (id, song) in self.songs.iter().enumerate() {
+                        .show_ui(ui, |ui| {
                             ui.selectable_value(
                                 &mut self.selected,
-                                Some(id),
-                                RichText::new(&song.title).size(15.0),
+                                None,
+                                RichText::new("none").size(15.0),
                             );
+
+                            for id in order {
+                                let title = self.list_title(ui, id, 15.0);
+                                ui.selectable_value(
+                                    &mut self.selected,
+                                    Some(id),
+                                    RichText::new(title).size(15.0),
+                                );
+                            }
+                        });
+                }
+
+                egui::ComboBox::from_label("Sort by")
+                    .selected_text(self.sort_mode.name())
+                    .show_ui(ui, |ui| {
+                        for mode in SortMode::ALL {
+                            ui.selectable_value(&mut self.sort_mode, mode, mode.name());
                         }
                     });
 
-                if self.selected != old_song {
+                ui.checkbox(&mut self.show_hidden, "Show hidden songs");
+
+                if ui
+                    .button("Rescan songs")
+                    .on_hover_text("Read every song again, even ones that haven't changed")
+                    .clicked()
+                {
+                    self.rescan().or_log("couldn't rescan songs");
+                }
+
+                if ui.button("Replays").clicked() {
+                    self.replay_files = list_replays(paths().replays_dir());
+                    self.replays_open = true;
+                }
+
+                if self.selected != self.previewed {
+                    self.previewed = self.selected;
+
+                    // The notes are needed to estimate levels the chart doesn't give
+                    if let Some(id) = self.selected {
+                        self.read_notes(id).or_log("couldn't read song");
+                    }
+
                     if let Some(handle) = self.song_preview_handle.as_mut() {
-                        handle.stop(*OUT_TWEEN).unwrap();
+                        handle.stop(*OUT_TWEEN).or_log("couldn't stop song preview");
                     }
 
-                    self.song_preview_handle = self
-                        .selected
-                        .map(|id| self.play_preview(audio, id).unwrap());
+                    self.song_preview_handle =
+                        self.selected
+                            .and_then(|id| match self.play_preview(audio, id) {
+                                Ok(handle) => Some(handle),
+                                Err(e) => {
+                                    log::error!("couldn't play song preview: {e}");
+                                    self.show_toast(
+                                        "This song's preview couldn't be played".to_string(),
+                                    );
+                                    None
+                                }
+                            });
                 }
 
                 ui.with_layout(egui::Layout::bottom_up(egui::Align::Min), |ui| {
@@ -247,8 +1934,18 @@ impl GameState for SongSelect {
             });
 
         if let Some(song_index) = self.selected {
+            let song = &self.songs[song_index];
+            let old_preview = (
+                song.course_audio_filename(self.difficulty).to_string(),
+                song.course_demostart(self.difficulty),
+            );
+
+            let mut edit = None;
+
             egui::Window::new("difficulty select").show(&ctx, |ui| {
-                const DIFFICULTY_NAMES: [&str; 5] = ["Easy", "Normal", "Hard", "Oni", "Ura"];
+                if let Some(subtitle) = &self.songs[song_index].subtitle {
+                    ui.label(RichText::new(subtitle).size(17.0));
+                }
 
                 egui::TopBottomPanel::top("difficulty select panel").show_inside(ui, |ui| {
                     for (i, difficulty) in self.songs[song_index]
@@ -259,23 +1956,578 @@ impl GameState for SongSelect {
                     {
                         egui::SidePanel::left(format!("{} difficulty block", DIFFICULTY_NAMES[i]))
                             .show_inside(ui, |ui| {
+                                let level = match difficulty
+                                    .level(settings().game.prefer_estimated_levels)
+                                {
+                                    Some((level, false)) => level.to_string(),
+                                    Some((level, true)) => format!("~{level}"),
+                                    None => "?".to_string(),
+                                };
+
                                 ui.selectable_value(
                                     &mut self.difficulty,
                                     i,
-                                    RichText::new(format!(
-                                        "{}\n{}★",
-                                        DIFFICULTY_NAMES[i], difficulty.star_level
-                                    ))
-                                    .size(20.0),
+                                    RichText::new(format!("{}\n{level}★", DIFFICULTY_NAMES[i]))
+                                        .size(20.0),
                                 );
                             });
                     }
                 });
 
-                if ui.button(RichText::new("Play!").size(17.0)).clicked() {
-                    self.go_to_song = Some((song_index, self.difficulty));
+                let song = &self.songs[song_index];
+                let charter = song.difficulties[self.difficulty]
+                    .as_ref()
+                    .and_then(|difficulty| difficulty.notes_designer.as_ref())
+                    .or(song.maker.as_ref());
+
+                if let Some(charter) = charter {
+                    ui.label(format!("Charted by {charter}"));
+                }
+                if self.audio_missing[song_index] {
+                    ui.label("⚠ This song's audio is missing");
+                }
+                for warning in song.warnings.iter().filter(|w| is_chart_warning(&w.kind)) {
+                    ui.label(format!("⚠ {}", parse_warning_message(&warning.kind)));
+                }
+
+                // The stats come from the notes, which are only there once they've been read
+                let difficulty = song.difficulties[self.difficulty].as_ref();
+                if let Some(stats) = difficulty
+                    .filter(|_| self.notes_read[song_index])
+                    .map(Difficulty::stats)
+                {
+                    let (summary, details) = describe_stats(&stats);
+                    ui.label(summary);
+                    ui.label(RichText::new(details).weak());
+                }
+                ui.label(
+                    RichText::new(self.song_files[song_index].relative_path.to_string_lossy())
+                        .weak(),
+                );
+
+                ui.horizontal(|ui| {
+                    ui.label("Speed:");
+                    for speed in PLAYBACK_SPEEDS {
+                        ui.selectable_value(&mut self.modifiers.speed, speed, format!("{speed}x"));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Notes:");
+                    for visibility in NoteVisibility::ALL {
+                        ui.selectable_value(
+                            &mut self.modifiers.note_visibility,
+                            visibility,
+                            visibility.name(),
+                        );
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Shuffle:");
+                    for shuffle in NoteShuffle::ALL {
+                        ui.selectable_value(
+                            &mut self.modifiers.note_shuffle,
+                            shuffle,
+                            shuffle.name(),
+                        );
+                    }
+                });
+                ui.checkbox(&mut self.battle, "2P battle").on_hover_text(
+                    "Two players play the song together on their own drum keys. Battles aren't \
+                    saved.",
+                );
+                if self.modifiers.speed != 1.0 || self.modifiers.note_shuffle != NoteShuffle::Off {
+                    ui.label(
+                        RichText::new(
+                            "Plays with this speed or shuffle don't count towards high scores",
+                        )
+                        .weak(),
+                    );
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button(RichText::new("Play!").size(17.0)).clicked() {
+                        self.go_to_song = Some((song_index, self.difficulty));
+                        self.practise_song = false;
+                    }
+
+                    if ui
+                        .button("Practice")
+                        .on_hover_text(
+                            "Seek through the song and loop parts of it, without scoring",
+                        )
+                        .clicked()
+                    {
+                        self.go_to_song = Some((song_index, self.difficulty));
+                        self.practise_song = true;
+                    }
+                });
+
+                if ui.button("Leaderboard").clicked() {
+                    self.leaderboard_open = true;
+                }
+
+                // Only tja files can be edited
+                let editable = is_tja_file(&self.song_files[song_index].path);
+                if ui
+                    .add_enabled(editable, egui::Button::new("Edit metadata"))
+                    .on_disabled_hover_text("Songs made from osu beatmaps can't be edited")
+                    .clicked()
+                {
+                    self.metadata_editor =
+                        Some(MetadataEditor::new(song_index, &self.songs[song_index]));
+                }
+
+                if !editable
+                    && ui
+                        .button("Convert to tja")
+                        .on_hover_text("Write the beatmaps out as a tja file, which can be edited")
+                        .clicked()
+                {
+                    self.convert_song(song_index);
                 }
+
+                let data = local_data()
+                    .song(&self.songs[song_index].audio_filename)
+                    .cloned()
+                    .unwrap_or_default();
+
+                ui.horizontal(|ui| {
+                    let mut favourite = data.favourite;
+                    if ui.checkbox(&mut favourite, "Favourite").changed() {
+                        edit = Some(SongDataEdit::Favourite(favourite));
+                    }
+
+                    let mut hidden = data.hidden;
+                    if ui.checkbox(&mut hidden, "Hidden").changed() {
+                        edit = Some(SongDataEdit::Hidden(hidden));
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(format!("Offset: {:+}ms", data.offset));
+
+                    for nudge in [-OFFSET_NUDGE, OFFSET_NUDGE] {
+                        if ui.button(format!("{nudge:+}ms")).clicked() {
+                            edit = Some(SongDataEdit::Offset(data.offset + nudge));
+                        }
+                    }
+                });
             });
+
+            if let Some(edit) = edit {
+                self.edit_song_data(song_index, edit);
+            }
+
+            // Some courses have their own audio, so the preview might need to change
+            let song = &self.songs[song_index];
+            let new_preview = (
+                song.course_audio_filename(self.difficulty),
+                song.course_demostart(self.difficulty),
+            );
+
+            if new_preview != (old_preview.0.as_str(), old_preview.1) {
+                if let Some(handle) = self.song_preview_handle.as_mut() {
+                    handle.stop(*OUT_TWEEN).or_log("couldn't stop song preview");
+                }
+
+                self.song_preview_handle = self
+                    .play_preview(audio, song_index)
+                    .or_log("couldn't play song preview");
+            }
+        }
+
+        self.metadata_editor_ui(&ctx);
+        self.leaderboard_ui(&ctx);
+        self.replays_ui(&ctx);
+        self.toast_ui(&ctx);
+    }
+
+    fn handle_event(&mut self, ctx: &mut Context, event: &WindowEvent) {
+        // The metadata editor has text boxes, which have their own undo. And nothing can be chosen
+        // while a song is loading.
+        if self.metadata_editor.is_some() || self.loading.is_some() {
+            return;
+        }
+
+        if let WindowEvent::KeyboardInput { event, .. } = event {
+            let now = ctx.time.ui_time();
+            let pressed = event.state == ElementState::Pressed;
+
+            let direction = match event.physical_key {
+                PhysicalKey::Code(KeyCode::ArrowUp) => Some(-1),
+                PhysicalKey::Code(KeyCode::ArrowDown) => Some(1),
+                _ => None,
+            };
+
+            if let Some(direction) = direction {
+                if pressed && !event.repeat {
+                    self.held_scroll = Some(HeldScroll::new(direction, now));
+                } else if !pressed
+                    && self
+                        .held_scroll
+                        .is_some_and(|scroll| scroll.direction() == direction)
+                {
+                    self.held_scroll = None;
+                    self.group_overlay.hide(now);
+                }
+            }
+
+            if pressed {
+                match event.physical_key {
+                    PhysicalKey::Code(KeyCode::PageUp) => self.jump_group(false, ctx.renderer, now),
+                    PhysicalKey::Code(KeyCode::PageDown) => {
+                        self.jump_group(true, ctx.renderer, now)
+                    }
+                    _ => {}
+                }
+            }
+
+            let ctrl = [KeyCode::ControlLeft, KeyCode::ControlRight]
+                .into_iter()
+                .any(|key| ctx.keyboard.is_pressed(PhysicalKey::Code(key)));
+
+            if event.state == ElementState::Pressed
+                && !event.repeat
+                && ctrl
+                && event.physical_key == PhysicalKey::Code(KeyCode::KeyZ)
+            {
+                self.undo_song_data_edit(ctx.time.ui_time());
+            }
+        }
+    }
+
+    fn ducks_music(&self) -> bool {
+        self.metadata_editor.is_some() || self.leaderboard_open || self.replays_open
+    }
+
+    fn control_hints(&self) -> Vec<(Action, &str)> {
+        // The editor takes all the keyboard input while it's open
+        if self.metadata_editor.is_some() {
+            return Vec::new();
+        }
+
+        let mut hints = vec![
+            (Action::Scroll, "Choose song"),
+            (Action::JumpGroup, "Jump to group"),
+        ];
+
+        if self.undo.can_undo() {
+            hints.push((Action::Undo, "Undo edit"));
+        }
+
+        hints
+    }
+
+    fn recreate_gpu_resources(
+        &mut self,
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+    ) -> anyhow::Result<()> {
+        self.bg_sprite = Rc::new(Self::background(textures, renderer)?);
+        self.group_overlay.recreate(renderer);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_parallel_results_in_order() {
+        // The first items take the longest, so they finish last
+        let items = (0..16).collect::<Vec<u64>>();
+        let results = map_in_parallel(&items, |&i| {
+            std::thread::sleep(Duration::from_millis(2 * (16 - i)));
+            i * 10
+        });
+        assert_eq!(results, items.iter().map(|i| i * 10).collect::<Vec<_>>());
+
+        assert_eq!(map_in_parallel(&[] as &[u64], |&i| i), []);
+    }
+
+    #[test]
+    fn test_describe_stats() {
+        let stats = ChartStats {
+            hit_notes: 678,
+            dons: 400,
+            kats: 278,
+            roll_time: 6.25,
+            balloons: 3,
+            duration: 124.3,
+            peak_density: 12.0,
+        };
+
+        assert_eq!(
+            describe_stats(&stats),
+            (
+                "678 notes, 2:04, peak 12 hits/sec".to_string(),
+                "400 don, 278 kat, 6.2s of drumrolls, 3 balloons".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_song_list_sorted_by_directory() {
+        let dir = temp_dir("song_list");
+
+        for name in ["b", "c", "a", "broken"] {
+            let song_dir = dir.join(name);
+            std::fs::create_dir_all(&song_dir).unwrap();
+            let tja = if name == "broken" {
+                "TITLE:broken\nWAVE:song.ogg\n\n#START\n1,\n".to_string()
+            } else {
+                format!("TITLE:{name}\nWAVE:song.ogg\n\n#START\n1,\n#END\n")
+            };
+            std::fs::write(song_dir.join(format!("{name}.tja")), tja).unwrap();
+        }
+        // Not a song directory
+        std::fs::write(dir.join("readme.txt"), "").unwrap();
+
+        // The song that can't be read is left out
+        let songs = read_song_list_dir(&dir, &mut SongCache::default()).unwrap();
+        let titles = songs
+            .iter()
+            .map(|(_, song)| song.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["a", "b", "c"]);
+        assert_eq!(songs[0].0.path, dir.join("a/a.tja"));
+
+        let count = count_songs(&dir).unwrap();
+        assert_eq!(count.parsed, 3);
+        assert_eq!(count.failed.len(), 1);
+        assert_eq!(count.failed[0].0, dir.join("broken/broken.tja"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_song_list_uses_cache() {
+        let dir = temp_dir("cached_songs");
+
+        for name in ["a", "b"] {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+            let tja = format!("TITLE:{name}\nWAVE:song.ogg\n\n#START\n1,\n#END\n");
+            std::fs::write(dir.join(format!("{name}/{name}.tja")), tja).unwrap();
+        }
+
+        let mut cache = SongCache::default();
+        read_song_list_dir(&dir, &mut cache).unwrap();
+
+        // A song that hasn't changed is taken from the cache rather than read again
+        let a_path = dir.join("a/a.tja");
+        let stamp = FileStamp::of(&a_path).unwrap();
+        let mut cached = cache.get(&a_path, stamp).unwrap().clone();
+        cached.title = "from the cache".to_string();
+        cache.insert(a_path.clone(), stamp, cached);
+
+        // Songs that are gone are dropped from the cache
+        std::fs::remove_dir_all(dir.join("b")).unwrap();
+        let b_path = dir.join("b/b.tja");
+        let b_stamp = FileStamp::of(&a_path).unwrap();
+        cache.insert(b_path.clone(), b_stamp, Song::default());
+
+        let songs = read_song_list_dir(&dir, &mut cache).unwrap();
+        assert_eq!(songs.len(), 1);
+        assert_eq!(songs[0].1.title, "from the cache");
+        assert!(cache.get(&b_path, b_stamp).is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_library_changes() {
+        let dir = temp_dir("library_changes");
+
+        let tja = "TITLE:Song\nWAVE:song.ogg\n\n#START\n1,\n#END\n";
+        for path in ["Pop/a/a.tja", "Pop/b/b.tja", "c/c.tja"] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, tja).unwrap();
+        }
+        let known = find_song_files(&dir).unwrap();
+
+        // A new folder, a new file in an old folder, an edit and a folder that's gone
+        for path in ["New/d/d.tja", "New/d/e.tja", "c/other.tja"] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, tja).unwrap();
+        }
+        std::fs::remove_dir_all(dir.join("Pop")).unwrap();
+
+        let changed = [
+            dir.join("New"),
+            dir.join("New/d/d.tja"),
+            dir.join("c/c.tja"),
+            dir.join("c/other.tja"),
+            dir.join("c/song.ogg"),
+            dir.join("Pop"),
+            dir.join("Pop/a/a.tja"),
+        ];
+        assert_eq!(
+            library_changes(&changed, &dir, &known),
+            [
+                LibraryChange::Added(SongFile::new(&dir, dir.join("New/d/d.tja"))),
+                LibraryChange::Added(SongFile::new(&dir, dir.join("New/d/e.tja"))),
+                LibraryChange::Modified(2),
+                LibraryChange::Added(SongFile::new(&dir, dir.join("c/other.tja"))),
+                LibraryChange::Removed(0),
+                LibraryChange::Removed(1),
+            ]
+        );
+        assert_eq!(
+            SongFile::new(&dir, dir.join("New/d/e.tja")).relative_path,
+            Path::new("New/d/e.tja")
+        );
+
+        let mut items = vec!["a", "b", "c", "d"];
+        remove_indices(&mut items, &HashSet::from([0, 2]));
+        assert_eq!(items, ["b", "d"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_nested_song_folders() {
+        let dir = temp_dir("nested_songs");
+
+        let tja = "TITLE:Song\nWAVE:song.ogg\n\n#START\n1,\n#END\n";
+        for path in [
+            "Pop/Artist/Song/Song.tja",
+            "Pop/Artist/Other name/song (oni).TJA",
+            "Pop/Artist/Other name/song (easy).tja",
+            "Game/Top level.tja",
+        ] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, tja).unwrap();
         }
+        std::fs::write(dir.join("Pop/Artist/Song/song.ogg"), "").unwrap();
+
+        // A link back up the tree is only looked through once
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&dir, dir.join("Pop/loop")).unwrap();
+
+        let relative_paths = find_song_files(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|file| file.relative_path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            relative_paths,
+            [
+                "Game/Top level.tja",
+                "Pop/Artist/Other name/song (easy).tja",
+                "Pop/Artist/Other name/song (oni).TJA",
+                "Pop/Artist/Song/Song.tja",
+            ]
+            .map(PathBuf::from)
+        );
+
+        // The song's files are found next to its tja file
+        let songs = read_song_list_dir(&dir, &mut SongCache::default()).unwrap();
+        assert_eq!(songs.len(), 4);
+        assert_eq!(
+            Path::new(&songs[3].1.audio_filename),
+            dir.join("Pop/Artist/Song/song.ogg")
+        );
+
+        // Songs without their audio are found while reading them
+        assert!(!audio_missing(&songs[3].1));
+        assert!(audio_missing(&songs[0].1));
+        let audio = dir.join("Game/song.ogg");
+        assert_eq!(
+            song_warnings(&songs[0].1),
+            [format!(
+                "the audio file \"{}\" doesn't exist",
+                audio.display()
+            )]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_osu_songs() {
+        let dir = temp_dir("osu_songs");
+
+        let fixture =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("src/notechart_parser/Osu fixture");
+        let set_dir = dir.join("Osu/Fixture");
+        std::fs::create_dir_all(&set_dir).unwrap();
+        for beatmap in osu_files(&fixture).unwrap() {
+            std::fs::copy(&beatmap, set_dir.join(beatmap.file_name().unwrap())).unwrap();
+        }
+        std::fs::write(set_dir.join("audio.mp3"), "").unwrap();
+
+        // A folder with a tja file in it isn't a set of beatmaps, even if it has some
+        let tja = "TITLE:Song\nWAVE:song.ogg\n\n#START\n1,\n#END\n";
+        std::fs::create_dir_all(dir.join("Mixed")).unwrap();
+        std::fs::write(dir.join("Mixed/Mixed.tja"), tja).unwrap();
+        std::fs::copy(
+            set_dir.join("Fixture (Mapper) [Oni].osu"),
+            dir.join("Mixed/beatmap.osu"),
+        )
+        .unwrap();
+
+        let known = find_song_files(&dir).unwrap();
+        let relative_paths: Vec<_> = known.iter().map(|file| &file.relative_path).collect();
+        assert_eq!(
+            relative_paths,
+            [Path::new("Mixed/Mixed.tja"), Path::new("Osu/Fixture")]
+        );
+
+        // The beatmaps make up one song, and the osu!standard one is left out
+        let songs = read_song_list_dir(&dir, &mut SongCache::default()).unwrap();
+        let song = &songs[1].1;
+        assert_eq!(song.title, "フィクスチャ");
+        assert_eq!(Path::new(&song.audio_filename), set_dir.join("audio.mp3"));
+        let courses: Vec<bool> = song.difficulties.iter().map(Option::is_some).collect();
+        assert_eq!(courses, [true, true, false, true, false]);
+        assert_eq!(read_song_dir(&fixture).unwrap().title, "フィクスチャ");
+
+        // Adding a beatmap changes the set's stamp, and the song it's part of
+        let stamp = song_stamp(&set_dir).unwrap();
+        let new_beatmap = set_dir.join("Fixture (Mapper) [Inner Oni].osu");
+        std::fs::copy(set_dir.join("Fixture (Mapper) [Oni].osu"), &new_beatmap).unwrap();
+        assert_ne!(song_stamp(&set_dir).unwrap(), stamp);
+
+        let new_set = dir.join("Osu/New set/beatmap.osu");
+        std::fs::create_dir_all(new_set.parent().unwrap()).unwrap();
+        std::fs::copy(&new_beatmap, &new_set).unwrap();
+        assert_eq!(
+            library_changes(&[new_beatmap, new_set.clone()], &dir, &known),
+            [
+                LibraryChange::Modified(1),
+                LibraryChange::Added(SongFile::new(&dir, dir.join("Osu/New set"))),
+            ]
+        );
+
+        // Converting the set to tja keeps the same song, which is read from the tja file after
+        let tja_path = convert_to_tja(&set_dir).unwrap();
+        assert_eq!(tja_path, set_dir.join("Fixture.tja"));
+        let converted = read_library_song(&tja_path, true).unwrap();
+        assert_eq!(converted.audio_filename, song.audio_filename);
+        let note_times = |song: &Song| {
+            let chart = &song.difficulties[3].as_ref().unwrap().chart;
+            chart.notes.iter().map(|note| note.time).collect::<Vec<_>>()
+        };
+        let (times, converted_times) = (
+            note_times(&read_osu_set(&set_dir).unwrap()),
+            note_times(&converted),
+        );
+        assert_eq!(times.len(), converted_times.len());
+        assert!(times
+            .iter()
+            .zip(&converted_times)
+            .all(|(a, b)| (a - b).abs() < 1e-3));
+        assert!(convert_to_tja(&set_dir).is_err());
+        assert!(!is_osu_set(&[tja_path]));
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
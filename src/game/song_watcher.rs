@@ -0,0 +1,74 @@
+//! Watching the songs directory for songs being added, removed or edited while the game is running.
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Watches a directory (and everything in it) for changes.
+pub struct SongWatcher {
+    // The watcher stops when it's dropped, so it has to be kept around
+    _watcher: RecommendedWatcher,
+    receiver: Receiver<notify::Result<Event>>,
+    /// The directory as it was given, which changed paths are made relative to.
+    dir: PathBuf,
+    /// The full path of the directory, which is how the watcher reports changed paths.
+    full_dir: PathBuf,
+}
+
+impl SongWatcher {
+    pub fn new<P: AsRef<Path>>(dir: P) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let full_dir = dir.canonicalize()?;
+
+        // The watcher sends its events from a thread of its own
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // The receiver only goes away once the watcher is dropped
+            let _ = sender.send(event);
+        })?;
+        watcher.watch(&full_dir, RecursiveMode::Recursive)?;
+
+        Ok(Self {
+            _watcher: watcher,
+            receiver,
+            dir,
+            full_dir,
+        })
+    }
+
+    /// Returns every path that's been created, removed or modified since the last time this was
+    /// called, each one once. The paths start with the directory the way it was given to
+    /// [SongWatcher::new].
+    pub fn changed_paths(&self) -> Vec<PathBuf> {
+        let mut seen = HashSet::new();
+        let mut paths = Vec::new();
+
+        for event in self.receiver.try_iter() {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    log::error!("error watching the songs directory: {e}");
+                    continue;
+                }
+            };
+
+            if matches!(event.kind, EventKind::Access(_)) {
+                continue;
+            }
+
+            for path in event.paths {
+                let path = match path.strip_prefix(&self.full_dir) {
+                    Ok(relative) => self.dir.join(relative),
+                    Err(_) => path,
+                };
+
+                if seen.insert(path.clone()) {
+                    paths.push(path);
+                }
+            }
+        }
+
+        paths
+    }
+}
@@ -0,0 +1,147 @@
+//! Playing a chart automatically, for testing charts and latency without having to play them.
+//!
+//! The autoplayer doesn't judge anything itself. It works out the drum hits a perfect player would
+//! make, and the scene feeds them to the judge along with the real ones, so an autoplayed song
+//! goes through exactly the same judging, scoring and effects as a played one.
+use crate::notechart_parser::{Note, NoteType};
+use crate::settings::DrumInput;
+
+use super::note::BAD;
+use super::scheduled_hits::HitCursor;
+
+/// How many times a second the autoplayer hits drumrolls and balloons.
+pub const AUTOPLAY_ROLL_RATE: f32 = 15.0;
+
+/// Works out the hits for playing the given notes perfectly, in order of when they're made.
+///
+/// Every don and kat is hit right on time, with both hands if it's big. Drumrolls and balloons are
+/// hit at [AUTOPLAY_ROLL_RATE] (balloons faster, if they couldn't be popped in time otherwise), and
+/// stop early enough not to take the next note's hit.
+pub fn autoplay_hits(notes: &[Note], timing_windows: &[f32; 3]) -> Vec<(DrumInput, f32)> {
+    let mut hits = Vec::new();
+    let mut right_hand = false;
+
+    let mut hit = |hits: &mut Vec<(DrumInput, f32)>, don: bool, time: f32| {
+        let input = match (don, right_hand) {
+            (true, false) => DrumInput::LeftDon,
+            (true, true) => DrumInput::RightDon,
+            (false, false) => DrumInput::LeftKat,
+            (false, true) => DrumInput::RightKat,
+        };
+        hits.push((input, time));
+        right_hand = !right_hand;
+    };
+
+    for (i, note) in notes.iter().enumerate() {
+        // Hits too close to the next note would be judged against it instead
+        let next_note = notes.get(i + 1).map_or(f32::INFINITY, |next| next.time);
+        let end = |duration: f32| (note.time + duration).min(next_note - timing_windows[BAD]);
+        let interval = AUTOPLAY_ROLL_RATE.recip();
+
+        match note.note_type {
+            NoteType::Don | NoteType::Kat => hit(&mut hits, note.note_type.is_don(), note.time),
+
+            NoteType::BigDon | NoteType::CoopDon | NoteType::BigKat | NoteType::CoopKat => {
+                hit(&mut hits, note.note_type.is_don(), note.time);
+                hit(&mut hits, note.note_type.is_don(), note.time);
+            }
+
+            NoteType::Roll(duration) | NoteType::BigRoll(duration) => {
+                let end = end(duration);
+                let times = (0..).map(|hit_number| note.time + hit_number as f32 * interval);
+                for time in times.take_while(|time| *time < end) {
+                    hit(&mut hits, true, time);
+                }
+            }
+
+            NoteType::BalloonRoll(duration, hit_target)
+            | NoteType::Kusudama(duration, hit_target) => {
+                let length = end(duration) - note.time;
+                if length <= 0.0 || hit_target == 0 {
+                    continue;
+                }
+
+                let interval = interval.min(length / hit_target as f32);
+                for hit_number in 0..hit_target {
+                    hit(&mut hits, true, note.time + hit_number as f32 * interval);
+                }
+            }
+        }
+    }
+
+    hits.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    hits
+}
+
+/// Hands the autoplayed hits over to the scene as the song goes on.
+#[derive(Debug, Clone)]
+pub struct Autoplayer {
+    hits: Vec<(DrumInput, f32)>,
+    cursor: HitCursor,
+}
+
+impl Autoplayer {
+    /// Starts autoplaying the given notes from the given note time. Anything before then is left
+    /// to the player.
+    pub fn new(notes: &[Note], timing_windows: &[f32; 3], from: f32) -> Self {
+        let hits = autoplay_hits(notes, timing_windows);
+        let cursor = HitCursor::starting_from(&hits, from);
+        Self { hits, cursor }
+    }
+
+    /// The hits that are due by the given note time, which haven't been handed over yet.
+    pub fn hits_until(&mut self, time: f32) -> &[(DrumInput, f32)] {
+        self.cursor.hits_until(&self.hits, time)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::taiko_mode::note::HARD_EXTREME_TIMING;
+
+    fn note(note_type: NoteType, time: f32) -> Note {
+        Note {
+            note_type,
+            time,
+            scroll_speed: 1.0,
+            gogo: false,
+        }
+    }
+
+    #[test]
+    fn test_hits() {
+        use DrumInput::*;
+
+        let notes = [
+            note(NoteType::Don, 0.0),
+            note(NoteType::BigKat, 1.0),
+            note(NoteType::Roll(0.25), 2.0),
+            note(NoteType::BalloonRoll(0.1, 4), 3.0),
+        ];
+        let hits = autoplay_hits(&notes, &HARD_EXTREME_TIMING);
+
+        assert_eq!(hits[0], (LeftDon, 0.0));
+        // Big notes are hit with both hands at once
+        assert_eq!(hits[1], (RightKat, 1.0));
+        assert_eq!(hits[2], (LeftKat, 1.0));
+        // The roll is hit four times in its quarter of a second
+        assert!(hits[3..7]
+            .iter()
+            .all(|(input, time)| input.is_don() && *time < 2.25));
+        // The balloon is too short to pop at the usual rate, so it's hit faster
+        let balloon: Vec<_> = hits[7..].iter().map(|(_, time)| *time).collect();
+        assert_eq!(balloon.len(), 4);
+        assert!(*balloon.last().unwrap() < 3.1);
+    }
+
+    #[test]
+    fn test_roll_stops_before_next_note() {
+        let notes = [note(NoteType::Roll(1.0), 0.0), note(NoteType::Kat, 1.0)];
+        let hits = autoplay_hits(&notes, &HARD_EXTREME_TIMING);
+
+        let (last_roll_hit, kat) = (hits[hits.len() - 2], hits[hits.len() - 1]);
+        assert!(last_roll_hit.1 < 1.0 - HARD_EXTREME_TIMING[BAD]);
+        assert_eq!(kat.1, 1.0);
+    }
+}
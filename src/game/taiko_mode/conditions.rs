@@ -0,0 +1,413 @@
+//! Everything about how a song was played that affects the score, so scores can be compared fairly.
+//!
+//! Each play records its [PlayConditions], along with a hash of them. Scores should only be
+//! compared directly if their hashes match.
+//!
+//! The hash has to stay the same for as long as the record exists, so it's worked out from an
+//! explicit encoding rather than from however the struct happens to look at the time. Every set
+//! of conditions records the version of the encoding it was made with. Adding a field means
+//! adding a new version, and conditions made with older versions keep hashing the old way.
+use serde::{Deserialize, Serialize};
+
+use super::modifiers::{NoteShuffle, NoteVisibility, PlayModifiers};
+use super::note::{EASY_NORMAL_TIMING, HARD_EXTREME_TIMING};
+
+/// The newest version of the conditions encoding. See the module docs.
+pub const CONDITIONS_VERSION: u32 = 4;
+
+/// The set of timing windows notes are judged with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JudgementPreset {
+    EasyNormal,
+    HardExtreme,
+}
+
+impl JudgementPreset {
+    /// The preset songs of the given difficulty are normally judged with.
+    pub fn for_difficulty(difficulty: usize) -> Self {
+        match difficulty {
+            0 | 1 => JudgementPreset::EasyNormal,
+            _ => JudgementPreset::HardExtreme,
+        }
+    }
+
+    pub fn timing_windows(&self) -> &'static [f32; 3] {
+        match self {
+            JudgementPreset::EasyNormal => &EASY_NORMAL_TIMING,
+            JudgementPreset::HardExtreme => &HARD_EXTREME_TIMING,
+        }
+    }
+
+    /// The name used in the hash encoding. These must never change.
+    fn key(&self) -> &'static str {
+        match self {
+            JudgementPreset::EasyNormal => "easy_normal",
+            JudgementPreset::HardExtreme => "hard_extreme",
+        }
+    }
+}
+
+/// The conditions a song was played under.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlayConditions {
+    /// The version of the encoding these conditions are hashed with.
+    pub version: u32,
+    /// The note offset that was applied (global and per-song together), in milliseconds.
+    pub note_offset: f32,
+    pub judgement_preset: JudgementPreset,
+    /// The standard preset for the difficulty that was played.
+    pub standard_preset: JudgementPreset,
+    /// Whether the game played the song by itself.
+    pub autoplay: bool,
+    /// How fast the song was played, as a multiple of its normal speed. Conditions from before
+    /// version 2 were always played at normal speed.
+    #[serde(default = "normal_speed")]
+    pub playback_speed: f32,
+    /// Which notes could be seen. Conditions from before version 3 could always see all of them.
+    #[serde(default)]
+    pub note_visibility: NoteVisibility,
+    /// How the dons and kats were swapped around. Conditions from before version 4 never swapped
+    /// any.
+    #[serde(default)]
+    pub note_shuffle: NoteShuffle,
+    /// The seed the notes were shuffled with, if the shuffle was random. It isn't part of the
+    /// hash, since plays with the same shuffle were under the same conditions whichever notes it
+    /// happened to swap.
+    #[serde(default)]
+    pub shuffle_seed: Option<u64>,
+    /// The version of the game the song was played on.
+    pub game_version: String,
+}
+
+impl PlayConditions {
+    /// The conditions for playing a song of the given difficulty right now.
+    pub fn new(difficulty: usize, note_offset: f32) -> Self {
+        let preset = JudgementPreset::for_difficulty(difficulty);
+
+        Self {
+            version: CONDITIONS_VERSION,
+            note_offset,
+            judgement_preset: preset,
+            standard_preset: preset,
+            autoplay: false,
+            playback_speed: 1.0,
+            note_visibility: NoteVisibility::Normal,
+            note_shuffle: NoteShuffle::Off,
+            shuffle_seed: None,
+            game_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// The same conditions, but with the song played at the given speed.
+    pub fn at_speed(self, playback_speed: f32) -> Self {
+        Self {
+            playback_speed,
+            ..self
+        }
+    }
+
+    /// The same conditions, but with the notes shown the given way.
+    pub fn with_note_visibility(self, note_visibility: NoteVisibility) -> Self {
+        Self {
+            note_visibility,
+            ..self
+        }
+    }
+
+    /// The same conditions, but with the notes shuffled the given way.
+    pub fn with_note_shuffle(self, note_shuffle: NoteShuffle, seed: u64) -> Self {
+        Self {
+            note_shuffle,
+            shuffle_seed: note_shuffle.is_random().then_some(seed),
+            ..self
+        }
+    }
+
+    /// The modifiers the song was played with, for setting it up the same way again.
+    pub fn modifiers(&self) -> PlayModifiers {
+        PlayModifiers {
+            speed: self.playback_speed,
+            note_visibility: self.note_visibility,
+            note_shuffle: self.note_shuffle,
+            // Only the random shuffles have a seed, and the others don't need one
+            shuffle_seed: self.shuffle_seed.unwrap_or(0),
+        }
+    }
+
+    /// Whether these are the normal conditions for playing a song, i.e. nothing that would make
+    /// the score incomparable with anyone else's.
+    ///
+    /// The note offset doesn't count, since it's there to correct for the player's setup. Neither
+    /// does hiding the notes, since that only makes the song harder. Shuffling them does, since
+    /// that makes it a different chart.
+    pub fn is_default(&self) -> bool {
+        self.judgement_preset == self.standard_preset
+            && !self.autoplay
+            && self.playback_speed == 1.0
+            && self.note_shuffle == NoteShuffle::Off
+    }
+
+    /// The encoding these conditions are hashed from. See the module docs.
+    fn encode(&self) -> String {
+        match self.version {
+            1 => format!(
+                "v1;offset={:08x};judgement={};standard={};autoplay={};game={}",
+                self.note_offset.to_bits(),
+                self.judgement_preset.key(),
+                self.standard_preset.key(),
+                self.autoplay,
+                self.game_version,
+            ),
+            2 => format!(
+                "v2;offset={:08x};judgement={};standard={};autoplay={};speed={:08x};game={}",
+                self.note_offset.to_bits(),
+                self.judgement_preset.key(),
+                self.standard_preset.key(),
+                self.autoplay,
+                self.playback_speed.to_bits(),
+                self.game_version,
+            ),
+            3 => format!(
+                "v3;offset={:08x};judgement={};standard={};autoplay={};speed={:08x};\
+                 visibility={};game={}",
+                self.note_offset.to_bits(),
+                self.judgement_preset.key(),
+                self.standard_preset.key(),
+                self.autoplay,
+                self.playback_speed.to_bits(),
+                self.note_visibility.key(),
+                self.game_version,
+            ),
+            4 => format!(
+                "v4;offset={:08x};judgement={};standard={};autoplay={};speed={:08x};\
+                 visibility={};shuffle={};game={}",
+                self.note_offset.to_bits(),
+                self.judgement_preset.key(),
+                self.standard_preset.key(),
+                self.autoplay,
+                self.playback_speed.to_bits(),
+                self.note_visibility.key(),
+                self.note_shuffle.key(),
+                self.game_version,
+            ),
+
+            // Conditions from a newer version of the game than this one. We can't know how they
+            // should be encoded, so they won't match anything this version makes.
+            version => format!("v{version};unknown"),
+        }
+    }
+
+    /// A hash of these conditions that stays the same across versions of the game.
+    pub fn hash(&self) -> u64 {
+        fnv1a(self.encode().as_bytes())
+    }
+}
+
+impl std::fmt::Display for PlayConditions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} judgement, {:+}ms offset",
+            self.judgement_preset, self.note_offset
+        )?;
+
+        if self.playback_speed != 1.0 {
+            write!(f, ", {}x speed", self.playback_speed)?;
+        }
+
+        if self.note_visibility != NoteVisibility::Normal {
+            write!(f, ", {}", self.note_visibility.name().to_lowercase())?;
+        }
+
+        match (self.note_shuffle, self.shuffle_seed) {
+            (NoteShuffle::Off, _) => {}
+            (shuffle, Some(seed)) => {
+                write!(f, ", {} (seed {seed:x})", shuffle.name().to_lowercase())?
+            }
+            (shuffle, None) => write!(f, ", {}", shuffle.name().to_lowercase())?,
+        }
+
+        if self.autoplay {
+            write!(f, ", autoplay")?;
+        }
+
+        write!(f, " (v{}, {:016x})", self.game_version, self.hash())
+    }
+}
+
+fn normal_speed() -> f32 {
+    1.0
+}
+
+/// The 64 bit FNV-1a hash. The standard library's hashers aren't guaranteed to give the same
+/// results between releases, so they can't be used for anything that's saved.
+pub(super) fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    bytes.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn v1_conditions() -> PlayConditions {
+        PlayConditions {
+            version: 1,
+            note_offset: -12.5,
+            judgement_preset: JudgementPreset::HardExtreme,
+            standard_preset: JudgementPreset::HardExtreme,
+            autoplay: false,
+            playback_speed: 1.0,
+            note_visibility: NoteVisibility::Normal,
+            note_shuffle: NoteShuffle::Off,
+            shuffle_seed: None,
+            game_version: "0.1.0".to_string(),
+        }
+    }
+
+    fn v2_conditions() -> PlayConditions {
+        PlayConditions {
+            version: 2,
+            ..v1_conditions()
+        }
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        // Known values for the FNV-1a test vectors
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn test_v1_hash_is_stable() {
+        // If this fails, the version 1 encoding has changed and every saved hash is now wrong.
+        // Add a new version instead.
+        let conditions = v1_conditions();
+        assert_eq!(
+            conditions.encode(),
+            "v1;offset=c1480000;judgement=hard_extreme;standard=hard_extreme;autoplay=false;game=0.1.0"
+        );
+        assert_eq!(conditions.hash(), fnv1a(conditions.encode().as_bytes()));
+
+        let saved = toml::to_string(&conditions).unwrap();
+        let loaded: PlayConditions = toml::from_str(&saved).unwrap();
+        assert_eq!(loaded, conditions);
+        assert_eq!(loaded.hash(), conditions.hash());
+
+        // Version 1 conditions were saved before there was a playback speed
+        let saved = saved.replace("playback_speed = 1.0\n", "");
+        assert!(!saved.contains("playback_speed"));
+        let loaded: PlayConditions = toml::from_str(&saved).unwrap();
+        assert_eq!(loaded, conditions);
+    }
+
+    #[test]
+    fn test_v2_hash_is_stable() {
+        let conditions = v2_conditions().at_speed(0.5);
+        assert_eq!(
+            conditions.encode(),
+            "v2;offset=c1480000;judgement=hard_extreme;standard=hard_extreme;autoplay=false;\
+             speed=3f000000;game=0.1.0"
+        );
+    }
+
+    #[test]
+    fn test_v3_hash_is_stable() {
+        let conditions = PlayConditions {
+            version: 3,
+            ..v1_conditions()
+        }
+        .with_note_visibility(NoteVisibility::Sudden);
+        assert_eq!(
+            conditions.encode(),
+            "v3;offset=c1480000;judgement=hard_extreme;standard=hard_extreme;autoplay=false;\
+             speed=3f800000;visibility=sudden;game=0.1.0"
+        );
+
+        // Older conditions were saved before notes could be hidden
+        let saved = toml::to_string(&v2_conditions()).unwrap();
+        let saved = saved.replace("note_visibility = \"Normal\"\n", "");
+        assert!(!saved.contains("note_visibility"));
+        let loaded: PlayConditions = toml::from_str(&saved).unwrap();
+        assert_eq!(loaded, v2_conditions());
+    }
+
+    #[test]
+    fn test_v4_hash_is_stable() {
+        let conditions = PlayConditions {
+            version: 4,
+            ..v1_conditions()
+        }
+        .with_note_shuffle(NoteShuffle::Kimagure, 1234);
+        assert_eq!(
+            conditions.encode(),
+            "v4;offset=c1480000;judgement=hard_extreme;standard=hard_extreme;autoplay=false;\
+             speed=3f800000;visibility=normal;shuffle=kimagure;game=0.1.0"
+        );
+
+        // The seed is saved, but doesn't change the hash
+        let loaded: PlayConditions =
+            toml::from_str(&toml::to_string(&conditions).unwrap()).unwrap();
+        assert_eq!(loaded.shuffle_seed, Some(1234));
+        let reseeded = conditions
+            .clone()
+            .with_note_shuffle(NoteShuffle::Kimagure, 5678);
+        assert_eq!(reseeded.hash(), conditions.hash());
+
+        // Swapping every note doesn't need a seed
+        let abekobe = conditions.with_note_shuffle(NoteShuffle::Abekobe, 1234);
+        assert_eq!(abekobe.shuffle_seed, None);
+    }
+
+    #[test]
+    fn test_conditions_differ() {
+        let conditions = PlayConditions {
+            version: 4,
+            ..v1_conditions()
+        };
+        assert!(conditions.is_default());
+
+        let autoplay = PlayConditions {
+            autoplay: true,
+            ..conditions.clone()
+        };
+        assert!(!autoplay.is_default());
+        assert_ne!(autoplay.hash(), conditions.hash());
+
+        let easier = PlayConditions {
+            judgement_preset: JudgementPreset::EasyNormal,
+            ..conditions.clone()
+        };
+        assert!(!easier.is_default());
+        assert_ne!(easier.hash(), conditions.hash());
+
+        let slower = conditions.clone().at_speed(0.75);
+        assert!(!slower.is_default());
+        assert_ne!(slower.hash(), conditions.hash());
+
+        // Hiding the notes only makes it harder, so it can still be a personal best
+        let hidden = conditions
+            .clone()
+            .with_note_visibility(NoteVisibility::Hidden);
+        assert!(hidden.is_default());
+        assert_ne!(hidden.hash(), conditions.hash());
+
+        let shuffled = conditions
+            .clone()
+            .with_note_shuffle(NoteShuffle::Detarame, 0);
+        assert!(!shuffled.is_default());
+        assert_ne!(shuffled.hash(), conditions.hash());
+
+        let future = PlayConditions {
+            version: CONDITIONS_VERSION + 100,
+            ..conditions.clone()
+        };
+        assert_ne!(future.hash(), conditions.hash());
+    }
+}
@@ -0,0 +1,571 @@
+//! Judging the player's drum hits against a chart's notes.
+//!
+//! None of this needs anything to draw the notes with, so a chart can be played through without a
+//! window or audio (e.g. in a test) and be judged exactly the way it would be in game. The notes
+//! that are drawn in game keep a [JudgedNote] each, and the [Judge] works through them either way.
+use std::borrow::BorrowMut;
+
+use super::note::{BasicNoteType, BAD};
+use super::scene::{NoteJudgement, PlayResult, ScoreInt};
+use super::scoring::{BIG_NOTE_SECOND_HIT_WINDOW, ROLL_SECOND_HIT_WINDOW};
+use crate::notechart_parser::{Note, NoteType};
+use crate::settings::DrumInput;
+
+/// How far the player has got with a note, along with what's needed to judge it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum NoteState {
+    Note {
+        kind: BasicNoteType,
+        is_hit: bool,
+    },
+    Roll {
+        big: bool,
+        duration: f32,
+        /// How many times the roll has been hit so far.
+        hits: u32,
+    },
+    Balloon {
+        hit_target: u32,
+        hits_left: u32,
+        duration: f32,
+        started: bool,
+        kusudama: bool,
+    },
+}
+
+/// A note as far as judging it is concerned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JudgedNote {
+    pub(crate) state: NoteState,
+    time: f32,
+    /// Whether the note is in gogo time, where it scores more.
+    gogo: bool,
+}
+
+/// Different ways a note can respond to a drum hit
+/// See [JudgedNote::receive_hit]
+#[derive(Debug, Copy, Clone)]
+pub enum NoteKeypressReaction {
+    /// Don was pressed but this note is Kat, or vice versa
+    /// Basically, do absolutely nothing.
+    WrongColour,
+    /// The keypress is too early, so the note is not yet able to be hit.
+    ///
+    /// *This variant is more important than WrongColour*. If a keypress is both too early and the
+    /// wrong colour, this is the one you should return, since the calling code uses this variant
+    /// to determine where to stop calling [JudgedNote::receive_hit]
+    TooEarly,
+    /// The note was hit, with the given time offset
+    ///
+    /// The offset is calculated as input_time - note_time. That is to say, it is *relative to the
+    /// note time*. For example, if you hit 15ms before you should have, the offset will be -0.015,
+    /// that is to say, 0.015 seconds *early*.
+    ///
+    /// The kind of note it was is returned too, so the right note can be shown flying off.
+    Hit { offset: f32, kind: BasicNoteType },
+    /// The note was hit, and is a drumroll. `hits` is how many times it has been hit now.
+    /// Since drumrolls can be big or small, and can be hit with either don or kat, we return the
+    /// note type so that we can display the correct flying note.
+    Drumroll { roll_note: BasicNoteType, hits: u32 },
+    /// The note was hit, and is a balloon (or a kusudama).
+    BalloonRoll {
+        hits_left: u32,
+        hit_target: u32,
+        kusudama: bool,
+    },
+    /// The note cannot be hit anymore.
+    TooLate,
+}
+
+impl JudgedNote {
+    pub fn new(note: &Note) -> Self {
+        let state = match note.note_type {
+            NoteType::Don
+            | NoteType::Kat
+            | NoteType::BigDon
+            | NoteType::CoopDon
+            | NoteType::BigKat
+            | NoteType::CoopKat => NoteState::Note {
+                kind: note.note_type.try_into().unwrap(),
+                is_hit: false,
+            },
+
+            NoteType::Roll(duration) | NoteType::BigRoll(duration) => NoteState::Roll {
+                big: matches!(note.note_type, NoteType::BigRoll(_)),
+                duration,
+                hits: 0,
+            },
+
+            // A kusudama is played like a balloon, it's just bigger
+            NoteType::BalloonRoll(duration, hit_target)
+            | NoteType::Kusudama(duration, hit_target) => NoteState::Balloon {
+                hit_target,
+                hits_left: hit_target,
+                duration,
+                started: false,
+                kusudama: matches!(note.note_type, NoteType::Kusudama(..)),
+            },
+        };
+
+        Self {
+            state,
+            time: note.time,
+            gogo: note.gogo,
+        }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Whether this note is a don/kat note that awards judgement and must be hit.
+    pub fn is_don_or_kat(&self) -> bool {
+        matches!(self.state, NoteState::Note { .. })
+    }
+
+    pub fn is_balloon(&self) -> bool {
+        matches!(self.state, NoteState::Balloon { .. })
+    }
+
+    /// Whether this is a kusudama, which is played like a balloon but scores more when popped.
+    pub fn is_kusudama(&self) -> bool {
+        matches!(self.state, NoteState::Balloon { kusudama, .. } if kusudama)
+    }
+
+    /// Whether this is a big don or kat, which can be hit with both hands.
+    pub fn is_big_note(&self) -> bool {
+        matches!(self.state, NoteState::Note { kind, .. } if kind.is_big())
+    }
+
+    /// Whether this is a drumroll (not a balloon).
+    pub fn is_roll(&self) -> bool {
+        matches!(self.state, NoteState::Roll { .. })
+    }
+
+    /// Whether this is a drumroll that's being played at the given time.
+    pub fn is_active_roll(&self, time: f32) -> bool {
+        matches!(self.state, NoteState::Roll { duration, .. }
+            if (self.time..self.time + duration).contains(&time))
+    }
+
+    /// Whether this is a don or kat that the given drum hit would hit.
+    fn is_hit_by(&self, input: DrumInput, time: f32, timing_windows: &[f32; 3]) -> bool {
+        matches!(self.state, NoteState::Note { kind, .. } if kind.is_hit_by(input))
+            && self.is_hittable(time, timing_windows)
+            && self.time - timing_windows[BAD] <= time
+    }
+
+    /// Reacts to a drum hit.
+    pub fn receive_hit(
+        &mut self,
+        input: DrumInput,
+        time: f32,
+        timing_windows: &[f32; 3],
+    ) -> NoteKeypressReaction {
+        if !self.is_hittable(time, timing_windows) {
+            return NoteKeypressReaction::TooLate;
+        }
+
+        match &mut self.state {
+            NoteState::Note { kind, is_hit } => {
+                if self.time - timing_windows[BAD] > time {
+                    // If the earliest the note could ever be hit is later (greater than) the
+                    // current time, then we are too early.
+                    NoteKeypressReaction::TooEarly
+                } else if kind.is_hit_by(input) {
+                    // We know the note is not too late (hittable), we know the note is not
+                    // too early, so this means the note is hit! Return the timing difference.
+                    *is_hit = true;
+                    NoteKeypressReaction::Hit {
+                        offset: time - self.time,
+                        kind: *kind,
+                    }
+                } else {
+                    NoteKeypressReaction::WrongColour
+                }
+            }
+
+            NoteState::Roll {
+                duration,
+                big,
+                hits,
+            } => {
+                let relative_time = time - self.time;
+                if relative_time < 0.0 {
+                    // This is before the drumroll
+                    NoteKeypressReaction::TooEarly
+                } else if relative_time >= *duration {
+                    // This is after
+                    NoteKeypressReaction::TooLate
+                } else {
+                    // This is just right
+                    *hits += 1;
+                    NoteKeypressReaction::Drumroll {
+                        roll_note: BasicNoteType::hit_with(input, *big),
+                        hits: *hits,
+                    }
+                }
+            }
+
+            NoteState::Balloon {
+                duration,
+                started: has_been_started,
+                hits_left,
+                hit_target,
+                kusudama,
+            } => {
+                if self.time > time {
+                    NoteKeypressReaction::TooEarly
+                } else if self.time + *duration < time || *hits_left == 0 {
+                    NoteKeypressReaction::TooLate
+                } else if input.is_don() {
+                    *hits_left -= 1;
+                    *has_been_started = true;
+                    NoteKeypressReaction::BalloonRoll {
+                        hits_left: *hits_left,
+                        hit_target: *hit_target,
+                        kusudama: *kusudama,
+                    }
+                } else {
+                    NoteKeypressReaction::WrongColour
+                }
+            }
+        }
+    }
+
+    /// Puts the note back the way it was before it was played, so it can be played again.
+    pub fn rearm(&mut self) {
+        match &mut self.state {
+            NoteState::Note { is_hit, .. } => *is_hit = false,
+            NoteState::Roll { hits, .. } => *hits = 0,
+            NoteState::Balloon {
+                hit_target,
+                hits_left,
+                started,
+                ..
+            } => {
+                *hits_left = *hit_target;
+                *started = false;
+            }
+        }
+    }
+
+    /// Whether the note is (or will at some point be) hittable.
+    ///
+    /// When checking if a note has been hit by the player, we start checking from the first
+    /// hittable note. If the note can be hit now or at some point in the future, it is considered
+    /// "hittable". If it is past its time, however, it is not hittable.
+    pub fn is_hittable(&self, time: f32, timing_windows: &[f32; 3]) -> bool {
+        match self.state {
+            NoteState::Note { is_hit, .. } => {
+                // If the note is hit, obviously it won't be hittable again.
+                // If the latest the note could ever be hit is later than the current time, then
+                // there's still a chance it's hittable.
+                !is_hit && self.time + timing_windows[BAD] > time
+            }
+            NoteState::Roll { duration, .. } => self.time + duration > time,
+            NoteState::Balloon {
+                duration,
+                hits_left,
+                ..
+            } => hits_left > 0 && self.time + duration > time,
+        }
+    }
+}
+
+/// What a drum hit did, as far as the player can see.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HitOutcome {
+    /// The hit didn't do anything to any note.
+    Nothing,
+    /// A don or kat was hit.
+    Note {
+        judgement: NoteJudgement,
+        kind: BasicNoteType,
+    },
+    /// The big note or big drumroll that was just hit was hit with the other hand too.
+    BigNoteBonus,
+    /// A drumroll was hit, and has been hit this many times now.
+    RollHit { roll_note: BasicNoteType, hits: u32 },
+    /// A balloon (or kusudama) was hit, and has this many hits left before it pops.
+    BalloonHit {
+        hits_left: u32,
+        hit_target: u32,
+        kusudama: bool,
+    },
+}
+
+/// Works through a chart's notes as the song goes on, judging the player's hits and recording
+/// them in a [PlayResult].
+///
+/// The notes can be anything that holds a [JudgedNote], so the same judge is used with the notes
+/// drawn in game and with bare notes when there's nothing to draw them with.
+#[derive(Debug)]
+pub struct Judge<N> {
+    notes: Vec<N>,
+    /// The index of the next note to be played
+    next_note_index: usize,
+    timing_windows: &'static [f32; 3],
+    /// Whether every hit is judged as good, however far off it was.
+    every_hit_good: bool,
+    /// Whether a balloon has gone by unfinished since this was last checked.
+    balloon_missed: bool,
+    /// The last hit, if it hit a big note or a drumroll, which the other hand can still hit too.
+    two_handed_hit: Option<TwoHandedHit>,
+}
+
+/// A hit on a big note or a drumroll, waiting to see if the other hand hits it at the same time.
+#[derive(Debug, Clone, Copy)]
+struct TwoHandedHit {
+    input: DrumInput,
+    time: f32,
+    /// How long after the first hit the other hand can hit, in seconds.
+    window: f32,
+    /// Whether it was a big note or a big roll, which the other hand scores again on. Both hands
+    /// on a small roll just count as one hit.
+    big: bool,
+    /// The points the first hit scored.
+    points: ScoreInt,
+}
+
+impl TwoHandedHit {
+    /// Whether the given hit is the other hand hitting along with this one.
+    fn is_other_hand(&self, input: DrumInput, time: f32) -> bool {
+        input != self.input
+            && input.is_don() == self.input.is_don()
+            && time - self.time <= self.window
+    }
+}
+
+impl<N: BorrowMut<JudgedNote>> Judge<N> {
+    pub fn new(notes: Vec<N>, timing_windows: &'static [f32; 3]) -> Self {
+        Self {
+            notes,
+            next_note_index: 0,
+            timing_windows,
+            every_hit_good: false,
+            balloon_missed: false,
+            two_handed_hit: None,
+        }
+    }
+
+    /// Judges every hit as good. The tutorial is about learning the notes, not timing.
+    pub fn judge_every_hit_good(&mut self) {
+        self.every_hit_good = true;
+    }
+
+    pub fn notes(&self) -> &[N] {
+        &self.notes
+    }
+
+    pub fn notes_mut(&mut self) -> &mut [N] {
+        &mut self.notes
+    }
+
+    /// Swaps the notes for new ones, returning the old ones. The new notes should be the same notes
+    /// in the same order.
+    pub fn replace_notes(&mut self, notes: Vec<N>) -> Vec<N> {
+        std::mem::replace(&mut self.notes, notes)
+    }
+
+    pub fn next_note_index(&self) -> usize {
+        self.next_note_index
+    }
+
+    /// The next note to be played, if there are any left.
+    pub fn next_note(&self) -> Option<&N> {
+        self.notes.get(self.next_note_index)
+    }
+
+    /// Whether every note has been played or gone by.
+    pub fn finished(&self) -> bool {
+        self.next_note_index == self.notes.len()
+    }
+
+    pub fn timing_windows(&self) -> &'static [f32; 3] {
+        self.timing_windows
+    }
+
+    /// Returns whether a balloon has gone by unfinished since the last time this was called.
+    pub fn take_balloon_missed(&mut self) -> bool {
+        std::mem::take(&mut self.balloon_missed)
+    }
+
+    /// Jumps to the given time, e.g. when the player seeks through the song in practice mode.
+    ///
+    /// Every note is re-armed, so the ones after the new time can be played again however they
+    /// were played before. The notes that are already over by then are gone by without being
+    /// judged at all, so seeking forward doesn't miss anything.
+    pub fn seek(&mut self, time: f32) {
+        for note in &mut self.notes {
+            note.borrow_mut().rearm();
+        }
+
+        self.next_note_index = self
+            .notes
+            .iter()
+            .position(|note| note.borrow().is_hittable(time, self.timing_windows))
+            .unwrap_or(self.notes.len());
+        self.balloon_missed = false;
+        self.two_handed_hit = None;
+    }
+
+    /// Considers the next note to have been missed. Updates the index of the next note, and adds a
+    /// miss to the play result if appropriate.
+    fn skip_next_note(&mut self, results: &mut PlayResult) {
+        if let Some(note) = self.notes.get(self.next_note_index) {
+            let note = note.borrow();
+            self.next_note_index += 1;
+
+            if note.is_don_or_kat() {
+                results.push_judgement(None);
+            } else if note.is_balloon() {
+                self.balloon_missed = true;
+                results.push_balloon(false, note.is_kusudama(), note.gogo);
+            }
+        }
+    }
+
+    /// Misses every note that can't be hit any more at the given time.
+    pub fn advance(&mut self, time: f32, results: &mut PlayResult) {
+        // Advance our position in the list of notes as far as we can go
+        while let Some(note) = self.notes.get(self.next_note_index) {
+            if note.borrow().is_hittable(time, self.timing_windows) {
+                break;
+            }
+
+            self.skip_next_note(results);
+        }
+    }
+
+    /// Whether a don or kat from the given index on is close enough to be hit by the given drum
+    /// hit.
+    fn note_in_reach(&self, from: usize, input: DrumInput, time: f32) -> bool {
+        self.notes[from.min(self.notes.len())..]
+            .iter()
+            .map(|note| note.borrow())
+            .take_while(|note| note.time - self.timing_windows[BAD] <= time)
+            .any(|note| note.is_hit_by(input, time, self.timing_windows))
+    }
+
+    /// Judges a drum hit made at the given time, first missing any notes that went by before it.
+    ///
+    /// Since everything is decided by the time of the hit, the judgement is the same no matter how
+    /// often [advance](Self::advance) has been called before it (i.e. whatever the frame rate).
+    pub fn judge_hit(
+        &mut self,
+        input: DrumInput,
+        time: f32,
+        results: &mut PlayResult,
+    ) -> HitOutcome {
+        self.advance(time, results);
+        self.hit(input, time, results)
+    }
+
+    /// Judges a drum hit at the given time.
+    fn hit(&mut self, input: DrumInput, time: f32, results: &mut PlayResult) -> HitOutcome {
+        // The other hand hitting the same colour straight after a big note or a roll goes along
+        // with that hit, rather than whatever note comes next
+        if let Some(first) = self.two_handed_hit.take() {
+            if first.is_other_hand(input, time) {
+                if !first.big {
+                    return HitOutcome::Nothing;
+                }
+
+                results.push_big_note_bonus(first.points);
+                return HitOutcome::BigNoteBonus;
+            }
+        }
+
+        let mut note_index = self.next_note_index;
+
+        // We now have to go through all the notes starting from the next one, and see if
+        // any of them react to this keypress. If any of them react, or any of them are too
+        // far away to react, then we stop.
+        loop {
+            // If there's no next note, we don't need to react.
+            let Some(next_note) = self.notes.get(note_index) else {
+                return HitOutcome::Nothing;
+            };
+
+            // A hit near the end of a roll goes to the note after it if it could, so the roll
+            // doesn't eat it
+            if next_note.borrow().is_roll() && self.note_in_reach(note_index + 1, input, time) {
+                note_index += 1;
+                continue;
+            }
+
+            let next_note = self.notes[note_index].borrow_mut();
+            let (big, gogo) = (next_note.is_big_note(), next_note.gogo);
+            let reaction = next_note.receive_hit(input, time, self.timing_windows);
+            match reaction {
+                // If it's the wrong colour, we'll keep checking to see if there's
+                // a note of the right colour in scope.
+                NoteKeypressReaction::WrongColour => {}
+
+                NoteKeypressReaction::TooEarly => {
+                    // Now we're only looking at notes that are unhittable, so stop here.
+                    return HitOutcome::Nothing;
+                }
+                NoteKeypressReaction::Hit { offset, kind } => {
+                    let judgement = if self.every_hit_good {
+                        NoteJudgement::Good
+                    } else {
+                        NoteJudgement::from_offset(offset, self.timing_windows).unwrap()
+                    };
+
+                    let points = results.push_hit(judgement, offset, gogo);
+                    self.next_note_index = note_index + 1;
+
+                    if big {
+                        self.two_handed_hit = Some(TwoHandedHit {
+                            input,
+                            time,
+                            window: BIG_NOTE_SECOND_HIT_WINDOW,
+                            big: true,
+                            points,
+                        });
+                    }
+
+                    // Ensure you only ever hit one note at a time
+                    return HitOutcome::Note { judgement, kind };
+                }
+                NoteKeypressReaction::Drumroll { roll_note, hits } => {
+                    let points = results.push_roll_hit(time, gogo);
+                    self.two_handed_hit = Some(TwoHandedHit {
+                        input,
+                        time,
+                        window: ROLL_SECOND_HIT_WINDOW,
+                        big: roll_note.is_big(),
+                        points,
+                    });
+
+                    return HitOutcome::RollHit { roll_note, hits };
+                }
+                NoteKeypressReaction::BalloonRoll {
+                    hits_left,
+                    hit_target,
+                    kusudama,
+                } => {
+                    results.push_roll_hit(time, gogo);
+
+                    if hits_left == 0 {
+                        results.push_balloon(true, kusudama, gogo);
+                        self.next_note_index = note_index + 1;
+                    }
+
+                    return HitOutcome::BalloonHit {
+                        hits_left,
+                        hit_target,
+                        kusudama,
+                    };
+                }
+                NoteKeypressReaction::TooLate => {
+                    self.skip_next_note(results);
+                }
+            }
+
+            note_index += 1;
+        }
+    }
+}
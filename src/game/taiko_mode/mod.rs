@@ -1,5 +1,25 @@
+mod autoplay;
+mod conditions;
+mod judge;
+mod modifiers;
 mod note;
+mod pause_menu;
+mod practice;
+mod replay;
 mod scene;
+mod scheduled_hits;
+mod scoring;
+#[cfg(test)]
+mod smoke_test;
+mod song_assets;
+mod tutorial;
 mod ui;
+mod versus;
 
-pub use scene::{PlayResult, TaikoMode};
+pub use conditions::PlayConditions;
+pub use modifiers::{NoteShuffle, NoteVisibility, PlayModifiers};
+pub use replay::{list_replays, Replay, REPLAYS_DIR};
+pub use scene::{PlayResult, ScoreInt, TaikoMode, PLAYBACK_SPEEDS};
+pub use scoring::{format_accuracy, Rally};
+pub use song_assets::SongAssets;
+pub use ui::{dimmed_background, judgement_text_centre, JUDGEMENT_TEXT_SIZE};
@@ -0,0 +1,318 @@
+//! Options the player can choose before a song to change how it's played.
+//!
+//! None of these change how hits are judged or scored. They only change how the song sounds and
+//! which notes there are to hit, so everything that happens in the scene works the same with them
+//! on.
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::layout::{NOTE_HIT_X, SCREEN_WIDTH};
+use crate::notechart_parser::Note;
+
+/// How far along the note field (see [field_position]) notes finish fading out with
+/// [NoteVisibility::Hidden], and start fading in with [NoteVisibility::Sudden].
+const HIDE_NEAR: f32 = 0.3;
+/// How far along the note field notes start fading out with [NoteVisibility::Hidden], and finish
+/// fading in with [NoteVisibility::Sudden].
+const HIDE_FAR: f32 = 0.5;
+
+/// Hides the notes for some (or all) of their way to the receptacle, so they have to be read
+/// early or remembered. Barlines are always shown.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NoteVisibility {
+    #[default]
+    Normal,
+    /// Notes fade out as they get close to the receptacle.
+    Hidden,
+    /// Notes can't be seen until they're close to the receptacle.
+    Sudden,
+    /// Notes can't be seen at all.
+    Stealth,
+}
+
+impl NoteVisibility {
+    /// All the options, in the order they're shown in the difficulty select window.
+    pub const ALL: [NoteVisibility; 4] = [
+        NoteVisibility::Normal,
+        NoteVisibility::Hidden,
+        NoteVisibility::Sudden,
+        NoteVisibility::Stealth,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoteVisibility::Normal => "Normal",
+            NoteVisibility::Hidden => "Hidden",
+            NoteVisibility::Sudden => "Sudden",
+            NoteVisibility::Stealth => "Stealth",
+        }
+    }
+
+    /// The name used in the play conditions' hash encoding. These must never change.
+    pub(super) fn key(&self) -> &'static str {
+        match self {
+            NoteVisibility::Normal => "normal",
+            NoteVisibility::Hidden => "hidden",
+            NoteVisibility::Sudden => "sudden",
+            NoteVisibility::Stealth => "stealth",
+        }
+    }
+
+    /// How opaque to draw a note whose head is at the given x position on the screen.
+    pub fn alpha_at(&self, x_position: f32) -> f32 {
+        let position = field_position(x_position);
+        let fade = ((position - HIDE_NEAR) / (HIDE_FAR - HIDE_NEAR)).clamp(0.0, 1.0);
+
+        match self {
+            NoteVisibility::Normal => 1.0,
+            NoteVisibility::Hidden => fade,
+            NoteVisibility::Sudden => 1.0 - fade,
+            NoteVisibility::Stealth => 0.0,
+        }
+    }
+}
+
+/// How far along the note field the given x position is, from 0 at the receptacle to 1 at the
+/// right edge of the screen, where notes come in.
+fn field_position(x_position: f32) -> f32 {
+    (x_position - NOTE_HIT_X) / (SCREEN_WIDTH - NOTE_HIT_X)
+}
+
+/// Swaps dons for kats and kats for dons. Drumrolls and balloons are never changed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NoteShuffle {
+    #[default]
+    Off,
+    /// Every note is swapped.
+    Abekobe,
+    /// About a fifth of the notes are swapped at random.
+    Kimagure,
+    /// Half of the notes are swapped at random.
+    Detarame,
+}
+
+impl NoteShuffle {
+    /// All the options, in the order they're shown in the difficulty select window.
+    pub const ALL: [NoteShuffle; 4] = [
+        NoteShuffle::Off,
+        NoteShuffle::Abekobe,
+        NoteShuffle::Kimagure,
+        NoteShuffle::Detarame,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoteShuffle::Off => "Off",
+            NoteShuffle::Abekobe => "Abekobe",
+            NoteShuffle::Kimagure => "Kimagure",
+            NoteShuffle::Detarame => "Detarame",
+        }
+    }
+
+    /// The name used in the play conditions' hash encoding. These must never change.
+    pub(super) fn key(&self) -> &'static str {
+        match self {
+            NoteShuffle::Off => "off",
+            NoteShuffle::Abekobe => "abekobe",
+            NoteShuffle::Kimagure => "kimagure",
+            NoteShuffle::Detarame => "detarame",
+        }
+    }
+
+    /// Whether which notes are swapped depends on the seed.
+    pub fn is_random(&self) -> bool {
+        matches!(self, NoteShuffle::Kimagure | NoteShuffle::Detarame)
+    }
+
+    /// The chance each note has of being swapped.
+    fn swap_chance(&self) -> f32 {
+        match self {
+            NoteShuffle::Off => 0.0,
+            NoteShuffle::Abekobe => 1.0,
+            NoteShuffle::Kimagure => 0.2,
+            NoteShuffle::Detarame => 0.5,
+        }
+    }
+
+    /// Swaps the notes. The seed decides which ones are swapped by the random shuffles, so the same
+    /// seed always swaps the same notes.
+    pub fn apply(&self, notes: &mut [Note], seed: u64) {
+        let chance = self.swap_chance();
+        let mut rng = ShuffleRng::new(seed);
+
+        for note in notes.iter_mut().filter(|note| !note.note_type.is_roll()) {
+            // Every note takes a number, so changing one doesn't change what happens to the rest
+            if rng.next_f32() < chance {
+                note.note_type = note.note_type.swapped();
+            }
+        }
+    }
+}
+
+/// A small random number generator (SplitMix64) for shuffling notes. Unlike anything the standard
+/// library has, it gives the same numbers for the same seed on every version of the game.
+struct ShuffleRng {
+    state: u64,
+}
+
+impl ShuffleRng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number from 0 (inclusive) to 1 (exclusive).
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// A new seed for shuffling notes, which is different every time.
+pub fn random_seed() -> u64 {
+    // The standard library's hashers are randomly keyed, which is all the randomness needed here
+    RandomState::new().build_hasher().finish()
+}
+
+/// The modifiers chosen for one play of a song.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlayModifiers {
+    /// How fast the song is played, as a multiple of its normal speed (see
+    /// [PLAYBACK_SPEEDS](super::PLAYBACK_SPEEDS)).
+    pub speed: f32,
+    pub note_visibility: NoteVisibility,
+    pub note_shuffle: NoteShuffle,
+    /// The seed the notes are shuffled with, if the shuffle is random.
+    pub shuffle_seed: u64,
+}
+
+impl PlayModifiers {
+    /// The same modifiers, with a new seed for the shuffle.
+    pub fn reseeded(self) -> Self {
+        Self {
+            shuffle_seed: random_seed(),
+            ..self
+        }
+    }
+}
+
+impl Default for PlayModifiers {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            note_visibility: NoteVisibility::Normal,
+            note_shuffle: NoteShuffle::Off,
+            shuffle_seed: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notechart_parser::NoteType;
+
+    #[test]
+    fn test_alpha() {
+        let receptacle = NOTE_HIT_X;
+        let middle = (NOTE_HIT_X + SCREEN_WIDTH) / 2.0;
+
+        for x in [receptacle, middle, SCREEN_WIDTH] {
+            assert_eq!(NoteVisibility::Normal.alpha_at(x), 1.0);
+            assert_eq!(NoteVisibility::Stealth.alpha_at(x), 0.0);
+        }
+
+        // Hidden notes are gone by the time they reach the receptacle, and sudden notes only show
+        // up near it. Missed notes stay the way they were when they got there.
+        for x in [receptacle - 100.0, receptacle] {
+            assert_eq!(NoteVisibility::Hidden.alpha_at(x), 0.0);
+            assert_eq!(NoteVisibility::Sudden.alpha_at(x), 1.0);
+        }
+        for x in [middle, SCREEN_WIDTH] {
+            assert_eq!(NoteVisibility::Hidden.alpha_at(x), 1.0);
+            assert_eq!(NoteVisibility::Sudden.alpha_at(x), 0.0);
+        }
+
+        // In between, they fade
+        let fading = NOTE_HIT_X + (SCREEN_WIDTH - NOTE_HIT_X) * 0.4;
+        assert!((NoteVisibility::Hidden.alpha_at(fading) - 0.5).abs() < 1e-4);
+        assert!((NoteVisibility::Sudden.alpha_at(fading) - 0.5).abs() < 1e-4);
+    }
+
+    fn notes(note_types: &[NoteType]) -> Vec<Note> {
+        note_types
+            .iter()
+            .enumerate()
+            .map(|(i, &note_type)| Note {
+                note_type,
+                time: i as f32,
+                scroll_speed: 1.0,
+                gogo: false,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_abekobe() {
+        use NoteType::*;
+
+        let mut shuffled = notes(&[
+            Don,
+            Kat,
+            BigDon,
+            BigKat,
+            CoopDon,
+            Roll(1.0),
+            BalloonRoll(1.0, 5),
+        ]);
+        NoteShuffle::Abekobe.apply(&mut shuffled, 0);
+
+        let note_types: Vec<_> = shuffled.iter().map(|note| note.note_type).collect();
+        assert_eq!(
+            note_types,
+            [
+                Kat,
+                Don,
+                BigKat,
+                BigDon,
+                CoopKat,
+                Roll(1.0),
+                BalloonRoll(1.0, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_random_shuffles() {
+        let original = notes(&[NoteType::Don; 1000]);
+        let shuffle = |shuffle: NoteShuffle, seed| {
+            let mut notes = original.clone();
+            shuffle.apply(&mut notes, seed);
+            notes
+        };
+        let kats = |notes: &[Note]| notes.iter().filter(|note| note.note_type.is_kat()).count();
+
+        // Roughly the right number of notes are swapped
+        assert!((150..250).contains(&kats(&shuffle(NoteShuffle::Kimagure, 1))));
+        assert!((450..550).contains(&kats(&shuffle(NoteShuffle::Detarame, 1))));
+        assert_eq!(shuffle(NoteShuffle::Off, 1), original);
+
+        // The same seed always gives the same notes, and a different one doesn't
+        assert_eq!(
+            shuffle(NoteShuffle::Detarame, 42),
+            shuffle(NoteShuffle::Detarame, 42)
+        );
+        assert_ne!(
+            shuffle(NoteShuffle::Detarame, 42),
+            shuffle(NoteShuffle::Detarame, 43)
+        );
+    }
+}
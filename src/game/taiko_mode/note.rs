@@ -1,9 +1,13 @@
 //! Defines structs for drawing notes and barlines to the screen
+use std::borrow::{Borrow, BorrowMut};
+use std::rc::Rc;
+
 use lyon::lyon_tessellation::TessellationError;
-use winit::keyboard::PhysicalKey;
 
+use super::judge::{JudgedNote, NoteState};
+use super::modifiers::NoteVisibility;
 use crate::notechart_parser::NoteType;
-use crate::notechart_parser::{Barline, Note};
+use crate::notechart_parser::{Barline, Note, NoteChart, ScrollMode, TimingPoint};
 use crate::render::texture::SpriteBuilder;
 use crate::render::Renderer;
 use crate::{game::TextureCache, render::shapes::ShapeBuilder};
@@ -13,12 +17,17 @@ use crate::render::{
     texture::Sprite,
     Renderable,
 };
-use crate::settings::{settings, SETTINGS};
+use crate::settings::DrumInput;
 
-use super::ui::{LEFT_PANEL_WIDTH, NOTE_FIELD_HEIGHT, NOTE_FIELD_Y, NOTE_HIT_X, NOTE_Y};
+use crate::game::layout::{LEFT_PANEL_WIDTH, NOTE_FIELD_HEIGHT, NOTE_FIELD_Y, NOTE_HIT_X, NOTE_Y};
 
 const VELOCITY: f32 = (1920. - NOTE_HIT_X) / 2.;
+/// The BPM that notes scroll at normal speed for.
+const DEFAULT_BPM: f32 = 120.0;
 const ROLL_COLOUR: [f32; 4] = [1., 195. / 255., 44. / 255., 1.];
+const GOGO_GLOW_COLOUR: [f32; 4] = [1., 140. / 255., 30. / 255., 0.6];
+/// How far the glow around a note in gogo time reaches past the note.
+const GOGO_GLOW_WIDTH: f32 = 10.;
 
 // Nice expressive aliases for the indices we'll use for note judgements
 pub const GOOD: usize = 0;
@@ -30,23 +39,36 @@ pub const BAD: usize = 2;
 pub const EASY_NORMAL_TIMING: [f32; 3] = [0.042, 0.108, 0.125];
 pub const HARD_EXTREME_TIMING: [f32; 3] = [0.025, 0.075, 0.108];
 
+/// How far ahead (in seconds) to warn the player about notes that aren't on screen yet.
+pub const INCOMING_NOTE_WINDOW: f32 = 1.0;
+
 /// Takes a list of notes in a song and creates visual representations for all of them.
+/// `beat_scroll` is the chart's, if it scrolls with the beat (see [BeatScroll::for_chart]).
 pub fn create_notes(
     renderer: &Renderer,
     textures: &mut TextureCache,
     notes: &[Note],
+    beat_scroll: Option<Rc<BeatScroll>>,
 ) -> Vec<TaikoModeNote> {
     notes
         .iter()
-        .filter_map(|note| TaikoModeNote::new(renderer, note, textures))
+        .filter_map(|note| TaikoModeNote::new(renderer, note, textures, beat_scroll.clone()))
         .collect()
 }
 
 /// Takes a list of barlines in a song and creates visual representations for all of them.
-pub fn create_barlines(renderer: &mut Renderer, barlines: &[Barline]) -> Vec<TaikoModeBarline> {
+/// `beat_scroll` is the chart's, if it scrolls with the beat (see [BeatScroll::for_chart]).
+pub fn create_barlines(
+    renderer: &mut Renderer,
+    barlines: &[Barline],
+    beat_scroll: Option<Rc<BeatScroll>>,
+) -> Vec<TaikoModeBarline> {
     barlines
         .iter()
         .map(|barline| {
+            let scroll_speed = beat_scroll.as_ref().map_or(barline.scroll_speed, |scroll| {
+                scroll.speed(barline.time, barline.scroll_speed)
+            });
             let visual_line = ShapeBuilder::new()
                 .filled_rectangle(
                     [-1., 0.],
@@ -55,7 +77,7 @@ pub fn create_barlines(renderer: &mut Renderer, barlines: &[Barline]) -> Vec<Tai
                 )
                 .expect("Error creating barline shape")
                 .position([
-                    x_position_of_note(barline.time, 0., barline.scroll_speed),
+                    x_position_of_note(barline.time, 0., scroll_speed),
                     NOTE_FIELD_Y,
                     0.,
                 ])
@@ -64,18 +86,134 @@ pub fn create_barlines(renderer: &mut Renderer, barlines: &[Barline]) -> Vec<Tai
             TaikoModeBarline {
                 visual_line,
                 time: barline.time,
-                scroll_speed: barline.scroll_speed,
+                scroll_speed,
+                beat_scroll: beat_scroll.clone(),
             }
         })
         .collect()
 }
 
+/// Returns the kind of the next don/kat note that will reach the receptacle within
+/// [INCOMING_NOTE_WINDOW] seconds, if it hasn't scrolled onto the screen yet.
+///
+/// This happens when the scroll speed is so high that the note will cross the whole screen in a
+/// moment. Notes are sorted by time, so this only needs to look forward from the next note to be
+/// hit rather than through the whole list.
+pub fn next_incoming_note(
+    notes: &[TaikoModeNote],
+    next_note_index: usize,
+    time: f32,
+) -> Option<BasicNoteType> {
+    notes
+        .get(next_note_index..)?
+        .iter()
+        .take_while(|note| note.time() <= time + INCOMING_NOTE_WINDOW)
+        .filter(|note| note.time() > time && !note.visible(time))
+        .find_map(|note| match note.judged.state {
+            NoteState::Note { kind, .. } => Some(kind),
+            _ => None,
+        })
+}
+
 /// Where on the screen a note should be drawn given the current time of the song, when the note
 /// should be hit and how fast it travels.
 pub fn x_position_of_note(current_time: f32, note_time: f32, scroll_speed: f32) -> f32 {
     NOTE_HIT_X + VELOCITY * (note_time - current_time) * scroll_speed
 }
 
+/// How far the track has scrolled through a chart that scrolls with the beat (see [ScrollMode]),
+/// rather than each note moving at its own speed.
+#[derive(Debug)]
+pub struct BeatScroll {
+    mode: ScrollMode,
+    /// The chart's timing points, each with how far the track has scrolled by then.
+    points: Vec<(TimingPoint, f32)>,
+}
+
+impl BeatScroll {
+    /// Works out the scrolling for the given chart, or returns `None` if its notes scroll normally.
+    pub fn for_chart(chart: &NoteChart) -> Option<Rc<Self>> {
+        if chart.scroll_mode == ScrollMode::Normal {
+            return None;
+        }
+
+        let mut points: Vec<(TimingPoint, f32)> = Vec::with_capacity(chart.timing.len());
+        for &point in &chart.timing {
+            let position = points.last().map_or(0.0, |(last, position)| {
+                position + Self::rate(last) * (point.time - last.time)
+            });
+            points.push((point, position));
+        }
+
+        Some(Rc::new(Self {
+            mode: chart.scroll_mode,
+            points,
+        }))
+    }
+
+    /// How fast the track scrolls from the given timing point on, relative to normal speed. It
+    /// stands still during a delay.
+    fn rate(point: &TimingPoint) -> f32 {
+        if point.seconds_per_measure.is_finite() {
+            point.bpm / DEFAULT_BPM
+        } else {
+            0.0
+        }
+    }
+
+    /// The timing point the given time comes under, and how far the track had scrolled by it.
+    fn point_at(&self, time: f32) -> Option<&(TimingPoint, f32)> {
+        let i = self.points.partition_point(|(point, _)| point.time <= time);
+        self.points.get(i.saturating_sub(1))
+    }
+
+    /// How far the track has scrolled by the given time, in seconds of scrolling at normal speed.
+    pub fn position(&self, time: f32) -> f32 {
+        match self.point_at(time) {
+            Some((point, position)) => position + Self::rate(point) * (time - point.time),
+            None => time,
+        }
+    }
+
+    /// How fast something at the given time moves along the track, given the scroll speed it was
+    /// placed with (which has the BPM taken into account).
+    pub fn speed(&self, time: f32, scroll_speed: f32) -> f32 {
+        match (self.mode, self.point_at(time)) {
+            (ScrollMode::Hbs, Some((point, _))) => scroll_speed * DEFAULT_BPM / point.bpm,
+            _ => 1.0,
+        }
+    }
+
+    /// Where on the screen something at `time` should be drawn given the current time of the
+    /// song, and how fast it moves along the track.
+    pub fn x_position(&self, current_time: f32, time: f32, speed: f32) -> f32 {
+        x_position_of_note(self.position(current_time), self.position(time), speed)
+    }
+
+    /// The note as it's drawn: moving at its speed along the track, and (for a roll) as long as
+    /// the track scrolls while it lasts.
+    fn shown_note(&self, note: &Note) -> Note {
+        let length = |length| self.position(note.time + length) - self.position(note.time);
+        let note_type = match note.note_type {
+            NoteType::Roll(l) => NoteType::Roll(length(l)),
+            NoteType::BigRoll(l) => NoteType::BigRoll(length(l)),
+            note_type => note_type,
+        };
+
+        Note {
+            note_type,
+            scroll_speed: self.speed(note.time, note.scroll_speed),
+            ..*note
+        }
+    }
+}
+
+/// Returns how wide (in pixels) a timing window is on screen, for notes moving at the given scroll
+/// speed. The window covers `window` seconds either side of the receptacle.
+pub fn timing_window_width(window: f32, scroll_speed: f32) -> f32 {
+    2.0 * window * VELOCITY * scroll_speed.abs()
+}
+
 fn drumroll_visual_length(scroll_speed: f32, length_of_time: f32) -> f32 {
     scroll_speed * length_of_time * VELOCITY
 }
@@ -96,12 +234,27 @@ pub(crate) struct BasicNoteType {
 }
 
 impl BasicNoteType {
-    fn is_hit_by(&self, key: PhysicalKey) -> bool {
-        let settings = SETTINGS.read().unwrap();
-        match self.colour {
-            NoteColour::Don => settings.key_is_don(key),
-            NoteColour::Kat => settings.key_is_kat(key),
-        }
+    pub fn is_don(&self) -> bool {
+        self.colour == NoteColour::Don
+    }
+
+    pub fn is_big(&self) -> bool {
+        self.big
+    }
+
+    pub fn is_hit_by(&self, input: DrumInput) -> bool {
+        self.is_don() == input.is_don()
+    }
+
+    /// The note that's shown flying off when a roll is hit with the given input.
+    pub fn hit_with(input: DrumInput, big: bool) -> Self {
+        let colour = if input.is_don() {
+            NoteColour::Don
+        } else {
+            NoteColour::Kat
+        };
+
+        Self { colour, big }
     }
 }
 
@@ -118,11 +271,11 @@ impl TryFrom<NoteType> for BasicNoteType {
                 colour: NoteColour::Kat,
                 big: false,
             }),
-            NoteType::BigDon => Ok(Self {
+            NoteType::BigDon | NoteType::CoopDon => Ok(Self {
                 colour: NoteColour::Don,
                 big: true,
             }),
-            NoteType::BigKat => Ok(Self {
+            NoteType::BigKat | NoteType::CoopKat => Ok(Self {
                 colour: NoteColour::Kat,
                 big: true,
             }),
@@ -131,35 +284,35 @@ impl TryFrom<NoteType> for BasicNoteType {
     }
 }
 
-/// The "Inner" taiko mode Note type is an enum containing data and behaviour specific to the note
-/// type.
+/// The "Inner" taiko mode Note type is an enum containing the sprites specific to the note type.
+/// How far the player has got with the note is kept in its [JudgedNote].
 #[derive(Debug)]
-pub(crate) enum NoteInner {
+enum NoteInner {
     Note {
         sprite: Sprite,
-        kind: BasicNoteType,
-        is_hit: bool,
+        /// Drawn behind the note if it's in gogo time.
+        glow: Option<Shape>,
     },
     Roll {
         start_sprite: Sprite,
         body_sprite: Shape,
-        big: bool,
         duration: f32,
     },
     Balloon {
         sprite: Sprite,
-        hit_target: u32,
-        hits_left: u32,
-        duration: f32,
-        started: bool,
     },
 }
 
 #[derive(Debug)]
 pub struct TaikoModeNote {
-    pub(crate) note: NoteInner,
-    time: f32,
+    note: NoteInner,
+    judged: JudgedNote,
     scroll_speed: f32,
+    /// How fast the note moves on screen. This is only different from `scroll_speed` in charts
+    /// that scroll with the beat.
+    shown_speed: f32,
+    beat_scroll: Option<Rc<BeatScroll>>,
+    gogo: bool,
 }
 
 #[derive(Debug)]
@@ -167,6 +320,7 @@ pub struct TaikoModeBarline {
     visual_line: Shape,
     time: f32,
     scroll_speed: f32,
+    beat_scroll: Option<Rc<BeatScroll>>,
 }
 
 impl NoteInner {
@@ -226,14 +380,26 @@ impl NoteInner {
                     _ => unreachable!(),
                 };
 
-                Self::Note {
-                    sprite: SpriteBuilder::new(get_texture(sprite_name))
-                        .centre()
-                        .depth(Some(0.))
-                        .build(renderer),
-                    kind: note_type.try_into().unwrap(),
-                    is_hit: false,
-                }
+                let sprite = SpriteBuilder::new(get_texture(sprite_name))
+                    .centre()
+                    .depth(Some(0.))
+                    .build(renderer);
+
+                let glow = if note.gogo {
+                    let ([left, _], [right, _]) = sprite.relative_bounding_box();
+                    let radius = (right - left) / 2. + GOGO_GLOW_WIDTH;
+
+                    let glow = ShapeBuilder::new()
+                        .has_depth(true)
+                        .filled_circle([0., 0.], radius, SolidColour::new(GOGO_GLOW_COLOUR))
+                        .ok()?
+                        .build(&renderer.device);
+                    Some(glow)
+                } else {
+                    None
+                };
+
+                Self::Note { sprite, glow }
             }
 
             NoteType::Roll(length) | NoteType::BigRoll(length) => {
@@ -249,323 +415,188 @@ impl NoteInner {
                     start_sprite: start,
                     body_sprite: body,
                     duration: length,
-                    big: matches!(note_type, NoteType::BigRoll(_)),
                 }
             }
 
-            NoteType::BalloonRoll(duration, hit_target) => {
-                Self::Balloon {
-                    sprite: SpriteBuilder::new(get_texture("balloon 1.png"))
-                        .depth(Some(0.))
-                        // The notehead is centred at [50, 50].
-                        .origin([50., 50.])
-                        .build(renderer),
-                    hit_target,
-                    hits_left: hit_target,
-                    duration,
-                    started: false,
-                }
-            }
+            NoteType::BalloonRoll(..) => Self::Balloon {
+                sprite: SpriteBuilder::new(get_texture("balloon 1.png"))
+                    .depth(Some(0.))
+                    // The notehead is centred at [50, 50].
+                    .origin([50., 50.])
+                    .build(renderer),
+            },
 
-            _ => return None,
+            NoteType::Kusudama(..) => Self::Balloon {
+                sprite: SpriteBuilder::new(get_texture("balloon 3.png"))
+                    .depth(Some(0.))
+                    // The notehead is centred at [50, 100].
+                    .origin([50., 100.])
+                    .build(renderer),
+            },
         };
 
         Some(result)
     }
 
-    /// Sets the position of the note. The note will be centred at that position.
-    fn set_x_position(&mut self, x: f32, depth: f32, renderer: &Renderer) {
-        let position = [x, NOTE_Y];
+    /// Sets the position and opacity of the note. The note will be centred at that position.
+    fn set_position(&mut self, position: [f32; 2], depth: f32, alpha: f32, renderer: &Renderer) {
         match self {
-            NoteInner::Note { sprite, .. } | NoteInner::Balloon { sprite, .. } => {
+            NoteInner::Note { sprite, glow } => {
+                sprite.set_alpha(alpha, renderer);
                 sprite.set_position(position, renderer);
                 sprite.set_depth(Some(depth), renderer);
-            }
-
-            NoteInner::Roll {
-                start_sprite: start,
-                body_sprite: body,
-                ..
-            } => {
-                start.set_position(position, renderer);
-                // TODO: do the same refactoring to shapes as I did to sprites
-                body.set_position([position[0], position[1], depth], renderer);
-            }
-        }
-    }
-
-    fn x_position_for_time(
-        &self,
-        current_time: f32,
-        note_time: f32,
-        scroll_speed: f32,
-    ) -> Option<f32> {
-        match &self {
-            NoteInner::Note { is_hit, .. } if *is_hit => None,
-
-            NoteInner::Roll { .. } | NoteInner::Note { .. } => {
-                Some(x_position_of_note(current_time, note_time, scroll_speed))
-            }
 
-            NoteInner::Balloon {
-                hits_left,
-                duration,
-                started: has_been_started,
-                ..
-            } => {
-                if *hits_left == 0 {
-                    // The balloon is popped so we won't display it anyway.
-                    None
-                } else if current_time < note_time {
-                    // Before it is active, draw it like any other note
-                    Some(x_position_of_note(current_time, note_time, scroll_speed))
-                } else if current_time > note_time + *duration {
-                    // After it is active, if it hasn't been started, draw it
-                    // if it was started, it will disappear, so don't do anything
-                    (!*has_been_started).then_some(x_position_of_note(
-                        current_time,
-                        note_time + *duration,
-                        scroll_speed,
-                    ))
-                } else {
-                    // The balloon is currently active so draw it on the receptacle
-                    Some(NOTE_HIT_X)
+                if let Some(glow) = glow {
+                    glow.set_position_and_alpha([position[0], position[1], depth], alpha, renderer);
                 }
             }
-        }
-    }
-
-    fn set_position_for_time(
-        &mut self,
-        current_time: f32,
-        note_time: f32,
-        scroll_speed: f32,
-        renderer: &Renderer,
-    ) {
-        let Some(x_position) = self.x_position_for_time(current_time, note_time, scroll_speed)
-        else {
-            return;
-        };
-
-        self.set_x_position(x_position, note_time, renderer);
-    }
 
-    /// Whether this note is a don/kat note that awards judgement and must be hit.
-    fn is_don_or_kat(&self) -> bool {
-        matches!(self, NoteInner::Note { .. },)
-    }
-}
-
-impl Renderable for NoteInner {
-    fn render<'pass>(
-        &'pass self,
-        renderer: &'pass Renderer,
-        render_pass: &mut wgpu::RenderPass<'pass>,
-    ) {
-        match self {
-            NoteInner::Note { sprite, is_hit, .. } => {
-                if !is_hit {
-                    sprite.render(renderer, render_pass)
-                }
-            }
-            NoteInner::Balloon {
-                sprite, started, ..
-            } => {
-                if !started {
-                    sprite.render(renderer, render_pass)
-                }
+            NoteInner::Balloon { sprite } => {
+                sprite.set_alpha(alpha, renderer);
+                sprite.set_position(position, renderer);
+                sprite.set_depth(Some(depth), renderer);
             }
+
             NoteInner::Roll {
                 start_sprite: start,
                 body_sprite: body,
                 ..
             } => {
-                // If start and body both have the same depth, then start should render on top
-                // of the body, given the compare function is `LessEqual`
-                body.render(renderer, render_pass);
-                start.render(renderer, render_pass);
+                start.set_alpha(alpha, renderer);
+                start.set_position(position, renderer);
+                // TODO: do the same refactoring to shapes as I did to sprites
+                body.set_position_and_alpha([position[0], position[1], depth], alpha, renderer);
             }
         }
     }
 }
 
-/// Different ways a note can respond to a keypress
-/// See [TaikoModeNote::receive_keypress]
-#[derive(Debug, Copy, Clone)]
-pub enum NoteKeypressReaction {
-    /// Don was pressed but this note is Kat, or vice versa
-    /// Basically, do absolutely nothing.
-    WrongColour,
-    /// The keypress is too early, so the note is not yet able to be hit.
-    ///
-    /// *This variant is more important than WrongColour*. If a keypress is both too early and the
-    /// wrong colour, this is the one you should return, since the calling code uses this variant
-    /// to determine where to stop calling [TaikoModeNote::receive_keypress]
-    TooEarly,
-    /// The note was hit, with the given time offset
-    ///
-    /// The offset is calculated as input_time - note_time. That is to say, it is *relative to the
-    /// note time*. For example, if you hit 15ms before you should have, the offset will be -0.015,
-    /// that is to say, 0.015 seconds *early*.
-    Hit { offset: f32 },
-    /// The note was hit, and is a drumroll.
-    /// Since drumrolls can be big or small, and can be hit with either don or kat, we return the
-    /// note type so that we can display the correct flying note.
-    Drumroll { roll_note: BasicNoteType },
-    /// The note was hit, and is a balloon.
-    BalloonRoll { hits_left: u32, hit_target: u32 },
-    /// The note cannot be hit anymore.
-    TooLate,
-}
-
 impl TaikoModeNote {
-    pub fn new(renderer: &Renderer, note: &Note, textures: &mut TextureCache) -> Option<Self> {
+    pub fn new(
+        renderer: &Renderer,
+        note: &Note,
+        textures: &mut TextureCache,
+        beat_scroll: Option<Rc<BeatScroll>>,
+    ) -> Option<Self> {
+        let shown = beat_scroll
+            .as_ref()
+            .map_or(*note, |scroll| scroll.shown_note(note));
+
         Some(Self {
-            note: NoteInner::new(renderer, note, textures)?,
+            judged: JudgedNote::new(note),
+            note: NoteInner::new(renderer, &shown, textures)?,
             scroll_speed: note.scroll_speed,
-            time: note.time,
+            shown_speed: shown.scroll_speed,
+            beat_scroll,
+            gogo: note.gogo,
         })
     }
 
-    pub fn update_position(&mut self, renderer: &Renderer, note_adjusted_time: f32) {
-        self.note
-            .set_position_for_time(note_adjusted_time, self.time, self.scroll_speed, renderer)
+    /// Whether the note is in gogo time.
+    pub fn is_gogo(&self) -> bool {
+        self.gogo
     }
 
-    /// Whether this note is a don/kat note that awards judgement and must be hit.
-    pub fn is_don_or_kat(&self) -> bool {
-        self.note.is_don_or_kat()
+    pub fn scroll_speed(&self) -> f32 {
+        self.scroll_speed
     }
 
-    pub fn visible(&self, note_adjusted_time: f32) -> bool {
-        let Some(x_position) =
-            self.note
-                .x_position_for_time(note_adjusted_time, self.time, self.scroll_speed)
-        else {
-            // If there is no possible x position, we're not going to display it anyway.
-            return false;
-        };
-        let (rel_start, rel_end) = self.relative_bounding_box();
-
-        let start_x = rel_start[0] + x_position;
-        let end_x = rel_end[0] + x_position;
+    pub fn time(&self) -> f32 {
+        self.judged.time()
+    }
 
-        // TODO: seriously dont use hard coded resolution
-        start_x < 1920. && end_x >= LEFT_PANEL_WIDTH
+    /// How far the player has got with this note.
+    pub fn judged(&self) -> &JudgedNote {
+        &self.judged
     }
 
-    /// Reacts to a keypress.
-    pub fn receive_keypress(
+    /// Moves the note to where it should be at the given time. Its opacity depends on where that
+    /// is, if the notes are being hidden. `y_offset` is how far the note field is below player 1's.
+    pub fn update_position(
         &mut self,
-        key: PhysicalKey,
-        time: f32,
-        timing_windows: &[f32; 3],
-    ) -> NoteKeypressReaction {
-        // Before this function was called, we should have checked that the keypress is actually
-        // don or kat.
-        if !settings().key_is_don_or_kat(key) {
-            panic!(
-                "Keycode was passed to TaikoModeNote::receive_keypress that was not don or kat."
-            );
-        }
+        renderer: &Renderer,
+        note_adjusted_time: f32,
+        visibility: NoteVisibility,
+        y_offset: f32,
+    ) {
+        let Some(x_position) = self.x_position_for_time(note_adjusted_time) else {
+            return;
+        };
 
-        if !self.is_hittable(time, timing_windows) {
-            return NoteKeypressReaction::TooLate;
+        let alpha = visibility.alpha_at(x_position);
+        self.note.set_position(
+            [x_position, NOTE_Y + y_offset],
+            self.time(),
+            alpha,
+            renderer,
+        );
+    }
+
+    /// Where on the screen part of the note at the given time should be drawn.
+    fn x_position_of(&self, current_time: f32, time: f32) -> f32 {
+        match &self.beat_scroll {
+            Some(scroll) => scroll.x_position(current_time, time, self.shown_speed),
+            None => x_position_of_note(current_time, time, self.shown_speed),
         }
+    }
 
-        match &mut self.note {
-            NoteInner::Note { kind, is_hit, .. } => {
-                if self.time - timing_windows[BAD] > time {
-                    // If the earliest the note could ever be hit is later (greater than) the current
-                    // time, then we are too early.
-                    NoteKeypressReaction::TooEarly
-                } else if kind.is_hit_by(key) {
-                    // We know the note is not too late (hittable), we know the note is not
-                    // too early, so this means the note is hit! Return the timing difference.
-                    *is_hit = true;
-                    NoteKeypressReaction::Hit {
-                        offset: time - self.time,
-                    }
-                } else {
-                    NoteKeypressReaction::WrongColour
-                }
-            }
+    fn x_position_for_time(&self, current_time: f32) -> Option<f32> {
+        let note_time = self.time();
 
-            NoteInner::Roll { duration, big, .. } => {
-                let relative_time = time - self.time;
-                if relative_time < 0.0 {
-                    // This is before the drumroll
-                    NoteKeypressReaction::TooEarly
-                } else if relative_time >= *duration {
-                    // This is after
-                    NoteKeypressReaction::TooLate
-                } else {
-                    // This is just right
-                    let settings = SETTINGS.read().unwrap();
-                    let colour = if settings.key_is_don(key) {
-                        NoteColour::Don
-                    } else {
-                        // We already checked that the key was either don or kat.
-                        NoteColour::Kat
-                    };
-
-                    let roll_note = BasicNoteType { colour, big: *big };
-
-                    NoteKeypressReaction::Drumroll { roll_note }
-                }
+        match self.judged.state {
+            NoteState::Note { is_hit, .. } if is_hit => None,
+
+            NoteState::Roll { .. } | NoteState::Note { .. } => {
+                Some(self.x_position_of(current_time, note_time))
             }
 
-            NoteInner::Balloon {
+            NoteState::Balloon {
+                hits_left,
                 duration,
                 started: has_been_started,
-                hits_left,
-                hit_target,
                 ..
             } => {
-                if self.time > time {
-                    NoteKeypressReaction::TooEarly
-                } else if self.time + *duration < time || *hits_left == 0 {
-                    NoteKeypressReaction::TooLate
-                } else if settings().key_is_don(key) {
-                    *hits_left -= 1;
-                    *has_been_started = true;
-                    NoteKeypressReaction::BalloonRoll {
-                        hits_left: *hits_left,
-                        hit_target: *hit_target,
-                    }
+                if hits_left == 0 {
+                    // The balloon is popped so we won't display it anyway.
+                    None
+                } else if current_time < note_time {
+                    // Before it is active, draw it like any other note
+                    Some(self.x_position_of(current_time, note_time))
+                } else if current_time > note_time + duration {
+                    // After it is active, if it hasn't been started, draw it
+                    // if it was started, it will disappear, so don't do anything
+                    (!has_been_started)
+                        .then(|| self.x_position_of(current_time, note_time + duration))
                 } else {
-                    NoteKeypressReaction::WrongColour
+                    // The balloon is currently active so draw it on the receptacle
+                    Some(NOTE_HIT_X)
                 }
             }
         }
     }
 
-    /// Whether the note is (or will at some point be) hittable.
-    ///
-    /// When checking if a note has been hit by the player, we start checking from the first
-    /// hittable note. If the note can be hit now or at some point in the future, it is considered
-    /// "hittable". If it is past its time, however, it is not hittable.
-    pub fn is_hittable(&self, time: f32, timing_windows: &[f32; 3]) -> bool {
-        match self.note {
-            NoteInner::Note { is_hit, .. } => {
-                // If the note is hit, obviously it won't be hittable again.
-                // If the latest the note could ever be hit is later than the current time, then
-                // there's still a chance it's hittable.
-                !is_hit && self.time + timing_windows[BAD] > time
-            }
-            NoteInner::Roll { duration, .. } => self.time + duration > time,
-            NoteInner::Balloon {
-                duration,
-                hits_left,
-                ..
-            } => hits_left > 0 && self.time + duration > time,
-        }
+    /// Whether any of the note can be seen at the given time. Hit notes can't (they fly off to the
+    /// soul gauge instead), but missed notes carry on scrolling until they're off the note field.
+    pub fn visible(&self, note_adjusted_time: f32) -> bool {
+        let Some(x_position) = self.x_position_for_time(note_adjusted_time) else {
+            // If there is no possible x position, we're not going to display it anyway.
+            return false;
+        };
+        let (rel_start, rel_end) = self.relative_bounding_box();
+
+        on_note_field(rel_start[0] + x_position, rel_end[0] + x_position)
+    }
+
+    /// Copies how far the player has got with another copy of this note (whether it's been hit,
+    /// how many hits a balloon has left), e.g. when the notes have to be created again.
+    pub fn copy_progress(&mut self, other: &TaikoModeNote) {
+        self.judged = other.judged.clone();
     }
 
     fn relative_bounding_box(&self) -> ([f32; 2], [f32; 2]) {
         match &self.note {
             NoteInner::Note { sprite, .. } => sprite.relative_bounding_box(),
-            NoteInner::Balloon { sprite, .. } => sprite.relative_bounding_box(),
+            NoteInner::Balloon { sprite } => sprite.relative_bounding_box(),
             NoteInner::Roll {
                 start_sprite,
                 duration: length_of_time,
@@ -575,7 +606,7 @@ impl TaikoModeNote {
 
                 let start = head_start;
                 let end = [
-                    head_fin[0] + drumroll_visual_length(self.scroll_speed, *length_of_time),
+                    head_fin[0] + drumroll_visual_length(self.shown_speed, *length_of_time),
                     head_fin[1],
                 ];
 
@@ -585,24 +616,39 @@ impl TaikoModeNote {
     }
 }
 
+/// Whether anything spanning the given x coordinates can be seen on the note field. Notes are hit
+/// or missed well before they reach the left panel, and any part of them that's still showing
+/// counts, so they don't disappear early.
+fn on_note_field(start_x: f32, end_x: f32) -> bool {
+    // TODO: seriously dont use hard coded resolution
+    start_x < 1920. && end_x >= LEFT_PANEL_WIDTH
+}
+
 impl TaikoModeBarline {
-    pub fn update_position(&mut self, renderer: &Renderer, note_adjusted_time: f32) {
+    pub fn visible(&self, note_adjusted_time: f32) -> bool {
+        let x_position = self.x_position(note_adjusted_time);
+        on_note_field(x_position, x_position)
+    }
+
+    /// Moves the barline to where it should be at the given time. `y_offset` is how far the note
+    /// field is below player 1's.
+    pub fn update_position(&mut self, renderer: &Renderer, note_adjusted_time: f32, y_offset: f32) {
         self.visual_line.set_position(
             [
-                x_position_of_note(note_adjusted_time, self.time, self.scroll_speed),
-                NOTE_FIELD_Y,
+                self.x_position(note_adjusted_time),
+                NOTE_FIELD_Y + y_offset,
                 0.0,
             ],
             renderer,
         );
     }
 
-    pub fn time(&self) -> f32 {
-        self.time
-    }
-
-    pub fn scroll_speed(&self) -> f32 {
-        self.scroll_speed
+    /// Where on the screen the barline should be drawn given the current time of the song.
+    pub fn x_position(&self, current_time: f32) -> f32 {
+        match &self.beat_scroll {
+            Some(scroll) => scroll.x_position(current_time, self.time, self.scroll_speed),
+            None => x_position_of_note(current_time, self.time, self.scroll_speed),
+        }
     }
 }
 
@@ -612,7 +658,45 @@ impl Renderable for TaikoModeNote {
         renderer: &'pass Renderer,
         render_pass: &mut wgpu::RenderPass<'pass>,
     ) {
-        self.note.render(renderer, render_pass);
+        match (&self.note, &self.judged.state) {
+            (NoteInner::Note { sprite, glow }, NoteState::Note { is_hit, .. }) if !is_hit => {
+                // The glow has the same depth as the note, so it goes underneath
+                if let Some(glow) = glow.as_ref().filter(|_| self.is_gogo()) {
+                    glow.render(renderer, render_pass);
+                }
+                sprite.render(renderer, render_pass)
+            }
+            (NoteInner::Balloon { sprite }, NoteState::Balloon { started, .. }) if !started => {
+                sprite.render(renderer, render_pass)
+            }
+            (
+                NoteInner::Roll {
+                    start_sprite: start,
+                    body_sprite: body,
+                    ..
+                },
+                _,
+            ) => {
+                // If start and body both have the same depth, then start should render on top
+                // of the body, given the compare function is `LessEqual`
+                body.render(renderer, render_pass);
+                start.render(renderer, render_pass);
+            }
+            // Hit notes and started balloons aren't drawn
+            _ => {}
+        }
+    }
+}
+
+impl Borrow<JudgedNote> for TaikoModeNote {
+    fn borrow(&self) -> &JudgedNote {
+        &self.judged
+    }
+}
+
+impl BorrowMut<JudgedNote> for TaikoModeNote {
+    fn borrow_mut(&mut self) -> &mut JudgedNote {
+        &mut self.judged
     }
 }
 
@@ -625,3 +709,95 @@ impl Renderable for TaikoModeBarline {
         self.visual_line.render(renderer, render_pass);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notechart_parser::parse_tja_file;
+
+    #[test]
+    fn test_on_note_field() {
+        // A note that's nearly all behind the left panel can still be seen
+        assert!(on_note_field(
+            LEFT_PANEL_WIDTH - 90.,
+            LEFT_PANEL_WIDTH + 10.
+        ));
+        assert!(!on_note_field(
+            LEFT_PANEL_WIDTH - 100.,
+            LEFT_PANEL_WIDTH - 1.
+        ));
+        // As can a drumroll whose head has gone by but whose body hasn't
+        assert!(on_note_field(-500., 2500.));
+        assert!(!on_note_field(1920., 2020.));
+    }
+
+    #[test]
+    fn test_beat_scroll() {
+        // The second measure is twice as fast and scrolls twice as fast on top of that
+        let tja = |mode: &str| {
+            format!(
+                "TITLE:Scrolling\nWAVE:song.ogg\nBPM:120\n\nCOURSE:Oni\nLEVEL:8\n{mode}\n#START\n1,\n#BPMCHANGE 240\n#SCROLL 2\n1111,\n#DELAY 1\n1,\n#END\n"
+            )
+        };
+        let chart = |mode: &str| {
+            let song = parse_tja_file(&tja(mode)).unwrap();
+            song.difficulties[3].as_ref().unwrap().chart.clone()
+        };
+
+        let normal = chart("");
+        assert!(BeatScroll::for_chart(&normal).is_none());
+        let note = normal.notes[1];
+        assert_eq!(
+            x_position_of_note(0.0, note.time, note.scroll_speed),
+            NOTE_HIT_X + VELOCITY * 2.0 * 4.0
+        );
+
+        // The track scrolls twice as far per second after the BPM doubles, and stops for the delay
+        let hbs = BeatScroll::for_chart(&chart("#HBSCROLL")).unwrap();
+        assert_eq!(hbs.position(1.0), 1.0);
+        assert_eq!(hbs.position(2.5), 3.0);
+        assert_eq!(hbs.position(3.5), 4.0);
+        assert_eq!(hbs.position(4.5), 5.0);
+
+        // With #HBSCROLL the #SCROLL still applies, but not the BPM
+        let speed = hbs.speed(note.time, note.scroll_speed);
+        assert_eq!(speed, 2.0);
+        assert_eq!(
+            hbs.x_position(0.0, note.time, speed),
+            NOTE_HIT_X + VELOCITY * 2.0 * 2.0
+        );
+        // Notes move along with the BPM, so later notes in the fast measure are further apart
+        assert_eq!(
+            hbs.x_position(0.0, normal.notes[2].time, speed),
+            NOTE_HIT_X + VELOCITY * 2.5 * 2.0
+        );
+
+        // And with #BMSCROLL, it doesn't either
+        let bms = BeatScroll::for_chart(&chart("#BMSCROLL")).unwrap();
+        assert_eq!(bms.speed(note.time, note.scroll_speed), 1.0);
+        assert_eq!(
+            bms.x_position(0.0, note.time, 1.0),
+            NOTE_HIT_X + VELOCITY * 2.0
+        );
+    }
+
+    #[test]
+    fn test_timing_window_width() {
+        // At normal speed, a note takes 2 seconds to cross from the right edge to the receptacle
+        assert_eq!(timing_window_width(1.0, 1.0), 1920. - NOTE_HIT_X);
+        assert_eq!(
+            timing_window_width(HARD_EXTREME_TIMING[GOOD], 1.0),
+            2.0 * 0.025 * VELOCITY
+        );
+
+        // Faster notes need wider windows, and scrolling backwards doesn't change the width
+        assert_eq!(
+            timing_window_width(0.1, 2.0),
+            2.0 * timing_window_width(0.1, 1.0)
+        );
+        assert_eq!(
+            timing_window_width(0.1, -2.0),
+            timing_window_width(0.1, 2.0)
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! The menu the player gets when they pause a song with escape.
+//!
+//! The menu only keeps track of what the player has highlighted and chosen. The scene stops the
+//! clock while it's open and acts on whatever was chosen, since that's where the song is.
+use egui::RichText;
+
+use crate::game::time::EffectTimer;
+use crate::settings::DrumInput;
+
+/// How long the countdown before the song carries on lasts, in seconds.
+const RESUME_COUNTDOWN: f32 = 1.5;
+/// How many numbers are counted down from over [RESUME_COUNTDOWN].
+const COUNTDOWN_STEPS: u32 = 3;
+
+/// One of the things the player can do from the pause menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseOption {
+    Resume,
+    /// Starts the song again from the beginning.
+    Restart,
+    /// Turns autoplay on or off.
+    Autoplay,
+    /// Goes back to song select without saving the play.
+    Quit,
+}
+
+impl PauseOption {
+    /// All the options, in the order they're listed in the menu.
+    pub const ALL: [PauseOption; 4] = [
+        PauseOption::Resume,
+        PauseOption::Restart,
+        PauseOption::Autoplay,
+        PauseOption::Quit,
+    ];
+
+    fn label(&self, autoplay: bool) -> &'static str {
+        match self {
+            PauseOption::Resume => "Resume",
+            PauseOption::Restart => "Restart",
+            PauseOption::Autoplay if autoplay => "Autoplay: On",
+            PauseOption::Autoplay => "Autoplay: Off",
+            PauseOption::Quit => "Quit to song select",
+        }
+    }
+}
+
+/// The state of the pause menu while the song is paused.
+#[derive(Debug, Clone, Default)]
+pub struct PauseMenu {
+    /// The index of the highlighted option in [PauseOption::ALL].
+    selected: usize,
+    /// The option the player just chose, which the scene hasn't acted on yet.
+    chosen: Option<PauseOption>,
+    /// The countdown to the song carrying on, once the player has chosen to resume.
+    countdown: Option<EffectTimer>,
+    /// The number the countdown is showing.
+    countdown_number: Option<u32>,
+}
+
+impl PauseMenu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn selected(&self) -> PauseOption {
+        PauseOption::ALL[self.selected]
+    }
+
+    /// Handles a drum hit. The kats move the highlight up and down, and the dons choose the
+    /// highlighted option. Nothing can be chosen once the countdown has started.
+    pub fn press(&mut self, input: DrumInput) {
+        if self.is_counting_down() {
+            return;
+        }
+
+        let count = PauseOption::ALL.len();
+        match input {
+            DrumInput::LeftKat => self.selected = (self.selected + count - 1) % count,
+            DrumInput::RightKat => self.selected = (self.selected + 1) % count,
+            DrumInput::LeftDon | DrumInput::RightDon => self.chosen = Some(self.selected()),
+        }
+    }
+
+    /// Takes the option the player chose since this was last called, if they chose one.
+    pub fn take_choice(&mut self) -> Option<PauseOption> {
+        self.chosen.take()
+    }
+
+    /// Closes the menu and starts counting down to the song carrying on.
+    pub fn start_countdown(&mut self, now: f32) {
+        self.countdown = Some(EffectTimer::start(now, RESUME_COUNTDOWN));
+        self.countdown_number = Some(COUNTDOWN_STEPS);
+    }
+
+    /// Stops the countdown and opens the menu again.
+    pub fn cancel_countdown(&mut self) {
+        self.countdown = None;
+        self.countdown_number = None;
+    }
+
+    pub fn is_counting_down(&self) -> bool {
+        self.countdown.is_some()
+    }
+
+    /// Moves the countdown on to the given UI time. Returns whether it's over, meaning the song
+    /// should carry on.
+    pub fn update(&mut self, now: f32) -> bool {
+        let Some(countdown) = self.countdown else {
+            return false;
+        };
+
+        match countdown.progress(now) {
+            Some(progress) => {
+                let steps_done = (progress * COUNTDOWN_STEPS as f32) as u32;
+                self.countdown_number = Some(COUNTDOWN_STEPS.saturating_sub(steps_done).max(1));
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Shows the menu, or the countdown if it's started. Options can be clicked as well as chosen
+    /// with the drum.
+    pub fn ui(&mut self, ctx: &egui::Context, autoplay: bool) {
+        if let Some(number) = self.countdown_number {
+            egui::Area::new("resume countdown".into())
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label(RichText::new(number.to_string()).size(96.0).strong());
+                });
+            return;
+        }
+
+        egui::Window::new("paused")
+            .title_bar(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(RichText::new("Paused").size(32.0).strong());
+                ui.add_space(10.0);
+
+                for (i, option) in PauseOption::ALL.into_iter().enumerate() {
+                    let label = RichText::new(option.label(autoplay)).size(24.0);
+                    if ui.selectable_label(i == self.selected, label).clicked() {
+                        self.selected = i;
+                        self.chosen = Some(option);
+                    }
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_drum_navigation() {
+        let mut menu = PauseMenu::new();
+        assert_eq!(menu.selected(), PauseOption::Resume);
+
+        // The highlight wraps around both ways
+        menu.press(DrumInput::LeftKat);
+        assert_eq!(menu.selected(), PauseOption::Quit);
+        menu.press(DrumInput::RightKat);
+        menu.press(DrumInput::RightKat);
+        assert_eq!(menu.selected(), PauseOption::Restart);
+
+        menu.press(DrumInput::RightDon);
+        assert_eq!(menu.take_choice(), Some(PauseOption::Restart));
+        assert_eq!(menu.take_choice(), None);
+    }
+
+    #[test]
+    fn test_countdown() {
+        let mut menu = PauseMenu::new();
+        assert!(!menu.update(0.0));
+
+        menu.start_countdown(10.0);
+        assert!(!menu.update(10.0));
+        assert_eq!(menu.countdown_number, Some(3));
+        assert!(!menu.update(10.0 + RESUME_COUNTDOWN * 0.5));
+        assert_eq!(menu.countdown_number, Some(2));
+
+        // Nothing can be chosen while it's counting down
+        menu.press(DrumInput::LeftDon);
+        assert_eq!(menu.take_choice(), None);
+
+        assert!(menu.update(10.0 + RESUME_COUNTDOWN + 0.1));
+
+        // Cancelling it brings the menu back
+        menu.start_countdown(20.0);
+        menu.cancel_countdown();
+        assert!(!menu.update(30.0));
+        menu.press(DrumInput::LeftDon);
+        assert_eq!(menu.take_choice(), Some(PauseOption::Resume));
+    }
+}
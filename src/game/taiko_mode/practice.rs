@@ -0,0 +1,231 @@
+//! Practice mode, where the player can move around the song a measure or a section at a time and
+//! play one part of it over and over.
+//!
+//! This only keeps track of where the measures and sections are, the loop the player has set, and
+//! where they've been making mistakes. The scene does the seeking itself, since that's where the
+//! song and the notes are. Nothing is scored in practice mode, so it doesn't matter how often the
+//! same notes are played.
+use crate::game::waveform::FailureLog;
+use crate::notechart_parser::{measure_starts, NoteChart};
+
+/// How far before the start of the loop the song goes back to, so there's time to get ready for
+/// the notes at the start of it.
+const LOOP_LEAD_IN: f32 = 1.0;
+/// How far (in seconds) into a measure or section seeking back still goes to the one before it,
+/// rather than the start of the one that's playing. Otherwise pressing it again right away would
+/// only ever go back to the same place.
+const SEEK_BACK_TOLERANCE: f32 = 0.25;
+
+/// What setting a loop point would do next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopMark {
+    Start,
+    End,
+    /// Both points are set, so the loop is taken away.
+    Clear,
+}
+
+/// The measures and sections in a song and the loop the player has set, in note time.
+#[derive(Debug, Clone)]
+pub struct Practice {
+    /// When every measure starts, in order.
+    measure_starts: Vec<f32>,
+    /// When every section the charter marked out starts, in order.
+    section_starts: Vec<f32>,
+    /// When the chart ends, which is where the last measure finishes.
+    end_time: f32,
+    loop_start: Option<f32>,
+    loop_end: Option<f32>,
+    /// Where the player has missed notes or hit bads, which is forgotten when the loop moves.
+    failures: FailureLog,
+}
+
+impl Practice {
+    pub fn new(chart: &NoteChart) -> Self {
+        let measure_starts = measure_starts(&chart.timing, chart.end_time)
+            .into_iter()
+            .map(|barline| barline.time)
+            .collect();
+        let section_starts = chart.sections.iter().map(|section| section.time).collect();
+        Self::with_starts(measure_starts, section_starts, chart.end_time)
+    }
+
+    fn with_starts(measure_starts: Vec<f32>, section_starts: Vec<f32>, end_time: f32) -> Self {
+        Self {
+            measure_starts,
+            section_starts,
+            end_time,
+            loop_start: None,
+            loop_end: None,
+            failures: FailureLog::new(),
+        }
+    }
+
+    /// Where to go to seek back a measure from the given time: the start of the measure that's
+    /// playing, or the one before it if it's only just started.
+    pub fn previous_measure(&self, time: f32) -> Option<f32> {
+        previous_start(&self.measure_starts, time)
+    }
+
+    /// Where to go to seek forward a measure from the given time: the start of the next measure.
+    pub fn next_measure(&self, time: f32) -> Option<f32> {
+        next_start(&self.measure_starts, time)
+    }
+
+    /// Where to go to seek back a section from the given time, the same way as
+    /// [previous_measure](Self::previous_measure).
+    pub fn previous_section(&self, time: f32) -> Option<f32> {
+        previous_start(&self.section_starts, time)
+    }
+
+    /// Where to go to seek forward a section from the given time: the start of the next section.
+    pub fn next_section(&self, time: f32) -> Option<f32> {
+        next_start(&self.section_starts, time)
+    }
+
+    /// The start of the measure that's playing at the given time.
+    fn current_measure(&self, time: f32) -> f32 {
+        let index = self.measure_starts.partition_point(|&start| start <= time);
+        index
+            .checked_sub(1)
+            .map_or(time, |index| self.measure_starts[index])
+    }
+
+    /// What [mark_loop](Self::mark_loop) will do next.
+    pub fn next_mark(&self) -> LoopMark {
+        match (self.loop_start, self.loop_end) {
+            (None, _) => LoopMark::Start,
+            (Some(_), None) => LoopMark::End,
+            (Some(_), Some(_)) => LoopMark::Clear,
+        }
+    }
+
+    /// Sets the next loop point at the given time. The loop starts at the start of the measure
+    /// that's playing when it's first marked, and ends at the end of the one that's playing when
+    /// it's marked again. Marking it a third time takes the loop away.
+    pub fn mark_loop(&mut self, time: f32) {
+        match self.next_mark() {
+            LoopMark::Start => self.loop_start = Some(self.current_measure(time)),
+            LoopMark::End => {
+                // The player might have gone back before the start since marking it, in which case
+                // the loop is just the first measure
+                let start = self.loop_start.unwrap_or(time);
+                self.loop_end = Some(self.next_measure(time.max(start)).unwrap_or(self.end_time));
+            }
+            LoopMark::Clear => {
+                self.loop_start = None;
+                self.loop_end = None;
+            }
+        }
+
+        self.failures.set_loop(self.loop_bounds());
+    }
+
+    /// Remembers that the player missed a note or hit a bad at the given time.
+    pub fn record_failure(&mut self, time: f32) {
+        self.failures.record(time);
+    }
+
+    /// The times the player has missed a note or hit a bad, since the loop last moved.
+    pub fn failures(&self) -> impl Iterator<Item = f32> + '_ {
+        self.failures.times()
+    }
+
+    /// The start and end of the loop, if both have been set.
+    pub fn loop_bounds(&self) -> Option<(f32, f32)> {
+        self.loop_start.zip(self.loop_end)
+    }
+
+    /// Where to go back to if the song has just reached the end of the loop, having gone from the
+    /// first time to the second since it was last checked.
+    ///
+    /// Only crossing the end goes back, so the player can still seek past the loop and play on.
+    pub fn rewind_to(&self, from: f32, to: f32) -> Option<f32> {
+        let (Some(start), Some(end)) = (self.loop_start, self.loop_end) else {
+            return None;
+        };
+
+        (from < end && to >= end).then_some(start - LOOP_LEAD_IN)
+    }
+}
+
+/// The start of whichever of the given (sorted) start times is playing at the given time, or the
+/// one before it if it's only just started.
+fn previous_start(starts: &[f32], time: f32) -> Option<f32> {
+    let index = starts.partition_point(|&start| start < time - SEEK_BACK_TOLERANCE);
+    index.checked_sub(1).map(|index| starts[index])
+}
+
+/// The first of the given (sorted) start times after the given time.
+fn next_start(starts: &[f32], time: f32) -> Option<f32> {
+    let index = starts.partition_point(|&start| start <= time);
+    starts.get(index).copied()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn practice() -> Practice {
+        Practice::with_starts(vec![0.0, 2.0, 4.0, 6.0], vec![2.0, 6.0], 8.0)
+    }
+
+    #[test]
+    fn test_seeking_by_measure() {
+        let practice = practice();
+
+        assert_eq!(practice.previous_measure(3.0), Some(2.0));
+        // Just after a measure starts, it goes back to the one before
+        assert_eq!(practice.previous_measure(2.1), Some(0.0));
+        assert_eq!(practice.previous_measure(0.1), None);
+
+        assert_eq!(practice.next_measure(2.0), Some(4.0));
+        assert_eq!(practice.next_measure(-1.0), Some(0.0));
+        assert_eq!(practice.next_measure(6.5), None);
+    }
+
+    #[test]
+    fn test_seeking_by_section() {
+        let practice = practice();
+
+        assert_eq!(practice.previous_section(5.0), Some(2.0));
+        assert_eq!(practice.previous_section(6.1), Some(2.0));
+        // There's no section before the first one to go back to
+        assert_eq!(practice.previous_section(1.0), None);
+
+        assert_eq!(practice.next_section(0.0), Some(2.0));
+        assert_eq!(practice.next_section(2.0), Some(6.0));
+        assert_eq!(practice.next_section(6.5), None);
+    }
+
+    #[test]
+    fn test_loop() {
+        let mut practice = practice();
+        assert_eq!(practice.rewind_to(7.0, 9.0), None);
+
+        // The loop covers the whole of the measures it was marked in
+        practice.mark_loop(2.5);
+        assert_eq!(practice.next_mark(), LoopMark::End);
+        assert_eq!(practice.loop_bounds(), None);
+        practice.mark_loop(5.0);
+        assert_eq!(practice.next_mark(), LoopMark::Clear);
+        assert_eq!(practice.loop_bounds(), Some((2.0, 6.0)));
+
+        assert_eq!(practice.rewind_to(5.9, 6.01), Some(2.0 - LOOP_LEAD_IN));
+        assert_eq!(practice.rewind_to(5.0, 5.5), None);
+        // Seeking past the end doesn't go back
+        assert_eq!(practice.rewind_to(6.5, 7.0), None);
+
+        // Failures in the loop are kept until it moves
+        practice.record_failure(4.5);
+        practice.mark_loop(3.0);
+        assert_eq!(practice.next_mark(), LoopMark::Start);
+        assert_eq!(practice.rewind_to(5.9, 6.01), None);
+        assert_eq!(practice.failures().count(), 0);
+
+        // Marking the end in the last measure loops to the end of the chart
+        practice.mark_loop(6.5);
+        practice.mark_loop(7.0);
+        assert_eq!(practice.rewind_to(7.9, 8.0), Some(6.0 - LOOP_LEAD_IN));
+    }
+}
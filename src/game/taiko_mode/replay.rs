@@ -0,0 +1,511 @@
+//! Recording replays of plays, and playing them back.
+//!
+//! A replay is every drum hit the judge took, along with everything needed to set the song up the
+//! same way again: which chart it was, the conditions it was played under (which include the
+//! modifiers and the seed the notes were shuffled with) and the score it got. Judging the same
+//! hits against the same notes always comes to the same score, so a replay that doesn't can be
+//! thrown out.
+//!
+//! Replays are written with bincode, which keeps them small but can't read anything written in a
+//! different format. Every replay starts with the version of the format it was written in, so old
+//! ones can be turned away before trying to read the rest.
+//!
+//! A replay is played back by feeding its hits into the scene's input queue in place of the
+//! keyboard, so they go through exactly the same judging as they did the first time. It can also
+//! be checked without the game running (see [Replay::verify]), which is how a replay that's been
+//! tampered with, or a change to the judging that changes old scores, gets noticed.
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::conditions::{fnv1a, PlayConditions};
+use super::judge::{Judge, JudgedNote};
+use super::scene::{modified_track, PlayResult, ScoreInt};
+use super::scheduled_hits::{HitCursor, ScheduledHit};
+use super::scoring::{Gauge, Score};
+use crate::game::frame_stats::FrameStats;
+use crate::game::song_select::DIFFICULTY_NAMES;
+use crate::local_data::{date_string, unix_time_now};
+use crate::notechart_parser::{Difficulty, Note, NoteType};
+use crate::settings::DrumInput;
+
+/// The directory replays are saved in.
+pub const REPLAYS_DIR: &str = "replays";
+/// The extension replay files are saved with.
+const REPLAY_EXTENSION: &str = "ltr";
+/// The newest version of the replay format. Replays written in any other version can't be read.
+pub const REPLAY_VERSION: u32 = 4;
+/// The most characters of a song's title that go in a replay's file name.
+const FILE_NAME_TITLE_LENGTH: usize = 48;
+
+/// A drum hit in a replay.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ReplayHit {
+    pub input: DrumInput,
+    /// When the hit was made, in seconds from the start of the song as it was played (so at the
+    /// play's speed), with the note offset already taken off. This is the time it was judged at.
+    pub time: f32,
+}
+
+impl ScheduledHit for ReplayHit {
+    fn time(&self) -> f32 {
+        self.time
+    }
+}
+
+/// How many of each judgement a play got.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JudgementCounts {
+    pub goods: usize,
+    pub okays: usize,
+    pub bads: usize,
+    pub misses: usize,
+    pub drumrolls: u64,
+    pub max_combo: usize,
+}
+
+impl JudgementCounts {
+    pub fn of(result: &PlayResult) -> Self {
+        Self {
+            goods: result.goods(),
+            okays: result.okays(),
+            bads: result.bads(),
+            misses: result.misses(),
+            drumrolls: result.drumrolls(),
+            max_combo: result.max_combo(),
+        }
+    }
+
+    /// Each count with its name, for reporting.
+    fn named(&self) -> [(&'static str, u64); 6] {
+        [
+            ("goods", self.goods as u64),
+            ("okays", self.okays as u64),
+            ("bads", self.bads as u64),
+            ("misses", self.misses as u64),
+            ("drumrolls", self.drumrolls),
+            ("max combo", self.max_combo as u64),
+        ]
+    }
+}
+
+/// One play of a chart, with every hit that was made in it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Replay {
+    /// The version of the format the replay was written in. This has to stay the first field, so
+    /// it can be read on its own.
+    pub version: u32,
+    /// The song's key in the local data (see
+    /// [LocalData::song](crate::local_data::LocalData::song)).
+    pub song_key: String,
+    pub song_title: String,
+    pub difficulty: usize,
+    /// The fingerprint of the chart's notes (see [chart_fingerprint]), so a replay of a chart
+    /// that's been changed since can be told apart.
+    pub chart_fingerprint: u64,
+    pub conditions: PlayConditions,
+    /// The score the play finished with.
+    pub score: ScoreInt,
+    /// How many of each judgement the play got.
+    pub judgements: JudgementCounts,
+    /// How long frames took during the play, or None if no frames were recorded.
+    pub frame_stats: Option<FrameStats>,
+    /// The note time the bonus rally started at, if the play earned it. The rally starts on
+    /// whichever frame comes after the last note, so it has to be recorded to be played back the
+    /// same way.
+    pub rally_start: Option<f32>,
+    /// When the play finished, in seconds since the Unix epoch.
+    pub date: u64,
+    pub hits: Vec<ReplayHit>,
+}
+
+impl Replay {
+    /// The name the replay is saved under, e.g. `Smoke_test-oni-2024-03-09_143052.ltr`.
+    pub fn file_name(&self) -> String {
+        let title: String = self
+            .song_title
+            .chars()
+            .take(FILE_NAME_TITLE_LENGTH)
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let difficulty = DIFFICULTY_NAMES
+            .get(self.difficulty)
+            .map_or_else(|| self.difficulty.to_string(), |name| name.to_lowercase());
+        let seconds = self.date % 86400;
+
+        format!(
+            "{title}-{difficulty}-{}_{:02}{:02}{:02}.{REPLAY_EXTENSION}",
+            date_string(self.date),
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+        )
+    }
+
+    /// Writes the replay into the given directory, creating it if needed. Returns the path of the
+    /// file it was written to.
+    pub fn save_in<P: AsRef<Path>>(&self, dir: P) -> anyhow::Result<PathBuf> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+
+        let path = dir.join(self.file_name());
+        std::fs::write(&path, bincode::serialize(self)?)?;
+        Ok(path)
+    }
+
+    /// Reads a replay from the given file. Replays written in another version of the format are
+    /// turned away, as are files that aren't whole replays.
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path)?;
+
+        // Anything after the version might not be in the format this version knows how to read
+        let version: u32 = bincode::deserialize(&bytes)?;
+        if version != REPLAY_VERSION {
+            anyhow::bail!(
+                "the replay is from version {version} of the format, not {REPLAY_VERSION}"
+            );
+        }
+
+        Ok(bincode::deserialize(&bytes)?)
+    }
+
+    /// Makes sure the replay was recorded on the given notes (the chart's own, before any
+    /// modifiers). If the chart's been changed since, the hits wouldn't line up with it any more.
+    pub fn check_chart(&self, notes: &[Note]) -> anyhow::Result<()> {
+        if chart_fingerprint(notes) != self.chart_fingerprint {
+            anyhow::bail!("the chart has changed since this replay was recorded");
+        }
+
+        Ok(())
+    }
+
+    /// Plays the replay back against the given chart (the one it was recorded on) without the
+    /// game running, and compares what it comes to with what was recorded.
+    pub fn verify(&self, chart: &Difficulty) -> anyhow::Result<Verification> {
+        self.check_chart(&chart.chart.notes)?;
+        let result = self.play_back(chart);
+
+        Ok(Verification {
+            recorded_score: self.score,
+            score: result.score(),
+            recorded: self.judgements,
+            judgements: JudgementCounts::of(&result),
+        })
+    }
+
+    /// Judges the replay's hits against the chart, set up the way it was played, the same way the
+    /// scene judges them.
+    fn play_back(&self, chart: &Difficulty) -> PlayResult {
+        let track = modified_track(&chart.chart, self.conditions.modifiers());
+        let notes = track.notes.iter().map(JudgedNote::new).collect();
+        let mut judge = Judge::new(notes, self.conditions.judgement_preset.timing_windows());
+        let mut result = PlayResult::with_conditions(self.conditions.clone())
+            .scored_with(Score::for_difficulty(chart))
+            .with_gauge(Gauge::for_chart(self.difficulty, &track));
+        // Like when it's played back in the scene, the rally can start straight away
+        if let Some(start) = self.rally_start {
+            result.start_rally(start);
+        }
+
+        // The hits go in the order they were judged in, which is all that matters. When the frames
+        // fell doesn't change anything.
+        for hit in &self.hits {
+            if result.rally_running(hit.time) {
+                result.push_rally_hit(hit.time);
+            } else {
+                judge.judge_hit(hit.input, hit.time, &mut result);
+            }
+        }
+
+        judge.advance(f32::INFINITY, &mut result);
+        result
+    }
+}
+
+/// What a replay came to when it was played back, next to what it was recorded with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verification {
+    pub recorded_score: ScoreInt,
+    pub score: ScoreInt,
+    pub recorded: JudgementCounts,
+    pub judgements: JudgementCounts,
+}
+
+impl Verification {
+    /// Whether the replay came to exactly what it was recorded with.
+    pub fn matches(&self) -> bool {
+        self.score == self.recorded_score && self.judgements == self.recorded
+    }
+}
+
+impl Display for Verification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.matches() {
+            let counts = self.judgements;
+            return write!(
+                f,
+                "verified: {} points, {} good, {} ok, {} bad, {} missed",
+                self.score, counts.goods, counts.okays, counts.bads, counts.misses
+            );
+        }
+
+        let counts = self
+            .recorded
+            .named()
+            .into_iter()
+            .zip(self.judgements.named());
+        let mismatches: Vec<_> = [("score", self.recorded_score, self.score)]
+            .into_iter()
+            .chain(counts.map(|((name, recorded), (_, played))| (name, recorded, played)))
+            .filter(|(_, recorded, played)| recorded != played)
+            .map(|(name, recorded, played)| {
+                format!("{name} recorded {recorded}, played back {played}")
+            })
+            .collect();
+
+        write!(f, "doesn't match: {}", mismatches.join("; "))
+    }
+}
+
+/// Lists the replay files in the given directory, newest first.
+pub fn list_replays<P: AsRef<Path>>(dir: P) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut replays: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == REPLAY_EXTENSION))
+        .collect();
+
+    replays.sort_by_key(|path| {
+        std::fs::metadata(path)
+            .and_then(|data| data.modified())
+            .ok()
+    });
+    replays.reverse();
+    replays
+}
+
+/// Keeps every drum hit the judge takes over a play, so a [Replay] can be made of it at the end.
+#[derive(Debug, Clone)]
+pub struct ReplayRecorder {
+    song_key: String,
+    song_title: String,
+    difficulty: usize,
+    chart_fingerprint: u64,
+    hits: Vec<ReplayHit>,
+}
+
+impl ReplayRecorder {
+    /// Starts recording a play of the given chart. The notes should be the chart's own, before
+    /// any modifiers have changed them.
+    pub fn new(song_key: &str, song_title: &str, difficulty: usize, notes: &[Note]) -> Self {
+        Self {
+            song_key: song_key.to_string(),
+            song_title: song_title.to_string(),
+            difficulty,
+            chart_fingerprint: chart_fingerprint(notes),
+            hits: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, input: DrumInput, time: f32) {
+        self.hits.push(ReplayHit { input, time });
+    }
+
+    /// Throws away every hit recorded so far, e.g. when the song is restarted.
+    pub fn clear(&mut self) {
+        self.hits.clear();
+    }
+
+    /// Makes the replay of the play that's just finished. There isn't one if the result doesn't
+    /// know the conditions it was played under, since it couldn't be set up the same way again.
+    pub fn finish(&self, result: &PlayResult) -> Option<Replay> {
+        Some(Replay {
+            version: REPLAY_VERSION,
+            song_key: self.song_key.clone(),
+            song_title: self.song_title.clone(),
+            difficulty: self.difficulty,
+            chart_fingerprint: self.chart_fingerprint,
+            conditions: result.conditions()?.clone(),
+            score: result.score(),
+            judgements: JudgementCounts::of(result),
+            frame_stats: result.frame_stats(),
+            rally_start: result.rally().map(|rally| rally.start_time()),
+            date: unix_time_now(),
+            hits: self.hits.clone(),
+        })
+    }
+}
+
+/// Hands a replay's hits over to the scene as the song goes on, the same way the autoplayer does.
+#[derive(Debug, Clone)]
+pub struct ReplayPlayer {
+    replay: Replay,
+    cursor: HitCursor,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            cursor: HitCursor::default(),
+        }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    /// Goes back to the first hit, for when the song is restarted.
+    pub fn restart(&mut self) {
+        self.cursor.restart();
+    }
+
+    /// The hits that are due by the given note time, which haven't been handed over yet. They're
+    /// handed over in the order they were judged in.
+    pub fn hits_until(&mut self, time: f32) -> &[ReplayHit] {
+        self.cursor.hits_until(&self.replay.hits, time)
+    }
+}
+
+/// A number that (almost certainly) changes if anything about the given notes that affects how
+/// they're played changes.
+///
+/// Like the play conditions' hash, it's worked out from an explicit encoding of the notes, so it
+/// stays the same between versions of the game.
+pub fn chart_fingerprint(notes: &[Note]) -> u64 {
+    let mut bytes = Vec::new();
+
+    for note in notes {
+        // The kind of note, how long it lasts and how many hits it takes
+        let (kind, length, hits) = match note.note_type {
+            NoteType::Don => (0u8, 0.0, 0),
+            NoteType::Kat => (1, 0.0, 0),
+            NoteType::BigDon => (2, 0.0, 0),
+            NoteType::BigKat => (3, 0.0, 0),
+            NoteType::Roll(length) => (4, length, 0),
+            NoteType::BigRoll(length) => (5, length, 0),
+            NoteType::BalloonRoll(length, hits) => (6, length, hits),
+            NoteType::Kusudama(length, hits) => (7, length, hits),
+            NoteType::CoopDon => (8, 0.0, 0),
+            NoteType::CoopKat => (9, 0.0, 0),
+        };
+
+        bytes.extend([kind, note.gogo as u8]);
+        bytes.extend(note.time.to_le_bytes());
+        bytes.extend(length.to_le_bytes());
+        bytes.extend(hits.to_le_bytes());
+    }
+
+    fnv1a(&bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn note(note_type: NoteType, time: f32) -> Note {
+        Note {
+            note_type,
+            time,
+            scroll_speed: 1.0,
+            gogo: false,
+        }
+    }
+
+    fn notes() -> Vec<Note> {
+        vec![note(NoteType::Don, 0.0), note(NoteType::Kat, 0.5)]
+    }
+
+    fn replay() -> Replay {
+        let mut recorder = ReplayRecorder::new("song.ogg", "Smoke test", 3, &notes());
+        recorder.record(DrumInput::LeftDon, 0.01);
+        recorder.record(DrumInput::RightKat, 0.49);
+        let result = PlayResult::with_conditions(PlayConditions::new(3, 0.0));
+
+        Replay {
+            // Sunday the 10th of March 2024, 2:30:52pm
+            date: 1_710_081_052,
+            ..recorder.finish(&result).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(replay().file_name(), "Smoke_test-oni-2024-03-10_143052.ltr");
+
+        let replay = Replay {
+            song_title: "夜に駆ける / YOASOBI".to_string(),
+            difficulty: 4,
+            ..replay()
+        };
+        assert_eq!(
+            replay.file_name(),
+            "夜に駆ける___YOASOBI-ura-2024-03-10_143052.ltr"
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = temp_dir("replay_save");
+        let replay = Replay {
+            frame_stats: Some(FrameStats {
+                p50: 8.3,
+                p95: 9.1,
+                p99: 12.5,
+                slow_frames: 0,
+            }),
+            ..replay()
+        };
+
+        let path = replay.save_in(dir.join(REPLAYS_DIR)).unwrap();
+        assert_eq!(Replay::load(&path).unwrap(), replay);
+
+        // A replay from another version of the format isn't read, even if it would happen to fit
+        let stale = Replay {
+            version: REPLAY_VERSION + 1,
+            ..replay.clone()
+        };
+        std::fs::write(&path, bincode::serialize(&stale).unwrap()).unwrap();
+        assert!(Replay::load(&path).is_err());
+
+        // Neither is one that's been cut short
+        let bytes = bincode::serialize(&replay).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 4]).unwrap();
+        assert!(Replay::load(&path).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_changed_chart() {
+        let replay = replay();
+        assert!(replay.check_chart(&notes()).is_ok());
+
+        let mut changed = notes();
+        changed[1].time = 0.75;
+        assert!(replay.check_chart(&changed).is_err());
+    }
+
+    #[test]
+    fn test_chart_fingerprint() {
+        let notes = vec![note(NoteType::Don, 0.0), note(NoteType::Roll(1.0), 0.5)];
+        let fingerprint = chart_fingerprint(&notes);
+        assert_eq!(chart_fingerprint(&notes.clone()), fingerprint);
+
+        // Changing anything that's played differently changes it
+        let changes = [
+            vec![note(NoteType::Kat, 0.0), note(NoteType::Roll(1.0), 0.5)],
+            vec![note(NoteType::Don, 0.01), note(NoteType::Roll(1.0), 0.5)],
+            vec![note(NoteType::Don, 0.0), note(NoteType::Roll(2.0), 0.5)],
+            vec![note(NoteType::Don, 0.0)],
+        ];
+        for changed in changes {
+            assert_ne!(chart_fingerprint(&changed), fingerprint);
+        }
+    }
+}
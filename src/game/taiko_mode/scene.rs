@@ -1,33 +1,68 @@
-use std::time::Instant;
+use std::collections::VecDeque;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
 
+use egui::RichText;
+use kaku::Text;
 use kira::manager::AudioManager;
 use kira::sound::static_sound::{StaticSoundData, StaticSoundHandle};
-use kira::sound::PlaybackState;
+use kira::sound::{PlaybackRate, PlaybackState};
 use kira::tween::Tween;
-use winit::event::{ElementState, WindowEvent};
+use winit::event::{ElementState, MouseButton, WindowEvent};
 use winit::keyboard::{KeyCode, PhysicalKey};
 
+use super::autoplay::Autoplayer;
+use super::conditions::{JudgementPreset, PlayConditions};
+use super::judge::{HitOutcome, Judge};
+use super::modifiers::{NoteVisibility, PlayModifiers};
 use super::note::{
-    create_barlines, create_notes, NoteInner, NoteKeypressReaction, TaikoModeBarline,
-    TaikoModeNote, BAD, EASY_NORMAL_TIMING, GOOD, HARD_EXTREME_TIMING, OK,
+    create_barlines, create_notes, next_incoming_note, BeatScroll, TaikoModeBarline, TaikoModeNote,
+    BAD, GOOD, OK,
 };
-use super::ui::{BalloonDisplay, Header, JudgementText, NoteField};
+use super::pause_menu::{PauseMenu, PauseOption};
+use super::practice::{LoopMark, Practice};
+use super::replay::{Replay, ReplayPlayer, ReplayRecorder};
+use super::scoring::{self, Gauge, Rally, Score, ScoringEvent};
+use super::song_assets::SongAssets;
+use super::tutorial::{tutorial_song, Tutorial};
+use super::ui::{
+    dimmed_background, replay_badge, BalloonDisplay, ComboCounter, FlyingNotes, Header,
+    IncomingNoteMarker, KeyInputDisplay, NoteField, RallyDisplay, RollDisplay, SectionLabels,
+    TimingWindowBands,
+};
+use super::versus::Versus;
+use crate::game::audio::{
+    combo_chime, hit_sound_volume, silence, AudioWatchdog, HitSounds, OrLog, PlaybackCommand,
+};
+use crate::game::frame_stats::FrameStats;
+use crate::game::input_queue::InputQueue;
+use crate::game::layout::{PRACTICE_WAVEFORM, PRACTICE_WAVEFORM_SIZE};
 use crate::game::score_screen::ScoreScreen;
-use crate::game::taiko_mode::note::x_position_of_note;
-use crate::game::{Context, GameState, RenderContext, StateTransition, TextureCache};
-use crate::render::texture::SpriteBuilder;
-use crate::settings::{settings, SETTINGS};
+use crate::game::song_select::DIFFICULTY_NAMES;
+use crate::game::waveform::{
+    heat_buckets, spawn_waveform_analysis, worst_bucket_time, WaveformBucket, WaveformStrip,
+    HEAT_BUCKETS,
+};
+use crate::game::{
+    Action, CloseResponse, Context, GameState, MouseState, RenderContext, StateTransition,
+    TextureCache,
+};
+use crate::local_data::{local_data, local_data_mut, save_local_data};
+use crate::paths::paths;
+use crate::settings::settings;
 use crate::{
-    notechart_parser::Song,
-    render::{
-        shapes::{Shape, ShapeBuilder, SolidColour},
-        texture::Sprite,
-        Renderer,
-    },
+    notechart_parser::{measure_starts, NoteChart, Player, Song},
+    render::{shapes::Shape, texture::Sprite, Renderer},
 };
 
 pub type ScoreInt = u64;
 
+/// The length of the window (in seconds) that roll speed is measured over.
+const ROLL_SPEED_WINDOW: f32 = 1.0;
+/// Bonus points for rolling faster than each speed (in hits per second). Only the highest bonus
+/// reached is awarded.
+const ROLL_SPEED_BONUSES: [(f32, ScoreInt); 3] = [(12.0, 1000), (16.0, 3000), (20.0, 5000)];
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum NoteJudgement {
     Bad,
@@ -36,7 +71,7 @@ pub enum NoteJudgement {
 }
 
 impl NoteJudgement {
-    fn from_offset(offset: f32, timing_windows: &[f32; 3]) -> Option<Self> {
+    pub(super) fn from_offset(offset: f32, timing_windows: &[f32; 3]) -> Option<Self> {
         let abs_offset = offset.abs();
         if abs_offset < timing_windows[GOOD] {
             Some(Self::Good)
@@ -60,6 +95,38 @@ impl NoteJudgement {
     }
 }
 
+/// Keeps track of the fastest the player has rolled, measured as the most hits landed within a
+/// sliding one second window.
+#[derive(Clone, Default, Debug)]
+pub struct RollSpeedTracker {
+    /// The times of the roll hits within the current window.
+    hits: VecDeque<f32>,
+    /// The most hits there have ever been in the window.
+    best: usize,
+}
+
+impl RollSpeedTracker {
+    /// Records a roll hit at the given time. Hits must be recorded in order.
+    pub fn hit(&mut self, time: f32) {
+        self.hits.push_back(time);
+
+        while self
+            .hits
+            .front()
+            .is_some_and(|&first| first <= time - ROLL_SPEED_WINDOW)
+        {
+            self.hits.pop_front();
+        }
+
+        self.best = self.best.max(self.hits.len());
+    }
+
+    /// The fastest roll speed so far, in hits per second.
+    pub fn best_speed(&self) -> f32 {
+        self.best as f32 / ROLL_SPEED_WINDOW
+    }
+}
+
 /// A record containing statistics about how the player has done.
 ///
 /// This struct will slowly collate data as the game progresses, and will be passed to the score
@@ -73,12 +140,21 @@ pub struct PlayResult {
     /// A None value indicates a miss.
     judgements: Vec<Option<NoteJudgement>>,
     drumrolls: u64,
-    score: ScoreInt,
+    roll_speed: RollSpeedTracker,
+    /// The points scored for notes, leaving out the roll speed and rally bonuses.
+    score: Score,
     current_combo: usize,
     max_combo: usize,
     /// For all the notes that were hit (good, okay, or bad), records the difference between when
     /// the note was hit and when the note should have been hit.
     hit_errors: Vec<f32>,
+    gauge: Gauge,
+    /// How long frames took during the song, so we know if the game ran badly.
+    frame_stats: Option<FrameStats>,
+    /// What the song was played under, which decides what this can fairly be compared with.
+    conditions: Option<PlayConditions>,
+    /// The bonus rally at the end of the song, if the player earned one.
+    rally: Option<Rally>,
 }
 
 impl PlayResult {
@@ -86,11 +162,42 @@ impl PlayResult {
         Self::default()
     }
 
-    fn current_combo(&self) -> usize {
+    pub fn with_conditions(conditions: PlayConditions) -> Self {
+        Self {
+            conditions: Some(conditions),
+            ..Self::new()
+        }
+    }
+
+    /// Scores the notes with the given rules instead of the default ones (see [Score::default]).
+    pub fn scored_with(mut self, score: Score) -> Self {
+        self.score = score;
+        self
+    }
+
+    /// Fills the soul gauge at the given rates instead of the default ones (see [Gauge::default]).
+    pub fn with_gauge(mut self, gauge: Gauge) -> Self {
+        self.gauge = gauge;
+        self
+    }
+
+    pub(super) fn current_combo(&self) -> usize {
         self.current_combo
     }
 
-    fn push_judgement(&mut self, judgement: Option<NoteJudgement>) {
+    /// Applies the effect an event has on the score and the soul gauge, and returns the points it
+    /// scored.
+    fn apply_event(&mut self, event: ScoringEvent, gogo: bool) -> ScoreInt {
+        self.gauge.apply(event);
+        self.score.add(event, self.current_combo, gogo)
+    }
+
+    /// Records the judgement for a don or kat outside of gogo time. None means it was missed.
+    pub(super) fn push_judgement(&mut self, judgement: Option<NoteJudgement>) {
+        self.push_note(judgement, false);
+    }
+
+    fn push_note(&mut self, judgement: Option<NoteJudgement>, gogo: bool) -> ScoreInt {
         self.judgements.push(judgement);
 
         if matches!(
@@ -102,6 +209,91 @@ impl PlayResult {
         } else {
             self.current_combo = 0;
         }
+
+        self.apply_event(ScoringEvent::Note(judgement), gogo)
+    }
+
+    /// Records a don or kat that was hit, and how far off it was. Returns the points it scored.
+    pub(super) fn push_hit(
+        &mut self,
+        judgement: NoteJudgement,
+        offset: f32,
+        gogo: bool,
+    ) -> ScoreInt {
+        self.hit_errors.push(offset);
+        self.push_note(Some(judgement), gogo)
+    }
+
+    /// Records a big note being hit with the other hand too, which scores its points again.
+    pub(super) fn push_big_note_bonus(&mut self, points: ScoreInt) {
+        self.score.add_big_note_bonus(points);
+    }
+
+    /// Records a drumroll or balloon hit, and returns the points it scored.
+    pub(super) fn push_roll_hit(&mut self, time: f32, gogo: bool) -> ScoreInt {
+        self.drumrolls += 1;
+        self.roll_speed.hit(time);
+        self.apply_event(ScoringEvent::RollHit, gogo)
+    }
+
+    /// Records a balloon (or kusudama) that has either been popped or has gone past unfinished.
+    pub(super) fn push_balloon(&mut self, popped: bool, kusudama: bool, gogo: bool) {
+        let event = match (popped, kusudama) {
+            (true, false) => ScoringEvent::BalloonPopped,
+            (true, true) => ScoringEvent::KusudamaPopped,
+            (false, _) => ScoringEvent::BalloonUnfinished,
+        };
+        self.apply_event(event, gogo);
+    }
+
+    /// Whether the player has earned the bonus rally, if this is the end of the song's notes.
+    /// Nobody earns it when the game is playing by itself.
+    fn earns_rally(&self) -> bool {
+        scoring::earns_rally(self.gauge.value())
+            && !self
+                .conditions
+                .as_ref()
+                .is_some_and(|conditions| conditions.autoplay)
+    }
+
+    /// A fresh start on the same chart, scored the same way and under the same conditions, with
+    /// nothing played yet. Autoplay has to mark it again if it's still on.
+    pub(super) fn restarted(&self) -> Self {
+        let mut conditions = self.conditions.clone();
+        if let Some(conditions) = conditions.as_mut() {
+            conditions.autoplay = false;
+        }
+
+        Self {
+            conditions,
+            ..Self::new()
+        }
+        .scored_with(self.score.restarted())
+        .with_gauge(self.gauge.restarted())
+    }
+
+    /// Marks the play as one the game played by itself, at least in part. There's no taking this
+    /// back once it's happened, even if the player carries on by themselves.
+    pub(super) fn mark_autoplay(&mut self) {
+        if let Some(conditions) = self.conditions.as_mut() {
+            conditions.autoplay = true;
+        }
+    }
+
+    pub(super) fn start_rally(&mut self, time: f32) {
+        self.rally = Some(Rally::start(time));
+    }
+
+    /// Whether the bonus rally is going on at the given time.
+    pub(super) fn rally_running(&self, time: f32) -> bool {
+        self.rally.is_some_and(|rally| rally.is_running(time))
+    }
+
+    /// Records a drum hit during the bonus rally.
+    pub(super) fn push_rally_hit(&mut self, time: f32) {
+        if let Some(rally) = self.rally.as_mut() {
+            rally.hit(time);
+        }
     }
 
     fn count_for_judgement(&self, judgement: Option<NoteJudgement>) -> usize {
@@ -131,159 +323,1308 @@ impl PlayResult {
     pub fn max_combo(&self) -> usize {
         self.max_combo
     }
+
+    /// The player's total score, including the roll speed and rally bonuses.
+    pub fn score(&self) -> ScoreInt {
+        self.score.total() + self.roll_speed_bonus() + self.rally.map_or(0, |rally| rally.bonus())
+    }
+
+    /// The player's accuracy so far, from 0 to 1, or None if no notes have been judged yet.
+    pub fn accuracy(&self) -> Option<f32> {
+        scoring::accuracy(&self.judgements)
+    }
+
+    /// How full the soul gauge is, from 0 to [GAUGE_MAX](scoring::GAUGE_MAX).
+    pub fn gauge(&self) -> f32 {
+        self.gauge.value()
+    }
+
+    /// Whether the gauge is full enough to clear the song, if this is the end of it.
+    pub fn cleared(&self) -> bool {
+        scoring::is_cleared(self.gauge.value())
+    }
+
+    /// How long frames took during the song, or None if no frames were recorded.
+    pub fn frame_stats(&self) -> Option<FrameStats> {
+        self.frame_stats
+    }
+
+    /// The conditions the song was played under, if they were recorded.
+    pub fn conditions(&self) -> Option<&PlayConditions> {
+        self.conditions.as_ref()
+    }
+
+    /// The fastest the player rolled during the song, in hits per second.
+    pub fn best_roll_speed(&self) -> f32 {
+        self.roll_speed.best_speed()
+    }
+
+    /// The bonus rally at the end of the song, or None if the player didn't earn one.
+    pub fn rally(&self) -> Option<Rally> {
+        self.rally
+    }
+
+    /// The bonus score awarded for how fast the player rolled.
+    pub fn roll_speed_bonus(&self) -> ScoreInt {
+        let speed = self.best_roll_speed();
+
+        ROLL_SPEED_BONUSES
+            .iter()
+            .rev()
+            .find(|(threshold, _)| speed > *threshold)
+            .map_or(0, |(_, bonus)| *bonus)
+    }
+}
+
+/// How long to wait before starting a song whose first note is at the given time, so the player
+/// gets at least `lead_in` seconds to see it coming. Songs that already have that long before their
+/// first note (e.g. because of a large OFFSET) don't wait at all.
+fn lead_in_before(first_note: Option<f32>, lead_in: f32) -> f32 {
+    first_note.map_or(0.0, |time| (lead_in - time).max(0.0))
+}
+
+/// The speeds a song can be played at, as multiples of its normal speed. Plays at any speed but
+/// 1.0 are practice plays, which don't count towards high scores.
+pub const PLAYBACK_SPEEDS: [f32; 6] = [0.5, 0.75, 1.0, 1.25, 1.5, 2.0];
+
+/// How long after the last note the song ends, in seconds, if the audio hasn't finished by then.
+const SONG_END_GRACE: f32 = 3.0;
+/// How long the song fades out for when it ends before the audio does.
+const SONG_END_FADE: f32 = 1.0;
+
+/// The track to play with the given modifiers: sped up or slowed down, and shuffled.
+pub(super) fn modified_track(chart: &NoteChart, modifiers: PlayModifiers) -> NoteChart {
+    let mut track = chart.at_speed(modifiers.speed);
+    modifiers
+        .note_shuffle
+        .apply(&mut track.notes, modifiers.shuffle_seed);
+    track
+}
+
+/// Returns the timing windows to use for the given difficulty.
+pub(super) fn timing_windows_for(difficulty: usize) -> &'static [f32; 3] {
+    JudgementPreset::for_difficulty(difficulty).timing_windows()
 }
 
 pub struct TaikoMode {
     song_name: String,
+    /// The subtitle shown in the header, if the chart wants it shown during the song.
+    song_subtitle: Option<String>,
+    /// The key the song's data is stored under. See [crate::local_data::LocalData::song].
+    song_key: String,
+    /// Whether the play should be saved to the chart's history when it finishes.
+    save_play: bool,
     // UI Stuff
     background: Sprite,
     // TODO: Give sprites a colour tint
     background_dim: Shape,
     header: Header,
     note_field: NoteField,
+    combo_counter: ComboCounter,
     balloon_display: BalloonDisplay,
+    incoming_note_marker: IncomingNoteMarker,
+    /// Shows the timing windows around the receptacle, if the player wants to see them.
+    timing_window_bands: Option<TimingWindowBands>,
+    /// Shows which drum keys are being hit, if the game is in stream mode.
+    key_input_display: Option<KeyInputDisplay>,
+    section_labels: SectionLabels,
+    rally_display: RallyDisplay,
+    roll_display: RollDisplay,
+    flying_notes: FlyingNotes,
 
+    /// The song's audio, kept so the song can be played again from the start when it's restarted.
+    song_data: StaticSoundData,
     /// A handle to the audio of the song
     song_handle: StaticSoundHandle,
+    /// Retries commands to the song handle that fail, and lets us know if we need to carry on
+    /// without the audio.
+    audio_watchdog: AudioWatchdog,
+    /// The length of the song's audio in seconds, at the speed it's being played at.
+    song_length: f32,
+    /// How fast the song is being played, as a multiple of its normal speed. The chart is sped up
+    /// to match, so everything else (the clock, the judge's timing windows) is in real time.
+    speed: f32,
+    /// How much of the notes can be seen.
+    note_visibility: NoteVisibility,
     // Record the global offset, so we don't need to keep querying the settings
     // This is fine bc the settings will never change mid-song but if that's ever possible, we'd
     // need to update this every time the setting changed.
     global_offset: f32,
+    /// Whether to warn the player about notes that are coming in too fast to see.
+    show_incoming_notes: bool,
+    /// Whether the player can earn the bonus rally at the end of the song.
+    rally_enabled: bool,
+    /// Played when the combo reaches a milestone (see [scoring::is_combo_milestone]).
+    combo_chime: StaticSoundData,
+    hit_sounds: HitSounds,
 
-    /// The instant the song started.
+    /// The instant the clock started, at the start of the lead-in.
     ///
     /// Even though the song handle keeps track of the position through the song, that value is
     /// choppy and using it for the position of the notes will cause the notes to stutter. So we
     /// need to keep track of the time ourselves.
     start_time: Instant,
     started: bool,
+    /// How long the clock runs before the song starts, in seconds, so the first notes can be seen
+    /// coming (see [lead_in_before]).
+    lead_in: f32,
+    /// Whether the song's audio has been started since the clock last started. It waits until
+    /// the lead-in is over.
+    song_playing: bool,
     difficulty: usize,
+    /// The note time the clock is stopped at, if it's waiting for the player to hit a note.
+    halted_at: Option<f32>,
+    /// The state of the tutorial, if this is the tutorial rather than a song.
+    tutorial: Option<Tutorial>,
+    /// The song's measures and the loop the player has set, in practice mode. Nothing is scored or
+    /// saved in practice mode.
+    practice: Option<Practice>,
+    /// Works out the song's waveform for practice mode, until it's ready.
+    waveform_analysis: Option<Receiver<Vec<WaveformBucket>>>,
+    /// The song's waveform once it's been worked out, kept so the strip can be built again.
+    waveform: Vec<WaveformBucket>,
+    /// The strip along the bottom of the screen in practice mode, which shows where the song is
+    /// and where the loop is, and can be clicked or dragged to seek.
+    waveform_strip: Option<WaveformStrip>,
+    /// Where the cursor was the last time the strip was seeked with it, while the mouse button is
+    /// held down.
+    waveform_dragged_at: Option<(f32, f32)>,
+    /// The heat map of the player's mistakes last shown on the strip, so it's only built again
+    /// when it changes.
+    shown_heat: Vec<f32>,
+    /// Whether to say when the player makes the most mistakes, above the strip.
+    show_worst_spot: bool,
+    /// Player 2's side of the game, if this is a 2P battle. Battles aren't saved.
+    versus: Option<Versus>,
+    /// The note time as of the last update.
+    last_note_time: f32,
+    /// Whether the game is paused after recovering from losing the graphics device, waiting for
+    /// the player to be ready to carry on.
+    paused_for_recovery: bool,
+    /// The pause menu, if the player has paused the song. The clock stays stopped until it's
+    /// closed and has finished counting down.
+    pause_menu: Option<PauseMenu>,
 
-    notes: Vec<TaikoModeNote>,
+    /// The chart being played, kept so the notes can be created again if they're lost.
+    chart: NoteChart,
+    /// Whether to show barlines at the start of every measure instead of the chart's own, for
+    /// comparing them. This is a debug option.
+    synthesised_barlines: bool,
+    /// Whether the barlines being shown are the synthesised ones.
+    showing_synthesised_barlines: bool,
     barlines: Vec<TaikoModeBarline>,
 
     // Note scoring/input handling
-    /// The index of the next note to be played
-    next_note_index: usize,
-    /// The percentage the soul gauge is filled
-    soul_gauge: f32,
-    note_judgement_text: JudgementText,
+    /// Judges the player's hits against the notes, which it keeps in order.
+    judge: Judge<TaikoModeNote>,
+    /// Drum hits that haven't been judged yet, with the note time each one was made at.
+    pending_hits: InputQueue,
+    /// Every hit taken from [pending_hits](Self::pending_hits), which is saved as a replay when the
+    /// song's finished.
+    replay: ReplayRecorder,
+    /// Makes the hits from a replay instead of the player, if one is being played back.
+    replay_player: Option<ReplayPlayer>,
+    /// The badge that shows a replay is being played back.
+    replay_badge: Option<Text>,
+    /// Whether the game should play the song by itself. This is a debug option.
+    autoplay: bool,
+    /// Makes the hits while autoplay is on. It's started when autoplay is turned on, from wherever
+    /// the song is at the time.
+    autoplayer: Option<Autoplayer>,
 
     /// An ongoing record of the player's performance.
     /// At the end of the song, this will be passed to the score screen.
     results: PlayResult,
+
+    /// Textures that were loaded just for this song, which are released when the scene exits.
+    song_textures: Vec<&'static str>,
+
+    /// Whether the player tried to close the game and we're asking them if they're sure.
+    confirming_quit: bool,
+    /// Whether the player has confirmed they want to close the game.
+    quit: bool,
 }
 
 impl TaikoMode {
     pub fn new(
         song: &Song,
-        song_data: StaticSoundData,
+        mut assets: SongAssets,
         audio_manager: &mut AudioManager,
         difficulty: usize,
+        modifiers: PlayModifiers,
         renderer: &mut Renderer,
         textures: &mut TextureCache,
     ) -> anyhow::Result<Self> {
-        let bg_texture = textures.get(&renderer.device, &renderer.queue, "song_select_bg.jpg")?;
-        let background = SpriteBuilder::new(bg_texture).build(renderer);
-
-        let background_dim = ShapeBuilder::new()
-            .filled_rectangle(
-                [0., 0.],
-                [1920., 1080.],
-                SolidColour::new([0., 0., 0., 0.6]),
-            )?
-            .build(&renderer.device);
-
-        let mut song_handle = audio_manager.play(song_data)?;
+        let (background, background_dim) = dimmed_background(renderer, textures)?;
+
+        // This is checked before the song starts playing, so a song that can't be played is never
+        // heard
+        let chart_difficulty = song.difficulties[difficulty].as_ref().ok_or_else(|| {
+            anyhow::anyhow!(
+                "the song doesn't have a {} chart",
+                DIFFICULTY_NAMES[difficulty]
+            )
+        })?;
+        let song_textures = assets.upload_textures(renderer, textures);
+        let speed = modifiers.speed;
+        // The shuffled chart is kept, so restarting the song keeps the same notes
+        let track = &modified_track(&chart_difficulty.chart, modifiers);
+
+        // There's no pitch correction, so a slower song sounds lower
+        let song_data = assets.sound_data.with_modified_settings(|settings| {
+            settings.playback_rate(PlaybackRate::Factor(speed as f64))
+        });
+        let song_length = song_data.duration().as_secs_f32() / speed;
+        let mut song_handle = audio_manager.play(song_data.clone())?;
         // We want to start the song once the scene is actually loaded
-        song_handle.pause(Tween::default())?;
+        let mut audio_watchdog = AudioWatchdog::new();
+        audio_watchdog.send(&mut song_handle, PlaybackCommand::Pause);
 
-        let track = &song.difficulties[difficulty]
-            .as_ref()
-            .expect("Difficulty doesn't exist!")
-            .chart;
+        let show_key_inputs = {
+            let visual = &settings().visual;
+            visual.stream_mode && visual.key_input_display
+        };
+        let key_input_display = if show_key_inputs {
+            Some(KeyInputDisplay::new(renderer)?)
+        } else {
+            None
+        };
+
+        // Songs can have their own offset, on top of the global one
+        let note_offset = settings().game.global_note_offset
+            + local_data()
+                .song(&song.audio_filename)
+                .map_or(0.0, |data| data.offset);
+
+        let title = song
+            .display_title(settings().visual.romanised_titles)
+            .to_string();
+        let subtitle = song.subtitle.clone().filter(|_| song.show_subtitle);
+        let replay = ReplayRecorder::new(
+            &song.audio_filename,
+            &title,
+            difficulty,
+            &chart_difficulty.chart.notes,
+        );
 
         Ok(Self {
-            song_name: song.title.clone(),
             background,
             background_dim,
-            header: Header::new(renderer, &song.title)?,
-            note_field: NoteField::new(renderer)?,
+            header: Header::new(renderer, &title, subtitle.as_deref(), 0.0)?,
+            note_field: NoteField::new(renderer, 0.0)?,
+            combo_counter: ComboCounter::new(renderer, 0.0),
             balloon_display: BalloonDisplay::new(textures, renderer)?,
+            incoming_note_marker: IncomingNoteMarker::new(renderer)?,
+            timing_window_bands: settings()
+                .game
+                .show_timing_windows
+                .then(|| TimingWindowBands::new(timing_windows_for(difficulty))),
+            key_input_display,
+            section_labels: SectionLabels::new(renderer, &track.sections),
+            rally_display: RallyDisplay::new(renderer)?,
+            roll_display: RollDisplay::new(renderer)?,
+            flying_notes: FlyingNotes::new(textures, renderer)?,
+            song_name: title,
+            song_subtitle: subtitle,
+            song_key: song.audio_filename.clone(),
+            save_play: true,
+            song_data,
             song_handle,
+            audio_watchdog,
+            song_length,
+            speed,
+            note_visibility: modifiers.note_visibility,
             started: false,
             start_time: Instant::now(),
-            global_offset: SETTINGS.read().unwrap().game.global_note_offset / 1000.0,
+            lead_in: lead_in_before(
+                track.notes.first().map(|note| note.time),
+                settings().game.lead_in(),
+            ),
+            song_playing: false,
+            global_offset: note_offset / 1000.0,
+            show_incoming_notes: settings().game.incoming_note_markers,
+            rally_enabled: settings().game.bonus_rally,
+            combo_chime: combo_chime(),
+            hit_sounds: HitSounds::load(hit_sound_volume(
+                song.se_volume,
+                settings().audio.hit_sound_volume(),
+            ))?,
             difficulty,
-            notes: create_notes(renderer, textures, &track.notes),
-            barlines: create_barlines(renderer, &track.barlines),
-            next_note_index: 0,
-            soul_gauge: 0.0,
-            note_judgement_text: JudgementText::new(renderer),
-            results: PlayResult::new(),
+            halted_at: None,
+            tutorial: None,
+            practice: None,
+            waveform_analysis: None,
+            waveform: Vec::new(),
+            waveform_strip: None,
+            waveform_dragged_at: None,
+            shown_heat: Vec::new(),
+            show_worst_spot: false,
+            versus: None,
+            last_note_time: 0.0,
+            paused_for_recovery: false,
+            pause_menu: None,
+            chart: track.clone(),
+            synthesised_barlines: false,
+            showing_synthesised_barlines: false,
+            barlines: create_barlines(renderer, &track.barlines, BeatScroll::for_chart(track)),
+            judge: Judge::new(
+                create_notes(
+                    renderer,
+                    textures,
+                    &track.notes,
+                    BeatScroll::for_chart(track),
+                ),
+                timing_windows_for(difficulty),
+            ),
+            pending_hits: InputQueue::new(),
+            replay,
+            replay_player: None,
+            replay_badge: None,
+            autoplay: false,
+            autoplayer: None,
+            results: PlayResult::with_conditions(
+                PlayConditions::new(difficulty, note_offset)
+                    .at_speed(speed)
+                    .with_note_visibility(modifiers.note_visibility)
+                    .with_note_shuffle(modifiers.note_shuffle, modifiers.shuffle_seed),
+            )
+            .scored_with(Score::for_difficulty(chart_difficulty))
+            .with_gauge(Gauge::for_chart(difficulty, track)),
+            song_textures,
+            confirming_quit: false,
+            quit: false,
         })
     }
 
+    /// Creates the barlines to show: the chart's own, or ones at the start of every measure if
+    /// the debug option for that is on.
+    fn create_shown_barlines(&mut self, renderer: &mut Renderer) {
+        let barlines = if self.synthesised_barlines {
+            measure_starts(&self.chart.timing, self.chart.end_time)
+        } else {
+            self.chart.barlines.clone()
+        };
+
+        self.barlines = create_barlines(renderer, &barlines, BeatScroll::for_chart(&self.chart));
+        self.showing_synthesised_barlines = self.synthesised_barlines;
+    }
+
+    /// Plays a different track from the one the scene was made with, before the song has started.
+    fn set_track(
+        &mut self,
+        track: NoteChart,
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+    ) {
+        self.lead_in = lead_in_before(
+            track.notes.first().map(|note| note.time),
+            settings().game.lead_in(),
+        );
+        self.results = self
+            .results
+            .restarted()
+            .with_gauge(Gauge::for_chart(self.difficulty, &track));
+        self.judge = Judge::new(
+            create_notes(
+                renderer,
+                textures,
+                &track.notes,
+                BeatScroll::for_chart(&track),
+            ),
+            self.timing_windows(),
+        );
+        self.section_labels = SectionLabels::new(renderer, &track.sections);
+        self.chart = track;
+        self.create_shown_barlines(renderer);
+    }
+
+    /// Stops the play from being saved to the chart's history, e.g. for charts that aren't in the
+    /// song library.
+    pub fn without_saving_play(mut self) -> Self {
+        self.save_play = false;
+        self
+    }
+
+    /// Plays the song in practice mode, where the player can seek through it a measure or a
+    /// section at a time (or by clicking on its waveform) and loop part of it. The incoming note
+    /// markers and timing windows are shown whatever the settings say. The score, soul gauge and
+    /// bonus rally are all turned off, and the play isn't saved.
+    pub fn practice(mut self) -> Self {
+        self.practice = Some(Practice::new(&self.chart));
+        self.show_incoming_notes = true;
+        self.timing_window_bands = Some(TimingWindowBands::new(self.timing_windows()));
+        self.waveform_analysis = Some(spawn_waveform_analysis(&self.song_data));
+        self.save_play = false;
+        self.rally_enabled = false;
+        self
+    }
+
+    /// Makes this a 2P battle, with a second player on their own note field under the first. If the
+    /// chart has a track for each player they play those, and otherwise they both play the same
+    /// one, with the same modifiers. Battles aren't saved and there's no bonus rally.
+    pub fn versus(
+        mut self,
+        song: &Song,
+        modifiers: PlayModifiers,
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+    ) -> anyhow::Result<Self> {
+        let chart_difficulty = song.difficulties[self.difficulty]
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("the song doesn't have this chart any more"))?;
+        // Player 1 has been given the track for playing alone
+        if chart_difficulty.player_charts[Player::Player1.index()].is_some() {
+            let track = modified_track(chart_difficulty.chart_for(Player::Player1), modifiers);
+            self.set_track(track, renderer, textures);
+        }
+        let track = modified_track(chart_difficulty.chart_for(Player::Player2), modifiers);
+
+        // Both players have to see their first notes coming
+        self.lead_in = self.lead_in.max(lead_in_before(
+            track.notes.first().map(|note| note.time),
+            settings().game.lead_in(),
+        ));
+        let results = self
+            .results
+            .restarted()
+            .with_gauge(Gauge::for_chart(self.difficulty, &track));
+
+        self.versus = Some(Versus::new(
+            renderer,
+            textures,
+            track,
+            self.timing_windows(),
+            results,
+        )?);
+        self.save_play = false;
+        self.rally_enabled = false;
+        Ok(self)
+    }
+
+    /// Plays back the given replay, with its hits going in instead of the player's. The replay
+    /// should be of this chart (see [Replay::check_chart]), and the scene should have been made
+    /// with the modifiers it was played with. The play isn't saved.
+    pub fn playing_back(mut self, replay: Replay, renderer: &mut Renderer) -> Self {
+        self.save_play = false;
+        // Whether there's a rally is up to the replay, not the settings
+        self.rally_enabled = false;
+        self.replay_badge = Some(replay_badge(renderer));
+        self.replay_player = Some(ReplayPlayer::new(replay));
+        self.start_playback();
+        self
+    }
+
+    /// Starts the replay being played back from its first hit, with the play result set up the way
+    /// it was when the replay was recorded.
+    fn start_playback(&mut self) {
+        let Some(player) = self.replay_player.as_mut() else {
+            return;
+        };
+
+        player.restart();
+        let replay = player.replay();
+        self.results.conditions = Some(replay.conditions.clone());
+        // None of the notes can be hit by the time the rally starts, so it can start straight away.
+        // Hits only count in it once its start time comes.
+        if let Some(start) = replay.rally_start {
+            self.results.start_rally(start);
+        }
+    }
+
+    /// Creates the tutorial scene, which plays the built in tutorial chart with no music.
+    pub fn tutorial(
+        audio_manager: &mut AudioManager,
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+    ) -> anyhow::Result<Self> {
+        const TUTORIAL_END_TIME: f32 = 2.0;
+
+        let song = tutorial_song();
+        let length = song.difficulties[0]
+            .as_ref()
+            .and_then(|difficulty| difficulty.chart.notes.last())
+            .map_or(0.0, |note| note.time)
+            + TUTORIAL_END_TIME;
+
+        let mut scene = Self::new(
+            &song,
+            SongAssets::with_sound(silence(length))?,
+            audio_manager,
+            0,
+            PlayModifiers::default(),
+            renderer,
+            textures,
+        )?;
+        scene.tutorial = Some(Tutorial::new());
+        // The tutorial is about learning the notes, not timing, so every hit is good
+        scene.judge.judge_every_hit_good();
+        Ok(scene)
+    }
+
     /// Returns what time it is with respect to the notes and global offset.
     fn note_time(&self) -> f32 {
-        self.start_time.elapsed().as_secs_f32() - self.global_offset
+        match self.halted_at {
+            Some(time) => time,
+            None => self.start_time.elapsed().as_secs_f32() - self.lead_in - self.global_offset,
+        }
     }
 
-    /// Returns the timing windows to use for the song's difficulty.
-    fn timing_windows(&self) -> &'static [f32; 3] {
-        match self.difficulty {
-            0 | 1 => &EASY_NORMAL_TIMING,
-            _ => &HARD_EXTREME_TIMING,
+    /// How far into the song the clock is, in real seconds. This is negative during the lead-in.
+    /// It's only the same as the position in the audio if the song is at normal speed.
+    fn song_position(&self) -> f32 {
+        self.note_time() + self.global_offset
+    }
+
+    /// Stops the clock (and the song) at the given note time.
+    fn halt_clock(&mut self, time: f32) {
+        self.halted_at = Some(time);
+        self.song_playing = false;
+        self.audio_watchdog
+            .send(&mut self.song_handle, PlaybackCommand::Pause);
+    }
+
+    /// Starts the clock again from wherever it was stopped.
+    fn resume_clock(&mut self) {
+        if let Some(time) = self.halted_at.take() {
+            let elapsed = time + self.global_offset + self.lead_in;
+            self.start_time = Instant::now() - Duration::from_secs_f32(elapsed.max(0.0));
+            self.start_song_if_due();
+        }
+    }
+
+    /// Starts the song's audio if the clock is running and has got past the lead-in.
+    fn start_song_if_due(&mut self) {
+        let position = self.song_position();
+        if self.song_playing || self.halted_at.is_some() || position < 0.0 {
+            return;
+        }
+
+        // The song might have carried on a bit before it was stopped (e.g. while the renderer was
+        // being recreated, or before the scene was ready), so put it where the clock is
+        self.song_handle
+            .seek_to(position as f64 * self.speed as f64)
+            .or_log("couldn't seek song");
+        self.audio_watchdog
+            .send(&mut self.song_handle, PlaybackCommand::Resume);
+        self.song_playing = true;
+    }
+
+    /// Jumps to the given note time. The notes from there on can be played again, however they were
+    /// played before, and the clock carries on from there if it was running.
+    fn seek(&mut self, ctx: &mut Context, time: f32) {
+        let running = self.halted_at.is_none();
+        self.halt_clock(time);
+
+        self.judge.seek(time);
+        self.last_note_time = time;
+        self.pending_hits.clear();
+        // The autoplayer picks up again from the new time
+        self.autoplayer = None;
+
+        // Like when restarting, effects from elsewhere in the song would be left waiting for the
+        // gameplay clock to get back to them
+        self.create_play_effects(ctx.renderer, ctx.textures)
+            .or_log("couldn't rebuild play effects");
+        ctx.time.seek(time);
+
+        if running {
+            self.resume_clock();
+        }
+    }
+
+    /// Builds the practice mode waveform strip from the song's waveform.
+    fn create_waveform_strip(&self, renderer: &Renderer) -> anyhow::Result<WaveformStrip> {
+        Ok(WaveformStrip::new(
+            renderer,
+            &self.waveform,
+            self.song_length,
+            PRACTICE_WAVEFORM,
+            PRACTICE_WAVEFORM_SIZE,
+        )?)
+    }
+
+    /// Where to seek to (in note time) if the player has clicked on the waveform strip, or dragged
+    /// the cursor along it since the last update.
+    fn waveform_seek(&mut self, mouse: &MouseState) -> Option<f32> {
+        let cursor = (mouse.cursor_pos()).filter(|_| mouse.is_pressed(MouseButton::Left));
+        // Holding the cursor still doesn't keep seeking back to the same place
+        let moved = cursor != self.waveform_dragged_at;
+        self.waveform_dragged_at = cursor;
+
+        // The strip is in song time, which the note offset is taken off to get note time
+        let time = self.waveform_strip.as_ref()?.time_at(cursor?)?;
+        moved.then_some(time - self.global_offset)
+    }
+
+    /// In practice mode, remembers a miss or bad at the given note time, so it shows up on the
+    /// strip's heat map.
+    fn record_failure(&mut self, time: f32) {
+        if let Some(practice) = self.practice.as_mut() {
+            practice.record_failure(time);
+        }
+    }
+
+    /// In practice mode, seeks a measure either way when the player presses left or right (or to
+    /// wherever they click on the waveform), sets the loop points, and goes back round the loop
+    /// when the song gets to the end of it. The strip shows where the player has been making
+    /// mistakes, and when the worst spot is if they've asked to see it.
+    fn update_practice(&mut self, ctx: &mut Context) {
+        if self.pause_menu.is_some() || self.paused_for_recovery || self.practice.is_none() {
+            return;
+        }
+
+        let analysed =
+            (self.waveform_analysis.as_ref()).and_then(|analysis| analysis.try_recv().ok());
+        if let Some(waveform) = analysed {
+            self.waveform_analysis = None;
+            self.waveform = waveform;
+            self.waveform_strip = self
+                .create_waveform_strip(ctx.renderer)
+                .or_log("couldn't build waveform strip");
+        }
+
+        let time = self.note_time();
+        let waveform_seek = self.waveform_seek(ctx.mouse);
+        let Some(practice) = self.practice.as_mut() else {
+            return;
+        };
+
+        // The player might have put a drum on one of these keys
+        let pressed = |code| {
+            let key = PhysicalKey::Code(code);
+            ctx.keyboard.is_just_pressed(key)
+                && settings().game.key_mappings.drum_input(key).is_none()
+        };
+
+        if pressed(KeyCode::KeyL) {
+            practice.mark_loop(time);
+        }
+
+        let toggled_worst_spot = pressed(KeyCode::KeyH);
+        if toggled_worst_spot {
+            self.show_worst_spot = !self.show_worst_spot;
+        }
+
+        let seek_to = if pressed(KeyCode::ArrowLeft) {
+            practice.previous_measure(time)
+        } else if pressed(KeyCode::ArrowRight) {
+            practice.next_measure(time)
+        } else if pressed(KeyCode::PageUp) {
+            practice.previous_section(time)
+        } else if pressed(KeyCode::PageDown) {
+            practice.next_section(time)
+        } else if waveform_seek.is_some() {
+            waveform_seek
+        } else {
+            practice.rewind_to(self.last_note_time, time)
+        };
+
+        // The strip is in song time, so the loop and mistakes are moved by the note offset to match
+        if let Some(strip) = self.waveform_strip.as_mut() {
+            let offset = self.global_offset;
+            let bounds = practice.loop_bounds();
+            strip.set_loop(
+                bounds.map(|(start, end)| (start + offset, end + offset)),
+                ctx.renderer,
+            );
+
+            let failures = practice.failures().map(|time| time + offset);
+            let heat = heat_buckets(failures, self.song_length, HEAT_BUCKETS);
+            if heat != self.shown_heat || toggled_worst_spot {
+                strip
+                    .set_heat(&heat, ctx.renderer)
+                    .or_log("couldn't build heat map");
+                let worst_spot = (self.show_worst_spot)
+                    .then(|| worst_bucket_time(&heat, self.song_length))
+                    .flatten();
+                strip.set_worst_label(worst_spot, ctx.renderer);
+                self.shown_heat = heat;
+            }
+        }
+
+        if let Some(seek_to) = seek_to {
+            self.seek(ctx, seek_to);
+        }
+
+        if let Some(strip) = &self.waveform_strip {
+            strip.set_playhead(self.song_position(), ctx.renderer);
+        }
+    }
+
+    /// Stops the song where it is and opens the pause menu.
+    fn pause(&mut self) {
+        // The tutorial might already be stopped waiting for a note, in which case it stays there
+        if self.halted_at.is_none() {
+            self.halted_at = Some(self.note_time());
+        }
+        self.song_playing = false;
+        self.audio_watchdog
+            .send(&mut self.song_handle, PlaybackCommand::FadeOut);
+        self.pause_menu = Some(PauseMenu::new());
+    }
+
+    /// Opens the pause menu when escape is pressed, and does whatever the player chooses from it.
+    /// Returns the transition to make if they chose to quit.
+    fn update_pause_menu(&mut self, ctx: &mut Context) -> Option<StateTransition> {
+        let escape = ctx
+            .keyboard
+            .is_just_pressed(PhysicalKey::Code(KeyCode::Escape));
+        let now = ctx.time.ui_time();
+
+        let Some(menu) = self.pause_menu.as_mut() else {
+            if escape && !self.paused_for_recovery && !self.confirming_quit {
+                self.pause();
+            }
+
+            return None;
+        };
+
+        // Escape backs out of the menu, or back into it if it's already counting down
+        if escape {
+            if menu.is_counting_down() {
+                menu.cancel_countdown();
+            } else {
+                menu.start_countdown(now);
+            }
+        }
+
+        match menu.take_choice() {
+            Some(PauseOption::Resume) => menu.start_countdown(now),
+            Some(PauseOption::Restart) => {
+                menu.start_countdown(now);
+                self.restart(ctx).or_log("couldn't restart the song");
+            }
+            Some(PauseOption::Autoplay) => self.autoplay = !self.autoplay,
+            Some(PauseOption::Quit) => {
+                self.song_handle
+                    .stop(Default::default())
+                    .or_log("couldn't stop song");
+                ctx.time.pause();
+                return Some(StateTransition::Pop);
+            }
+            None => {}
+        }
+
+        if self
+            .pause_menu
+            .as_mut()
+            .is_some_and(|menu| menu.update(now))
+        {
+            self.pause_menu = None;
+            self.resume_clock();
+        }
+
+        None
+    }
+
+    /// Takes the song back to the start, with every note to be played again and nothing scored.
+    /// The clock is left stopped at the start, so it can be started again when the player's ready.
+    fn restart(&mut self, ctx: &mut Context) -> anyhow::Result<()> {
+        // The song might have finished already (e.g. during the bonus rally), and a finished sound
+        // can't be started again, so a new one is played from the start. The sound data is
+        // shared, so nothing is loaded again.
+        let song_handle = ctx.audio.play(self.song_data.clone())?;
+        std::mem::replace(&mut self.song_handle, song_handle)
+            .stop(Tween::default())
+            .or_log("couldn't stop song");
+        self.audio_watchdog
+            .send(&mut self.song_handle, PlaybackCommand::Pause);
+        self.song_playing = false;
+
+        // The lead-in comes round again too
+        let start = -self.lead_in - self.global_offset;
+        self.halted_at = Some(start);
+        self.last_note_time = start;
+        self.pending_hits.clear();
+        self.replay.clear();
+        self.autoplayer = None;
+        self.results = self.results.restarted();
+        self.start_playback();
+
+        self.judge = Judge::new(
+            create_notes(
+                ctx.renderer,
+                ctx.textures,
+                &self.chart.notes,
+                BeatScroll::for_chart(&self.chart),
+            ),
+            self.timing_windows(),
+        );
+        if self.tutorial.is_some() {
+            self.tutorial = Some(Tutorial::new());
+            self.judge.judge_every_hit_good();
+        }
+        self.create_shown_barlines(ctx.renderer);
+        if let Some(versus) = self.versus.as_mut() {
+            versus.restart(ctx.renderer, ctx.textures)?;
+        }
+
+        // Effects that were going on later in the song would be stuck waiting for the gameplay
+        // clock to get back to them, so they all start afresh
+        self.create_play_effects(ctx.renderer, ctx.textures)?;
+        if self.key_input_display.is_some() {
+            self.key_input_display = Some(KeyInputDisplay::new(ctx.renderer)?);
+        }
+
+        ctx.time.seek(start);
+        ctx.frame_times.clear();
+        Ok(())
+    }
+
+    /// Starts the song again from the beginning straight away, without counting down.
+    fn quick_restart(&mut self, ctx: &mut Context) {
+        self.pause_menu = None;
+        self.restart(ctx).or_log("couldn't restart the song");
+        self.resume_clock();
+    }
+
+    /// Builds the parts of the UI that animate as the song is played.
+    fn create_play_effects(
+        &mut self,
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+    ) -> anyhow::Result<()> {
+        self.note_field = NoteField::new(renderer, 0.0)?;
+        self.combo_counter = ComboCounter::new(renderer, 0.0);
+        self.combo_counter
+            .set_combo(self.results.current_combo(), renderer);
+        self.balloon_display = BalloonDisplay::new(textures, renderer)?;
+        self.section_labels = SectionLabels::new(renderer, &self.chart.sections);
+        self.rally_display = RallyDisplay::new(renderer)?;
+        self.roll_display = RollDisplay::new(renderer)?;
+        self.flying_notes = FlyingNotes::new(textures, renderer)?;
+        Ok(())
+    }
+
+    /// While autoplay is on, hits the drum for every hit that's due, just like the player's own
+    /// hits go in.
+    fn queue_autoplay_hits(&mut self, ctx: &mut Context) {
+        // A replay already has every hit it needs
+        if !self.autoplay || self.replay_player.is_some() {
+            self.autoplayer = None;
+            return;
+        }
+
+        let time = self.note_time();
+        let autoplayer = self.autoplayer.get_or_insert_with(|| {
+            self.results.mark_autoplay();
+            Autoplayer::new(&self.chart.notes, self.judge.timing_windows(), time)
+        });
+
+        for &(input, hit_time) in autoplayer.hits_until(time) {
+            self.hit_sounds.play(input, ctx.audio);
+            if let Some(display) = self.key_input_display.as_mut() {
+                display.press(input, hit_time, ctx.renderer);
+            }
+
+            self.pending_hits.push(input, hit_time);
+        }
+    }
+
+    /// While a replay is being played back, hits the drum for every recorded hit that's due.
+    fn queue_replay_hits(&mut self, ctx: &mut Context) {
+        let time = self.note_time();
+        let Some(player) = self.replay_player.as_mut() else {
+            return;
+        };
+
+        for hit in player.hits_until(time) {
+            self.hit_sounds.play(hit.input, ctx.audio);
+            if let Some(display) = self.key_input_display.as_mut() {
+                display.press(hit.input, hit.time, ctx.renderer);
+            }
+
+            self.pending_hits.push(hit.input, hit.time);
         }
     }
 
-    /// Considers the next note to have been missed. Updates the index of the next note, and adds a
-    /// miss to the play result if appropriate.
-    fn skip_next_note(&mut self) {
-        if let Some(note) = self.notes.get(self.next_note_index) {
-            self.next_note_index += 1;
+    /// Judges the drum hits made since the last update, each at the time it was made.
+    fn judge_pending_hits(&mut self, ctx: &mut Context) {
+        while let Some((input, time)) = self.pending_hits.pop() {
+            self.replay.record(input, time);
+
+            // Every hit counts in the rally, and there aren't any notes left to hit anyway
+            if self.results.rally_running(time) {
+                self.results.push_rally_hit(time);
+                if let Some(rally) = self.results.rally() {
+                    self.rally_display.hit(&rally, time, ctx.renderer);
+                }
+
+                continue;
+            }
+
+            let misses = self.results.misses();
+            match self.judge.judge_hit(input, time, &mut self.results) {
+                HitOutcome::Note { judgement, kind } => {
+                    self.note_field.judge(judgement, ctx.time.gameplay_time());
+                    if judgement != NoteJudgement::Bad {
+                        self.flying_notes.launch(kind, ctx.time.gameplay_time());
+                    } else {
+                        self.record_failure(time);
+                    }
+
+                    // Every hit adds one to the combo at most, so it can't skip over a milestone
+                    if judgement != NoteJudgement::Bad
+                        && scoring::is_combo_milestone(self.results.current_combo())
+                    {
+                        ctx.audio
+                            .play(self.combo_chime.clone())
+                            .or_log("couldn't play combo chime");
+                    }
+                }
+                HitOutcome::BalloonHit {
+                    hits_left,
+                    hit_target,
+                    kusudama,
+                } => self
+                    .balloon_display
+                    .hit(hits_left, hit_target, kusudama, time, ctx.renderer),
+                HitOutcome::RollHit { roll_note, hits } => {
+                    self.roll_display.hit(roll_note, hits, time, ctx.renderer)
+                }
+                HitOutcome::BigNoteBonus | HitOutcome::Nothing => {}
+            }
+
+            // Hitting a note can miss the ones before it that weren't hit
+            if self.results.misses() > misses {
+                self.record_failure(time);
+            }
 
-            if note.is_don_or_kat() {
-                self.results.push_judgement(None);
-            } else if matches!(note.note, NoteInner::Balloon { .. }) {
+            if self.judge.take_balloon_missed() {
                 self.balloon_display.discard();
             }
         }
     }
+
+    /// In the tutorial, stops the clock when a note the player has to hit reaches the receptacle,
+    /// and starts it again once they've hit it.
+    fn update_tutorial_clock(&mut self) {
+        if self.paused_for_recovery || self.pause_menu.is_some() {
+            return;
+        }
+
+        let time = self.note_time();
+        let Some(tutorial) = self.tutorial.as_mut() else {
+            return;
+        };
+
+        tutorial.update(time);
+        let next_note = self.judge.next_note().map(TaikoModeNote::judged);
+
+        match self.halted_at {
+            Some(time) => {
+                if next_note.is_none_or(|note| note.time() > time) {
+                    self.resume_clock();
+                }
+            }
+            None => {
+                let Some(note) = next_note else {
+                    return;
+                };
+
+                let must_hit = note.is_don_or_kat() || note.is_balloon();
+                if must_hit && tutorial.waits_for(note.time()) && time >= note.time() {
+                    self.halt_clock(note.time());
+                }
+            }
+        }
+    }
+
+    /// Returns the timing windows to use for the song's difficulty.
+    fn timing_windows(&self) -> &'static [f32; 3] {
+        self.judge.timing_windows()
+    }
+
+    /// Saves the replay of the play that's just finished.
+    fn save_replay(&self) {
+        let Some(replay) = self.replay.finish(&self.results) else {
+            return;
+        };
+
+        match replay.save_in(paths().replays_dir()) {
+            Ok(path) => log::info!("saved replay to {}", path.display()),
+            Err(e) => log::error!("couldn't save replay: {e}"),
+        }
+    }
+
+    /// Whether the song is over: either every note has gone by and [SONG_END_GRACE] seconds have
+    /// passed since the last one, or the song has finished playing.
+    ///
+    /// Usually we can just ask the audio handle whether it's finished, but if the audio couldn't be
+    /// started we have to work it out with our own clock instead.
+    fn song_finished(&self) -> bool {
+        // In a battle, both players' notes have to have gone by
+        let last_note = (self.chart.notes.last().map(|note| note.time))
+            .into_iter()
+            .chain(self.versus.as_ref().and_then(Versus::last_note_time))
+            .fold(0.0, f32::max);
+        let judged = self.judge.finished() && self.versus.as_ref().is_none_or(Versus::finished);
+        if judged && self.note_time() >= last_note + SONG_END_GRACE {
+            return true;
+        }
+
+        if self.audio_watchdog.is_degraded() {
+            self.note_time() + self.global_offset >= self.song_length
+        } else {
+            self.song_handle.state() == PlaybackState::Stopped
+        }
+    }
 }
 
 impl GameState for TaikoMode {
-    fn update(&mut self, ctx: &mut Context, delta_time: f32) -> StateTransition {
+    fn update(&mut self, ctx: &mut Context, _delta_time: f32) -> StateTransition {
+        self.audio_watchdog.update(&mut self.song_handle);
+
+        if self.quit {
+            return StateTransition::Exit;
+        }
+
         if !self.started {
-            self.song_handle.resume(Default::default()).unwrap();
+            // The clock starts now rather than when the scene was made, since loading it can take
+            // a while and the song would start in the past otherwise. The song itself waits for
+            // the lead-in.
             self.started = true;
             self.start_time = Instant::now();
-        } else if self.song_handle.state() == PlaybackState::Stopped {
+            ctx.time.resume();
+            ctx.frame_times.clear();
+        } else if self.song_finished() && !self.results.rally_running(self.note_time()) {
+            ctx.time.pause();
+            // The song might still have an outro to go, which shouldn't carry on over the results
+            self.song_handle
+                .stop(Tween {
+                    duration: Duration::from_secs_f32(SONG_END_FADE),
+                    ..Default::default()
+                })
+                .or_log("couldn't stop song");
+
+            if self.tutorial.is_some() {
+                local_data_mut().stats.tutorial_completed = true;
+                if let Err(e) = save_local_data() {
+                    log::error!("couldn't save local data: {e}");
+                }
+
+                return StateTransition::Pop;
+            }
+
+            // There's no score to show for a practice
+            if self.practice.is_some() {
+                return StateTransition::Pop;
+            }
+
+            self.results.frame_stats = ctx.frame_times.stats();
+            if let Some(player) = &self.replay_player {
+                let recorded = player.replay().score;
+                if self.results.score() != recorded {
+                    log::warn!(
+                        "the replay scored {recorded} when it was recorded, but {} played back",
+                        self.results.score()
+                    );
+                }
+            }
+            if let Some(versus) = &self.versus {
+                return StateTransition::Swap(Box::new(ScoreScreen::versus(
+                    ctx,
+                    self.song_name.clone(),
+                    self.results.clone(),
+                    versus.results().clone(),
+                )));
+            }
+            if self.save_play {
+                self.save_replay();
+            }
             return StateTransition::Swap(Box::new(ScoreScreen::new(
                 ctx,
                 self.song_name.clone(),
+                self.save_play.then_some(self.song_key.as_str()),
+                self.difficulty,
                 self.results.clone(),
             )));
         }
 
-        self.note_judgement_text.update(ctx.renderer);
-        self.balloon_display.update(delta_time);
+        // This comes after the results are shown, so a song that's just finished isn't started
+        // again underneath them
+        let quick_restart = settings().game.key_mappings.quick_restart_key();
+        if quick_restart.is_some_and(|key| ctx.keyboard.is_just_pressed(key))
+            && !self.paused_for_recovery
+            && !self.confirming_quit
+        {
+            self.quick_restart(ctx);
+        }
+
+        if let Some(transition) = self.update_pause_menu(ctx) {
+            return transition;
+        }
+
+        self.update_practice(ctx);
+        self.start_song_if_due();
+
+        self.queue_autoplay_hits(ctx);
+        self.queue_replay_hits(ctx);
+        self.judge_pending_hits(ctx);
+        self.update_tutorial_clock();
+
+        // Gameplay effects follow the note clock
+        ctx.time.seek(self.note_time());
+
+        if self.synthesised_barlines != self.showing_synthesised_barlines {
+            self.create_shown_barlines(ctx.renderer);
+        }
+
+        self.note_field
+            .update(ctx.renderer, ctx.time.gameplay_time());
+        self.flying_notes
+            .update(ctx.renderer, ctx.time.gameplay_time());
+        self.balloon_display
+            .update(ctx.renderer, ctx.time.gameplay_time());
+        self.header
+            .set_accuracy(self.results.accuracy(), ctx.renderer);
+        if self.practice.is_none() {
+            self.header
+                .set_gauge(self.results.gauge(), ctx.renderer)
+                .or_log("couldn't build soul gauge");
+            self.header.set_score(self.results.score(), ctx.renderer);
+        }
+        self.combo_counter
+            .set_combo(self.results.current_combo(), ctx.renderer);
 
         let time = self.note_time();
-        // Advance our position in the list of notes as far as we can go
-        while let Some(note) = self.notes.get(self.next_note_index) {
-            if note.is_hittable(time, self.timing_windows()) {
-                break;
-            }
+        self.last_note_time = time;
 
-            self.skip_next_note();
+        if let Some(versus) = self.versus.as_mut() {
+            versus.update(ctx.renderer, time, ctx.time.gameplay_time());
         }
 
-        if ctx.keyboard.is_pressed(PhysicalKey::Code(KeyCode::Escape)) {
-            self.song_handle.stop(Default::default()).unwrap();
-            StateTransition::Pop
-        } else {
-            StateTransition::Continue
+        if let Some(display) = self.key_input_display.as_mut() {
+            display.update(time);
+        }
+
+        self.section_labels.update(ctx.renderer, time);
+
+        let misses = self.results.misses();
+        self.judge.advance(time, &mut self.results);
+        if self.results.misses() > misses {
+            self.record_failure(time);
+        }
+        if self.judge.take_balloon_missed() {
+            self.balloon_display.discard();
+        }
+
+        let rolling = self
+            .judge
+            .next_note()
+            .is_some_and(|note| note.judged().is_active_roll(time));
+        self.roll_display.update(ctx.renderer, time, rolling);
+
+        // Once the last note has gone by, a cleared gauge earns the bonus rally. The tutorial is
+        // just for learning, so there's no rally there.
+        if self.rally_enabled
+            && self.tutorial.is_none()
+            && self.judge.finished()
+            && self.results.rally().is_none()
+            && self.results.earns_rally()
+        {
+            self.results.start_rally(time);
+        }
+
+        self.rally_display
+            .update(ctx.renderer, time, self.results.rally_running(time));
+
+        // The bands should match the speed of the notes that are about to reach the receptacle
+        if let (Some(bands), Some(note)) =
+            (self.timing_window_bands.as_mut(), self.judge.next_note())
+        {
+            bands
+                .update(ctx.renderer, note.scroll_speed())
+                .or_log("couldn't build timing window bands");
+        }
+
+        StateTransition::Continue
+    }
+
+    fn debug_ui(&mut self, ctx: egui::Context, _audio: &mut AudioManager) {
+        if self.confirming_quit {
+            egui::Window::new("quit")
+                .title_bar(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(&ctx, |ui| {
+                    ui.label(RichText::new("Quit without saving?").size(25.0));
+
+                    ui.horizontal(|ui| {
+                        if ui.button(RichText::new("Quit").size(20.0)).clicked() {
+                            self.quit = true;
+                        }
+
+                        if ui.button(RichText::new("Cancel").size(20.0)).clicked() {
+                            self.confirming_quit = false;
+                        }
+                    });
+                });
+        }
+
+        if let Some(menu) = self.pause_menu.as_mut() {
+            menu.ui(&ctx, self.autoplay);
+        }
+
+        if let Some(tutorial) = &self.tutorial {
+            egui::Area::new("tutorial".into())
+                .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -150.0])
+                .show(&ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.label(RichText::new(tutorial.step().text).size(28.0));
+
+                        if self.halted_at.is_some() {
+                            ui.label(
+                                RichText::new("Waiting for you to hit the note...").size(20.0),
+                            );
+                        }
+                    });
+                });
+        }
+
+        if self.paused_for_recovery {
+            egui::Area::new("recovery pause".into())
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(&ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.label(
+                            RichText::new("The graphics driver stopped responding.").size(28.0),
+                        );
+                        ui.label(RichText::new("Press a drum key to carry on").size(20.0));
+                    });
+                });
+        }
+
+        if cfg!(debug_assertions) && !settings().visual.stream_mode && self.tutorial.is_none() {
+            egui::Area::new("debug options".into())
+                .fixed_pos(egui::pos2(20.0, 900.0))
+                .show(&ctx, |ui| {
+                    ui.checkbox(&mut self.synthesised_barlines, "Synthesised barlines");
+                    ui.checkbox(&mut self.autoplay, "Autoplay");
+                });
+
+            // Lyrics aren't drawn properly yet, but they can be checked here
+            if let Some(lyric) = self.chart.lyric_at(self.last_note_time) {
+                egui::Area::new("lyric debug".into())
+                    .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -100.0])
+                    .show(&ctx, |ui| {
+                        egui::Frame::popup(ui.style()).show(ui, |ui| {
+                            ui.label(RichText::new(lyric).size(24.0));
+                        });
+                    });
+            }
+        }
+
+        if self.audio_watchdog.is_degraded() {
+            egui::Area::new("audio warning".into())
+                .fixed_pos(egui::pos2(20.0, 20.0))
+                .show(&ctx, |ui| {
+                    ui.label(
+                        egui::RichText::new("Audio playback failed! Notes may be out of sync.")
+                            .color(egui::Color32::from_rgb(255, 80, 80))
+                            .size(20.0),
+                    );
+                });
         }
     }
 
@@ -291,109 +1632,451 @@ impl GameState for TaikoMode {
         // Update the positions of all the notes that are currently visible.
         let time = self.note_time();
 
-        let on_screen_notes = self.notes.iter_mut().filter(|note| note.visible(time));
+        let on_screen_notes = (self.judge.notes_mut().iter_mut()).filter(|note| note.visible(time));
 
         for note in on_screen_notes {
-            note.update_position(ctx.renderer, time);
+            note.update_position(ctx.renderer, time, self.note_visibility, 0.0);
         }
 
-        let on_screen_barlines = self.barlines.iter_mut().filter(|barline| {
-            let pos = x_position_of_note(time, barline.time(), barline.scroll_speed());
-            (0.0..1920.0).contains(&pos)
-        });
+        let on_screen_barlines = self
+            .barlines
+            .iter_mut()
+            .filter(|barline| barline.visible(time));
 
         for barline in on_screen_barlines {
-            barline.update_position(ctx.renderer, time);
+            barline.update_position(ctx.renderer, time, 0.0);
         }
 
         ctx.render(&self.background);
         ctx.render(&self.background_dim);
-        self.header.render(ctx);
+        self.header.render(ctx, self.practice.is_none());
+        if let Some(badge) = &self.replay_badge {
+            ctx.render(badge);
+        }
 
-        let notes = self.notes.iter().filter(|note| note.visible(time));
+        let notes = self.judge.notes().iter().filter(|note| note.visible(time));
 
-        let barlines = self.barlines.iter().filter(|barline| {
-            let pos = x_position_of_note(time, barline.time(), barline.scroll_speed());
-            // TODO: another hardcoded resolution to get rid of
-            (0.0..190.0).contains(&pos)
-        });
+        let barlines = self.barlines.iter().filter(|barline| barline.visible(time));
+
+        self.note_field.render(
+            ctx,
+            notes,
+            barlines,
+            self.timing_window_bands.as_ref(),
+            self.chart.is_gogo(time),
+        );
+        ctx.render(&self.flying_notes);
+        ctx.render(&self.combo_counter);
+
+        if self.show_incoming_notes {
+            let incoming =
+                next_incoming_note(self.judge.notes(), self.judge.next_note_index(), time);
+            self.incoming_note_marker.render(ctx, incoming);
+        }
 
-        self.note_field.render(ctx, notes, barlines);
-        ctx.render(&self.note_judgement_text);
+        ctx.render(&self.section_labels);
         ctx.render(&self.balloon_display);
+        ctx.render(&self.roll_display);
+        ctx.render(&self.rally_display);
+
+        if let Some(display) = &self.key_input_display {
+            display.render(ctx);
+        }
+
+        if let Some(strip) = &self.waveform_strip {
+            ctx.render(strip);
+        }
+
+        if let Some(versus) = self.versus.as_mut() {
+            versus.render(ctx, time, self.note_visibility);
+        }
     }
 
     fn handle_event(&mut self, ctx: &mut Context, event: &WindowEvent) {
         // We handle the note input keyboard events the moment they are received for extra accuracy
         if let &WindowEvent::KeyboardInput { event, .. } = &event {
-            let mut note_index = self.next_note_index;
             let key = event.physical_key;
 
             // Keys have this annoying tendency to repeat presses when held down,
             // so we gotta ensure it's not being held down.
             let pressed = event.state == ElementState::Pressed && !ctx.keyboard.is_pressed(key);
 
-            if settings().key_is_don_or_kat(key) && pressed {
-                let time = self.note_time();
-                let timing_windows = self.timing_windows();
-
-                // We now have to go through all the notes starting from the next one, and see if
-                // any of them react to this keypress. If any of them react, or any of them are too
-                // far away to react, then we stop.
-                loop {
-                    // If there's no next note, we don't need to react.
-                    let Some(next_note) = self.notes.get_mut(note_index) else {
-                        break;
-                    };
-
-                    let reaction = next_note.receive_keypress(key, time, timing_windows);
-                    match reaction {
-                        // If it's the wrong colour, we'll keep checking to see if there's
-                        // a note of the right colour in scope.
-                        NoteKeypressReaction::WrongColour => {}
-
-                        NoteKeypressReaction::TooEarly => {
-                            // Now we're only looking at notes that are unhittable, so stop here.
-                            break;
-                        }
-                        NoteKeypressReaction::Hit { offset } => {
-                            let judgement =
-                                NoteJudgement::from_offset(offset, self.timing_windows()).unwrap();
-                            self.note_judgement_text.display_judgement(judgement);
+            let input = settings().game.key_mappings.drum_input(key);
+            // Player 2's keys only count if they aren't player 1's too
+            let player_2_input = match (&self.versus, input) {
+                (Some(_), None) => Versus::drum_input(key),
+                _ => None,
+            };
 
-                            self.results.push_judgement(Some(judgement));
-                            self.results.hit_errors.push(offset);
+            // While the song's paused, the drum works the pause menu instead
+            if let Some(menu) = self.pause_menu.as_mut() {
+                if let (Some(input), true) = (input, pressed) {
+                    menu.press(input);
+                }
 
-                            self.next_note_index = note_index + 1;
+                return;
+            }
 
-                            // Ensure you only ever hit one note at a time
-                            break;
-                        }
-                        NoteKeypressReaction::Drumroll { .. } => {
-                            self.results.drumrolls += 1;
-                            break;
-                        }
-                        NoteKeypressReaction::BalloonRoll {
-                            hits_left,
-                            hit_target,
-                        } => {
-                            self.results.drumrolls += 1;
-                            self.balloon_display
-                                .hit(hits_left, hit_target, &mut ctx.renderer);
-
-                            if hits_left == 0 {
-                                self.next_note_index = note_index + 1;
-                            }
-                            break;
-                        }
-                        NoteKeypressReaction::TooLate => {
-                            self.skip_next_note();
-                        }
-                    }
+            // The drum always sounds, whether or not it hits anything. While a replay is played
+            // back, only its own hits do.
+            if let (Some(input), true, None) = (input, pressed, &self.replay_player) {
+                self.hit_sounds.play(input, ctx.audio);
+            }
+            if let (Some(input), true) = (player_2_input, pressed) {
+                self.hit_sounds.play(input, ctx.audio);
+            }
+
+            // The press that ends the pause is just the player saying they're ready
+            if self.paused_for_recovery {
+                if input.is_some() && pressed {
+                    self.paused_for_recovery = false;
+                    self.resume_clock();
+                }
+
+                return;
+            }
+
+            if self.replay_player.is_some() {
+                return;
+            }
 
-                    note_index += 1;
+            if let (Some(input), true) = (input, pressed) {
+                let time = self.note_time();
+
+                if let Some(display) = self.key_input_display.as_mut() {
+                    display.press(input, time, ctx.renderer);
+                }
+
+                self.pending_hits.push(input, time);
+            }
+
+            if let (Some(input), true) = (player_2_input, pressed) {
+                let time = self.note_time();
+                if let Some(versus) = self.versus.as_mut() {
+                    versus.hit(input, time);
                 }
             }
         }
     }
+
+    fn on_exit(&mut self, ctx: &mut Context) {
+        for texture in self.song_textures.drain(..) {
+            ctx.textures.release(texture);
+        }
+    }
+
+    fn control_hints(&self) -> Vec<(Action, &str)> {
+        if self.pause_menu.is_some() {
+            return vec![
+                (Action::Kat, "Move"),
+                (Action::Don, "Choose"),
+                (Action::Back, "Resume"),
+            ];
+        }
+
+        let mut hints = vec![
+            (Action::Don, "Don"),
+            (Action::Kat, "Kat"),
+            (Action::Back, "Pause"),
+            (Action::Restart, "Restart"),
+        ];
+
+        if let Some(practice) = &self.practice {
+            let mark = match practice.next_mark() {
+                LoopMark::Start => "Loop from here",
+                LoopMark::End => "Loop to here",
+                LoopMark::Clear => "Clear loop",
+            };
+            let worst_spot = if self.show_worst_spot {
+                "Hide worst spot"
+            } else {
+                "Show worst spot"
+            };
+            hints.extend([
+                (Action::Seek, "Seek"),
+                (Action::SeekSection, "Seek section"),
+                (Action::Loop, mark),
+                (Action::WorstSpot, worst_spot),
+            ]);
+        }
+
+        hints
+    }
+
+    fn checks_draw_budgets(&self) -> bool {
+        true
+    }
+
+    fn ducks_music(&self) -> bool {
+        self.confirming_quit || self.paused_for_recovery || self.pause_menu.is_some()
+    }
+
+    fn accepts_dropped_charts(&self) -> bool {
+        // Opening a chart in the middle of a song would leave the song playing underneath it
+        false
+    }
+
+    fn close_requested(&mut self) -> CloseResponse {
+        // The play hasn't finished, so make sure the player knows it won't be saved
+        self.confirming_quit = true;
+        CloseResponse::Veto
+    }
+
+    fn recreate_gpu_resources(
+        &mut self,
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+    ) -> anyhow::Result<()> {
+        // Stop where the player last saw the notes, and wait for them to be ready again. The
+        // tutorial might already be stopped waiting for a note, in which case it stays there. If
+        // the game's paused, the pause menu already counts down before carrying on.
+        if self.pause_menu.is_none() {
+            if self.halted_at.is_none() {
+                self.halt_clock(self.last_note_time);
+            }
+            self.paused_for_recovery = true;
+        }
+
+        (self.background, self.background_dim) = dimmed_background(renderer, textures)?;
+        self.header = Header::new(
+            renderer,
+            &self.song_name,
+            self.song_subtitle.as_deref(),
+            0.0,
+        )?;
+        self.create_play_effects(renderer, textures)?;
+        self.incoming_note_marker = IncomingNoteMarker::new(renderer)?;
+        if self.timing_window_bands.is_some() {
+            self.timing_window_bands = Some(TimingWindowBands::new(self.timing_windows()));
+        }
+        if let Some(display) = self.key_input_display.as_mut() {
+            display.recreate(renderer)?;
+        }
+        if self.replay_badge.is_some() {
+            self.replay_badge = Some(replay_badge(renderer));
+        }
+        if self.waveform_strip.is_some() {
+            self.waveform_strip = Some(self.create_waveform_strip(renderer)?);
+            // The heat map goes back on the new strip in the next update
+            self.shown_heat.clear();
+        }
+
+        let old_notes = self.judge.replace_notes(create_notes(
+            renderer,
+            textures,
+            &self.chart.notes,
+            BeatScroll::for_chart(&self.chart),
+        ));
+        for (note, old_note) in self.judge.notes_mut().iter_mut().zip(&old_notes) {
+            note.copy_progress(old_note);
+        }
+        self.create_shown_barlines(renderer);
+
+        if let Some(versus) = self.versus.as_mut() {
+            versus.recreate_gpu_resources(renderer, textures)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::score_screen::Score;
+    use scoring::format_accuracy;
+
+    #[test]
+    fn test_roll_speed_window() {
+        let mut tracker = RollSpeedTracker::default();
+        assert_eq!(tracker.best_speed(), 0.0);
+
+        // 10 hits in 0.9 seconds
+        for i in 0..10 {
+            tracker.hit(i as f32 * 0.1);
+        }
+        assert_eq!(tracker.best_speed(), 10.0);
+
+        // Slower hits push the old ones out of the window without lowering the best
+        for i in 0..5 {
+            tracker.hit(2.0 + i as f32 * 0.5);
+        }
+        assert_eq!(tracker.hits.len(), 2);
+        assert_eq!(tracker.best_speed(), 10.0);
+
+        // A hit exactly one window later evicts the first one
+        let mut tracker = RollSpeedTracker::default();
+        tracker.hit(0.0);
+        tracker.hit(1.0);
+        assert_eq!(tracker.best_speed(), 1.0);
+    }
+
+    #[test]
+    fn test_roll_speed_bonus() {
+        let mut result = PlayResult::new();
+        assert_eq!(result.roll_speed_bonus(), 0);
+
+        for i in 0..17 {
+            result.push_roll_hit(i as f32 * 0.05, false);
+        }
+
+        assert_eq!(result.drumrolls(), 17);
+        assert_eq!(result.best_roll_speed(), 17.0);
+        assert_eq!(result.roll_speed_bonus(), 3000);
+    }
+
+    /// Plays through a chart with some of everything in it.
+    fn simulate_chart() -> PlayResult {
+        let mut result = PlayResult::new();
+
+        result.push_judgement(Some(NoteJudgement::Good));
+        result.push_judgement(Some(NoteJudgement::Ok));
+        for i in 0..10 {
+            result.push_roll_hit(i as f32 * 0.1, false);
+        }
+        result.push_judgement(Some(NoteJudgement::Good));
+        result.push_balloon(true, false, false);
+        result.push_judgement(None);
+        result.push_balloon(false, false, false);
+        result.push_judgement(Some(NoteJudgement::Good));
+
+        result
+    }
+
+    #[test]
+    fn test_rolls_dont_affect_accuracy_or_combo() {
+        let result = simulate_chart();
+        let notes_only = [
+            Some(NoteJudgement::Good),
+            Some(NoteJudgement::Ok),
+            Some(NoteJudgement::Good),
+            None,
+            Some(NoteJudgement::Good),
+        ];
+
+        assert_eq!(result.accuracy(), scoring::accuracy(&notes_only));
+        assert_eq!(result.accuracy(), Some(0.7));
+        // The roll and balloon in the middle don't break or extend the combo
+        assert_eq!(result.max_combo(), 3);
+    }
+
+    #[test]
+    fn test_max_combo_survives_break() {
+        let mut result = PlayResult::new();
+        for _ in 0..12 {
+            result.push_judgement(Some(NoteJudgement::Ok));
+        }
+        result.push_judgement(Some(NoteJudgement::Bad));
+        assert_eq!(result.current_combo(), 0);
+
+        result.push_judgement(Some(NoteJudgement::Good));
+        result.push_roll_hit(0.0, false);
+        assert_eq!(result.current_combo(), 1);
+        assert_eq!(result.max_combo(), 12);
+    }
+
+    #[test]
+    fn test_balloon_gauge_bonus() {
+        let mut popped = PlayResult::new();
+        popped.push_judgement(Some(NoteJudgement::Good));
+        let before = popped.gauge();
+
+        let mut unfinished = popped.clone();
+        popped.push_balloon(true, false, false);
+        unfinished.push_balloon(false, false, false);
+        for i in 0..10 {
+            unfinished.push_roll_hit(i as f32 * 0.1, false);
+        }
+
+        assert_eq!(
+            popped.gauge(),
+            before + Gauge::default().change(ScoringEvent::BalloonPopped)
+        );
+        assert_eq!(unfinished.gauge(), before);
+    }
+
+    #[test]
+    fn test_rally() {
+        let mut result = PlayResult::with_conditions(PlayConditions::new(3, 0.0));
+        for _ in 0..100 {
+            result.push_judgement(Some(NoteJudgement::Good));
+        }
+        assert!(result.earns_rally());
+
+        result.start_rally(60.0);
+        assert!(result.rally_running(60.5));
+        result.push_rally_hit(60.5);
+        result.push_rally_hit(63.0);
+
+        let rally = result.rally().unwrap();
+        assert_eq!(rally.hits(), 1);
+        assert_eq!(result.score(), 100 * scoring::GOOD_POINTS + rally.bonus());
+        // The rally is a bonus on top, and doesn't change how the song was played
+        assert_eq!(result.accuracy(), Some(1.0));
+        assert_eq!(result.max_combo(), 100);
+
+        // Nobody earns the rally when the game plays by itself
+        let mut autoplay = PlayConditions::new(3, 0.0);
+        autoplay.autoplay = true;
+        let mut result = PlayResult {
+            conditions: Some(autoplay),
+            ..result
+        };
+        result.rally = None;
+        assert!(!result.earns_rally());
+
+        // Or without clearing the gauge
+        assert!(!PlayResult::new().earns_rally());
+    }
+
+    #[test]
+    fn test_accuracy_agrees_everywhere() {
+        let result = simulate_chart();
+
+        // The header shows the play result's accuracy, and the results screen keeps its own copy
+        let header = format_accuracy(result.accuracy());
+        let score = Score::from_result(&result);
+
+        assert_eq!(score.accuracy, result.accuracy());
+        assert_eq!(format_accuracy(score.accuracy), header);
+        assert_eq!(score.gauge, result.gauge());
+    }
+
+    #[test]
+    fn test_lead_in() {
+        // A note right at the start gets nearly the whole lead-in
+        assert!((lead_in_before(Some(0.1), 2.0) - 1.9).abs() < 1e-6);
+        // Songs with enough time before their first note aren't held back any more
+        assert_eq!(lead_in_before(Some(2.5), 2.0), 0.0);
+        assert_eq!(lead_in_before(Some(30.0), 2.0), 0.0);
+        assert_eq!(lead_in_before(None, 2.0), 0.0);
+    }
+
+    #[test]
+    fn test_restarted_result() {
+        let mut conditions = PlayConditions::new(3, 0.0);
+        conditions.autoplay = true;
+        let mut result = PlayResult::with_conditions(conditions)
+            .scored_with(scoring::Score::new(1000, 100))
+            .with_gauge(Gauge::new(10.0, 2.0));
+        let first_points = result.push_hit(NoteJudgement::Good, 0.0, false);
+        let first_gauge = result.gauge();
+        result.push_judgement(None);
+
+        // Nothing's been played, but it's scored the same way
+        let mut restarted = result.restarted();
+        assert_eq!(restarted.score(), 0);
+        assert_eq!(restarted.gauge(), 0.0);
+        assert_eq!(restarted.misses(), 0);
+        assert_eq!(
+            restarted.push_hit(NoteJudgement::Good, 0.0, false),
+            first_points
+        );
+        assert_eq!(restarted.gauge(), first_gauge);
+
+        // Autoplay has to be turned on again for the new play to count as autoplayed
+        let conditions = restarted.conditions().unwrap();
+        assert!(!conditions.autoplay);
+        assert_eq!(conditions.judgement_preset, JudgementPreset::HardExtreme);
+    }
 }
@@ -0,0 +1,83 @@
+//! Handing hits that are known ahead of time over to the scene as the song goes on.
+//!
+//! Both the autoplayer and replay playback have a list of hits to make, each at a set note time.
+//! Each frame the scene asks for the ones that have come due since the last frame, and feeds them
+//! to the judge like any other hit.
+use crate::settings::DrumInput;
+
+/// A hit that's made at a set note time.
+pub trait ScheduledHit {
+    fn time(&self) -> f32;
+}
+
+impl ScheduledHit for (DrumInput, f32) {
+    fn time(&self) -> f32 {
+        self.1
+    }
+}
+
+/// How far through a list of scheduled hits the song has got.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HitCursor {
+    /// The index of the next hit to be made.
+    next_hit: usize,
+}
+
+impl HitCursor {
+    /// Starts from the given note time, so every hit before then is left out.
+    pub fn starting_from<T: ScheduledHit>(hits: &[T], time: f32) -> Self {
+        Self {
+            next_hit: hits.iter().take_while(|hit| hit.time() < time).count(),
+        }
+    }
+
+    /// Goes back to the first hit, for when the song is restarted.
+    pub fn restart(&mut self) {
+        self.next_hit = 0;
+    }
+
+    /// The hits that are due by the given note time, which haven't been handed over yet.
+    ///
+    /// The hits are handed over in the order they're in, so one that's due waits for the ones
+    /// before it. They can be a little out of order by time, e.g. in a replay of a play where
+    /// autoplay was on alongside the player, since those are kept in the order they were judged.
+    pub fn hits_until<'a, T: ScheduledHit>(&mut self, hits: &'a [T], time: f32) -> &'a [T] {
+        let start = self.next_hit;
+        self.next_hit += hits[start..]
+            .iter()
+            .take_while(|hit| hit.time() <= time)
+            .count();
+        &hits[start..self.next_hit]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use DrumInput::*;
+
+    #[test]
+    fn test_hit_cursor() {
+        let hits = [
+            (LeftDon, 0.0),
+            (RightDon, 1.0),
+            (LeftKat, 2.0),
+            (RightKat, 3.0),
+        ];
+
+        // Hits before it started are left out
+        let mut cursor = HitCursor::starting_from(&hits, 0.5);
+        assert_eq!(cursor.hits_until(&hits, 1.5), [(RightDon, 1.0)]);
+        assert!(cursor.hits_until(&hits, 1.5).is_empty());
+        assert_eq!(cursor.hits_until(&hits, 10.0).len(), 2);
+
+        cursor.restart();
+        assert_eq!(cursor.hits_until(&hits, 0.0), [(LeftDon, 0.0)]);
+
+        // A hit that's due waits for the ones before it
+        let out_of_order = [(LeftDon, 1.0), (RightDon, 0.5), (LeftKat, 2.0)];
+        let mut cursor = HitCursor::default();
+        assert!(cursor.hits_until(&out_of_order, 0.75).is_empty());
+        assert_eq!(cursor.hits_until(&out_of_order, 1.0).len(), 2);
+    }
+}
@@ -0,0 +1,493 @@
+//! The rules for how everything the player does affects their score, accuracy, combo and soul
+//! gauge.
+//!
+//! Everything that shows or uses one of these numbers (judging, the header, the results screen)
+//! gets it from here, so they can never disagree. The rules are:
+//! - Only dons and kats count towards accuracy and combo. Drumrolls and balloons never do.
+//! - Goods score what the chart's `SCOREINIT` says (see [Score] for charts that don't say), plus
+//!   `SCOREDIFF` for every [COMBO_BONUS_STEP] combo. Okays score half that.
+//! - Hitting a big note or a big drumroll with both hands doubles its points. Both hands on a small
+//!   drumroll only count as one hit.
+//! - Drumroll hits and popped balloons score a few points too. Popping a kusudama scores more.
+//! - Everything scores 1.2 times as much in gogo time.
+//! - Goods fill the gauge by enough that hitting a share of the notes good (set for each
+//!   difficulty in [GAUGE_RATES]) reaches [GAUGE_CLEAR]. Okays fill it by half as much, and bads
+//!   and misses empty it by a few goods' worth.
+//! - Drumroll hits don't affect the gauge.
+//! - Popping a balloon or kusudama fills the gauge by a couple of goods' worth. One that isn't
+//!   finished gives nothing.
+//! - Finishing a song's notes with the gauge at or above [GAUGE_CLEAR] earns a short bonus
+//!   [Rally], where every drum hit scores bonus points. The rally never affects accuracy, combo or
+//!   the gauge.
+use super::scene::{NoteJudgement, ScoreInt};
+use crate::notechart_parser::{ChartStats, Difficulty, NoteChart};
+
+/// The most the soul gauge can hold.
+pub const GAUGE_MAX: f32 = 100.0;
+
+/// How much a good fills the gauge by when nothing says otherwise.
+const GOOD_GAUGE: f32 = 1.0;
+/// How many goods' worth a bad or a miss empties the gauge by when nothing says otherwise.
+const BAD_GAUGE_PENALTY: f32 = 2.0;
+/// How many goods' worth popping a balloon fills the gauge by.
+const BALLOON_POP_GAUGE_BONUS: f32 = 2.0;
+
+/// How the soul gauge fills on a difficulty.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GaugeRates {
+    /// The share of the notes that have to be hit good (with nothing else hit or missed) to reach
+    /// [GAUGE_CLEAR].
+    pub clear_share: f32,
+    /// How many goods' worth a bad or a miss empties the gauge by.
+    pub bad_penalty: f32,
+}
+
+/// The soul gauge rates for each difficulty, by its index (easy, normal, hard, oni, ura).
+pub const GAUGE_RATES: [GaugeRates; 5] = [
+    GaugeRates {
+        clear_share: 0.6,
+        bad_penalty: 0.5,
+    },
+    GaugeRates {
+        clear_share: 0.6,
+        bad_penalty: 1.0,
+    },
+    GaugeRates {
+        clear_share: 0.65,
+        bad_penalty: 1.5,
+    },
+    GaugeRates {
+        clear_share: 0.7,
+        bad_penalty: 2.0,
+    },
+    GaugeRates {
+        clear_share: 0.7,
+        bad_penalty: 2.0,
+    },
+];
+
+/// The points a good scores when nothing says otherwise.
+pub const GOOD_POINTS: ScoreInt = 1000;
+const ROLL_HIT_POINTS: ScoreInt = 100;
+const BALLOON_POP_POINTS: ScoreInt = 1000;
+const KUSUDAMA_POP_POINTS: ScoreInt = 5000;
+/// How much the combo has to go up by for each good to score `SCOREDIFF` more.
+pub const COMBO_BONUS_STEP: usize = 10;
+/// The most times `SCOREDIFF` can be added, which happens from a combo of 100.
+const COMBO_BONUS_MAX_STEPS: usize = 10;
+/// About what hitting every note in a chart scores, for charts that don't give a `SCOREINIT`.
+const FALLBACK_FULL_SCORE: ScoreInt = 1_000_000;
+/// How long after hitting a big note the other hand can hit it too, in seconds.
+pub const BIG_NOTE_SECOND_HIT_WINDOW: f32 = 0.05;
+/// How close together two hands have to hit a drumroll to count as hitting it at the same time,
+/// in seconds. This is shorter than for big notes, so fast rolling with alternate hands still
+/// counts every hit.
+pub const ROLL_SECOND_HIT_WINDOW: f32 = 0.03;
+
+/// How full the soul gauge has to be to clear a song.
+pub const GAUGE_CLEAR: f32 = 80.0;
+/// How long the bonus rally at the end of a song lasts, in seconds.
+pub const RALLY_DURATION: f32 = 2.0;
+/// The points each hit in the rally scores, before the multiplier.
+const RALLY_HIT_POINTS: ScoreInt = 100;
+/// How many hits it takes for the rally multiplier to go up by one.
+const RALLY_HITS_PER_MULTIPLIER: u32 = 5;
+const RALLY_MAX_MULTIPLIER: ScoreInt = 5;
+/// The first combo that's celebrated with a chime. After this, every hundred is.
+const FIRST_COMBO_MILESTONE: usize = 50;
+const COMBO_MILESTONE_STEP: usize = 100;
+
+/// Something the player did that might affect their score.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoringEvent {
+    /// A don or kat was judged. None means it was missed.
+    Note(Option<NoteJudgement>),
+    RollHit,
+    BalloonPopped,
+    KusudamaPopped,
+    /// A balloon or kusudama went by before it was popped.
+    BalloonUnfinished,
+}
+
+/// The soul gauge, along with how much each event fills or empties it by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gauge {
+    /// How much a good fills the gauge by.
+    good: f32,
+    /// How many goods' worth a bad or a miss empties the gauge by.
+    bad_penalty: f32,
+    /// How full the gauge is, from 0 to [GAUGE_MAX].
+    value: f32,
+}
+
+impl Default for Gauge {
+    fn default() -> Self {
+        Self::new(GOOD_GAUGE, BAD_GAUGE_PENALTY)
+    }
+}
+
+impl Gauge {
+    pub fn new(good: f32, bad_penalty: f32) -> Self {
+        Self {
+            good,
+            bad_penalty,
+            value: 0.0,
+        }
+    }
+
+    /// An empty gauge that fills at the same rates as this one.
+    pub fn restarted(&self) -> Self {
+        Self::new(self.good, self.bad_penalty)
+    }
+
+    /// Fills the gauge at the given difficulty's rates (see [GAUGE_RATES]), scaled by how many
+    /// notes the chart has.
+    pub fn for_chart(difficulty: usize, chart: &NoteChart) -> Self {
+        let rates = GAUGE_RATES[difficulty.min(GAUGE_RATES.len() - 1)];
+
+        match ChartStats::new(chart).hit_notes {
+            0 => Self::default(),
+            notes => Self::new(
+                GAUGE_CLEAR / (rates.clear_share * notes as f32),
+                rates.bad_penalty,
+            ),
+        }
+    }
+
+    /// How much an event changes the gauge by.
+    pub fn change(&self, event: ScoringEvent) -> f32 {
+        let goods = match event {
+            ScoringEvent::Note(Some(NoteJudgement::Good)) => 1.0,
+            ScoringEvent::Note(Some(NoteJudgement::Ok)) => 0.5,
+            ScoringEvent::Note(Some(NoteJudgement::Bad) | None) => -self.bad_penalty,
+            ScoringEvent::RollHit | ScoringEvent::BalloonUnfinished => 0.0,
+            ScoringEvent::BalloonPopped | ScoringEvent::KusudamaPopped => BALLOON_POP_GAUGE_BONUS,
+        };
+
+        goods * self.good
+    }
+
+    /// Fills or empties the gauge by however much the event does.
+    pub fn apply(&mut self, event: ScoringEvent) {
+        self.value = (self.value + self.change(event)).clamp(0.0, GAUGE_MAX);
+    }
+
+    /// How full the gauge is, from 0 to [GAUGE_MAX].
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+}
+
+/// The player's score, along with how many points the chart's notes are worth.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score {
+    /// The points a good scores with no combo bonus.
+    init: ScoreInt,
+    /// The points a good goes up by for every [COMBO_BONUS_STEP] combo.
+    diff: ScoreInt,
+    total: ScoreInt,
+}
+
+impl Default for Score {
+    fn default() -> Self {
+        Self::new(GOOD_POINTS, 0)
+    }
+}
+
+impl Score {
+    pub fn new(init: ScoreInt, diff: ScoreInt) -> Self {
+        Self {
+            init,
+            diff,
+            total: 0,
+        }
+    }
+
+    /// A score of nothing, scored with the same rules as this one.
+    pub fn restarted(&self) -> Self {
+        Self::new(self.init, self.diff)
+    }
+
+    /// Scores a difficulty with its `SCOREINIT` and `SCOREDIFF`. If it doesn't give a `SCOREINIT`,
+    /// goods are worth enough for a play that hits every note good to score about
+    /// [FALLBACK_FULL_SCORE], with no combo bonus.
+    pub fn for_difficulty(difficulty: &Difficulty) -> Self {
+        let diff = difficulty.score_diff.unwrap_or(0) as ScoreInt;
+        if let Some(init) = difficulty.score_init {
+            return Self::new(init as ScoreInt, diff);
+        }
+
+        match ChartStats::new(&difficulty.chart).hit_notes as ScoreInt {
+            0 => Self::default(),
+            notes => Self::new(FALLBACK_FULL_SCORE.div_ceil(notes).next_multiple_of(10), 0),
+        }
+    }
+
+    /// How many points an event scores with the given combo (counting the event itself).
+    pub fn points(&self, event: ScoringEvent, combo: usize, gogo: bool) -> ScoreInt {
+        let steps = (combo / COMBO_BONUS_STEP).min(COMBO_BONUS_MAX_STEPS) as ScoreInt;
+        let good = self.init + self.diff * steps;
+
+        let points = match event {
+            ScoringEvent::Note(Some(NoteJudgement::Good)) => good,
+            ScoringEvent::Note(Some(NoteJudgement::Ok)) => good / 2,
+            ScoringEvent::Note(Some(NoteJudgement::Bad) | None) => 0,
+            ScoringEvent::RollHit => ROLL_HIT_POINTS,
+            ScoringEvent::BalloonPopped => BALLOON_POP_POINTS,
+            ScoringEvent::KusudamaPopped => KUSUDAMA_POP_POINTS,
+            ScoringEvent::BalloonUnfinished => 0,
+        };
+
+        // Scores only ever go up in tens
+        let points = if gogo { points * 6 / 5 } else { points };
+        points / 10 * 10
+    }
+
+    /// Adds the points for an event, and returns them.
+    pub fn add(&mut self, event: ScoringEvent, combo: usize, gogo: bool) -> ScoreInt {
+        let points = self.points(event, combo, gogo);
+        self.total += points;
+        points
+    }
+
+    /// Adds the bonus for hitting a big note with both hands, which is whatever it scored again.
+    pub fn add_big_note_bonus(&mut self, points: ScoreInt) {
+        self.total += points;
+    }
+
+    pub fn total(&self) -> ScoreInt {
+        self.total
+    }
+}
+
+/// The player's accuracy over the given judgements, from 0 to 1. Goods count fully and okays
+/// count for half. Returns None if nothing has been judged yet.
+pub fn accuracy(judgements: &[Option<NoteJudgement>]) -> Option<f32> {
+    if judgements.is_empty() {
+        return None;
+    }
+
+    let points: f32 = judgements
+        .iter()
+        .map(|judgement| match judgement {
+            Some(NoteJudgement::Good) => 1.0,
+            Some(NoteJudgement::Ok) => 0.5,
+            Some(NoteJudgement::Bad) | None => 0.0,
+        })
+        .sum();
+
+    Some(points / judgements.len() as f32)
+}
+
+/// Formats an accuracy for showing to the player.
+pub fn format_accuracy(accuracy: Option<f32>) -> String {
+    match accuracy {
+        Some(accuracy) => format!("{:.2}%", accuracy * 100.0),
+        None => "-".to_string(),
+    }
+}
+
+/// Whether reaching this combo is worth celebrating: 50, 100, and every hundred after that.
+pub fn is_combo_milestone(combo: usize) -> bool {
+    combo == FIRST_COMBO_MILESTONE || (combo > 0 && combo.is_multiple_of(COMBO_MILESTONE_STEP))
+}
+
+/// Whether a gauge this full at the end of a song clears it.
+pub fn is_cleared(gauge: f32) -> bool {
+    gauge >= GAUGE_CLEAR
+}
+
+/// Whether a gauge this full at the end of a song's notes earns the bonus rally.
+pub fn earns_rally(gauge: f32) -> bool {
+    is_cleared(gauge)
+}
+
+/// The bonus phase at the end of a song. For [RALLY_DURATION] seconds, every drum hit scores bonus
+/// points, and the more hits the player lands the more each one is worth.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rally {
+    /// The note time the rally started at.
+    start: f32,
+    hits: u32,
+    bonus: ScoreInt,
+}
+
+impl Rally {
+    pub fn start(time: f32) -> Self {
+        Self {
+            start: time,
+            hits: 0,
+            bonus: 0,
+        }
+    }
+
+    /// The note time the rally started at.
+    pub fn start_time(&self) -> f32 {
+        self.start
+    }
+
+    /// What the next hit's points will be multiplied by.
+    pub fn multiplier(&self) -> ScoreInt {
+        (1 + (self.hits / RALLY_HITS_PER_MULTIPLIER) as ScoreInt).min(RALLY_MAX_MULTIPLIER)
+    }
+
+    /// Whether the rally is still going at the given time.
+    pub fn is_running(&self, time: f32) -> bool {
+        (self.start..self.start + RALLY_DURATION).contains(&time)
+    }
+
+    /// Records a drum hit at the given time, and returns the points it scored. Hits outside of the
+    /// rally score nothing.
+    pub fn hit(&mut self, time: f32) -> ScoreInt {
+        if !self.is_running(time) {
+            return 0;
+        }
+
+        let points = RALLY_HIT_POINTS * self.multiplier();
+        self.hits += 1;
+        self.bonus += points;
+        points
+    }
+
+    pub fn hits(&self) -> u32 {
+        self.hits
+    }
+
+    /// The total bonus scored in the rally.
+    pub fn bonus(&self) -> ScoreInt {
+        self.bonus
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::tja_fixture;
+
+    #[test]
+    fn test_score_from_chart() {
+        let score =
+            Score::for_difficulty(&tja_fixture(120, "SCOREINIT:380\nSCOREDIFF:100\n", "1,"));
+        assert_eq!(score, Score::new(380, 100));
+
+        // Without a SCOREINIT, 3 notes share a million points, rounded up to the next ten
+        let score = Score::for_difficulty(&tja_fixture(120, "SCOREDIFF:100\n", "1120,"));
+        assert_eq!(score, Score::new(333_340, 0));
+
+        assert_eq!(
+            Score::for_difficulty(&tja_fixture(120, "", ",")),
+            Score::default()
+        );
+    }
+
+    #[test]
+    fn test_score_points() {
+        let good = ScoringEvent::Note(Some(NoteJudgement::Good));
+        let ok = ScoringEvent::Note(Some(NoteJudgement::Ok));
+        let mut score = Score::new(380, 100);
+
+        // A good on the first note, then an ok at a combo of 10 and a good at 25
+        assert_eq!(score.add(good, 1, false), 380);
+        assert_eq!(score.add(ok, 10, false), 240);
+        assert_eq!(score.add(good, 25, false), 580);
+        // The combo bonus stops going up at 100
+        assert_eq!(score.points(good, 250, false), 1380);
+        // Gogo time is worth 1.2 times as much, rounded down to ten
+        assert_eq!(score.add(ok, 1, true), 220);
+        assert_eq!(score.add(ScoringEvent::RollHit, 0, true), 120);
+        assert_eq!(score.add(ScoringEvent::BalloonPopped, 0, false), 1000);
+        assert_eq!(score.add(ScoringEvent::KusudamaPopped, 0, false), 5000);
+        assert_eq!(
+            score.add(ScoringEvent::Note(Some(NoteJudgement::Bad)), 0, true),
+            0
+        );
+        score.add_big_note_bonus(580);
+
+        assert_eq!(
+            score.total(),
+            380 + 240 + 580 + 220 + 120 + 1000 + 5000 + 580
+        );
+    }
+
+    #[test]
+    fn test_combo_milestones() {
+        let milestones: Vec<_> = (0..=500)
+            .filter(|&combo| is_combo_milestone(combo))
+            .collect();
+        assert_eq!(milestones, [50, 100, 200, 300, 400, 500]);
+    }
+
+    #[test]
+    fn test_gauge_rates() {
+        let good = ScoringEvent::Note(Some(NoteJudgement::Good));
+        let chart = tja_fixture(120, "", "1111,\n1111,\n1111,\n1111,\n1111,").chart;
+
+        for (difficulty, rates) in GAUGE_RATES.iter().enumerate() {
+            // Hitting the clear share of the 20 notes good reaches the clear line
+            let mut gauge = Gauge::for_chart(difficulty, &chart);
+            let goods = (rates.clear_share * 20.0).round() as usize;
+            for _ in 0..goods {
+                gauge.apply(good);
+            }
+            assert!((gauge.value() - GAUGE_CLEAR).abs() < 0.01, "{difficulty}");
+            assert!(!is_cleared(gauge.value() - 0.1));
+
+            // A miss empties it by the difficulty's penalty
+            let before = gauge.value();
+            gauge.apply(ScoringEvent::Note(None));
+            assert_eq!(
+                gauge.change(ScoringEvent::Note(None)),
+                -rates.bad_penalty * gauge.change(good)
+            );
+            assert!(gauge.value() < before);
+        }
+
+        // The gauge can't go past either end
+        let mut gauge = Gauge::for_chart(3, &chart);
+        gauge.apply(ScoringEvent::Note(None));
+        assert_eq!(gauge.value(), 0.0);
+        for _ in 0..30 {
+            gauge.apply(good);
+        }
+        assert_eq!(gauge.value(), GAUGE_MAX);
+
+        // A chart without any notes to hit fills at the default rates
+        assert_eq!(Gauge::for_chart(3, &NoteChart::default()), Gauge::default());
+    }
+
+    #[test]
+    fn test_rally_needs_clear() {
+        assert!(!earns_rally(GAUGE_CLEAR - 0.5));
+        assert!(earns_rally(GAUGE_CLEAR));
+        assert!(earns_rally(GAUGE_MAX));
+    }
+
+    #[test]
+    fn test_rally_multiplier_escalates() {
+        let mut rally = Rally::start(10.0);
+
+        // Nothing counts before or after the rally
+        assert_eq!(rally.hit(9.9), 0);
+        assert_eq!(rally.multiplier(), 1);
+
+        let points: Vec<_> = (0..30).map(|i| rally.hit(10.0 + i as f32 * 0.05)).collect();
+
+        assert_eq!(points[0], RALLY_HIT_POINTS);
+        assert_eq!(
+            points[RALLY_HITS_PER_MULTIPLIER as usize],
+            RALLY_HIT_POINTS * 2
+        );
+        assert!(points.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert_eq!(
+            *points.last().unwrap(),
+            RALLY_HIT_POINTS * RALLY_MAX_MULTIPLIER
+        );
+
+        assert_eq!(rally.hits(), 30);
+        assert_eq!(rally.bonus(), points.iter().sum::<ScoreInt>());
+
+        assert!(!rally.is_running(10.0 + RALLY_DURATION));
+        assert_eq!(rally.hit(10.0 + RALLY_DURATION), 0);
+        assert_eq!(rally.hits(), 30);
+    }
+}
@@ -0,0 +1,540 @@
+//! Plays a whole song from start to finish without a window or audio, to make sure reading a song,
+//! judging the hits, scoring them and saving the play all still fit together.
+use super::autoplay::autoplay_hits;
+use super::conditions::PlayConditions;
+use super::judge::{HitOutcome, Judge, JudgedNote};
+use super::note::BasicNoteType;
+use super::replay::{Replay, ReplayPlayer, ReplayRecorder};
+use super::scene::{timing_windows_for, NoteJudgement, PlayResult};
+use super::scoring::{self, GAUGE_CLEAR};
+use crate::game::score_screen::Score;
+use crate::game::song_select::read_song_dir;
+use crate::local_data::{PlayRecord, SongData};
+use crate::notechart_parser::{parse_tja_file, Difficulty, NoteChart, Player};
+use crate::settings::DrumInput;
+use crate::test_support::temp_dir;
+
+const ONI: usize = 3;
+
+/// Starts playing a chart, with the judge and results set up the way they are in game.
+fn start_play(chart: &Difficulty, difficulty: usize) -> (Judge<JudgedNote>, PlayResult) {
+    start_player_play(chart, difficulty, &chart.chart)
+}
+
+/// Starts playing one of a chart's tracks, like [start_play].
+fn start_player_play(
+    chart: &Difficulty,
+    difficulty: usize,
+    track: &NoteChart,
+) -> (Judge<JudgedNote>, PlayResult) {
+    let notes = track.notes.iter().map(JudgedNote::new).collect();
+    let judge = Judge::new(notes, timing_windows_for(difficulty));
+    let results = PlayResult::with_conditions(PlayConditions::new(difficulty, 0.0))
+        .scored_with(scoring::Score::for_difficulty(chart))
+        .with_gauge(scoring::Gauge::for_chart(difficulty, track));
+    (judge, results)
+}
+
+/// Plays through a chart, hitting the drum at the given note times (which must be in order).
+///
+/// Everything is judged the same way it is in game, apart from the bonus rally, which is left out.
+fn simulate_play(chart: &Difficulty, difficulty: usize, hits: &[(f32, DrumInput)]) -> PlayResult {
+    let (mut judge, mut results) = start_play(chart, difficulty);
+
+    for &(time, input) in hits {
+        judge.judge_hit(input, time, &mut results);
+    }
+
+    // Anything that wasn't hit by the end of the song is missed
+    judge.advance(f32::INFINITY, &mut results);
+    results
+}
+
+/// Plays through a chart like [simulate_play], but with the game updating at the given frame
+/// rate. The hits made during each frame are judged together at the start of the next one, like
+/// they are in game. Returns what each hit did along with the results.
+fn simulate_play_at_frame_rate(
+    chart: &Difficulty,
+    difficulty: usize,
+    hits: &[(f32, DrumInput)],
+    fps: f32,
+) -> (Vec<HitOutcome>, PlayResult) {
+    let (mut judge, mut results) = start_play(chart, difficulty);
+    let mut outcomes = Vec::new();
+    let mut pending = hits.iter().peekable();
+
+    let mut frame = 0;
+    while pending.peek().is_some() {
+        let frame_time = frame as f32 / fps;
+        while let Some(&(time, input)) = pending.next_if(|(time, _)| *time <= frame_time) {
+            outcomes.push(judge.judge_hit(input, time, &mut results));
+        }
+
+        judge.advance(frame_time, &mut results);
+        frame += 1;
+    }
+
+    judge.advance(f32::INFINITY, &mut results);
+    (outcomes, results)
+}
+
+fn smoke_test_chart() -> Difficulty {
+    let mut song = read_song_dir(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/game/taiko_mode/Smoke test"
+    ))
+    .unwrap();
+    song.difficulties[ONI].take().unwrap()
+}
+
+/// Reads the oni chart from a TJA file.
+fn oni_chart(tja: &str) -> Difficulty {
+    parse_tja_file(tja).unwrap().difficulties[ONI]
+        .take()
+        .unwrap()
+}
+
+/// The hits for a play through the smoke test chart, with a bit of everything in it.
+fn smoke_test_hits() -> Vec<(f32, DrumInput)> {
+    use DrumInput::*;
+
+    // At 120bpm, each note in the chart is half a second apart
+    let mut hits = vec![
+        (0.0, LeftDon),
+        // 40ms late is an ok
+        (0.54, RightKat),
+        // The don at 1.0 is missed
+        (1.49, LeftKat),
+    ];
+    // Ten hits on the drumroll
+    hits.extend((0..10).map(|i| (2.0 + i as f32 * 0.1, [LeftDon, RightKat][i % 2])));
+    // A kat doesn't pop a balloon, but four dons do
+    hits.push((4.05, LeftKat));
+    hits.extend((1..=4).map(|i| (4.0 + i as f32 * 0.1, RightDon)));
+    hits.extend([
+        (6.02, RightDon),
+        (6.45, LeftDon),
+        (7.0, LeftKat),
+        (7.56, RightKat),
+    ]);
+    hits
+}
+
+#[test]
+fn test_full_play() {
+    let chart = smoke_test_chart();
+    let result = simulate_play(&chart, ONI, &smoke_test_hits());
+
+    assert_eq!(result.goods(), 4);
+    assert_eq!(result.okays(), 3);
+    assert_eq!(result.bads(), 0);
+    assert_eq!(result.misses(), 1);
+    // The roll hits and the balloon hits
+    assert_eq!(result.drumrolls(), 14);
+    // The miss breaks the combo, but the roll and balloon don't
+    assert_eq!(result.max_combo(), 5);
+    assert_eq!(result.accuracy(), Some(5.5 / 8.0));
+    // The chart doesn't give a SCOREINIT, so its 8 notes share a million points. Then there are 4
+    // goods, 3 oks, 14 roll hits and a popped balloon
+    assert_eq!(result.score(), 4 * 125_000 + 3 * 62_500 + 14 * 100 + 1000);
+    assert_eq!(result.roll_speed_bonus(), 0);
+    // The miss empties the gauge, which can't go below zero. After that come 3 goods, 2 oks and a
+    // popped balloon. On oni, the clear line is 70% of the notes good.
+    let good = GAUGE_CLEAR / (0.7 * 8.0);
+    assert!((result.gauge() - (3.0 + 1.0 + 2.0) * good).abs() < 0.001);
+    // With so few notes, each one fills the gauge a lot, so that's enough to clear
+    assert!(result.gauge() >= GAUGE_CLEAR);
+    assert!(result.cleared());
+
+    let score = Score::from_result(&result);
+    assert_eq!(score.accuracy, result.accuracy());
+    assert_eq!(score.gauge, result.gauge());
+
+    // The play is saved to the song's history, and reads back the same
+    let conditions = result.conditions().unwrap().clone();
+    let play = PlayRecord::now(
+        ONI,
+        result.score(),
+        result.accuracy(),
+        conditions,
+        result.frame_stats(),
+    );
+    let mut song_data = SongData::default();
+    assert!(song_data.record_play(play.clone()));
+
+    let saved: SongData = toml::from_str(&toml::to_string(&song_data).unwrap()).unwrap();
+    assert_eq!(saved, song_data);
+    assert_eq!(saved.plays_for(ONI), [play]);
+}
+
+#[test]
+fn test_frame_rate_independence() {
+    let chart = smoke_test_chart();
+    let hits = smoke_test_hits();
+    let (expected_outcomes, expected) = simulate_play_at_frame_rate(&chart, ONI, &hits, 240.0);
+
+    for fps in [30.0, 60.0, 144.0] {
+        let (outcomes, result) = simulate_play_at_frame_rate(&chart, ONI, &hits, fps);
+
+        assert_eq!(outcomes, expected_outcomes, "at {fps}fps");
+        assert_eq!(result.goods(), expected.goods(), "at {fps}fps");
+        assert_eq!(result.okays(), expected.okays(), "at {fps}fps");
+        assert_eq!(result.bads(), expected.bads(), "at {fps}fps");
+        assert_eq!(result.misses(), expected.misses(), "at {fps}fps");
+        assert_eq!(result.drumrolls(), expected.drumrolls(), "at {fps}fps");
+        assert_eq!(result.max_combo(), expected.max_combo(), "at {fps}fps");
+        assert_eq!(result.score(), expected.score(), "at {fps}fps");
+        assert_eq!(result.gauge(), expected.gauge(), "at {fps}fps");
+    }
+
+    // And it's all the same as judging each hit the moment it's made
+    let result = simulate_play(&chart, ONI, &hits);
+    assert_eq!(result.score(), expected.score());
+    assert_eq!(result.misses(), expected.misses());
+}
+
+#[test]
+fn test_hand_hint_notes() {
+    use DrumInput::*;
+
+    let big_notes = "TITLE:Big\nWAVE:big.ogg\n\nCOURSE:Oni\nLEVEL:5\n\n#START\n3040,\n#END\n";
+    let hand_hints = big_notes.replace("3040,", "#SENOTECHANGE 2\nA0B0,");
+
+    // Big notes take both drums, but one is enough to hit them
+    let hits = [(0.0, LeftDon), (1.01, RightKat)];
+    let big_result = simulate_play(&oni_chart(big_notes), ONI, &hits);
+    let hint_result = simulate_play(&oni_chart(&hand_hints), ONI, &hits);
+
+    assert_eq!(hint_result.goods(), 2);
+    assert_eq!(hint_result.goods(), big_result.goods());
+    assert_eq!(hint_result.score(), big_result.score());
+    assert_eq!(hint_result.gauge(), big_result.gauge());
+}
+
+#[test]
+fn test_gogo_and_big_note_scores() {
+    use DrumInput::*;
+
+    // At 120bpm, the notes are a second apart, and the last two are in gogo time
+    let chart = oni_chart(
+        "TITLE:Scores\nWAVE:scores.ogg\nBPM:120\nSCOREINIT:400\nSCOREDIFF:100\n\nCOURSE:Oni\n\n\
+         #START\n3040,\n#GOGOSTART\n1040,\n#END\n",
+    );
+
+    let hits = [
+        // Both hands on the first big don, but the second is too late for the big kat
+        (0.0, LeftDon),
+        (0.03, RightDon),
+        (1.0, LeftKat),
+        (1.1, RightKat),
+        (2.0, RightDon),
+        // A don doesn't count as the other hand for a big kat
+        (3.0, LeftKat),
+        (3.02, RightDon),
+    ];
+    let result = simulate_play(&chart, ONI, &hits);
+
+    assert_eq!(result.goods(), 4);
+    assert_eq!(result.misses(), 0);
+    // 400 for each good outside gogo time, and 480 in it. Only the first big don was doubled.
+    assert_eq!(result.score(), 2 * 400 + 400 + 2 * 480);
+}
+
+#[test]
+fn test_drumroll_hits() {
+    use DrumInput::*;
+
+    // At 120bpm each 32nd is 1/16 of a second. The roll lasts from 0 until 1.6875, and the don
+    // comes right after it at 1.75.
+    let measure = format!("5{}81000,", "0".repeat(26));
+    let chart = oni_chart(&format!(
+        "TITLE:Rolls\nWAVE:rolls.ogg\nBPM:120\n\nCOURSE:Oni\n\n#START\n{measure}\n#END\n"
+    ));
+
+    // Either colour counts on a drumroll
+    let mut hits: Vec<_> = (0..10)
+        .map(|i| (i as f32 * 0.1, if i % 2 == 0 { LeftDon } else { RightKat }))
+        .collect();
+    // This is still during the roll, but close enough to the don to hit it instead
+    hits.push((1.68, LeftDon));
+
+    let (outcomes, result) = simulate_play_at_frame_rate(&chart, ONI, &hits, 60.0);
+
+    assert_eq!(
+        outcomes[9],
+        HitOutcome::RollHit {
+            roll_note: BasicNoteType::hit_with(RightKat, false),
+            hits: 10
+        }
+    );
+    assert_eq!(
+        outcomes[10],
+        HitOutcome::Note {
+            judgement: NoteJudgement::Ok,
+            kind: BasicNoteType::hit_with(LeftDon, false)
+        }
+    );
+    assert_eq!(result.drumrolls(), 10);
+    assert_eq!(result.okays(), 1);
+    assert_eq!(result.misses(), 0);
+}
+
+#[test]
+fn test_two_handed_drumroll_hits() {
+    use DrumInput::*;
+
+    let chart = oni_chart(
+        "TITLE:Rolls\nWAVE:rolls.ogg\nBPM:120\n\nCOURSE:Oni\n\n#START\n5008,\n6008,\n#END\n",
+    );
+
+    // Both hands at once on each roll
+    let hits = [
+        (0.5, LeftDon),
+        (0.51, RightDon),
+        (2.5, LeftDon),
+        (2.51, RightDon),
+    ];
+    let (outcomes, result) = simulate_play_at_frame_rate(&chart, ONI, &hits, 60.0);
+
+    // The small roll only counts it as one hit, but the big roll doubles it
+    assert_eq!(outcomes[1], HitOutcome::Nothing);
+    assert_eq!(outcomes[3], HitOutcome::BigNoteBonus);
+    assert_eq!(result.drumrolls(), 2);
+    assert_eq!(result.score(), 100 + 2 * 100);
+}
+
+#[test]
+fn test_balloons() {
+    use DrumInput::*;
+
+    // A balloon, a kusudama and another balloon, each lasting 1.5 seconds from the start of their
+    // measure
+    let chart = oni_chart(
+        "TITLE:Balloons\nWAVE:balloons.ogg\nBPM:120\nBALLOON:5,3,4\n\nCOURSE:Oni\n\n\
+         #START\n7008,\n9008,\n7008,\n#END\n",
+    );
+
+    let mut hits = vec![(0.1, LeftKat)];
+    hits.extend((0..5).map(|i| (0.2 + i as f32 * 0.1, LeftDon)));
+    hits.extend((0..3).map(|i| (2.1 + i as f32 * 0.1, RightDon)));
+    hits.push((4.1, LeftDon));
+    let (outcomes, result) = simulate_play_at_frame_rate(&chart, ONI, &hits, 60.0);
+
+    // Kats don't do anything to a balloon
+    assert_eq!(outcomes[0], HitOutcome::Nothing);
+    assert_eq!(
+        outcomes[5],
+        HitOutcome::BalloonHit {
+            hits_left: 0,
+            hit_target: 5,
+            kusudama: false
+        }
+    );
+    assert_eq!(
+        outcomes[8],
+        HitOutcome::BalloonHit {
+            hits_left: 0,
+            hit_target: 3,
+            kusudama: true
+        }
+    );
+    // Every don scores as a roll hit, then popping the balloon and the kusudama score their
+    // bonuses. The last balloon isn't finished, so it doesn't score anything more.
+    assert_eq!(result.score(), 9 * 100 + 1000 + 5000);
+    assert_eq!(result.misses(), 0);
+}
+
+#[test]
+fn test_autoplay() {
+    let chart = smoke_test_chart();
+    let hits: Vec<_> = autoplay_hits(&chart.chart.notes, timing_windows_for(ONI))
+        .into_iter()
+        .map(|(input, time)| (time, input))
+        .collect();
+
+    let (_, mut result) = simulate_play_at_frame_rate(&chart, ONI, &hits, 60.0);
+    result.mark_autoplay();
+
+    // Every note is good, and the drumroll and balloon are played through
+    assert_eq!(result.goods(), 8);
+    assert_eq!(result.misses(), 0);
+    assert_eq!(result.accuracy(), Some(1.0));
+    assert!(result.drumrolls() > 4);
+
+    // However well it went, it's never the player's best
+    let conditions = result.conditions().unwrap().clone();
+    assert!(conditions.autoplay);
+    let play = PlayRecord::now(
+        ONI,
+        result.score(),
+        result.accuracy(),
+        conditions,
+        result.frame_stats(),
+    );
+    assert!(!SongData::default().record_play(play));
+}
+
+#[test]
+fn test_seeking() {
+    use DrumInput::*;
+
+    let chart = smoke_test_chart();
+    let (mut judge, mut results) = start_play(&chart, ONI);
+
+    // Hit the dons in the first measure and pop the balloon, missing the kats
+    judge.judge_hit(LeftDon, 0.0, &mut results);
+    judge.judge_hit(LeftDon, 1.0, &mut results);
+    for i in 0..4 {
+        judge.judge_hit(RightDon, 4.1 + i as f32 * 0.1, &mut results);
+    }
+    assert_eq!(results.misses(), 2);
+
+    // Going back to the start puts every note back the way it was
+    judge.seek(-1.0);
+    let fresh: Vec<_> = chart.chart.notes.iter().map(JudgedNote::new).collect();
+    assert_eq!(judge.notes(), fresh);
+    assert_eq!(judge.next_note_index(), 0);
+    assert!(matches!(
+        judge.judge_hit(LeftDon, 0.0, &mut results),
+        HitOutcome::Note {
+            judgement: NoteJudgement::Good,
+            ..
+        }
+    ));
+
+    // Going forward skips the notes in between without missing them
+    let misses = results.misses();
+    judge.seek(5.9);
+    assert_eq!(judge.next_note().map(JudgedNote::time), Some(6.0));
+    judge.advance(5.9, &mut results);
+    assert_eq!(results.misses(), misses);
+
+    // Landing in the middle of a drumroll starts it again from no hits
+    judge.seek(3.0);
+    assert!(judge.next_note().is_some_and(JudgedNote::is_roll));
+    assert!(matches!(
+        judge.judge_hit(LeftDon, 3.0, &mut results),
+        HitOutcome::RollHit { hits: 1, .. }
+    ));
+}
+
+#[test]
+fn test_replay_playback() {
+    let chart = smoke_test_chart();
+    let hits: Vec<_> = autoplay_hits(&chart.chart.notes, timing_windows_for(ONI))
+        .into_iter()
+        .map(|(input, time)| (time, input))
+        .collect();
+
+    // Record an autoplayed play at 60fps. The hits are judged in the order they're listed, so
+    // that's the order they're recorded in.
+    let (_, mut recorded) = simulate_play_at_frame_rate(&chart, ONI, &hits, 60.0);
+    recorded.mark_autoplay();
+    let mut recorder = ReplayRecorder::new("song.ogg", "Smoke test", ONI, &chart.chart.notes);
+    for &(time, input) in &hits {
+        recorder.record(input, time);
+    }
+    let replay = recorder.finish(&recorded).unwrap();
+
+    // It goes through a file like any other replay
+    let dir = temp_dir("replay_playback");
+    let replay = Replay::load(replay.save_in(&dir).unwrap()).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+    replay.check_chart(&chart.chart.notes).unwrap();
+    assert!(replay.conditions.autoplay);
+
+    // Play it back at a different frame rate, with the hits handed over the way the scene does
+    let (mut judge, mut played_back) = start_play(&chart, ONI);
+    let mut player = ReplayPlayer::new(replay.clone());
+    let fps = 144.0;
+    let end = replay.hits.last().unwrap().time;
+    for frame in 0..=(end * fps).ceil() as usize {
+        let frame_time = frame as f32 / fps;
+        for hit in player.hits_until(frame_time) {
+            judge.judge_hit(hit.input, hit.time, &mut played_back);
+        }
+
+        judge.advance(frame_time, &mut played_back);
+    }
+    judge.advance(f32::INFINITY, &mut played_back);
+
+    assert_eq!(played_back.goods(), recorded.goods());
+    assert_eq!(played_back.okays(), recorded.okays());
+    assert_eq!(played_back.bads(), recorded.bads());
+    assert_eq!(played_back.misses(), recorded.misses());
+    assert_eq!(played_back.drumrolls(), recorded.drumrolls());
+    assert_eq!(played_back.max_combo(), recorded.max_combo());
+    assert_eq!(played_back.score(), replay.score);
+}
+
+#[test]
+fn test_battle() {
+    let chart = oni_chart(
+        "TITLE:Battle\nWAVE:song.ogg\nBPM:120\n\nCOURSE:Oni\nLEVEL:8\nSTYLE:Double\n\n\
+        #START P1\n1010,\n#END\n\n#START P2\n2222,\n#END\n",
+    );
+
+    // Each player is judged on their own track, with their own results. Player 1 hits everything
+    // and player 2 doesn't hit anything.
+    let (mut judge_1, mut player_1) =
+        start_player_play(&chart, ONI, chart.chart_for(Player::Player1));
+    let (mut judge_2, mut player_2) =
+        start_player_play(&chart, ONI, chart.chart_for(Player::Player2));
+    for (input, time) in autoplay_hits(
+        &chart.chart_for(Player::Player1).notes,
+        judge_1.timing_windows(),
+    ) {
+        judge_1.judge_hit(input, time, &mut player_1);
+        judge_2.advance(time, &mut player_2);
+    }
+    judge_1.advance(f32::INFINITY, &mut player_1);
+    judge_2.advance(f32::INFINITY, &mut player_2);
+
+    assert_eq!(player_1.goods(), 2);
+    assert_eq!(player_1.misses(), 0);
+    assert_eq!(player_2.goods(), 0);
+    assert_eq!(player_2.misses(), 4);
+    assert!(player_1.score() > player_2.score());
+    assert!(player_1.gauge() > player_2.gauge());
+}
+
+#[test]
+fn test_replay_fixtures() {
+    let chart = smoke_test_chart();
+    let dir = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/game/taiko_mode/Smoke test"
+    );
+
+    // These were recorded on the smoke test chart, and have to keep coming to the same scores. If
+    // one stops verifying, the judging has changed in a way that changes players' old scores.
+    for name in ["Smoke test.ltr", "Smoke test (shuffled).ltr"] {
+        let replay = Replay::load(format!("{dir}/{name}")).unwrap();
+        let verification = replay.verify(&chart).unwrap();
+        assert!(verification.matches(), "{name} {verification}");
+    }
+
+    // The shuffled one is sped up and has a rally, so all of that has to be set up again too
+    let replay = Replay::load(format!("{dir}/Smoke test (shuffled).ltr")).unwrap();
+    assert!(replay.rally_start.is_some());
+    assert_eq!(replay.conditions.playback_speed, 1.5);
+
+    // Any change to the recorded results is noticed
+    let tampered = Replay {
+        score: replay.score + 100,
+        ..replay.clone()
+    };
+    assert!(!tampered.verify(&chart).unwrap().matches());
+
+    let mut tampered = replay.clone();
+    tampered.judgements.goods -= 1;
+    tampered.judgements.bads += 1;
+    let verification = tampered.verify(&chart).unwrap();
+    assert!(!verification.matches());
+    assert!(verification.to_string().contains("goods recorded"));
+
+    // So is a hit that's been moved
+    let mut tampered = replay.clone();
+    tampered.hits[0].time += 0.1;
+    assert!(!tampered.verify(&chart).unwrap().matches());
+}
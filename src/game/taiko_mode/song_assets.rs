@@ -0,0 +1,118 @@
+//! Loading what a song needs that isn't kept loaded between songs.
+//!
+//! Decoding a song's audio can take a while for a long song, so song select does it on another
+//! thread and shows that the song is loading in the meantime. The textures that are only used
+//! while playing are decoded along with it. Only putting them on the GPU has to wait for the main
+//! thread, once everything's been decoded.
+use std::sync::mpsc::{self, Receiver};
+
+use image::RgbaImage;
+use kira::sound::static_sound::{StaticSoundData, StaticSoundSettings};
+
+use super::ui::BALLOON_BUBBLE_TEXTURE;
+use crate::{
+    game::{TextureCache, SPRITES_PATH},
+    render::{texture::Texture, Renderer},
+};
+
+/// Textures that are only used while playing a song. They're loaded along with each song and
+/// released when it ends.
+const SONG_TEXTURES: &[&str] = &[BALLOON_BUBBLE_TEXTURE];
+
+/// A song's audio and the textures loaded just for it, decoded but not on the GPU yet.
+pub struct SongAssets {
+    pub sound_data: StaticSoundData,
+    textures: Vec<(&'static str, RgbaImage)>,
+}
+
+impl SongAssets {
+    /// Loads the song's audio and textures, waiting until they're done.
+    pub fn load(audio_filename: &str, settings: StaticSoundSettings) -> anyhow::Result<Self> {
+        let sound_data = StaticSoundData::from_file(audio_filename, settings)
+            .map_err(|e| anyhow::anyhow!("couldn't load {audio_filename}: {e}"))?;
+        Self::with_sound(sound_data)
+    }
+
+    /// Loads the song's textures to go with audio that's already loaded (e.g. the tutorial's
+    /// silence).
+    pub fn with_sound(sound_data: StaticSoundData) -> anyhow::Result<Self> {
+        let textures = SONG_TEXTURES
+            .iter()
+            .map(|&filename| {
+                let image = Texture::decode_file(format!("{SPRITES_PATH}/{filename}"))?;
+                Ok((filename, image))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            sound_data,
+            textures,
+        })
+    }
+
+    /// Loads the song's audio and textures on another thread. They're sent back once they're
+    /// done, or the error if they couldn't be loaded.
+    pub fn spawn_load(
+        audio_filename: String,
+        settings: StaticSoundSettings,
+    ) -> Receiver<anyhow::Result<Self>> {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(Self::load(&audio_filename, settings));
+        });
+
+        receiver
+    }
+
+    /// Puts the song's textures in the texture cache. Returns their names, so they can be released
+    /// once the song ends.
+    pub fn upload_textures(
+        &mut self,
+        renderer: &Renderer,
+        textures: &mut TextureCache,
+    ) -> Vec<&'static str> {
+        self.textures
+            .drain(..)
+            .map(|(filename, image)| {
+                textures.insert(&renderer.device, &renderer.queue, filename, &image);
+                filename
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{silent_wav, temp_dir};
+
+    #[test]
+    fn test_load_in_background() {
+        let dir = temp_dir("song_assets");
+        let path = dir.join("song.wav");
+        std::fs::write(&path, silent_wav(8000, 800)).unwrap();
+
+        let receiver = SongAssets::spawn_load(
+            path.to_str().unwrap().to_string(),
+            StaticSoundSettings::default(),
+        );
+        let assets = receiver.recv().unwrap().unwrap();
+        assert_eq!(assets.sound_data.frames.len(), 800);
+
+        // Every texture the scene releases on exit is decoded along with the song
+        let names = assets
+            .textures
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, SONG_TEXTURES);
+        assert!(assets.textures.iter().all(|(_, image)| image.width() > 0));
+
+        // A song that can't be loaded sends back the error instead
+        let missing = dir.join("missing.wav").to_str().unwrap().to_string();
+        let receiver = SongAssets::spawn_load(missing, StaticSoundSettings::default());
+        assert!(receiver.recv().unwrap().is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+//! The tutorial for new players.
+//!
+//! The tutorial is a normal taiko mode scene playing a short chart that's built into the game,
+//! along with a script of steps that explain what's going on. During steps that wait for the
+//! player, the clock stops whenever a note reaches the receptacle and only starts again once the
+//! player hits it, so nobody can fall behind.
+use crate::notechart_parser::{parse_tja_file, Song};
+
+/// The chart the tutorial is played on. It's at 60bpm, so there's one beat every second.
+const TUTORIAL_CHART: &str = include_str!("./tutorial.tja");
+
+/// A single step of the tutorial.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TutorialStep {
+    /// The note time the step starts at.
+    pub time: f32,
+    /// What to tell the player.
+    pub text: &'static str,
+    /// Whether the clock should stop at each note in this step until the player hits it.
+    pub wait: bool,
+}
+
+/// The steps of the tutorial, in order. The times line up with the notes in the tutorial chart.
+pub const TUTORIAL_STEPS: [TutorialStep; 8] = [
+    TutorialStep {
+        time: f32::NEG_INFINITY,
+        text: "Welcome! Notes scroll in from the right. Hit each one as it reaches the circle.",
+        wait: false,
+    },
+    TutorialStep {
+        time: 2.0,
+        text: "Red notes are dons. Hit a don key when one reaches the circle.",
+        wait: true,
+    },
+    TutorialStep {
+        time: 7.0,
+        text: "Blue notes are kats. Hit a kat key for these.",
+        wait: true,
+    },
+    TutorialStep {
+        time: 11.0,
+        text: "Now try them mixed together. Watch the colours!",
+        wait: true,
+    },
+    TutorialStep {
+        time: 15.5,
+        text: "Big notes are hit just like small ones, with the key of the same colour.",
+        wait: true,
+    },
+    TutorialStep {
+        time: 19.5,
+        text: "Yellow notes are drumrolls. Hit any key as many times as you can until it ends!",
+        wait: false,
+    },
+    TutorialStep {
+        time: 23.5,
+        text: "Balloons pop after a certain number of hits with a don key. Keep hitting!",
+        wait: true,
+    },
+    TutorialStep {
+        time: 27.5,
+        text: "That's everything. You're ready to play some songs!",
+        wait: false,
+    },
+];
+
+/// Parses the tutorial chart.
+pub fn tutorial_song() -> Song {
+    parse_tja_file(TUTORIAL_CHART).expect("the tutorial chart should be valid")
+}
+
+/// Keeps track of which step of the tutorial the player is on.
+#[derive(Debug, Clone, Default)]
+pub struct Tutorial {
+    step: usize,
+}
+
+impl Tutorial {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves on to whichever step the given note time is in.
+    pub fn update(&mut self, time: f32) {
+        while TUTORIAL_STEPS
+            .get(self.step + 1)
+            .is_some_and(|step| step.time <= time)
+        {
+            self.step += 1;
+        }
+    }
+
+    pub fn step(&self) -> &'static TutorialStep {
+        &TUTORIAL_STEPS[self.step]
+    }
+
+    /// Whether the clock should stop for a note at the given time.
+    pub fn waits_for(&self, note_time: f32) -> bool {
+        TUTORIAL_STEPS
+            .iter()
+            .rev()
+            .find(|step| step.time <= note_time)
+            .is_some_and(|step| step.wait)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_steps_are_in_order() {
+        for pair in TUTORIAL_STEPS.windows(2) {
+            assert!(
+                pair[0].time < pair[1].time,
+                "\"{}\" comes after \"{}\"",
+                pair[0].text,
+                pair[1].text
+            );
+        }
+    }
+
+    #[test]
+    fn test_steps_match_chart() {
+        let song = tutorial_song();
+        let notes = &song.difficulties[0].as_ref().unwrap().chart.notes;
+
+        // Every step that waits should have something to wait for
+        for (i, step) in TUTORIAL_STEPS.iter().enumerate() {
+            let end = TUTORIAL_STEPS
+                .get(i + 1)
+                .map_or(f32::INFINITY, |next| next.time);
+            let step_notes = notes
+                .iter()
+                .filter(|note| (step.time..end).contains(&note.time));
+
+            if step.wait {
+                assert!(step_notes.count() > 0, "\"{}\" has no notes", step.text);
+            }
+        }
+
+        // Nothing should happen before the player has had a chance to read the first step
+        assert!(notes.iter().all(|note| note.time >= 2.0));
+    }
+
+    #[test]
+    fn test_current_step() {
+        let mut tutorial = Tutorial::new();
+        assert_eq!(tutorial.step(), &TUTORIAL_STEPS[0]);
+
+        tutorial.update(8.0);
+        assert_eq!(tutorial.step(), &TUTORIAL_STEPS[2]);
+        assert!(tutorial.waits_for(8.0));
+        assert!(!tutorial.waits_for(20.0));
+    }
+}
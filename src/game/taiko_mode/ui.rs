@@ -1,17 +1,27 @@
-use crate::game::taiko_mode::scene::NoteJudgement;
+use crate::game::layout::{
+    clamp_text_centre, safe_area_inset, Anchor, HEADER_ACCURACY, HEADER_BADGE, HEADER_GAUGE,
+    HEADER_HEIGHT, HEADER_SCORE, HEADER_SUBTITLE, HEADER_TITLE, KEY_DISPLAY, LEFT_PANEL_WIDTH,
+    NOTE_FIELD_HEIGHT, NOTE_FIELD_Y, NOTE_HIT_X, NOTE_Y, SPACER_WIDTH,
+};
+use crate::game::taiko_mode::scene::{NoteJudgement, ScoreInt};
+use crate::game::taiko_mode::scoring::{format_accuracy, Rally, GAUGE_CLEAR, GAUGE_MAX};
+use crate::game::time::EffectTimer;
 use crate::game::{RenderContext, TextureCache};
+use crate::notechart_parser::SectionLabel;
 use crate::render::shapes::{LinearGradient, Shape, ShapeBuilder, SolidColour};
 use crate::render::text::BuildTextWithRenderer;
 use crate::render::texture::{AnimatedSprite, AnimatedSpriteBuilder, Frame, Sprite, SpriteBuilder};
-use crate::render::{rgb, Renderable, Renderer};
+use crate::render::{rgb, Renderable, Renderer, TITLE_FONTS};
+use crate::settings::{settings, DrumInput, JudgementPosition};
 use kaku::{FontSize, HorizontalAlignment, Text, TextBuilder, VerticalAlignment};
 use lyon::geom::point;
 use lyon::lyon_tessellation::{BuffersBuilder, StrokeOptions};
 use lyon::path::Path;
-use std::time::Instant;
 use wgpu::RenderPass;
 
-use super::note::{TaikoModeBarline, TaikoModeNote};
+use super::note::{
+    timing_window_width, BasicNoteType, TaikoModeBarline, TaikoModeNote, BAD, GOOD, OK,
+};
 
 // Colours
 pub const HEADER_TOP_COL: [f32; 4] = [30. / 255., 67. / 255., 198. / 255., 1.];
@@ -19,90 +29,405 @@ pub const HEADER_BOTTOM_COL: [f32; 4] = [150. / 255., 90. / 255., 225. / 255., 1
 pub const NOTE_FIELD_COL: [f32; 4] = [45. / 255., 45. / 255., 45. / 255., 1.];
 pub const CREAM: [f32; 4] = [1., 235. / 255., 206. / 255., 1.];
 pub const RECEPTACLE_COL: [f32; 4] = [0.26, 0.26, 0.26, 1.0];
+/// Laid over the note field in gogo time.
+const GOGO_FIELD_COL: [f32; 4] = [1., 110. / 255., 30. / 255., 0.25];
 pub const LEFT_PANEL_TOP_COL: [f32; 4] = [1., 73. / 255., 73. / 255., 1.];
 pub const LEFT_PANEL_BOTTOM_COL: [f32; 4] = [229. / 255., 41. / 255., 41. / 255., 1.];
 
-// Positions.
-// TODO: Replace this system something more sophisticated that respects resolution
-pub const HEADER_HEIGHT: f32 = 315.;
-pub const SPACER_WIDTH: f32 = 8.;
-pub const NOTE_FIELD_Y: f32 = HEADER_HEIGHT + SPACER_WIDTH;
-// The point on the screen where notes should be hit
-pub const NOTE_HIT_X: f32 = 690.;
-// The Y value where notes should be drawn
-pub const NOTE_Y: f32 = NOTE_FIELD_Y + NOTE_FIELD_HEIGHT / 2.0;
-pub const NOTE_FIELD_HEIGHT: f32 = 232.;
-pub const LEFT_PANEL_WIDTH: f32 = 480.;
+/// The widest the song title in the header can be.
+pub const HEADER_TITLE_MAX_WIDTH: f32 = 1840.;
+const HEADER_TITLE_SIZE: f32 = 80.;
+const HEADER_TITLE_OUTLINE: f32 = 5.;
+const HEADER_SUBTITLE_SIZE: f32 = 36.;
+const HEADER_SUBTITLE_OUTLINE: f32 = 3.;
 
 pub struct Header {
     background: Shape,
     title: Text,
+    subtitle: Option<Text>,
+    accuracy: Text,
+    /// The accuracy that's currently being shown, so the text is only rebuilt when it changes.
+    accuracy_string: String,
+    score: Text,
+    /// The score that's currently being shown, so the text is only rebuilt when it changes.
+    shown_score: ScoreInt,
+    gauge: SoulGauge,
 }
 
 impl Header {
-    pub fn new(renderer: &mut Renderer, title: &str) -> anyhow::Result<Self> {
+    /// Creates the header. `y_offset` moves it down the screen, for player 2's header in a 2P
+    /// battle (see [PLAYER_2_HEADER_OFFSET](crate::game::layout::PLAYER_2_HEADER_OFFSET)).
+    pub fn new(
+        renderer: &mut Renderer,
+        title: &str,
+        subtitle: Option<&str>,
+        y_offset: f32,
+    ) -> anyhow::Result<Self> {
         let background = ShapeBuilder::new()
             .filled_rectangle(
-                [0., 0.],
-                [1920., HEADER_HEIGHT],
+                [0., y_offset],
+                [1920., y_offset + HEADER_HEIGHT],
                 LinearGradient::new(
                     HEADER_TOP_COL,
                     HEADER_BOTTOM_COL,
-                    [0., 0.],
-                    [0., HEADER_HEIGHT],
+                    [0., y_offset],
+                    [0., y_offset + HEADER_HEIGHT],
                 )
                 .ok_or(anyhow::format_err!("cant construct linear gradient"))?,
             )?
             .build(&renderer.device);
 
-        let title = TextBuilder::new(title, renderer.font("mochiy pop one"), [1880., 20.])
+        let margin = settings().visual.safe_area_margin();
+        let position = |anchor: Anchor| {
+            let [x, y] = anchor.position(margin);
+            [x, y + y_offset]
+        };
+
+        let (title_font, title) = renderer.fit_text(
+            &TITLE_FONTS,
+            title,
+            HEADER_TITLE_SIZE,
+            HEADER_TITLE_OUTLINE,
+            HEADER_TITLE_MAX_WIDTH - 2. * safe_area_inset(margin)[0],
+        );
+        let title = TextBuilder::new(title, title_font, position(HEADER_TITLE))
             .horizontal_align(HorizontalAlignment::Right)
             .vertical_align(VerticalAlignment::Top)
-            .font_size(Some(FontSize::Px(80.)))
+            .font_size(Some(FontSize::Px(HEADER_TITLE_SIZE)))
+            .color([1.0; 4])
+            .outlined([0., 0., 0., 1.], HEADER_TITLE_OUTLINE)
+            .build_text(renderer);
+
+        let subtitle = subtitle.map(|subtitle| {
+            let (font, subtitle) = renderer.fit_text(
+                &TITLE_FONTS,
+                subtitle,
+                HEADER_SUBTITLE_SIZE,
+                HEADER_SUBTITLE_OUTLINE,
+                HEADER_TITLE_MAX_WIDTH - 2. * safe_area_inset(margin)[0],
+            );
+            TextBuilder::new(subtitle, font, position(HEADER_SUBTITLE))
+                .horizontal_align(HorizontalAlignment::Right)
+                .vertical_align(VerticalAlignment::Top)
+                .font_size(Some(FontSize::Px(HEADER_SUBTITLE_SIZE)))
+                .color([1.0; 4])
+                .outlined([0., 0., 0., 1.], HEADER_SUBTITLE_OUTLINE)
+                .build_text(renderer)
+        });
+
+        // The HUD is a little bigger in stream mode, so it's easier to read on a stream
+        let accuracy_size = if settings().visual.stream_mode {
+            52.
+        } else {
+            40.
+        };
+
+        let accuracy_string = format_accuracy(None);
+        let accuracy = TextBuilder::new(
+            &accuracy_string,
+            renderer.font("mochiy pop one"),
+            position(HEADER_ACCURACY),
+        )
+        .horizontal_align(HorizontalAlignment::Right)
+        .vertical_align(VerticalAlignment::Top)
+        .font_size(Some(FontSize::Px(accuracy_size)))
+        .color([1.0; 4])
+        .outlined([0., 0., 0., 1.], 3.)
+        .build_text(renderer);
+
+        let score = TextBuilder::new("0", renderer.font("mochiy pop one"), position(HEADER_SCORE))
+            .horizontal_align(HorizontalAlignment::Right)
+            .vertical_align(VerticalAlignment::Top)
+            .font_size(Some(FontSize::Px(accuracy_size)))
             .color([1.0; 4])
-            .outlined([0., 0., 0., 1.], 5.)
+            .outlined([0., 0., 0., 1.], 3.)
             .build_text(renderer);
 
-        Ok(Self { background, title })
+        Ok(Self {
+            background,
+            title,
+            subtitle,
+            accuracy,
+            accuracy_string,
+            score,
+            shown_score: 0,
+            gauge: SoulGauge::new(renderer, position(HEADER_GAUGE))?,
+        })
+    }
+
+    /// Shows the given accuracy (see [PlayResult::accuracy](super::PlayResult::accuracy)).
+    pub fn set_accuracy(&mut self, accuracy: Option<f32>, renderer: &mut Renderer) {
+        let accuracy_string = format_accuracy(accuracy);
+        if accuracy_string == self.accuracy_string {
+            return;
+        }
+
+        self.accuracy.set_text(
+            accuracy_string.clone(),
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+        self.accuracy_string = accuracy_string;
+    }
+
+    /// Shows the given score (see [PlayResult::score](super::PlayResult::score)).
+    pub fn set_score(&mut self, score: ScoreInt, renderer: &mut Renderer) {
+        if score == self.shown_score {
+            return;
+        }
+
+        self.score.set_text(
+            score.to_string(),
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+        self.shown_score = score;
     }
 
-    pub fn render<'pass>(&'pass mut self, ctx: &mut RenderContext<'_, 'pass>) {
+    /// Shows how full the soul gauge is (see [PlayResult::gauge](super::PlayResult::gauge)).
+    pub fn set_gauge(&mut self, gauge: f32, renderer: &Renderer) -> anyhow::Result<()> {
+        self.gauge.set_gauge(gauge, renderer)
+    }
+
+    /// Draws the header. The score and soul gauge are left out if `show_score` is false, e.g. in
+    /// practice mode.
+    pub fn render<'pass>(&'pass mut self, ctx: &mut RenderContext<'_, 'pass>, show_score: bool) {
         ctx.render(&self.background);
         ctx.render(&self.title);
+        if let Some(subtitle) = &self.subtitle {
+            ctx.render(subtitle);
+        }
+        ctx.render(&self.accuracy);
+        if show_score {
+            ctx.render(&self.score);
+            ctx.render(&self.gauge.shape);
+        }
+    }
+}
+
+const REPLAY_BADGE_SIZE: f32 = 48.;
+const REPLAY_BADGE_COL: [f32; 4] = rgb!(0xFF, 0xCA, 0x0E);
+
+/// The "REPLAY" badge shown in the header while a replay is played back.
+pub fn replay_badge(renderer: &mut Renderer) -> Text {
+    let margin = settings().visual.safe_area_margin();
+    TextBuilder::new(
+        "REPLAY",
+        renderer.font("mochiy pop one"),
+        HEADER_BADGE.position(margin),
+    )
+    .horizontal_align(HorizontalAlignment::Left)
+    .vertical_align(VerticalAlignment::Top)
+    .font_size(Some(FontSize::Px(REPLAY_BADGE_SIZE)))
+    .color(REPLAY_BADGE_COL)
+    .outlined([0., 0., 0., 1.], 3.)
+    .build_text(renderer)
+}
+
+const GAUGE_SEGMENTS: usize = 50;
+const GAUGE_WIDTH: f32 = 700.;
+const GAUGE_HEIGHT: f32 = 24.;
+const GAUGE_SEGMENT_GAP: f32 = 2.;
+const GAUGE_EMPTY_COL: [f32; 4] = [0.1, 0.1, 0.1, 0.8];
+/// Empty segments past the clear line are tinted, so the player can see where it is.
+const GAUGE_EMPTY_CLEAR_COL: [f32; 4] = [0.3, 0.24, 0.05, 0.8];
+const GAUGE_FILL_COL: [f32; 4] = [1., 60. / 255., 60. / 255., 1.];
+const GAUGE_CLEAR_FILL_COL: [f32; 4] = [1., 202. / 255., 14. / 255., 1.];
+
+/// How many segments of the soul gauge are filled when it's this full.
+fn filled_gauge_segments(gauge: f32) -> usize {
+    ((gauge / GAUGE_MAX * GAUGE_SEGMENTS as f32).floor() as usize).min(GAUGE_SEGMENTS)
+}
+
+/// The colour of one segment of the soul gauge, given how many segments are filled. Segments past
+/// the clear line are a different colour, and a full gauge is a rainbow.
+fn gauge_segment_colour(segment: usize, filled: usize) -> [f32; 4] {
+    let past_clear = segment >= filled_gauge_segments(GAUGE_CLEAR);
+
+    if filled == GAUGE_SEGMENTS {
+        rainbow(segment as f32 / GAUGE_SEGMENTS as f32)
+    } else if segment < filled && past_clear {
+        GAUGE_CLEAR_FILL_COL
+    } else if segment < filled {
+        GAUGE_FILL_COL
+    } else if past_clear {
+        GAUGE_EMPTY_CLEAR_COL
+    } else {
+        GAUGE_EMPTY_COL
+    }
+}
+
+/// A fully saturated colour with the given hue, from 0 to 1.
+fn rainbow(hue: f32) -> [f32; 4] {
+    let channel = |offset: f32| {
+        let h = (hue * 6. + offset) % 6.;
+        (h - 3.).abs().clamp(1., 2.) - 1.
+    };
+
+    [channel(0.), channel(4.), channel(2.), 1.]
+}
+
+/// The soul gauge, as a row of segments in the header.
+struct SoulGauge {
+    shape: Shape,
+    /// The top right corner of the gauge.
+    position: [f32; 2],
+    /// How many segments are being shown filled, so the shape is only rebuilt when it changes.
+    filled: usize,
+}
+
+impl SoulGauge {
+    fn new(renderer: &Renderer, position: [f32; 2]) -> anyhow::Result<Self> {
+        Ok(Self {
+            shape: Self::build(renderer, position, 0)?,
+            position,
+            filled: 0,
+        })
+    }
+
+    fn build(renderer: &Renderer, [right, top]: [f32; 2], filled: usize) -> anyhow::Result<Shape> {
+        let segment_width = GAUGE_WIDTH / GAUGE_SEGMENTS as f32;
+        let left = right - GAUGE_WIDTH;
+        let mut builder = ShapeBuilder::new();
+
+        for segment in 0..GAUGE_SEGMENTS {
+            let x = left + segment as f32 * segment_width;
+            builder = builder.filled_rectangle(
+                [x, top],
+                [x + segment_width - GAUGE_SEGMENT_GAP, top + GAUGE_HEIGHT],
+                SolidColour::new(gauge_segment_colour(segment, filled)),
+            )?;
+        }
+
+        Ok(builder.build(&renderer.device))
+    }
+
+    fn set_gauge(&mut self, gauge: f32, renderer: &Renderer) -> anyhow::Result<()> {
+        let filled = filled_gauge_segments(gauge);
+        if filled != self.filled {
+            self.shape = Self::build(renderer, self.position, filled)?;
+            self.filled = filled;
+        }
+
+        Ok(())
+    }
+}
+
+/// Creates the background shown behind the game and the results, and the shape that dims it.
+pub fn dimmed_background(
+    renderer: &mut Renderer,
+    textures: &mut TextureCache,
+) -> anyhow::Result<(Sprite, Shape)> {
+    let bg_texture = textures.get(&renderer.device, &renderer.queue, "song_select_bg.jpg")?;
+    let background = SpriteBuilder::new(bg_texture).build(renderer);
+
+    let background_dim = ShapeBuilder::new()
+        .filled_rectangle(
+            [0., 0.],
+            [1920., 1080.],
+            SolidColour::new([0., 0., 0., 0.6]),
+        )?
+        .build(&renderer.device);
+
+    Ok((background, background_dim))
+}
+
+const TIMING_GOOD_BAND_COL: [f32; 4] = [1., 202. / 255., 14. / 255., 0.35];
+const TIMING_OK_BAND_COL: [f32; 4] = [1., 1., 1., 0.2];
+const TIMING_BAD_BAND_COL: [f32; 4] = [1., 60. / 255., 60. / 255., 0.2];
+
+/// Translucent bands around the receptacle that show how big the timing windows are.
+///
+/// The bands depend on the scroll speed of the notes reaching the receptacle, so they're rebuilt
+/// whenever that changes.
+pub struct TimingWindowBands {
+    timing_windows: [f32; 3],
+    scroll_speed: f32,
+    bands: Option<Shape>,
+}
+
+impl TimingWindowBands {
+    pub fn new(timing_windows: &[f32; 3]) -> Self {
+        Self {
+            timing_windows: *timing_windows,
+            scroll_speed: 1.0,
+            bands: None,
+        }
+    }
+
+    /// Makes sure the bands match the given scroll speed.
+    pub fn update(&mut self, renderer: &Renderer, scroll_speed: f32) -> anyhow::Result<()> {
+        if self.bands.is_some() && scroll_speed == self.scroll_speed {
+            return Ok(());
+        }
+
+        let mut builder = ShapeBuilder::new();
+
+        // Widest first, so the narrower bands are drawn on top
+        for (window, colour) in [
+            (self.timing_windows[BAD], TIMING_BAD_BAND_COL),
+            (self.timing_windows[OK], TIMING_OK_BAND_COL),
+            (self.timing_windows[GOOD], TIMING_GOOD_BAND_COL),
+        ] {
+            let half_width = timing_window_width(window, scroll_speed) / 2.0;
+            builder = builder.filled_rectangle(
+                [NOTE_HIT_X - half_width, NOTE_FIELD_Y],
+                [NOTE_HIT_X + half_width, NOTE_FIELD_Y + NOTE_FIELD_HEIGHT],
+                SolidColour::new(colour),
+            )?;
+        }
+
+        self.bands = Some(builder.build(&renderer.device));
+        self.scroll_speed = scroll_speed;
+        Ok(())
+    }
+}
+
+impl Renderable for TimingWindowBands {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        if let Some(bands) = &self.bands {
+            bands.render(renderer, render_pass);
+        }
     }
 }
 
 pub struct NoteField {
     field: Shape,
+    gogo_tint: Shape,
     left_panel: Shape,
+    hit_feedback: HitFeedback,
 }
 
 impl NoteField {
-    pub fn new(renderer: &mut Renderer) -> anyhow::Result<Self> {
+    /// Creates the note field. `y_offset` moves it down the screen, for player 2's note field in a
+    /// 2P battle (see [PLAYER_2_FIELD_OFFSET](crate::game::layout::PLAYER_2_FIELD_OFFSET)).
+    pub fn new(renderer: &mut Renderer, y_offset: f32) -> anyhow::Result<Self> {
+        let top = NOTE_FIELD_Y + y_offset;
+        let bottom = top + NOTE_FIELD_HEIGHT;
+        let note_y = NOTE_Y + y_offset;
+
         let field = ShapeBuilder::new()
             // Background
-            .filled_rectangle(
-                [0., NOTE_FIELD_Y],
-                [1920., NOTE_FIELD_Y + NOTE_FIELD_HEIGHT],
-                SolidColour::new(NOTE_FIELD_COL),
-            )?
+            .filled_rectangle([0., top], [1920., bottom], SolidColour::new(NOTE_FIELD_COL))?
             // Top spacer
             .filled_rectangle(
-                [0., HEADER_HEIGHT],
-                [1920., HEADER_HEIGHT + SPACER_WIDTH],
+                [0., top - SPACER_WIDTH],
+                [1920., top],
                 SolidColour::new(CREAM),
             )?
             // Bottom spacer
             .filled_rectangle(
-                [0., NOTE_FIELD_Y + NOTE_FIELD_HEIGHT],
-                [1920., NOTE_FIELD_Y + NOTE_FIELD_HEIGHT + SPACER_WIDTH],
+                [0., bottom],
+                [1920., bottom + SPACER_WIDTH],
                 SolidColour::new(CREAM),
             )?
             // Note recepticle
             .stroke_shape(|tess, out| {
                 let mut path = Path::builder();
-                path.begin(point(NOTE_HIT_X, NOTE_Y - NOTE_FIELD_HEIGHT / 2.0));
-                path.line_to(point(NOTE_HIT_X, NOTE_Y + NOTE_FIELD_HEIGHT / 2.0));
+                path.begin(point(NOTE_HIT_X, top));
+                path.line_to(point(NOTE_HIT_X, bottom));
                 path.end(false);
 
                 let options = StrokeOptions::DEFAULT.with_line_width(4.0);
@@ -112,62 +437,150 @@ impl NoteField {
                 tess.tessellate_path(&path.build(), &options, &mut builder)?;
 
                 // The outline of a small note
-                tess.tessellate_circle(point(NOTE_HIT_X, NOTE_Y), 50.0, &options, &mut builder)?;
+                tess.tessellate_circle(point(NOTE_HIT_X, note_y), 50.0, &options, &mut builder)?;
 
                 // The outline of a large note
-                tess.tessellate_circle(point(NOTE_HIT_X, NOTE_Y), 75.0, &options, &mut builder)?;
+                tess.tessellate_circle(point(NOTE_HIT_X, note_y), 75.0, &options, &mut builder)?;
 
                 Ok(())
             })?
             .build(&renderer.device);
 
+        let gogo_tint = ShapeBuilder::new()
+            .filled_rectangle([0., top], [1920., bottom], SolidColour::new(GOGO_FIELD_COL))?
+            .build(&renderer.device);
+
         let left_panel = ShapeBuilder::new()
             .filled_rectangle(
-                [0.0, NOTE_FIELD_Y],
-                [LEFT_PANEL_WIDTH, NOTE_FIELD_Y + NOTE_FIELD_HEIGHT],
+                [0.0, top],
+                [LEFT_PANEL_WIDTH, bottom],
                 LinearGradient::new(
                     LEFT_PANEL_TOP_COL,
                     LEFT_PANEL_BOTTOM_COL,
-                    [0.0, NOTE_FIELD_Y],
-                    [0.0, NOTE_FIELD_Y + NOTE_FIELD_HEIGHT],
+                    [0.0, top],
+                    [0.0, bottom],
                 )
                 .ok_or(anyhow::format_err!("couldnt construct linear gradient"))?,
             )?
             .filled_rectangle(
-                [LEFT_PANEL_WIDTH, NOTE_FIELD_Y],
-                [LEFT_PANEL_WIDTH + 3., NOTE_FIELD_Y + NOTE_FIELD_HEIGHT],
+                [LEFT_PANEL_WIDTH, top],
+                [LEFT_PANEL_WIDTH + 3., bottom],
                 SolidColour::new([0., 0., 0., 1.]),
             )?
             .build(&renderer.device);
 
-        Ok(Self { field, left_panel })
+        Ok(Self {
+            field,
+            gogo_tint,
+            left_panel,
+            hit_feedback: HitFeedback::new(renderer, y_offset)?,
+        })
+    }
+
+    /// Shows that a note was judged, starting at the given gameplay time (see [HitFeedback]).
+    pub fn judge(&mut self, judgement: NoteJudgement, now: f32) {
+        self.hit_feedback.judge(judgement, now);
     }
 
+    /// Animates the hit feedback. `now` should be the current gameplay time.
+    pub fn update(&mut self, renderer: &Renderer, now: f32) {
+        self.hit_feedback.update(renderer, now);
+    }
+
+    /// Renders the note field with the given notes and barlines. If there are timing window bands,
+    /// they're drawn underneath everything else. The field is tinted during gogo time.
     pub fn render<'pass>(
         &'pass mut self,
         ctx: &mut RenderContext<'_, 'pass>,
-        notes: impl Iterator<Item = &'pass TaikoModeNote>,
+        notes: impl DoubleEndedIterator<Item = &'pass TaikoModeNote>,
         barlines: impl Iterator<Item = &'pass TaikoModeBarline>,
+        timing_bands: Option<&'pass TimingWindowBands>,
+        gogo: bool,
     ) {
         ctx.render(&self.field);
 
+        if gogo {
+            ctx.render(&self.gogo_tint);
+        }
+
+        if let Some(bands) = timing_bands {
+            ctx.render(bands);
+        }
+
         // Thankfully barlines are all drawn before all the notes
         // so we don't have to worry about ordering shenanigans :D
         for b in barlines {
             ctx.render(b);
         }
 
-        for n in notes {
+        // Later notes are drawn first, so notes that are fading in or out (with the hidden and
+        // sudden modifiers) show the ones behind them
+        for n in notes.rev() {
             ctx.render(n);
         }
 
         ctx.render(&self.left_panel);
+        ctx.render(&self.hit_feedback);
+    }
+}
+
+const DON_MARKER_COL: [f32; 4] = [1., 73. / 255., 73. / 255., 0.5];
+const KAT_MARKER_COL: [f32; 4] = [73. / 255., 160. / 255., 1., 0.5];
+
+/// A faint marker at the right edge of the note field that warns the player about a note that's
+/// coming in too fast to see.
+///
+/// The marker is half a circle, the same size and colour as the note it's warning about.
+pub struct IncomingNoteMarker {
+    /// The markers for small and big dons.
+    don: [Shape; 2],
+    /// The markers for small and big kats.
+    kat: [Shape; 2],
+}
+
+impl IncomingNoteMarker {
+    pub fn new(renderer: &Renderer) -> anyhow::Result<Self> {
+        let marker = |colour, radius| -> anyhow::Result<Shape> {
+            Ok(ShapeBuilder::new()
+                .filled_circle([1920., NOTE_Y], radius, SolidColour::new(colour))?
+                .build(&renderer.device))
+        };
+
+        Ok(Self {
+            don: [marker(DON_MARKER_COL, 50.)?, marker(DON_MARKER_COL, 75.)?],
+            kat: [marker(KAT_MARKER_COL, 50.)?, marker(KAT_MARKER_COL, 75.)?],
+        })
+    }
+
+    /// Renders the marker for the given kind of note, if there is one.
+    pub fn render<'pass>(
+        &'pass self,
+        ctx: &mut RenderContext<'_, 'pass>,
+        incoming: Option<BasicNoteType>,
+    ) {
+        if let Some(kind) = incoming {
+            let markers = if kind.is_don() { &self.don } else { &self.kat };
+            ctx.render(&markers[kind.is_big() as usize]);
+        }
     }
 }
 
-const JUDGEMENT_TEXT_DISPLAY_TIME: f32 = 0.5;
-const JUDGEMENT_TEXT_Y: f32 = NOTE_Y - 50.;
+const JUDGEMENT_TEXT_DISPLAY_TIME: f32 = 0.3;
+/// How many copies of each judgement's text there are (see [JudgementText]).
+const JUDGEMENT_TEXT_COPIES: usize = 3;
+/// How much of its display time the text takes to pop in.
+const JUDGEMENT_TEXT_POP_IN: f32 = 0.2;
+/// How far below where it floats from the text starts when it pops in.
+const JUDGEMENT_TEXT_POP_DROP: f32 = 12.;
+/// How far through its display time the text starts to fade out.
+const JUDGEMENT_TEXT_FADE_START: f32 = 0.6;
+/// The size of the judgement text before it's scaled up by the player's settings.
+pub const JUDGEMENT_TEXT_SIZE: f32 = 30.;
+const JUDGEMENT_TEXT_OUTLINE: f32 = 3.;
 const JUDGEMENT_TEXT_FLOAT_DIST: f32 = -20.;
+/// How far the text floats up by the time it disappears, as a multiple of
+/// [JUDGEMENT_TEXT_FLOAT_DIST].
+const JUDGEMENT_TEXT_FLOAT_EXTENT: f32 = 0.916_290_7; // ln(2.5)
 const JUDGEMENT_TEXT_GOOD_COLOUR: [f32; 4] = [1., 202. / 255., 14. / 255., 1.];
 const JUDGEMENT_TEXT_GOOD_OUTLINE_COLOUR: [f32; 4] = [37. / 255., 29. / 255., 0., 1.];
 const JUDGEMENT_TEXT_OK_COLOUR: [f32; 4] = [1.; 4];
@@ -175,29 +588,83 @@ const JUDGEMENT_TEXT_OK_OUTLINE_COLOUR: [f32; 4] = [21. / 255., 21. / 255., 21.
 const JUDGEMENT_TEXT_BAD_COLOUR: [f32; 4] = [46. / 255., 103. / 255., 209. / 255., 1.];
 const JUDGEMENT_TEXT_BAD_OUTLINE_COLOUR: [f32; 4] = [0., 0., 0., 1.];
 
+/// Where the bottom middle of the judgement text goes for each position, before it's moved to fit
+/// on the screen.
+fn judgement_anchor(position: JudgementPosition) -> [f32; 2] {
+    match position {
+        JudgementPosition::Receptacle => [NOTE_HIT_X, NOTE_Y - 20.],
+        // Clear of the outline of a big note
+        JudgementPosition::AboveField => [NOTE_HIT_X, NOTE_Y - 80.],
+        JudgementPosition::ScreenCentre => [960., 540.],
+    }
+}
+
+/// Works out where the centre of a piece of judgement text goes, given its size (width and height)
+/// and the player's settings.
+pub fn judgement_text_centre(
+    position: JudgementPosition,
+    scale: f32,
+    size: [f32; 2],
+    margin: f32,
+) -> [f32; 2] {
+    let [x, bottom] = judgement_anchor(position);
+    let rise = -JUDGEMENT_TEXT_FLOAT_DIST * scale * JUDGEMENT_TEXT_FLOAT_EXTENT;
+
+    clamp_text_centre([x, bottom - size[1] / 2.], size, rise, margin)
+}
+
 // TODO: Japanese localisation
 /// A UI element that displays some text indicating how well the player hit the last note.
-/// The text is displayed for a short time while moving upwards, and becomes transparent as it ages.
+/// The text pops in, then floats upwards for a short time and fades out.
+///
+/// There are a few copies of each judgement's text, so a quick run of the same judgement doesn't
+/// cut the last one off.
 pub struct JudgementText {
-    judgement_sprites: [Text; 3],
-    /// Contains the index of the current sprite, and the moment it was instantiated, or None if
-    /// there's no currently visible sprite.
-    current_sprite: Option<(usize, Instant)>,
+    /// The copies of each judgement's text, each with the time it was shown if it's showing.
+    judgement_sprites: [Vec<(Text, Option<EffectTimer>)>; 3],
+    /// The copy of each judgement's text to show next.
+    next_copy: [usize; 3],
+    /// Where the centre of each judgement's text starts out.
+    origins: [[f32; 2]; 3],
+    colours: [[f32; 4]; 3],
+    /// How much the text is scaled up by.
+    scale: f32,
 }
 
 impl JudgementText {
-    pub fn new(renderer: &mut Renderer) -> Self {
-        let mut build_judgement_text = |text, colour, outline_colour| {
-            TextBuilder::new(
-                text,
-                renderer.font("mochiy pop one"),
-                [NOTE_HIT_X, JUDGEMENT_TEXT_Y],
+    /// Creates the text for the note field `y_offset` down from player 1's.
+    pub fn new(renderer: &mut Renderer, y_offset: f32) -> Self {
+        let (scale, position, margin) = {
+            let visual = &settings().visual;
+            (
+                visual.judgement_scale(),
+                visual.judgement_position,
+                visual.safe_area_margin(),
             )
-            .font_size(Some(FontSize::Px(30.)))
-            .horizontal_align(HorizontalAlignment::Center)
-            .color(colour)
-            .outlined(outline_colour, 3.)
-            .build_text(renderer)
+        };
+        let size = JUDGEMENT_TEXT_SIZE * scale;
+        let mut origins = Vec::with_capacity(3);
+
+        let mut build_judgement_text = |text, colour, outline_colour| {
+            let width = renderer.text_width("mochiy pop one", text, size);
+            let outline = JUDGEMENT_TEXT_OUTLINE * 2.;
+            let [x, y] =
+                judgement_text_centre(position, scale, [width + outline, size + outline], margin);
+            let origin = [x, y + y_offset];
+            origins.push(origin);
+
+            (0..JUDGEMENT_TEXT_COPIES)
+                .map(|_| {
+                    let text = TextBuilder::new(text, renderer.font("mochiy pop one"), origin)
+                        .font_size(Some(FontSize::Px(size)))
+                        .horizontal_align(HorizontalAlignment::Center)
+                        .vertical_align(VerticalAlignment::Middle)
+                        .color(colour)
+                        .outlined(outline_colour, JUDGEMENT_TEXT_OUTLINE)
+                        .build_text(renderer);
+                    (text, None)
+                })
+                .collect()
         };
 
         let judgement_sprites = [
@@ -220,41 +687,561 @@ impl JudgementText {
 
         Self {
             judgement_sprites,
-            current_sprite: None,
+            next_copy: [0; 3],
+            origins: [origins[0], origins[1], origins[2]],
+            colours: [
+                JUDGEMENT_TEXT_GOOD_COLOUR,
+                JUDGEMENT_TEXT_OK_COLOUR,
+                JUDGEMENT_TEXT_BAD_COLOUR,
+            ],
+            scale,
         }
     }
 
-    pub fn display_judgement(&mut self, judgement: NoteJudgement) {
+    /// Displays the text for the given judgement, starting at the given gameplay time.
+    pub fn display_judgement(&mut self, judgement: NoteJudgement, now: f32) {
         let index = judgement.index();
-        self.current_sprite = Some((index, Instant::now()));
+        let copy = self.next_copy[index];
+        self.judgement_sprites[index][copy].1 =
+            Some(EffectTimer::start(now, JUDGEMENT_TEXT_DISPLAY_TIME));
+        self.next_copy[index] = (copy + 1) % JUDGEMENT_TEXT_COPIES;
     }
 
-    pub fn update(&mut self, renderer: &Renderer) {
-        if let Some((index, instant)) = self.current_sprite {
-            let elapsed = instant.elapsed().as_secs_f32();
-            if elapsed > JUDGEMENT_TEXT_DISPLAY_TIME {
-                // Time's up, so just disappear
-                self.current_sprite = None;
-                return;
-            }
+    /// Animates the text. `now` should be the current gameplay time, so the text freezes while
+    /// gameplay is paused.
+    pub fn update(&mut self, renderer: &Renderer, now: f32) {
+        for (index, copies) in self.judgement_sprites.iter_mut().enumerate() {
+            for (text, shown) in copies.iter_mut() {
+                let Some(timer) = *shown else {
+                    continue;
+                };
 
-            let progress = elapsed / JUDGEMENT_TEXT_DISPLAY_TIME;
-            let y = JUDGEMENT_TEXT_Y + JUDGEMENT_TEXT_FLOAT_DIST * (progress * 1.5 + 1.).ln();
-            // This sets the position of the text relative to the starting position
-            self.judgement_sprites[index].set_position([NOTE_HIT_X, y], &renderer.queue);
-            // TODO: set transparency using a colour tint
+                let Some(progress) = timer.progress(now) else {
+                    // Time's up, so just disappear
+                    *shown = None;
+                    continue;
+                };
+
+                let [x, y] = self.origins[index];
+                let float = JUDGEMENT_TEXT_FLOAT_DIST * self.scale * (progress * 1.5 + 1.).ln();
+                let pop = JUDGEMENT_TEXT_POP_DROP
+                    * self.scale
+                    * (1. - progress / JUDGEMENT_TEXT_POP_IN).max(0.).powi(2);
+                text.set_position([x, y + float + pop], &renderer.queue);
+
+                let alpha = 1.0
+                    - ((progress - JUDGEMENT_TEXT_FADE_START) / (1.0 - JUDGEMENT_TEXT_FADE_START))
+                        .max(0.0);
+                let [r, g, b, _] = self.colours[index];
+                text.set_color([r, g, b, alpha], &renderer.queue);
+            }
         }
     }
 }
 
 impl Renderable for JudgementText {
     fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
-        if let Some((index, _)) = self.current_sprite {
-            self.judgement_sprites[index].render(renderer, render_pass);
+        for (text, shown) in self.judgement_sprites.iter().flatten() {
+            if shown.is_some() {
+                text.render(renderer, render_pass);
+            }
+        }
+    }
+}
+
+/// How long a hit note takes to fly to the soul gauge, in seconds.
+const FLYING_NOTE_TIME: f32 = 0.5;
+/// How far through its flight a note starts to fade out.
+const FLYING_NOTE_FADE_START: f32 = 0.7;
+/// How far above the higher end of the flight the arc's control point is.
+const FLYING_NOTE_ARC_HEIGHT: f32 = 250.;
+/// How many of each kind of note can be flying at once. Half a second of a dense chart can have a
+/// lot of notes in it.
+const FLYING_NOTE_COUNT: usize = 24;
+/// The notes that fly, in the order they're kept in [FlyingNotes].
+const FLYING_NOTE_TEXTURES: [&str; 4] = ["don.png", "kat.png", "big_don.png", "big_kat.png"];
+
+/// A point along a quadratic Bézier curve from `start` to `end`, `t` (from 0 to 1) of the way
+/// along.
+fn quadratic_bezier(start: [f32; 2], control: [f32; 2], end: [f32; 2], t: f32) -> [f32; 2] {
+    let u = 1. - t;
+    [0, 1].map(|i| u * u * start[i] + 2. * u * t * control[i] + t * t * end[i])
+}
+
+/// Hit notes, flying along an arc from the receptacle up to the soul gauge.
+///
+/// There's a pool of sprites for each kind of note, which are reused so nothing has to be created
+/// while the song is playing.
+pub struct FlyingNotes {
+    /// The sprites for each kind of note (see [FLYING_NOTE_TEXTURES]), each with the time it was
+    /// launched if it's flying.
+    sprites: [Vec<(Sprite, Option<EffectTimer>)>; 4],
+    next_sprite: [usize; 4],
+    /// Where the notes end up, at the end of the soul gauge.
+    target: [f32; 2],
+}
+
+impl FlyingNotes {
+    pub fn new(textures: &mut TextureCache, renderer: &mut Renderer) -> anyhow::Result<Self> {
+        let mut pool = |filename: &'static str| -> anyhow::Result<Vec<_>> {
+            let texture = textures.get(&renderer.device, &renderer.queue, filename)?;
+            Ok((0..FLYING_NOTE_COUNT)
+                .map(|_| {
+                    let sprite = SpriteBuilder::new(texture.clone())
+                        .centre()
+                        .position([NOTE_HIT_X, NOTE_Y])
+                        .build(renderer);
+                    (sprite, None)
+                })
+                .collect())
+        };
+
+        let sprites = [
+            pool(FLYING_NOTE_TEXTURES[0])?,
+            pool(FLYING_NOTE_TEXTURES[1])?,
+            pool(FLYING_NOTE_TEXTURES[2])?,
+            pool(FLYING_NOTE_TEXTURES[3])?,
+        ];
+
+        let [x, y] = HEADER_GAUGE.position(settings().visual.safe_area_margin());
+
+        Ok(Self {
+            sprites,
+            next_sprite: [0; 4],
+            target: [x, y + GAUGE_HEIGHT / 2.],
+        })
+    }
+
+    /// Sends a note of the given kind flying, starting at the given gameplay time.
+    pub fn launch(&mut self, kind: BasicNoteType, now: f32) {
+        let index = kind.is_big() as usize * 2 + !kind.is_don() as usize;
+        let next = self.next_sprite[index];
+        self.sprites[index][next].1 = Some(EffectTimer::start(now, FLYING_NOTE_TIME));
+        self.next_sprite[index] = (next + 1) % FLYING_NOTE_COUNT;
+    }
+
+    /// Moves the notes along. `now` should be the current gameplay time.
+    pub fn update(&mut self, renderer: &Renderer, now: f32) {
+        let start = [NOTE_HIT_X, NOTE_Y];
+        let control = [
+            (start[0] + self.target[0]) / 2.,
+            start[1].min(self.target[1]) - FLYING_NOTE_ARC_HEIGHT,
+        ];
+
+        for (sprite, flight) in self.sprites.iter_mut().flatten() {
+            let Some(timer) = *flight else {
+                continue;
+            };
+
+            let Some(progress) = timer.progress(now) else {
+                *flight = None;
+                continue;
+            };
+
+            sprite.set_position(
+                quadratic_bezier(start, control, self.target, progress),
+                renderer,
+            );
+            let alpha = 1.0
+                - ((progress - FLYING_NOTE_FADE_START) / (1.0 - FLYING_NOTE_FADE_START)).max(0.0);
+            sprite.set_alpha(alpha, renderer);
+        }
+    }
+}
+
+impl Renderable for FlyingNotes {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        for (sprite, flight) in self.sprites.iter().flatten() {
+            if flight.is_some() {
+                sprite.render(renderer, render_pass);
+            }
+        }
+    }
+}
+
+/// How long a ring flashes on the receptacle for when a note is hit, in seconds.
+const HIT_RING_TIME: f32 = 0.12;
+const HIT_RING_RADIUS: f32 = 50.;
+const HIT_RING_WIDTH: f32 = 10.;
+const HIT_RING_GOOD_COL: [f32; 4] = rgb!(0xFF, 0xCA, 0x0E);
+const HIT_RING_OK_COL: [f32; 4] = rgb!(0xD4, 0xD8, 0xE0);
+/// How many rings can be flashing at once.
+const HIT_RING_COUNT: usize = 4;
+
+/// A ring that flashes on the receptacle when a note is hit.
+struct HitRing {
+    /// A gold ring for goods and a silver one for okays.
+    shapes: [Shape; 2],
+    /// When the ring was flashed and which of its shapes is showing, if it is.
+    flash: Option<(EffectTimer, usize)>,
+}
+
+/// The feedback for each judged note: a ring that flashes on the receptacle (gold for a good,
+/// silver for an ok, and nothing otherwise) and the [JudgementText].
+pub struct HitFeedback {
+    rings: Vec<HitRing>,
+    next_ring: usize,
+    judgement_text: JudgementText,
+}
+
+impl HitFeedback {
+    /// Creates the feedback for the note field `y_offset` down from player 1's.
+    pub fn new(renderer: &mut Renderer, y_offset: f32) -> anyhow::Result<Self> {
+        let ring = |colour: [f32; 4]| -> anyhow::Result<Shape> {
+            Ok(ShapeBuilder::new()
+                .stroke_circle(
+                    [NOTE_HIT_X, NOTE_Y + y_offset],
+                    HIT_RING_RADIUS,
+                    SolidColour::new(colour),
+                    HIT_RING_WIDTH,
+                )?
+                .build(&renderer.device))
+        };
+
+        let rings = (0..HIT_RING_COUNT)
+            .map(|_| {
+                Ok(HitRing {
+                    shapes: [ring(HIT_RING_GOOD_COL)?, ring(HIT_RING_OK_COL)?],
+                    flash: None,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            rings,
+            next_ring: 0,
+            judgement_text: JudgementText::new(renderer, y_offset),
+        })
+    }
+
+    /// Shows the feedback for a judged note, starting at the given gameplay time.
+    pub fn judge(&mut self, judgement: NoteJudgement, now: f32) {
+        self.judgement_text.display_judgement(judgement, now);
+
+        let colour = match judgement {
+            NoteJudgement::Good => 0,
+            NoteJudgement::Ok => 1,
+            NoteJudgement::Bad => return,
+        };
+        self.rings[self.next_ring].flash = Some((EffectTimer::start(now, HIT_RING_TIME), colour));
+        self.next_ring = (self.next_ring + 1) % self.rings.len();
+    }
+
+    /// Animates the feedback. `now` should be the current gameplay time.
+    pub fn update(&mut self, renderer: &Renderer, now: f32) {
+        for ring in self.rings.iter_mut() {
+            if ring
+                .flash
+                .is_some_and(|(timer, _)| timer.progress(now).is_none())
+            {
+                ring.flash = None;
+            }
+        }
+
+        self.judgement_text.update(renderer, now);
+    }
+}
+
+impl Renderable for HitFeedback {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        for ring in &self.rings {
+            if let Some((_, colour)) = ring.flash {
+                ring.shapes[colour].render(renderer, render_pass);
+            }
+        }
+
+        self.judgement_text.render(renderer, render_pass);
+    }
+}
+
+/// The size of the combo counter before it's scaled up by the player's settings.
+const COMBO_TEXT_SIZE: f32 = 56.;
+/// The combo is only shown once it's at least this high.
+const COMBO_SHOW_FROM: usize = 10;
+/// The widest the combo is expected to get, for making sure the counter fits.
+const COMBO_WIDEST: &str = "9999";
+
+/// Shows the player's current combo on the left panel of the note field.
+pub struct ComboCounter {
+    text: Text,
+    combo: usize,
+}
+
+impl ComboCounter {
+    /// Creates the counter for the note field `y_offset` down from player 1's.
+    pub fn new(renderer: &mut Renderer, y_offset: f32) -> Self {
+        let (scale, margin) = {
+            let visual = &settings().visual;
+            (visual.combo_scale(), visual.safe_area_margin())
+        };
+        let size = COMBO_TEXT_SIZE * scale;
+        let width = renderer.text_width("mochiy pop one", COMBO_WIDEST, size);
+        let [x, y] = clamp_text_centre([LEFT_PANEL_WIDTH / 2., NOTE_Y], [width, size], 0., margin);
+        let centre = [x, y + y_offset];
+
+        let text = TextBuilder::new("0", renderer.font("mochiy pop one"), centre)
+            .font_size(Some(FontSize::Px(size)))
+            .horizontal_align(HorizontalAlignment::Center)
+            .vertical_align(VerticalAlignment::Middle)
+            .color([1.; 4])
+            .outlined([0., 0., 0., 1.], 4.)
+            .build_text(renderer);
+
+        Self { text, combo: 0 }
+    }
+
+    pub fn set_combo(&mut self, combo: usize, renderer: &mut Renderer) {
+        if combo == self.combo {
+            return;
+        }
+
+        self.combo = combo;
+        self.text.set_text(
+            combo.to_string(),
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+    }
+}
+
+impl Renderable for ComboCounter {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        if self.combo >= COMBO_SHOW_FROM {
+            self.text.render(renderer, render_pass);
+        }
+    }
+}
+
+const SECTION_LABEL_POS: [f32; 2] = [NOTE_HIT_X, NOTE_FIELD_Y + 10.];
+/// How long a section label is shown for, in seconds.
+const SECTION_LABEL_DISPLAY_TIME: f32 = 2.5;
+/// How far through being shown a section label starts to fade out.
+const SECTION_LABEL_FADE_START: f32 = 0.6;
+
+/// Shows the name of each section of the chart (see [SectionLabel]) at the top of the note field
+/// when it reaches the receptacle. The name fades out after a couple of seconds.
+pub struct SectionLabels {
+    /// The time and text of every section that has a name, in order.
+    labels: Vec<(f32, Text)>,
+    /// The index of the next label to be shown.
+    next_label: usize,
+    /// The label that's currently being shown, and how long it has been visible for.
+    current_label: Option<(usize, EffectTimer)>,
+}
+
+impl SectionLabels {
+    pub fn new(renderer: &mut Renderer, sections: &[SectionLabel]) -> Self {
+        let labels = sections
+            .iter()
+            .filter_map(|section| {
+                let text = TextBuilder::new(
+                    section.name.as_ref()?,
+                    renderer.font("mochiy pop one"),
+                    SECTION_LABEL_POS,
+                )
+                .horizontal_align(HorizontalAlignment::Center)
+                .vertical_align(VerticalAlignment::Top)
+                .font_size(Some(FontSize::Px(28.)))
+                .color([1.0; 4])
+                .outlined([0., 0., 0., 1.], 3.)
+                .build_text(renderer);
+
+                Some((section.time, text))
+            })
+            .collect();
+
+        Self {
+            labels,
+            next_label: 0,
+            current_label: None,
+        }
+    }
+
+    /// Shows the label for any section that has reached the receptacle, and fades out the current
+    /// one. `now` should be the current note time.
+    pub fn update(&mut self, renderer: &Renderer, now: f32) {
+        while let Some((time, _)) = self.labels.get(self.next_label) {
+            if *time > now {
+                break;
+            }
+
+            // If more than one section started since the last update, only the latest is shown
+            self.current_label = Some((
+                self.next_label,
+                EffectTimer::start(*time, SECTION_LABEL_DISPLAY_TIME),
+            ));
+            self.next_label += 1;
+        }
+
+        if let Some((index, timer)) = self.current_label {
+            let Some(progress) = timer.progress(now) else {
+                self.current_label = None;
+                return;
+            };
+
+            let alpha = 1.0
+                - ((progress - SECTION_LABEL_FADE_START) / (1.0 - SECTION_LABEL_FADE_START))
+                    .max(0.0);
+            self.labels[index]
+                .1
+                .set_color([1.0, 1.0, 1.0, alpha], &renderer.queue);
+        }
+    }
+}
+
+impl Renderable for SectionLabels {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        if let Some((index, _)) = self.current_label {
+            self.labels[index].1.render(renderer, render_pass);
         }
     }
 }
 
+const KEY_DISPLAY_PAD_SIZE: f32 = 80.;
+const KEY_DISPLAY_PAD_GAP: f32 = 12.;
+/// How long a pad stays lit after its key is pressed, in seconds.
+const KEY_DISPLAY_FLASH_TIME: f32 = 0.08;
+const KEY_DISPLAY_DON_COL: [f32; 4] = [1., 73. / 255., 73. / 255., 1.];
+const KEY_DISPLAY_KAT_COL: [f32; 4] = [73. / 255., 160. / 255., 1., 1.];
+const KEY_DISPLAY_UNLIT_ALPHA: f32 = 0.25;
+
+/// Shows which of the four drum inputs are being hit, with a count of how many times each has
+/// been hit. It's meant for viewers when the game is being streamed.
+///
+/// It should be given exactly the same inputs that are judged, so it always agrees with the game.
+pub struct KeyInputDisplay {
+    /// The unlit and lit pad for each input, in the order of [DrumInput::ALL].
+    pads: Vec<[Shape; 2]>,
+    counters: Vec<Text>,
+    counts: [usize; 4],
+    flashes: [Option<EffectTimer>; 4],
+    lit: [bool; 4],
+}
+
+impl KeyInputDisplay {
+    pub fn new(renderer: &mut Renderer) -> anyhow::Result<Self> {
+        let [x, y] = KEY_DISPLAY.position(settings().visual.safe_area_margin());
+        let pad_x = |i: usize| x + i as f32 * (KEY_DISPLAY_PAD_SIZE + KEY_DISPLAY_PAD_GAP);
+
+        let mut pads = Vec::with_capacity(4);
+        let mut counters = Vec::with_capacity(4);
+
+        for (i, input) in DrumInput::ALL.into_iter().enumerate() {
+            let colour = if input.is_don() {
+                KEY_DISPLAY_DON_COL
+            } else {
+                KEY_DISPLAY_KAT_COL
+            };
+            let unlit_colour = [colour[0], colour[1], colour[2], KEY_DISPLAY_UNLIT_ALPHA];
+
+            let pad = |colour| -> anyhow::Result<Shape> {
+                Ok(ShapeBuilder::new()
+                    .filled_roundrect(
+                        [pad_x(i), y],
+                        [pad_x(i) + KEY_DISPLAY_PAD_SIZE, y + KEY_DISPLAY_PAD_SIZE],
+                        12.,
+                        SolidColour::new(colour),
+                    )?
+                    .build(&renderer.device))
+            };
+
+            pads.push([pad(unlit_colour)?, pad(colour)?]);
+
+            counters.push(
+                TextBuilder::new(
+                    "0",
+                    renderer.font("mplus bold"),
+                    [
+                        pad_x(i) + KEY_DISPLAY_PAD_SIZE / 2.,
+                        y + KEY_DISPLAY_PAD_SIZE / 2.,
+                    ],
+                )
+                .horizontal_align(HorizontalAlignment::Center)
+                .vertical_align(VerticalAlignment::Middle)
+                .font_size(Some(FontSize::Px(28.)))
+                .color([1.0; 4])
+                .outlined([0., 0., 0., 1.], 2.)
+                .build_text(renderer),
+            );
+        }
+
+        Ok(Self {
+            pads,
+            counters,
+            counts: [0; 4],
+            flashes: [None; 4],
+            lit: [false; 4],
+        })
+    }
+
+    /// Builds the display again after the graphics device was lost, keeping the counts.
+    pub fn recreate(&mut self, renderer: &mut Renderer) -> anyhow::Result<()> {
+        let old = std::mem::replace(self, Self::new(renderer)?);
+        self.counts = old.counts;
+        self.flashes = old.flashes;
+        self.lit = old.lit;
+
+        for (counter, count) in self.counters.iter_mut().zip(self.counts) {
+            counter.set_text(
+                count.to_string(),
+                &renderer.device,
+                &renderer.queue,
+                &mut renderer.text_renderer,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Records a press of the given input at the given time.
+    pub fn press(&mut self, input: DrumInput, now: f32, renderer: &mut Renderer) {
+        let index = DrumInput::ALL
+            .iter()
+            .position(|i| *i == input)
+            .expect("every input is in DrumInput::ALL");
+
+        self.counts[index] += 1;
+        self.flashes[index] = Some(EffectTimer::start(now, KEY_DISPLAY_FLASH_TIME));
+        self.counters[index].set_text(
+            self.counts[index].to_string(),
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+    }
+
+    pub fn update(&mut self, now: f32) {
+        for (lit, flash) in self.lit.iter_mut().zip(&self.flashes) {
+            *lit = flash.is_some_and(|flash| flash.progress(now).is_some());
+        }
+    }
+
+    pub fn render<'pass>(&'pass self, ctx: &mut RenderContext<'_, 'pass>) {
+        for ((pads, counter), lit) in self.pads.iter().zip(&self.counters).zip(self.lit) {
+            ctx.render(&pads[lit as usize]);
+            ctx.render(counter);
+        }
+    }
+}
+
+/// How much bigger a balloon has swollen by the time it's about to pop.
+const BALLOON_SWELL: f32 = 0.15;
+const KUSUDAMA_SWELL: f32 = 0.3;
+/// The speech bubble behind the balloon counter. Nothing outside of a song uses it, so it isn't
+/// pinned, and the scene releases it when it exits.
+pub const BALLOON_BUBBLE_TEXTURE: &str = "balloon speech bubble.png";
+/// About where the middle of a balloon is while it's being played, for it to burst from.
+const BALLOON_POP_CENTRE: [f32; 2] = [NOTE_HIT_X + 150., NOTE_Y];
+const BALLOON_POP_COL: [f32; 4] = rgb!(0xFF, 0xA0, 0x3C);
+/// How many pieces a balloon bursts into.
+const BALLOON_POP_PIECES: usize = 10;
+const BALLOON_POP_PIECE_RADIUS: f32 = 12.;
+/// How far the pieces fly before disappearing.
+const BALLOON_POP_DISTANCE: f32 = 160.;
+/// How long the pieces fly for, in seconds.
+const BALLOON_POP_TIME: f32 = 0.3;
+
 /// Displays the progress of a balloon roll as it is being played
 /// visually, it appears to blow up a balloon, while showing how many hits are left
 pub struct BalloonDisplay {
@@ -262,7 +1249,15 @@ pub struct BalloonDisplay {
     drumroll_message: Text,
     roll_number_text: Text,
     balloon_sprite: AnimatedSprite,
+    kusudama_sprite: Sprite,
+    /// Whether the balloon being played is a kusudama.
+    kusudama: bool,
     displaying: bool,
+    /// The pieces that fly out of a popped balloon. They're built at the middle of the balloon
+    /// and moved from there.
+    pop_pieces: Vec<Shape>,
+    /// When the last balloon was popped, if the pieces are still flying.
+    popped_at: Option<EffectTimer>,
 }
 
 impl BalloonDisplay {
@@ -271,7 +1266,7 @@ impl BalloonDisplay {
         let bg_bubble = SpriteBuilder::new(textures.get(
             &renderer.device,
             &renderer.queue,
-            "balloon speech bubble.png",
+            BALLOON_BUBBLE_TEXTURE,
         )?)
         .position([575., 130.])
         .build(renderer);
@@ -310,31 +1305,62 @@ impl BalloonDisplay {
         .position([NOTE_HIT_X, NOTE_Y])
         .build(renderer);
 
+        // A kusudama starts out as big as a balloon gets, and swells from there
+        let kusudama_sprite =
+            SpriteBuilder::new(textures.get(&renderer.device, &renderer.queue, "balloon 5.png")?)
+                .origin([50., 150.])
+                .position([NOTE_HIT_X, NOTE_Y])
+                .build(renderer);
+
+        let pop_pieces = (0..BALLOON_POP_PIECES)
+            .map(|_| -> anyhow::Result<_> {
+                Ok(ShapeBuilder::new()
+                    .filled_circle(
+                        BALLOON_POP_CENTRE,
+                        BALLOON_POP_PIECE_RADIUS,
+                        SolidColour::new(BALLOON_POP_COL),
+                    )?
+                    .build(&renderer.device))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
         Ok(Self {
             bg_bubble,
             drumroll_message,
             balloon_sprite,
+            kusudama_sprite,
+            kusudama: false,
             roll_number_text,
             displaying: false,
+            pop_pieces,
+            popped_at: None,
         })
     }
 
-    /// Plays the animation for when the drumroll is over but the balloon hasn't been popped
+    /// Stops showing the balloon when the drumroll is over but it hasn't been popped. It just
+    /// goes away, since there's no penalty for not finishing it.
     pub fn discard(&mut self) {
-        // TODO
         self.displaying = false;
     }
 
-    /// Displays the balloon and number of hits left
-    pub fn hit(&mut self, hits_left: u32, hit_target: u32, renderer: &mut Renderer) {
-        if !self.displaying {
-            self.displaying = true;
-        }
-
+    /// Displays the balloon and number of hits left, at the given gameplay time. The balloon
+    /// swells as it gets closer to popping.
+    pub fn hit(
+        &mut self,
+        hits_left: u32,
+        hit_target: u32,
+        kusudama: bool,
+        now: f32,
+        renderer: &mut Renderer,
+    ) {
         if hits_left == 0 {
-            self.displaying = false;
+            self.pop(now);
+            return;
         }
 
+        self.displaying = true;
+        self.kusudama = kusudama;
+
         self.roll_number_text.set_text(
             format!("{hits_left}"),
             &renderer.device,
@@ -344,6 +1370,12 @@ impl BalloonDisplay {
 
         let ratio = hits_left as f32 / hit_target as f32;
 
+        if kusudama {
+            self.kusudama_sprite
+                .set_scale(1. + KUSUDAMA_SWELL * (1. - ratio), renderer);
+            return;
+        }
+
         let image_index = if ratio > 0.8 {
             0
         } else if ratio > 0.4 {
@@ -353,27 +1385,388 @@ impl BalloonDisplay {
         };
 
         self.balloon_sprite.set_index(image_index, renderer);
+        self.balloon_sprite
+            .set_scale(1. + BALLOON_SWELL * (1. - ratio), renderer);
     }
 
     /// Plays the animation for popping the balloon
-    fn pop(&mut self) {
-        // TODO
+    fn pop(&mut self, now: f32) {
         self.displaying = false;
+        self.popped_at = Some(EffectTimer::start(now, BALLOON_POP_TIME));
     }
 
-    /// Updates the animated sprites
-    pub fn update(&mut self, _delta_time: f32) {
-        // TODO
+    /// Animates the pieces of a popped balloon.
+    pub fn update(&mut self, renderer: &Renderer, now: f32) {
+        let Some(timer) = self.popped_at else {
+            return;
+        };
+
+        let Some(progress) = timer.progress(now) else {
+            self.popped_at = None;
+            return;
+        };
+
+        let distance = BALLOON_POP_DISTANCE * (1. - (1. - progress).powi(2));
+        for (i, piece) in self.pop_pieces.iter().enumerate() {
+            let angle = i as f32 * std::f32::consts::TAU / BALLOON_POP_PIECES as f32;
+            piece.set_position(
+                [angle.cos() * distance, angle.sin() * distance, 0.],
+                renderer,
+            );
+        }
     }
 }
 
 impl Renderable for BalloonDisplay {
     fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
         if self.displaying {
-            self.balloon_sprite.render(renderer, render_pass);
+            if self.kusudama {
+                self.kusudama_sprite.render(renderer, render_pass);
+            } else {
+                self.balloon_sprite.render(renderer, render_pass);
+            }
             self.bg_bubble.render(renderer, render_pass);
             self.drumroll_message.render(renderer, render_pass);
             self.roll_number_text.render(renderer, render_pass);
         }
+
+        if self.popped_at.is_some() {
+            for piece in &self.pop_pieces {
+                piece.render(renderer, render_pass);
+            }
+        }
+    }
+}
+
+/// Where the bottom of the roll hit count goes, just above the receptacle.
+const ROLL_COUNT_POS: [f32; 2] = [NOTE_HIT_X, NOTE_FIELD_Y - 10.];
+const ROLL_COUNT_COL: [f32; 4] = [1., 142. / 255., 75. / 255., 1.];
+/// How many ticks can be flying up from the receptacle at once.
+const ROLL_TICK_COUNT: usize = 12;
+const ROLL_TICK_RADIUS: f32 = 10.;
+/// How far the ticks rise before disappearing.
+const ROLL_TICK_RISE: f32 = 90.;
+/// How long each tick rises for, in seconds.
+const ROLL_TICK_TIME: f32 = 0.25;
+
+/// A tick that rises out of the receptacle when a drumroll is hit.
+struct RollTick {
+    /// A don and a kat coloured shape, to show whichever the roll was hit with.
+    shapes: [Shape; 2],
+    /// When the tick was launched and which of its shapes is rising, if it is.
+    rise: Option<(EffectTimer, usize)>,
+}
+
+/// Shows how many times the drumroll being played has been hit, with a little tick rising out of
+/// the receptacle for every hit.
+pub struct RollDisplay {
+    count_text: Text,
+    ticks: Vec<RollTick>,
+    next_tick: usize,
+    /// The number of hits being shown, or 0 if there's no roll being played.
+    hits: u32,
+}
+
+impl RollDisplay {
+    pub fn new(renderer: &mut Renderer) -> anyhow::Result<Self> {
+        let count_text = TextBuilder::new("0", renderer.font("mochiy pop one"), ROLL_COUNT_POS)
+            .font_size(Some(FontSize::Px(56.)))
+            .horizontal_align(HorizontalAlignment::Center)
+            .vertical_align(VerticalAlignment::Bottom)
+            .color(ROLL_COUNT_COL)
+            .outlined(rgb!(0x60, 0x2B, 0x0C), 3.)
+            .build_text(renderer);
+
+        let tick = |colour: [f32; 4]| -> anyhow::Result<Shape> {
+            Ok(ShapeBuilder::new()
+                .filled_circle(
+                    [NOTE_HIT_X, NOTE_Y],
+                    ROLL_TICK_RADIUS,
+                    SolidColour::new([colour[0], colour[1], colour[2], 1.]),
+                )?
+                .build(&renderer.device))
+        };
+
+        let ticks = (0..ROLL_TICK_COUNT)
+            .map(|_| {
+                Ok(RollTick {
+                    shapes: [tick(DON_MARKER_COL)?, tick(KAT_MARKER_COL)?],
+                    rise: None,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            count_text,
+            ticks,
+            next_tick: 0,
+            hits: 0,
+        })
+    }
+
+    /// Shows a hit on a drumroll at the given note time, which has now been hit `hits` times.
+    pub fn hit(&mut self, roll_note: BasicNoteType, hits: u32, now: f32, renderer: &mut Renderer) {
+        self.hits = hits;
+        self.count_text.set_text(
+            hits.to_string(),
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+
+        let colour = if roll_note.is_don() { 0 } else { 1 };
+        self.ticks[self.next_tick].rise = Some((EffectTimer::start(now, ROLL_TICK_TIME), colour));
+        self.next_tick = (self.next_tick + 1) % self.ticks.len();
+    }
+
+    /// Animates the ticks. `rolling` is whether a drumroll is being played at the given note time;
+    /// the count is hidden once it isn't.
+    pub fn update(&mut self, renderer: &Renderer, now: f32, rolling: bool) {
+        if !rolling {
+            self.hits = 0;
+        }
+
+        for tick in self.ticks.iter_mut() {
+            let Some((timer, colour)) = tick.rise else {
+                continue;
+            };
+
+            let Some(progress) = timer.progress(now) else {
+                tick.rise = None;
+                continue;
+            };
+
+            tick.shapes[colour].set_position([0., -ROLL_TICK_RISE * progress, 0.], renderer);
+        }
+    }
+}
+
+impl Renderable for RollDisplay {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        for tick in &self.ticks {
+            if let Some((_, colour)) = tick.rise {
+                tick.shapes[colour].render(renderer, render_pass);
+            }
+        }
+
+        if self.hits > 0 {
+            self.count_text.render(renderer, render_pass);
+        }
+    }
+}
+
+const RALLY_TEXT_POS: [f32; 2] = [NOTE_HIT_X, NOTE_FIELD_Y + NOTE_FIELD_HEIGHT + 20.];
+const RALLY_TITLE_COL: [f32; 4] = [1., 202. / 255., 14. / 255., 1.];
+const RALLY_TITLE_OUTLINE_COL: [f32; 4] = [37. / 255., 29. / 255., 0., 1.];
+/// How many targets can be flying out of the receptacle at once.
+const RALLY_BURST_SIZE: usize = 16;
+const RALLY_TARGET_RADIUS: f32 = 14.;
+/// How far the targets fly before disappearing.
+const RALLY_BURST_DISTANCE: f32 = 180.;
+/// How long each target flies for, in seconds.
+const RALLY_BURST_TIME: f32 = 0.4;
+/// The angle between one target and the next, in radians. Going round by the golden angle spreads
+/// them out evenly however many there are.
+const RALLY_BURST_ANGLE: f32 = 2.399_963;
+
+/// Shows the bonus rally at the end of a song: the multiplier and bonus so far, and a burst of
+/// little targets flying out of the receptacle with every hit.
+pub struct RallyDisplay {
+    title: Text,
+    bonus_text: Text,
+    /// The targets, and the time each one was launched and the direction it's going in (if it's
+    /// flying).
+    targets: Vec<(Shape, Option<(EffectTimer, f32)>)>,
+    next_target: usize,
+    showing: bool,
+}
+
+impl RallyDisplay {
+    pub fn new(renderer: &mut Renderer) -> anyhow::Result<Self> {
+        let title = TextBuilder::new("Rally!", renderer.font("mochiy pop one"), RALLY_TEXT_POS)
+            .font_size(Some(FontSize::Px(40.)))
+            .horizontal_align(HorizontalAlignment::Center)
+            .vertical_align(VerticalAlignment::Top)
+            .color(RALLY_TITLE_COL)
+            .outlined(RALLY_TITLE_OUTLINE_COL, 3.)
+            .build_text(renderer);
+
+        let bonus_text = TextBuilder::new(
+            "x1  +0",
+            renderer.font("mplus bold"),
+            [RALLY_TEXT_POS[0], RALLY_TEXT_POS[1] + 55.],
+        )
+        .font_size(Some(FontSize::Px(30.)))
+        .horizontal_align(HorizontalAlignment::Center)
+        .vertical_align(VerticalAlignment::Top)
+        .color([1.; 4])
+        .outlined([0., 0., 0., 1.], 3.)
+        .build_text(renderer);
+
+        // Every other target is a don or a kat. They're built at the receptacle and moved from
+        // there.
+        let targets = (0..RALLY_BURST_SIZE)
+            .map(|i| -> anyhow::Result<_> {
+                let colour = if i % 2 == 0 {
+                    DON_MARKER_COL
+                } else {
+                    KAT_MARKER_COL
+                };
+
+                let target = ShapeBuilder::new()
+                    .filled_circle(
+                        [NOTE_HIT_X, NOTE_Y],
+                        RALLY_TARGET_RADIUS,
+                        SolidColour::new([colour[0], colour[1], colour[2], 1.]),
+                    )?
+                    .build(&renderer.device);
+
+                Ok((target, None))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            title,
+            bonus_text,
+            targets,
+            next_target: 0,
+            showing: false,
+        })
+    }
+
+    /// Shows a hit in the rally, at the given gameplay time.
+    pub fn hit(&mut self, rally: &Rally, now: f32, renderer: &mut Renderer) {
+        self.bonus_text.set_text(
+            format!("x{}  +{}", rally.multiplier(), rally.bonus()),
+            &renderer.device,
+            &renderer.queue,
+            &mut renderer.text_renderer,
+        );
+
+        let angle = rally.hits() as f32 * RALLY_BURST_ANGLE;
+        self.targets[self.next_target].1 = Some((EffectTimer::start(now, RALLY_BURST_TIME), angle));
+        self.next_target = (self.next_target + 1) % self.targets.len();
+    }
+
+    /// Animates the targets. `running` is whether the rally is going on at the given gameplay
+    /// time; the display is hidden once it isn't.
+    pub fn update(&mut self, renderer: &Renderer, now: f32, running: bool) {
+        self.showing = running;
+
+        for (target, flight) in self.targets.iter_mut() {
+            let Some((timer, angle)) = *flight else {
+                continue;
+            };
+
+            let Some(progress) = timer.progress(now) else {
+                *flight = None;
+                continue;
+            };
+
+            let distance = RALLY_BURST_DISTANCE * (1. - (1. - progress).powi(2));
+            target.set_position(
+                [angle.cos() * distance, angle.sin() * distance, 0.],
+                renderer,
+            );
+        }
+    }
+}
+
+impl Renderable for RallyDisplay {
+    fn render<'pass>(&'pass self, renderer: &'pass Renderer, render_pass: &mut RenderPass<'pass>) {
+        if !self.showing {
+            return;
+        }
+
+        for (target, flight) in &self.targets {
+            if flight.is_some() {
+                target.render(renderer, render_pass);
+            }
+        }
+
+        self.title.render(renderer, render_pass);
+        self.bonus_text.render(renderer, render_pass);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::layout::HUD_MARGIN;
+
+    #[test]
+    fn test_quadratic_bezier() {
+        let (start, control, end) = ([0., 100.], [50., -100.], [100., 0.]);
+        assert_eq!(quadratic_bezier(start, control, end, 0.), start);
+        assert_eq!(quadratic_bezier(start, control, end, 1.), end);
+        // Halfway along, the curve is pulled a quarter of the way towards the control point
+        assert_eq!(quadratic_bezier(start, control, end, 0.5), [50., -25.]);
+    }
+
+    #[test]
+    fn test_judgement_text_stays_clear_of_header() {
+        for position in JudgementPosition::ALL {
+            for scale in [1.0, 1.5, 2.0] {
+                let size = [120. * scale, JUDGEMENT_TEXT_SIZE * scale];
+                let [x, y] = judgement_text_centre(position, scale, size, 0.0);
+                let rise = -JUDGEMENT_TEXT_FLOAT_DIST * scale * JUDGEMENT_TEXT_FLOAT_EXTENT;
+
+                // Even at the top of its float, the text is below the header...
+                assert!(y - size[1] / 2. - rise >= HEADER_HEIGHT + SPACER_WIDTH);
+                // ...and on the screen, even while it pops in from below
+                assert!(x - size[0] / 2. >= 0. && x + size[0] / 2. <= 1920.);
+                assert!(y + size[1] / 2. + JUDGEMENT_TEXT_POP_DROP * scale <= 1080.);
+            }
+        }
+    }
+
+    #[test]
+    fn test_judgement_text_positions() {
+        // At the normal size, nothing needs to move
+        let size = [80., JUDGEMENT_TEXT_SIZE];
+        assert_eq!(
+            judgement_text_centre(JudgementPosition::Receptacle, 1.0, size, 0.0),
+            [NOTE_HIT_X, NOTE_Y - 20. - JUDGEMENT_TEXT_SIZE / 2.]
+        );
+        assert_eq!(
+            judgement_text_centre(JudgementPosition::ScreenCentre, 1.0, size, 0.0),
+            [960., 540. - JUDGEMENT_TEXT_SIZE / 2.]
+        );
+
+        // Text that would go over the header is pushed down instead
+        let big = [160., JUDGEMENT_TEXT_SIZE * 2.];
+        let [_, y] = judgement_text_centre(JudgementPosition::AboveField, 2.0, big, 0.0);
+        assert!(y > NOTE_Y - 80. - big[1] / 2.);
+
+        // And text hanging off the side of the screen is pulled back on
+        assert_eq!(
+            clamp_text_centre([0., 800.], [100., 20.], 0., 0.0)[0],
+            50. + HUD_MARGIN
+        );
+    }
+
+    #[test]
+    fn test_gauge_segments() {
+        assert_eq!(filled_gauge_segments(0.0), 0);
+        assert_eq!(filled_gauge_segments(GAUGE_CLEAR), 40);
+        assert_eq!(filled_gauge_segments(GAUGE_MAX), GAUGE_SEGMENTS);
+
+        // Just under the clear line, the empty segments past it stand out
+        let filled = filled_gauge_segments(GAUGE_CLEAR - 1.0);
+        assert_eq!(gauge_segment_colour(0, filled), GAUGE_FILL_COL);
+        assert_eq!(gauge_segment_colour(filled, filled), GAUGE_EMPTY_COL);
+        assert_eq!(gauge_segment_colour(40, filled), GAUGE_EMPTY_CLEAR_COL);
+
+        // Past it, the segments past the line are filled in a different colour
+        let filled = filled_gauge_segments(90.0);
+        assert_eq!(gauge_segment_colour(39, filled), GAUGE_FILL_COL);
+        assert_eq!(gauge_segment_colour(40, filled), GAUGE_CLEAR_FILL_COL);
+
+        // And a full gauge is a rainbow
+        assert_eq!(gauge_segment_colour(0, GAUGE_SEGMENTS), [1., 0., 0., 1.]);
+        assert_ne!(
+            gauge_segment_colour(20, GAUGE_SEGMENTS),
+            gauge_segment_colour(40, GAUGE_SEGMENTS)
+        );
     }
 }
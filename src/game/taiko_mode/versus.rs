@@ -0,0 +1,208 @@
+//! Local 2P battles, where a second player plays the same song on a note field of their own,
+//! underneath the first player's.
+//!
+//! The scene still runs the clock, the song and everything else the players share, along with all
+//! of player 1's side. This is only player 2's side: their notes, how they've played them, and the
+//! header and combo counter that show it.
+use winit::keyboard::PhysicalKey;
+
+use super::judge::{HitOutcome, Judge};
+use super::modifiers::NoteVisibility;
+use super::note::{create_barlines, create_notes, BeatScroll, TaikoModeBarline, TaikoModeNote};
+use super::scene::PlayResult;
+use super::ui::{ComboCounter, Header, NoteField};
+use crate::game::audio::OrLog;
+use crate::game::input_queue::InputQueue;
+use crate::game::layout::{PLAYER_2_FIELD_OFFSET, PLAYER_2_HEADER_OFFSET};
+use crate::game::{RenderContext, TextureCache};
+use crate::notechart_parser::NoteChart;
+use crate::render::Renderer;
+use crate::settings::{settings, DrumInput};
+
+/// Player 2's side of a 2P battle.
+pub struct Versus {
+    header: Header,
+    note_field: NoteField,
+    combo_counter: ComboCounter,
+
+    /// The track player 2 is playing, which is player 1's unless the chart has one for each.
+    chart: NoteChart,
+    barlines: Vec<TaikoModeBarline>,
+    judge: Judge<TaikoModeNote>,
+    /// Drum hits that haven't been judged yet, with the note time each one was made at, the same
+    /// as player 1's.
+    pending_hits: InputQueue,
+    results: PlayResult,
+}
+
+impl Versus {
+    /// Sets up player 2's side for the given track, judged with the given timing windows and
+    /// scored into the given (empty) result.
+    pub fn new(
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+        chart: NoteChart,
+        timing_windows: &'static [f32; 3],
+        results: PlayResult,
+    ) -> anyhow::Result<Self> {
+        let judge = Judge::new(
+            create_notes(
+                renderer,
+                textures,
+                &chart.notes,
+                BeatScroll::for_chart(&chart),
+            ),
+            timing_windows,
+        );
+
+        Ok(Self {
+            header: Header::new(renderer, "", None, PLAYER_2_HEADER_OFFSET)?,
+            note_field: NoteField::new(renderer, PLAYER_2_FIELD_OFFSET)?,
+            combo_counter: ComboCounter::new(renderer, PLAYER_2_FIELD_OFFSET),
+            barlines: create_barlines(renderer, &chart.barlines, BeatScroll::for_chart(&chart)),
+            chart,
+            judge,
+            pending_hits: InputQueue::new(),
+            results,
+        })
+    }
+
+    /// The drum input player 2 has on the given key, if any. Player 1's keys should be checked
+    /// first, since they win if the same key is set for both.
+    pub fn drum_input(key: PhysicalKey) -> Option<DrumInput> {
+        settings().game.player2_key_mappings.drum_input(key)
+    }
+
+    /// Queues up a hit player 2 made at the given note time, to be judged in the next update.
+    pub fn hit(&mut self, input: DrumInput, time: f32) {
+        self.pending_hits.push(input, time);
+    }
+
+    /// Judges player 2's hits since the last update, and moves their notes on to the given note
+    /// time. `now` should be the current gameplay time, which the hit feedback is animated with.
+    pub fn update(&mut self, renderer: &mut Renderer, time: f32, now: f32) {
+        while let Some((input, hit_time)) = self.pending_hits.pop() {
+            if let HitOutcome::Note { judgement, .. } =
+                self.judge.judge_hit(input, hit_time, &mut self.results)
+            {
+                self.note_field.judge(judgement, now);
+            }
+        }
+
+        self.judge.advance(time, &mut self.results);
+        // Player 2 doesn't get a balloon display, so there's nothing to take down
+        self.judge.take_balloon_missed();
+
+        self.note_field.update(renderer, now);
+        self.header.set_accuracy(self.results.accuracy(), renderer);
+        self.header
+            .set_gauge(self.results.gauge(), renderer)
+            .or_log("couldn't build soul gauge");
+        self.header.set_score(self.results.score(), renderer);
+        self.combo_counter
+            .set_combo(self.results.current_combo(), renderer);
+    }
+
+    /// Draws player 2's header and note field, with the notes where they should be at the given
+    /// note time.
+    pub fn render<'pass>(
+        &'pass mut self,
+        ctx: &mut RenderContext<'_, 'pass>,
+        time: f32,
+        visibility: NoteVisibility,
+    ) {
+        for note in (self.judge.notes_mut().iter_mut()).filter(|note| note.visible(time)) {
+            note.update_position(ctx.renderer, time, visibility, PLAYER_2_FIELD_OFFSET);
+        }
+
+        for barline in (self.barlines.iter_mut()).filter(|barline| barline.visible(time)) {
+            barline.update_position(ctx.renderer, time, PLAYER_2_FIELD_OFFSET);
+        }
+
+        self.header.render(ctx, true);
+
+        let notes = self.judge.notes().iter().filter(|note| note.visible(time));
+        let barlines = self.barlines.iter().filter(|barline| barline.visible(time));
+        self.note_field
+            .render(ctx, notes, barlines, None, self.chart.is_gogo(time));
+        ctx.render(&self.combo_counter);
+    }
+
+    /// Takes player 2 back to the start of the song, with every note to be played again and
+    /// nothing scored.
+    pub fn restart(
+        &mut self,
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+    ) -> anyhow::Result<()> {
+        self.pending_hits.clear();
+        self.results = self.results.restarted();
+        self.judge = Judge::new(
+            create_notes(
+                renderer,
+                textures,
+                &self.chart.notes,
+                BeatScroll::for_chart(&self.chart),
+            ),
+            self.judge.timing_windows(),
+        );
+        self.barlines = create_barlines(
+            renderer,
+            &self.chart.barlines,
+            BeatScroll::for_chart(&self.chart),
+        );
+
+        self.create_play_effects(renderer)
+    }
+
+    /// Builds the parts of player 2's side that animate as the song is played.
+    fn create_play_effects(&mut self, renderer: &mut Renderer) -> anyhow::Result<()> {
+        self.note_field = NoteField::new(renderer, PLAYER_2_FIELD_OFFSET)?;
+        self.combo_counter = ComboCounter::new(renderer, PLAYER_2_FIELD_OFFSET);
+        self.combo_counter
+            .set_combo(self.results.current_combo(), renderer);
+        Ok(())
+    }
+
+    /// Builds everything that's drawn again, after the graphics device was lost. How far player 2
+    /// has got with each note is kept.
+    pub fn recreate_gpu_resources(
+        &mut self,
+        renderer: &mut Renderer,
+        textures: &mut TextureCache,
+    ) -> anyhow::Result<()> {
+        self.header = Header::new(renderer, "", None, PLAYER_2_HEADER_OFFSET)?;
+        self.create_play_effects(renderer)?;
+
+        let old_notes = self.judge.replace_notes(create_notes(
+            renderer,
+            textures,
+            &self.chart.notes,
+            BeatScroll::for_chart(&self.chart),
+        ));
+        for (note, old_note) in self.judge.notes_mut().iter_mut().zip(&old_notes) {
+            note.copy_progress(old_note);
+        }
+        self.barlines = create_barlines(
+            renderer,
+            &self.chart.barlines,
+            BeatScroll::for_chart(&self.chart),
+        );
+
+        Ok(())
+    }
+
+    /// Whether all of player 2's notes have gone by.
+    pub fn finished(&self) -> bool {
+        self.judge.finished()
+    }
+
+    /// When player 2's last note is, if they have any.
+    pub fn last_note_time(&self) -> Option<f32> {
+        self.chart.notes.last().map(|note| note.time)
+    }
+
+    pub fn results(&self) -> &PlayResult {
+        &self.results
+    }
+}
@@ -0,0 +1,181 @@
+//! Statistics for measuring how early or late the player taps, used to help them find the right
+//! latency and offset settings.
+//!
+//! The latency strip on the settings screen shows these live, and the offset calibration screen
+//! works out the offset from them.
+use std::collections::VecDeque;
+
+/// The number of taps the statistics are calculated over by default.
+pub const DEFAULT_TAP_WINDOW: usize = 10;
+/// How many times further from the median than the taps typically are (their median absolute
+/// deviation) a tap has to be to be an outlier.
+const OUTLIER_DEVIATIONS: f32 = 3.0;
+/// Taps within this many seconds of the median are never outliers, so a player who taps very
+/// consistently doesn't have most of their taps thrown away.
+const MIN_OUTLIER_DISTANCE: f32 = 0.015;
+
+/// Keeps track of the timing offsets of the player's most recent taps.
+#[derive(Debug, Clone)]
+pub struct TapStatistics {
+    /// The offsets of the most recent taps, in seconds. Positive offsets are late.
+    offsets: VecDeque<f32>,
+    window: usize,
+}
+
+impl TapStatistics {
+    /// Creates a new set of statistics that only considers the last `window` taps.
+    pub fn new(window: usize) -> Self {
+        Self {
+            offsets: VecDeque::with_capacity(window),
+            window,
+        }
+    }
+
+    /// Records a tap with the given offset.
+    pub fn push(&mut self, offset: f32) {
+        if self.offsets.len() == self.window {
+            self.offsets.pop_front();
+        }
+
+        self.offsets.push_back(offset);
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// The average offset of the recorded taps, or None if there aren't any.
+    pub fn mean(&self) -> Option<f32> {
+        if self.offsets.is_empty() {
+            return None;
+        }
+
+        Some(self.offsets.iter().sum::<f32>() / self.offsets.len() as f32)
+    }
+
+    /// How much the offsets vary (their standard deviation), or None if there aren't any taps.
+    pub fn jitter(&self) -> Option<f32> {
+        let mean = self.mean()?;
+        let variance = self
+            .offsets
+            .iter()
+            .map(|offset| (offset - mean).powi(2))
+            .sum::<f32>()
+            / self.offsets.len() as f32;
+
+        Some(variance.sqrt())
+    }
+
+    /// The middle offset of the recorded taps, or None if there aren't any.
+    pub fn median(&self) -> Option<f32> {
+        median(self.offsets.iter().copied())
+    }
+
+    /// How far the offsets typically are from their median (their median absolute deviation), or
+    /// None if there aren't any taps.
+    pub fn median_deviation(&self) -> Option<f32> {
+        let median_offset = self.median()?;
+        median(
+            self.offsets
+                .iter()
+                .map(|offset| (offset - median_offset).abs()),
+        )
+    }
+
+    /// The same statistics without the outliers: taps that are much further from the median than
+    /// the rest (see [OUTLIER_DEVIATIONS]), so that one mistimed tap doesn't skew them.
+    pub fn without_outliers(&self) -> TapStatistics {
+        let mut kept = TapStatistics::new(self.window);
+        let (Some(median_offset), Some(deviation)) = (self.median(), self.median_deviation())
+        else {
+            return kept;
+        };
+
+        let limit = (deviation * OUTLIER_DEVIATIONS).max(MIN_OUTLIER_DISTANCE);
+        for &offset in &self.offsets {
+            if (offset - median_offset).abs() <= limit {
+                kept.push(offset);
+            }
+        }
+
+        kept
+    }
+}
+
+/// The middle of the values, or the average of the middle two if there's an even number of them.
+fn median(values: impl Iterator<Item = f32>) -> Option<f32> {
+    let mut sorted: Vec<f32> = values.collect();
+    sorted.sort_by(f32::total_cmp);
+
+    let middle = sorted.len() / 2;
+    match sorted.len() {
+        0 => None,
+        len if len % 2 == 0 => Some((sorted[middle - 1] + sorted[middle]) / 2.0),
+        _ => Some(sorted[middle]),
+    }
+}
+
+impl Default for TapStatistics {
+    fn default() -> Self {
+        Self::new(DEFAULT_TAP_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{a} != {b}");
+    }
+
+    fn taps(offsets: &[f32]) -> TapStatistics {
+        let mut stats = TapStatistics::new(offsets.len());
+        for &offset in offsets {
+            stats.push(offset);
+        }
+        stats
+    }
+
+    #[test]
+    fn test_tap_statistics() {
+        let mut stats = TapStatistics::new(4);
+        assert_eq!(stats.mean(), None);
+        assert_eq!(stats.jitter(), None);
+
+        for offset in [0.010, 0.020, 0.010, 0.020] {
+            stats.push(offset);
+        }
+        assert_close(stats.mean().unwrap(), 0.015);
+        assert_close(stats.jitter().unwrap(), 0.005);
+
+        // Old taps fall out of the window
+        for _ in 0..4 {
+            stats.push(-0.030);
+        }
+        assert_eq!(stats.len(), 4);
+        assert_close(stats.mean().unwrap(), -0.030);
+        assert_close(stats.jitter().unwrap(), 0.0);
+    }
+    #[test]
+    fn test_median() {
+        assert_eq!(taps(&[]).median(), None);
+        assert_eq!(taps(&[3.0, 1.0, 2.0]).median(), Some(2.0));
+        assert_eq!(taps(&[4.0, 1.0, 3.0, 2.0]).median(), Some(2.5));
+        assert_eq!(taps(&[4.0, 1.0, 3.0, 2.0]).median_deviation(), Some(1.0));
+    }
+
+    #[test]
+    fn test_without_outliers() {
+        let mut offsets = [0.026, 0.034].repeat(10);
+        offsets[5] = -0.400;
+        let kept = taps(&offsets).without_outliers();
+        assert_eq!(kept.len(), 19);
+        assert!(kept.mean().unwrap() > 0.0);
+
+        // Taps that are all very close together are all kept
+        let steady = taps(&[0.010, 0.011, 0.010, 0.020]);
+        assert_eq!(steady.without_outliers().len(), 4);
+        assert_eq!(taps(&[]).without_outliers().len(), 0);
+    }
+}
@@ -0,0 +1,141 @@
+//! Clocks for timing animations and effects.
+
+/// Keeps track of time for everything that animates.
+///
+/// There are two time bases. UI time always advances, and is what menus should animate with.
+/// Gameplay time stops while gameplay is paused and follows the note clock, so effects that are
+/// tied to gameplay (e.g. hit effects) freeze along with the notes and stay in sync with them
+/// after resuming.
+#[derive(Debug, Clone)]
+pub struct GameTime {
+    ui: f32,
+    gameplay: f32,
+    paused: bool,
+}
+
+impl GameTime {
+    /// Creates a new clock. Gameplay time starts paused, since there's no gameplay until a song
+    /// starts.
+    pub fn new() -> Self {
+        Self {
+            ui: 0.0,
+            gameplay: 0.0,
+            paused: true,
+        }
+    }
+
+    /// Moves time forward by the given number of seconds. Gameplay time only advances if it isn't
+    /// paused.
+    pub fn advance(&mut self, delta: f32) {
+        self.ui += delta;
+
+        if !self.paused {
+            self.gameplay += delta;
+        }
+    }
+
+    /// The number of seconds the game has been running for.
+    pub fn ui_time(&self) -> f32 {
+        self.ui
+    }
+
+    /// The current time for gameplay effects, in seconds.
+    pub fn gameplay_time(&self) -> f32 {
+        self.gameplay
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Jumps gameplay time to the given time. Used to keep it in line with the note clock.
+    pub fn seek(&mut self, time: f32) {
+        self.gameplay = time;
+    }
+}
+
+/// A timer for an effect that plays for a fixed length of time, like a hit effect.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EffectTimer {
+    start: f32,
+    duration: f32,
+}
+
+impl EffectTimer {
+    /// Starts an effect at the given time that lasts for the given number of seconds.
+    pub fn start(now: f32, duration: f32) -> Self {
+        Self {
+            start: now,
+            duration,
+        }
+    }
+
+    /// Returns how far through the effect we are, from 0 to 1, or None if it has finished.
+    pub fn progress(&self, now: f32) -> Option<f32> {
+        let elapsed = (now - self.start).max(0.0);
+        (elapsed <= self.duration).then(|| elapsed / self.duration)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pause_and_resume() {
+        let mut time = GameTime::new();
+        time.advance(1.0);
+        assert_eq!(time.ui_time(), 1.0);
+        assert_eq!(time.gameplay_time(), 0.0);
+
+        time.resume();
+        time.advance(1.0);
+        assert_eq!(time.gameplay_time(), 1.0);
+
+        time.pause();
+        time.advance(0.5);
+        assert_eq!(time.ui_time(), 2.5);
+        assert_eq!(time.gameplay_time(), 1.0);
+
+        time.resume();
+        time.advance(0.5);
+        assert_eq!(time.ui_time(), 3.0);
+        assert_eq!(time.gameplay_time(), 1.5);
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut time = GameTime::new();
+        time.resume();
+        time.advance(3.0);
+
+        time.seek(-1.0);
+        assert_eq!(time.gameplay_time(), -1.0);
+        assert_eq!(time.ui_time(), 3.0);
+
+        time.advance(0.5);
+        assert_eq!(time.gameplay_time(), -0.5);
+    }
+
+    #[test]
+    fn test_effect_frozen_while_paused() {
+        let mut time = GameTime::new();
+        time.resume();
+        let effect = EffectTimer::start(time.gameplay_time(), 0.5);
+
+        time.advance(0.25);
+        assert_eq!(effect.progress(time.gameplay_time()), Some(0.5));
+
+        time.pause();
+        time.advance(10.0);
+        assert_eq!(effect.progress(time.gameplay_time()), Some(0.5));
+
+        time.resume();
+        time.advance(0.5);
+        assert_eq!(effect.progress(time.gameplay_time()), None);
+    }
+}
@@ -0,0 +1,483 @@
+//! An overview of a song's waveform, for seeing (and seeking to) where things happen in it.
+//!
+//! The overview is worked out from the song's samples once they're loaded, on another thread,
+//! and drawn as a strip with a playhead and loop markers. The strip itself is built once, so the
+//! only thing that changes from frame to frame is where the playhead is.
+//!
+//! The strip can also show a heat map of where the player keeps failing (see [FailureLog]), so
+//! the part of a loop that needs work stands out.
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver};
+
+use kaku::{FontSize, HorizontalAlignment, Text, TextBuilder, VerticalAlignment};
+use kira::dsp::Frame;
+use kira::sound::static_sound::StaticSoundData;
+use lyon::lyon_tessellation::TessellationError;
+
+use crate::render::shapes::{Shape, ShapeBuilder, SolidColour};
+use crate::render::text::BuildTextWithRenderer;
+use crate::render::{Renderable, Renderer};
+
+/// How many buckets the waveform is split into.
+pub const WAVEFORM_BUCKETS: usize = 2000;
+
+const STRIP_BACKGROUND_COL: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const PEAK_COL: [f32; 4] = [0.45, 0.55, 0.8, 1.0];
+const RMS_COL: [f32; 4] = [0.75, 0.85, 1.0, 1.0];
+const PLAYHEAD_COL: [f32; 4] = [1.0, 0.3, 0.2, 1.0];
+const LOOP_MARKER_COL: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+const MARKER_WIDTH: f32 = 2.0;
+const HEAT_COL: [f32; 4] = [1.0, 0.15, 0.1, 0.85];
+/// How much of the strip's height the heat map covers, from the bottom.
+const HEAT_HEIGHT: f32 = 0.3;
+const WORST_LABEL_SIZE: f32 = 24.0;
+/// How far above the strip the worst spot's label is.
+const WORST_LABEL_GAP: f32 = 6.0;
+/// How far the middle of the worst spot's label is kept from the ends of the strip, so none of it
+/// goes off the end.
+const WORST_LABEL_INSET: f32 = 150.0;
+
+/// How many buckets the heat map is split into.
+pub const HEAT_BUCKETS: usize = 200;
+/// The most failures remembered in a practice session. The oldest ones are forgotten first.
+pub const FAILURE_LIMIT: usize = 1000;
+
+/// The loudness of one slice of a song, from 0 to 1.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct WaveformBucket {
+    /// The loudest sample in the slice.
+    pub peak: f32,
+    /// The average (root mean square) level over the slice.
+    pub rms: f32,
+}
+
+/// Splits the given samples into the given number of slices, and measures how loud each one is.
+///
+/// Both channels are measured together. If there are fewer samples than buckets, the extra
+/// buckets are silent.
+pub fn waveform_buckets(frames: &[Frame], buckets: usize) -> Vec<WaveformBucket> {
+    (0..buckets)
+        .map(|i| {
+            let start = i * frames.len() / buckets;
+            let end = (i + 1) * frames.len() / buckets;
+            let slice = &frames[start..end];
+
+            if slice.is_empty() {
+                return WaveformBucket::default();
+            }
+
+            let peak = slice
+                .iter()
+                .map(|frame| frame.left.abs().max(frame.right.abs()))
+                .fold(0.0, f32::max);
+
+            let mean_square = slice
+                .iter()
+                .map(|frame| (frame.left * frame.left + frame.right * frame.right) / 2.0)
+                .sum::<f32>()
+                / slice.len() as f32;
+
+            WaveformBucket {
+                peak: peak.min(1.0),
+                rms: mean_square.sqrt().min(1.0),
+            }
+        })
+        .collect()
+}
+
+/// Works out the waveform of a song on another thread. The song's samples are shared rather than
+/// copied, so this doesn't have to read the audio file again.
+pub fn spawn_waveform_analysis(sound: &StaticSoundData) -> Receiver<Vec<WaveformBucket>> {
+    let frames = sound.frames.clone();
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let _ = sender.send(waveform_buckets(&frames, WAVEFORM_BUCKETS));
+    });
+
+    receiver
+}
+
+/// Where in a song the player has broken their combo or hit a bad while practising a loop.
+///
+/// This only lasts for the session, and is forgotten when the loop moves, since failures from a
+/// different loop aren't about the part being practised.
+#[derive(Debug, Clone, Default)]
+pub struct FailureLog {
+    /// The time of each failure in the song, in seconds.
+    times: VecDeque<f32>,
+    loop_bounds: Option<(f32, f32)>,
+}
+
+impl FailureLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a combo break or bad at the given time in the song.
+    pub fn record(&mut self, time: f32) {
+        if self.times.len() == FAILURE_LIMIT {
+            self.times.pop_front();
+        }
+
+        self.times.push_back(time);
+    }
+
+    /// Sets the bounds of the loop being practised. If they've moved, the failures so far are
+    /// forgotten.
+    pub fn set_loop(&mut self, bounds: Option<(f32, f32)>) {
+        if bounds != self.loop_bounds {
+            self.times.clear();
+            self.loop_bounds = bounds;
+        }
+    }
+
+    pub fn times(&self) -> impl Iterator<Item = f32> + '_ {
+        self.times.iter().copied()
+    }
+}
+
+/// Splits a song of the given length (in seconds) into the given number of buckets, and works
+/// out how many of the given failures happened in each one. The counts are scaled so that the
+/// worst bucket is 1 (or they're all 0 if there are no failures).
+pub fn heat_buckets(
+    failures: impl IntoIterator<Item = f32>,
+    duration: f32,
+    buckets: usize,
+) -> Vec<f32> {
+    let mut counts = vec![0u32; buckets];
+
+    if duration > 0.0 && buckets > 0 {
+        for time in failures {
+            let bucket = (time / duration * buckets as f32).floor();
+            if bucket >= 0.0 {
+                counts[(bucket as usize).min(buckets - 1)] += 1;
+            }
+        }
+    }
+
+    let worst = counts.iter().copied().max().unwrap_or(0).max(1);
+    counts
+        .into_iter()
+        .map(|count| count as f32 / worst as f32)
+        .collect()
+}
+
+/// Returns the time (in seconds) at the middle of the worst bucket in a heat map of a song of the
+/// given length, or None if there weren't any failures. If there's a tie, the earliest bucket
+/// wins.
+pub fn worst_bucket_time(heat: &[f32], duration: f32) -> Option<f32> {
+    let (worst, _) = heat
+        .iter()
+        .enumerate()
+        .filter(|(_, heat)| **heat > 0.0)
+        .min_by(|(_, a), (_, b)| b.total_cmp(a))?;
+
+    Some((worst as f32 + 0.5) / heat.len() as f32 * duration)
+}
+
+/// A strip showing a song's waveform, with a playhead and (optionally) the bounds of a loop.
+pub struct WaveformStrip {
+    waveform: Shape,
+    playhead: Shape,
+    loop_markers: [Shape; 2],
+    show_loop: bool,
+    /// The heat map of where the player has been failing, if there's anything to show.
+    heat: Option<Shape>,
+    /// Says when the worst part of the heat map is, if it's being shown.
+    worst_label: Option<Text>,
+    /// The top left corner of the strip.
+    position: [f32; 2],
+    size: [f32; 2],
+    /// The length of the song, in seconds.
+    duration: f32,
+}
+
+impl WaveformStrip {
+    /// Builds a strip for a song of the given length (in seconds) with the top left corner at the
+    /// given position.
+    pub fn new(
+        renderer: &Renderer,
+        buckets: &[WaveformBucket],
+        duration: f32,
+        position: [f32; 2],
+        size: [f32; 2],
+    ) -> Result<Self, TessellationError> {
+        let [x, y] = position;
+        let [width, height] = size;
+        let middle = y + height / 2.0;
+        let bar_width = width / buckets.len().max(1) as f32;
+
+        let mut waveform = ShapeBuilder::new().filled_rectangle(
+            [x, y],
+            [x + width, y + height],
+            SolidColour::new(STRIP_BACKGROUND_COL),
+        )?;
+
+        for (i, bucket) in buckets.iter().enumerate() {
+            let left = x + i as f32 * bar_width;
+            let right = left + bar_width;
+
+            for (level, colour) in [(bucket.peak, PEAK_COL), (bucket.rms, RMS_COL)] {
+                let half_height = level * height / 2.0;
+                waveform = waveform.filled_rectangle(
+                    [left, middle - half_height],
+                    [right, middle + half_height],
+                    SolidColour::new(colour),
+                )?;
+            }
+        }
+
+        // The markers are built at x = 0 and moved into place
+        let marker = |colour| -> Result<Shape, TessellationError> {
+            Ok(ShapeBuilder::new()
+                .filled_rectangle(
+                    [-MARKER_WIDTH / 2.0, y],
+                    [MARKER_WIDTH / 2.0, y + height],
+                    SolidColour::new(colour),
+                )?
+                .position([x, 0.0, 0.0])
+                .build(&renderer.device))
+        };
+
+        Ok(Self {
+            waveform: waveform.build(&renderer.device),
+            playhead: marker(PLAYHEAD_COL)?,
+            loop_markers: [marker(LOOP_MARKER_COL)?, marker(LOOP_MARKER_COL)?],
+            show_loop: false,
+            heat: None,
+            worst_label: None,
+            position,
+            size,
+            duration,
+        })
+    }
+
+    /// The x coordinate of the given time in the song.
+    fn x_for_time(&self, time: f32) -> f32 {
+        let progress = if self.duration > 0.0 {
+            (time / self.duration).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        self.position[0] + progress * self.size[0]
+    }
+
+    /// Returns the time in the song at the given point on the screen, or None if the point isn't
+    /// on the strip. This is where to seek to when the strip is clicked or dragged.
+    pub fn time_at(&self, point: (f32, f32)) -> Option<f32> {
+        let (px, py) = point;
+        let [x, y] = self.position;
+        let [width, height] = self.size;
+
+        if !(x..=x + width).contains(&px) || !(y..=y + height).contains(&py) {
+            return None;
+        }
+
+        Some((px - x) / width * self.duration)
+    }
+
+    /// Moves the playhead to the given time in the song.
+    pub fn set_playhead(&self, time: f32, renderer: &Renderer) {
+        self.playhead
+            .set_position([self.x_for_time(time), 0.0, 0.0], renderer);
+    }
+
+    /// Shows the bounds of the loop being practised, or hides them if there isn't one.
+    pub fn set_loop(&mut self, bounds: Option<(f32, f32)>, renderer: &Renderer) {
+        self.show_loop = bounds.is_some();
+
+        if let Some((start, end)) = bounds {
+            for (marker, time) in self.loop_markers.iter().zip([start, end]) {
+                marker.set_position([self.x_for_time(time), 0.0, 0.0], renderer);
+            }
+        }
+    }
+}
+
+impl WaveformStrip {
+    /// Shows a heat map (see [heat_buckets]) along the bottom of the strip. Each bucket is more
+    /// opaque the hotter it is.
+    pub fn set_heat(&mut self, heat: &[f32], renderer: &Renderer) -> Result<(), TessellationError> {
+        if heat.iter().all(|heat| *heat <= 0.0) {
+            self.heat = None;
+            return Ok(());
+        }
+
+        let [x, y] = self.position;
+        let [width, height] = self.size;
+        let bucket_width = width / heat.len() as f32;
+        let top = y + height * (1.0 - HEAT_HEIGHT);
+
+        let mut shape = ShapeBuilder::new();
+        for (i, heat) in heat.iter().enumerate().filter(|(_, heat)| **heat > 0.0) {
+            let left = x + i as f32 * bucket_width;
+            let [r, g, b, a] = HEAT_COL;
+
+            shape = shape.filled_rectangle(
+                [left, top],
+                [left + bucket_width, y + height],
+                SolidColour::new([r, g, b, a * heat]),
+            )?;
+        }
+
+        self.heat = Some(shape.build(&renderer.device));
+        Ok(())
+    }
+
+    /// Shows the given time in the song above the strip, over where it is on the strip, or takes it
+    /// away if there isn't one. This is for pointing out the worst part of the heat map (see
+    /// [worst_bucket_time]) precisely enough to seek to.
+    pub fn set_worst_label(&mut self, time: Option<f32>, renderer: &mut Renderer) {
+        let [x, y] = self.position;
+        let width = self.size[0];
+
+        self.worst_label = time.map(|time| {
+            let centre =
+                (self.x_for_time(time)).clamp(x + WORST_LABEL_INSET, x + width - WORST_LABEL_INSET);
+
+            TextBuilder::new(
+                format!("Most mistakes at {}", format_timestamp(time)),
+                renderer.font("mplus bold"),
+                [centre, y - WORST_LABEL_GAP],
+            )
+            .horizontal_align(HorizontalAlignment::Center)
+            .vertical_align(VerticalAlignment::Bottom)
+            .font_size(Some(FontSize::Px(WORST_LABEL_SIZE)))
+            .color(HEAT_COL)
+            .outlined([0., 0., 0., 1.], 2.)
+            .build_text(renderer)
+        });
+    }
+}
+
+/// Formats a time in the song as minutes and seconds to the nearest tenth, e.g. "1:23.4".
+pub fn format_timestamp(seconds: f32) -> String {
+    let tenths = (seconds.max(0.0) * 10.0).round() as u32;
+    format!("{}:{:02}.{}", tenths / 600, tenths / 10 % 60, tenths % 10)
+}
+
+impl Renderable for WaveformStrip {
+    fn render<'pass>(
+        &'pass self,
+        renderer: &'pass Renderer,
+        render_pass: &mut wgpu::RenderPass<'pass>,
+    ) {
+        self.waveform.render(renderer, render_pass);
+
+        if let Some(heat) = &self.heat {
+            heat.render(renderer, render_pass);
+        }
+
+        if self.show_loop {
+            for marker in &self.loop_markers {
+                marker.render(renderer, render_pass);
+            }
+        }
+
+        self.playhead.render(renderer, render_pass);
+
+        if let Some(label) = &self.worst_label {
+            label.render(renderer, render_pass);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frames(samples: &[f32]) -> Vec<Frame> {
+        samples
+            .iter()
+            .map(|&sample| Frame::new(sample, -sample))
+            .collect()
+    }
+
+    #[test]
+    fn test_waveform_buckets() {
+        let frames = frames(&[0.5, -0.5, 0.5, -0.5, 0.0, 0.0, 1.0, 0.0]);
+        let buckets = waveform_buckets(&frames, 4);
+
+        assert_eq!(buckets.len(), 4);
+        assert_eq!(
+            buckets[0],
+            WaveformBucket {
+                peak: 0.5,
+                rms: 0.5
+            }
+        );
+        assert_eq!(
+            buckets[1],
+            WaveformBucket {
+                peak: 0.5,
+                rms: 0.5
+            }
+        );
+        assert_eq!(buckets[2], WaveformBucket::default());
+        assert_eq!(buckets[3].peak, 1.0);
+        assert!((buckets[3].rms - 0.5f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_waveform_buckets_uneven() {
+        // Every sample ends up in exactly one bucket, even when they don't divide evenly
+        let frames = frames(&[1.0; 7]);
+        let buckets = waveform_buckets(&frames, 3);
+        assert!(buckets.iter().all(|bucket| bucket.peak == 1.0));
+
+        // More buckets than samples leaves some of them empty
+        let buckets = waveform_buckets(&frames, 10);
+        assert_eq!(buckets.len(), 10);
+        assert_eq!(buckets.iter().filter(|bucket| bucket.peak > 0.0).count(), 7);
+
+        assert!(waveform_buckets(&[], 5)
+            .iter()
+            .all(|bucket| *bucket == WaveformBucket::default()));
+    }
+
+    #[test]
+    fn test_heat_buckets() {
+        // A 10 second song in 5 buckets, with failures bunched up at 4-6 and 8-10 seconds
+        let failures = [0.5, 4.1, 4.9, 6.5, 9.99, 10.0, -1.0];
+        let heat = heat_buckets(failures, 10.0, 5);
+
+        assert_eq!(heat, [0.5, 0.0, 1.0, 0.5, 1.0]);
+        // The earlier of the two worst buckets wins
+        assert_eq!(worst_bucket_time(&heat, 10.0), Some(5.0));
+
+        let cold = heat_buckets([], 10.0, 5);
+        assert_eq!(cold, [0.0; 5]);
+        assert_eq!(worst_bucket_time(&cold, 10.0), None);
+        assert_eq!(heat_buckets([1.0], 0.0, 3), [0.0; 3]);
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0.0), "0:00.0");
+        assert_eq!(format_timestamp(83.44), "1:23.4");
+        assert_eq!(format_timestamp(59.96), "1:00.0");
+        assert_eq!(format_timestamp(-1.0), "0:00.0");
+    }
+
+    #[test]
+    fn test_failure_log() {
+        let mut log = FailureLog::new();
+        log.set_loop(Some((10.0, 20.0)));
+
+        for i in 0..FAILURE_LIMIT + 10 {
+            log.record(10.0 + i as f32 / 100.0);
+        }
+
+        // The oldest failures are forgotten first
+        assert_eq!(log.times().count(), FAILURE_LIMIT);
+        assert_eq!(log.times().next(), Some(10.1));
+
+        // Setting the same loop again keeps them, but moving it doesn't
+        log.set_loop(Some((10.0, 20.0)));
+        assert_eq!(log.times().count(), FAILURE_LIMIT);
+        log.set_loop(Some((12.0, 20.0)));
+        assert_eq!(log.times().count(), 0);
+    }
+}
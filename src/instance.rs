@@ -0,0 +1,193 @@
+//! Makes sure only one copy of the game runs at a time.
+//!
+//! Two copies would fight over the data files and the audio device, which is easy to end up with
+//! when launching the game from a launcher. The first copy takes a lock on a file in the data
+//! directory and holds it until it closes. Any copy started after that can't take the lock, so it
+//! asks the first one to bring its window to the front and exits.
+//!
+//! The lock is held by the operating system, so it's released even if the game crashes. A lock
+//! file left behind by a crash doesn't stop the game from starting.
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+/// The name of the lock file held by the running game.
+pub const INSTANCE_LOCK_PATH: &str = "taiko.lock";
+/// The name of the file the running game writes the port it listens for focus requests on to.
+///
+/// This can't go in the lock file because some platforms don't let other processes read a locked
+/// file.
+pub const INSTANCE_PORT_PATH: &str = "taiko.port";
+
+/// What another copy of the game sends to ask this one to bring its window to the front.
+const FOCUS_MESSAGE: &[u8] = b"focus\n";
+/// How long to wait for the running game to answer before giving up on it.
+const PING_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Proof that this is the only copy of the game running. The lock is released when this is
+/// dropped.
+pub struct InstanceGuard {
+    _lock: File,
+    port_path: PathBuf,
+    focus_requests: Option<Receiver<()>>,
+}
+
+impl InstanceGuard {
+    /// Returns whether another copy of the game has asked this one to bring its window to the
+    /// front since the last time this was called.
+    pub fn focus_requested(&self) -> bool {
+        self.focus_requests
+            .as_ref()
+            .is_some_and(|requests| requests.try_iter().count() > 0)
+    }
+}
+
+impl Drop for InstanceGuard {
+    fn drop(&mut self) {
+        if self.focus_requests.is_some() {
+            let _ = std::fs::remove_file(&self.port_path);
+        }
+    }
+}
+
+/// Tries to become the only running copy of the game, using the lock file in the given directory.
+///
+/// Returns None if another copy of the game is already running.
+pub fn acquire<P: AsRef<Path>>(dir: P) -> anyhow::Result<Option<InstanceGuard>> {
+    let dir = dir.as_ref();
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(INSTANCE_LOCK_PATH))?;
+
+    match lock.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => return Ok(None),
+        Err(TryLockError::Error(e)) => return Err(e.into()),
+    }
+
+    let port_path = dir.join(INSTANCE_PORT_PATH);
+
+    // Being brought to the front is a nicety, so the game still runs if this doesn't work
+    let focus_requests = listen_for_focus_requests(&port_path)
+        .inspect_err(|e| log::warn!("couldn't listen for other copies of the game: {e}"))
+        .ok();
+
+    Ok(Some(InstanceGuard {
+        _lock: lock,
+        port_path,
+        focus_requests,
+    }))
+}
+
+/// Starts listening for other copies of the game asking this one to come to the front, and writes
+/// the port it's listening on to the given file.
+fn listen_for_focus_requests(port_path: &Path) -> anyhow::Result<Receiver<()>> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    std::fs::write(port_path, listener.local_addr()?.port().to_string())?;
+
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+
+            let _ = stream.set_read_timeout(Some(PING_TIMEOUT));
+            let mut message = [0; FOCUS_MESSAGE.len()];
+            if stream.read_exact(&mut message).is_ok()
+                && message == FOCUS_MESSAGE
+                && sender.send(()).is_err()
+            {
+                // The guard is gone, so nobody's listening any more
+                break;
+            }
+        }
+    });
+
+    Ok(receiver)
+}
+
+/// Asks the copy of the game that's already running (using the files in the given directory) to
+/// bring its window to the front. Returns whether it was asked successfully.
+pub fn ping_existing<P: AsRef<Path>>(dir: P) -> bool {
+    let ping = || -> anyhow::Result<()> {
+        let port = std::fs::read_to_string(dir.as_ref().join(INSTANCE_PORT_PATH))?
+            .trim()
+            .parse()?;
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+
+        let mut stream = TcpStream::connect_timeout(&address, PING_TIMEOUT)?;
+        stream.write_all(FOCUS_MESSAGE)?;
+        Ok(())
+    };
+
+    ping()
+        .inspect_err(|e| log::warn!("couldn't reach the running copy of the game: {e}"))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_single_instance() {
+        let dir = temp_dir("instance_lock");
+
+        let guard = acquire(&dir).unwrap().expect("nothing else has the lock");
+        assert!(acquire(&dir).unwrap().is_none());
+
+        // Once the first copy closes, the next one can start
+        drop(guard);
+        assert!(!dir.join(INSTANCE_PORT_PATH).exists());
+        assert!(acquire(&dir).unwrap().is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stale_lock_file() {
+        let dir = temp_dir("instance_stale");
+
+        // A copy that crashed leaves its files behind, but not the lock itself
+        std::fs::write(dir.join(INSTANCE_LOCK_PATH), "").unwrap();
+        std::fs::write(dir.join(INSTANCE_PORT_PATH), "1").unwrap();
+
+        let guard = acquire(&dir).unwrap().expect("the old lock was released");
+        let port = std::fs::read_to_string(dir.join(INSTANCE_PORT_PATH)).unwrap();
+        assert_ne!(port, "1");
+
+        drop(guard);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_focus_request() {
+        let dir = temp_dir("instance_focus");
+
+        let guard = acquire(&dir).unwrap().unwrap();
+        assert!(!guard.focus_requested());
+
+        assert!(ping_existing(&dir));
+
+        let start = std::time::Instant::now();
+        while !guard.focus_requested() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "never got the request"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        drop(guard);
+        assert!(!ping_existing(&dir));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,567 @@
+//! Per-song data the game remembers between sessions.
+//!
+//! Unlike [crate::settings], nothing in here is meant to be edited by hand. It's a cache of things
+//! the game has worked out (or the player has done) for each song, stored in a toml file (by
+//! default `taiko_data.toml`). Use [read_local_data] to load it and [save_local_data] to write it
+//! back out.
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::{FrameStats, PlayConditions, ScoreInt};
+use crate::paths::paths;
+use crate::persistence::write_locked;
+
+/// The name of the local data file. See [crate::paths] for where it's stored.
+pub const LOCAL_DATA_PATH: &str = "taiko_data.toml";
+
+pub static LOCAL_DATA: RwLock<LocalData> = RwLock::new(LocalData {
+    songs: BTreeMap::new(),
+    stats: PlayerStats {
+        best_roll_speed: 0.0,
+        tutorial_completed: false,
+    },
+});
+
+/// Convenience function that returns an immutable reference to [LOCAL_DATA].
+pub fn local_data() -> impl Deref<Target = LocalData> {
+    LOCAL_DATA.read().unwrap()
+}
+
+/// Convenience function that returns a mutable reference to [LOCAL_DATA].
+pub fn local_data_mut() -> impl DerefMut<Target = LocalData> {
+    LOCAL_DATA.write().unwrap()
+}
+
+/// Everything stored in the local data file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LocalData {
+    songs: BTreeMap<String, SongData>,
+    pub stats: PlayerStats,
+}
+
+impl LocalData {
+    /// Returns the data stored for the song with the given key, if there is any.
+    ///
+    /// Songs are keyed by the path of their audio file.
+    pub fn song(&self, key: &str) -> Option<&SongData> {
+        self.songs.get(key)
+    }
+
+    /// Returns the data stored for the song with the given key, creating an empty entry if
+    /// there isn't one.
+    pub fn song_mut(&mut self, key: &str) -> &mut SongData {
+        self.songs.entry(key.to_string()).or_default()
+    }
+}
+
+/// The data stored for a single song.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct SongData {
+    /// The gain (in decibels) that should be applied to the song to normalise its loudness. This
+    /// is None until the song's loudness has been analysed.
+    pub preview_gain_db: Option<f32>,
+    /// Whether the player has marked the song as a favourite.
+    pub favourite: bool,
+    /// Whether the player has hidden the song from the song list.
+    pub hidden: bool,
+    /// An offset (in milliseconds) applied to the song's notes on top of the global note offset.
+    pub offset: f32,
+    /// The most recent plays of each of the song's charts, oldest first. See
+    /// [SongData::record_play].
+    pub plays: Vec<PlayRecord>,
+}
+
+/// The most plays that are remembered for each chart.
+pub const PLAY_HISTORY_LIMIT: usize = 20;
+
+/// One play of a chart.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlayRecord {
+    pub difficulty: usize,
+    pub score: ScoreInt,
+    pub accuracy: Option<f32>,
+    /// When the play finished, in seconds since the Unix epoch.
+    pub date: u64,
+    pub conditions: PlayConditions,
+    /// How long frames took during the play, so a bad play can be put down to the game running
+    /// badly. Plays recorded before this was kept don't have it.
+    #[serde(default)]
+    pub frame_stats: Option<FrameStats>,
+}
+
+impl PlayRecord {
+    /// Records a play that has just finished.
+    pub fn now(
+        difficulty: usize,
+        score: ScoreInt,
+        accuracy: Option<f32>,
+        conditions: PlayConditions,
+        frame_stats: Option<FrameStats>,
+    ) -> Self {
+        Self {
+            difficulty,
+            score,
+            accuracy,
+            date: unix_time_now(),
+            conditions,
+            frame_stats,
+        }
+    }
+
+    /// The day the play finished on (in UTC), e.g. "2024-03-09".
+    pub fn date_string(&self) -> String {
+        date_string(self.date)
+    }
+}
+
+/// The current time, in seconds since the Unix epoch.
+pub fn unix_time_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// The day the given time (in seconds since the Unix epoch) falls on in UTC, e.g. "2024-03-09".
+pub fn date_string(time: u64) -> String {
+    // Converts days since the epoch to a date in the proleptic Gregorian calendar. See
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (time / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Compares two plays by how well they went: score first, then accuracy.
+fn compare_plays(a: &PlayRecord, b: &PlayRecord) -> Ordering {
+    let accuracy = |play: &PlayRecord| play.accuracy.unwrap_or(-1.0);
+
+    a.score
+        .cmp(&b.score)
+        .then(accuracy(a).total_cmp(&accuracy(b)))
+}
+
+/// Returns the position of the personal best among the given plays of a chart: the best play made
+/// under the usual conditions (see [PlayConditions::is_default]). If two plays are equally good,
+/// the earlier one counts.
+pub fn personal_best(plays: &[PlayRecord]) -> Option<usize> {
+    plays
+        .iter()
+        .enumerate()
+        .filter(|(_, play)| play.conditions.is_default())
+        .min_by(|(_, a), (_, b)| compare_plays(b, a))
+        .map(|(i, _)| i)
+}
+
+/// Sorts the given plays of a chart (oldest first) into leaderboard order. The personal best is
+/// always at the top, and the rest are sorted from best to worst, with earlier plays first if
+/// they're equally good.
+pub fn leaderboard(plays: &[PlayRecord]) -> Vec<&PlayRecord> {
+    let best = personal_best(plays);
+
+    let mut rest: Vec<_> = plays
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| Some(*i) != best)
+        .map(|(_, play)| play)
+        .collect();
+    // This is a stable sort, so equally good plays stay in the order they were played
+    rest.sort_by(|a, b| compare_plays(b, a));
+
+    best.map(|i| &plays[i]).into_iter().chain(rest).collect()
+}
+
+/// Forgets all but the most recent [PLAY_HISTORY_LIMIT] of the given plays of a chart (oldest
+/// first). The personal best is always kept, in place of the oldest of the recent plays if it has
+/// to be.
+fn prune_plays(mut plays: Vec<PlayRecord>) -> Vec<PlayRecord> {
+    if plays.len() <= PLAY_HISTORY_LIMIT {
+        return plays;
+    }
+
+    let first_recent = plays.len() - PLAY_HISTORY_LIMIT;
+
+    match personal_best(&plays) {
+        Some(best) if best < first_recent => {
+            let best = plays.remove(best);
+            let mut kept = vec![best];
+            kept.extend(plays.drain(first_recent..));
+            kept
+        }
+
+        _ => plays.split_off(first_recent),
+    }
+}
+
+/// A change the player can make to a song's data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SongDataEdit {
+    Favourite(bool),
+    Hidden(bool),
+    Offset(f32),
+}
+
+impl SongDataEdit {
+    /// Describes the change, for showing to the player.
+    pub fn description(&self) -> String {
+        match self {
+            SongDataEdit::Favourite(true) => "added to favourites".to_string(),
+            SongDataEdit::Favourite(false) => "removed from favourites".to_string(),
+            SongDataEdit::Hidden(true) => "hidden".to_string(),
+            SongDataEdit::Hidden(false) => "unhidden".to_string(),
+            SongDataEdit::Offset(offset) => format!("offset set to {offset:+}ms"),
+        }
+    }
+}
+
+impl SongData {
+    /// The remembered plays of the given chart, oldest first.
+    pub fn plays_for(&self, difficulty: usize) -> Vec<PlayRecord> {
+        self.plays
+            .iter()
+            .filter(|play| play.difficulty == difficulty)
+            .cloned()
+            .collect()
+    }
+
+    /// Remembers a play, forgetting old plays of the same chart if there are too many. Returns
+    /// whether the play is a new personal best.
+    pub fn record_play(&mut self, play: PlayRecord) -> bool {
+        let difficulty = play.difficulty;
+        let (mut chart, others): (Vec<_>, Vec<_>) = std::mem::take(&mut self.plays)
+            .into_iter()
+            .partition(|play| play.difficulty == difficulty);
+
+        chart.push(play);
+        let new_best = personal_best(&chart) == Some(chart.len() - 1);
+
+        self.plays = others;
+        self.plays.extend(prune_plays(chart));
+        new_best
+    }
+
+    /// Applies an edit, returning the edit that will undo it.
+    pub fn apply(&mut self, edit: SongDataEdit) -> SongDataEdit {
+        match edit {
+            SongDataEdit::Favourite(favourite) => {
+                SongDataEdit::Favourite(std::mem::replace(&mut self.favourite, favourite))
+            }
+
+            SongDataEdit::Hidden(hidden) => {
+                SongDataEdit::Hidden(std::mem::replace(&mut self.hidden, hidden))
+            }
+
+            SongDataEdit::Offset(offset) => {
+                SongDataEdit::Offset(std::mem::replace(&mut self.offset, offset))
+            }
+        }
+    }
+}
+
+/// The most edits that can be undone.
+pub const UNDO_LIMIT: usize = 20;
+
+/// An edit that can be undone.
+#[derive(Debug, Clone, PartialEq)]
+struct UndoEntry {
+    song: String,
+    /// The name of the song, for describing the edit.
+    title: String,
+    edit: SongDataEdit,
+    inverse: SongDataEdit,
+}
+
+/// The edits the player has made to song data this session, so that they can be undone.
+///
+/// This isn't saved anywhere. Undoing an edit is just another edit, so it's saved the same way.
+#[derive(Debug, Clone, Default)]
+pub struct UndoStack {
+    entries: VecDeque<UndoEntry>,
+}
+
+impl UndoStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies an edit to the data for the song with the given key, remembering how to undo it.
+    pub fn apply(&mut self, data: &mut LocalData, song: &str, title: &str, edit: SongDataEdit) {
+        let inverse = data.song_mut(song).apply(edit);
+
+        if self.entries.len() == UNDO_LIMIT {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(UndoEntry {
+            song: song.to_string(),
+            title: title.to_string(),
+            edit,
+            inverse,
+        });
+    }
+
+    /// Whether there are any edits to undo.
+    pub fn can_undo(&self) -> bool {
+        !self.entries.is_empty()
+    }
+
+    /// Undoes the last edit, returning a description of what was undone. Returns None if there is
+    /// nothing to undo.
+    pub fn undo(&mut self, data: &mut LocalData) -> Option<String> {
+        let entry = self.entries.pop_back()?;
+        data.song_mut(&entry.song).apply(entry.inverse);
+
+        Some(format!(
+            "Undid \"{}\" {}",
+            entry.title,
+            entry.edit.description()
+        ))
+    }
+}
+
+/// Fun statistics about the player, kept across all songs.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct PlayerStats {
+    /// The fastest the player has ever rolled, in hits per second.
+    pub best_roll_speed: f32,
+    /// Whether the player has finished the tutorial.
+    pub tutorial_completed: bool,
+}
+
+/// Try to read and deserialize the local data file.
+///
+/// If the file doesn't exist yet, the game starts with empty data and it will be created the next
+/// time something is saved. If the file is corrupt, it is ignored (and will be overwritten).
+pub fn read_local_data() {
+    *LOCAL_DATA.write().unwrap() = load_local_data_from(&paths().local_data_file());
+}
+
+/// Reads the local data in the given file, the same way as [read_local_data].
+fn load_local_data_from(path: &Path) -> LocalData {
+    match std::fs::read_to_string(path) {
+        Ok(str) => toml::from_str(&str).unwrap_or_else(|e| {
+            log::error!(
+                "couldn't parse local data file \"{}\", ignoring it: {e}",
+                path.display()
+            );
+            LocalData::default()
+        }),
+
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => LocalData::default(),
+
+        Err(e) => {
+            log::error!("couldn't read local data file \"{}\": {e}", path.display());
+            LocalData::default()
+        }
+    }
+}
+
+/// Writes the current local data out to the local data file.
+pub fn save_local_data() -> anyhow::Result<()> {
+    save_local_data_to(paths().local_data_file())
+}
+
+/// Writes the current local data out to the given path.
+pub fn save_local_data_to<P: AsRef<Path>>(path: P) -> anyhow::Result<()> {
+    let contents = toml::to_string(&*local_data())?;
+    write_locked(path, contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn play(score: ScoreInt, accuracy: f32, date: u64) -> PlayRecord {
+        PlayRecord {
+            difficulty: 3,
+            score,
+            accuracy: Some(accuracy),
+            date,
+            conditions: PlayConditions::new(3, 0.0),
+            frame_stats: None,
+        }
+    }
+
+    fn autoplay(score: ScoreInt, date: u64) -> PlayRecord {
+        let mut play = play(score, 1.0, date);
+        play.conditions.autoplay = true;
+        play
+    }
+
+    #[test]
+    fn test_leaderboard_order() {
+        let plays = [
+            play(500, 0.5, 1),
+            play(900, 0.8, 2),
+            play(900, 0.9, 3),
+            play(700, 0.7, 4),
+            play(900, 0.9, 5),
+            // Better than everything, but not played under the usual conditions
+            autoplay(2000, 6),
+        ];
+
+        // Ties on score are broken by accuracy, and then by who got there first
+        assert_eq!(personal_best(&plays), Some(2));
+
+        let dates: Vec<_> = leaderboard(&plays).iter().map(|play| play.date).collect();
+        assert_eq!(dates, [3, 6, 5, 2, 4, 1]);
+
+        // With nothing to pin, it's just sorted
+        let dates: Vec<_> = leaderboard(&[autoplay(1, 1), autoplay(2, 2)])
+            .iter()
+            .map(|play| play.date)
+            .collect();
+        assert_eq!(dates, [2, 1]);
+        assert!(leaderboard(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_play_history_pruning() {
+        let mut song = SongData::default();
+        let mut other_chart = play(100, 0.1, 0);
+        other_chart.difficulty = 0;
+        song.record_play(other_chart.clone());
+
+        assert!(song.record_play(play(1000, 1.0, 1)));
+        for date in 2..(PLAY_HISTORY_LIMIT as u64 + 10) {
+            assert!(!song.record_play(play(date, 0.5, date)));
+        }
+
+        // The personal best is kept, along with the most recent plays
+        let plays = song.plays_for(3);
+        assert_eq!(plays.len(), PLAY_HISTORY_LIMIT);
+        assert_eq!(plays[0].date, 1);
+        assert_eq!(plays[1].date, 11);
+        assert_eq!(plays.last().unwrap().date, PLAY_HISTORY_LIMIT as u64 + 9);
+
+        // Other charts have their own history
+        assert_eq!(song.plays_for(0), [other_chart]);
+
+        // Once the best is one of the recent plays, the oldest play goes
+        assert!(song.record_play(play(2000, 1.0, 100)));
+        song.record_play(play(0, 0.0, 101));
+        let plays = song.plays_for(3);
+        assert_eq!(plays.len(), PLAY_HISTORY_LIMIT);
+        assert_eq!(plays[0].date, 12);
+
+        // Plays under other conditions never become the personal best, so they can be forgotten
+        let mut song = SongData::default();
+        assert!(!song.record_play(autoplay(5000, 0)));
+        for date in 1..=PLAY_HISTORY_LIMIT as u64 {
+            song.record_play(play(1, 0.1, date));
+        }
+        assert!(song.plays_for(3).iter().all(|play| play.date > 0));
+
+        song.plays.last_mut().unwrap().frame_stats = Some(FrameStats {
+            p50: 16.6,
+            p95: 17.2,
+            p99: 24.0,
+            slow_frames: 2,
+        });
+        let saved: SongData = toml::from_str(&toml::to_string(&song).unwrap()).unwrap();
+        assert_eq!(saved, song);
+    }
+
+    #[test]
+    fn test_play_dates() {
+        assert_eq!(play(0, 0.0, 0).date_string(), "1970-01-01");
+        assert_eq!(play(0, 0.0, 951_782_400).date_string(), "2000-02-29");
+        assert_eq!(play(0, 0.0, 1_709_999_999).date_string(), "2024-03-09");
+    }
+
+    #[test]
+    fn test_edit_inverses() {
+        let mut song = SongData {
+            offset: 5.0,
+            ..Default::default()
+        };
+
+        for edit in [
+            SongDataEdit::Favourite(true),
+            SongDataEdit::Hidden(true),
+            SongDataEdit::Offset(-10.0),
+        ] {
+            let before = song.clone();
+            let inverse = song.apply(edit);
+            assert_ne!(song, before);
+
+            song.apply(inverse);
+            assert_eq!(song, before);
+        }
+
+        assert_eq!(
+            song.apply(SongDataEdit::Offset(1.0)),
+            SongDataEdit::Offset(5.0)
+        );
+    }
+
+    #[test]
+    fn test_undo_limit() {
+        let mut data = LocalData::default();
+        let mut undo = UndoStack::new();
+
+        for i in 0..UNDO_LIMIT + 5 {
+            undo.apply(
+                &mut data,
+                "song",
+                "Song",
+                SongDataEdit::Offset(i as f32 + 1.0),
+            );
+        }
+
+        for _ in 0..UNDO_LIMIT {
+            assert!(undo.undo(&mut data).is_some());
+        }
+
+        assert_eq!(undo.undo(&mut data), None);
+        // The oldest edits fell off the stack, so they can't be undone
+        assert_eq!(data.song("song").unwrap().offset, 5.0);
+    }
+
+    #[test]
+    fn test_undo_after_save() {
+        let dir = temp_dir("undo_after_save");
+        let path = dir.join("local_data.toml");
+        // The local data is shared with other tests, so this edits a song of its own
+        let song = "undo after save";
+        let mut undo = UndoStack::new();
+
+        undo.apply(
+            &mut local_data_mut(),
+            song,
+            "Song",
+            SongDataEdit::Hidden(true),
+        );
+        save_local_data_to(&path).unwrap();
+        assert!(load_local_data_from(&path).song(song).unwrap().hidden);
+
+        let undone = undo.undo(&mut local_data_mut());
+        assert_eq!(undone.as_deref(), Some("Undid \"Song\" hidden"));
+        save_local_data_to(&path).unwrap();
+        assert!(!load_local_data_from(&path).song(song).unwrap().hidden);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,16 +1,60 @@
 mod app;
+mod diagnostics;
 mod game;
+mod instance;
+mod local_data;
 mod notechart_parser;
+mod paths;
+mod persistence;
 mod render;
 mod settings;
+#[cfg(test)]
+mod test_support;
+mod verify;
 
 use app::TaikoApp;
 use winit::event_loop::EventLoop;
 
 fn main() {
+    if std::env::args().any(|arg| arg == diagnostics::DIAGNOSE_FLAG) {
+        // Run before the settings are read, since that creates the settings file if it's missing
+        let result = diagnostics::write_diagnostics(None, None);
+        println!("{}", diagnostics::describe_diagnostics(&result));
+
+        return;
+    }
+
+    if std::env::args().any(|arg| arg == verify::VERIFY_REPLAY_FLAG) {
+        // The chart has to be read the same way the game reads it
+        settings::read_settings();
+        std::process::exit(verify::run(std::env::args_os()));
+    }
+
+    let data_dir = paths::paths().data_dir();
+    let instance = match instance::acquire(data_dir) {
+        Ok(Some(guard)) => Some(guard),
+
+        Ok(None) => {
+            if instance::ping_existing(data_dir) {
+                println!("The game is already running, switching to it.");
+            } else {
+                eprintln!("The game is already running.");
+            }
+
+            return;
+        }
+
+        // Better to run without the guard than not at all
+        Err(e) => {
+            eprintln!("Couldn't check whether the game is already running: {e}");
+            None
+        }
+    };
+
     settings::read_settings();
+    local_data::read_local_data();
 
     let event_loop = EventLoop::new().expect("Couldn't construct window event loop!");
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
-    event_loop.run_app(&mut TaikoApp::new()).unwrap()
+    event_loop.run_app(&mut TaikoApp::new(instance)).unwrap()
 }
@@ -0,0 +1,212 @@
+//! Working out where a chart's measures start, from its BPM and measure changes.
+//!
+//! Barlines normally come straight from the chart, but some charts (often ones converted from
+//! other formats) turn them off for the whole song, which leaves the note field with nothing to
+//! show the rhythm. Those charts get barlines put back in at the start of every measure.
+//!
+//! Charts with barlines that don't line up with the measures are usually doing something clever
+//! with `#DELAY` or BPM changes, so those are only flagged (see [misplaced_barlines]) and never
+//! changed.
+use super::{Barline, NoteChart, TimingPoint};
+
+/// How far (in seconds) a barline can be from the start of a measure and still count as being on
+/// it.
+pub const BARLINE_TOLERANCE: f32 = 0.005;
+
+/// Measures that end this close (in seconds) to a timing change end on it, so that float error
+/// doesn't push the barline into the next segment.
+const MEASURE_END_EPSILON: f32 = 1e-4;
+
+/// Works out where every measure from the first timing point up to `end` starts.
+pub fn measure_starts(timing: &[TimingPoint], end: f32) -> Vec<Barline> {
+    let Some(first) = timing.first() else {
+        return Vec::new();
+    };
+
+    let mut starts = vec![Barline {
+        time: first.time,
+        scroll_speed: first.scroll_speed,
+    }];
+
+    // How far through the current measure we are, from 0 to 1. This carries over timing changes,
+    // since a BPM change in the middle of a measure only changes how long the rest of it lasts.
+    let mut progress = 0.0;
+
+    for (i, point) in timing.iter().enumerate() {
+        let segment_end = timing.get(i + 1).map_or(end, |next| next.time);
+
+        // Measures don't move on during a delay
+        if !point.seconds_per_measure.is_finite() || point.seconds_per_measure <= 0.0 {
+            continue;
+        }
+
+        let mut time = point.time;
+
+        loop {
+            let measure_end = time + (1.0 - progress) * point.seconds_per_measure;
+
+            if measure_end > segment_end + MEASURE_END_EPSILON {
+                progress += (segment_end - time) / point.seconds_per_measure;
+                break;
+            }
+
+            starts.push(Barline {
+                time: measure_end,
+                scroll_speed: point.scroll_speed,
+            });
+            time = measure_end;
+            progress = 0.0;
+        }
+    }
+
+    starts
+}
+
+/// Returns the positions of the barlines that are more than [BARLINE_TOLERANCE] away from the
+/// start of every measure.
+pub fn misplaced_barlines(barlines: &[Barline], measure_starts: &[Barline]) -> Vec<usize> {
+    let distance_to_measure = |time: f32| {
+        let next = measure_starts.partition_point(|start| start.time < time);
+
+        [next.checked_sub(1), Some(next)]
+            .into_iter()
+            .flatten()
+            .filter_map(|i| measure_starts.get(i))
+            .map(|start| (start.time - time).abs())
+            .fold(f32::INFINITY, f32::min)
+    };
+
+    barlines
+        .iter()
+        .enumerate()
+        .filter(|(_, barline)| distance_to_measure(barline.time) > BARLINE_TOLERANCE)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Puts barlines at the start of every measure if the chart doesn't have any of its own. The one
+/// at the very start of the chart is always there, so it doesn't count.
+pub fn fill_missing_barlines(chart: &mut NoteChart) {
+    if chart.barlines.len() <= 1 {
+        chart.barlines = measure_starts(&chart.timing, chart.end_time);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::tja_fixture;
+
+    fn times(barlines: &[Barline]) -> Vec<f32> {
+        barlines.iter().map(|barline| barline.time).collect()
+    }
+
+    fn assert_times_eq(barlines: &[Barline], expected: &[f32]) {
+        let times = times(barlines);
+        assert_eq!(times.len(), expected.len(), "{times:?} vs {expected:?}");
+        for (time, expected) in times.iter().zip(expected) {
+            assert!((time - expected).abs() < 1e-4, "{times:?} vs {expected:?}");
+        }
+    }
+
+    #[test]
+    fn test_measure_starts() {
+        let timing = [
+            TimingPoint {
+                time: 0.0,
+                seconds_per_measure: 2.0,
+                scroll_speed: 1.0,
+                bpm: 120.0,
+            },
+            // Halfway through the second measure, the BPM doubles
+            TimingPoint {
+                time: 3.0,
+                seconds_per_measure: 1.0,
+                scroll_speed: 2.0,
+                bpm: 240.0,
+            },
+        ];
+
+        let starts = measure_starts(&timing, 5.5);
+        assert_times_eq(&starts, &[0.0, 2.0, 3.5, 4.5, 5.5]);
+        assert_eq!(starts[2].scroll_speed, 2.0);
+
+        assert!(measure_starts(&[], 10.0).is_empty());
+    }
+
+    #[test]
+    fn test_delays_hold_the_measure() {
+        let timing = [
+            TimingPoint {
+                time: 0.0,
+                seconds_per_measure: 2.0,
+                scroll_speed: 1.0,
+                bpm: 120.0,
+            },
+            TimingPoint {
+                time: 1.0,
+                seconds_per_measure: f32::INFINITY,
+                scroll_speed: 1.0,
+                bpm: 120.0,
+            },
+            TimingPoint {
+                time: 1.5,
+                seconds_per_measure: 2.0,
+                scroll_speed: 1.0,
+                bpm: 120.0,
+            },
+        ];
+
+        assert_times_eq(&measure_starts(&timing, 4.5), &[0.0, 2.5, 4.5]);
+    }
+
+    #[test]
+    fn test_synthesised_barlines_match_chart() {
+        // The synthesised barlines should be the same ones the chart would have had
+        let track = "1111,\n#BPMCHANGE 240\n11112222,\n#MEASURE 3/4\n111,\n#BPMCHANGE 60\n10,\n\
+            #MEASURE 4/4\n#SCROLL 2\n1111,\n";
+        let chart = tja_fixture(120, "", track).chart;
+        let starts = measure_starts(&chart.timing, chart.end_time);
+
+        assert_times_eq(&starts, &times(&chart.barlines));
+        assert_times_eq(&starts, &[0.0, 2.0, 3.0, 3.75, 6.75, 10.75]);
+        assert!(misplaced_barlines(&chart.barlines, &starts).is_empty());
+
+        // Charts that hide all their barlines get them back
+        let mut hidden = tja_fixture(120, "", &format!("#BARLINEOFF\n{track}")).chart;
+        let scroll_speeds = |barlines: &[Barline]| {
+            barlines
+                .iter()
+                .map(|barline| barline.scroll_speed)
+                .collect::<Vec<_>>()
+        };
+        assert_times_eq(&hidden.barlines, &times(&chart.barlines));
+        assert_eq!(
+            scroll_speeds(&hidden.barlines),
+            scroll_speeds(&chart.barlines)
+        );
+
+        // Charts that only hide some of their barlines are left alone
+        let partly_hidden = tja_fixture(120, "", &format!("1111,\n#BARLINEOFF\n{track}")).chart;
+        assert_eq!(partly_hidden.barlines.len(), 2);
+
+        hidden.barlines.clear();
+        fill_missing_barlines(&mut hidden);
+        assert_times_eq(&hidden.barlines, &times(&chart.barlines));
+    }
+
+    #[test]
+    fn test_misplaced_barlines() {
+        let starts = [0.0, 2.0, 4.0].map(|time| Barline {
+            time,
+            scroll_speed: 1.0,
+        });
+        let barlines = [0.0, 2.003, 3.0, 4.5, -1.0].map(|time| Barline {
+            time,
+            scroll_speed: 1.0,
+        });
+
+        assert_eq!(misplaced_barlines(&barlines, &starts), [2, 3, 4]);
+        assert_eq!(misplaced_barlines(&barlines, &[]), [0, 1, 2, 3, 4]);
+    }
+}
@@ -11,11 +11,17 @@
 
 use std::collections::HashMap;
 
-const DEFAULT_BPM: f32 = 120.0;
+use serde::{Deserialize, Serialize};
+
+use super::TJAParseError;
+
+/// The BPM that scroll speeds are relative to, and that charts play at if they don't give one.
+pub const DEFAULT_BPM: f32 = 120.0;
 
 /// The type of note (e.g., Don, Ka, Balloon etc)
 ///
 /// Drumroll variants also contain a float value indicating how long the drumroll continues for.
+/// Balloons and kusudama (the big balloons) also contain how many hits it takes to pop them.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum NoteType {
     Don,
@@ -25,8 +31,12 @@ pub enum NoteType {
     Roll(f32),
     BigRoll(f32),
     BalloonRoll(f32, u32),
-    SpecialRoll(f32, u32),
+    Kusudama(f32, u32),
+    /// A big don that's meant to be hit with both hands (`A` in a chart). It's played the same way
+    /// as a [NoteType::BigDon].
     CoopDon,
+    /// A big kat that's meant to be hit with both hands (`B` in a chart). It's played the same way
+    /// as a [NoteType::BigKat].
     CoopKat,
 }
 
@@ -37,7 +47,7 @@ impl NoteType {
             NoteType::Roll(_)
                 | NoteType::BigRoll(_)
                 | NoteType::BalloonRoll(_, _)
-                | NoteType::SpecialRoll(_, _)
+                | NoteType::Kusudama(_, _)
         )
     }
 
@@ -48,6 +58,20 @@ impl NoteType {
     pub fn is_kat(&self) -> bool {
         matches!(self, NoteType::Kat | NoteType::BigKat | NoteType::CoopKat)
     }
+
+    /// The same note with dons and kats swapped, keeping its size. Drumrolls and balloons stay as
+    /// they are.
+    pub fn swapped(&self) -> Self {
+        match self {
+            NoteType::Don => NoteType::Kat,
+            NoteType::Kat => NoteType::Don,
+            NoteType::BigDon => NoteType::BigKat,
+            NoteType::BigKat => NoteType::BigDon,
+            NoteType::CoopDon => NoteType::CoopKat,
+            NoteType::CoopKat => NoteType::CoopDon,
+            note_type => *note_type,
+        }
+    }
 }
 
 /// A note, as it will be stored during the actual game.
@@ -64,6 +88,8 @@ pub struct Note {
     /// This will automatically be scaled with frame rate, so default scroll for notes at 240bpm
     /// will be 2.0.
     pub scroll_speed: f32,
+    /// Whether the note is in gogo time.
+    pub gogo: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -72,11 +98,140 @@ pub struct Barline {
     pub scroll_speed: f32,
 }
 
+/// A point in a chart from which measures last a certain length, e.g. the start of the chart or a
+/// BPM change.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimingPoint {
+    pub time: f32,
+    /// How long a measure lasts from this point on, in seconds. This is infinite during a
+    /// `#DELAY`, since the measure doesn't move on until it's over.
+    pub seconds_per_measure: f32,
+    pub scroll_speed: f32,
+    pub bpm: f32,
+}
+
+/// How notes scroll across the screen. This is set for a course by putting `#BMSCROLL` or
+/// `#HBSCROLL` before its `#START`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScrollMode {
+    /// Every note moves at its own speed, from the BPM and `#SCROLL` it was placed with.
+    #[default]
+    Normal,
+    /// Notes move along with the beat, so they all speed up and slow down together when the BPM
+    /// changes, and stand still during a `#DELAY`. `#SCROLL` is ignored.
+    Bms,
+    /// The same as [ScrollMode::Bms], except each note's `#SCROLL` still applies on top.
+    Hbs,
+}
+
+/// A stretch of a chart between a `#GOGOSTART` and a `#GOGOEND`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GogoTime {
+    pub start: f32,
+    pub end: f32,
+}
+
+/// The start of a section of a chart (e.g. the chorus), marked with `#SECTION` or a comment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionLabel {
+    pub time: f32,
+    /// The section's name, if it was given one.
+    pub name: Option<String>,
+}
+
+/// A `#BGAON` or `#BGAOFF` command, which shows or hides the background movie.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BgaEvent {
+    pub time: f32,
+    /// Whether the movie is shown from this point on.
+    pub visible: bool,
+}
+
+/// A line of lyrics from a `#LYRIC` command, shown from its time until the next one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lyric {
+    pub time: f32,
+    pub text: String,
+}
+
+/// What a dan course exam is measured by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExamCondition {
+    /// How full the soul gauge is, as a percentage (`g`).
+    Gauge,
+    /// Good hits (`jp`).
+    Goods,
+    /// Ok hits (`jg`).
+    Oks,
+    /// Bad hits and misses (`jb`).
+    Bads,
+    /// Score (`s`).
+    Score,
+    /// Drumroll and balloon hits (`r`).
+    Rolls,
+    /// Notes hit, whether good or ok (`h`).
+    Hits,
+    /// The longest combo (`c`).
+    Combo,
+}
+
+/// Whether an exam is passed by reaching its values or by staying under them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExamScope {
+    /// At least the value is needed (`m`).
+    AtLeast,
+    /// Less than the value is needed (`l`).
+    LessThan,
+}
+
+/// One of the exams a dan course has to be passed with, from an `EXAM1`, `EXAM2`... line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExamRequirement {
+    pub condition: ExamCondition,
+    /// The value needed to pass.
+    pub red: u32,
+    /// The value needed to pass with a gold rank.
+    pub gold: u32,
+    pub scope: ExamScope,
+}
+
+/// A song a dan course goes on to, from a `#NEXTSONG` command. The course's first song is the one
+/// given in the song's own metadata.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DanSong {
+    /// When this song starts in the course's chart.
+    pub time: f32,
+    pub title: String,
+    /// The subtitle, without its `--` or `++`.
+    pub subtitle: Option<String>,
+    pub genre: Option<String>,
+    /// The audio file for this song, relative to the tja file.
+    pub audio_filename: String,
+    pub score_init: Option<u32>,
+    pub score_diff: Option<u32>,
+    /// The level of this song, if given.
+    pub level: Option<u8>,
+    /// The difficulty this song is usually played on, if given.
+    pub course: Option<usize>,
+}
+
 /// The data for a song, including its metadata and difficulties/note tracks.
-#[derive(Debug, Clone)]
+///
+/// Everything but the notes can be saved and loaded with serde (see [Difficulty::chart]).
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Song {
     pub title: String,
+    /// A romanised (or otherwise ASCII) version of the title, which some charts give with
+    /// `TITLEEN`.
+    pub title_en: Option<String>,
+    /// The subtitle (usually the artist or where the song is from), without its `--` or `++`.
     pub subtitle: Option<String>,
+    /// Whether the subtitle is shown while the song is played, not just in song select. Charts
+    /// start the subtitle with `--` to hide it.
+    pub show_subtitle: bool,
+    pub genre: Option<String>,
+    /// Who made the chart (`MAKER`).
+    pub maker: Option<String>,
     pub audio_filename: String,
     pub bpm: f32,
     /// The offset of the notes in seconds.
@@ -85,19 +240,162 @@ pub struct Song {
     pub offset: f32,
     /// The time that the song preview should start from.
     pub demostart: f32,
+    /// How loud the song should be played, as a percentage (`SONGVOL`).
+    pub song_volume: f32,
+    /// How loud hit sounds should be played, as a percentage (`SEVOL`).
+    pub se_volume: f32,
+    /// The background movie file, if the chart has one (`BGMOVIE`).
+    pub bgmovie: Option<String>,
+    /// How many seconds after the song starts the background movie should start, given by
+    /// `MOVIEOFFSET` (or `BGOFFSET` in older charts).
+    pub movie_offset: f32,
+    /// A file with the song's lyrics in it (`LYRICS`). This is only kept track of, not read.
+    pub lyrics_file: Option<String>,
     pub difficulties: [Option<Difficulty>; 5],
+    /// The dan course (`COURSE:Dan`), if this is one. These chain several songs together, which
+    /// are in its chart's [dan_songs](NoteChart::dan_songs). Dan courses can't be played yet.
+    pub dan: Option<Difficulty>,
+    /// The exams the dan course has to be passed with, in order.
+    pub exams: Vec<ExamRequirement>,
+    /// Anything that was skipped over while reading the chart, if it was read leniently (see
+    /// [ParseOptions::lenient](super::ParseOptions::lenient)).
+    pub warnings: Vec<TJAParseError>,
+}
+
+impl Song {
+    /// The title to show the player. If they'd rather see romanised titles, that's used when the
+    /// chart has one.
+    pub fn display_title(&self, prefer_romanised: bool) -> &str {
+        match &self.title_en {
+            Some(title_en) if prefer_romanised && !title_en.is_empty() => title_en,
+            _ => &self.title,
+        }
+    }
+
+    /// The audio file that should be played for the given difficulty.
+    ///
+    /// This is usually the same for every difficulty, but courses can override it.
+    pub fn course_audio_filename(&self, difficulty: usize) -> &str {
+        self.difficulties
+            .get(difficulty)
+            .and_then(|d| d.as_ref()?.audio_filename.as_deref())
+            .unwrap_or(&self.audio_filename)
+    }
+
+    /// The time that the song preview should start from for the given difficulty.
+    pub fn course_demostart(&self, difficulty: usize) -> f32 {
+        self.difficulties
+            .get(difficulty)
+            .and_then(|d| d.as_ref()?.demostart)
+            .unwrap_or(self.demostart)
+    }
 }
 
 impl Default for Song {
     fn default() -> Self {
         Self {
             title: "".to_string(),
+            title_en: None,
             subtitle: None,
+            show_subtitle: true,
+            genre: None,
+            maker: None,
             audio_filename: "".to_string(),
             bpm: DEFAULT_BPM,
             offset: 0.0,
             demostart: 0.0,
+            song_volume: 100.0,
+            se_volume: 100.0,
+            bgmovie: None,
+            movie_offset: 0.0,
+            lyrics_file: None,
             difficulties: [None, None, None, None, None],
+            dan: None,
+            exams: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+/// The most stars a level is shown with.
+pub const MAX_STARS: u8 = 10;
+
+/// Whether a level was written as a little harder (`8+`) or easier (`8-`) than usual.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LevelModifier {
+    Plus,
+    Minus,
+}
+
+/// The level a chart says it is (`LEVEL`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StarLevel {
+    /// The level as it was written, which can be more than [MAX_STARS].
+    pub level: u8,
+    pub modifier: Option<LevelModifier>,
+}
+
+impl StarLevel {
+    pub fn new(level: u8) -> Self {
+        Self {
+            level,
+            modifier: None,
+        }
+    }
+
+    /// How many stars to show for this level, from 1 to [MAX_STARS].
+    pub fn stars(&self) -> u8 {
+        self.level.clamp(1, MAX_STARS)
+    }
+}
+
+impl std::str::FromStr for StarLevel {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (number, modifier) = if let Some(number) = s.strip_suffix('+') {
+            (number, Some(LevelModifier::Plus))
+        } else if let Some(number) = s.strip_suffix('-') {
+            (number, Some(LevelModifier::Minus))
+        } else {
+            (s, None)
+        };
+
+        Ok(Self {
+            level: number.trim_end().parse()?,
+            modifier,
+        })
+    }
+}
+
+/// Shows the level the way it's shown next to the stars, e.g. `8+`.
+impl std::fmt::Display for StarLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let modifier = match self.modifier {
+            Some(LevelModifier::Plus) => "+",
+            Some(LevelModifier::Minus) => "-",
+            None => "",
+        };
+
+        write!(f, "{}{modifier}", self.stars())
+    }
+}
+
+/// Which of the two players a note track is for, in courses that have a track for each
+/// (`#START P1` and `#START P2`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Player {
+    Player1,
+    Player2,
+}
+
+impl Player {
+    /// 0 for player 1 and 1 for player 2.
+    pub fn index(self) -> usize {
+        match self {
+            Player::Player1 => 0,
+            Player::Player2 => 1,
         }
     }
 }
@@ -105,21 +403,239 @@ impl Default for Song {
 /// A single difficulty setting and its associated chart.
 ///
 /// TODO: currently this cannot handle "Diverge Notes". see [NoteChart]
-/// for details. It also cannot handle multiple tracks for different
-/// players.
-#[derive(Debug, Clone)]
+/// for details.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Difficulty {
-    pub star_level: u8,
+    /// The level the chart says it is, if it says. See [Difficulty::estimated_level] for when it
+    /// doesn't (or when it can't be trusted).
+    pub star_level: Option<StarLevel>,
+    /// The level estimated from the chart's notes, if they've been read (see
+    /// [parse_tja_metadata](super::parse_tja_metadata)). See
+    /// [estimate_difficulty](super::difficulty::estimate_difficulty).
+    pub estimated_level: Option<u8>,
+    /// The notes. These are left out when the difficulty is saved, and are empty when it's loaded
+    /// again, the same as when only the metadata was parsed.
+    ///
+    /// If the course only has tracks for each player (see [player_charts](Self::player_charts)),
+    /// these are the notes of the first one.
+    #[serde(skip)]
     pub chart: NoteChart,
+    /// The notes each player plays in a 2P battle, if the course has a separate track for them
+    /// (`#START P1` or `#START P2`), indexed by [Player::index]. Like [chart](Self::chart), these
+    /// are left out when the difficulty is saved.
+    #[serde(skip)]
+    pub player_charts: [Option<NoteChart>; 2],
+    /// The audio file for this difficulty, if it is different to the song's.
+    pub audio_filename: Option<String>,
+    /// The preview start time for this difficulty, if it is different to the song's.
+    pub demostart: Option<f32>,
+    /// The points for the first good hit (`SCOREINIT`), if the chart gives them.
+    pub score_init: Option<u32>,
+    /// How many points each good hit goes up by as the combo grows (`SCOREDIFF`), if the chart
+    /// gives it.
+    pub score_diff: Option<u32>,
+    /// Who charted this difficulty (`NOTESDESIGNER`), if it's credited separately.
+    pub notes_designer: Option<String>,
+}
+
+impl Difficulty {
+    /// Returns the level to show for this difficulty, and whether it's an estimate.
+    ///
+    /// The estimate is used if the chart doesn't give a level, or if `prefer_estimate` is set.
+    /// Returns None if there's no estimate because the notes haven't been read, and the chart
+    /// doesn't give a level either.
+    pub fn level(&self, prefer_estimate: bool) -> Option<(StarLevel, bool)> {
+        match (self.star_level, self.estimated_level) {
+            (Some(level), None) => Some((level, false)),
+            (Some(level), Some(_)) if !prefer_estimate => Some((level, false)),
+            (_, estimate) => Some((StarLevel::new(estimate?), true)),
+        }
+    }
+
+    /// The notes the given player plays in a 2P battle. Players without a track of their own play
+    /// the course's usual notes.
+    pub fn chart_for(&self, player: Player) -> &NoteChart {
+        self.player_charts[player.index()]
+            .as_ref()
+            .unwrap_or(&self.chart)
+    }
+}
+
+/// The branches of a [BranchSection], in the order they're indexed in.
+pub const BRANCH_NAMES: [&str; 3] = ["Normal", "Expert", "Master"];
+/// The index of the master branch, which is the one that's played for now.
+pub const MASTER_BRANCH: usize = 2;
+
+/// What a [BranchCondition] measures the player's playing by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchRequirement {
+    /// How many times the player hit drumrolls.
+    Rolls,
+    /// The player's accuracy, as a percentage.
+    Accuracy,
+    Score,
+}
+
+/// Decides which branch of a [BranchSection] the player is sent down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BranchCondition {
+    pub requirement: BranchRequirement,
+    /// What the player needs to reach for the expert branch.
+    pub expert: f32,
+    /// What the player needs to reach for the master branch.
+    pub master: f32,
+}
+
+/// A part of a chart that's split into branches ("diverge notes"), which the player is sent down
+/// depending on how well they've been playing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BranchSection {
+    pub condition: BranchCondition,
+    /// When the section starts.
+    pub start: f32,
+    /// When the section ends, and the chart goes back to having one set of notes.
+    pub end: f32,
+    /// The notes in each branch, indexed as in [BRANCH_NAMES].
+    pub notes: [Vec<Note>; 3],
 }
 
 /// The notes for a single difficulty setting.
 ///
-/// TODO: Currently, this is just a linear stream of notes. Eventually
-/// we will have to handle songs with multiple streams that switch
-/// depending on the player's performance ("diverge notes").
+/// Charts with branches only play the master branch for now, so that's what is in `notes`. The
+/// other branches are kept in `branches`.
 #[derive(Default, Debug, Clone)]
 pub struct NoteChart {
     pub notes: Vec<Note>,
+    /// The parts of the chart that are split into branches, in order.
+    pub branches: Vec<BranchSection>,
     pub barlines: Vec<Barline>,
+    /// The sections the charter marked out, in order.
+    pub sections: Vec<SectionLabel>,
+    /// Every change to the length of a measure, in order. See
+    /// [measure_starts](super::barlines::measure_starts).
+    pub timing: Vec<TimingPoint>,
+    /// The time the chart's last measure ends.
+    pub end_time: f32,
+    /// The parts of the chart in gogo time, in order.
+    pub gogo_times: Vec<GogoTime>,
+    /// When the background movie is shown and hidden, in order. Nothing uses these until
+    /// background movies are played.
+    pub bga_events: Vec<BgaEvent>,
+    /// The lines of lyrics, in order.
+    pub lyrics: Vec<Lyric>,
+    /// The songs a dan course goes on to after its first, in order.
+    pub dan_songs: Vec<DanSong>,
+    pub scroll_mode: ScrollMode,
+}
+
+impl NoteChart {
+    /// Whether the chart is in gogo time at the given time.
+    pub fn is_gogo(&self, time: f32) -> bool {
+        self.gogo_times
+            .iter()
+            .any(|gogo| gogo.start <= time && time < gogo.end)
+    }
+
+    /// The chart as it's played with the song sped up (or slowed down) by the given factor. Every
+    /// time is divided by it, and everything scrolls that much faster, so the chart looks the same
+    /// on screen.
+    pub fn at_speed(&self, speed: f32) -> NoteChart {
+        let time = |time: f32| time / speed;
+        let note = |note: &Note| Note {
+            note_type: match note.note_type {
+                NoteType::Roll(length) => NoteType::Roll(time(length)),
+                NoteType::BigRoll(length) => NoteType::BigRoll(time(length)),
+                NoteType::BalloonRoll(length, hits) => NoteType::BalloonRoll(time(length), hits),
+                NoteType::Kusudama(length, hits) => NoteType::Kusudama(time(length), hits),
+                note_type => note_type,
+            },
+            time: time(note.time),
+            scroll_speed: note.scroll_speed * speed,
+            gogo: note.gogo,
+        };
+
+        NoteChart {
+            notes: self.notes.iter().map(note).collect(),
+            branches: self
+                .branches
+                .iter()
+                .map(|branch| BranchSection {
+                    condition: branch.condition,
+                    start: time(branch.start),
+                    end: time(branch.end),
+                    notes: branch
+                        .notes
+                        .clone()
+                        .map(|notes| notes.iter().map(note).collect()),
+                })
+                .collect(),
+            barlines: self
+                .barlines
+                .iter()
+                .map(|barline| Barline {
+                    time: time(barline.time),
+                    scroll_speed: barline.scroll_speed * speed,
+                })
+                .collect(),
+            sections: self
+                .sections
+                .iter()
+                .map(|section| SectionLabel {
+                    time: time(section.time),
+                    ..section.clone()
+                })
+                .collect(),
+            timing: self
+                .timing
+                .iter()
+                .map(|point| TimingPoint {
+                    time: time(point.time),
+                    seconds_per_measure: time(point.seconds_per_measure),
+                    scroll_speed: point.scroll_speed * speed,
+                    bpm: point.bpm * speed,
+                })
+                .collect(),
+            end_time: time(self.end_time),
+            gogo_times: self
+                .gogo_times
+                .iter()
+                .map(|gogo| GogoTime {
+                    start: time(gogo.start),
+                    end: time(gogo.end),
+                })
+                .collect(),
+            bga_events: self
+                .bga_events
+                .iter()
+                .map(|event| BgaEvent {
+                    time: time(event.time),
+                    ..*event
+                })
+                .collect(),
+            lyrics: self
+                .lyrics
+                .iter()
+                .map(|lyric| Lyric {
+                    time: time(lyric.time),
+                    ..lyric.clone()
+                })
+                .collect(),
+            dan_songs: self
+                .dan_songs
+                .iter()
+                .map(|song| DanSong {
+                    time: time(song.time),
+                    ..song.clone()
+                })
+                .collect(),
+            scroll_mode: self.scroll_mode,
+        }
+    }
+
+    /// The line of lyrics being sung at the given time, if there is one.
+    pub fn lyric_at(&self, time: f32) -> Option<&str> {
+        let i = self.lyrics.partition_point(|lyric| lyric.time <= time);
+        let lyric = &self.lyrics[..i].last()?.text;
+        (!lyric.is_empty()).then_some(lyric.as_str())
+    }
 }
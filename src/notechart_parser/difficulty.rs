@@ -0,0 +1,211 @@
+//! Estimates how difficult a chart is from its notes.
+//!
+//! Lots of charts floating around have no `LEVEL`, or one that has little to do with the chart, so
+//! this gives a rating on the same 1 to 10 scale as official charts that can be used instead. It
+//! only looks at the notes, so it's entirely deterministic: the same chart always gets the same
+//! rating.
+//!
+//! The rating is a weighted sum of a few measurements:
+//! - how dense the notes are, both in the busiest parts of the chart and on average,
+//! - how long the longest burst of closely spaced notes is,
+//! - how mixed up the colours are (a stream of all dons is easier to read than a jumble),
+//! - how much of the chart is drumrolls and balloons.
+//!
+//! The weights were picked so that official charts land roughly on their stated levels. It's not
+//! going to be perfect, but it's close enough to tell a 3 from an 8.
+use super::{NoteChart, NoteType};
+
+/// The length of the window that note density is measured over, in seconds.
+const DENSITY_WINDOW: f32 = 2.0;
+/// Notes closer together than this (in seconds) are part of the same burst.
+const BURST_GAP: f32 = 0.13;
+
+const BASE_RATING: f32 = 0.5;
+const PEAK_DENSITY_WEIGHT: f32 = 0.45;
+const MEDIAN_DENSITY_WEIGHT: f32 = 0.25;
+const BURST_WEIGHT: f32 = 0.8;
+const ENTROPY_WEIGHT: f32 = 1.0;
+const ROLL_WEIGHT: f32 = 0.5;
+const BALLOON_WEIGHT: f32 = 0.5;
+
+/// Balloons that need this many hits per second (or more) count as fully loaded.
+const MAX_BALLOON_RATE: f32 = 20.0;
+
+pub const MIN_LEVEL: u8 = 1;
+pub const MAX_LEVEL: u8 = 10;
+
+/// Returns the value at the given percentile (from 0 to 1) of a sorted list.
+fn percentile(sorted: &[f32], p: f32) -> f32 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+
+    sorted[((sorted.len() - 1) as f32 * p).round() as usize]
+}
+
+/// The number of notes per second in the window starting at each note, sorted.
+fn note_densities(times: &[f32]) -> Vec<f32> {
+    let mut end = 0;
+    let mut densities: Vec<f32> = times
+        .iter()
+        .enumerate()
+        .map(|(start, &time)| {
+            while end < times.len() && times[end] < time + DENSITY_WINDOW {
+                end += 1;
+            }
+
+            (end - start) as f32 / DENSITY_WINDOW
+        })
+        .collect();
+
+    densities.sort_by(f32::total_cmp);
+    densities
+}
+
+/// The number of notes in the longest burst, or 0 if there are no bursts at all.
+fn longest_burst(times: &[f32]) -> usize {
+    let mut longest = 0;
+    let mut current = 1;
+
+    for pair in times.windows(2) {
+        if pair[1] - pair[0] <= BURST_GAP {
+            current += 1;
+            longest = longest.max(current);
+        } else {
+            current = 1;
+        }
+    }
+
+    longest
+}
+
+/// How mixed up the colours are, from 0 (always the same colour) to 1 (completely random).
+///
+/// This is the entropy of every run of three colours, so a chart of alternating colours is
+/// somewhere in between.
+fn colour_entropy(dons: &[bool]) -> f32 {
+    let mut counts = [0usize; 8];
+    for pattern in dons.windows(3) {
+        let index = pattern.iter().fold(0, |acc, &don| acc * 2 + don as usize);
+        counts[index] += 1;
+    }
+
+    let total = counts.iter().sum::<usize>() as f32;
+    if total == 0.0 {
+        return 0.0;
+    }
+
+    let entropy: f32 = counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f32 / total;
+            -p * p.log2()
+        })
+        .sum();
+
+    entropy / 3.0
+}
+
+/// Estimates the difficulty of a chart, on a scale from 1 to 10.
+pub fn estimate_difficulty(chart: &NoteChart) -> f32 {
+    let mut notes: Vec<_> = chart.notes.iter().collect();
+    notes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let hits: Vec<_> = notes
+        .iter()
+        .filter(|note| note.note_type.is_don() || note.note_type.is_kat())
+        .collect();
+    let times: Vec<f32> = hits.iter().map(|note| note.time).collect();
+    let dons: Vec<bool> = hits.iter().map(|note| note.note_type.is_don()).collect();
+
+    let densities = note_densities(&times);
+    let burst = longest_burst(&times) as f32;
+
+    let mut roll_time = 0.0;
+    let mut balloon_rate: f32 = 0.0;
+    for note in &notes {
+        match note.note_type {
+            NoteType::Roll(length) | NoteType::BigRoll(length) => roll_time += length,
+            NoteType::BalloonRoll(length, hits) | NoteType::Kusudama(length, hits) => {
+                balloon_rate = balloon_rate.max(hits as f32 / length.max(f32::EPSILON));
+            }
+            _ => {}
+        }
+    }
+
+    let song_length = match (notes.first(), notes.last()) {
+        (Some(first), Some(last)) => last.time - first.time,
+        _ => 0.0,
+    };
+    let roll_load = if song_length > 0.0 {
+        (roll_time / song_length).min(1.0)
+    } else {
+        0.0
+    };
+
+    let rating = BASE_RATING
+        + PEAK_DENSITY_WEIGHT * percentile(&densities, 0.9)
+        + MEDIAN_DENSITY_WEIGHT * percentile(&densities, 0.5)
+        + BURST_WEIGHT * (1.0 + burst / 4.0).ln()
+        + ENTROPY_WEIGHT * colour_entropy(&dons)
+        + ROLL_WEIGHT * roll_load
+        + BALLOON_WEIGHT * (balloon_rate / MAX_BALLOON_RATE).min(1.0);
+
+    rating.clamp(MIN_LEVEL as f32, MAX_LEVEL as f32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notechart_parser::parse_tja_file;
+    use crate::test_support::tja_fixture;
+
+    #[test]
+    fn test_empty_chart() {
+        assert_eq!(estimate_difficulty(&NoteChart::default()), 1.0);
+    }
+
+    #[test]
+    fn test_easy_fixture() {
+        // A don on every beat at 120bpm
+        let chart = tja_fixture(120, "", &"1111,\n".repeat(32)).chart;
+        let rating = estimate_difficulty(&chart);
+        assert!((1.0..=3.0).contains(&rating), "rating was {rating}");
+    }
+
+    #[test]
+    fn test_hard_fixture() {
+        // Long streams of mixed sixteenth notes at 180bpm
+        let track = "1212112121121122,\n2112212211212121,\n".repeat(16);
+        let chart = tja_fixture(180, "", &track).chart;
+        let rating = estimate_difficulty(&chart);
+        assert!((8.5..=10.0).contains(&rating), "rating was {rating}");
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let chart = tja_fixture(150, "", &"1020102210201122,\n5000000000000008,\n".repeat(8)).chart;
+        assert_eq!(estimate_difficulty(&chart), estimate_difficulty(&chart));
+    }
+
+    #[test]
+    fn test_official_chart() {
+        // The estimates should be in the right ballpark of the levels the chart gives
+        let song = parse_tja_file(include_str!("./Ready to.tja")).unwrap();
+        let mut last_rating = 0.0;
+
+        for difficulty in song.difficulties.iter().flatten() {
+            let stated = difficulty.star_level.unwrap().level as f32;
+            let rating = estimate_difficulty(&difficulty.chart);
+            assert!(
+                (rating - stated).abs() <= 2.0,
+                "rating was {rating}, chart says {stated}"
+            );
+
+            // Harder courses should never be estimated as easier
+            assert!(rating >= last_rating);
+            last_rating = rating;
+        }
+    }
+}
@@ -1,6 +1,16 @@
+mod barlines;
 mod chart;
+mod difficulty;
+pub mod osu;
+mod stats;
 mod test;
 mod tja_parser;
+mod tja_serialiser;
+mod tja_writer;
 
+pub use barlines::*;
 pub use chart::*;
+pub use stats::*;
 pub use tja_parser::*;
+pub use tja_serialiser::to_tja_string;
+pub use tja_writer::*;
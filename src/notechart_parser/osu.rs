@@ -0,0 +1,700 @@
+//! Reads osu!taiko beatmaps (`.osu` files) as songs.
+//!
+//! Each beatmap is a single difficulty, so a song is made from every beatmap in a set (see
+//! [combine_beatmaps]). The conversion follows what osu!taiko does when it plays them:
+//!
+//! - Circles are dons, or kats if they have a whistle or clap hitsound. A finish makes them big.
+//! - Sliders are drumrolls, which last as long as the slider would take to slide.
+//! - Spinners are balloons, which take more hits the longer they are and the higher the beatmap's
+//!   overall difficulty.
+//! - Timing points change the BPM (and time signature), inherited timing points change the scroll
+//!   speed, and kiai time is gogo time.
+//!
+//! Only beatmaps made for osu!taiko (`Mode: 1`) can be read.
+use std::collections::HashMap;
+
+use super::difficulty::estimate_difficulty;
+use super::tja_parser::push_timing_point;
+use super::{Barline, Difficulty, GogoTime, Note, NoteChart, NoteType, Song, DEFAULT_BPM};
+
+/// The game mode number of osu!taiko.
+const TAIKO_MODE: u8 = 1;
+
+/// How many times faster than the base slider velocity (SliderMultiplier) osu measures slider
+/// length in.
+const SLIDER_LENGTH_SCALE: f32 = 100.0;
+
+/// How many hits each second of a spinner takes (before the overall difficulty is taken into
+/// account), the same as osu!taiko.
+const SWELL_HIT_MULTIPLIER: f32 = 1.65;
+
+/// How much a timing point can speed up or slow down the scroll speed.
+const SCROLL_MULTIPLIER_RANGE: std::ops::RangeInclusive<f32> = 0.1..=10.0;
+
+/// Hitsound bits that make a circle a kat (whistle and clap).
+const KAT_HITSOUNDS: u32 = 2 | 8;
+/// The hitsound bit that makes a note big (finish).
+const BIG_HITSOUND: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OsuParseError {
+    /// The beatmap isn't for osu!taiko. This has the mode it's for.
+    WrongMode(u8),
+    /// A value that every beatmap needs wasn't given.
+    MissingValue(&'static str),
+    /// A timing point or hit object couldn't be read. Lines are counted from 1.
+    InvalidLine(usize),
+    /// The beatmap has no uninherited timing points, so its BPM isn't known.
+    NoTimingPoints,
+}
+
+impl std::fmt::Display for OsuParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OsuParseError::WrongMode(mode) => {
+                write!(f, "the beatmap is for game mode {mode}, not osu!taiko")
+            }
+            OsuParseError::MissingValue(key) => write!(f, "the beatmap doesn't have a {key}"),
+            OsuParseError::InvalidLine(line) => write!(f, "line {line} couldn't be read"),
+            OsuParseError::NoTimingPoints => {
+                write!(f, "the beatmap doesn't have any timing points")
+            }
+        }
+    }
+}
+
+impl std::error::Error for OsuParseError {}
+
+/// One osu!taiko beatmap, which is one difficulty of a song.
+#[derive(Debug, Clone)]
+pub struct Beatmap {
+    /// The song's metadata. This doesn't have any difficulties; the beatmap's is
+    /// [Beatmap::difficulty].
+    pub song: Song,
+    pub difficulty: Difficulty,
+    /// The name of the difficulty (`Version`), e.g. "Oni" or "Inner Oni".
+    pub version: String,
+    pub overall_difficulty: f32,
+}
+
+/// A line in the `[TimingPoints]` section.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TimingPoint {
+    /// The time in milliseconds.
+    time: f32,
+    /// How long a beat lasts in milliseconds for uninherited timing points. For inherited ones,
+    /// this is negative, and 100 divided by it is the (negative) scroll speed multiplier.
+    beat_length: f32,
+    /// How many beats there are in a measure.
+    meter: u32,
+    /// Whether this starts a new BPM, rather than only changing the scroll speed.
+    uninherited: bool,
+    kiai: bool,
+    /// Whether the barline at the start of this timing point is left out.
+    omit_first_barline: bool,
+}
+
+/// A line in the `[HitObjects]` section.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HitObject {
+    Circle {
+        time: f32,
+        hitsound: u32,
+    },
+    Slider {
+        time: f32,
+        hitsound: u32,
+        slides: u32,
+        /// How long the slider is, in osu pixels.
+        length: f32,
+    },
+    Spinner {
+        time: f32,
+        end_time: f32,
+    },
+}
+
+/// Scales a beatmap difficulty value (like overall difficulty) to a range, the way osu does.
+fn difficulty_range(difficulty: f32, min: f32, mid: f32, max: f32) -> f32 {
+    if difficulty > 5.0 {
+        mid + (max - mid) * (difficulty - 5.0) / 5.0
+    } else {
+        mid - (mid - min) * (5.0 - difficulty) / 5.0
+    }
+}
+
+fn timing_point(line: &str) -> Option<TimingPoint> {
+    let values: Vec<&str> = line.split(',').map(str::trim).collect();
+    // Older beatmaps leave off the values at the end
+    let value = |i: usize| values.get(i).copied();
+    let effects = value(7).map_or(Ok(0), str::parse::<u32>).ok()?;
+
+    Some(TimingPoint {
+        time: value(0)?.parse().ok()?,
+        beat_length: value(1)?.parse().ok()?,
+        meter: value(2).map_or(Ok(4), str::parse).ok()?,
+        uninherited: value(6).map_or(Ok(1), str::parse::<u8>).ok()? == 1,
+        kiai: effects & 1 != 0,
+        omit_first_barline: effects & 8 != 0,
+    })
+}
+
+/// Reads a hit object. Returns `Some(None)` for hit objects osu!taiko doesn't have (like
+/// osu!mania's hold notes), and None if the line can't be read.
+fn hit_object(line: &str) -> Option<Option<HitObject>> {
+    let values: Vec<&str> = line.split(',').map(str::trim).collect();
+    let value = |i: usize| values.get(i).copied();
+    let time = value(2)?.parse().ok()?;
+    let object_type: u32 = value(3)?.parse().ok()?;
+    let hitsound = value(4)?.parse().ok()?;
+
+    let object = if object_type & 1 != 0 {
+        HitObject::Circle { time, hitsound }
+    } else if object_type & 2 != 0 {
+        HitObject::Slider {
+            time,
+            hitsound,
+            slides: value(6)?.parse().ok()?,
+            length: value(7)?.parse().ok()?,
+        }
+    } else if object_type & 8 != 0 {
+        HitObject::Spinner {
+            time,
+            end_time: value(5)?.parse().ok()?,
+        }
+    } else {
+        return Some(None);
+    };
+
+    Some(Some(object))
+}
+
+/// The sections of a beatmap that are read.
+#[derive(Default)]
+struct Sections<'a> {
+    /// The `key: value` pairs of the `[General]`, `[Metadata]` and `[Difficulty]` sections. None
+    /// of their keys are the same.
+    values: HashMap<&'a str, &'a str>,
+    timing_points: Vec<TimingPoint>,
+    hit_objects: Vec<HitObject>,
+}
+
+fn read_sections(input: &str) -> Result<Sections<'_>, OsuParseError> {
+    let mut sections = Sections::default();
+    let mut section = "";
+
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name;
+            continue;
+        }
+
+        match section {
+            "General" | "Metadata" | "Difficulty" => {
+                if let Some((key, value)) = line.split_once(':') {
+                    sections.values.insert(key.trim(), value.trim());
+                }
+            }
+            "TimingPoints" => sections
+                .timing_points
+                .push(timing_point(line).ok_or(OsuParseError::InvalidLine(i + 1))?),
+            "HitObjects" => sections
+                .hit_objects
+                .extend(hit_object(line).ok_or(OsuParseError::InvalidLine(i + 1))?),
+            _ => {}
+        }
+    }
+
+    Ok(sections)
+}
+
+/// The timing a beatmap has at some point in the song.
+#[derive(Debug, Clone, Copy)]
+struct Timing {
+    /// How long a beat lasts, in milliseconds.
+    beat_length: f32,
+    /// The scroll speed multiplier of the last inherited timing point.
+    scroll_multiplier: f32,
+    kiai: bool,
+}
+
+impl Timing {
+    fn scroll_speed(&self) -> f32 {
+        60_000.0 / self.beat_length / DEFAULT_BPM * self.scroll_multiplier
+    }
+}
+
+/// Works out the timing at every timing point, in order. Times before the first timing point use
+/// its timing.
+fn resolve_timing(points: &[TimingPoint]) -> Result<Vec<(f32, Timing)>, OsuParseError> {
+    let first = points
+        .iter()
+        .find(|point| point.uninherited && point.beat_length > 0.0)
+        .ok_or(OsuParseError::NoTimingPoints)?;
+    let mut timing = Timing {
+        beat_length: first.beat_length,
+        scroll_multiplier: 1.0,
+        kiai: first.kiai,
+    };
+
+    let mut resolved = Vec::with_capacity(points.len());
+    for point in points {
+        if point.uninherited {
+            // Timing points with no length can't be used, but their kiai still can
+            if point.beat_length > 0.0 {
+                timing.beat_length = point.beat_length;
+            }
+            timing.scroll_multiplier = 1.0;
+        } else if point.beat_length < 0.0 {
+            let multiplier = -100.0 / point.beat_length;
+            timing.scroll_multiplier = multiplier.clamp(
+                *SCROLL_MULTIPLIER_RANGE.start(),
+                *SCROLL_MULTIPLIER_RANGE.end(),
+            );
+        }
+        timing.kiai = point.kiai;
+
+        resolved.push((point.time, timing));
+    }
+
+    Ok(resolved)
+}
+
+/// The timing in effect at the given time (in milliseconds).
+fn timing_at(timing: &[(f32, Timing)], time: f32) -> Timing {
+    let i = timing.partition_point(|&(start, _)| start <= time);
+    timing[i.saturating_sub(1)].1
+}
+
+/// Builds the chart for a beatmap. Times in osu are in milliseconds, and are turned into seconds.
+fn chart(sections: &Sections, overall_difficulty: f32) -> Result<NoteChart, OsuParseError> {
+    let mut points = sections.timing_points.clone();
+    // Timing points at the same time stay in the order they're written in
+    points.sort_by(|a, b| a.time.total_cmp(&b.time));
+    let timing = resolve_timing(&points)?;
+
+    let slider_multiplier = sections
+        .values
+        .get("SliderMultiplier")
+        .and_then(|value| value.parse::<f32>().ok())
+        .unwrap_or(1.4);
+    let swell_hit_rate = difficulty_range(overall_difficulty, 3.0, 5.0, 7.5) * SWELL_HIT_MULTIPLIER;
+
+    let mut chart = NoteChart::default();
+
+    for object in &sections.hit_objects {
+        let (time, hitsound) = match *object {
+            HitObject::Circle { time, hitsound } | HitObject::Slider { time, hitsound, .. } => {
+                (time, hitsound)
+            }
+            HitObject::Spinner { time, .. } => (time, 0),
+        };
+        let at = timing_at(&timing, time);
+        let big = hitsound & BIG_HITSOUND != 0;
+
+        let note_type = match *object {
+            HitObject::Circle { .. } => match (hitsound & KAT_HITSOUNDS != 0, big) {
+                (false, false) => NoteType::Don,
+                (true, false) => NoteType::Kat,
+                (false, true) => NoteType::BigDon,
+                (true, true) => NoteType::BigKat,
+            },
+            HitObject::Slider { slides, length, .. } => {
+                let velocity = slider_multiplier * SLIDER_LENGTH_SCALE * at.scroll_multiplier;
+                let duration = length / velocity * at.beat_length * slides as f32 / 1000.0;
+                if big {
+                    NoteType::BigRoll(duration)
+                } else {
+                    NoteType::Roll(duration)
+                }
+            }
+            HitObject::Spinner { time, end_time } => {
+                let duration = (end_time - time).max(0.0) / 1000.0;
+                let hits = (duration * swell_hit_rate).max(1.0) as u32;
+                NoteType::BalloonRoll(duration, hits)
+            }
+        };
+
+        chart.notes.push(Note {
+            note_type,
+            time: time / 1000.0,
+            scroll_speed: at.scroll_speed(),
+            gogo: at.kiai,
+        });
+    }
+    chart.notes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    chart.end_time = chart
+        .notes
+        .iter()
+        .map(|note| match note.note_type {
+            NoteType::Roll(length)
+            | NoteType::BigRoll(length)
+            | NoteType::BalloonRoll(length, _)
+            | NoteType::Kusudama(length, _) => note.time + length,
+            _ => note.time,
+        })
+        .fold(0.0, f32::max);
+
+    for (i, &(time, point_timing)) in timing.iter().enumerate() {
+        let point = &points[i];
+        let seconds_per_measure = point_timing.beat_length * point.meter.max(1) as f32 / 1000.0;
+        push_timing_point(
+            &mut chart.timing,
+            time / 1000.0,
+            seconds_per_measure,
+            point_timing.scroll_speed(),
+            60_000.0 / point_timing.beat_length,
+        );
+
+        // Kiai time is gogo time
+        let in_gogo = chart
+            .gogo_times
+            .last()
+            .is_some_and(|gogo| gogo.end.is_nan());
+        if point_timing.kiai && !in_gogo {
+            chart.gogo_times.push(GogoTime {
+                start: time / 1000.0,
+                end: f32::NAN,
+            });
+        } else if !point_timing.kiai && in_gogo {
+            chart.gogo_times.last_mut().unwrap().end = time / 1000.0;
+        }
+
+        // Each uninherited timing point starts a new measure, and measures go on until the next
+        if !point.uninherited || point.beat_length <= 0.0 {
+            continue;
+        }
+
+        // A measure cut short by the next timing point doesn't get a barline at its end, but the
+        // last measure of the song does
+        let last_barline = points[i + 1..]
+            .iter()
+            .find(|next| next.uninherited && next.beat_length > 0.0)
+            .map_or(chart.end_time * 1000.0 + 1.0, |next| next.time - 1.0);
+        let measure_length = seconds_per_measure * 1000.0;
+        let first_measure = if point.omit_first_barline { 1 } else { 0 };
+
+        for measure in first_measure.. {
+            let barline_time = time + measure as f32 * measure_length;
+            if barline_time > last_barline {
+                break;
+            }
+
+            chart.barlines.push(Barline {
+                time: barline_time / 1000.0,
+                scroll_speed: timing_at(&timing, barline_time).scroll_speed(),
+            });
+        }
+    }
+
+    // Kiai time that's never ended lasts until the end of the song
+    if let Some(gogo) = chart.gogo_times.last_mut().filter(|gogo| gogo.end.is_nan()) {
+        gogo.end = chart.end_time.max(gogo.start);
+    }
+
+    Ok(chart)
+}
+
+/// Reads an osu beatmap. Only beatmaps for osu!taiko can be read.
+pub fn parse_osu_file(input: &str) -> Result<Beatmap, OsuParseError> {
+    let sections = read_sections(input)?;
+    let value = |key| sections.values.get(key).copied().filter(|v| !v.is_empty());
+
+    let mode = value("Mode")
+        .and_then(|mode| mode.parse().ok())
+        .unwrap_or(0);
+    if mode != TAIKO_MODE {
+        return Err(OsuParseError::WrongMode(mode));
+    }
+
+    let overall_difficulty = value("OverallDifficulty")
+        .and_then(|od| od.parse().ok())
+        .unwrap_or(5.0);
+    let chart = chart(&sections, overall_difficulty)?;
+
+    // The unicode title is the original one, if the beatmap has it
+    let title = value("Title").ok_or(OsuParseError::MissingValue("Title"))?;
+    let (title, title_en) = match value("TitleUnicode") {
+        Some(unicode) if unicode != title => (unicode, Some(title.to_string())),
+        _ => (title, None),
+    };
+
+    let bpm = sections
+        .timing_points
+        .iter()
+        .find(|point| point.uninherited && point.beat_length > 0.0)
+        .map_or(DEFAULT_BPM, |point| 60_000.0 / point.beat_length);
+    // PreviewTime is -1 when there isn't one
+    let demostart = value("PreviewTime")
+        .and_then(|time| time.parse::<f32>().ok())
+        .map_or(0.0, |time| time.max(0.0) / 1000.0);
+
+    let song = Song {
+        title: title.to_string(),
+        title_en,
+        subtitle: value("ArtistUnicode")
+            .or(value("Artist"))
+            .map(str::to_string),
+        maker: value("Creator").map(str::to_string),
+        audio_filename: value("AudioFilename")
+            .ok_or(OsuParseError::MissingValue("AudioFilename"))?
+            .to_string(),
+        bpm,
+        demostart,
+        ..Default::default()
+    };
+
+    let difficulty = Difficulty {
+        star_level: None,
+        estimated_level: Some(estimate_difficulty(&chart).round() as u8),
+        chart,
+        player_charts: [None, None],
+        audio_filename: None,
+        demostart: None,
+        score_init: None,
+        score_diff: None,
+        notes_designer: None,
+    };
+
+    Ok(Beatmap {
+        song,
+        difficulty,
+        version: value("Version").unwrap_or_default().to_string(),
+        overall_difficulty,
+    })
+}
+
+/// The difficulty a beatmap is for, going by its name. osu!taiko beatmaps are usually named after
+/// the difficulties in taiko (or the ones in osu).
+fn named_course(version: &str) -> Option<usize> {
+    let version = version.to_lowercase();
+    let words: Vec<&str> = version.split(|c: char| !c.is_alphanumeric()).collect();
+    let has = |names: &[&str]| names.iter().any(|name| words.contains(name));
+
+    if has(&["ura", "extra"]) || has(&["inner"]) && has(&["oni"]) {
+        Some(4)
+    } else if has(&["oni", "insane"]) {
+        Some(3)
+    } else if has(&["muzukashii", "hard"]) {
+        Some(2)
+    } else if has(&["futsuu", "normal"]) {
+        Some(1)
+    } else if has(&["kantan", "easy"]) {
+        Some(0)
+    } else {
+        None
+    }
+}
+
+/// Puts the beatmaps of a set together into one song, with a difficulty for each beatmap. The
+/// song's metadata is taken from the first beatmap, apart from who made it, which is whoever made
+/// the most of the beatmaps. Returns None if there aren't any beatmaps.
+///
+/// Beatmaps named after a difficulty (see [named_course]) go in that difficulty. The rest fill the
+/// difficulties that are left from easiest to hardest, in order of their overall difficulty.
+/// Beatmaps that don't fit are left out.
+pub fn combine_beatmaps(mut beatmaps: Vec<Beatmap>) -> Option<Song> {
+    let mut song = beatmaps.first()?.song.clone();
+    let makers: Vec<&String> = beatmaps
+        .iter()
+        .filter_map(|b| b.song.maker.as_ref())
+        .collect();
+    song.maker = makers
+        .iter()
+        .max_by_key(|&&maker| {
+            // Ties go to the first one
+            let count = makers.iter().filter(|&&other| other == maker).count();
+            let first = makers.iter().position(|&other| other == maker);
+            (count, std::cmp::Reverse(first))
+        })
+        .map(|maker| maker.to_string());
+
+    beatmaps.sort_by(|a, b| a.overall_difficulty.total_cmp(&b.overall_difficulty));
+    let mut unnamed = Vec::new();
+
+    for beatmap in beatmaps {
+        match named_course(&beatmap.version) {
+            Some(course) if song.difficulties[course].is_none() => {
+                song.difficulties[course] = Some(course_from(&song, beatmap));
+            }
+            _ => unnamed.push(beatmap),
+        }
+    }
+
+    for beatmap in unnamed {
+        match song.difficulties.iter().position(Option::is_none) {
+            Some(course) => song.difficulties[course] = Some(course_from(&song, beatmap)),
+            None => log::warn!(
+                "the beatmap \"{}\" of {} was left out, since there are no difficulties left for it",
+                beatmap.version,
+                song.title
+            ),
+        }
+    }
+
+    Some(song)
+}
+
+/// Makes a beatmap into a difficulty of the given song, keeping anything it has that's different
+/// to the song's.
+fn course_from(song: &Song, beatmap: Beatmap) -> Difficulty {
+    let Beatmap {
+        song: beatmap_song,
+        mut difficulty,
+        ..
+    } = beatmap;
+
+    difficulty.audio_filename =
+        Some(beatmap_song.audio_filename).filter(|audio| *audio != song.audio_filename);
+    difficulty.demostart = Some(beatmap_song.demostart).filter(|&start| start != song.demostart);
+    // Guest difficulties are credited to whoever made them
+    difficulty.notes_designer = beatmap_song
+        .maker
+        .filter(|maker| Some(maker) != song.maker.as_ref());
+
+    difficulty
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn beatmap(name: &str) -> Beatmap {
+        let path = format!(
+            "{}/src/notechart_parser/Osu fixture/{name}",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        parse_osu_file(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn test_circles() {
+        let beatmap = beatmap("Fixture (Mapper) [Oni].osu");
+        let song = &beatmap.song;
+        assert_eq!(song.title, "フィクスチャ");
+        assert_eq!(song.title_en.as_deref(), Some("Fixture"));
+        assert_eq!(song.subtitle.as_deref(), Some("Artist"));
+        assert_eq!(song.maker.as_deref(), Some("Mapper"));
+        assert_eq!(song.audio_filename, "audio.mp3");
+        assert_eq!(song.bpm, 120.0);
+        assert_eq!(song.demostart, 1.5);
+        assert_eq!(beatmap.version, "Oni");
+
+        // At 120bpm, the circles are a beat apart
+        let notes = &beatmap.difficulty.chart.notes;
+        let types: Vec<_> = notes[..5].iter().map(|note| note.note_type).collect();
+        assert_eq!(
+            types,
+            [
+                NoteType::Don,
+                NoteType::Kat,
+                NoteType::Kat,
+                NoteType::BigDon,
+                NoteType::BigKat
+            ]
+        );
+        for (i, note) in notes[..5].iter().enumerate() {
+            assert!(close(note.time, 1.0 + i as f32 * 0.5), "{note:?}");
+            assert!(close(note.scroll_speed, 1.0));
+        }
+    }
+
+    #[test]
+    fn test_sliders_and_spinners() {
+        let chart = beatmap("Fixture (Mapper) [Oni].osu").difficulty.chart;
+
+        // 140 pixels at a slider multiplier of 1.4 is one beat long, and the second slider slides
+        // back again
+        assert_eq!(chart.notes[5].note_type, NoteType::Roll(0.5));
+        assert!(close(chart.notes[5].time, 4.0));
+        assert_eq!(chart.notes[6].note_type, NoteType::BigRoll(1.0));
+
+        // Two seconds of spinner at overall difficulty 5 takes 2 * 5 * 1.65 hits
+        assert_eq!(chart.notes[7].note_type, NoteType::BalloonRoll(2.0, 16));
+        assert!(close(chart.notes[7].time, 6.0));
+    }
+
+    #[test]
+    fn test_timing_points() {
+        let chart = beatmap("Fixture (Mapper) [Oni].osu").difficulty.chart;
+
+        // The inherited timing point doubles the scroll speed, and is in kiai time
+        let fast = &chart.notes[8];
+        assert!(close(fast.time, 9.0));
+        assert!(close(fast.scroll_speed, 2.0));
+        assert!(fast.gogo);
+        assert!(!chart.notes[7].gogo);
+        assert_eq!(chart.gogo_times.len(), 1);
+        assert!(close(chart.gogo_times[0].start, 8.5));
+        assert!(close(chart.gogo_times[0].end, 10.0));
+
+        // The BPM doubles, in 3/4 time
+        let last = chart.notes.last().unwrap();
+        assert!(close(last.time, 10.25));
+        assert!(close(last.scroll_speed, 2.0));
+        let timing = chart.timing.last().unwrap();
+        assert!(close(timing.time, 10.0));
+        assert!(close(timing.seconds_per_measure, 0.75));
+
+        // Barlines start again at the BPM change
+        let barlines: Vec<f32> = chart.barlines.iter().map(|barline| barline.time).collect();
+        let expected = [0.5, 2.5, 4.5, 6.5, 8.5, 10.0];
+        assert_eq!(barlines.len(), expected.len(), "{barlines:?}");
+        assert!(barlines.iter().zip(expected).all(|(&a, b)| close(a, b)));
+    }
+
+    #[test]
+    fn test_wrong_mode() {
+        let osu = "osu file format v14\n\n[General]\nAudioFilename: audio.mp3\nMode: 0\n";
+        assert_eq!(
+            parse_osu_file(osu).unwrap_err(),
+            OsuParseError::WrongMode(0)
+        );
+
+        let no_timing = "[General]\nAudioFilename: a.mp3\nMode: 1\n[Metadata]\nTitle:T\n";
+        assert_eq!(
+            parse_osu_file(no_timing).unwrap_err(),
+            OsuParseError::NoTimingPoints
+        );
+
+        let broken = format!("{no_timing}[TimingPoints]\n0,500,4\n[HitObjects]\nnot a note\n");
+        assert_eq!(
+            parse_osu_file(&broken).unwrap_err(),
+            OsuParseError::InvalidLine(9)
+        );
+    }
+
+    #[test]
+    fn test_combine_beatmaps() {
+        let oni = beatmap("Fixture (Mapper) [Oni].osu");
+        let kantan = beatmap("Fixture (Mapper) [Guest's Kantan].osu");
+        let unnamed = beatmap("Fixture (Mapper) [Something].osu");
+
+        let song = combine_beatmaps(vec![oni, kantan, unnamed]).unwrap();
+        // The first beatmap's metadata is used, but most of the beatmaps are by the set's mapper
+        assert_eq!(song.title, "フィクスチャ");
+        assert_eq!(song.maker.as_deref(), Some("Mapper"));
+
+        let names: Vec<bool> = song.difficulties.iter().map(Option::is_some).collect();
+        assert_eq!(names, [true, true, false, true, false]);
+        let kantan = song.difficulties[0].as_ref().unwrap();
+        assert_eq!(kantan.notes_designer.as_deref(), Some("Guest"));
+        assert_eq!(kantan.chart.notes.len(), 2);
+        // The beatmap that isn't named after a difficulty takes the easiest one left
+        let something = song.difficulties[1].as_ref().unwrap();
+        assert_eq!(something.chart.notes.len(), 1);
+        assert_eq!(something.notes_designer, None);
+
+        assert!(combine_beatmaps(Vec::new()).is_none());
+    }
+}
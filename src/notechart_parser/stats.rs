@@ -0,0 +1,127 @@
+//! Counts what's in a chart, for showing alongside it in the song list.
+use super::{Difficulty, NoteChart, NoteType};
+
+/// The length of the window that the peak note density is measured over, in seconds.
+const PEAK_DENSITY_WINDOW: f32 = 1.0;
+
+/// Statistics about a chart's notes. Barlines don't count for anything, and big notes count the
+/// same as small ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChartStats {
+    /// How many dons and kats there are. Drumrolls and balloons aren't counted.
+    pub hit_notes: usize,
+    pub dons: usize,
+    pub kats: usize,
+    /// How long all the drumrolls last together, in seconds. Balloons aren't counted.
+    pub roll_time: f32,
+    /// How many balloons (and kusudama) there are.
+    pub balloons: usize,
+    /// How long it is from the first note to the end of the last one, in seconds.
+    pub duration: f32,
+    /// The most dons and kats there are in any one second of the chart.
+    pub peak_density: f32,
+}
+
+impl ChartStats {
+    pub fn new(chart: &NoteChart) -> Self {
+        let mut stats = Self::default();
+        let mut hit_times = Vec::new();
+        let mut start = f32::INFINITY;
+        let mut end = f32::NEG_INFINITY;
+
+        for note in &chart.notes {
+            let length = match note.note_type {
+                NoteType::Roll(length) | NoteType::BigRoll(length) => {
+                    stats.roll_time += length;
+                    length
+                }
+                NoteType::BalloonRoll(length, _) | NoteType::Kusudama(length, _) => {
+                    stats.balloons += 1;
+                    length
+                }
+                note_type => {
+                    if note_type.is_don() {
+                        stats.dons += 1;
+                    } else {
+                        stats.kats += 1;
+                    }
+                    hit_times.push(note.time);
+                    0.0
+                }
+            };
+
+            start = start.min(note.time);
+            end = end.max(note.time + length);
+        }
+
+        stats.hit_notes = hit_times.len();
+        if !chart.notes.is_empty() {
+            stats.duration = end - start;
+        }
+
+        // With the notes in order, the window can slide along them
+        hit_times.sort_by(f32::total_cmp);
+        let mut window_start = 0;
+        let mut peak = 0;
+        for (i, &time) in hit_times.iter().enumerate() {
+            while time - hit_times[window_start] >= PEAK_DENSITY_WINDOW {
+                window_start += 1;
+            }
+            peak = peak.max(i + 1 - window_start);
+        }
+        stats.peak_density = peak as f32 / PEAK_DENSITY_WINDOW;
+
+        stats
+    }
+}
+
+impl Difficulty {
+    /// Statistics about the difficulty's chart. These are all zero if its notes haven't been read
+    /// (see [parse_tja_metadata](super::parse_tja_metadata)).
+    pub fn stats(&self) -> ChartStats {
+        ChartStats::new(&self.chart)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::tja_fixture;
+
+    fn stats(bpm: u32, track: &str) -> ChartStats {
+        tja_fixture(bpm, "BALLOON:\n", track).stats()
+    }
+
+    #[test]
+    fn test_empty_chart() {
+        assert_eq!(
+            ChartStats::new(&NoteChart::default()),
+            ChartStats::default()
+        );
+    }
+
+    #[test]
+    fn test_counts() {
+        // At 120bpm each measure lasts 2 seconds
+        let stats = stats(120, "1234,\n5008,\n7008,\n12,");
+
+        // Big notes are single notes, and rolls and balloons aren't hit notes
+        assert_eq!(stats.hit_notes, 6);
+        assert_eq!(stats.dons, 3);
+        assert_eq!(stats.kats, 3);
+        assert_eq!(stats.roll_time, 1.5);
+        assert_eq!(stats.balloons, 1);
+        // From the first don to the last kat
+        assert_eq!(stats.duration, 7.0);
+    }
+
+    #[test]
+    fn test_peak_density() {
+        // Sixteenth notes at 140bpm are a little over 9 a second, so up to 10 fit in one second
+        let busiest = stats(140, "1000,\n1111111111111111,\n,\n1,");
+        assert_eq!(busiest.peak_density, 10.0);
+
+        // A chart with one note still has a density
+        assert_eq!(stats(120, "1,").peak_density, 1.0);
+    }
+}
@@ -45,3 +45,1611 @@ fn test_real_tja_file_succeeds() {
     println!("{:?}", res);
     assert!(res.is_ok());
 }
+
+#[test]
+fn test_course_audio_override() {
+    let track = "TITLE:Override
+WAVE:song.ogg
+DEMOSTART:10
+
+COURSE:Oni
+LEVEL:8
+WAVE:oni.ogg
+DEMOSTART:20.5
+
+#START
+1,
+#END
+
+COURSE:Easy
+LEVEL:2
+
+#START
+1,
+#END
+";
+
+    let song = parse_tja_file(track).unwrap();
+    assert_eq!(song.audio_filename, "song.ogg");
+    assert_eq!(song.demostart, 10.0);
+
+    let oni = song.difficulties[3].as_ref().unwrap();
+    assert_eq!(oni.audio_filename.as_deref(), Some("oni.ogg"));
+    assert_eq!(oni.demostart, Some(20.5));
+    assert_eq!(song.course_audio_filename(3), "oni.ogg");
+
+    let easy = song.difficulties[0].as_ref().unwrap();
+    assert_eq!(easy.audio_filename, None);
+    assert_eq!(easy.demostart, None);
+    assert_eq!(song.course_audio_filename(0), "song.ogg");
+    assert_eq!(song.course_demostart(0), 10.0);
+}
+
+#[test]
+fn test_subtitle() {
+    let subtitle = |line: &str| {
+        let song = parse_tja_file(&format!(
+            "TITLE:Subtitle\n{line}\nWAVE:song.ogg\n\n#START\n1,\n#END\n"
+        ))
+        .unwrap();
+        (song.subtitle, song.show_subtitle)
+    };
+    let shown = |subtitle: &str| (Some(subtitle.to_string()), true);
+
+    assert_eq!(
+        subtitle("SUBTITLE:--STEINS;GATE"),
+        (Some("STEINS;GATE".to_string()), false)
+    );
+    assert_eq!(subtitle("SUBTITLE:++Someone"), shown("Someone"));
+    assert_eq!(subtitle("SUBTITLE:Someone"), shown("Someone"));
+    // Only the first colon ends the key
+    assert_eq!(subtitle("SUBTITLE:++Re:Zero"), shown("Re:Zero"));
+    assert_eq!(subtitle("SUBTITLE:"), (None, true));
+    assert_eq!(subtitle("SUBTITLE:--"), (None, false));
+    assert_eq!(subtitle(""), (None, true));
+}
+
+#[test]
+fn test_offset() {
+    let chart = |offset: &str| {
+        let song = parse_tja_file(&format!(
+            "TITLE:Offset\nWAVE:song.ogg\nBPM:120\nOFFSET:{offset}\n\n#START\n1010,\n#GOGOSTART\n2,\n#END\n"
+        ))
+        .unwrap();
+        song.difficulties[3].clone().unwrap().chart
+    };
+    let times = |chart: &NoteChart| {
+        let notes = chart.notes.iter().map(|note| note.time);
+        let barlines = chart.barlines.iter().map(|barline| barline.time);
+        notes.chain(barlines).collect::<Vec<_>>()
+    };
+
+    let plain = chart("0");
+    assert_eq!(times(&plain), [0.0, 1.0, 2.0, 0.0, 2.0, 4.0]);
+
+    // A negative offset means the notes start later in the song, and a positive one earlier
+    for offset in [-1.5, 0.25] {
+        let shifted = chart(&offset.to_string());
+        let expected: Vec<f32> = times(&plain).iter().map(|time| time - offset).collect();
+        assert_eq!(times(&shifted), expected);
+        assert_eq!(shifted.gogo_times[0].start, 2.0 - offset);
+        assert_eq!(shifted.end_time, plain.end_time - offset);
+    }
+}
+
+#[test]
+fn test_score_metadata() {
+    let track = "TITLE:Scores
+WAVE:song.ogg
+
+COURSE:Oni
+LEVEL:8
+SCOREINIT: 1000
+SCOREDIFF:250
+
+#START
+1,
+#END
+
+COURSE:Hard
+LEVEL:6
+SCOREINIT:
+SCOREDIFF:120,0
+
+#START
+1,
+#END
+
+COURSE:Normal
+LEVEL:4
+
+#START
+1,
+#END
+";
+
+    let song = parse_tja_file(track).unwrap();
+    let scores = |difficulty: usize| {
+        let difficulty = song.difficulties[difficulty].as_ref().unwrap();
+        (difficulty.score_init, difficulty.score_diff)
+    };
+
+    assert_eq!(scores(3), (Some(1000), Some(250)));
+    // Empty values are missing, and only the first of a list counts
+    assert_eq!(scores(2), (None, Some(120)));
+    // Scores don't carry over to the next course
+    assert_eq!(scores(1), (None, None));
+
+    let bad = track.replace("SCOREDIFF:250", "SCOREDIFF:lots");
+    assert_eq!(
+        parse_tja_file(&bad).unwrap_err(),
+        TJAParseError {
+            kind: TJAParseErrorKind::InvalidMetadata,
+            line: 6
+        }
+    );
+}
+
+#[test]
+fn test_volumes() {
+    let volumes = |metadata: &str| {
+        let song = parse_tja_file(&format!(
+            "TITLE:Volume\nWAVE:song.ogg\n{metadata}\n\n#START\n1,\n#END\n"
+        ))
+        .unwrap();
+        (song.song_volume, song.se_volume)
+    };
+
+    assert_eq!(volumes(""), (100.0, 100.0));
+    assert_eq!(volumes("SONGVOL:80\nSEVOL:120"), (80.0, 120.0));
+    assert_eq!(volumes("SONGVOL:0"), (0.0, 100.0));
+    // Volumes only go up to double
+    assert_eq!(volumes("SONGVOL:500\nSEVOL:-10"), (200.0, 0.0));
+}
+
+#[test]
+fn test_charters() {
+    let track = "TITLE:Charters
+WAVE:song.ogg
+MAKER:Someone
+NOTESDESIGNER3:Someone else
+NOTESDESIGNER1:
+
+COURSE:Oni
+LEVEL:8
+
+#START
+1,
+#END
+
+COURSE:Normal
+LEVEL:4
+NOTESDESIGNER0:Nobody
+
+#START
+1,
+#END
+";
+
+    // The designer for easy doesn't match a course, but that isn't an error
+    let song = parse_tja_file(track).unwrap();
+    let designer = |i: usize| {
+        song.difficulties[i]
+            .as_ref()
+            .unwrap()
+            .notes_designer
+            .as_deref()
+    };
+
+    assert_eq!(song.maker.as_deref(), Some("Someone"));
+    assert_eq!(designer(3), Some("Someone else"));
+    assert_eq!(designer(1), None);
+}
+
+#[test]
+fn test_course_names() {
+    let course = |name: &str| {
+        let song = parse_tja_file(&format!(
+            "TITLE:Courses\nWAVE:song.ogg\nCOURSE:{name}\n\n#START\n1,\n#END\n"
+        ))?;
+        Ok(song.difficulties.iter().position(Option::is_some).unwrap())
+    };
+
+    assert_eq!(course("4"), Ok(4));
+    assert_eq!(course("0"), Ok(0));
+    assert_eq!(course("Edit"), Ok(4));
+    assert_eq!(course("ura"), Ok(4));
+    assert_eq!(course("NORMAL"), Ok(1));
+    assert_eq!(course("むずかしい"), Ok(2));
+    assert_eq!(course("おに"), Ok(3));
+    assert_eq!(course("裏"), Ok(4));
+
+    assert_eq!(
+        course("Extreme"),
+        Err(TJAParseError {
+            kind: TJAParseErrorKind::UnknownCourse("Extreme".to_string()),
+            line: 2
+        })
+    );
+}
+
+#[test]
+fn test_course_metadata_scope() {
+    let song = parse_tja_file(include_str!("./Three courses.tja")).unwrap();
+
+    // Song metadata applies to every course
+    assert_eq!(song.title, "Three Courses");
+    assert_eq!(song.bpm, 150.0);
+    assert_eq!(song.offset, -0.5);
+    assert_eq!(song.audio_filename, "three courses.ogg");
+    assert_eq!(
+        song.difficulties.each_ref().map(|d| d.is_some()),
+        [true, false, true, true, false]
+    );
+
+    let course = |i: usize| song.difficulties[i].as_ref().unwrap();
+    let balloons = |i: usize| {
+        course(i)
+            .chart
+            .notes
+            .iter()
+            .filter_map(|note| match note.note_type {
+                NoteType::BalloonRoll(_, hits) | NoteType::Kusudama(_, hits) => Some(hits),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let oni = course(3);
+    assert_eq!(oni.star_level, Some(StarLevel::new(8)));
+    assert_eq!((oni.score_init, oni.score_diff), (Some(1000), Some(300)));
+    assert_eq!(balloons(3), [12, 20]);
+    // Notes are 0.4s apart at 150bpm, and start half a second in
+    assert_eq!(oni.chart.notes[0].time, 0.5);
+
+    // Easy doesn't give a level, so it's estimated rather than taken from Oni. It falls back on
+    // the SCOREINIT given before the first course
+    let easy = course(0);
+    assert_eq!(easy.star_level, None);
+    assert_eq!((easy.score_init, easy.score_diff), (Some(500), Some(120)));
+    assert_eq!(balloons(0), []);
+
+    let hard = course(2);
+    assert_eq!(hard.star_level, Some(StarLevel::new(6)));
+    assert_eq!((hard.score_init, hard.score_diff), (Some(500), None));
+    assert_eq!(balloons(2), [7]);
+}
+
+#[test]
+fn test_missing_level_is_estimated() {
+    let track = "TITLE:No level
+WAVE:song.ogg
+COURSE:Easy
+
+#START
+1111,
+#END
+";
+
+    let song = parse_tja_file(track).unwrap();
+    let easy = song.difficulties[0].as_ref().unwrap();
+    assert_eq!(easy.star_level, None);
+    assert_eq!(
+        easy.level(false),
+        Some((StarLevel::new(easy.estimated_level.unwrap()), true))
+    );
+}
+
+#[test]
+fn test_section_labels() {
+    // At 120bpm each measure is two seconds long
+    let track = "TITLE:Sections
+WAVE:sections.ogg
+BPM:120
+OFFSET:-1
+COURSE:Oni
+LEVEL:5
+
+#START
+// intro
+1111,
+1111,
+#SECTION
+// verse
+1111,
+11
+// not a label, it's in the middle of a measure
+00,
+#BPMCHANGE 240
+#SECTION chorus
+1111,
+#SECTION
+1111,
+#END
+";
+
+    let sections = |options| {
+        let song = parse_tja_file_with_options(track, options).unwrap();
+        song.difficulties[3]
+            .as_ref()
+            .unwrap()
+            .chart
+            .sections
+            .clone()
+    };
+
+    let label = |time: f32, name: Option<&str>| SectionLabel {
+        time,
+        name: name.map(str::to_string),
+    };
+
+    // Comments are only kept when asked for
+    assert_eq!(
+        sections(ParseOptions::default()),
+        vec![
+            label(5.0, None),
+            label(9.0, Some("chorus")),
+            label(10.0, None)
+        ]
+    );
+
+    assert_eq!(
+        sections(ParseOptions {
+            comment_labels: true,
+            ..Default::default()
+        }),
+        vec![
+            label(1.0, Some("intro")),
+            label(5.0, Some("verse")),
+            label(9.0, Some("chorus")),
+            label(10.0, None)
+        ]
+    );
+}
+
+#[test]
+fn test_romanised_title() {
+    let track = "TITLE:千本桜
+TITLEEN:Senbonzakura
+WAVE:song.ogg
+COURSE:Easy
+
+#START
+1111,
+#END
+";
+
+    let song = parse_tja_file(track).unwrap();
+    assert_eq!(song.title_en.as_deref(), Some("Senbonzakura"));
+    assert_eq!(song.display_title(false), "千本桜");
+    assert_eq!(song.display_title(true), "Senbonzakura");
+
+    // Songs without one always show the original title
+    let song = parse_tja_file(&track.replace("TITLEEN:Senbonzakura\n", "")).unwrap();
+    assert_eq!(song.display_title(true), "千本桜");
+}
+
+#[test]
+fn test_full_width_metadata() {
+    let well_formed = "TITLE:夜に駆ける
+SUBTITLE:--YOASOBI  feat. ikura
+GENRE:J-POP
+WAVE:Yoru ni Kakeru.ogg
+BPM:130
+OFFSET:-1.5
+DEMOSTART:52.1
+
+COURSE:Oni
+LEVEL:8
+
+#START
+1011,
+2220,
+#END
+";
+
+    // The same chart, with full-width tags and colons, full-width spaces, zero-width spaces and
+    // stray control characters around the values
+    let messy = parse_tja_file(include_str!("./Full-width metadata.tja")).unwrap();
+    let clean = parse_tja_file(well_formed).unwrap();
+
+    assert_eq!(messy.title, "夜に駆ける");
+    // Spacing inside values is kept exactly
+    assert_eq!(messy.subtitle.as_deref(), Some("YOASOBI  feat. ikura"));
+    assert!(!messy.show_subtitle);
+
+    assert_eq!(messy.title, clean.title);
+    assert_eq!(messy.subtitle, clean.subtitle);
+    assert_eq!(messy.show_subtitle, clean.show_subtitle);
+    assert_eq!(messy.genre, clean.genre);
+    assert_eq!(messy.audio_filename, clean.audio_filename);
+    assert_eq!(messy.bpm, clean.bpm);
+    assert_eq!(messy.offset, clean.offset);
+    assert_eq!(messy.demostart, clean.demostart);
+
+    let messy_oni = messy.difficulties[3].as_ref().unwrap();
+    let clean_oni = clean.difficulties[3].as_ref().unwrap();
+    assert_eq!(messy_oni.star_level, Some(StarLevel::new(8)));
+    assert_eq!(messy_oni.star_level, clean_oni.star_level);
+    assert_eq!(messy_oni.chart.notes, clean_oni.chart.notes);
+}
+
+#[test]
+fn test_line_endings() {
+    let chart = "TITLE:Line Endings
+BPM:120
+WAVE:line endings.ogg
+
+COURSE:Oni
+LEVEL:5
+BALLOON:4
+
+#START
+1020,
+7008,
+#END";
+    let expected = parse_tja_file(chart).unwrap();
+    let notes = |song: &Song| song.difficulties[3].as_ref().unwrap().chart.notes.clone();
+
+    let parsed = [
+        // A byte order mark at the start of the file
+        format!("\u{feff}{chart}"),
+        // Windows and old Mac line endings
+        chart.replace('\n', "\r\n"),
+        chart.replace('\n', "\r"),
+        // Trailing whitespace after each line, and a newline after the last one
+        chart.replace('\n', "  \t\n") + "\n",
+    ]
+    .map(|file| parse_tja_file(&file).unwrap());
+
+    for song in parsed {
+        assert_eq!(song.title, expected.title);
+        assert_eq!(song.audio_filename, expected.audio_filename);
+        assert_eq!(notes(&song), notes(&expected));
+    }
+
+    // Errors still point at the right line with bare carriage returns
+    let broken = chart.replace("7008", "7x08").replace('\n', "\r");
+    assert_eq!(parse_tja_file(&broken).unwrap_err().line, 10);
+}
+
+#[test]
+fn test_inline_comments() {
+    let commented = parse_tja_file(
+        "TITLE:Comments // the title
+SUBTITLE:--https://example.com/comments
+WAVE:comments.ogg
+BPM:120 //fast enough
+
+COURSE:Oni
+LEVEL:5
+
+#START
+1100, // kat pattern here
+    // a comment on its own
+#GOGOSTART //fire
+2211,
+    //
+#GOGOEND
+1,
+#END
+",
+    )
+    .unwrap();
+    let plain = parse_tja_file(
+        "TITLE:Comments
+WAVE:comments.ogg
+BPM:120
+
+COURSE:Oni
+LEVEL:5
+
+#START
+1100,
+#GOGOSTART
+2211,
+#GOGOEND
+1,
+#END
+",
+    )
+    .unwrap();
+
+    assert_eq!(commented.title, "Comments");
+    // Slashes in a value are only a comment if there's a space before them
+    assert_eq!(
+        commented.subtitle.as_deref(),
+        Some("https://example.com/comments")
+    );
+    assert_eq!(commented.bpm, 120.0);
+
+    let chart = |song: &Song| song.difficulties[3].as_ref().unwrap().chart.clone();
+    assert_eq!(chart(&commented).notes, chart(&plain).notes);
+    assert_eq!(chart(&commented).barlines, chart(&plain).barlines);
+    assert_eq!(chart(&commented).gogo_times, chart(&plain).gogo_times);
+}
+
+#[test]
+fn test_lenient_parsing() {
+    let chart = "TITLE:Lenient
+WAVE:lenient.ogg
+BPM:120
+this isn't metadata
+
+COURSE:Oni
+LEVEL:5
+
+#START
+1010,
+#SUDDEN 1 2
+2020,
+#END
+";
+
+    // Strictly, one unknown command means the whole file can't be read
+    assert_eq!(
+        parse_tja_file(chart).unwrap_err(),
+        TJAParseError {
+            kind: TJAParseErrorKind::SyntaxError,
+            line: 3,
+        }
+    );
+    assert_eq!(
+        parse_tja_file(&chart.replace("this isn't metadata\n", ""))
+            .unwrap_err()
+            .kind,
+        TJAParseErrorKind::UnknownCommand("SUDDEN".to_string())
+    );
+
+    let lenient = ParseOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    let song = parse_tja_file_with_options(chart, lenient).unwrap();
+    assert_eq!(
+        song.warnings,
+        [
+            TJAParseError {
+                kind: TJAParseErrorKind::SyntaxError,
+                line: 3,
+            },
+            TJAParseError {
+                kind: TJAParseErrorKind::UnknownCommand("SUDDEN".to_string()),
+                line: 10,
+            },
+        ]
+    );
+
+    // The skipped lines don't change anything else
+    let clean = parse_tja_file(
+        &chart
+            .replace("this isn't metadata\n", "")
+            .replace("#SUDDEN 1 2\n", ""),
+    )
+    .unwrap();
+    assert!(clean.warnings.is_empty());
+    assert_eq!(
+        song.difficulties[3].as_ref().unwrap().chart.notes,
+        clean.difficulties[3].as_ref().unwrap().chart.notes
+    );
+
+    // Commands that are known but wrong are still an error
+    assert_eq!(
+        parse_tja_file_with_options(&chart.replace("#SUDDEN 1 2", "#BPMCHANGE fast"), lenient)
+            .unwrap_err()
+            .kind,
+        TJAParseErrorKind::CourseCommandError
+    );
+}
+
+#[test]
+fn test_error_lines() {
+    let chart = "TITLE:Errors
+WAVE:errors.ogg
+BPM:120
+
+COURSE:Oni
+LEVEL:5
+
+#START
+1010,
+2020,
+#END
+";
+    let error = |chart: &str| {
+        let error = parse_tja_file(chart).unwrap_err();
+        let source_line = error.source_line(chart).unwrap().to_string();
+        (error.line_number(), source_line, error.kind.to_string())
+    };
+
+    assert_eq!(
+        error(&chart.replace("2020,", "20x0,")),
+        (
+            10,
+            "20x0,".to_string(),
+            "unexpected character 'x' in note data".to_string()
+        )
+    );
+    assert_eq!(
+        error(&chart.replace("1010,", "1010,\n#SUDDEN 1 2")),
+        (
+            10,
+            "#SUDDEN 1 2".to_string(),
+            "unknown command \"#SUDDEN\"".to_string()
+        )
+    );
+    assert_eq!(
+        error(&chart.replace("BPM:120", "BPM:fast")),
+        (
+            3,
+            "BPM:fast".to_string(),
+            "invalid song metadata".to_string()
+        )
+    );
+    assert_eq!(
+        error(&chart.replace("#END\n", "")),
+        (10, "2020,".to_string(), "expected #END command".to_string())
+    );
+
+    let error = parse_tja_file(&chart.replace("1010,", "1o10,")).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "unexpected character 'o' in note data (at line 9)"
+    );
+}
+
+#[test]
+fn test_lyrics() {
+    let song = parse_tja_file(
+        "TITLE:Lyrics
+WAVE:lyrics.ogg
+LYRICS:lyrics.lrc
+BPM:120
+
+COURSE:Oni
+LEVEL:5
+
+#START
+#LYRIC first line
+1010,
+1010,
+#LYRIC second\\nline
+1010,
+#LYRIC
+1010,
+#END
+",
+    )
+    .unwrap();
+    assert_eq!(song.lyrics_file.as_deref(), Some("lyrics.lrc"));
+
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+    assert_eq!(
+        chart
+            .lyrics
+            .iter()
+            .map(|lyric| (lyric.time, lyric.text.as_str()))
+            .collect::<Vec<_>>(),
+        [(0.0, "first line"), (4.0, "second\nline"), (6.0, "")]
+    );
+
+    assert_eq!(chart.lyric_at(-1.0), None);
+    assert_eq!(chart.lyric_at(3.9), Some("first line"));
+    assert_eq!(chart.lyric_at(4.0), Some("second\nline"));
+    // An empty lyric clears the line
+    assert_eq!(chart.lyric_at(7.0), None);
+}
+
+#[test]
+fn test_hand_hints() {
+    let track = |line: &str| {
+        format!(
+            "TITLE:Hands\nWAVE:hands.ogg\n\nCOURSE:Oni\nLEVEL:5\n\n#START\n{line}\nA0B0,\n#END\n"
+        )
+    };
+
+    let song = parse_tja_file(&track("#SENOTECHANGE 3")).unwrap();
+    let notes = &song.difficulties[3].as_ref().unwrap().chart.notes;
+    assert_eq!(
+        notes
+            .iter()
+            .map(|note| (note.time, note.note_type))
+            .collect::<Vec<_>>(),
+        [(0.0, NoteType::CoopDon), (1.0, NoteType::CoopKat)]
+    );
+    assert!(notes[0].note_type.is_don() && notes[1].note_type.is_kat());
+
+    // The voice has to be a number, even when parsing leniently
+    let lenient = ParseOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    for line in ["#SENOTECHANGE", "#SENOTECHANGE don"] {
+        assert_eq!(
+            parse_tja_file_with_options(&track(line), lenient).unwrap_err(),
+            TJAParseError {
+                kind: TJAParseErrorKind::CourseCommandError,
+                line: 7,
+            }
+        );
+    }
+}
+
+#[test]
+fn test_dan_course() {
+    let tja = include_str!("./Dan course.tja");
+    let song = parse_tja_file(tja).unwrap();
+
+    // A dan course isn't one of the difficulties
+    assert!(song.difficulties.iter().all(Option::is_none));
+    assert_eq!(song.audio_filename, "first.ogg");
+
+    assert_eq!(
+        song.exams,
+        [
+            ExamRequirement {
+                condition: ExamCondition::Gauge,
+                red: 95,
+                gold: 100,
+                scope: ExamScope::AtLeast,
+            },
+            ExamRequirement {
+                condition: ExamCondition::Bads,
+                red: 20,
+                gold: 10,
+                scope: ExamScope::LessThan,
+            },
+            ExamRequirement {
+                condition: ExamCondition::Rolls,
+                red: 100,
+                gold: 120,
+                scope: ExamScope::AtLeast,
+            },
+        ]
+    );
+
+    let dan = song.dan.as_ref().unwrap();
+    assert_eq!(dan.star_level, Some(StarLevel::new(10)));
+    assert_eq!(
+        dan.chart.dan_songs,
+        [
+            DanSong {
+                time: 4.0,
+                title: "Second Song".to_string(),
+                subtitle: Some("Someone".to_string()),
+                genre: Some("J-POP".to_string()),
+                audio_filename: "second.ogg".to_string(),
+                score_init: Some(900),
+                score_diff: Some(0),
+                level: Some(9),
+                course: Some(3),
+            },
+            DanSong {
+                time: 8.0,
+                title: "Third Song".to_string(),
+                subtitle: None,
+                genre: None,
+                audio_filename: "third.ogg".to_string(),
+                score_init: None,
+                score_diff: None,
+                level: None,
+                course: None,
+            },
+        ]
+    );
+    assert_eq!(dan.chart.notes.len(), 15);
+
+    // Editing the metadata keeps everything about the course
+    let edits = MetadataEdits {
+        title: Some("Renamed".to_string()),
+        ..Default::default()
+    };
+    let edited = parse_tja_file(&write_metadata_edits(tja, &edits)).unwrap();
+    assert_eq!(edited.title, "Renamed");
+    assert_eq!(edited.exams, song.exams);
+    let edited_dan = edited.dan.as_ref().unwrap();
+    assert_eq!(edited_dan.chart.dan_songs, dan.chart.dan_songs);
+    assert_eq!(edited_dan.chart.notes, dan.chart.notes);
+
+    // Only one dan course is allowed, and the songs it goes on to need their audio
+    assert_eq!(
+        parse_tja_file(&format!("{tja}\n#START\n1,\n#END\n"))
+            .unwrap_err()
+            .kind,
+        TJAParseErrorKind::MultipleTracksSameDifficulty(DAN_COURSE)
+    );
+    assert_eq!(
+        parse_tja_file(&tja.replace(",,,third.ogg", ",,"))
+            .unwrap_err()
+            .kind,
+        TJAParseErrorKind::CourseCommandError
+    );
+    assert_eq!(
+        parse_tja_file(&tja.replace("jb,20", "jb,twenty")).unwrap_err(),
+        TJAParseError {
+            kind: TJAParseErrorKind::InvalidMetadata,
+            line: 14,
+        }
+    );
+}
+
+#[test]
+fn test_star_levels() {
+    let track = |level: &str| {
+        format!("TITLE:Levels\nWAVE:levels.ogg\n\nCOURSE:Oni\nLEVEL:{level}\n\n#START\n1,\n#END\n")
+    };
+    let level = |level: &str| {
+        let song = parse_tja_file(&track(level)).unwrap();
+        song.difficulties[3].as_ref().unwrap().star_level.unwrap()
+    };
+
+    let plus = level("8+");
+    assert_eq!(plus.level, 8);
+    assert_eq!(plus.modifier, Some(LevelModifier::Plus));
+    assert_eq!(plus.to_string(), "8+");
+    assert_eq!(level("7 -").modifier, Some(LevelModifier::Minus));
+
+    // Levels past the most stars are kept, but shown as the most stars
+    let eleven = level("11");
+    assert_eq!(eleven.level, 11);
+    assert_eq!(eleven.stars(), MAX_STARS);
+    assert_eq!(eleven.to_string(), "10");
+
+    // A level that isn't a number loses the course, unless parsing leniently
+    assert_eq!(
+        parse_tja_file(&track("hard")).unwrap_err(),
+        TJAParseError {
+            kind: TJAParseErrorKind::InvalidMetadata,
+            line: 4,
+        }
+    );
+    let lenient = ParseOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    let song = parse_tja_file_with_options(&track("hard"), lenient).unwrap();
+    let oni = song.difficulties[3].as_ref().unwrap();
+    assert_eq!(oni.star_level, None);
+    assert_eq!(
+        song.warnings,
+        [TJAParseError {
+            kind: TJAParseErrorKind::InvalidMetadata,
+            line: 4,
+        }]
+    );
+}
+
+#[test]
+fn test_metadata_only() {
+    let broken_notes =
+        "TITLE:Broken\nWAVE:broken.ogg\n\nCOURSE:Easy\nLEVEL:3\n\n#START\n1x0,\n#END\n\nCOURSE:Oni\nLEVEL:8+\nSCOREINIT:500\n\n#START\n1,\n#END\n";
+
+    // The notes aren't read, so broken notes don't stop the song from being listed
+    let song = parse_tja_metadata(broken_notes, ParseOptions::default()).unwrap();
+    assert_eq!(song.title, "Broken");
+    let easy = song.difficulties[0].as_ref().unwrap();
+    assert_eq!(easy.star_level, Some(StarLevel::new(3)));
+    assert!(easy.chart.notes.is_empty());
+    assert_eq!(easy.estimated_level, None);
+    let oni = song.difficulties[3].as_ref().unwrap();
+    assert_eq!(oni.star_level.unwrap().to_string(), "8+");
+    assert_eq!(oni.score_init, Some(500));
+    assert_eq!(oni.level(true), Some((oni.star_level.unwrap(), false)));
+
+    // They're found once the song is read in full
+    assert_eq!(
+        parse_tja_file(broken_notes).unwrap_err(),
+        TJAParseError {
+            kind: TJAParseErrorKind::UnexpectedCharacter('x'),
+            line: 7,
+        }
+    );
+
+    // A course still has to end
+    let unended = broken_notes.strip_suffix("#END\n").unwrap();
+    assert_eq!(
+        parse_tja_metadata(unended, ParseOptions::default())
+            .unwrap_err()
+            .kind,
+        TJAParseErrorKind::ExpectedEndCommand
+    );
+}
+
+#[test]
+fn test_background_movie() {
+    let song = parse_tja_file(include_str!("./Background movie.tja")).unwrap();
+
+    assert_eq!(song.bgmovie.as_deref(), Some("movie.mp4"));
+    // MOVIEOFFSET wins over the older BGOFFSET
+    assert_eq!(song.movie_offset, -1.5);
+
+    // At 120bpm, a measure lasts 2 seconds
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+    assert_eq!(
+        chart.bga_events,
+        vec![
+            BgaEvent {
+                time: 0.0,
+                visible: true
+            },
+            BgaEvent {
+                time: 4.0,
+                visible: false
+            },
+            BgaEvent {
+                time: 6.0,
+                visible: true
+            },
+        ]
+    );
+    assert_eq!(chart.notes.len(), 16);
+
+    let old_offset = "TITLE:Old\nWAVE:song.ogg\nBGOFFSET:2.5\n\n#START\n1,\n#END\n";
+    let old = parse_tja_file(old_offset).unwrap();
+    assert_eq!(old.bgmovie, None);
+    assert_eq!(old.movie_offset, 2.5);
+}
+
+#[test]
+fn test_bpm_change() {
+    let track = |bpm: &str| {
+        format!(
+            "TITLE:Tempo\nWAVE:song.ogg\nBPM:200\n\nCOURSE:Oni\nLEVEL:9\n\n#START\n11,\n#BPMCHANGE {bpm}\n11,\n#BPMCHANGE 200\n11,\n#END\n"
+        )
+    };
+
+    let assert_times = |times: Vec<f32>, expected: &[f32]| {
+        assert_eq!(times.len(), expected.len(), "{times:?}");
+        for (time, expected) in times.iter().zip(expected) {
+            assert!((time - expected).abs() < 1e-5, "{times:?}");
+        }
+    };
+
+    // At 200bpm a measure lasts 1.2 seconds, and at 100bpm it lasts 2.4
+    let song = parse_tja_file(&track("100")).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+    assert_times(
+        chart.notes.iter().map(|note| note.time).collect(),
+        &[0.0, 0.6, 1.2, 2.4, 3.6, 4.2],
+    );
+    assert_times(
+        chart.barlines.iter().map(|barline| barline.time).collect(),
+        &[0.0, 1.2, 3.6, 4.8],
+    );
+
+    // Tempos that aren't a positive number are rejected, pointing at the line they're on
+    for bpm in ["0", "-100", "fast", "inf"] {
+        assert_eq!(
+            parse_tja_file(&track(bpm)).unwrap_err(),
+            TJAParseError {
+                kind: TJAParseErrorKind::CourseCommandError,
+                line: 9,
+            },
+            "{bpm}"
+        );
+    }
+}
+
+#[test]
+fn test_delay() {
+    let track = |delay: &str| {
+        format!(
+            "TITLE:Delay\nWAVE:song.ogg\nBPM:120\n\nCOURSE:Oni\nLEVEL:5\n\n#START\n1111,\n11\n#DELAY {delay}\n11,\n#DELAY 1\n,\n1,\n#END\n"
+        )
+    };
+    let times = |song: Song| -> Vec<f32> {
+        let chart = &song.difficulties[3].as_ref().unwrap().chart;
+        chart.notes.iter().map(|note| note.time).collect()
+    };
+
+    // At 120bpm each measure is 2 seconds long. Everything after a delay moves along with it,
+    // including measures with no notes in them
+    let song = parse_tja_file(&track("1.5")).unwrap();
+    assert_eq!(
+        song.difficulties[3]
+            .as_ref()
+            .unwrap()
+            .chart
+            .barlines
+            .iter()
+            .map(|barline| barline.time)
+            .collect::<Vec<_>>(),
+        [0.0, 2.0, 5.5, 8.5, 10.5]
+    );
+    assert_eq!(times(song), [0.0, 0.5, 1.0, 1.5, 2.0, 2.5, 4.5, 5.0, 8.5]);
+
+    // Negative delays pull the notes after them earlier
+    let song = parse_tja_file(&track("-0.5")).unwrap();
+    assert_eq!(times(song), [0.0, 0.5, 1.0, 1.5, 2.0, 2.5, 2.5, 3.0, 6.5]);
+
+    // But not so far that they'd come before the notes they follow
+    assert_eq!(
+        parse_tja_file(&track("-1")).unwrap_err().kind,
+        TJAParseErrorKind::DelayBeforePreviousNote
+    );
+}
+
+#[test]
+fn test_measure_subdivisions() {
+    let track = |course: &str| {
+        format!(
+            "TITLE:Subdivisions\nWAVE:song.ogg\nBPM:120\n\nCOURSE:Oni\nLEVEL:5\n\n#START\n{course}\n#END\n"
+        )
+    };
+    let fine_measure = format!("{},\n1,", "1".repeat(1024));
+
+    // Measures split finer than the limit are turned away, even leniently
+    let lenient = ParseOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        parse_tja_file_with_options(&track(&fine_measure), lenient)
+            .unwrap_err()
+            .kind,
+        TJAParseErrorKind::TooManySubdivisions {
+            subdivisions: 1024,
+            limit: DEFAULT_MAX_SUBDIVISIONS,
+        }
+    );
+
+    // But the limit can be raised, and then every note still gets a time in order
+    let raised = ParseOptions {
+        max_subdivisions: 1024,
+        ..Default::default()
+    };
+    let song = parse_tja_file_with_options(&track(&fine_measure), raised).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+    assert_eq!(chart.notes.len(), 1025);
+    assert!(chart.notes.iter().all(|note| note.time.is_finite()));
+    assert!(chart
+        .notes
+        .windows(2)
+        .all(|pair| pair[0].time <= pair[1].time));
+    assert_eq!(chart.notes.last().unwrap().time, 2.0);
+
+    // A tempo change in an empty measure doesn't leave anything without a time
+    let song = parse_tja_file(&track("1,\n#BPMCHANGE 240\n,\n1,")).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+    assert_eq!(
+        chart.notes.iter().map(|note| note.time).collect::<Vec<_>>(),
+        [0.0, 3.0]
+    );
+
+    // Measures with no length can't have notes spaced out in them
+    for signature in ["0/4", "4/0"] {
+        assert_eq!(
+            parse_tja_file(&track(&format!("#MEASURE {signature}\n1,"))).unwrap_err(),
+            TJAParseError {
+                kind: TJAParseErrorKind::CourseCommandError,
+                line: 8,
+            },
+            "{signature}"
+        );
+    }
+}
+
+#[test]
+fn test_rolls_across_measures() {
+    let notes = |course: &str| -> Result<Vec<(f32, NoteType)>, TJAParseError> {
+        let song = parse_tja_file(&format!(
+            "TITLE:Rolls\nWAVE:song.ogg\nBPM:120\n\nCOURSE:Oni\nLEVEL:5\n\n#START\n{course}\n#END\n"
+        ))?;
+        let chart = &song.difficulties[3].as_ref().unwrap().chart;
+        Ok(chart
+            .notes
+            .iter()
+            .map(|note| (note.time, note.note_type))
+            .collect())
+    };
+
+    // At 120bpm each measure is two seconds long
+    assert_eq!(
+        notes("0050,\n0000,\n,\n8001,").unwrap(),
+        [(1.0, NoteType::Roll(5.0)), (7.5, NoteType::Don)]
+    );
+
+    // Two rolls in a row
+    assert_eq!(
+        notes("5008,\n6000,\n0800,").unwrap(),
+        [(0.0, NoteType::Roll(1.5)), (2.0, NoteType::BigRoll(2.5))]
+    );
+
+    // The roll goes on at the new tempo after the change
+    assert_eq!(
+        notes("0050,\n#BPMCHANGE 240\n0800,").unwrap(),
+        [(1.0, NoteType::Roll(1.25))]
+    );
+
+    // A roll that's never ended
+    assert_eq!(
+        notes("5000,\n1000,").unwrap_err().kind,
+        TJAParseErrorKind::RollNotEnded
+    );
+    assert_eq!(
+        notes("5000,\n0000,").unwrap_err().kind,
+        TJAParseErrorKind::RollNotEnded
+    );
+}
+
+#[test]
+fn test_balloon_counts() {
+    let track = |balloons: &str| {
+        format!(
+            "TITLE:Balloons\nWAVE:song.ogg\nBPM:120\n\nCOURSE:Oni\nLEVEL:5\nBALLOON:{balloons}\n\n#START\n7008,\n7800,\n#END\n"
+        )
+    };
+    let counts = |song: Song| -> Vec<u32> {
+        let chart = &song.difficulties[3].as_ref().unwrap().chart;
+        chart
+            .notes
+            .iter()
+            .filter_map(|note| match note.note_type {
+                NoteType::BalloonRoll(_, hits) => Some(hits),
+                _ => None,
+            })
+            .collect()
+    };
+
+    assert_eq!(counts(parse_tja_file(&track("10,20")).unwrap()), [10, 20]);
+    assert_eq!(
+        counts(parse_tja_file(&track(" 10, 20 ,")).unwrap()),
+        [10, 20]
+    );
+    // An empty list gives every balloon 5 hits
+    assert_eq!(counts(parse_tja_file(&track("")).unwrap()), [5, 5]);
+
+    // But a list with too few or too many counts is an error
+    let mismatch = |counts| TJAParseError {
+        kind: TJAParseErrorKind::BalloonCountMismatch {
+            course: "Oni".to_string(),
+            balloons: 2,
+            counts,
+        },
+        line: 6,
+    };
+    assert_eq!(parse_tja_file(&track("10")).unwrap_err(), mismatch(1));
+    assert_eq!(parse_tja_file(&track("10,20,30")).unwrap_err(), mismatch(3));
+
+    // Unless the file's read leniently, where balloons without a count take 5 hits
+    let lenient = ParseOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    let song = parse_tja_file_with_options(&track("10"), lenient).unwrap();
+    assert_eq!(song.warnings, [mismatch(1)]);
+    assert_eq!(counts(song), [10, 5]);
+
+    let song = parse_tja_file_with_options(&track("10,20,30"), lenient).unwrap();
+    assert_eq!(song.warnings, [mismatch(3)]);
+    assert_eq!(counts(song), [10, 20]);
+
+    // Leaving the list out altogether is an error too, and gives every balloon 5 hits leniently
+    let no_list = track("").replace("BALLOON:\n", "");
+    assert_eq!(
+        parse_tja_file(&no_list).unwrap_err().kind,
+        TJAParseErrorKind::MissingMetadataForCourse("BALLOON".to_string())
+    );
+    let song = parse_tja_file_with_options(&no_list, lenient).unwrap();
+    assert_eq!(
+        song.warnings,
+        [TJAParseError {
+            kind: TJAParseErrorKind::BalloonCountMismatch {
+                course: "Oni".to_string(),
+                balloons: 2,
+                counts: 0,
+            },
+            line: 4,
+        }]
+    );
+    assert_eq!(counts(song), [5, 5]);
+}
+
+#[test]
+fn test_kusudama() {
+    // The run of kusudama notes in the second measure is all one kusudama
+    let song = parse_tja_file(
+        "TITLE:Kusudama\nWAVE:song.ogg\nBPM:120\n\nCOURSE:Oni\nLEVEL:5\nBALLOON:10,30,20\n\n#START\n7008,\n9990,\n0800,\n7800,\n#END\n",
+    )
+    .unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+
+    // Counts go to balloons and kusudama alike, in the order they're written
+    assert_eq!(
+        chart
+            .notes
+            .iter()
+            .map(|note| (note.time, note.note_type))
+            .collect::<Vec<_>>(),
+        [
+            (0.0, NoteType::BalloonRoll(1.5, 10)),
+            (2.0, NoteType::Kusudama(2.5, 30)),
+            (6.0, NoteType::BalloonRoll(0.5, 20)),
+        ]
+    );
+}
+
+#[test]
+fn test_gogo_time() {
+    let song = parse_tja_file(
+        "TITLE:Gogo\nWAVE:song.ogg\nBPM:120\n\nCOURSE:Oni\nLEVEL:5\n\n#START\n11\n#GOGOSTART\n11,\n1\n#GOGOEND\n1,\n#GOGOSTART\n11,\n#END\n",
+    )
+    .unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+
+    // The last gogo time is never ended, so it lasts until the end of the chart
+    assert_eq!(
+        chart.gogo_times,
+        [
+            GogoTime {
+                start: 1.0,
+                end: 3.0
+            },
+            GogoTime {
+                start: 4.0,
+                end: 6.0
+            },
+        ]
+    );
+    assert_eq!(
+        chart.notes.iter().map(|note| note.gogo).collect::<Vec<_>>(),
+        [false, false, true, true, true, false, true, true]
+    );
+    assert!(!chart.is_gogo(0.5));
+    assert!(chart.is_gogo(1.0));
+    assert!(!chart.is_gogo(3.0));
+    assert!(chart.is_gogo(5.9));
+}
+
+#[test]
+fn test_branches() {
+    let song = parse_tja_file(include_str!("./Branches.tja")).unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+    let times = |notes: &[Note]| notes.iter().map(|note| note.time).collect::<Vec<_>>();
+
+    // At 120bpm each measure is two seconds long
+    assert_eq!(chart.branches.len(), 2);
+    let first = &chart.branches[0];
+    assert_eq!(
+        first.condition,
+        BranchCondition {
+            requirement: BranchRequirement::Accuracy,
+            expert: 80.0,
+            master: 90.0,
+        }
+    );
+    assert_eq!((first.start, first.end), (2.0, 4.0));
+    assert_eq!(times(&first.notes[0]), [2.0]);
+    assert_eq!(times(&first.notes[1]), [2.0, 3.0]);
+    assert_eq!(
+        first.notes[MASTER_BRANCH][0].note_type,
+        NoteType::BalloonRoll(1.5, 3)
+    );
+
+    // The last branched section goes on to the end of the chart, and the expert branch it leaves
+    // out is the same as the normal one. Balloons are counted in the order they're written.
+    let second = &chart.branches[1];
+    assert_eq!(second.condition.requirement, BranchRequirement::Rolls);
+    assert_eq!((second.start, second.end), (6.0, 8.0));
+    assert_eq!(second.notes[0], second.notes[1]);
+    assert_eq!(second.notes[0][0].note_type, NoteType::BalloonRoll(1.5, 4));
+    assert_eq!(times(&second.notes[MASTER_BRANCH]), [6.0, 6.5, 7.0, 7.5]);
+
+    // The master branch is the one that's played
+    assert_eq!(
+        times(&chart.notes),
+        [0.0, 0.5, 1.0, 1.5, 2.0, 4.0, 4.5, 5.0, 5.5, 6.0, 6.5, 7.0, 7.5]
+    );
+    // A section marked inside a branch is the same as the one just before it
+    assert_eq!(chart.sections.len(), 1);
+
+    let stray_branch = "TITLE:Stray\nWAVE:song.ogg\n\n#START\n#N\n1,\n#END\n";
+    assert_eq!(
+        parse_tja_file(stray_branch).unwrap_err().kind,
+        TJAParseErrorKind::CourseCommandError
+    );
+}
+
+/// Checks that a song reads back the same after being written out with [to_tja_string].
+#[cfg(test)]
+fn assert_round_trips(song: &Song) {
+    let tja = to_tja_string(song);
+    let read_back = parse_tja_file(&tja).unwrap_or_else(|e| panic!("{e}\n{tja}"));
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-3;
+
+    assert_eq!(read_back.title, song.title);
+    assert_eq!(read_back.subtitle, song.subtitle);
+    assert_eq!(read_back.show_subtitle, song.show_subtitle);
+    assert_eq!(read_back.audio_filename, song.audio_filename);
+
+    for (difficulty, read_back) in song.difficulties.iter().zip(&read_back.difficulties) {
+        let (Some(difficulty), Some(read_back)) = (difficulty, read_back) else {
+            assert!(difficulty.is_none() && read_back.is_none());
+            continue;
+        };
+
+        assert_eq!(read_back.star_level, difficulty.star_level);
+        assert_eq!(read_back.score_init, difficulty.score_init);
+        assert_eq!(read_back.score_diff, difficulty.score_diff);
+
+        let (chart, read_chart) = (&difficulty.chart, &read_back.chart);
+        assert_eq!(read_chart.notes.len(), chart.notes.len(), "{tja}");
+
+        for (note, read_note) in chart.notes.iter().zip(&read_chart.notes) {
+            assert!(
+                close(read_note.time, note.time),
+                "{note:?} vs {read_note:?}\n{tja}"
+            );
+            assert!(
+                close(read_note.scroll_speed, note.scroll_speed),
+                "{note:?} vs {read_note:?}"
+            );
+            assert_eq!(read_note.gogo, note.gogo, "{note:?} vs {read_note:?}");
+
+            use NoteType::*;
+            match (note.note_type, read_note.note_type) {
+                (Roll(a), Roll(b)) | (BigRoll(a), BigRoll(b)) => assert!(close(a, b)),
+                (BalloonRoll(a, hits), BalloonRoll(b, read_hits))
+                | (Kusudama(a, hits), Kusudama(b, read_hits)) => {
+                    assert!(close(a, b));
+                    assert_eq!(hits, read_hits);
+                }
+                (a, b) => assert_eq!(a, b),
+            }
+        }
+
+        let barlines =
+            |chart: &NoteChart| chart.barlines.iter().map(|b| b.time).collect::<Vec<_>>();
+        let (expected, read) = (barlines(chart), barlines(read_chart));
+        assert_eq!(
+            read.len(),
+            expected.len(),
+            "{expected:?} vs {read:?}\n{tja}"
+        );
+        assert!(expected.iter().zip(&read).all(|(&a, &b)| close(a, b)));
+
+        assert_eq!(read_chart.gogo_times.len(), chart.gogo_times.len());
+        for (gogo, read_gogo) in chart.gogo_times.iter().zip(&read_chart.gogo_times) {
+            assert!(close(gogo.start, read_gogo.start) && close(gogo.end, read_gogo.end));
+        }
+    }
+
+    // Writing it out again gives exactly the same file
+    assert_eq!(to_tja_string(&read_back), tja);
+}
+
+#[test]
+fn test_roll_ending_on_a_note() {
+    let tja = "TITLE:Roll\nWAVE:song.ogg\nBPM:120\n\nCOURSE:Oni\n#START\n5008,\n1,\n#END\n";
+    let mut song = parse_tja_file(tja).unwrap();
+    let chart = &mut song.difficulties[3].as_mut().unwrap().chart;
+    // Charts made from osu beatmaps can have a roll end right where the next note starts
+    chart.notes[0].note_type = NoteType::Roll(2.0);
+
+    // The roll ends a 48th of a measure early so the note after it is kept
+    let read_back = parse_tja_file(&to_tja_string(&song)).unwrap();
+    let notes = &read_back.difficulties[3].as_ref().unwrap().chart.notes;
+    assert_eq!(notes.len(), 2);
+    let NoteType::Roll(length) = notes[0].note_type else {
+        panic!("{:?} isn't a roll", notes[0].note_type);
+    };
+    assert!((length - (2.0 - 2.0 / 48.0)).abs() < 1e-3);
+    assert_eq!(notes[1].time, 2.0);
+}
+
+#[test]
+fn test_scroll_modes() {
+    let tja = "TITLE:Scrolling\nWAVE:song.ogg\nBPM:120\n\nCOURSE:Oni\nLEVEL:8\n#HBSCROLL\n#START\n1,\n#END\n\nCOURSE:Hard\nLEVEL:6\n#BMSCROLL\n#START\n1,\n#END\n\nCOURSE:Normal\nLEVEL:4\n#START\n1,\n#END\n";
+    let song = parse_tja_file(tja).unwrap();
+    let mode = |i: usize| song.difficulties[i].as_ref().unwrap().chart.scroll_mode;
+
+    // Each command only applies to the course it comes before
+    assert_eq!(mode(3), ScrollMode::Hbs);
+    assert_eq!(mode(2), ScrollMode::Bms);
+    assert_eq!(mode(1), ScrollMode::Normal);
+
+    let written = to_tja_string(&song);
+    assert!(written.contains("#HBSCROLL\n#START\n"));
+    assert!(written.contains("#BMSCROLL\n#START\n"));
+    let reread = parse_tja_file(&written).unwrap();
+    for i in 1..=3 {
+        assert_eq!(
+            reread.difficulties[i].as_ref().unwrap().chart.scroll_mode,
+            mode(i)
+        );
+    }
+}
+
+#[test]
+fn test_serialise_real_tja_file() {
+    let song = parse_tja_file(include_str!("./Ready to.tja")).unwrap();
+    assert_round_trips(&song);
+
+    // Moving every chart to a different offset keeps the notes in the same place in their measures
+    for shift in [-0.25, 0.1, 1.0 / 3.0, 2.5] {
+        let mut shifted = song.clone();
+        shifted.offset -= shift;
+
+        for difficulty in shifted.difficulties.iter_mut().flatten() {
+            let chart = &mut difficulty.chart;
+            chart.notes.iter_mut().for_each(|note| note.time += shift);
+            chart
+                .barlines
+                .iter_mut()
+                .for_each(|barline| barline.time += shift);
+            chart
+                .timing
+                .iter_mut()
+                .for_each(|point| point.time += shift);
+            chart.gogo_times.iter_mut().for_each(|gogo| {
+                gogo.start += shift;
+                gogo.end += shift;
+            });
+            chart.end_time += shift;
+        }
+
+        assert_round_trips(&shifted);
+    }
+}
+
+#[test]
+fn test_serialise_timing_changes() {
+    let tja = "TITLE:Timing
+SUBTITLE:--Hidden
+WAVE:timing.ogg
+BPM:150
+OFFSET:-1.5
+
+COURSE:Oni
+LEVEL:9+
+BALLOON:12,30
+
+#START
+#MEASURE 3/4
+1010201,
+#BPMCHANGE 200
+#SCROLL 1.5
+100200100200,
+#GOGOSTART
+500000000008,
+10
+#DELAY 0.5
+20,
+#MEASURE 4/4
+#BARLINEOFF
+7008,
+#BARLINEON
+30004000,
+#SCROLL 0.75
+111111111111111111111111,
+9000000000000008,
+#GOGOEND
+,
+AB,
+#END
+
+COURSE:Easy
+LEVEL:2
+
+#START
+#BPMCHANGE 90
+1,
+,
+#BPMCHANGE 180
+1
+#BPMCHANGE 120
+1,
+#END
+";
+    let song = parse_tja_file(tja).unwrap();
+    assert_round_trips(&song);
+
+    let written = to_tja_string(&song);
+    assert!(written.contains("SUBTITLE:--Hidden\n"));
+    assert!(written.contains("BALLOON:12,30\n"));
+    // The course starts in 3/4, and the later 4/4 becomes a BPM change
+    assert!(written.contains("#START\n#MEASURE 3/4\n1010201,\n#BPMCHANGE 200\n#SCROLL 1.5\n"));
+    assert!(written.contains("#BPMCHANGE 150\n"));
+    assert!(written.contains("#DELAY 0.5\n"));
+    // A course can't start at its own BPM without changing it for the courses after it
+    assert!(written.contains("#START\n#BPMCHANGE 90\n1,\n"));
+}
+
+#[test]
+fn test_chart_at_speed() {
+    let song = parse_tja_file(
+        "TITLE:Speed\nWAVE:song.ogg\nBPM:120\n\nCOURSE:Oni\nLEVEL:5\n\n#START\n1,\n#GOGOSTART\n5008,\n#GOGOEND\n1,\n#END\n",
+    )
+    .unwrap();
+    let chart = &song.difficulties[3].as_ref().unwrap().chart;
+
+    // At half speed everything takes twice as long, but looks the same on screen
+    let slow = chart.at_speed(0.5);
+    assert_eq!(
+        slow.notes.iter().map(|note| note.time).collect::<Vec<_>>(),
+        [0.0, 4.0, 8.0]
+    );
+    assert_eq!(slow.notes[1].note_type, NoteType::Roll(3.0));
+    assert_eq!(
+        slow.notes[1].scroll_speed,
+        chart.notes[1].scroll_speed * 0.5
+    );
+    assert_eq!(
+        slow.barlines
+            .iter()
+            .map(|barline| barline.time)
+            .collect::<Vec<_>>(),
+        [0.0, 4.0, 8.0, 12.0]
+    );
+    assert_eq!(
+        slow.gogo_times,
+        [GogoTime {
+            start: 4.0,
+            end: 8.0
+        }]
+    );
+    assert_eq!(slow.timing[0].seconds_per_measure, 4.0);
+    assert_eq!(slow.end_time, 2.0 * chart.end_time);
+
+    // Going back to normal speed gets the same chart back
+    let normal = slow.at_speed(2.0);
+    assert_eq!(normal.notes, chart.notes);
+    assert_eq!(normal.timing, chart.timing);
+}
+
+#[test]
+fn test_player_tracks() {
+    let header = "TITLE:Battle\nWAVE:song.ogg\nBPM:120\n\nCOURSE:Oni\nLEVEL:8\n";
+    let notes = |song: &Song, player| {
+        let course = song.difficulties[3].as_ref().unwrap();
+        let notes = &course.chart_for(player).notes;
+        notes.iter().map(|note| note.note_type).collect::<Vec<_>>()
+    };
+
+    // Each player gets their own track
+    let song = parse_tja_file(&format!(
+        "{header}#START P1\n1,\n#END\n\n#START P2\n2,\n#END\n"
+    ))
+    .unwrap();
+    assert_eq!(notes(&song, Player::Player1), [NoteType::Don]);
+    assert_eq!(notes(&song, Player::Player2), [NoteType::Kat]);
+    // Playing alone goes by player 1's
+    let course = song.difficulties[3].as_ref().unwrap();
+    assert_eq!(course.chart.notes.len(), 1);
+    assert_eq!(course.chart.notes[0].note_type, NoteType::Don);
+    assert_eq!(course.star_level, Some(StarLevel::new(8)));
+
+    // A track for playing alone is kept alongside the players' own, whichever comes first
+    for tja in [
+        format!("{header}#START\n11,\n#END\n#START P1\n1,\n#END\n#START P2\n2,\n#END\n"),
+        format!("{header}#START P1\n1,\n#END\n#START P2\n2,\n#END\n#START\n11,\n#END\n"),
+    ] {
+        let song = parse_tja_file(&tja).unwrap();
+        let course = song.difficulties[3].as_ref().unwrap();
+        assert_eq!(course.chart.notes.len(), 2);
+        assert_eq!(notes(&song, Player::Player1), [NoteType::Don]);
+        assert_eq!(notes(&song, Player::Player2), [NoteType::Kat]);
+    }
+
+    // Without a track of their own, both players play the same notes
+    let song = parse_tja_file(&format!("{header}#START\n12,\n#END\n")).unwrap();
+    assert_eq!(notes(&song, Player::Player1), notes(&song, Player::Player2));
+
+    // Each player only gets one track
+    assert_eq!(
+        parse_tja_file(&format!(
+            "{header}#START P2\n1,\n#END\n#START P2\n2,\n#END\n"
+        ))
+        .unwrap_err()
+        .kind,
+        TJAParseErrorKind::MultipleTracksSameDifficulty(3)
+    );
+
+    // Reading just the metadata skips over them all
+    let song = parse_tja_metadata(
+        &format!("{header}#START P1\n1,\n#END\n#START P2\n2,\n#END\n"),
+        ParseOptions::default(),
+    )
+    .unwrap();
+    assert!(song.difficulties[3].is_some());
+}
@@ -1,21 +1,29 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 
 use lookahead::Lookahead;
 use nom::{
     branch::alt,
-    bytes::complete::{is_not, tag, take_while1},
-    character::complete::satisfy,
-    combinator::{eof, map_res, opt, recognize},
+    bytes::complete::{is_not, tag, take_while, take_while1},
+    character::complete::{satisfy, space0},
+    combinator::{eof, map_res, opt, recognize, rest},
     error::{FromExternalError, ParseError},
     multi::{many0_count, many1, separated_list0},
-    sequence::{pair, preceded, separated_pair, terminated},
+    sequence::{delimited, pair, preceded, separated_pair, terminated},
     Finish, IResult, Parser,
 };
+use serde::{Deserialize, Serialize};
 
-use super::chart::{Barline, Difficulty, Note, NoteChart, NoteType, Song};
+use super::barlines::fill_missing_barlines;
+use super::chart::{
+    Barline, BgaEvent, BranchCondition, BranchRequirement, BranchSection, DanSong, Difficulty,
+    ExamCondition, ExamRequirement, ExamScope, GogoTime, Lyric, Note, NoteChart, NoteType, Player,
+    ScrollMode, SectionLabel, Song, StarLevel, TimingPoint, MASTER_BRANCH,
+};
+use super::difficulty::estimate_difficulty;
 /// Types of errors that can be encountered while parsing a TJA file. This is used in the
 /// [TJAParseError] struct.
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TJAParseErrorKind {
     SyntaxError,
     CourseCommandError,
@@ -26,31 +34,72 @@ pub enum TJAParseErrorKind {
     MissingMetadataForSong(String),
     RollNotEnded,
     RollEndWithoutRoll,
+    /// A negative `#DELAY` that would put the next notes before ones that came earlier.
+    DelayBeforePreviousNote,
+    /// A course's `BALLOON` list doesn't have a hit count for each of its balloons (and kusudama).
+    BalloonCountMismatch {
+        course: String,
+        balloons: usize,
+        counts: usize,
+    },
+    /// A `COURSE` that isn't one of the difficulties.
+    UnknownCourse(String),
+    /// A command in a note track that isn't supported (usually one from another simulator).
+    UnknownCommand(String),
+    /// A character in a line of notes that isn't a note.
+    UnexpectedCharacter(char),
+    /// A measure split into more notes (counting the blank ones) than
+    /// [ParseOptions::max_subdivisions] allows.
+    TooManySubdivisions {
+        subdivisions: usize,
+        limit: usize,
+    },
 }
 
 /// An error that can be encountered while parsing a TJA file. Contains an enum for the kind of
 /// error as well as the line where the error is (or pertains to).
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TJAParseError {
     pub kind: TJAParseErrorKind,
+    /// The line the error is on, counting from 0. See [TJAParseError::line_number] for the one to
+    /// show people.
     pub line: usize,
 }
 
+impl TJAParseError {
+    /// The line the error is on, counting from 1 like a text editor does.
+    pub fn line_number(&self) -> usize {
+        self.line + 1
+    }
+
+    /// The line the error is on, taken from the file that was parsed.
+    pub fn source_line<'a>(&self, input: &'a str) -> Option<&'a str> {
+        split_lines(input).nth(self.line).map(str::trim)
+    }
+}
+
 impl std::fmt::Display for TJAParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self.kind {
+        write!(f, "{} (at line {})", self.kind, self.line_number())
+    }
+}
+
+impl std::fmt::Display for TJAParseErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
             TJAParseErrorKind::SyntaxError => f.write_str("syntax error")?,
             TJAParseErrorKind::CourseCommandError => {
                 f.write_str("invalid song notation command")?
             }
             TJAParseErrorKind::InvalidMetadata => f.write_str("invalid song metadata")?,
             TJAParseErrorKind::MultipleTracksSameDifficulty(diff) => {
-                let difficulty = match diff {
+                let difficulty = match *diff {
                     0 => "easy",
                     1 => "normal",
                     2 => "hard",
                     3 => "extreme",
                     4 => "extra extreme",
+                    DAN_COURSE => "dan",
                     _ => panic!("difficulty is out of range 0-4"),
                 };
 
@@ -71,9 +120,35 @@ impl std::fmt::Display for TJAParseError {
             TJAParseErrorKind::RollEndWithoutRoll => {
                 f.write_str("drumroll end without preceding drumroll")?
             }
+            TJAParseErrorKind::DelayBeforePreviousNote => {
+                f.write_str("delay goes back past the previous note")?
+            }
+            TJAParseErrorKind::BalloonCountMismatch {
+                course,
+                balloons,
+                counts,
+            } => f.write_fmt(format_args!(
+                "the {course} course has {balloons} balloons but {counts} hit counts in its \
+                 BALLOON list"
+            ))?,
+            TJAParseErrorKind::UnknownCourse(course) => {
+                f.write_fmt(format_args!("unknown course \"{course}\""))?;
+            }
+            TJAParseErrorKind::UnknownCommand(command) => {
+                f.write_fmt(format_args!("unknown command \"#{command}\""))?;
+            }
+            TJAParseErrorKind::UnexpectedCharacter(c) => {
+                f.write_fmt(format_args!("unexpected character '{c}' in note data"))?;
+            }
+            TJAParseErrorKind::TooManySubdivisions {
+                subdivisions,
+                limit,
+            } => f.write_fmt(format_args!(
+                "a measure is split into {subdivisions} notes, more than the limit of {limit}"
+            ))?,
         }
 
-        f.write_fmt(format_args!(" (at line {})", self.line + 1))
+        Ok(())
     }
 }
 
@@ -101,12 +176,6 @@ impl<E> FromExternalError<&str, E> for TJAParseErrorKind {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum Player {
-    Player1,
-    Player2,
-}
-
 #[derive(Debug, Clone, PartialEq)]
 enum CourseCommand<'a> {
     Lyric(&'a str),
@@ -118,7 +187,88 @@ enum CourseCommand<'a> {
     GogoEnd,
     BarlineOff,
     BarlineOn,
-    // TODO: Commands for diverge notes
+    /// The start of a section. Charters sometimes give these a name.
+    Section(Option<&'a str>),
+    BgaOn,
+    BgaOff,
+    /// The start of the next song in a dan course (`#NEXTSONG`). Its time is filled in once the
+    /// chart is put together.
+    NextSong(DanSong),
+    /// Changes the voice callout used for the next notes (`#SENOTECHANGE`). Callouts aren't
+    /// played yet, so this is only checked.
+    SeNoteChange(u8),
+    BranchStart(BranchCondition),
+    /// The start of the normal branch (`#N`).
+    BranchNormal,
+    /// The start of the expert branch (`#E`).
+    BranchExpert,
+    /// The start of the master branch (`#M`).
+    BranchMaster,
+    BranchEnd,
+}
+
+/// Parses the argument of a `#BRANCHSTART` command, e.g. `p,80,90`: what to measure, then what the
+/// player needs for the expert and master branches.
+fn branch_condition(arg: &str) -> Result<BranchCondition, TJAParseErrorKind> {
+    let parts: Vec<&str> = arg.split(',').map(str::trim).collect();
+    let [requirement, expert, master] = parts[..] else {
+        return Err(TJAParseErrorKind::CourseCommandError);
+    };
+
+    let requirement = match requirement {
+        "r" => BranchRequirement::Rolls,
+        "p" => BranchRequirement::Accuracy,
+        "s" => BranchRequirement::Score,
+        _ => return Err(TJAParseErrorKind::CourseCommandError),
+    };
+    let threshold = |value: &str| {
+        value
+            .parse::<f32>()
+            .map_err(|_| TJAParseErrorKind::CourseCommandError)
+    };
+
+    Ok(BranchCondition {
+        requirement,
+        expert: threshold(expert)?,
+        master: threshold(master)?,
+    })
+}
+
+/// Parses the argument of a `#NEXTSONG` command:
+/// `title,subtitle,genre,wave[,scoreinit,scorediff[,level,course]]`.
+fn next_song(arg: &str) -> Result<DanSong, TJAParseErrorKind> {
+    let parts: Vec<&str> = arg.split(',').map(str::trim).collect();
+    let [title, subtitle, genre, wave, ref rest @ ..] = parts[..] else {
+        return Err(TJAParseErrorKind::CourseCommandError);
+    };
+
+    // The optional values can also just be left empty
+    let optional = |i: usize| rest.get(i).copied().filter(|value| !value.is_empty());
+    let number = |i: usize| {
+        optional(i)
+            .map(|value| value.parse::<u32>())
+            .transpose()
+            .map_err(|_| TJAParseErrorKind::CourseCommandError)
+    };
+
+    let level = number(2)?
+        .map(|level| u8::try_from(level).map_err(|_| TJAParseErrorKind::CourseCommandError))
+        .transpose()?;
+    let course = optional(3)
+        .map(|course| course_index(course).ok_or(TJAParseErrorKind::UnknownCourse(course.into())))
+        .transpose()?;
+
+    Ok(DanSong {
+        time: 0.0,
+        title: title.to_string(),
+        subtitle: split_subtitle(subtitle).0,
+        genre: (!genre.is_empty()).then(|| genre.to_string()),
+        audio_filename: wave.to_string(),
+        score_init: number(0)?,
+        score_diff: number(1)?,
+        level,
+        course,
+    })
 }
 
 impl<'a> CourseCommand<'a> {
@@ -130,21 +280,59 @@ impl<'a> CourseCommand<'a> {
 
         let command = match name {
             "END" => panic!("fatal error parsing song command: end command should have been handled seperately!"),
-            "LYRIC" => CourseCommand::Lyric(arg_res?),
+            // An empty lyric clears the last one
+            "LYRIC" => CourseCommand::Lyric(arg.unwrap_or("")),
             "BPMCHANGE" => {
-                CourseCommand::BpmChange(arg_res?.parse::<f32>().map_err(|_| TJAParseErrorKind::CourseCommandError)?)
+                let bpm = arg_res?
+                    .parse::<f32>()
+                    .map_err(|_| TJAParseErrorKind::CourseCommandError)?;
+
+                // Measures would last forever (or go backwards) at a tempo that isn't positive
+                if !(bpm.is_finite() && bpm > 0.0) {
+                    return Err(TJAParseErrorKind::CourseCommandError);
+                }
+
+                CourseCommand::BpmChange(bpm)
             }
             "MEASURE" => {
                 let (_, (numerator, denominator)) =
                     time_signature(arg_res?).map_err(|_| TJAParseErrorKind::CourseCommandError)?;
 
+                // A measure with no length (or an infinite one) can't have notes spaced out in it
+                if numerator == 0 || denominator == 0 {
+                    return Err(TJAParseErrorKind::CourseCommandError);
+                }
+
                 CourseCommand::Measure(numerator, denominator)
             }
-            "DELAY" => CourseCommand::Delay(arg_res?.parse::<f32>().map_err(|_| TJAParseErrorKind::CourseCommandError)?),
+            "DELAY" => {
+                let delay = arg_res?
+                    .parse::<f32>()
+                    .map_err(|_| TJAParseErrorKind::CourseCommandError)?;
+                if !delay.is_finite() {
+                    return Err(TJAParseErrorKind::CourseCommandError);
+                }
+
+                CourseCommand::Delay(delay)
+            }
             "SCROLL" => {
                 CourseCommand::Scroll(arg_res?.parse::<f32>().map_err(|_| TJAParseErrorKind::CourseCommandError)?)
             }
-            "GOGOSTART" | "GOGOEND" | "BARLINEOFF" | "BARLINEON" => {
+            "SECTION" => CourseCommand::Section(arg.map(str::trim)),
+            // Some simulators let these pick between several movies and say where to start them,
+            // but there's only ever one movie here, so any arguments are ignored
+            "BGAON" => CourseCommand::BgaOn,
+            "BGAOFF" => CourseCommand::BgaOff,
+            "NEXTSONG" => CourseCommand::NextSong(next_song(arg_res?)?),
+            "SENOTECHANGE" => CourseCommand::SeNoteChange(
+                arg_res?
+                    .trim()
+                    .parse::<u8>()
+                    .map_err(|_| TJAParseErrorKind::CourseCommandError)?,
+            ),
+            "BRANCHSTART" => CourseCommand::BranchStart(branch_condition(arg_res?)?),
+            "GOGOSTART" | "GOGOEND" | "BARLINEOFF" | "BARLINEON" | "N" | "E" | "M"
+            | "BRANCHEND" => {
                 // These dont take any arguments, so ensure there is no arg
                 if arg.is_some() {
                     return Err(TJAParseErrorKind::CourseCommandError);
@@ -155,10 +343,14 @@ impl<'a> CourseCommand<'a> {
                     "GOGOEND" => CourseCommand::GogoEnd,
                     "BARLINEOFF" => CourseCommand::BarlineOff,
                     "BARLINEON" => CourseCommand::BarlineOn,
+                    "N" => CourseCommand::BranchNormal,
+                    "E" => CourseCommand::BranchExpert,
+                    "M" => CourseCommand::BranchMaster,
+                    "BRANCHEND" => CourseCommand::BranchEnd,
                     _ => unreachable!(),
                 }
             }
-            _ => return Err(TJAParseErrorKind::CourseCommandError),
+            _ => return Err(TJAParseErrorKind::UnknownCommand(name.to_string())),
         };
 
         Ok(command)
@@ -168,9 +360,7 @@ impl<'a> CourseCommand<'a> {
 /// The type of a note.
 ///
 /// This includes a special note, which defines the end
-/// of a drum roll. All drum rolls should be terminated with this note
-/// (with the exception of the [SpecialRoll][NoteType::SpecialRoll], which can be terminated
-/// with another [SpecialRoll][NoteType::SpecialRoll]).
+/// of a drum roll. All drum rolls should be terminated with this note.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum TJANoteType {
     Don,
@@ -181,7 +371,7 @@ enum TJANoteType {
     BigRoll,
     BalloonRoll(u32),
     RollEnd,
-    SpecialRoll(u32),
+    Kusudama(u32),
     CoopDon,
     CoopKat,
 }
@@ -226,16 +416,74 @@ fn time_signature(i: &str) -> IResult<&str, (u8, u8), TJAParseErrorKind> {
     separated_pair(integer::<u8>, tag("/"), integer::<u8>)(i)
 }
 
-/// Parses a metadata pair in the form `KEY:value`. The key must be made up entirely of uppercase
-/// letters.
-fn metadata_pair(input: &str) -> IResult<&str, (&str, &str)> {
+/// The full-width colon, which some charts use to separate metadata keys from their values.
+pub(crate) const FULL_WIDTH_COLON: char = '：';
+
+/// Converts a full-width character (e.g. `Ａ`) to its ASCII equivalent. Anything else is returned
+/// as it is.
+fn from_full_width(c: char) -> char {
+    match c {
+        '！'..='～' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// Whether the character is one that doesn't take up any space, which sometimes ends up at the
+/// ends of metadata values when charts are copied from elsewhere.
+fn is_zero_width(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}' | '\u{180E}'
+    )
+}
+
+/// Trims whitespace (including full-width spaces), control characters and zero-width characters
+/// from both ends of a metadata value. Spacing inside the value is left exactly as it is.
+fn trim_metadata_value(value: &str) -> &str {
+    value.trim_matches(|c: char| c.is_whitespace() || c.is_control() || is_zero_width(c))
+}
+
+/// Parses the name of a metadata tag, which is made up of letters, sometimes followed by a number
+/// (as in `NOTESDESIGNER3`). Full-width characters (as in `ＴＩＴＬＥ`) and lower case letters are
+/// accepted too, and the name is normalised to ASCII upper case.
+fn metadata_tagname(input: &str) -> IResult<&str, Cow<'_, str>> {
+    recognize(pair(
+        take_while1(|c: char| from_full_width(c).is_ascii_alphabetic()),
+        take_while(|c: char| from_full_width(c).is_ascii_digit()),
+    ))
+    .map(|name: &str| {
+        if name
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        {
+            Cow::Borrowed(name)
+        } else {
+            Cow::Owned(
+                name.chars()
+                    .map(|c| from_full_width(c).to_ascii_uppercase())
+                    .collect(),
+            )
+        }
+    })
+    .parse(input)
+}
+
+/// Parses a metadata pair in the form `KEY:value` (see [metadata_tagname] for the key). The colon
+/// can be full-width too. The value is trimmed with [trim_metadata_value].
+fn metadata_pair(input: &str) -> IResult<&str, (Cow<'_, str>, &str)> {
     separated_pair(
-        take_while1(|c| ('A'..='Z').contains(&c)),
-        tag(":"),
-        opt(is_not("\r\n")).map(|value| value.unwrap_or("")),
+        metadata_tagname,
+        satisfy(|c| c == ':' || c == FULL_WIDTH_COLON),
+        opt(is_not("\r\n")).map(|value| trim_metadata_value(value.unwrap_or(""))),
     )(input)
 }
 
+/// Splits a line into its normalised metadata key and trimmed value, if it's a metadata line. The
+/// line shouldn't have any comments in it.
+pub(crate) fn split_metadata_line(line: &str) -> Option<(Cow<'_, str>, &str)> {
+    parse(metadata_pair)(line.trim()).ok()
+}
+
 /// Parses a beatmap start command `#START [player]`. The player argument is optional, and is
 /// used when a track has different note maps for players playing on the same difficulty. It
 /// can either be `"P1", "P2", or absent if there is only one track for the difficulty.
@@ -253,6 +501,16 @@ fn start_command(input: &str) -> IResult<&str, Option<Player>, TJAParseErrorKind
     Ok((input, player))
 }
 
+/// Parses a `#BMSCROLL` or `#HBSCROLL` command, which goes before the `#START` of the course it
+/// applies to.
+fn scroll_mode_command(line: &str) -> Option<ScrollMode> {
+    match line {
+        "#BMSCROLL" => Some(ScrollMode::Bms),
+        "#HBSCROLL" => Some(ScrollMode::Hbs),
+        _ => None,
+    }
+}
+
 /// Parses a beatmap end command `#END`.
 fn end_command(input: &str) -> IResult<&str, (), TJAParseErrorKind> {
     let (input, _) = tag("#END")(input)?;
@@ -261,10 +519,13 @@ fn end_command(input: &str) -> IResult<&str, (), TJAParseErrorKind> {
 
 // Functions for parsing courses/beatmaps
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 enum CourseItem<'a> {
     EndCommand,
     Command(CourseCommand<'a>),
+    /// A line that is only a comment. These are only kept if
+    /// [ParseOptions::comment_labels] is set.
+    Comment(&'a str),
     Notes {
         notes: Vec<Option<TJANoteType>>,
         end_measure: bool,
@@ -286,11 +547,20 @@ fn course_command(input: &str) -> IResult<&str, CourseCommand, TJAParseErrorKind
 }
 
 fn balloon_list(input: &str) -> IResult<&str, Vec<u32>, TJAParseErrorKind> {
-    terminated(separated_list0(tag(","), integer::<u32>), opt(tag(",")))(input)
+    // Charters often leave spaces and a trailing comma in the list
+    terminated(
+        separated_list0(tag(","), delimited(space0, integer::<u32>, space0)),
+        opt(pair(tag(","), space0)),
+    )(input)
+}
+
+/// Whether a character in note data is a note (including a gap, `0`).
+fn is_note(c: char) -> bool {
+    c.is_ascii_digit() || ['A', 'B'].contains(&c)
 }
 
 fn note(i: &str) -> IResult<&str, Option<TJANoteType>, TJAParseErrorKind> {
-    satisfy(|c| c.is_ascii_digit() || ['A', 'B'].contains(&c))
+    satisfy(is_note)
         .map(|c| match c {
             '0' => None,
             '1' => Some(TJANoteType::Don),
@@ -301,7 +571,7 @@ fn note(i: &str) -> IResult<&str, Option<TJANoteType>, TJAParseErrorKind> {
             '6' => Some(TJANoteType::BigRoll),
             '7' => Some(TJANoteType::BalloonRoll(0)),
             '8' => Some(TJANoteType::RollEnd),
-            '9' => Some(TJANoteType::SpecialRoll(0)),
+            '9' => Some(TJANoteType::Kusudama(0)),
             'A' => Some(TJANoteType::CoopDon),
             'B' => Some(TJANoteType::CoopKat),
             _ => unreachable!("matching on invalid note!"),
@@ -338,9 +608,16 @@ fn notes(input: &str) -> IResult<&str, CourseItem, TJAParseErrorKind> {
     }
 }
 
+fn comment(input: &str) -> IResult<&str, CourseItem, TJAParseErrorKind> {
+    preceded(tag("//"), rest)
+        .map(|comment: &str| CourseItem::Comment(comment.trim()))
+        .parse(input)
+}
+
 fn course_item(input: &str) -> IResult<&str, CourseItem, TJAParseErrorKind> {
     alt((
         end_command.map(|_| CourseItem::EndCommand),
+        comment,
         notes,
         course_command.map(CourseItem::Command),
     ))(input)
@@ -349,8 +626,13 @@ fn course_item(input: &str) -> IResult<&str, CourseItem, TJAParseErrorKind> {
 /// Preprocess the lines that define a course and turn them into a vector of [CourseItem]s.
 /// This is necessary because we may need to look ahead while we're iterating through these items
 /// and constructing the difficulty.
+///
+/// If the file is being parsed leniently, unknown commands are added to `warnings` and skipped
+/// rather than being an error.
 fn process_course<'a>(
     lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    lenient: bool,
+    warnings: &mut Vec<TJAParseError>,
 ) -> Result<Vec<CourseItem<'a>>, TJAParseError> {
     // Needed for returning a line number error if we ever run out of lines
     let mut line_num = 0;
@@ -359,7 +641,28 @@ fn process_course<'a>(
     for (i, line) in lines {
         line_num = i;
 
-        match parse(course_item)(line).map_err(|e| TJAParseError { kind: e, line: i })? {
+        let item = match parse(course_item)(line) {
+            Ok(item) => item,
+            Err(kind @ TJAParseErrorKind::UnknownCommand(_)) if lenient => {
+                warnings.push(TJAParseError { kind, line: i });
+                continue;
+            }
+            Err(TJAParseErrorKind::SyntaxError)
+                if line.starts_with(|c: char| c.is_ascii_digit()) =>
+            {
+                let kind = line
+                    .chars()
+                    .find(|&c| c != ',' && !is_note(c))
+                    .map_or(TJAParseErrorKind::SyntaxError, |c| {
+                        TJAParseErrorKind::UnexpectedCharacter(c)
+                    });
+
+                return Err(TJAParseError { kind, line: i });
+            }
+            Err(kind) => return Err(TJAParseError { kind, line: i }),
+        };
+
+        match item {
             CourseItem::EndCommand => return Ok(res),
             item => res.push(item),
         }
@@ -372,7 +675,7 @@ fn process_course<'a>(
 }
 
 fn get_parsed_metadata<'a, T: std::str::FromStr>(
-    metadata: &HashMap<&'a str, (usize, &'a str)>,
+    metadata: &HashMap<Cow<'a, str>, (usize, &'a str)>,
     key: &'a str,
     default: Option<T>,
     course_line: Option<usize>,
@@ -403,8 +706,19 @@ fn get_parsed_metadata<'a, T: std::str::FromStr>(
         })
 }
 
+/// Takes the `--` or `++` off the front of a subtitle, returning what's left and whether the
+/// subtitle should be shown during the song. Empty subtitles are treated as missing.
+fn split_subtitle(subtitle: &str) -> (Option<String>, bool) {
+    let (subtitle, show) = match subtitle.strip_prefix("--") {
+        Some(rest) => (rest, false),
+        None => (subtitle.strip_prefix("++").unwrap_or(subtitle), true),
+    };
+
+    ((!subtitle.is_empty()).then(|| subtitle.to_string()), show)
+}
+
 fn get_metadata_owned<'a>(
-    metadata: &HashMap<&'a str, (usize, &'a str)>,
+    metadata: &HashMap<Cow<'a, str>, (usize, &'a str)>,
     key: &'a str,
     default: Option<&str>,
     course_line: Option<usize>,
@@ -459,12 +773,293 @@ fn notes_in_next_measure<'a, I: Iterator<Item = CourseItem<'a>>>(iter: &mut Look
     num_notes
 }
 
-fn construct_difficulty(
+/// Adds a section starting at the given time.
+///
+/// A comment and a `#SECTION` command right next to each other are almost certainly marking the
+/// same section, so they're merged into one label.
+fn add_section(sections: &mut Vec<SectionLabel>, time: f32, name: Option<&str>) {
+    match sections.last_mut() {
+        Some(last) if last.time == time => {
+            if last.name.is_none() {
+                last.name = name.map(str::to_string);
+            }
+        }
+        _ => sections.push(SectionLabel {
+            time,
+            name: name.map(str::to_string),
+        }),
+    }
+}
+
+/// Works out how many hits each balloon in the course takes, in the order they're written
+/// (branches included).
+///
+/// A run of kusudama notes before the roll end makes up a single kusudama, so the ones after the
+/// first are taken out.
+///
+/// The number of balloons has to match the number of hit counts. If the file is being parsed
+/// leniently, a mismatch is added to `warnings` instead, and balloons without a count take 5 hits.
+fn assign_balloon_hits(
+    items: &mut [CourseItem<'_>],
+    metadata: &HashMap<Cow<str>, (usize, &str)>,
+    course_line_number: usize,
+    lenient: bool,
+    warnings: &mut Vec<TJAParseError>,
+) -> Result<(), TJAParseError> {
+    // If the number of balloons in the course is nonzero, we have to store
+    // how many hits it takes to complete each one. This is the BALLOON metadata
+    let balloons = metadata
+        .get("BALLOON")
+        .map(|&(i, list)| {
+            let balloons = parse(balloon_list)(list).map_err(|_| TJAParseError {
+                kind: TJAParseErrorKind::InvalidMetadata,
+                line: i,
+            })?;
+            Ok((i, balloons))
+        })
+        .transpose()?;
+
+    let mut balloon_index = 0;
+    let mut kusudama_open = false;
+
+    for item in items {
+        let CourseItem::Notes { notes, .. } = item else {
+            continue;
+        };
+
+        for note in notes.iter_mut() {
+            match note {
+                Some(TJANoteType::Kusudama(_)) if kusudama_open => {
+                    *note = None;
+                    continue;
+                }
+                Some(TJANoteType::Kusudama(_)) => kusudama_open = true,
+                Some(TJANoteType::RollEnd) => kusudama_open = false,
+                _ => {}
+            }
+
+            let Some(note_type @ (TJANoteType::BalloonRoll(_) | TJANoteType::Kusudama(_))) = note
+            else {
+                continue;
+            };
+
+            // If there are no balloons listed, (`BALLOON:`) then every balloon
+            // roll gets a value of 5, for compatibility with TJAPlayer.
+            // Otherwise it gets the value listed in order, and every balloon
+            // has to have one (which is checked once they've all been counted).
+            if balloons.is_none() && !lenient {
+                // No balloons were specified in metadata but there was a
+                // balloon note. Thats invalid!
+                return Err(TJAParseError {
+                    kind: TJAParseErrorKind::MissingMetadataForCourse("BALLOON".to_string()),
+                    line: course_line_number,
+                });
+            }
+
+            let roll_num = balloons
+                .as_ref()
+                .and_then(|(_, balloons)| balloons.get(balloon_index))
+                .copied()
+                .unwrap_or(DEFAULT_BALLOON_HITS);
+
+            balloon_index += 1;
+
+            *note_type = match note_type {
+                TJANoteType::BalloonRoll(_) => TJANoteType::BalloonRoll(roll_num),
+                TJANoteType::Kusudama(_) => TJANoteType::Kusudama(roll_num),
+                _ => unreachable!(),
+            };
+        }
+    }
+
+    let course = metadata.get("COURSE");
+    // Without a list, the mismatch is pointed out at the course instead
+    let (line, counts) = match &balloons {
+        Some((_, balloons)) if balloons.is_empty() => return Ok(()),
+        Some((line, balloons)) => (*line, balloons.len()),
+        None => (course.map_or(course_line_number, |&(line, _)| line), 0),
+    };
+
+    if counts != balloon_index {
+        let error = TJAParseError {
+            kind: TJAParseErrorKind::BalloonCountMismatch {
+                course: course.map_or("Oni", |&(_, course)| course).to_string(),
+                balloons: balloon_index,
+                counts,
+            },
+            line,
+        };
+
+        if !lenient {
+            return Err(error);
+        }
+        warnings.push(error);
+    }
+
+    Ok(())
+}
+
+/// A stretch of a course that's either the same for everyone, or split into branches.
+#[derive(Debug)]
+enum CourseSegment<'a> {
+    Shared(Vec<CourseItem<'a>>),
+    Branched {
+        condition: BranchCondition,
+        /// The items in each branch, indexed as in [BRANCH_NAMES](super::chart::BRANCH_NAMES).
+        branches: [Vec<CourseItem<'a>>; 3],
+    },
+}
+
+/// A branched stretch of a course that's still being read.
+struct OpenBranches<'a> {
+    condition: BranchCondition,
+    branches: [Vec<CourseItem<'a>>; 3],
+    /// Which branches the chart has started.
+    written: [bool; 3],
+    /// The branch being read, or None if the chart hasn't started one yet.
+    current: Option<usize>,
+}
+
+impl<'a> OpenBranches<'a> {
+    fn new(condition: BranchCondition) -> Self {
+        Self {
+            condition,
+            branches: Default::default(),
+            written: [false; 3],
+            current: None,
+        }
+    }
+
+    fn push(&mut self, item: CourseItem<'a>) {
+        match self.current {
+            Some(branch) => self.branches[branch].push(item),
+            // Anything before the first branch starts is in all of them
+            None => {
+                for branch in &mut self.branches {
+                    branch.push(item.clone());
+                }
+            }
+        }
+    }
+
+    fn close(mut self) -> CourseSegment<'a> {
+        // A branch the chart leaves out is played the same as the first one it has
+        if let Some(first) = self.written.iter().position(|&written| written) {
+            for branch in 0..3 {
+                if !self.written[branch] {
+                    self.branches[branch] = self.branches[first].clone();
+                }
+            }
+        }
+
+        CourseSegment::Branched {
+            condition: self.condition,
+            branches: self.branches,
+        }
+    }
+}
+
+/// Splits a course into the parts that are shared and the parts that are branched.
+///
+/// A branched part lasts until `#BRANCHEND`, the next `#BRANCHSTART` or the end of the course.
+fn split_branches(
     items: Vec<CourseItem<'_>>,
-    metadata: &HashMap<&str, (usize, &str)>,
     course_line_number: usize,
-) -> Result<Difficulty, TJAParseError> {
+) -> Result<Vec<CourseSegment<'_>>, TJAParseError> {
+    let outside_branch = TJAParseError {
+        kind: TJAParseErrorKind::CourseCommandError,
+        line: course_line_number,
+    };
+
+    let mut segments = Vec::new();
+    let mut shared = Vec::new();
+    let mut open: Option<OpenBranches> = None;
+
+    for item in items {
+        let branch = match item {
+            CourseItem::Command(CourseCommand::BranchStart(condition)) => {
+                if let Some(open) = open.take() {
+                    segments.push(open.close());
+                } else if !shared.is_empty() {
+                    segments.push(CourseSegment::Shared(std::mem::take(&mut shared)));
+                }
+
+                open = Some(OpenBranches::new(condition));
+                continue;
+            }
+            CourseItem::Command(CourseCommand::BranchEnd) => {
+                segments.push(open.take().ok_or(outside_branch.clone())?.close());
+                continue;
+            }
+            CourseItem::Command(CourseCommand::BranchNormal) => 0,
+            CourseItem::Command(CourseCommand::BranchExpert) => 1,
+            CourseItem::Command(CourseCommand::BranchMaster) => 2,
+            item => {
+                match open.as_mut() {
+                    Some(open) => open.push(item),
+                    None => shared.push(item),
+                }
+                continue;
+            }
+        };
+
+        let open = open.as_mut().ok_or(outside_branch.clone())?;
+        open.current = Some(branch);
+        open.written[branch] = true;
+    }
+
+    if let Some(open) = open {
+        segments.push(open.close());
+    } else if !shared.is_empty() {
+        segments.push(CourseSegment::Shared(shared));
+    }
+
+    Ok(segments)
+}
+
+/// The items of a course as they're played when the given branch is taken every time. Each
+/// branched section is marked with `#BRANCHSTART` and `#BRANCHEND` commands.
+fn branch_path<'a>(segments: &[CourseSegment<'a>], branch: usize) -> Vec<CourseItem<'a>> {
+    let mut items = Vec::new();
+
+    for segment in segments {
+        match segment {
+            CourseSegment::Shared(shared) => items.extend(shared.iter().cloned()),
+            CourseSegment::Branched {
+                condition,
+                branches,
+            } => {
+                items.push(CourseItem::Command(CourseCommand::BranchStart(*condition)));
+                items.extend(branches[branch].iter().cloned());
+                items.push(CourseItem::Command(CourseCommand::BranchEnd));
+            }
+        }
+    }
+
+    items
+}
+
+/// How long each note in a measure lasts, given how many notes (blank ones included) it's split
+/// into. A measure with no notes has nothing to space out, so it's zero then rather than infinite.
+fn note_length(seconds_per_measure: f32, notes_in_measure: usize) -> f32 {
+    if notes_in_measure == 0 {
+        0.0
+    } else {
+        seconds_per_measure / notes_in_measure as f32
+    }
+}
+
+/// Works out the times of everything in a course with no branches in it (see [branch_path]).
+/// Returns the chart, along with when each branched section starts and ends.
+fn construct_chart(
+    items: Vec<CourseItem<'_>>,
+    metadata: &HashMap<Cow<str>, (usize, &str)>,
+    course_line_number: usize,
+    max_subdivisions: usize,
+) -> Result<(NoteChart, Vec<[f32; 2]>), TJAParseError> {
     let mut chart = NoteChart::default();
+    let mut branch_spans = Vec::new();
+    let mut branch_start = None;
 
     // Various metadata needed for constructing the track
     // The time signature, as numerator divided by denominator (musicians might
@@ -474,42 +1069,58 @@ fn construct_difficulty(
     const DEFAULT_BPM: f32 = 120.0;
     let mut bpm =
         get_parsed_metadata::<f32>(metadata, "BPM", Some(DEFAULT_BPM), Some(course_line_number))?;
+    // The same goes for the starting BPM as for a #BPMCHANGE
+    if !(bpm.is_finite() && bpm > 0.0) {
+        return Err(TJAParseError {
+            kind: TJAParseErrorKind::InvalidMetadata,
+            line: metadata
+                .get("BPM")
+                .map_or(course_line_number, |&(line, _)| line),
+        });
+    }
     let offset =
         get_parsed_metadata::<f32>(metadata, "OFFSET", Some(0.0), Some(course_line_number))?;
     let init_scroll_speed =
         get_parsed_metadata::<f32>(metadata, "HEADSCROLL", Some(1.0), Some(course_line_number))?;
 
-    // If the number of balloons in the course is nonzero, we have to store
-    // how many hits it takes to complete each one. This is the BALLOON metadata
-    let balloons = metadata
-        .get("BALLOON")
-        .map(|&(i, list)| {
-            parse(balloon_list)(list).map_err(|_| TJAParseError {
-                kind: TJAParseErrorKind::InvalidMetadata,
-                line: i,
-            })
-        })
-        .transpose()?;
-
-    let mut balloon_index = 0;
-
     let mut unscaled_scroll = init_scroll_speed;
     let mut scroll_speed = init_scroll_speed * bpm / DEFAULT_BPM;
 
-    let mut items_iter = lookahead::lookahead(items);
-    let mut notes_in_measure = notes_in_next_measure(&mut items_iter);
-    let mut seconds_per_measure = 60.0 * signature * 4.0 / bpm;
+    // Measures split absurdly finely are turned away before their notes are given times
+    let check_subdivisions = |subdivisions: usize| {
+        if subdivisions > max_subdivisions {
+            return Err(TJAParseError {
+                kind: TJAParseErrorKind::TooManySubdivisions {
+                    subdivisions,
+                    limit: max_subdivisions,
+                },
+                line: course_line_number,
+            });
+        }
 
-    let mut seconds_per_note = if notes_in_measure == 0 {
-        0.0
-    } else {
-        seconds_per_measure / notes_in_measure as f32
+        Ok(subdivisions)
     };
 
+    let mut items_iter = lookahead::lookahead(items);
+    let mut notes_in_measure = check_subdivisions(notes_in_next_measure(&mut items_iter))?;
+    let mut seconds_per_measure = 60.0 * signature * 4.0 / bpm;
+    let mut seconds_per_note = note_length(seconds_per_measure, notes_in_measure);
+
     let mut time = -offset;
     let mut measure_start_time = time;
     let mut barlines = vec![Barline { time, scroll_speed }];
     let mut barline_on = true;
+    let mut timing = vec![TimingPoint {
+        time,
+        seconds_per_measure,
+        scroll_speed,
+        bpm,
+    }];
+    // Whether no notes have been placed since the last measure ended. Comments are only taken as
+    // section labels here, since ones in the middle of a measure are usually about the notes.
+    let mut at_measure_start = true;
+    // When the current gogo time started, if it's gogo time
+    let mut gogo_start = None;
 
     let mut notes = Vec::new();
 
@@ -519,25 +1130,78 @@ fn construct_difficulty(
                 CourseCommand::BpmChange(new_bpm) => {
                     bpm = new_bpm;
                     seconds_per_measure = 60.0 * signature * 4.0 / bpm;
-                    seconds_per_note = seconds_per_measure / notes_in_measure as f32;
-                    scroll_speed = init_scroll_speed * (unscaled_scroll) * bpm / DEFAULT_BPM
+                    seconds_per_note = note_length(seconds_per_measure, notes_in_measure);
+                    scroll_speed = init_scroll_speed * (unscaled_scroll) * bpm / DEFAULT_BPM;
+                    push_timing_point(&mut timing, time, seconds_per_measure, scroll_speed, bpm);
                 }
                 CourseCommand::Measure(num, den) => {
                     signature = num as f32 / den as f32;
                     seconds_per_measure = 60.0 * signature * 4.0 / bpm;
-                    seconds_per_note = seconds_per_measure / notes_in_measure as f32;
+                    seconds_per_note = note_length(seconds_per_measure, notes_in_measure);
+                    push_timing_point(&mut timing, time, seconds_per_measure, scroll_speed, bpm);
+                }
+                CourseCommand::Delay(t) => {
+                    // Negative delays are fine, as long as the notes stay in order
+                    if notes
+                        .last()
+                        .is_some_and(|&(_, last_time, _, _)| time + t < last_time)
+                    {
+                        return Err(TJAParseError {
+                            kind: TJAParseErrorKind::DelayBeforePreviousNote,
+                            line: course_line_number,
+                        });
+                    }
+
+                    // Measures stand still during the delay. A negative delay just jumps back.
+                    if t > 0.0 {
+                        push_timing_point(&mut timing, time, f32::INFINITY, scroll_speed, bpm);
+                    }
+                    time += t;
+                    // The rest of the measure moves along with it, even if it's empty
+                    measure_start_time += t;
+                    push_timing_point(&mut timing, time, seconds_per_measure, scroll_speed, bpm);
                 }
-                CourseCommand::Delay(t) => time += t,
                 CourseCommand::Scroll(s) => {
                     scroll_speed = init_scroll_speed * (s) * bpm / DEFAULT_BPM;
                     unscaled_scroll = s;
+                    push_timing_point(&mut timing, time, seconds_per_measure, scroll_speed, bpm);
+                }
+                CourseCommand::GogoStart => {
+                    gogo_start.get_or_insert(time);
+                }
+                CourseCommand::GogoEnd => {
+                    if let Some(start) = gogo_start.take() {
+                        chart.gogo_times.push(GogoTime { start, end: time });
+                    }
                 }
-                CourseCommand::GogoStart => {}
-                CourseCommand::GogoEnd => {}
                 CourseCommand::BarlineOff => barline_on = false,
                 CourseCommand::BarlineOn => barline_on = true,
+                CourseCommand::Section(name) => add_section(&mut chart.sections, time, name),
+                CourseCommand::BgaOn => chart.bga_events.push(BgaEvent {
+                    time,
+                    visible: true,
+                }),
+                CourseCommand::BgaOff => chart.bga_events.push(BgaEvent {
+                    time,
+                    visible: false,
+                }),
+                CourseCommand::Lyric(text) => chart.lyrics.push(Lyric {
+                    time,
+                    // Line breaks are written as `\n`
+                    text: text.trim().replace("\\n", "\n"),
+                }),
+                CourseCommand::NextSong(song) => chart.dan_songs.push(DanSong { time, ..song }),
+                CourseCommand::BranchStart(_) => branch_start = Some(time),
+                CourseCommand::BranchEnd => {
+                    branch_spans.push([branch_start.take().unwrap_or(time), time]);
+                }
                 _ => {}
             },
+            CourseItem::Comment(comment) => {
+                if at_measure_start && !comment.is_empty() {
+                    add_section(&mut chart.sections, time, Some(comment));
+                }
+            }
             CourseItem::Notes {
                 notes: new_notes,
                 end_measure,
@@ -549,63 +1213,20 @@ fn construct_difficulty(
                 // no notes). Thus, we can multiply the milliseconds per note by each note's
                 // index in the vector and add this to the current time to find when the note should
                 // be hit.
-                let new_notes = new_notes
-                    .iter()
-                    .enumerate()
-                    .filter_map(|(i, note)| {
-                        note.map(|note_type| {
-                            let mut note_type = note_type;
-
-                            if matches!(
-                                note_type,
-                                TJANoteType::BalloonRoll(_) | TJANoteType::SpecialRoll(_)
-                            ) {
-                                // TJA balloon roll logic is... very strange.
-                                // If there are no balloons listed, (`BALLOON:`) then every balloon
-                                // roll gets a value of 5. Otherwise it gets the value listed in
-                                // order, but if there are more balloons in the track than are
-                                // listed in the metadata, it gets a DIFFERENT value of 10.
-                                // It's very strange but it has to be this way for... compatibility
-                                // reasons? I think it might have even been a bug in TJAPlayer that
-                                // was unintentionally carried into the format.
-                                //
-                                // i'm doing great
-                                if let Some(balloons) = balloons.as_ref() {
-                                    let roll_num = if balloons.is_empty() {
-                                        5
-                                    } else {
-                                        *balloons.get(balloon_index).unwrap_or(&10)
-                                    };
-
-                                    balloon_index += 1;
-
-                                    note_type = match note_type {
-                                        TJANoteType::BalloonRoll(_) => {
-                                            TJANoteType::BalloonRoll(roll_num)
-                                        }
-                                        TJANoteType::SpecialRoll(_) => {
-                                            TJANoteType::SpecialRoll(roll_num)
-                                        }
-                                        _ => unreachable!(),
-                                    }
-                                } else {
-                                    // No balloons were specified in metadata but there was a
-                                    // balloon note. Thats invalid!
-                                    return Err(TJAParseError {
-                                        kind: TJAParseErrorKind::MissingMetadataForCourse(
-                                            "BALLOON".to_string(),
-                                        ),
-                                        line: course_line_number,
-                                    });
-                                }
-                            };
-
-                            Ok((note_type, time + seconds_per_note * i as f32, scroll_speed))
-                        })
+                let new_notes = new_notes.iter().enumerate().filter_map(|(i, note)| {
+                    note.map(|note_type| {
+                        let gogo = gogo_start.is_some();
+                        (
+                            note_type,
+                            time + seconds_per_note * i as f32,
+                            scroll_speed,
+                            gogo,
+                        )
                     })
-                    .collect::<Result<Vec<_>, _>>()?;
+                });
 
                 notes.extend(new_notes);
+                at_measure_start = end_measure;
                 // Update the current time. We didn't have to do this for each note
                 // because they're evenly spaced.
                 let elapsed_time = num_notes as f32 * seconds_per_note;
@@ -625,13 +1246,8 @@ fn construct_difficulty(
                     }
 
                     // Recalculate our measure-based variables
-                    notes_in_measure = notes_in_next_measure(&mut items_iter);
-
-                    seconds_per_note = if notes_in_measure == 0 {
-                        0.0
-                    } else {
-                        seconds_per_measure / notes_in_measure as f32
-                    };
+                    notes_in_measure = check_subdivisions(notes_in_next_measure(&mut items_iter))?;
+                    seconds_per_note = note_length(seconds_per_measure, notes_in_measure);
                 }
             }
 
@@ -641,52 +1257,30 @@ fn construct_difficulty(
         }
     }
 
+    // Gogo time that's never ended lasts until the end of the chart
+    if let Some(start) = gogo_start {
+        chart.gogo_times.push(GogoTime { start, end: time });
+    }
+
     let mut track_notes = Vec::with_capacity(notes.len());
-    let mut notes = notes.into_iter().peekable();
+    let mut notes = notes.into_iter();
 
-    while let Some((note_type, time, scroll_speed)) = notes.next() {
+    while let Some((note_type, time, scroll_speed, gogo)) = notes.next() {
         use TJANoteType::*;
 
-        // If the next note is a drum roll, look ahead to find where it ends
-        let roll_time = if matches!(note_type, Roll | BigRoll | BalloonRoll(_) | SpecialRoll(_)) {
-            // Annoyingly, special rolls can be ended by other special rolls.
-            // So we have to do some extra logic.
-            let next = notes.peek().ok_or(TJAParseError {
+        // If the next note is a drum roll, the note after it is where it ends
+        let roll_time = if matches!(note_type, Roll | BigRoll | BalloonRoll(_) | Kusudama(_)) {
+            let (next_type, next_time, _, _) = notes.next().ok_or(TJAParseError {
                 kind: TJAParseErrorKind::RollNotEnded,
                 line: course_line_number,
             })?;
 
-            let next_time = if matches!(note_type, SpecialRoll(_)) {
-                let next_type = next.0;
-
-                if matches!(next_type, SpecialRoll(_)) {
-                    next.1
-                } else if next_type == RollEnd {
-                    let time = next.1;
-                    let _ = next; // So that notes isn't borrowed and we can call the next line
-                    notes.next();
-                    time
-                } else {
-                    return Err(TJAParseError {
-                        kind: TJAParseErrorKind::RollNotEnded,
-                        line: course_line_number,
-                    });
-                }
-            } else {
-                let (next_type, next_time, _) = notes.next().ok_or(TJAParseError {
+            if next_type != RollEnd {
+                return Err(TJAParseError {
                     kind: TJAParseErrorKind::RollNotEnded,
                     line: course_line_number,
-                })?;
-
-                if next_type != RollEnd {
-                    return Err(TJAParseError {
-                        kind: TJAParseErrorKind::RollNotEnded,
-                        line: course_line_number,
-                    });
-                }
-
-                next_time
-            };
+                });
+            }
 
             Some(next_time - time)
         } else {
@@ -701,7 +1295,7 @@ fn construct_difficulty(
             TJANoteType::Roll => NoteType::Roll(roll_time.unwrap()),
             TJANoteType::BigRoll => NoteType::BigRoll(roll_time.unwrap()),
             TJANoteType::BalloonRoll(n) => NoteType::BalloonRoll(roll_time.unwrap(), n),
-            TJANoteType::SpecialRoll(n) => NoteType::SpecialRoll(roll_time.unwrap(), n),
+            TJANoteType::Kusudama(n) => NoteType::Kusudama(roll_time.unwrap(), n),
             TJANoteType::CoopDon => NoteType::CoopDon,
             TJANoteType::CoopKat => NoteType::CoopKat,
             TJANoteType::RollEnd => {
@@ -716,16 +1310,349 @@ fn construct_difficulty(
             note_type,
             time,
             scroll_speed,
+            gogo,
         });
     }
 
     chart.notes = track_notes;
     chart.notes.shrink_to_fit();
-
-    let star_level = get_parsed_metadata::<u8>(metadata, "LEVEL", None, Some(course_line_number))?;
     chart.barlines = barlines;
+    chart.timing = timing;
+    chart.end_time = time;
+
+    Ok((chart, branch_spans))
+}
+
+fn construct_difficulty(
+    mut items: Vec<CourseItem<'_>>,
+    metadata: &HashMap<Cow<str>, (usize, &str)>,
+    course_line_number: usize,
+    options: ParseOptions,
+    warnings: &mut Vec<TJAParseError>,
+) -> Result<Difficulty, TJAParseError> {
+    assign_balloon_hits(
+        &mut items,
+        metadata,
+        course_line_number,
+        options.lenient,
+        warnings,
+    )?;
+    let segments = split_branches(items, course_line_number)?;
+
+    let conditions: Vec<BranchCondition> = segments
+        .iter()
+        .filter_map(|segment| match segment {
+            CourseSegment::Branched { condition, .. } => Some(*condition),
+            CourseSegment::Shared(_) => None,
+        })
+        .collect();
+
+    let mut chart = if conditions.is_empty() {
+        construct_chart(
+            branch_path(&segments, MASTER_BRANCH),
+            metadata,
+            course_line_number,
+            options.max_subdivisions,
+        )?
+        .0
+    } else {
+        // Each branch is worked out separately, as if the player took it every time
+        let paths = (0..3)
+            .map(|branch| {
+                construct_chart(
+                    branch_path(&segments, branch),
+                    metadata,
+                    course_line_number,
+                    options.max_subdivisions,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let branches = conditions
+            .into_iter()
+            .enumerate()
+            .map(|(i, condition)| {
+                let [start, end] = paths[MASTER_BRANCH].1[i];
+                let notes = std::array::from_fn(|branch| {
+                    let (chart, spans) = &paths[branch];
+                    let [start, end] = spans[i];
+                    chart
+                        .notes
+                        .iter()
+                        .filter(|note| (start..end).contains(&note.time))
+                        .cloned()
+                        .collect()
+                });
+
+                BranchSection {
+                    condition,
+                    start,
+                    end,
+                    notes,
+                }
+            })
+            .collect();
+
+        let (mut chart, _) = paths.into_iter().nth(MASTER_BRANCH).unwrap();
+        chart.branches = branches;
+        chart
+    };
+
+    let mut difficulty = course_metadata(metadata)?;
+    fill_missing_barlines(&mut chart);
+    difficulty.estimated_level = Some(estimate_difficulty(&chart).round() as u8);
+    difficulty.chart = chart;
+
+    Ok(difficulty)
+}
+
+/// Reads a course's metadata into a [Difficulty], leaving its chart empty.
+fn course_metadata(
+    metadata: &HashMap<Cow<str>, (usize, &str)>,
+) -> Result<Difficulty, TJAParseError> {
+    let star_level = metadata
+        .get("LEVEL")
+        .map(|&(line, level)| {
+            level.parse::<StarLevel>().map_err(|_| TJAParseError {
+                kind: TJAParseErrorKind::InvalidMetadata,
+                line,
+            })
+        })
+        .transpose()?;
+
+    Ok(Difficulty {
+        star_level,
+        estimated_level: None,
+        chart: NoteChart::default(),
+        player_charts: [None, None],
+        audio_filename: None,
+        demostart: None,
+        score_init: score_metadata(metadata, "SCOREINIT")?,
+        score_diff: score_metadata(metadata, "SCOREDIFF")?,
+        notes_designer: None,
+    })
+}
+
+/// Skips over the lines of a course up to its `#END`, without reading them.
+fn skip_course<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    course_line_number: usize,
+) -> Result<(), TJAParseError> {
+    let mut line_num = course_line_number;
+
+    for (i, line) in lines {
+        line_num = i;
+
+        if parse(end_command)(line).is_ok() {
+            return Ok(());
+        }
+    }
+
+    Err(TJAParseError {
+        kind: TJAParseErrorKind::ExpectedEndCommand,
+        line: line_num,
+    })
+}
+
+/// Reads a `SCOREINIT` or `SCOREDIFF` value. Empty values are treated as missing, and only the
+/// first of a list of values is used (some charts add a second one for other scoring modes).
+fn score_metadata(
+    metadata: &HashMap<Cow<str>, (usize, &str)>,
+    key: &str,
+) -> Result<Option<u32>, TJAParseError> {
+    let Some(&(line, value)) = metadata.get(key) else {
+        return Ok(None);
+    };
+
+    let value = value.split(',').next().unwrap_or_default().trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    value.parse().map(Some).map_err(|_| TJAParseError {
+        kind: TJAParseErrorKind::InvalidMetadata,
+        line,
+    })
+}
+
+/// Records that measures last a different length (or scroll at a different speed) from the given
+/// time. A change at the same time as the last one replaces it.
+pub(super) fn push_timing_point(
+    timing: &mut Vec<TimingPoint>,
+    time: f32,
+    seconds_per_measure: f32,
+    scroll_speed: f32,
+    bpm: f32,
+) {
+    if timing.last().is_some_and(|last| last.time == time) {
+        timing.pop();
+    }
+
+    timing.push(TimingPoint {
+        time,
+        seconds_per_measure,
+        scroll_speed,
+        bpm,
+    });
+}
 
-    Ok(Difficulty { star_level, chart })
+/// The difficulty a course is for if it doesn't say (Oni).
+pub const DEFAULT_COURSE: usize = 3;
+
+/// Returns the index of the difficulty named by the value of a `COURSE` metadata line.
+///
+/// Courses can be given by number, by name (in any case, with Ura as another name for Edit) or by
+/// their Japanese name.
+pub fn course_index(course: &str) -> Option<usize> {
+    match course.to_ascii_lowercase().as_str() {
+        "easy" | "0" | "かんたん" => Some(0),
+        "normal" | "1" | "ふつう" => Some(1),
+        "hard" | "2" | "むずかしい" => Some(2),
+        "oni" | "3" | "おに" => Some(3),
+        "edit" | "ura" | "4" | "裏" | "おに裏" => Some(4),
+        _ => None,
+    }
+}
+
+/// The index used for the dan course (`COURSE:Dan` or `COURSE:6`), which isn't one of the
+/// difficulties.
+pub const DAN_COURSE: usize = 6;
+
+/// Returns whether the value of a `COURSE` metadata line is for a dan course.
+pub fn is_dan_course(course: &str) -> bool {
+    matches!(course.to_ascii_lowercase().as_str(), "dan" | "6" | "段位")
+}
+
+/// Parses the value of an `EXAM1`, `EXAM2`... line: `condition,red,gold,scope`.
+fn exam_requirement(value: &str) -> Option<ExamRequirement> {
+    let parts: Vec<&str> = value.split(',').map(str::trim).collect();
+    let [condition, red, gold, scope] = parts[..] else {
+        return None;
+    };
+
+    let condition = match condition {
+        "g" => ExamCondition::Gauge,
+        "jp" => ExamCondition::Goods,
+        "jg" => ExamCondition::Oks,
+        "jb" => ExamCondition::Bads,
+        "s" => ExamCondition::Score,
+        "r" => ExamCondition::Rolls,
+        "h" => ExamCondition::Hits,
+        "c" => ExamCondition::Combo,
+        _ => return None,
+    };
+    let scope = match scope {
+        "m" => ExamScope::AtLeast,
+        "l" => ExamScope::LessThan,
+        _ => return None,
+    };
+
+    Some(ExamRequirement {
+        condition,
+        red: red.parse().ok()?,
+        gold: gold.parse().ok()?,
+        scope,
+    })
+}
+
+/// Reads the `EXAM1`, `EXAM2`... lines, in order. Empty ones are left out.
+fn exams(
+    metadata: &HashMap<Cow<str>, (usize, &str)>,
+) -> Result<Vec<ExamRequirement>, TJAParseError> {
+    let mut exams: Vec<(u32, usize, &str)> = metadata
+        .iter()
+        .filter_map(|(key, &(line, value))| {
+            let number = key.strip_prefix("EXAM")?.parse().ok()?;
+            Some((number, line, value))
+        })
+        .filter(|(_, _, value)| !value.is_empty())
+        .collect();
+    exams.sort_unstable_by_key(|&(number, _, _)| number);
+
+    exams
+        .into_iter()
+        .map(|(_, line, value)| {
+            exam_requirement(value).ok_or(TJAParseError {
+                kind: TJAParseErrorKind::InvalidMetadata,
+                line,
+            })
+        })
+        .collect()
+}
+
+/// Metadata keys that can be set for a single course, overriding the value for the whole song.
+pub(crate) const COURSE_OVERRIDABLE_KEYS: [&str; 2] = ["WAVE", "DEMOSTART"];
+
+/// The loudest `SONGVOL` or `SEVOL` can make a sound, as a percentage.
+const MAX_VOLUME: f32 = 200.0;
+
+/// The most notes a measure can be split into, unless [ParseOptions] says otherwise.
+pub const DEFAULT_MAX_SUBDIVISIONS: usize = 512;
+
+/// How many hits a balloon takes when the `BALLOON` list doesn't say.
+const DEFAULT_BALLOON_HITS: u32 = 5;
+
+/// Metadata keys that are given for each course. A value given before the first course is used for
+/// any course that doesn't give its own.
+const COURSE_KEYS: [&str; 4] = ["LEVEL", "BALLOON", "SCOREINIT", "SCOREDIFF"];
+
+/// Returns the value of the given key if it is different for this course than it is for the rest
+/// of the song.
+fn course_override<'a>(
+    metadata: &HashMap<Cow<'a, str>, (usize, &'a str)>,
+    song_metadata: &HashMap<Cow<'a, str>, (usize, &'a str)>,
+    key: &str,
+) -> Option<(usize, &'a str)> {
+    let value = metadata.get(key)?;
+    (song_metadata.get(key) != Some(value)).then_some(*value)
+}
+
+/// Finds where the `//` comment at the end of a line starts, if it has one.
+///
+/// Comments can come after note data and commands as well as on their own line. In a metadata
+/// line, the comment has to come after a space, so that URLs and titles with slashes in them are
+/// kept whole.
+pub(crate) fn comment_start(line: &str) -> Option<usize> {
+    let is_metadata = !line
+        .trim_start()
+        .starts_with(|c: char| c == '#' || c == ',' || c.is_ascii_digit());
+
+    line.match_indices("//")
+        .map(|(i, _)| i)
+        .find(|&i| !is_metadata || i == 0 || line[..i].ends_with(char::is_whitespace))
+}
+
+/// Splits a file into lines, whether they end in `\r\n`, `\n` or (as some older editors save them)
+/// a bare `\r`.
+fn split_lines(input: &str) -> impl Iterator<Item = &str> {
+    input
+        .split("\r\n")
+        .flat_map(|line| line.split(['\r', '\n']))
+}
+
+/// Options for things the parser can be more lenient about than the format strictly allows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParseOptions {
+    /// Whether to treat comments on their own line between measures (e.g. `// chorus`) as section
+    /// labels. These are just comments as far as the format is concerned, so this is off by
+    /// default.
+    pub comment_labels: bool,
+    /// Whether to skip over unknown commands and lines that aren't metadata instead of failing.
+    /// Whatever is skipped is kept in [Song::warnings].
+    pub lenient: bool,
+    /// The most notes (counting the blank ones) a measure can be split into. Charts that go past
+    /// this are an error, even when parsing leniently.
+    pub max_subdivisions: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            comment_labels: false,
+            lenient: false,
+            max_subdivisions: DEFAULT_MAX_SUBDIVISIONS,
+        }
+    }
 }
 
 /// Parses a TJA file into a [Song] struct.
@@ -734,15 +1661,37 @@ fn construct_difficulty(
 /// but it does require that the TJA file is. See [TJAParseErrorKind] to see the errors that
 /// can be encountered while parsing.
 pub fn parse_tja_file(input: &str) -> Result<Song, TJAParseError> {
+    parse_tja_file_with_options(input, ParseOptions::default())
+}
+
+/// Parses a TJA file into a [Song] struct, with the given [ParseOptions].
+pub fn parse_tja_file_with_options(
+    input: &str,
+    options: ParseOptions,
+) -> Result<Song, TJAParseError> {
+    parse_tja(input, options, true)
+}
+
+/// Parses only the metadata of a TJA file, which is all the song list needs.
+///
+/// The notes in each course are skipped over without being read, so the difficulties' charts are
+/// left empty and their levels aren't estimated. Problems with the notes won't be found until the
+/// file is parsed with [parse_tja_file_with_options].
+pub fn parse_tja_metadata(input: &str, options: ParseOptions) -> Result<Song, TJAParseError> {
+    parse_tja(input, options, false)
+}
+
+fn parse_tja(input: &str, options: ParseOptions, read_notes: bool) -> Result<Song, TJAParseError> {
     // Preprocess lines (get rid of comments, empty lines, extra space etc)
-    let mut lines = input.lines().enumerate().filter_map(|(i, line)| {
+    let mut lines = split_lines(input).enumerate().filter_map(|(i, line)| {
         // This seems to be necessary as a lot of tja files have the utf-16 alignment character at
         // the beginning. But as far as i'm aware, are not utf-16? If there's a satisfying
         // conclusion to this problem, I would love to know it.
         let mut line = line.strip_prefix('\u{feff}').unwrap_or(line);
 
-        // Remove comments
-        if let Some(i) = line.find("//") {
+        // Remove comments, unless the whole line is one and we're keeping those
+        let keep_comment = options.comment_labels && line.trim_start().starts_with("//");
+        if let Some(i) = comment_start(line).filter(|_| !keep_comment) {
             line = &line[0..i];
         }
 
@@ -756,54 +1705,130 @@ pub fn parse_tja_file(input: &str) -> Result<Song, TJAParseError> {
     });
 
     let mut metadata = HashMap::new();
+    // The metadata for the song as a whole, i.e. everything declared before the first course.
+    // Used to figure out which values a course has overridden.
+    let mut song_metadata: Option<HashMap<Cow<str>, (usize, &str)>> = None;
     let mut difficulties: [Option<Difficulty>; 5] = [None, None, None, None, None];
+    let mut dan = None;
+    // The courses that have a track for playing alone, as well as (or instead of) one for each
+    // player
+    let mut single_tracks = HashSet::new();
+    let mut warnings = Vec::new();
+    // Set by a command before the next `#START`, for that course only
+    let mut scroll_mode = ScrollMode::Normal;
 
     while let Some((i, line)) = lines.next() {
+        // Comments outside of a course don't label anything
+        if line.starts_with("//") {
+            continue;
+        }
+
         if let Ok((key, value)) = parse(metadata_pair)(line) {
+            if key == "COURSE" && song_metadata.is_none() {
+                song_metadata = Some(metadata.clone());
+            }
+
             metadata.insert(key, (i, value));
+        } else if let Some(mode) = scroll_mode_command(line) {
+            scroll_mode = mode;
         } else {
             match parse(start_command)(line) {
                 Ok(player) => {
-                    // TODO: actually deal with the player argument lol
-                    if player.is_some() {
-                        unimplemented!()
-                    }
-
                     let difficulty_level = match metadata.get("COURSE") {
-                        Some(&(line, course)) => match course {
-                            "Easy" | "0" => 0,
-                            "Normal" | "1" => 1,
-                            "Hard" | "2" => 2,
-                            "Oni" | "3" => 3,
-                            "Edit" | "4" => 4,
-                            _ => {
-                                return Err(TJAParseError {
-                                    kind: TJAParseErrorKind::InvalidMetadata,
-                                    line,
-                                })
-                            }
-                        },
+                        Some(&(_, course)) if is_dan_course(course) => DAN_COURSE,
+                        Some(&(line, course)) => course_index(course).ok_or(TJAParseError {
+                            kind: TJAParseErrorKind::UnknownCourse(course.to_string()),
+                            line,
+                        })?,
 
                         // Default difficulty is oni
-                        None => 3,
+                        None => DEFAULT_COURSE,
                     };
-
-                    // If there is already a course for this difficulty, thats an error
-                    if difficulties[difficulty_level].is_some() {
+                    let slot = difficulties.get_mut(difficulty_level).unwrap_or(&mut dan);
+
+                    // A course can have one track for playing alone and one for each player, but
+                    // only one of each
+                    let taken = match (player, slot.as_ref()) {
+                        (_, None) => false,
+                        (None, Some(_)) => single_tracks.contains(&difficulty_level),
+                        (Some(player), Some(course)) => {
+                            course.player_charts[player.index()].is_some()
+                        }
+                    };
+                    if taken {
                         return Err(TJAParseError {
                             kind: TJAParseErrorKind::MultipleTracksSameDifficulty(difficulty_level),
                             line: i + 1,
                         });
                     }
 
-                    let items = process_course(&mut lines)?;
-                    let difficulty = construct_difficulty(items, &metadata, i + 1)?;
-                    difficulties[difficulty_level] = Some(difficulty);
+                    // A level that can't be read isn't worth losing the course over
+                    if let Some(&(line, level)) = metadata.get("LEVEL") {
+                        if options.lenient && level.parse::<StarLevel>().is_err() {
+                            warnings.push(TJAParseError {
+                                kind: TJAParseErrorKind::InvalidMetadata,
+                                line,
+                            });
+                            metadata.remove("LEVEL");
+                        }
+                    }
+
+                    let mut difficulty = if read_notes {
+                        let items = process_course(&mut lines, options.lenient, &mut warnings)?;
+                        construct_difficulty(items, &metadata, i + 1, options, &mut warnings)?
+                    } else {
+                        skip_course(&mut lines, i)?;
+                        course_metadata(&metadata)?
+                    };
+
+                    difficulty.chart.scroll_mode = std::mem::take(&mut scroll_mode);
+                    let song_metadata = song_metadata.get_or_insert_with(|| metadata.clone());
+
+                    difficulty.audio_filename = course_override(&metadata, song_metadata, "WAVE")
+                        .map(|(_, wave)| wave.to_string());
+                    difficulty.demostart = course_override(&metadata, song_metadata, "DEMOSTART")
+                        .map(|(line, demostart)| {
+                            demostart.parse::<f32>().map_err(|_| TJAParseError {
+                                kind: TJAParseErrorKind::InvalidMetadata,
+                                line,
+                            })
+                        })
+                        .transpose()?;
+
+                    // Overrides and course metadata only apply to the course they were declared
+                    // for, so go back to the song's values for the next one.
+                    for key in COURSE_OVERRIDABLE_KEYS.into_iter().chain(COURSE_KEYS) {
+                        match song_metadata.get(key) {
+                            Some(&value) => metadata.insert(Cow::Borrowed(key), value),
+                            None => metadata.remove(key),
+                        };
+                    }
+
+                    match (player, slot.as_mut()) {
+                        (None, _) => {
+                            single_tracks.insert(difficulty_level);
+
+                            // The players' tracks might have come first
+                            if let Some(course) = slot.take() {
+                                difficulty.player_charts = course.player_charts;
+                            }
+                            *slot = Some(difficulty);
+                        }
+                        (Some(player), Some(course)) => {
+                            course.player_charts[player.index()] = Some(difficulty.chart);
+                        }
+                        (Some(player), None) => {
+                            difficulty.player_charts[player.index()] =
+                                Some(difficulty.chart.clone());
+                            *slot = Some(difficulty);
+                        }
+                    }
                 }
 
                 // The reason we return the error that the start_command function returned, is that
                 // parse(metadata_pair) can only return a syntax error. So if it is a syntax error
                 // for both, it will be a syntax error for just parse(start_command).
+                Err(e) if options.lenient => warnings.push(TJAParseError { kind: e, line: i }),
                 Err(e) => return Err(TJAParseError { kind: e, line: i }),
             }
         }
@@ -811,20 +1836,90 @@ pub fn parse_tja_file(input: &str) -> Result<Song, TJAParseError> {
 
     // Now get the rest of the metadata needed for the song.
     let title = get_metadata_owned(&metadata, "TITLE", None, None)?;
-    let subtitle = get_metadata_owned(&metadata, "SUBTITLE", None, None).ok();
-    let audio_filename = get_metadata_owned(&metadata, "WAVE", None, None)?;
+    let title_en = get_metadata_owned(&metadata, "TITLEEN", None, None).ok();
+    let (subtitle, show_subtitle) = get_metadata_owned(&metadata, "SUBTITLE", None, None)
+        .map_or((None, true), |subtitle| split_subtitle(&subtitle));
+    let genre = get_metadata_owned(&metadata, "GENRE", None, None).ok();
+    // If the audio is only given per-course, treat the first one as the song's audio.
+    let audio_filename = get_metadata_owned(&metadata, "WAVE", None, None).or_else(|e| {
+        difficulties
+            .iter()
+            .chain([&dan])
+            .flatten()
+            .find_map(|d| d.audio_filename.clone())
+            .ok_or(e)
+    })?;
+
+    for difficulty in difficulties.iter_mut().chain([&mut dan]).flatten() {
+        if difficulty.audio_filename.as_ref() == Some(&audio_filename) {
+            difficulty.audio_filename = None;
+        }
+    }
+
+    let maker = get_metadata_owned(&metadata, "MAKER", None, None)
+        .ok()
+        .filter(|maker| !maker.is_empty());
+
+    // NOTESDESIGNER0 to NOTESDESIGNER4 credit each difficulty, numbered the same as the courses
+    for (key, &(line, designer)) in &metadata {
+        let Some(index) = key.strip_prefix("NOTESDESIGNER") else {
+            continue;
+        };
+
+        let difficulty = index
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| difficulties.get_mut(i)?.as_mut());
+
+        match difficulty {
+            Some(difficulty) => {
+                difficulty.notes_designer = Some(designer.to_string()).filter(|d| !d.is_empty());
+            }
+            None => log::warn!("{key} (at line {}) isn't for any course", line + 1),
+        }
+    }
+
     let demostart = get_parsed_metadata::<f32>(&metadata, "DEMOSTART", Some(0.0), None)?;
     let offset = get_parsed_metadata::<f32>(&metadata, "OFFSET", Some(0.0), None)?;
     let bpm = get_parsed_metadata::<f32>(&metadata, "BPM", Some(120.0), None)?;
+    let song_volume =
+        get_parsed_metadata::<f32>(&metadata, "SONGVOL", Some(100.0), None)?.clamp(0.0, MAX_VOLUME);
+    let se_volume =
+        get_parsed_metadata::<f32>(&metadata, "SEVOL", Some(100.0), None)?.clamp(0.0, MAX_VOLUME);
+    let lyrics_file = get_metadata_owned(&metadata, "LYRICS", None, None)
+        .ok()
+        .filter(|lyrics| !lyrics.is_empty());
+    let bgmovie = get_metadata_owned(&metadata, "BGMOVIE", None, None)
+        .ok()
+        .filter(|movie| !movie.is_empty());
+    let movie_offset_key = if metadata.contains_key("MOVIEOFFSET") {
+        "MOVIEOFFSET"
+    } else {
+        "BGOFFSET"
+    };
+    let movie_offset = get_parsed_metadata::<f32>(&metadata, movie_offset_key, Some(0.0), None)?;
+    let exams = exams(&metadata)?;
 
     Ok(Song {
         title,
+        title_en,
         subtitle,
+        show_subtitle,
+        genre,
+        maker,
         audio_filename,
         demostart,
+        song_volume,
+        se_volume,
         bpm,
         offset,
+        bgmovie,
+        movie_offset,
+        lyrics_file,
         difficulties,
+        dan,
+        exams,
+        warnings,
     })
 }
 
@@ -843,6 +1938,19 @@ mod test {
             parse(course_command)("#MEASURE 9/8"),
             Ok(CourseCommand::Measure(9, 8))
         );
+
+        assert_eq!(
+            parse(course_command)("#BRANCHSTART p, 80.5, 90"),
+            Ok(CourseCommand::BranchStart(BranchCondition {
+                requirement: BranchRequirement::Accuracy,
+                expert: 80.5,
+                master: 90.0,
+            }))
+        );
+        assert_eq!(parse(course_command)("#M"), Ok(CourseCommand::BranchMaster));
+        assert!(parse(course_command)("#BRANCHSTART x,1,2").is_err());
+        assert!(parse(course_command)("#BRANCHSTART r,1").is_err());
+        assert!(parse(course_command)("#N 1").is_err());
     }
 
     #[test]
@@ -865,6 +1973,58 @@ mod test {
             })
         );
     }
+    #[test]
+    fn test_metadata_tagname() {
+        assert_eq!(parse(metadata_tagname)("TITLE"), Ok(Cow::Borrowed("TITLE")));
+        assert_eq!(
+            parse(metadata_tagname)("ＴＩＴＬＥ"),
+            Ok(Cow::Owned("TITLE".to_string()))
+        );
+        assert_eq!(parse(metadata_tagname)("Wave"), Ok(Cow::Borrowed("WAVE")));
+        assert_eq!(parse(metadata_tagname)("ＢＰＭ"), Ok(Cow::Borrowed("BPM")));
+
+        assert_eq!(
+            parse(metadata_tagname)("NOTESDESIGNER3"),
+            Ok(Cow::Borrowed("NOTESDESIGNER3"))
+        );
+        assert_eq!(
+            parse(metadata_tagname)("Exam１"),
+            Ok(Cow::Borrowed("EXAM1"))
+        );
+
+        assert!(parse(metadata_tagname)("").is_err());
+        assert!(parse(metadata_tagname)("1EXAM").is_err());
+        assert!(parse(metadata_tagname)("EXAM1A").is_err());
+        assert!(parse(metadata_tagname)("ＴＩＴＬＥ：").is_err());
+    }
+
+    #[test]
+    fn test_metadata_pair() {
+        let pair =
+            |input| parse(metadata_pair)(input).map(|(key, value)| (key.into_owned(), value));
+        let expected = Ok(("TITLE".to_string(), "Angel Dream"));
+
+        assert_eq!(pair("TITLE:Angel Dream"), expected);
+        assert_eq!(pair("ＴＩＴＬＥ：Angel Dream"), expected);
+        assert_eq!(pair("TITLE：Angel Dream"), expected);
+        assert_eq!(pair("ＴＩＴＬＥ:Angel Dream"), expected);
+
+        // Junk around the value goes, but the spacing inside it stays
+        assert_eq!(pair("TITLE:\u{3000}Angel Dream\u{3000}"), expected);
+        assert_eq!(pair("TITLE:\u{200B}Angel Dream\u{FEFF}"), expected);
+        assert_eq!(pair("TITLE: Angel Dream\t\u{1A}"), expected);
+        assert_eq!(
+            pair("TITLE:Angel\u{3000}\u{3000}Dream"),
+            Ok(("TITLE".to_string(), "Angel\u{3000}\u{3000}Dream"))
+        );
+
+        assert_eq!(pair("WAVE:"), Ok(("WAVE".to_string(), "")));
+        assert_eq!(pair("WAVE：\u{3000}"), Ok(("WAVE".to_string(), "")));
+
+        assert!(pair("TITLE Angel Dream").is_err());
+        assert!(pair("#START").is_err());
+    }
+
     #[test]
     fn test_course_item() {
         assert_eq!(
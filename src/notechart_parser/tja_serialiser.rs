@@ -0,0 +1,581 @@
+//! Writes a [Song] out as a TJA file, working from its parsed notes rather than its original text.
+//!
+//! This is for charts that have been changed after they were read (e.g. moved to a new offset), so
+//! everything is written from what the parser keeps: the metadata, and each course's notes, timing,
+//! gogo time, barlines, sections, lyrics and scroll mode. Each measure is split as finely as its
+//! notes need.
+//!
+//! Some things aren't kept in enough detail to be written back exactly as they were:
+//!
+//! - Time signatures are only known at the start of a course. A later `#MEASURE` comes out as the
+//!   `#BPMCHANGE` that makes measures last just as long, so the notes stay where they were.
+//! - Only the master branch of a branching chart is written.
+//! - Only player 1's track is written for courses that have a track for each player.
+//! - Dan courses are left out.
+//! - A drum roll that ends right where another note starts (which osu beatmaps can have) ends a
+//!   48th of a measure early, since the two can't be written in the same place.
+//!
+//! To change a song's metadata without touching the rest of its file, use
+//! [write_metadata_edits](super::write_metadata_edits) instead.
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use super::{Difficulty, NoteChart, NoteType, ScrollMode, Song, DEFAULT_BPM};
+
+/// The names courses are written with, indexed by difficulty.
+const COURSE_NAMES: [&str; 5] = ["Easy", "Normal", "Hard", "Oni", "Edit"];
+
+/// The most notes a measure is split into. Measures that would need more have their notes moved
+/// to the nearest of these.
+const MAX_SUBDIVISION: usize = 960;
+
+/// How far (as a fraction of a measure) something can be from a subdivision and still be on it.
+/// This is far finer than any subdivision charts actually use, but leaves room for float error.
+const POSITION_TOLERANCE: f64 = 2e-4;
+
+/// How far (as a fraction of a measure) a drum roll is ended early when another note starts right
+/// where it ends, since they can't both be written in the same place.
+const ROLL_END_NUDGE: f64 = 1.0 / 48.0;
+
+/// How close together (in seconds) two times can be and still be the same.
+const TIME_TOLERANCE: f64 = 1e-4;
+
+/// How different two timing values can be (relative to their size) and still be the same.
+const VALUE_TOLERANCE: f64 = 1e-5;
+
+/// The denominators tried, in order, when writing the time signature a course starts with.
+const SIGNATURE_DENOMINATORS: [u32; 4] = [4, 8, 16, 32];
+
+/// Whether two timing values are the same, give or take float error.
+fn same(a: f64, b: f64) -> bool {
+    (a - b).abs() <= VALUE_TOLERANCE * a.abs().max(b.abs()).max(1.0)
+}
+
+/// Formats a number without the float error that builds up in timing calculations, e.g. 160
+/// rather than 159.99998. Numbers are only read back as f32s, so that's all the precision kept.
+fn number(value: f64) -> String {
+    let rounded = (value * 1000.0).round() / 1000.0;
+
+    if same(rounded, value) {
+        rounded.to_string()
+    } else {
+        (value as f32).to_string()
+    }
+}
+
+/// Writes a time signature (as numerator divided by denominator) as a `#MEASURE` argument, if it
+/// can be written with one of the usual denominators.
+fn time_signature(signature: f64) -> Option<(u32, u32)> {
+    SIGNATURE_DENOMINATORS.into_iter().find_map(|denominator| {
+        let numerator = signature * denominator as f64;
+        let rounded = numerator.round();
+
+        ((numerator - rounded).abs() < 1e-4 && (1.0..=255.0).contains(&rounded))
+            .then_some((rounded as u32, denominator))
+    })
+}
+
+/// The character a note is written as, and how long it lasts if it's a drum roll.
+fn note_char(note_type: NoteType) -> (char, Option<f32>) {
+    match note_type {
+        NoteType::Don => ('1', None),
+        NoteType::Kat => ('2', None),
+        NoteType::BigDon => ('3', None),
+        NoteType::BigKat => ('4', None),
+        NoteType::Roll(length) => ('5', Some(length)),
+        NoteType::BigRoll(length) => ('6', Some(length)),
+        NoteType::BalloonRoll(length, _) => ('7', Some(length)),
+        NoteType::Kusudama(length, _) => ('9', Some(length)),
+        NoteType::CoopDon => ('A', None),
+        NoteType::CoopKat => ('B', None),
+    }
+}
+
+/// A stretch of a chart between two timing points.
+struct Segment {
+    time: f64,
+    /// How many measures into the chart the segment starts.
+    measures: f64,
+    /// How long a measure lasts in this segment. This is infinite during a delay.
+    seconds_per_measure: f64,
+}
+
+/// Works out where things in a chart are, counted in measures from the start of the chart.
+struct Timeline {
+    segments: Vec<Segment>,
+}
+
+impl Timeline {
+    fn new(chart: &NoteChart) -> Self {
+        let mut measures = 0.0;
+        let mut segments = Vec::with_capacity(chart.timing.len());
+
+        for (i, point) in chart.timing.iter().enumerate() {
+            let time = point.time as f64;
+            let seconds_per_measure = point.seconds_per_measure as f64;
+            segments.push(Segment {
+                time,
+                measures,
+                seconds_per_measure,
+            });
+
+            if let Some(next) = chart.timing.get(i + 1) {
+                measures += measures_in(next.time as f64 - time, seconds_per_measure);
+            }
+        }
+
+        Self { segments }
+    }
+
+    /// Returns how many measures into the chart the given time is, and how many delays are over by
+    /// then. Something at the very end of a delay comes after it.
+    fn position(&self, time: f32) -> (f64, usize) {
+        let time = time as f64;
+        let i = self
+            .segments
+            .partition_point(|segment| segment.time <= time + TIME_TOLERANCE)
+            .saturating_sub(1);
+        let segment = &self.segments[i];
+        let delays = self.segments[..i]
+            .iter()
+            .filter(|segment| !segment.seconds_per_measure.is_finite())
+            .count();
+
+        (
+            segment.measures + measures_in(time - segment.time, segment.seconds_per_measure),
+            delays,
+        )
+    }
+
+    fn event(&self, time: f32, placement: Placement, item: TrackItem) -> TrackEvent {
+        let (position, delays) = self.position(time);
+        let measure = (position + POSITION_TOLERANCE).floor();
+
+        TrackEvent {
+            measure: measure as i64,
+            fraction: (position - measure).max(0.0),
+            delays,
+            placement,
+            item,
+        }
+    }
+}
+
+/// How many measures go by in the given number of seconds.
+fn measures_in(seconds: f64, seconds_per_measure: f64) -> f64 {
+    if seconds_per_measure.is_finite() && seconds_per_measure > 0.0 {
+        seconds / seconds_per_measure
+    } else {
+        0.0
+    }
+}
+
+/// Something written in a note track.
+enum TrackItem {
+    /// A command, which goes on a line of its own.
+    Command(String),
+    Note(char),
+}
+
+/// Where something goes among the other things at the same point in a measure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Placement {
+    BeforeNotes,
+    Note,
+    /// Delays start after the note they're on has been hit.
+    AfterNotes,
+}
+
+/// A [TrackItem] and where it goes in the track.
+struct TrackEvent {
+    measure: i64,
+    /// How far through the measure it is, from 0 to 1.
+    fraction: f64,
+    /// How many delays are over by the time of this event, to tell apart things either side of a
+    /// delay, which are at the same point in the measure.
+    delays: usize,
+    placement: Placement,
+    item: TrackItem,
+}
+
+/// The fewest notes a measure can be split into with every event still on one of them, or 0 if
+/// the measure is empty apart from commands at its start.
+fn subdivision(events: &[TrackEvent]) -> usize {
+    if events.iter().all(|event| {
+        matches!(event.item, TrackItem::Command(_)) && event.fraction <= POSITION_TOLERANCE
+    }) {
+        return 0;
+    }
+
+    let on_subdivision = |&subdivision: &usize| {
+        events.iter().all(|event| {
+            let ticks = event.fraction * subdivision as f64;
+            (ticks - ticks.round()).abs() <= POSITION_TOLERANCE * subdivision as f64
+        })
+    };
+
+    (1..=MAX_SUBDIVISION)
+        .find(on_subdivision)
+        .unwrap_or(MAX_SUBDIVISION)
+}
+
+/// Writes one measure of a note track.
+fn write_measure(output: &mut String, events: &mut [TrackEvent]) {
+    let subdivision = subdivision(events);
+    let tick = |event: &TrackEvent| {
+        let tick = (event.fraction * subdivision as f64).round() as usize;
+        tick.min(subdivision.saturating_sub(1))
+    };
+    events.sort_by_key(|event| (tick(event), event.delays, event.placement));
+
+    let mut line = String::new();
+    let mut filled = 0;
+
+    for event in events.iter() {
+        let tick = tick(event);
+        while filled < tick {
+            line.push('0');
+            filled += 1;
+        }
+
+        match &event.item {
+            TrackItem::Command(command) => {
+                if !line.is_empty() {
+                    writeln!(output, "{line}").unwrap();
+                    line.clear();
+                }
+                writeln!(output, "{command}").unwrap();
+            }
+            // Notes too close together to be told apart at the finest subdivision can't both be
+            // written, so only the first one is kept
+            TrackItem::Note(_) if filled > tick => {}
+            TrackItem::Note(note) => {
+                line.push(*note);
+                filled += 1;
+            }
+        }
+    }
+
+    while filled < subdivision {
+        line.push('0');
+        filled += 1;
+    }
+
+    writeln!(output, "{line},").unwrap();
+}
+
+/// Writes the commands and notes of a course, between its `#START` and `#END`. `bpm` and
+/// `signature` are what the course starts with.
+fn write_track(output: &mut String, chart: &NoteChart, mut bpm: f64, signature: f64) {
+    let timeline = Timeline::new(chart);
+    let mut events = Vec::new();
+    let command = |time: f32, placement, command: String| {
+        timeline.event(time, placement, TrackItem::Command(command))
+    };
+
+    // The course starts out with the measure length its header gives it, and the default scroll
+    // speed (since HEADSCROLL is never written)
+    let mut seconds_per_measure = 240.0 * signature / bpm;
+    let mut scroll = 1.0;
+
+    for (i, point) in chart.timing.iter().enumerate() {
+        if !point.seconds_per_measure.is_finite() {
+            if let Some(next) = chart.timing.get(i + 1) {
+                let delay = number((next.time - point.time) as f64);
+                events.push(command(
+                    point.time,
+                    Placement::AfterNotes,
+                    format!("#DELAY {delay}"),
+                ));
+            }
+            continue;
+        }
+
+        if !same(point.seconds_per_measure as f64, seconds_per_measure) {
+            seconds_per_measure = point.seconds_per_measure as f64;
+            bpm = 240.0 * signature / seconds_per_measure;
+            let change = format!("#BPMCHANGE {}", number(bpm));
+            events.push(command(point.time, Placement::BeforeNotes, change));
+        }
+
+        // Scroll speeds are kept with the BPM already taken into account
+        let point_scroll = point.scroll_speed as f64 * DEFAULT_BPM as f64 / bpm;
+        if !same(point_scroll, scroll) {
+            scroll = point_scroll;
+            let change = format!("#SCROLL {}", number(scroll));
+            events.push(command(point.time, Placement::BeforeNotes, change));
+        }
+    }
+
+    for gogo in &chart.gogo_times {
+        events.push(command(
+            gogo.start,
+            Placement::BeforeNotes,
+            "#GOGOSTART".into(),
+        ));
+        events.push(command(gogo.end, Placement::BeforeNotes, "#GOGOEND".into()));
+    }
+
+    for section in &chart.sections {
+        let label = match &section.name {
+            Some(name) => format!("#SECTION {name}"),
+            None => "#SECTION".to_string(),
+        };
+        events.push(command(section.time, Placement::BeforeNotes, label));
+    }
+
+    for lyric in &chart.lyrics {
+        let text = lyric.text.replace('\n', "\\n");
+        events.push(command(
+            lyric.time,
+            Placement::BeforeNotes,
+            format!("#LYRIC {text}"),
+        ));
+    }
+
+    for bga in &chart.bga_events {
+        let bga_command = if bga.visible { "#BGAON" } else { "#BGAOFF" };
+        events.push(command(
+            bga.time,
+            Placement::BeforeNotes,
+            bga_command.into(),
+        ));
+    }
+
+    for note in &chart.notes {
+        let (note_char, roll_length) = note_char(note.note_type);
+        events.push(timeline.event(note.time, Placement::Note, TrackItem::Note(note_char)));
+
+        if let Some(length) = roll_length {
+            let end_time = note.time + length;
+            let mut end = timeline.event(end_time, Placement::Note, TrackItem::Note('8'));
+
+            let note_at_end = (chart.notes.iter())
+                .any(|other| ((other.time - end_time) as f64).abs() <= TIME_TOLERANCE);
+            if note_at_end {
+                end.fraction -= ROLL_END_NUDGE;
+                if end.fraction < 0.0 {
+                    end.measure -= 1;
+                    end.fraction += 1.0;
+                }
+            }
+
+            events.push(end);
+        }
+    }
+
+    // The measure a barline ends. The one at the start of the chart is always there.
+    let barlines: HashSet<i64> = chart
+        .barlines
+        .iter()
+        .filter_map(|barline| {
+            let (position, _) = timeline.position(barline.time);
+            let measure = position.round();
+            ((position - measure).abs() <= POSITION_TOLERANCE).then_some(measure as i64 - 1)
+        })
+        .collect();
+
+    // Every measure up to the end of the chart is written, even if it's empty
+    let (end, _) = timeline.position(chart.end_time);
+    let last_note = events
+        .iter()
+        .filter(|event| matches!(event.item, TrackItem::Note(_)))
+        .map(|event| event.measure + 1)
+        .max()
+        .unwrap_or(0);
+    let measure_count = ((end - POSITION_TOLERANCE).ceil() as i64).max(last_note);
+
+    let mut measures: Vec<Vec<TrackEvent>> = (0..=measure_count).map(|_| Vec::new()).collect();
+    for event in events {
+        let measure = event.measure.clamp(0, measure_count);
+        measures[measure as usize].push(event);
+    }
+
+    // Anything after the last measure (like gogo time that lasts until the end) is only commands
+    let mut after_end = measures.pop().unwrap_or_default();
+    let mut barline_on = true;
+
+    for (i, events) in measures.iter_mut().enumerate() {
+        if barlines.contains(&(i as i64)) != barline_on {
+            barline_on = !barline_on;
+            let barline = if barline_on {
+                "#BARLINEON"
+            } else {
+                "#BARLINEOFF"
+            };
+            writeln!(output, "{barline}").unwrap();
+        }
+
+        write_measure(output, events);
+    }
+
+    after_end.sort_by(|a, b| {
+        (a.delays, a.placement)
+            .cmp(&(b.delays, b.placement))
+            .then(a.fraction.total_cmp(&b.fraction))
+    });
+    for event in after_end {
+        if let TrackItem::Command(command) = event.item {
+            writeln!(output, "{command}").unwrap();
+        }
+    }
+}
+
+/// Writes a course. `offset` is the offset it starts with unless it gives its own, which then
+/// carries on to the courses after it (the parser only goes back to the song's values for the
+/// metadata that's meant to be per course).
+fn write_course(
+    output: &mut String,
+    offset: &mut f64,
+    bpm: f64,
+    index: usize,
+    difficulty: &Difficulty,
+) {
+    let chart = &difficulty.chart;
+    writeln!(output, "COURSE:{}", COURSE_NAMES[index]).unwrap();
+
+    if let Some(level) = difficulty.star_level {
+        writeln!(output, "LEVEL:{level}").unwrap();
+    }
+
+    let balloons: Vec<String> = chart
+        .notes
+        .iter()
+        .filter_map(|note| match note.note_type {
+            NoteType::BalloonRoll(_, hits) | NoteType::Kusudama(_, hits) => Some(hits.to_string()),
+            _ => None,
+        })
+        .collect();
+    if !balloons.is_empty() {
+        writeln!(output, "BALLOON:{}", balloons.join(",")).unwrap();
+    }
+
+    if let Some(score_init) = difficulty.score_init {
+        writeln!(output, "SCOREINIT:{score_init}").unwrap();
+    }
+    if let Some(score_diff) = difficulty.score_diff {
+        writeln!(output, "SCOREDIFF:{score_diff}").unwrap();
+    }
+    if let Some(audio_filename) = &difficulty.audio_filename {
+        writeln!(output, "WAVE:{audio_filename}").unwrap();
+    }
+    if let Some(demostart) = difficulty.demostart {
+        writeln!(output, "DEMOSTART:{}", number(demostart as f64)).unwrap();
+    }
+
+    let start = chart
+        .timing
+        .iter()
+        .find(|point| point.seconds_per_measure.is_finite());
+
+    if let Some(first) = chart.timing.first() {
+        let course_offset = -first.time as f64;
+        if !same(course_offset, *offset) {
+            *offset = course_offset;
+            writeln!(output, "OFFSET:{}", number(course_offset)).unwrap();
+        }
+    }
+
+    // If the measures at the start don't last as long as the song's BPM says they should, it's
+    // either a different time signature or a different BPM. A BPM (unlike a time signature) can't
+    // be given in the course's header without changing it for every course after, so that's
+    // changed at the start of the track instead.
+    let measure = start.and_then(|start| {
+        let signature = start.seconds_per_measure as f64 * bpm / 240.0;
+        (!same(signature, 1.0))
+            .then(|| time_signature(signature))
+            .flatten()
+    });
+
+    writeln!(output).unwrap();
+    match chart.scroll_mode {
+        ScrollMode::Normal => {}
+        ScrollMode::Bms => writeln!(output, "#BMSCROLL").unwrap(),
+        ScrollMode::Hbs => writeln!(output, "#HBSCROLL").unwrap(),
+    }
+    writeln!(output, "#START").unwrap();
+    if let Some((numerator, denominator)) = measure {
+        writeln!(output, "#MEASURE {numerator}/{denominator}").unwrap();
+    }
+    if start.is_some() {
+        let signature = measure.map_or(1.0, |(numerator, denominator)| {
+            numerator as f64 / denominator as f64
+        });
+        write_track(output, chart, bpm, signature);
+    }
+    writeln!(output, "#END\n").unwrap();
+}
+
+/// Writes a song out as a TJA file. See the [module docs](self) for what can and can't be written.
+///
+/// The notes come from each difficulty's chart, so the song needs to have been read with
+/// [parse_tja_file](super::parse_tja_file) (not just
+/// [parse_tja_metadata](super::parse_tja_metadata)) for its courses to have anything in them.
+pub fn to_tja_string(song: &Song) -> String {
+    let mut output = String::new();
+    let mut header = |key: &str, value: &str| writeln!(output, "{key}:{value}").unwrap();
+
+    header("TITLE", &song.title);
+    if let Some(title_en) = &song.title_en {
+        header("TITLEEN", title_en);
+    }
+
+    // A subtitle that already starts with `--` or `++` needs another one in front of it, so it
+    // isn't taken off
+    match (&song.subtitle, song.show_subtitle) {
+        (Some(subtitle), false) => header("SUBTITLE", &format!("--{subtitle}")),
+        (Some(subtitle), true) if subtitle.starts_with("--") || subtitle.starts_with("++") => {
+            header("SUBTITLE", &format!("++{subtitle}"))
+        }
+        (Some(subtitle), true) => header("SUBTITLE", subtitle),
+        (None, _) => {}
+    }
+
+    if let Some(genre) = &song.genre {
+        header("GENRE", genre);
+    }
+    if let Some(maker) = &song.maker {
+        header("MAKER", maker);
+    }
+
+    header("WAVE", &song.audio_filename);
+    header("BPM", &number(song.bpm as f64));
+    header("OFFSET", &number(song.offset as f64));
+    header("DEMOSTART", &number(song.demostart as f64));
+
+    if song.song_volume != 100.0 {
+        header("SONGVOL", &number(song.song_volume as f64));
+    }
+    if song.se_volume != 100.0 {
+        header("SEVOL", &number(song.se_volume as f64));
+    }
+    if let Some(bgmovie) = &song.bgmovie {
+        header("BGMOVIE", bgmovie);
+    }
+    if song.movie_offset != 0.0 {
+        header("MOVIEOFFSET", &number(song.movie_offset as f64));
+    }
+    if let Some(lyrics_file) = &song.lyrics_file {
+        header("LYRICS", lyrics_file);
+    }
+
+    for (i, difficulty) in song.difficulties.iter().enumerate() {
+        if let Some(designer) = difficulty.as_ref().and_then(|d| d.notes_designer.as_ref()) {
+            header(&format!("NOTESDESIGNER{i}"), designer);
+        }
+    }
+
+    output.push('\n');
+
+    let bpm = song.bpm as f64;
+    let mut offset = song.offset as f64;
+
+    // Harder courses usually come first
+    for (i, difficulty) in song.difficulties.iter().enumerate().rev() {
+        if let Some(difficulty) = difficulty {
+            write_course(&mut output, &mut offset, bpm, i, difficulty);
+        }
+    }
+
+    output
+}
@@ -0,0 +1,322 @@
+//! Writes changes to a song's metadata back into its TJA file.
+//!
+//! Rather than serialising a [Song](super::Song) from scratch, this edits the metadata lines of
+//! the original file and copies everything else across verbatim. That way the note tracks (and any
+//! comments or formatting the chart author used) are left exactly as they were.
+use std::borrow::Cow;
+
+use super::tja_parser::{
+    comment_start, course_index, split_metadata_line, COURSE_OVERRIDABLE_KEYS, DEFAULT_COURSE,
+    FULL_WIDTH_COLON,
+};
+
+/// The song metadata keys that can be edited, in the order they're added if they're missing.
+const SONG_KEYS: [&str; 4] = ["TITLE", "SUBTITLE", "GENRE", "DEMOSTART"];
+
+/// Changes to make to a song's metadata. Anything that is None is left as it is.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetadataEdits {
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub genre: Option<String>,
+    pub demostart: Option<f32>,
+    /// The new star level for each difficulty.
+    pub levels: [Option<u8>; 5],
+}
+
+/// Splits a line into its metadata key and value, if it's a metadata line. The key is normalised
+/// the same way the parser does it, so e.g. `ＴＩＴＬＥ：` is a title.
+fn split_metadata(line: &str) -> Option<(Cow<'_, str>, &str)> {
+    let line = line.strip_prefix('\u{feff}').unwrap_or(line);
+    split_metadata_line(&line[..comment_start(line).unwrap_or(line.len())])
+}
+
+/// Replaces the value of a metadata line, keeping its key, any comment and its line ending.
+fn replace_value(line: &str, value: &str) -> String {
+    let (colon, separator) = line
+        .char_indices()
+        .find(|&(_, c)| c == ':' || c == FULL_WIDTH_COLON)
+        .expect("metadata line should have a colon");
+    let value_start = colon + separator.len_utf8();
+    let content_end = line.trim_end_matches(['\r', '\n']).len();
+    let comment_start = comment_start(&line[..content_end]).unwrap_or(content_end);
+    let value_end = line[..comment_start].trim_end().len().max(value_start);
+    let comment = &line[value_end..content_end];
+
+    format!(
+        "{}{value}{comment}{}",
+        &line[..value_start],
+        &line[content_end..]
+    )
+}
+
+struct MetadataWriter<'a> {
+    edits: &'a MetadataEdits,
+    newline: &'static str,
+    output: String,
+    /// Whether we're still in the song's metadata, before the first course.
+    in_header: bool,
+    /// The song metadata keys that were found in the header.
+    seen_in_header: Vec<&'static str>,
+    /// The course that the next note track is for.
+    course: usize,
+}
+
+impl MetadataWriter<'_> {
+    /// Returns the new value for the given song metadata key, if it's being edited.
+    fn song_value(&self, key: &str) -> Option<String> {
+        // Values that can be overridden by a course should only be edited for the song as a whole
+        if !self.in_header && COURSE_OVERRIDABLE_KEYS.contains(&key) {
+            return None;
+        }
+
+        match key {
+            "TITLE" => self.edits.title.clone(),
+            "SUBTITLE" => self.edits.subtitle.clone(),
+            "GENRE" => self.edits.genre.clone(),
+            "DEMOSTART" => self.edits.demostart.map(|demostart| demostart.to_string()),
+            _ => None,
+        }
+    }
+
+    fn push_line(&mut self, line: &str) {
+        if !self.output.is_empty() && !self.output.ends_with('\n') {
+            self.output.push_str(self.newline);
+        }
+
+        self.output.push_str(line);
+        self.output.push_str(self.newline);
+    }
+
+    /// Finishes the song's metadata, adding any edited values that weren't in the file.
+    fn end_header(&mut self) {
+        for key in SONG_KEYS {
+            if self.seen_in_header.contains(&key) {
+                continue;
+            }
+
+            if let Some(value) = self.song_value(key) {
+                self.push_line(&format!("{key}:{value}"));
+            }
+        }
+
+        self.in_header = false;
+    }
+
+    /// Writes the lines between two note tracks (or before the first, or after the last).
+    fn write_segment(&mut self, lines: &[&str], before_track: bool) {
+        // Like the parser, the course lasts until it's changed, and the last one before the track
+        // is the one that counts.
+        if let Some(course) = lines
+            .iter()
+            .rev()
+            .filter_map(|line| split_metadata(line))
+            .find(|(key, _)| *key == "COURSE")
+            .and_then(|(_, value)| course_index(value))
+        {
+            self.course = course;
+        }
+
+        let level = before_track
+            .then(|| self.edits.levels[self.course])
+            .flatten();
+        let mut level_written = false;
+
+        for &line in lines {
+            let Some((key, _)) = split_metadata(line) else {
+                self.output.push_str(line);
+                continue;
+            };
+
+            if self.in_header && key == "COURSE" {
+                self.end_header();
+            }
+
+            if let Some(value) = self.song_value(&key) {
+                if self.in_header {
+                    if let Some(&key) = SONG_KEYS.iter().find(|k| **k == key) {
+                        self.seen_in_header.push(key);
+                    }
+                }
+
+                self.output.push_str(&replace_value(line, &value));
+            } else if let (Some(level), "LEVEL") = (level, key.as_ref()) {
+                self.output
+                    .push_str(&replace_value(line, &level.to_string()));
+                level_written = true;
+            } else {
+                self.output.push_str(line);
+            }
+        }
+
+        if before_track {
+            if self.in_header {
+                self.end_header();
+            }
+
+            if let (Some(level), false) = (level, level_written) {
+                self.push_line(&format!("LEVEL:{level}"));
+            }
+        }
+    }
+}
+
+/// Applies the given edits to the contents of a TJA file, returning the new contents.
+///
+/// Everything other than the edited metadata lines is copied verbatim. Edited values that aren't
+/// in the file are added: song metadata at the end of the song's metadata, and course levels just
+/// before the course's note track.
+pub fn write_metadata_edits(input: &str, edits: &MetadataEdits) -> String {
+    let mut writer = MetadataWriter {
+        edits,
+        newline: if input.contains("\r\n") { "\r\n" } else { "\n" },
+        output: String::with_capacity(input.len()),
+        in_header: true,
+        seen_in_header: Vec::new(),
+        course: DEFAULT_COURSE,
+    };
+
+    let mut segment = Vec::new();
+    let mut in_track = false;
+
+    for line in input.split_inclusive('\n') {
+        let trimmed = line.trim();
+
+        if in_track {
+            writer.output.push_str(line);
+            in_track = !trimmed.starts_with("#END");
+        } else if trimmed.starts_with("#START") {
+            writer.write_segment(&segment, true);
+            segment.clear();
+            writer.output.push_str(line);
+            in_track = true;
+        } else {
+            segment.push(line);
+        }
+    }
+
+    writer.write_segment(&segment, false);
+
+    if writer.in_header {
+        writer.end_header();
+    }
+
+    writer.output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notechart_parser::{parse_tja_file, StarLevel};
+
+    const TRACK: &str = "TITLE:Old title // the title
+WAVE:song.ogg
+DEMOSTART:10
+
+COURSE:Oni
+LEVEL:8
+
+#START
+1201,
+#SCROLL 2
+2210, // fast
+#END
+
+COURSE:Easy
+LEVEL:2
+#START
+1,
+#END
+";
+
+    /// Returns all the lines that are part of a note track.
+    fn note_tracks(input: &str) -> Vec<&str> {
+        let mut in_track = false;
+
+        input
+            .lines()
+            .filter(|line| {
+                if line.starts_with("#START") {
+                    in_track = true;
+                } else if line.starts_with("#END") {
+                    in_track = false;
+                }
+
+                in_track
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_no_edits() {
+        assert_eq!(
+            write_metadata_edits(TRACK, &MetadataEdits::default()),
+            TRACK
+        );
+    }
+
+    #[test]
+    fn test_edit_metadata() {
+        let edits = MetadataEdits {
+            title: Some("New title".to_string()),
+            subtitle: Some("--Someone".to_string()),
+            genre: None,
+            demostart: Some(20.5),
+            levels: [Some(3), None, None, Some(9), None],
+        };
+
+        let output = write_metadata_edits(TRACK, &edits);
+        assert!(output.starts_with("TITLE:New title // the title\n"));
+        assert_eq!(note_tracks(&output), note_tracks(TRACK));
+
+        let old_song = parse_tja_file(TRACK).unwrap();
+        let song = parse_tja_file(&output).unwrap();
+        assert_eq!(song.title, "New title");
+        assert_eq!(song.subtitle.as_deref(), Some("Someone"));
+        assert!(!song.show_subtitle);
+        assert_eq!(song.demostart, 20.5);
+
+        for (i, level) in [(0, 3), (3, 9)] {
+            let old = old_song.difficulties[i].as_ref().unwrap();
+            let new = song.difficulties[i].as_ref().unwrap();
+            assert_eq!(new.star_level, Some(StarLevel::new(level)));
+            assert_eq!(new.chart.notes, old.chart.notes);
+            assert_eq!(new.chart.barlines, old.chart.barlines);
+        }
+    }
+
+    #[test]
+    fn test_keeps_line_endings() {
+        let track = TRACK.replace('\n', "\r\n");
+        let edits = MetadataEdits {
+            genre: Some("Anime".to_string()),
+            ..Default::default()
+        };
+
+        let output = write_metadata_edits(&track, &edits);
+        assert!(output.contains("DEMOSTART:10\r\n\r\nGENRE:Anime\r\nCOURSE:Oni\r\n"));
+        assert!(!output.replace("\r\n", "").contains('\n'));
+        assert_eq!(
+            parse_tja_file(&output).unwrap().genre.as_deref(),
+            Some("Anime")
+        );
+    }
+
+    #[test]
+    fn test_edit_full_width_metadata() {
+        let track = TRACK
+            .replace("TITLE:", "ＴＩＴＬＥ：")
+            .replace("LEVEL:8", "ＬＥＶＥＬ：8");
+        let edits = MetadataEdits {
+            title: Some("New title".to_string()),
+            levels: [None, None, None, Some(9), None],
+            ..Default::default()
+        };
+
+        // The existing lines are edited rather than new ones being added
+        let output = write_metadata_edits(&track, &edits);
+        assert!(output.starts_with("ＴＩＴＬＥ：New title // the title\n"));
+        assert!(output.contains("ＬＥＶＥＬ：9\n"));
+        assert!(!output.contains("TITLE:") && !output.contains("LEVEL:9"));
+    }
+}
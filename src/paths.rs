@@ -0,0 +1,133 @@
+//! Works out where the game's files live.
+//!
+//! Normally the game keeps its data (settings, local data, etc.) in the directory it's run from.
+//! In portable mode, everything lives next to the executable instead, so the game can be run from
+//! anywhere (e.g. a USB stick) and still find its data. Portable mode is turned on by putting a
+//! file called `portable.txt` next to the executable, or by running the game with `--portable`.
+//!
+//! Anything that reads or writes the game's data should get its path from [paths].
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::game::{REPLAYS_DIR, SONG_CACHE_PATH};
+use crate::local_data::LOCAL_DATA_PATH;
+use crate::settings::SETTINGS_PATH;
+
+/// If a file with this name is next to the executable, the game runs in portable mode.
+pub const PORTABLE_MARKER: &str = "portable.txt";
+/// Running the game with this flag turns on portable mode.
+pub const PORTABLE_FLAG: &str = "--portable";
+
+static PATHS: OnceLock<Paths> = OnceLock::new();
+
+/// Returns the paths the game should use, working them out the first time it's called.
+pub fn paths() -> &'static Paths {
+    PATHS.get_or_init(Paths::from_env)
+}
+
+/// The locations of all the game's data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paths {
+    data_dir: PathBuf,
+    portable: bool,
+}
+
+impl Paths {
+    /// Works out the paths given the directory the executable is in and the directory the game
+    /// was run from.
+    pub fn resolve(exe_dir: &Path, working_dir: &Path, portable_flag: bool) -> Self {
+        let portable = portable_flag || exe_dir.join(PORTABLE_MARKER).exists();
+        let data_dir = if portable { exe_dir } else { working_dir };
+
+        Self {
+            data_dir: data_dir.to_path_buf(),
+            portable,
+        }
+    }
+
+    /// Works out the paths for the running game.
+    fn from_env() -> Self {
+        let working_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        let exe_dir = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf))
+            .unwrap_or_else(|| working_dir.clone());
+        let portable_flag = std::env::args().any(|arg| arg == PORTABLE_FLAG);
+
+        let paths = Self::resolve(&exe_dir, &working_dir, portable_flag);
+
+        if paths.portable {
+            log::info!(
+                "running in portable mode, data will be stored in \"{}\"",
+                paths.data_dir.display()
+            );
+        }
+
+        paths
+    }
+
+    /// The directory the game's data is stored in.
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    pub fn settings_file(&self) -> PathBuf {
+        self.data_dir.join(SETTINGS_PATH)
+    }
+
+    pub fn local_data_file(&self) -> PathBuf {
+        self.data_dir.join(LOCAL_DATA_PATH)
+    }
+
+    /// The file the song list's metadata is cached in.
+    pub fn song_cache_file(&self) -> PathBuf {
+        self.data_dir.join(SONG_CACHE_PATH)
+    }
+
+    /// The directory replays of plays are saved in.
+    pub fn replays_dir(&self) -> PathBuf {
+        self.data_dir.join(REPLAYS_DIR)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_normal_mode() {
+        let exe_dir = temp_dir("paths_normal_exe");
+        let working_dir = temp_dir("paths_normal_working");
+
+        let paths = Paths::resolve(&exe_dir, &working_dir, false);
+        assert!(!paths.portable);
+        assert_eq!(paths.data_dir(), working_dir);
+        assert_eq!(paths.settings_file(), working_dir.join(SETTINGS_PATH));
+        assert_eq!(paths.local_data_file(), working_dir.join(LOCAL_DATA_PATH));
+
+        std::fs::remove_dir_all(exe_dir).unwrap();
+        std::fs::remove_dir_all(working_dir).unwrap();
+    }
+
+    #[test]
+    fn test_portable_mode() {
+        let exe_dir = temp_dir("paths_portable_exe");
+        let working_dir = temp_dir("paths_portable_working");
+
+        // The flag turns on portable mode
+        let paths = Paths::resolve(&exe_dir, &working_dir, true);
+        assert!(paths.portable);
+        assert_eq!(paths.settings_file(), exe_dir.join(SETTINGS_PATH));
+
+        // So does the marker file
+        std::fs::write(exe_dir.join(PORTABLE_MARKER), "").unwrap();
+        let paths = Paths::resolve(&exe_dir, &working_dir, false);
+        assert!(paths.portable);
+        assert_eq!(paths.data_dir(), exe_dir);
+        assert_eq!(paths.local_data_file(), exe_dir.join(LOCAL_DATA_PATH));
+
+        std::fs::remove_dir_all(exe_dir).unwrap();
+        std::fs::remove_dir_all(working_dir).unwrap();
+    }
+}
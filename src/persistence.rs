@@ -0,0 +1,82 @@
+//! Saving everything the game needs to remember between sessions.
+//!
+//! The game writes its files as it goes, but anything that hasn't been written yet needs to be
+//! flushed before the game closes. [flush] does that, and is used whenever the game exits.
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use crate::local_data::{save_local_data_to, LOCAL_DATA_PATH};
+use crate::settings::{save_settings_to, SETTINGS_PATH};
+
+/// Writes the settings and local data to their files in the given directory.
+///
+/// Both files are attempted even if the first one fails, and the first error is returned.
+pub fn flush<P: AsRef<Path>>(dir: P) -> anyhow::Result<()> {
+    let dir = dir.as_ref();
+    let settings_result = save_settings_to(dir.join(SETTINGS_PATH));
+    let local_data_result = save_local_data_to(dir.join(LOCAL_DATA_PATH));
+
+    settings_result.and(local_data_result)
+}
+
+/// Writes a file while holding a lock on it.
+///
+/// Only one copy of the game should ever be running (see [crate::instance]), but if two somehow
+/// are, this at least stops them from writing over each other halfway through.
+pub fn write_locked<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> anyhow::Result<()> {
+    // Don't truncate until we have the lock, in case someone else is partway through writing
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+
+    file.lock()?;
+    file.set_len(0)?;
+    file.write_all(contents.as_ref())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::local_data::LocalData;
+    use crate::settings::Settings;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn test_flush() {
+        let dir = temp_dir("flush_test");
+
+        flush(&dir).unwrap();
+
+        let settings = std::fs::read_to_string(dir.join(SETTINGS_PATH)).unwrap();
+        assert!(toml::from_str::<Settings>(&settings).is_ok());
+
+        let local_data = std::fs::read_to_string(dir.join(LOCAL_DATA_PATH)).unwrap();
+        assert!(toml::from_str::<LocalData>(&local_data).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_locked() {
+        let dir = temp_dir("write_locked");
+        let path = dir.join("data.toml");
+
+        write_locked(&path, "a longer first version").unwrap();
+        write_locked(&path, "shorter").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "shorter");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_flush_missing_dir() {
+        let dir = temp_dir("flush_missing");
+        assert!(flush(dir.join("missing")).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
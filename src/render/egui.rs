@@ -38,6 +38,24 @@ impl Egui {
         }
     }
 
+    /// Adds a font for egui to use for anything its own fonts can't draw.
+    pub fn add_fallback_font(&mut self, name: &str, data: Vec<u8>) {
+        let mut fonts = egui::FontDefinitions::default();
+        fonts
+            .font_data
+            .insert(name.to_string(), egui::FontData::from_owned(data));
+
+        for family in [egui::FontFamily::Proportional, egui::FontFamily::Monospace] {
+            fonts
+                .families
+                .entry(family)
+                .or_default()
+                .push(name.to_string());
+        }
+
+        self.platform.context().set_fonts(fonts);
+    }
+
     /// Passes a winit event to egui for processing.
     ///
     /// Returns true if the event is "captured", which means it should not be handled by anything
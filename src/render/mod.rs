@@ -1,3 +1,7 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
 use anyhow::anyhow;
 use egui_wgpu::ScreenDescriptor;
 use kaku::{ab_glyph::FontVec, FontId, FontSize, SdfSettings, TextRendererBuilder};
@@ -7,8 +11,11 @@ use wgpu::include_wgsl;
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use winit::{dpi::PhysicalSize, window::Window};
 
+use crate::diagnostics::GraphicsInfo;
 use crate::game::Game;
 use shapes::ShapeVertex;
+use stats::DrawCounter;
+use text::FontMetrics;
 use texture::TextureVertex;
 
 use self::texture::SpriteInstance;
@@ -42,8 +49,25 @@ const SAMPLE_COUNT: u32 = 4;
 const CLEAR_COLOUR: wgpu::Color = wgpu::Color::BLACK;
 const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
+pub const FONTS_PATH: &str = "assets/fonts";
+/// The fonts the game loads: their names, files and the size their SDFs are generated at.
+pub const FONTS: [(&str, &str, f32); 3] = [
+    ("mplus bold", "MPLUSRounded1c-Bold.ttf", 50.),
+    ("mplus regular", "MPLUSRounded1c-Regular.ttf", 50.),
+    ("mochiy pop one", "MochiyPopOne-Regular.ttf", 80.),
+];
+/// Fonts that are loaded if they're in [FONTS_PATH], to fill in characters (like symbols) the
+/// main fonts don't have.
+pub const OPTIONAL_FONTS: [(&str, &str, f32); 1] =
+    [("symbols", "NotoSansSymbols2-Regular.ttf", 50.)];
+/// The fonts song titles are drawn with, in the order they're tried. See [text::fit_text].
+pub const TITLE_FONTS: [&str; 3] = ["mochiy pop one", "mplus bold", "symbols"];
+/// The font egui falls back on for anything its own fonts don't have (like Japanese).
+const EGUI_FALLBACK_FONT: &str = "mplus regular";
+
 mod egui;
 pub mod shapes;
+pub mod stats;
 pub mod text;
 pub mod texture;
 
@@ -77,6 +101,14 @@ pub struct Renderer {
     screen_bind_group: wgpu::BindGroup,
     pipeline_cache: Vec<(&'static str, wgpu::RenderPipeline)>,
     font_cache: Vec<(&'static str, FontId)>,
+    /// What each loaded font can draw, for picking fonts and measuring text.
+    font_metrics: Vec<(&'static str, FontMetrics)>,
+    adapter_info: wgpu::AdapterInfo,
+    /// Set when the device is lost (e.g. the driver crashed or the GPU hung). Everything on the GPU
+    /// is gone when this happens, so the renderer has to be recreated.
+    device_lost: Arc<AtomicBool>,
+    /// What's been drawn so far this frame.
+    pub draw_stats: DrawCounter,
 
     pub text_renderer: kaku::TextRenderer,
     egui_handler: egui::Egui,
@@ -266,6 +298,35 @@ impl Renderer {
             )
             .await?;
 
+        let device_lost = Arc::new(AtomicBool::new(false));
+        {
+            let device_lost = Arc::clone(&device_lost);
+            device.set_device_lost_callback(move |reason, message| {
+                // This is also called when the device is dropped normally
+                if matches!(
+                    reason,
+                    wgpu::DeviceLostReason::Dropped | wgpu::DeviceLostReason::ReplacedCallback
+                ) {
+                    return;
+                }
+
+                log::error!("graphics device lost ({reason:?}): {message}");
+                device_lost.store(true, Ordering::Relaxed);
+            });
+        }
+        {
+            let device_lost = Arc::clone(&device_lost);
+            device.on_uncaptured_error(Box::new(move |error| {
+                // Everything fails once the device is lost, which is expected until we've
+                // recovered. Otherwise, crash like wgpu normally would.
+                if device_lost.load(Ordering::Relaxed) {
+                    log::warn!("graphics error while the device is lost: {error}");
+                } else {
+                    panic!("wgpu error: {error}");
+                }
+            }));
+        }
+
         let surface_capabilities = surface.get_capabilities(&adapter);
 
         let format = surface_capabilities
@@ -413,22 +474,33 @@ impl Renderer {
         );
 
         let depth_view = create_depth_texture(&device, &size);
-        let egui_handler = egui::Egui::new(&device, &config, window.scale_factor());
+        let mut egui_handler = egui::Egui::new(&device, &config, window.scale_factor());
 
         let mut font_cache = Vec::new();
+        let mut font_metrics = Vec::new();
         let mut text_renderer =
             TextRendererBuilder::new(config.format, (config.width, config.height))
                 .with_msaa_sample_count(SAMPLE_COUNT)
                 .with_depth(DEPTH_FORMAT)
                 .build(&device);
 
-        for (font, filename, size) in [
-            ("mplus bold", "MPLUSRounded1c-Bold.ttf", 50.),
-            ("mplus regular", "MPLUSRounded1c-Regular.ttf", 50.),
-            ("mochiy pop one", "MochiyPopOne-Regular.ttf", 80.),
-        ] {
-            let font_data =
-                FontVec::try_from_vec(std::fs::read(format!("assets/fonts/{filename}"))?)?;
+        let optional_fonts = OPTIONAL_FONTS.into_iter().filter(|(_, filename, _)| {
+            let exists = Path::new(FONTS_PATH).join(filename).exists();
+            if !exists {
+                log::info!("optional font \"{filename}\" isn't there, skipping it");
+            }
+            exists
+        });
+
+        for (font, filename, size) in FONTS.into_iter().chain(optional_fonts) {
+            let bytes = std::fs::read(format!("{FONTS_PATH}/{filename}"))?;
+            if font == EGUI_FALLBACK_FONT {
+                egui_handler.add_fallback_font(font, bytes.clone());
+            }
+
+            let font_data = FontVec::try_from_vec(bytes)?;
+            font_metrics.push((font, FontMetrics::from_font(&font_data)));
+
             let id = text_renderer.load_font_with_sdf(
                 font_data,
                 FontSize::Px(size),
@@ -455,6 +527,10 @@ impl Renderer {
                 ("primitive_depth", primitive_pipeline_depth),
             ],
             font_cache,
+            font_metrics,
+            adapter_info: adapter.get_info(),
+            device_lost,
+            draw_stats: DrawCounter::default(),
             text_renderer,
             egui_handler,
         })
@@ -524,9 +600,34 @@ impl Renderer {
         self.queue.submit([encoder.finish()]);
         texture.present();
 
+        app.record_draw_stats(self.draw_stats.take());
+
         Ok(())
     }
 
+    /// Whether the graphics device has been lost. If it has, nothing can be drawn until the
+    /// renderer is recreated and the game has rebuilt everything it had on the GPU (see
+    /// [Game::recreate_gpu_resources]).
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+
+    /// Destroys the graphics device, as if the driver had crashed. This is for testing that the
+    /// game can recover.
+    pub fn simulate_device_loss(&self) {
+        log::warn!("simulating the graphics device being lost");
+        self.device.destroy();
+        self.device_lost.store(true, Ordering::Relaxed);
+    }
+
+    /// Information about what the renderer is drawing with, for diagnostics.
+    pub fn graphics_info(&self) -> GraphicsInfo {
+        GraphicsInfo {
+            adapter: self.adapter_info.clone(),
+            surface_format: self.config.format,
+        }
+    }
+
     pub fn resize(&mut self, size: PhysicalSize<u32>) {
         if size.width > 0 && size.height > 0 {
             self.size = size;
@@ -547,7 +648,7 @@ impl Renderer {
 
             // Resize the screen space transformation matrirx
             let screen_uniform = create_screen_uniform(&size);
-            self.queue.write_buffer(
+            self.write_buffer(
                 &self.screen_uniform,
                 0,
                 bytemuck::cast_slice(&[screen_uniform]),
@@ -570,6 +671,12 @@ impl Renderer {
         &self.size
     }
 
+    /// Writes data to a GPU buffer, counting it in the frame's [draw stats](Renderer::draw_stats).
+    pub fn write_buffer(&self, buffer: &wgpu::Buffer, offset: wgpu::BufferAddress, data: &[u8]) {
+        self.draw_stats.buffer_write(data.len());
+        self.queue.write_buffer(buffer, offset, data);
+    }
+
     pub fn pipeline(&self, name: &str) -> Option<&wgpu::RenderPipeline> {
         self.pipeline_cache.iter().find_map(
             |(n, pipeline)| {
@@ -589,4 +696,37 @@ impl Renderer {
             .expect("Font does not exist")
             .1
     }
+
+    /// Gets text ready to be drawn in no more than `max_width` pixels with the first font in the
+    /// chain that can draw it (see [text::fit_text]). Fonts in the chain that weren't loaded are
+    /// skipped, but at least one of them must have been.
+    pub fn fit_text(
+        &self,
+        chain: &[&str],
+        text: &str,
+        size: f32,
+        outline: f32,
+        max_width: f32,
+    ) -> (FontId, String) {
+        let fonts: Vec<_> = chain
+            .iter()
+            .filter_map(|name| self.font_metrics.iter().find(|(n, _)| n == name))
+            .map(|(name, metrics)| (*name, metrics))
+            .collect();
+        assert!(!fonts.is_empty(), "none of the fonts {chain:?} are loaded");
+
+        let (font, text) = text::fit_text(&fonts, text, size, outline, max_width);
+        (self.font(font), text)
+    }
+
+    /// How wide the text would be in the given font and size, in pixels. The font must be loaded.
+    pub fn text_width(&self, font: &str, text: &str, size: f32) -> f32 {
+        let (_, metrics) = self
+            .font_metrics
+            .iter()
+            .find(|(name, _)| *name == font)
+            .unwrap_or_else(|| panic!("the font {font:?} isn't loaded"));
+
+        metrics.width(text, size)
+    }
 }
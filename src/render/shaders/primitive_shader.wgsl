@@ -12,6 +12,7 @@ struct VertexOutput {
 
 struct Instance {
     @location(2) world_position: vec3<f32>,
+    @location(4) alpha: f32,
 };
 
 struct ScreenUniform {
@@ -42,7 +43,7 @@ fn vs_main(in: VertexInput, instance: Instance) -> VertexOutput {
     out.clip_position = screen_matrix * vec4<f32>(in.position + instance.world_position, 1.0);
     out.clip_position.z = quick_sigmoid(out.clip_position.z);
     // For non-srgb:
-    out.colour = in.colour;
+    out.colour = vec4<f32>(in.colour.rgb, in.colour.a * instance.alpha);
     // // For srgb:
     // out.colour = vec4<f32>(pow(in.colour.xyz, vec3<f32>(2.2)), in.colour.w);
     return out;
@@ -50,5 +51,10 @@ fn vs_main(in: VertexInput, instance: Instance) -> VertexOutput {
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Invisible shapes shouldn't hide what's behind them from the depth test
+    if in.colour.a <= 0.01 {
+        discard;
+    }
+
     return in.colour;
 }
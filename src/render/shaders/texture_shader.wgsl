@@ -8,10 +8,13 @@ struct VertexInput {
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coord: vec2<f32>,
+    @location(1) alpha: f32,
 };
 
 struct Instance {
     @location(2) world_position: vec3<f32>,
+    @location(3) scale: f32,
+    @location(4) alpha: f32,
 };
 
 struct ScreenUniform {
@@ -39,9 +42,10 @@ fn vs_main(vert: VertexInput, inst: Instance) -> VertexOutput {
         screen_uniform.mat3,
     );
 
-    out.clip_position = screen_matrix * vec4<f32>(vert.position.xy + inst.world_position.xy, inst.world_position.z, 1.0);
+    out.clip_position = screen_matrix * vec4<f32>(vert.position.xy * inst.scale + inst.world_position.xy, inst.world_position.z, 1.0);
     out.clip_position.z = quick_sigmoid(out.clip_position.z);
     out.tex_coord = vert.tex_coord;
+    out.alpha = inst.alpha;
     return out;
 }
 
@@ -53,10 +57,11 @@ var texture_sampler: sampler;
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let sample = textureSample(texture, texture_sampler, in.tex_coord);
+    let colour = vec4<f32>(sample.rgb, sample.a * in.alpha);
 
-    if sample.a <= 0.01 {
+    if colour.a <= 0.01 {
         discard;
     }
 
-    return sample;
+    return colour;
 }
@@ -206,6 +206,8 @@ impl ShapeBuilder {
             label: Some("primitive instance buffer"),
             contents: bytemuck::cast_slice(&[SpriteInstance {
                 position: self.position,
+                scale: 1.0,
+                alpha: 1.0,
             }]),
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
@@ -450,10 +452,20 @@ impl Default for ShapeBuilder {
 impl Shape {
     /// Moves the whole shape to the given position.
     pub fn set_position(&self, position: [f32; 3], renderer: &Renderer) {
-        renderer.queue.write_buffer(
+        self.set_position_and_alpha(position, 1.0, renderer);
+    }
+
+    /// Moves the whole shape to the given position, and draws it with the given opacity, from 0
+    /// to 1.
+    pub fn set_position_and_alpha(&self, position: [f32; 3], alpha: f32, renderer: &Renderer) {
+        renderer.write_buffer(
             &self.instance,
             0,
-            bytemuck::cast_slice(&[SpriteInstance { position }]),
+            bytemuck::cast_slice(&[SpriteInstance {
+                position,
+                scale: 1.0,
+                alpha,
+            }]),
         );
     }
 }
@@ -480,5 +492,6 @@ impl Renderable for Shape {
         render_pass.set_vertex_buffer(1, self.instance.slice(..));
         render_pass.set_index_buffer(self.index.slice(..), wgpu::IndexFormat::Uint32);
         render_pass.draw_indexed(0..self.indices, 0, 0..1);
+        renderer.draw_stats.shape();
     }
 }
@@ -0,0 +1,202 @@
+//! Counting what gets drawn each frame, so that a change that makes the game draw a lot more than
+//! it used to shows up before it's felt as dropped frames.
+//!
+//! Everything is counted with plain integer increments as it's drawn, and the counts are taken
+//! (and reset) once the frame has been submitted. During gameplay, going over one of the soft
+//! [DRAW_BUDGETS] logs a warning, at most once every [WARNING_INTERVAL] seconds.
+use std::cell::Cell;
+
+/// How often a budget warning can be logged, in seconds.
+pub const WARNING_INTERVAL: f32 = 10.0;
+
+/// How much a frame is expected to draw at most during gameplay. These are soft limits: going
+/// over them just logs a warning.
+pub const DRAW_BUDGETS: DrawStats = DrawStats {
+    sprites: 1500,
+    instances: 1500,
+    shapes: 500,
+    texts: 200,
+    draw_calls: 2000,
+    buffer_bytes: 1024 * 1024,
+};
+
+/// What was drawn in a single frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DrawStats {
+    pub sprites: u64,
+    /// How many instances were drawn across every draw call.
+    pub instances: u64,
+    pub shapes: u64,
+    /// Pieces of text drawn. The text renderer doesn't say how many glyphs are in each one.
+    pub texts: u64,
+    pub draw_calls: u64,
+    /// How many bytes were written to GPU buffers, including writes made while updating.
+    pub buffer_bytes: u64,
+}
+
+impl DrawStats {
+    /// Each category's name and count, in the order they're shown in.
+    pub fn categories(&self) -> [(&'static str, u64); 6] {
+        [
+            ("sprites", self.sprites),
+            ("instances", self.instances),
+            ("shapes", self.shapes),
+            ("texts", self.texts),
+            ("draw calls", self.draw_calls),
+            ("buffer bytes", self.buffer_bytes),
+        ]
+    }
+
+    /// The categories that are over the given budgets, with their counts and budgets.
+    pub fn over_budget(&self, budgets: &DrawStats) -> Vec<(&'static str, u64, u64)> {
+        self.categories()
+            .into_iter()
+            .zip(budgets.categories())
+            .filter(|((_, count), (_, budget))| count > budget)
+            .map(|((name, count), (_, budget))| (name, count, budget))
+            .collect()
+    }
+}
+
+/// Counts what's drawn over the course of a frame.
+///
+/// This uses a [Cell] so that it can be counted through the shared reference to the renderer that
+/// renderables get.
+#[derive(Debug, Default)]
+pub struct DrawCounter {
+    stats: Cell<DrawStats>,
+}
+
+impl DrawCounter {
+    fn count(&self, f: impl FnOnce(&mut DrawStats)) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+
+    pub fn sprite(&self) {
+        self.count(|stats| {
+            stats.sprites += 1;
+            stats.instances += 1;
+            stats.draw_calls += 1;
+        });
+    }
+
+    pub fn shape(&self) {
+        self.count(|stats| {
+            stats.shapes += 1;
+            stats.instances += 1;
+            stats.draw_calls += 1;
+        });
+    }
+
+    pub fn text(&self) {
+        self.count(|stats| {
+            stats.texts += 1;
+            stats.draw_calls += 1;
+        });
+    }
+
+    pub fn buffer_write(&self, bytes: usize) {
+        self.count(|stats| stats.buffer_bytes += bytes as u64);
+    }
+
+    /// Returns everything counted since the last call, and starts counting from zero again.
+    pub fn take(&self) -> DrawStats {
+        self.stats.take()
+    }
+}
+
+/// Logs a warning when a frame goes over the draw budgets, without flooding the log when every
+/// frame does.
+#[derive(Debug, Default)]
+pub struct BudgetWarnings {
+    last_warning: Option<f32>,
+}
+
+impl BudgetWarnings {
+    /// Checks a frame's stats against the budgets. Returns the warning to log, if there is one and
+    /// it's been long enough since the last one.
+    pub fn check(&mut self, stats: &DrawStats, budgets: &DrawStats, now: f32) -> Option<String> {
+        let over = stats.over_budget(budgets);
+        if over.is_empty() {
+            return None;
+        }
+
+        if self
+            .last_warning
+            .is_some_and(|last| now - last < WARNING_INTERVAL)
+        {
+            return None;
+        }
+
+        self.last_warning = Some(now);
+
+        let details = over
+            .iter()
+            .map(|(name, count, budget)| format!("{count} {name} (budget {budget})"))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        Some(format!("frame went over the draw budget: {details}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_counting() {
+        let counter = DrawCounter::default();
+        counter.sprite();
+        counter.sprite();
+        counter.shape();
+        counter.text();
+        counter.buffer_write(12);
+        counter.buffer_write(64);
+
+        let stats = counter.take();
+        assert_eq!(
+            stats,
+            DrawStats {
+                sprites: 2,
+                instances: 3,
+                shapes: 1,
+                texts: 1,
+                draw_calls: 4,
+                buffer_bytes: 76,
+            }
+        );
+
+        // Each frame starts from nothing
+        assert_eq!(counter.take(), DrawStats::default());
+    }
+
+    #[test]
+    fn test_budget_warnings() {
+        let budgets = DrawStats {
+            sprites: 2,
+            ..DRAW_BUDGETS
+        };
+        let mut stats = DrawStats {
+            sprites: 3,
+            ..Default::default()
+        };
+        assert_eq!(stats.over_budget(&budgets), vec![("sprites", 3, 2)]);
+
+        let mut warnings = BudgetWarnings::default();
+        let warning = warnings.check(&stats, &budgets, 1.0).unwrap();
+        assert!(warning.contains("3 sprites (budget 2)"));
+
+        // Going over every frame only warns every so often
+        assert!(warnings.check(&stats, &budgets, 2.0).is_none());
+        assert!(warnings
+            .check(&stats, &budgets, 1.0 + WARNING_INTERVAL)
+            .is_some());
+
+        stats.sprites = 2;
+        assert!(stats.over_budget(&budgets).is_empty());
+        assert!(warnings.check(&stats, &budgets, 100.0).is_none());
+    }
+}
@@ -1,4 +1,16 @@
+//! Drawing text, and making sure text fits where it's drawn.
+//!
+//! Each piece of text is drawn with a single font, so text that mixes scripts (like song titles)
+//! is drawn with the first font in a fallback chain that has every character in it. Anything no
+//! font has is replaced with a placeholder rather than left to show up as an empty box. Text that's
+//! too wide for the space it's in is cut short with an ellipsis, always between graphemes so
+//! accents and emoji sequences are never split.
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use kaku::ab_glyph::Font;
 use kaku::{Text, TextBuilder};
+use unicode_segmentation::UnicodeSegmentation;
 
 use super::{Renderable, Renderer};
 
@@ -9,6 +21,7 @@ impl Renderable for Text {
         render_pass: &mut wgpu::RenderPass<'pass>,
     ) {
         renderer.text_renderer.draw_text(render_pass, &self);
+        renderer.draw_stats.text();
     }
 }
 
@@ -25,3 +38,280 @@ impl BuildTextWithRenderer for TextBuilder {
         )
     }
 }
+
+/// What's drawn in place of anything none of the fonts can draw.
+pub const LAST_RESORT: &str = "?";
+/// What's put at the end of text that's been cut short.
+pub const ELLIPSIS: &str = "…";
+/// Used instead of [ELLIPSIS] if the font doesn't have it.
+const ASCII_ELLIPSIS: &str = "...";
+
+/// Which characters a font can draw, and how wide they are.
+#[derive(Debug, Clone, Default)]
+pub struct FontMetrics {
+    /// The advance width of every character the font has, in ems.
+    advances: HashMap<char, f32>,
+}
+
+impl FontMetrics {
+    pub fn from_font<F: Font>(font: &F) -> Self {
+        let units_per_em = font.units_per_em().unwrap_or(1.0);
+        let advances = font
+            .codepoint_ids()
+            .map(|(id, c)| (c, font.h_advance_unscaled(id) / units_per_em))
+            .collect();
+
+        Self { advances }
+    }
+
+    /// Makes metrics from a list of characters and their widths in ems.
+    #[cfg(test)]
+    pub fn from_advances(advances: &[(char, f32)]) -> Self {
+        Self {
+            advances: advances.iter().copied().collect(),
+        }
+    }
+
+    pub fn covers(&self, c: char) -> bool {
+        is_invisible(c) || self.advances.contains_key(&c)
+    }
+
+    /// Whether the font can draw every character in the text.
+    pub fn covers_all(&self, text: &str) -> bool {
+        text.chars().all(|c| self.covers(c))
+    }
+
+    /// How wide the text would be at the given font size, in pixels. Anything the font doesn't
+    /// have is measured as [LAST_RESORT], since that's what will be drawn instead.
+    pub fn width(&self, text: &str, size: f32) -> f32 {
+        let last_resort = LAST_RESORT
+            .chars()
+            .filter_map(|c| self.advances.get(&c))
+            .sum::<f32>();
+
+        text.chars()
+            .filter(|c| !is_invisible(*c))
+            .map(|c| self.advances.get(&c).copied().unwrap_or(last_resort))
+            .sum::<f32>()
+            * size
+    }
+}
+
+/// Characters that don't take up any space of their own, like joiners and variation selectors.
+/// Fonts often leave these out, but that doesn't mean they can't draw the text around them.
+fn is_invisible(c: char) -> bool {
+    c.is_control() || matches!(c, '\u{200B}'..='\u{200D}' | '\u{FE00}'..='\u{FE0F}' | '\u{2060}')
+}
+
+/// Picks the first font in the chain that can draw all of the text. If none of them can, picks
+/// the one that can draw the most of it. Returns the index of the font in the chain.
+pub fn pick_font(text: &str, chain: &[&FontMetrics]) -> usize {
+    if let Some(i) = chain.iter().position(|font| font.covers_all(text)) {
+        return i;
+    }
+
+    chain
+        .iter()
+        .enumerate()
+        .max_by_key(|(i, font)| {
+            // Ties go to the font earlier in the chain
+            let covered = text.chars().filter(|c| font.covers(*c)).count();
+            (covered, std::cmp::Reverse(*i))
+        })
+        .map_or(0, |(i, _)| i)
+}
+
+/// Replaces every grapheme the font can't fully draw with [LAST_RESORT], so it shows up as one
+/// placeholder rather than a box for each missing character.
+pub fn replace_missing<'a>(text: &'a str, font: &FontMetrics) -> Cow<'a, str> {
+    if font.covers_all(text) {
+        return Cow::Borrowed(text);
+    }
+
+    Cow::Owned(
+        text.graphemes(true)
+            .map(|grapheme| {
+                if font.covers_all(grapheme) {
+                    grapheme
+                } else {
+                    LAST_RESORT
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Cuts the text short (at a grapheme boundary) with an ellipsis so that it's no wider than
+/// `max_width`, as measured by `measure`. Text that already fits is left alone.
+pub fn truncate_to_width<'a>(
+    text: &'a str,
+    max_width: f32,
+    ellipsis: &str,
+    measure: impl Fn(&str) -> f32,
+) -> Cow<'a, str> {
+    if measure(text) <= max_width {
+        return Cow::Borrowed(text);
+    }
+
+    let available = max_width - measure(ellipsis);
+    let mut end = 0;
+
+    for (start, grapheme) in text.grapheme_indices(true) {
+        let next = start + grapheme.len();
+        if measure(text[..next].trim_end()) > available {
+            break;
+        }
+        end = next;
+    }
+
+    let kept = text[..end].trim_end();
+    if kept.is_empty() {
+        // Not even one character fits, so the ellipsis will have to do
+        return Cow::Owned(ellipsis.to_string());
+    }
+
+    Cow::Owned(format!("{kept}{ellipsis}"))
+}
+
+/// Gets text ready to be drawn in no more than `max_width` pixels: picks the first font in the
+/// chain that can draw it, replaces anything it can't draw, and cuts it short if it's too wide.
+/// Returns the name of the font to use and the text to draw with it.
+///
+/// The widths are worked out from the fonts' advance widths (plus the given outline on either
+/// side), which is close to what's drawn but doesn't include kerning.
+pub fn fit_text(
+    chain: &[(&'static str, &FontMetrics)],
+    text: &str,
+    size: f32,
+    outline: f32,
+    max_width: f32,
+) -> (&'static str, String) {
+    let fonts: Vec<&FontMetrics> = chain.iter().map(|(_, font)| *font).collect();
+    let (name, font) = chain[pick_font(text, &fonts)];
+
+    let text = replace_missing(text, font);
+    let ellipsis = if font.covers_all(ELLIPSIS) {
+        ELLIPSIS
+    } else {
+        ASCII_ELLIPSIS
+    };
+    let text = truncate_to_width(&text, max_width - outline * 2.0, ellipsis, |s| {
+        font.width(s, size)
+    });
+
+    (name, text.into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A font with every ASCII character at half an em, and the given other characters.
+    fn font(extra: &[(char, f32)]) -> FontMetrics {
+        let mut advances: Vec<_> = (' '..='~').map(|c| (c, 0.5)).collect();
+        advances.extend_from_slice(extra);
+        FontMetrics::from_advances(&advances)
+    }
+
+    fn japanese_font() -> FontMetrics {
+        font(
+            &"千本桜夜に紛れ…"
+                .chars()
+                .map(|c| (c, 1.0))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn test_width() {
+        let font = japanese_font();
+        assert_eq!(font.width("ab", 10.0), 10.0);
+        assert_eq!(font.width("千本桜", 10.0), 30.0);
+        // Missing characters are measured as the placeholder, and joiners take no space
+        assert_eq!(font.width("a🌸", 10.0), 10.0);
+        assert_eq!(font.width("a\u{200D}b", 10.0), 10.0);
+    }
+
+    #[test]
+    fn test_pick_font() {
+        let latin = font(&[]);
+        let japanese = japanese_font();
+        let symbols = FontMetrics::from_advances(&[('★', 1.0), ('♪', 1.0)]);
+        let chain = [&latin, &japanese, &symbols];
+
+        assert_eq!(pick_font("Angel Dream", &chain), 0);
+        assert_eq!(pick_font("千本桜", &chain), 1);
+        // Nothing has everything, so go with whatever has the most
+        assert_eq!(pick_font("千本桜 ★", &chain), 1);
+        assert_eq!(pick_font("★♪", &chain), 2);
+        assert_eq!(pick_font("🌸", &chain), 0);
+    }
+
+    #[test]
+    fn test_replace_missing() {
+        let font = japanese_font();
+        assert!(matches!(replace_missing("千本桜", &font), Cow::Borrowed(_)));
+
+        // A whole emoji sequence (or accented letter) becomes one placeholder
+        assert_eq!(replace_missing("桜👩‍🎤!", &font), "桜?!");
+        assert_eq!(replace_missing("cafe\u{301}", &font), "caf?");
+        assert_eq!(replace_missing("🇯🇵 flag", &font), "? flag");
+    }
+
+    #[test]
+    fn test_truncate_mixed_scripts() {
+        let font = japanese_font();
+        let measure = |s: &str| font.width(s, 10.0);
+
+        let fits = "千本桜 Senbonzakura";
+        assert_eq!(truncate_to_width(fits, 1000.0, ELLIPSIS, measure), fits);
+
+        for max_width in [0.0, 5.0, 10.0, 25.0, 40.0, 63.0, 100.0] {
+            for text in [
+                "千本桜 Senbonzakura",
+                "夜に紛れ👩‍🎤 (TV size)",
+                "cafe\u{301} au lait",
+            ] {
+                let truncated = truncate_to_width(text, max_width, ELLIPSIS, measure);
+                let width = measure(&truncated);
+
+                assert!(
+                    width <= max_width.max(measure(ELLIPSIS)),
+                    "\"{truncated}\" is {width} wide, more than {max_width}"
+                );
+                // It's always cut between graphemes, so nothing is left half drawn
+                assert!(text.starts_with(truncated.trim_end_matches(ELLIPSIS)));
+                assert!(!truncated.contains('\u{200D}') || truncated.contains("👩‍🎤"));
+            }
+        }
+
+        assert_eq!(
+            truncate_to_width("千本桜 Senbonzakura", 45.0, ELLIPSIS, measure),
+            "千本桜…"
+        );
+        assert_eq!(
+            truncate_to_width("cafe\u{301} au lait", 35.0, ELLIPSIS, measure),
+            "cafe\u{301}…"
+        );
+    }
+
+    #[test]
+    fn test_fit_text() {
+        let latin = font(&[]);
+        let japanese = japanese_font();
+        let chain = [("latin", &latin), ("japanese", &japanese)];
+
+        assert_eq!(
+            fit_text(&chain, "Angel Dream", 10.0, 0.0, 1000.0),
+            ("latin", "Angel Dream".to_string())
+        );
+
+        // The Latin font has no ellipsis, so the ASCII one is used
+        let (font, text) = fit_text(&chain, "Angel Dream", 10.0, 1.0, 42.0);
+        assert_eq!((font, text.as_str()), ("latin", "Angel..."));
+
+        let (font, text) = fit_text(&chain, "千本桜🌸 long title", 10.0, 0.0, 60.0);
+        assert_eq!(font, "japanese");
+        assert_eq!(text, "千本桜? lo…");
+    }
+}
@@ -1,6 +1,6 @@
 //! Various types used for drawing textures
 
-use image::GenericImageView;
+use image::RgbaImage;
 use std::{path::Path, rc::Rc, sync::OnceLock};
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
@@ -67,10 +67,15 @@ impl TextureVertex {
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Debug)]
 pub struct SpriteInstance {
     pub position: [f32; 3],
+    /// How much bigger the sprite is drawn than its texture. Shapes are always drawn at 1.
+    pub scale: f32,
+    /// How opaque the sprite is drawn, from 0 to 1.
+    pub alpha: f32,
 }
 
 impl SpriteInstance {
-    const ATTRS: &'static [wgpu::VertexAttribute] = &vertex_attr_array![2 => Float32x3];
+    const ATTRS: &'static [wgpu::VertexAttribute] =
+        &vertex_attr_array![2 => Float32x3, 3 => Float32, 4 => Float32];
 
     /// Returns the vertex buffer layout describing this vertex
     pub fn vertex_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
@@ -199,10 +204,24 @@ impl Texture {
         queue: &wgpu::Queue,
     ) -> anyhow::Result<Self> {
         let name = path.as_ref().to_str().unwrap_or_default().to_string();
-        let image = image::load_from_memory(&std::fs::read(path)?)?;
+        let image = Self::decode_file(path)?;
+        Ok(Self::from_image(&name, &image, device, queue))
+    }
 
-        let rgba = image.to_rgba8();
-        let dimensions = image.dimensions();
+    /// Reads and decodes an image file without putting it on the GPU. This doesn't need the
+    /// graphics device, so it can be done on another thread.
+    pub fn decode_file<P: AsRef<Path>>(path: P) -> anyhow::Result<RgbaImage> {
+        Ok(image::load_from_memory(&std::fs::read(path)?)?.to_rgba8())
+    }
+
+    /// Creates a texture from an image that's already been decoded.
+    pub fn from_image(
+        name: &str,
+        rgba: &RgbaImage,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Self {
+        let dimensions = rgba.dimensions();
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
@@ -211,7 +230,7 @@ impl Texture {
         };
 
         let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some(&name),
+            label: Some(name),
             size,
             mip_level_count: 1,
             sample_count: 1,
@@ -228,7 +247,7 @@ impl Texture {
                 origin: wgpu::Origin3d::ZERO,
                 aspect: wgpu::TextureAspect::All,
             },
-            &rgba,
+            rgba,
             wgpu::ImageDataLayout {
                 offset: 0,
                 bytes_per_row: Some(dimensions.0 * 4),
@@ -265,13 +284,13 @@ impl Texture {
             usage: wgpu::BufferUsages::INDEX,
         });
 
-        Ok(Self {
+        Self {
             bind_group,
             vertex_buffer,
             index_buffer,
             view,
             dimensions,
-        })
+        }
     }
 }
 
@@ -291,16 +310,45 @@ impl Frame {
 struct SpriteInstanceController {
     position: [f32; 2],
     depth: Option<f32>,
+    /// The sprite is scaled around its origin, so the origin stays at its position.
+    scale: f32,
+    alpha: f32,
     instance_buffer: wgpu::Buffer,
 }
 
 impl SpriteInstanceController {
-    fn position_3d(&self, frame: &Frame) -> [f32; 3] {
-        [
-            self.position[0] - frame.origin[0],
-            self.position[1] - frame.origin[1],
-            self.depth.unwrap_or_default(),
-        ]
+    fn new(position: [f32; 2], depth: Option<f32>, frame: &Frame, renderer: &Renderer) -> Self {
+        let instance = sprite_instance(position, depth, 1.0, 1.0, frame);
+        let instance_buffer =
+            renderer
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("sprite instance buffer"),
+                    contents: bytemuck::cast_slice(&[instance]),
+                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                });
+
+        Self {
+            position,
+            depth,
+            scale: 1.0,
+            alpha: 1.0,
+            instance_buffer,
+        }
+    }
+
+    fn write_instance(&self, renderer: &Renderer, frame: &Frame) {
+        renderer.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&[sprite_instance(
+                self.position,
+                self.depth,
+                self.scale,
+                self.alpha,
+                frame,
+            )]),
+        )
     }
 
     fn render<'pass>(
@@ -327,28 +375,45 @@ impl SpriteInstanceController {
         render_pass.set_bind_group(1, &frame.texture.bind_group, &[]);
         render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
         render_pass.draw_indexed(0..6 as _, 0, 0..1);
+        renderer.draw_stats.sprite();
     }
 
     fn set_position(&mut self, position: [f32; 2], renderer: &Renderer, frame: &Frame) {
         self.position = position;
-        renderer.queue.write_buffer(
-            &self.instance_buffer,
-            0,
-            bytemuck::cast_slice(&[SpriteInstance {
-                position: self.position_3d(frame),
-            }]),
-        )
+        self.write_instance(renderer, frame);
     }
 
     fn set_depth(&mut self, depth: Option<f32>, renderer: &Renderer, frame: &Frame) {
         self.depth = depth;
-        renderer.queue.write_buffer(
-            &self.instance_buffer,
-            0,
-            bytemuck::cast_slice(&[SpriteInstance {
-                position: self.position_3d(frame),
-            }]),
-        )
+        self.write_instance(renderer, frame);
+    }
+
+    fn set_scale(&mut self, scale: f32, renderer: &Renderer, frame: &Frame) {
+        self.scale = scale;
+        self.write_instance(renderer, frame);
+    }
+
+    fn set_alpha(&mut self, alpha: f32, renderer: &Renderer, frame: &Frame) {
+        self.alpha = alpha;
+        self.write_instance(renderer, frame);
+    }
+}
+
+fn sprite_instance(
+    position: [f32; 2],
+    depth: Option<f32>,
+    scale: f32,
+    alpha: f32,
+    frame: &Frame,
+) -> SpriteInstance {
+    SpriteInstance {
+        position: [
+            position[0] - frame.origin[0] * scale,
+            position[1] - frame.origin[1] * scale,
+            depth.unwrap_or_default(),
+        ],
+        scale,
+        alpha,
     }
 }
 
@@ -386,6 +451,16 @@ impl Sprite {
     pub fn set_depth(&mut self, depth: Option<f32>, renderer: &Renderer) {
         self.controller.set_depth(depth, renderer, &self.frame)
     }
+
+    /// Draws the sprite this many times bigger than its texture, around its origin.
+    pub fn set_scale(&mut self, scale: f32, renderer: &Renderer) {
+        self.controller.set_scale(scale, renderer, &self.frame)
+    }
+
+    /// Draws the sprite with the given opacity, from 0 to 1.
+    pub fn set_alpha(&mut self, alpha: f32, renderer: &Renderer) {
+        self.controller.set_alpha(alpha, renderer, &self.frame)
+    }
 }
 
 impl Renderable for Sprite {
@@ -435,6 +510,12 @@ impl AnimatedSprite {
             .set_depth(depth, renderer, &self.frames[self.index])
     }
 
+    /// Draws the sprite this many times bigger than its frames, around their origins.
+    pub fn set_scale(&mut self, scale: f32, renderer: &Renderer) {
+        self.controller
+            .set_scale(scale, renderer, &self.frames[self.index])
+    }
+
     pub fn set_index(&mut self, index: usize, renderer: &Renderer) {
         assert!(
             index < self.frames.len(),
@@ -530,34 +611,13 @@ impl SpriteBuilder {
     }
 
     pub fn build(self, renderer: &Renderer) -> Sprite {
-        let instance = SpriteInstance {
-            position: [
-                self.position[0] - self.origin[0],
-                self.position[1] - self.origin[1],
-                self.depth.unwrap_or_default(),
-            ],
+        let frame = Frame {
+            texture: self.texture,
+            origin: self.origin,
         };
+        let controller = SpriteInstanceController::new(self.position, self.depth, &frame, renderer);
 
-        let instance_buffer =
-            renderer
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("sprite instance buffer"),
-                    contents: bytemuck::cast_slice(&[instance]),
-                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-                });
-
-        Sprite {
-            frame: Frame {
-                texture: self.texture,
-                origin: self.origin,
-            },
-            controller: SpriteInstanceController {
-                position: self.position,
-                depth: self.depth,
-                instance_buffer,
-            },
-        }
+        Sprite { frame, controller }
     }
 }
 
@@ -614,22 +674,12 @@ impl AnimatedSpriteBuilder {
     }
 
     pub fn build(self, renderer: &Renderer) -> AnimatedSprite {
-        let instance = SpriteInstance {
-            position: [
-                self.position[0] - self.frames[self.index].origin[0],
-                self.position[1] - self.frames[self.index].origin[1],
-                self.depth.unwrap_or_default(),
-            ],
-        };
-
-        let instance_buffer =
-            renderer
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("sprite instance buffer"),
-                    contents: bytemuck::cast_slice(&[instance]),
-                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-                });
+        let controller = SpriteInstanceController::new(
+            self.position,
+            self.depth,
+            &self.frames[self.index],
+            renderer,
+        );
 
         AnimatedSprite {
             frames: self.frames,
@@ -637,11 +687,7 @@ impl AnimatedSpriteBuilder {
             looping: self.looping,
             progress: 0.0,
             playback_state: self.playback_state,
-            controller: SpriteInstanceController {
-                position: self.position,
-                depth: self.depth,
-                instance_buffer,
-            },
+            controller,
         }
     }
 }
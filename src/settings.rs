@@ -2,23 +2,46 @@
 //!
 //! The settings for lunataiko are stored in a toml file (by default `taiko_settings.toml`). Use
 //! the function [read_settings] to read this config from file.
-use std::ops::Deref;
+use std::ops::{Deref, RangeInclusive};
+use std::path::Path;
 use std::sync::RwLock;
 
 use serde::{Deserialize, Serialize};
 use winit::keyboard::{KeyCode, PhysicalKey};
 
-/// The path to the settings file
+use crate::notechart_parser::DEFAULT_MAX_SUBDIVISIONS;
+use crate::paths::paths;
+use crate::persistence::write_locked;
+
+/// The name of the settings file. See [crate::paths] for where it's stored.
 pub const SETTINGS_PATH: &str = "taiko_settings.toml";
 
 pub static SETTINGS: RwLock<Settings> = RwLock::new(Settings {
     visual: VisualSettings {
         resolution: ResolutionState::BorderlessFullscreen,
+        monitor: None,
+        stream_mode: false,
+        key_input_display: true,
+        romanised_titles: false,
+        control_hints: true,
+        judgement_scale: 1.0,
+        judgement_position: JudgementPosition::Receptacle,
+        combo_scale: 1.0,
+        safe_area_margin: 0.0,
     },
     game: GameSettings {
         global_note_offset: 0.0,
         key_mappings: KeyMap::default_mapping(),
+        player2_key_mappings: KeyMap::player2_default_mapping(),
+        incoming_note_markers: false,
+        show_timing_windows: false,
+        prefer_estimated_levels: false,
+        comment_section_labels: false,
+        bonus_rally: true,
+        max_measure_subdivisions: DEFAULT_MAX_SUBDIVISIONS,
+        lead_in: DEFAULT_LEAD_IN,
     },
+    audio: AudioSettings::default_settings(),
 });
 
 /// Convenience function that returns an immutable reference to [settings::SETTINGS].
@@ -33,6 +56,7 @@ pub fn settings() -> impl Deref<Target = Settings> {
 pub struct Settings {
     pub visual: VisualSettings,
     pub game: GameSettings,
+    pub audio: AudioSettings,
 }
 
 impl Settings {
@@ -58,10 +82,123 @@ pub enum ResolutionState {
     Fullscreen(u32, u32),
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+/// How far the judgement text and combo counter can be scaled up, for players who find them hard
+/// to see.
+pub const HUD_SCALE_RANGE: RangeInclusive<f32> = 1.0..=2.0;
+
+/// How big the safe area margin can be, as a percentage of the screen size.
+pub const SAFE_AREA_RANGE: RangeInclusive<f32> = 0.0..=10.0;
+
+/// How loud the hit sounds can be set, as a percentage.
+pub const HIT_SOUND_VOLUME_RANGE: RangeInclusive<f32> = 0.0..=100.0;
+
+/// How long the lead-in before a song's first note can be set to, in seconds.
+pub const LEAD_IN_RANGE: RangeInclusive<f32> = 0.0..=5.0;
+const DEFAULT_LEAD_IN: f32 = 2.0;
+
+/// Where the judgement text appears during gameplay.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JudgementPosition {
+    /// Just above the receptacle, where the notes are hit.
+    #[default]
+    Receptacle,
+    /// Above the big note outline at the top of the note field, so it's clear of the notes.
+    AboveField,
+    /// In the middle of the screen.
+    ScreenCentre,
+}
+
+impl JudgementPosition {
+    pub const ALL: [JudgementPosition; 3] = [
+        JudgementPosition::Receptacle,
+        JudgementPosition::AboveField,
+        JudgementPosition::ScreenCentre,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            JudgementPosition::Receptacle => "At the receptacle",
+            JudgementPosition::AboveField => "Above the notes",
+            JudgementPosition::ScreenCentre => "Screen centre",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(default)]
 pub struct VisualSettings {
     pub resolution: ResolutionState,
+    /// The monitor to display the game on. If this is None, or the monitor can't be found, the
+    /// primary monitor is used.
+    pub monitor: Option<MonitorPreference>,
+    /// Makes the game look nicer for streaming or recording: debug overlays are hidden and the
+    /// HUD is a little bigger.
+    pub stream_mode: bool,
+    /// Whether to show which drum keys are being pressed during gameplay in stream mode.
+    pub key_input_display: bool,
+    /// Whether to show songs' romanised titles (see [crate::notechart_parser::Song::title_en])
+    /// instead of their original ones, for songs that have them.
+    pub romanised_titles: bool,
+    /// Whether to show the bar at the bottom of the screen that says what the keys do.
+    pub control_hints: bool,
+    /// How much bigger to make the judgement text (Good, Ok, Bad), within [HUD_SCALE_RANGE].
+    pub judgement_scale: f32,
+    /// Where the judgement text appears.
+    pub judgement_position: JudgementPosition,
+    /// How much bigger to make the combo counter, within [HUD_SCALE_RANGE].
+    pub combo_scale: f32,
+    /// How far to keep the HUD in from the edges of the screen, as a percentage of the screen size
+    /// within [SAFE_AREA_RANGE]. This is for TVs and projectors that cut off the edges of the
+    /// picture.
+    pub safe_area_margin: f32,
+}
+
+impl VisualSettings {
+    /// The judgement text scale, kept within [HUD_SCALE_RANGE] in case the settings file was
+    /// edited by hand.
+    pub fn judgement_scale(&self) -> f32 {
+        self.judgement_scale
+            .clamp(*HUD_SCALE_RANGE.start(), *HUD_SCALE_RANGE.end())
+    }
+
+    /// The combo counter scale, kept within [HUD_SCALE_RANGE].
+    pub fn combo_scale(&self) -> f32 {
+        self.combo_scale
+            .clamp(*HUD_SCALE_RANGE.start(), *HUD_SCALE_RANGE.end())
+    }
+
+    /// The safe area margin as a fraction of the screen size, kept within [SAFE_AREA_RANGE].
+    pub fn safe_area_margin(&self) -> f32 {
+        self.safe_area_margin
+            .clamp(*SAFE_AREA_RANGE.start(), *SAFE_AREA_RANGE.end())
+            / 100.
+    }
+}
+
+impl Default for VisualSettings {
+    fn default() -> Self {
+        Self {
+            resolution: ResolutionState::default(),
+            monitor: None,
+            stream_mode: false,
+            key_input_display: true,
+            romanised_titles: false,
+            control_hints: true,
+            judgement_scale: 1.0,
+            judgement_position: JudgementPosition::default(),
+            combo_scale: 1.0,
+            safe_area_margin: 0.0,
+        }
+    }
+}
+
+/// Identifies the monitor the player wants the game to be displayed on.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MonitorPreference {
+    /// The name of the monitor, as reported by the OS.
+    pub name: Option<String>,
+    /// The position of the monitor in the list of monitors. Only used if the monitor has no name.
+    pub index: usize,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -69,6 +206,37 @@ pub struct VisualSettings {
 pub struct GameSettings {
     pub global_note_offset: f32,
     pub key_mappings: KeyMap,
+    /// The keys player 2 plays with in a 2P battle. Only the drum keys are used, and any that are
+    /// also player 1's go to player 1.
+    pub player2_key_mappings: KeyMap,
+    /// Whether to show a marker at the edge of the screen for notes that are coming in too fast
+    /// to see.
+    pub incoming_note_markers: bool,
+    /// Whether to show the timing windows as coloured bands around the receptacle.
+    pub show_timing_windows: bool,
+    /// Whether to go by the levels estimated from each chart's notes instead of the levels the
+    /// charts give. Charts that don't give a level always use the estimate.
+    pub prefer_estimated_levels: bool,
+    /// Whether comments between measures (e.g. `// chorus`) should be shown as section labels,
+    /// like `#SECTION` is.
+    pub comment_section_labels: bool,
+    /// Whether finishing a song with the gauge cleared earns a bonus rally before the results.
+    pub bonus_rally: bool,
+    /// The most notes (counting the blank ones) a measure in a chart can be split into. Charts
+    /// with measures split any finer can't be played.
+    pub max_measure_subdivisions: usize,
+    /// The least time the player gets to see the notes coming before the first one has to be
+    /// hit, in seconds (within [LEAD_IN_RANGE]). Songs whose first note comes any sooner start
+    /// after a silent lead-in.
+    pub lead_in: f32,
+}
+
+impl GameSettings {
+    /// The lead-in, kept within [LEAD_IN_RANGE].
+    pub fn lead_in(&self) -> f32 {
+        self.lead_in
+            .clamp(*LEAD_IN_RANGE.start(), *LEAD_IN_RANGE.end())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -78,6 +246,9 @@ pub struct KeyMap {
     pub right_don: PhysicalKey,
     pub left_kat: PhysicalKey,
     pub right_kat: PhysicalKey,
+    /// Starts the song that's being played again straight away. It does nothing if it's also
+    /// mapped to a drum input.
+    pub quick_restart: PhysicalKey,
 }
 
 impl Default for GameSettings {
@@ -85,17 +256,116 @@ impl Default for GameSettings {
         Self {
             global_note_offset: 0.0,
             key_mappings: KeyMap::default(),
+            player2_key_mappings: KeyMap::player2_default_mapping(),
+            incoming_note_markers: false,
+            show_timing_windows: false,
+            prefer_estimated_levels: false,
+            comment_section_labels: false,
+            bonus_rally: true,
+            max_measure_subdivisions: DEFAULT_MAX_SUBDIVISIONS,
+            lead_in: DEFAULT_LEAD_IN,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AudioSettings {
+    /// Whether song previews should be normalised so that they all play at a similar volume.
+    pub normalise_previews: bool,
+    /// Whether the normalisation used for previews should also be applied during gameplay.
+    pub normalise_gameplay: bool,
+    /// How loud the don and ka sounds are when the drum is hit, as a percentage within
+    /// [HIT_SOUND_VOLUME_RANGE].
+    pub hit_sound_volume: f32,
+}
+
+impl AudioSettings {
+    const fn default_settings() -> Self {
+        Self {
+            normalise_previews: true,
+            normalise_gameplay: false,
+            hit_sound_volume: 100.0,
         }
     }
+
+    /// The hit sound volume, kept within [HIT_SOUND_VOLUME_RANGE].
+    pub fn hit_sound_volume(&self) -> f32 {
+        self.hit_sound_volume.clamp(
+            *HIT_SOUND_VOLUME_RANGE.start(),
+            *HIT_SOUND_VOLUME_RANGE.end(),
+        )
+    }
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self::default_settings()
+    }
+}
+
+/// One of the four drum inputs.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrumInput {
+    LeftKat,
+    LeftDon,
+    RightDon,
+    RightKat,
+}
+
+impl DrumInput {
+    /// All the inputs, in the order they are on the drum from left to right.
+    pub const ALL: [DrumInput; 4] = [
+        DrumInput::LeftKat,
+        DrumInput::LeftDon,
+        DrumInput::RightDon,
+        DrumInput::RightKat,
+    ];
+
+    pub fn is_don(&self) -> bool {
+        matches!(self, DrumInput::LeftDon | DrumInput::RightDon)
+    }
 }
 
 impl KeyMap {
+    /// Returns the drum input the given key is mapped to, if any.
+    pub fn drum_input(&self, key: PhysicalKey) -> Option<DrumInput> {
+        DrumInput::ALL
+            .into_iter()
+            .find(|input| key == self.key_for(*input))
+    }
+
+    /// The quick restart key, unless it's also mapped to a drum input (which gets it instead).
+    pub fn quick_restart_key(&self) -> Option<PhysicalKey> {
+        Some(self.quick_restart).filter(|key| self.drum_input(*key).is_none())
+    }
+
+    pub fn key_for(&self, input: DrumInput) -> PhysicalKey {
+        match input {
+            DrumInput::LeftKat => self.left_kat,
+            DrumInput::LeftDon => self.left_don,
+            DrumInput::RightDon => self.right_don,
+            DrumInput::RightKat => self.right_kat,
+        }
+    }
+
     const fn default_mapping() -> Self {
         Self {
             left_don: PhysicalKey::Code(KeyCode::KeyF),
             right_don: PhysicalKey::Code(KeyCode::KeyJ),
             left_kat: PhysicalKey::Code(KeyCode::KeyD),
             right_kat: PhysicalKey::Code(KeyCode::KeyK),
+            quick_restart: PhysicalKey::Code(KeyCode::KeyR),
+        }
+    }
+
+    const fn player2_default_mapping() -> Self {
+        Self {
+            left_don: PhysicalKey::Code(KeyCode::KeyV),
+            right_don: PhysicalKey::Code(KeyCode::KeyN),
+            left_kat: PhysicalKey::Code(KeyCode::KeyC),
+            right_kat: PhysicalKey::Code(KeyCode::KeyM),
+            quick_restart: PhysicalKey::Code(KeyCode::KeyR),
         }
     }
 }
@@ -112,13 +382,15 @@ impl Default for KeyMap {
 /// contents are in error, it will also return the default settings. Panics if it encounters any
 /// other errors.
 pub fn read_settings() {
-    let settings = try_read_settings().unwrap_or_else(|e| match e {
+    let path = paths().settings_file();
+
+    let settings = try_read_settings(&path).unwrap_or_else(|e| match e {
         SettingsError::InvalidSettings => {
             eprintln!(
                 "Couldn't read settings file due to invalid contents. \
                           Please fix the settings file at \"{}\". \
                           Continuing with default settings...",
-                SETTINGS_PATH
+                path.display()
             );
 
             Settings::default()
@@ -128,13 +400,13 @@ pub fn read_settings() {
             if e.kind() == std::io::ErrorKind::NotFound {
                 eprintln!(
                     "Settings file not found. Creating it at \"{}\"",
-                    SETTINGS_PATH
+                    path.display()
                 );
 
                 let settings = Settings::default();
 
-                std::fs::write(SETTINGS_PATH, toml::to_string(&settings).unwrap())
-                    .unwrap_or_else(|_| panic!("couldnt write to file \"{}\"", SETTINGS_PATH));
+                std::fs::write(&path, toml::to_string(&settings).unwrap())
+                    .unwrap_or_else(|_| panic!("couldnt write to file \"{}\"", path.display()));
                 settings
             } else {
                 panic!("unexpected error reading settings!: {e}");
@@ -145,18 +417,30 @@ pub fn read_settings() {
     *SETTINGS.write().unwrap() = settings;
 }
 
-/// Tries to read and deserialize config from the settings path.
+/// Writes the current settings out to the settings file.
+pub fn save_settings() -> anyhow::Result<()> {
+    save_settings_to(paths().settings_file())
+}
+
+/// Writes the current settings out to the given path.
+pub fn save_settings_to<P: AsRef<Path>>(path: P) -> anyhow::Result<()> {
+    let contents = toml::to_string(&*settings())?;
+    write_locked(path, contents)?;
+    Ok(())
+}
+
+/// Tries to read and deserialize config from the given path.
 ///
 /// Will return an error if the file does not exist, so the file must be created in this case.
-fn try_read_settings() -> Result<Settings, SettingsError> {
-    let str = std::fs::read_to_string(SETTINGS_PATH)?;
+pub(crate) fn try_read_settings(path: &Path) -> Result<Settings, SettingsError> {
+    let str = std::fs::read_to_string(path)?;
 
     Ok(toml::from_str(&str)?)
 }
 
 // Errors
 #[derive(Debug)]
-enum SettingsError {
+pub(crate) enum SettingsError {
     FileError(std::io::Error),
     InvalidSettings,
 }
@@ -172,3 +456,48 @@ impl From<toml::de::Error> for SettingsError {
         Self::InvalidSettings
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_drum_input() {
+        let keys = KeyMap::default();
+
+        for input in DrumInput::ALL {
+            assert_eq!(keys.drum_input(keys.key_for(input)), Some(input));
+        }
+
+        assert_eq!(
+            keys.drum_input(PhysicalKey::Code(KeyCode::KeyJ)),
+            Some(DrumInput::RightDon)
+        );
+        assert_eq!(keys.drum_input(PhysicalKey::Code(KeyCode::Space)), None);
+    }
+
+    #[test]
+    fn test_quick_restart_key() {
+        let mut keys = KeyMap::default();
+        assert_eq!(
+            keys.quick_restart_key(),
+            Some(PhysicalKey::Code(KeyCode::KeyR))
+        );
+
+        // A drum hit shouldn't throw the song away
+        keys.quick_restart = keys.left_don;
+        assert_eq!(keys.quick_restart_key(), None);
+    }
+
+    #[test]
+    fn test_player2_keys() {
+        let player1 = KeyMap::default();
+        let player2 = GameSettings::default().player2_key_mappings;
+
+        // Out of the box, the two players don't share any drum keys
+        for input in DrumInput::ALL {
+            assert_eq!(player2.drum_input(player2.key_for(input)), Some(input));
+            assert_eq!(player1.drum_input(player2.key_for(input)), None);
+        }
+    }
+}
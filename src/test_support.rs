@@ -0,0 +1,43 @@
+//! Helpers shared by the tests of different modules.
+use std::path::PathBuf;
+
+use crate::notechart_parser::{parse_tja_file, Difficulty};
+
+/// Creates an empty directory in the system temp directory for a test to use.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("taiko_{name}_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A 16 bit mono wav file of silence, with the given number of samples.
+pub(crate) fn silent_wav(sample_rate: u32, samples: u32) -> Vec<u8> {
+    let data_size = samples * 2;
+    let mut wav = Vec::new();
+    wav.extend(b"RIFF");
+    wav.extend((36 + data_size).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(sample_rate.to_le_bytes());
+    wav.extend((sample_rate * 2).to_le_bytes());
+    wav.extend(2u16.to_le_bytes());
+    wav.extend(16u16.to_le_bytes());
+    wav.extend(b"data");
+    wav.extend(data_size.to_le_bytes());
+    wav.extend(vec![0; data_size as usize]);
+    wav
+}
+
+/// Reads a TJA file with a single Oni course, with the given BPM and note track. `header` goes
+/// just before the course, for any other metadata the test needs (e.g. `SCOREINIT`).
+pub(crate) fn tja_fixture(bpm: u32, header: &str, track: &str) -> Difficulty {
+    let tja = format!(
+        "TITLE:Fixture\nWAVE:fixture.ogg\nBPM:{bpm}\n{header}COURSE:Oni\n\n#START\n{track}\n#END\n"
+    );
+    parse_tja_file(&tja).unwrap().difficulties[3]
+        .take()
+        .unwrap()
+}
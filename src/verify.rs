@@ -0,0 +1,119 @@
+//! Checking a replay from the command line, without opening a window.
+//!
+//! Running the game with `--verify-replay <replay> [chart]` plays the replay back against the chart
+//! it was recorded on (see [Replay::verify]) and says whether it comes to the same score and
+//! judgements it was recorded with. The chart is looked for in the song library, and if it isn't
+//! there (e.g. it's been moved) it can be given after the replay, or typed in when asked for.
+//!
+//! The game exits with a nonzero status if the replay doesn't match, or couldn't be checked, so
+//! this can be used from scripts.
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::game::{find_library_song, read_chart_file, Replay, SONGS_DIR};
+use crate::notechart_parser::Song;
+
+/// Running the game with this flag (followed by the replay's path) verifies the replay and exits
+/// without opening a window.
+pub const VERIFY_REPLAY_FLAG: &str = "--verify-replay";
+/// The exit status when the replay doesn't come to what it was recorded with.
+const MISMATCH_STATUS: i32 = 1;
+/// The exit status when the replay couldn't be checked at all.
+const ERROR_STATUS: i32 = 2;
+
+/// Verifies the replay named after [VERIFY_REPLAY_FLAG] in the given command line arguments.
+/// Returns the status the game should exit with.
+pub fn run(args: impl IntoIterator<Item = OsString>) -> i32 {
+    let mut args = args.into_iter().skip_while(|arg| arg != VERIFY_REPLAY_FLAG);
+    args.next();
+
+    let Some(replay_path) = args.next().map(PathBuf::from) else {
+        eprintln!("usage: {VERIFY_REPLAY_FLAG} <replay> [chart]");
+        return ERROR_STATUS;
+    };
+    let chart_path = args.next().map(PathBuf::from);
+
+    let verification = Replay::load(&replay_path).and_then(|replay| {
+        let song = match &chart_path {
+            Some(path) => read_chart_file(path)?,
+            None => find_song(&replay)?,
+        };
+        let chart = song
+            .difficulties
+            .get(replay.difficulty)
+            .and_then(Option::as_ref)
+            .ok_or_else(|| anyhow::anyhow!("the chart doesn't have the difficulty played"))?;
+
+        replay.verify(chart)
+    });
+
+    match verification {
+        Ok(verification) => {
+            println!("{}: {verification}", replay_path.display());
+            if verification.matches() {
+                0
+            } else {
+                MISMATCH_STATUS
+            }
+        }
+
+        Err(e) => {
+            eprintln!("Couldn't verify {}: {e}", replay_path.display());
+            ERROR_STATUS
+        }
+    }
+}
+
+/// Finds the song the replay was recorded on in the song library, or asks where it is if it isn't
+/// there.
+fn find_song(replay: &Replay) -> anyhow::Result<Song> {
+    if let Some(song) = find_library_song(SONGS_DIR, &replay.song_key)? {
+        return Ok(song);
+    }
+
+    print!(
+        "{} isn't in the song library. Where is its chart? ",
+        replay.song_title
+    );
+    std::io::stdout().flush()?;
+
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    let path = line.trim();
+    if path.is_empty() {
+        anyhow::bail!("{} isn't in the song library", replay.song_title);
+    }
+
+    read_chart_file(Path::new(path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SMOKE_TEST_DIR: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/game/taiko_mode/Smoke test"
+    );
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        ["taiko", VERIFY_REPLAY_FLAG]
+            .iter()
+            .chain(args)
+            .map(OsString::from)
+            .collect()
+    }
+
+    #[test]
+    fn test_verify_replay_command() {
+        let replay = format!("{SMOKE_TEST_DIR}/Smoke test.ltr");
+        let chart = format!("{SMOKE_TEST_DIR}/Smoke test.tja");
+        assert_eq!(run(args(&[&replay, &chart])), 0);
+
+        // A replay that isn't there can't be checked
+        let missing = format!("{SMOKE_TEST_DIR}/missing.ltr");
+        assert_eq!(run(args(&[&missing, &chart])), ERROR_STATUS);
+        assert_eq!(run(args(&[])), ERROR_STATUS);
+    }
+}